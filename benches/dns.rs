@@ -0,0 +1,57 @@
+//! Benchmarks of the DNS hot path: from domain name to the serialized answer with blockchain records
+
+use std::net::Ipv6Addr;
+use std::sync::{Arc, Mutex};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use gis::{Chain, Context, Settings};
+use gis::blockchain::filter::BlockchainFilter;
+use gis::blockchain::hash_utils::hash_identity;
+use gis::blockchain::transaction::DomainData;
+use gis::dns::buffer::VectorPacketBuffer;
+use gis::dns::filter::DnsFilter;
+use gis::dns::protocol::{DnsPacket, DnsQuestion, DnsRecord, QueryType, TransientTtl};
+
+const DB_NAME: &str = "./tests/guachain.db";
+const DOMAIN: &str = "test.ygg";
+
+fn make_data() -> String {
+    let mut records = Vec::new();
+    for name in &["@", "www", "cloud", "repo", "*"] {
+        records.push(DnsRecord::AAAA { domain: name.to_string(), addr: Ipv6Addr::LOCALHOST, ttl: TransientTtl(3600) });
+    }
+    let data = DomainData::new(Default::default(), String::from("ygg"), records, Vec::new(), Vec::new());
+    serde_json::to_string(&data).unwrap()
+}
+
+fn bench_lookup(c: &mut Criterion) {
+    let settings = Settings::default();
+    // Opening the DB changes it, so the bench works on a copy
+    let db_name = std::env::temp_dir().join("gis_bench_dns.db");
+    std::fs::copy(DB_NAME, &db_name).unwrap();
+    let chain = Chain::new(&settings, db_name.to_str().unwrap());
+
+    c.bench_function("identity hash", |b| b.iter(|| hash_identity(black_box(DOMAIN), None)));
+    c.bench_function("domain transaction", |b| b.iter(|| chain.get_domain_transaction(black_box(DOMAIN))));
+
+    let data = make_data();
+    c.bench_function("domain data parse", |b| b.iter(|| serde_json::from_str::<DomainData>(black_box(&data)).unwrap()));
+
+    let records = serde_json::from_str::<DomainData>(&data).unwrap().records;
+    c.bench_function("answer serialization", |b| b.iter(|| {
+        let mut packet = DnsPacket::new();
+        packet.questions.push(DnsQuestion::new(DOMAIN.to_owned(), QueryType::AAAA));
+        packet.answers = records.clone();
+        let mut buffer = VectorPacketBuffer::new();
+        packet.write(&mut buffer, 0xFFFF).unwrap();
+        buffer.pos
+    }));
+
+    let context = Context::new(String::from("bench"), settings, None, chain);
+    let filter = BlockchainFilter::new(Arc::new(Mutex::new(context)));
+    c.bench_function("blockchain filter lookup", |b| b.iter(|| filter.lookup(black_box(DOMAIN), QueryType::AAAA)));
+}
+
+criterion_group!(benches, bench_lookup);
+criterion_main!(benches);
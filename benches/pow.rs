@@ -0,0 +1,30 @@
+//! Benchmarks of proof of work hashing, every backend on bytes of blocks of both encodings
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use gis::{Block, Bytes, Transaction};
+use gis::blockchain::pow::get_hashers;
+use gis::commons::constants::{BINARY_RULES_VERSION, CHAIN_VERSION};
+
+fn make_block(version: u32) -> Block {
+    let data = String::from(r#"{"domain":"0101","zone":"ygg","records":[],"owners":[]}"#);
+    let transaction = Transaction::from_str(String::from("test.ygg"), String::from("domain"), data, Bytes::from_bytes(&[1u8; 32]));
+    let mut block = Block::new(Some(transaction), Bytes::from_bytes(&[2u8; 32]), Bytes::from_bytes(&[3u8; 32]), 24);
+    block.version = version;
+    block
+}
+
+fn bench_hashers(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pow");
+    for (encoding, version) in &[("json", CHAIN_VERSION), ("binary", BINARY_RULES_VERSION)] {
+        let bytes = make_block(*version).as_bytes();
+        for mut hasher in get_hashers() {
+            let id = BenchmarkId::new(hasher.name(), encoding);
+            group.bench_function(id, |b| b.iter(|| hasher.hash(black_box(&bytes)).len()));
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_hashers);
+criterion_main!(benches);
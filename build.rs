@@ -16,6 +16,8 @@ fn main() {
     if cfg!(target_os = "windows") {
         let mut res = winres::WindowsResource::new();
         res.set_icon("img/logo/gis.ico");
+        // The same icon by name, for the tray
+        res.set_icon_with_id("img/logo/gis.ico", "gis-icon");
         res.compile().unwrap();
     }
 
@@ -36,7 +36,7 @@ else
   exit 1
 fi
 
-cross build --release --no-default-features --target $TARGET
+cross build --release --no-default-features --features node --target $TARGET
 upx target/$TARGET/release/gis
 cp target/$TARGET/release/gis ./gis
 cp target/$TARGET/release/gis ./bin/gis-linux-$PKGARCH-v$PKGVERSION-nogui
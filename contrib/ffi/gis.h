@@ -0,0 +1,49 @@
+/* C interface of GIS library (libgis.so, libgis.a), see src/ffi.rs for details.
+ * Strings are UTF-8 and null-terminated, strings returned by GIS must be freed by gis_free_string().
+ * Callbacks are called from GIS threads, they must not call GIS functions synchronously.
+ */
+#ifndef GIS_H
+#define GIS_H
+
+#include <stddef.h>
+#include <stdint.h>
+#include <sys/types.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct GisHandle GisHandle;
+
+typedef void (*gis_event_callback)(void *user_data, const char *event);
+typedef void (*gis_resolve_callback)(void *user_data, const char *result);
+
+/* Version of the library, must not be freed */
+const char *gis_version(void);
+
+/* Starts GIS with settings from config file, work_dir may be NULL. Returns NULL on error. */
+GisHandle *gis_start(const char *config, const char *work_dir);
+
+/* Stops GIS and frees the handle */
+void gis_stop(GisHandle *handle);
+
+/* Sets callback for network, sync and blockchain events */
+void gis_set_event_callback(GisHandle *handle, gis_event_callback callback, void *user_data);
+
+/* Resolves the name, returns JSON like {"rescode":"NOERROR","answers":[...]} */
+char *gis_resolve(GisHandle *handle, const char *name, uint16_t qtype);
+
+/* Resolves the name in another thread, and gives the same JSON to the callback */
+void gis_resolve_async(GisHandle *handle, const char *name, uint16_t qtype, gis_resolve_callback callback, void *user_data);
+
+/* Answers raw DNS query, returns length of the response or -1 on error */
+ssize_t gis_handle_query(GisHandle *handle, const uint8_t *query, size_t query_len, uint8_t *response, size_t response_len);
+
+/* Frees the string returned by GIS */
+void gis_free_string(char *string);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* GIS_H */
@@ -0,0 +1,244 @@
+//! Detection of our own address, for records of our domains when the address changes.
+//! Detectors are tried in the order from settings, the first one that finds an address wins.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use crate::is_yggdrasil;
+use crate::settings::AddressDetection;
+
+/// Time to wait for answers of outside servers
+const DETECT_TIMEOUT_SEC: u64 = 5;
+/// Magic cookie of STUN messages, RFC 5389
+const STUN_COOKIE: u32 = 0x2112A442;
+const STUN_BINDING_REQUEST: u16 = 0x0001;
+const STUN_BINDING_RESPONSE: u16 = 0x0101;
+const STUN_MAPPED_ADDRESS: u16 = 0x0001;
+const STUN_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+
+/// Some way to find out our address
+pub trait AddressDetector {
+    fn get_name(&self) -> &str;
+    fn detect(&self) -> io::Result<IpAddr>;
+}
+
+/// Finds our Yggdrasil address by the route to 200::/7, nothing is sent to the network
+pub struct InterfaceDetector;
+
+impl AddressDetector for InterfaceDetector {
+    fn get_name(&self) -> &str {
+        "interface"
+    }
+
+    fn detect(&self) -> io::Result<IpAddr> {
+        let socket = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?;
+        // Connecting UDP socket only chooses the route and local address
+        socket.connect((Ipv6Addr::new(0x200, 0, 0, 0, 0, 0, 0, 1), 9))?;
+        let addr = socket.local_addr()?.ip();
+        match is_yggdrasil(&addr) {
+            true => Ok(addr),
+            false => Err(io::Error::new(io::ErrorKind::NotFound, "No Yggdrasil address"))
+        }
+    }
+}
+
+/// Asks STUN servers for our public address
+pub struct StunDetector {
+    servers: Vec<String>,
+}
+
+impl AddressDetector for StunDetector {
+    fn get_name(&self) -> &str {
+        "stun"
+    }
+
+    fn detect(&self) -> io::Result<IpAddr> {
+        let mut error = io::Error::new(io::ErrorKind::NotFound, "No STUN servers");
+        for server in &self.servers {
+            match stun_request(server) {
+                Ok(addr) => return Ok(addr),
+                Err(e) => {
+                    debug!("STUN server {} has failed: {}", server, e);
+                    error = e;
+                }
+            }
+        }
+        Err(error)
+    }
+}
+
+/// Asks echo services, that answer with the address of the client in plain text
+pub struct HttpDetector {
+    urls: Vec<String>,
+}
+
+impl AddressDetector for HttpDetector {
+    fn get_name(&self) -> &str {
+        "http"
+    }
+
+    fn detect(&self) -> io::Result<IpAddr> {
+        let mut error = io::Error::new(io::ErrorKind::NotFound, "No echo services");
+        for url in &self.urls {
+            let result = minreq::get(url)
+                .with_timeout(DETECT_TIMEOUT_SEC)
+                .send()
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+                .and_then(|response| {
+                    let text = response.as_str().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+                    text.trim().parse::<IpAddr>().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+                });
+            match result {
+                Ok(addr) => return Ok(addr),
+                Err(e) => {
+                    debug!("Echo service {} has failed: {}", url, e);
+                    error = e;
+                }
+            }
+        }
+        Err(error)
+    }
+}
+
+/// Gets Yggdrasil address of this node, for `{ygg}` in records
+pub fn get_node_address() -> Option<Ipv6Addr> {
+    match InterfaceDetector.detect() {
+        Ok(IpAddr::V6(addr)) => Some(addr),
+        _ => None
+    }
+}
+
+/// Makes detectors in the order from settings
+pub fn make_detectors(settings: &AddressDetection) -> Result<Vec<Box<dyn AddressDetector>>, String> {
+    let mut result: Vec<Box<dyn AddressDetector>> = Vec::new();
+    for method in &settings.methods {
+        match method.as_str() {
+            "interface" => result.push(Box::new(InterfaceDetector)),
+            "stun" => result.push(Box::new(StunDetector { servers: settings.stun_servers.clone() })),
+            "http" => result.push(Box::new(HttpDetector { urls: settings.echo_urls.clone() })),
+            _ => return Err(format!("Unknown address detection method '{}'", method))
+        }
+    }
+    Ok(result)
+}
+
+/// Tries detectors in turn, returns the name of the first successful one and its address
+pub fn detect_address(detectors: &[Box<dyn AddressDetector>]) -> Option<(String, IpAddr)> {
+    for detector in detectors {
+        match detector.detect() {
+            Ok(addr) => return Some((detector.get_name().to_owned(), addr)),
+            Err(e) => debug!("Unable to detect address by {}: {}", detector.get_name(), e)
+        }
+    }
+    None
+}
+
+/// Sends STUN binding request and gets our address from the response
+fn stun_request(server: &str) -> io::Result<IpAddr> {
+    let server = server.to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Unable to resolve STUN server"))?;
+    let socket = match server {
+        SocketAddr::V4(_) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?,
+        SocketAddr::V6(_) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?
+    };
+    socket.set_read_timeout(Some(Duration::from_secs(DETECT_TIMEOUT_SEC)))?;
+    let transaction: [u8; 12] = rand::random();
+    let mut request = Vec::with_capacity(20);
+    request.extend_from_slice(&STUN_BINDING_REQUEST.to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes());
+    request.extend_from_slice(&STUN_COOKIE.to_be_bytes());
+    request.extend_from_slice(&transaction);
+    socket.send_to(&request, server)?;
+
+    let mut buf = [0u8; 512];
+    let (size, _) = socket.recv_from(&mut buf)?;
+    parse_stun_response(&buf[..size], &transaction)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Wrong STUN response"))
+}
+
+/// Gets the address from (XOR-)MAPPED-ADDRESS attribute of STUN binding response
+fn parse_stun_response(data: &[u8], transaction: &[u8; 12]) -> Option<IpAddr> {
+    let read_u16 = |pos: usize| Some(u16::from_be_bytes([*data.get(pos)?, *data.get(pos + 1)?]));
+    if read_u16(0)? != STUN_BINDING_RESPONSE || data.get(4..8)? != STUN_COOKIE.to_be_bytes() || data.get(8..20)? != *transaction {
+        return None;
+    }
+    let end = (20 + read_u16(2)? as usize).min(data.len());
+    let mut pos = 20;
+    let mut mapped = None;
+    while pos + 4 <= end {
+        let kind = read_u16(pos)?;
+        let length = read_u16(pos + 2)? as usize;
+        let value = data.get(pos + 4..pos + 4 + length)?;
+        if kind == STUN_XOR_MAPPED_ADDRESS || kind == STUN_MAPPED_ADDRESS {
+            // The key to unmask the address is the cookie followed by transaction id
+            let mut key = STUN_COOKIE.to_be_bytes().to_vec();
+            key.extend_from_slice(transaction);
+            let xor = kind == STUN_XOR_MAPPED_ADDRESS;
+            let addr = match (*value.get(1)?, value.len()) {
+                (1, 8) => {
+                    let mut octets = [0u8; 4];
+                    for (i, octet) in octets.iter_mut().enumerate() {
+                        *octet = if xor { value[4 + i] ^ key[i] } else { value[4 + i] };
+                    }
+                    IpAddr::from(octets)
+                }
+                (2, 20) => {
+                    let mut octets = [0u8; 16];
+                    for (i, octet) in octets.iter_mut().enumerate() {
+                        *octet = if xor { value[4 + i] ^ key[i] } else { value[4 + i] };
+                    }
+                    IpAddr::from(octets)
+                }
+                _ => return None
+            };
+            if xor {
+                return Some(addr);
+            }
+            mapped = Some(addr);
+        }
+        // Attributes are padded to 4 bytes
+        pos += 4 + (length + 3) / 4 * 4;
+    }
+    mapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stun_response() {
+        let transaction = [7u8; 12];
+        let mut response = Vec::new();
+        response.extend_from_slice(&STUN_BINDING_RESPONSE.to_be_bytes());
+        response.extend_from_slice(&12u16.to_be_bytes());
+        response.extend_from_slice(&STUN_COOKIE.to_be_bytes());
+        response.extend_from_slice(&transaction);
+        // XOR-MAPPED-ADDRESS of 203.0.113.5:40000
+        response.extend_from_slice(&STUN_XOR_MAPPED_ADDRESS.to_be_bytes());
+        response.extend_from_slice(&8u16.to_be_bytes());
+        response.extend_from_slice(&[0, 1]);
+        response.extend_from_slice(&(40000u16 ^ (STUN_COOKIE >> 16) as u16).to_be_bytes());
+        let cookie = STUN_COOKIE.to_be_bytes();
+        response.extend_from_slice(&[203 ^ cookie[0], cookie[1], 113 ^ cookie[2], 5 ^ cookie[3]]);
+
+        assert_eq!(Some("203.0.113.5".parse().unwrap()), parse_stun_response(&response, &transaction));
+        assert_eq!(None, parse_stun_response(&response, &[8u8; 12]));
+        assert_eq!(None, parse_stun_response(&response[..10], &transaction));
+    }
+
+    #[test]
+    fn detectors() {
+        let mut settings = AddressDetection::default();
+        let detectors = make_detectors(&settings).unwrap();
+        let names: Vec<&str> = detectors.iter().map(|d| d.get_name()).collect();
+        assert_eq!(vec!["interface", "stun", "http"], names);
+        settings.methods.push(String::from("magic"));
+        assert!(make_detectors(&settings).is_err());
+    }
+}
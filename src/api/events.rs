@@ -0,0 +1,247 @@
+//! Live events of the node by WebSocket (RFC 6455), for dashboards and other tools that don't want to poll the API.
+//! Clients connect to `/api/events`, optionally with `?events=minerStats,blockchainChanged` to get only some events,
+//! and get every event of the bus as a JSON text message like `{"event":"blockchainChanged","index":100}`.
+//! Events of mining and watched domains are sent only to local clients with the token of the API,
+//! and web pages of other sites can't connect at all.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{IpAddr, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+
+use crate::Context;
+use crate::api::http::{HttpRequest, HttpResponse};
+use crate::cert::base64;
+use crate::event::Event;
+
+/// Maximum number of clients that get events at once
+pub const MAX_SUBSCRIBERS: usize = 16;
+/// Clients are pinged when there are no events for this long, to find out that they are gone
+const PING_INTERVAL_SEC: u64 = 30;
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+
+/// Upgrades the connection to WebSocket and sends events to it until the client is gone
+pub fn serve_events(context: &Arc<Mutex<Context>>, request: &HttpRequest, mut stream: TcpStream, ip: &IpAddr, token: &str) {
+    // Browsers let any page open WebSockets, but they always tell its origin
+    if let Some(origin) = request.headers.get("origin") {
+        if !is_local_origin(origin) {
+            let _ = HttpResponse::error(403, "Only local pages can get events").write(&mut stream);
+            return;
+        }
+    }
+    // Clients that give a token get private events too, it is checked as for changes of watched domains
+    let private = request.headers.contains_key("authorization");
+    if private {
+        let refusal = if !ip.is_loopback() {
+            Some(HttpResponse::error(403, "Only local requests are allowed"))
+        } else if token.is_empty() {
+            Some(HttpResponse::error(403, "Token of the API is not set"))
+        } else if !request.has_token(token) {
+            Some(HttpResponse::error(401, "Wrong or absent token"))
+        } else {
+            None
+        };
+        if let Some(response) = refusal {
+            let _ = response.write(&mut stream);
+            return;
+        }
+    }
+    let upgrade = request.headers.get("upgrade").map_or(false, |value| value.eq_ignore_ascii_case("websocket"));
+    let key = match request.headers.get("sec-websocket-key") {
+        Some(key) if upgrade && request.method == "GET" => key.clone(),
+        _ => {
+            let _ = HttpResponse::error(426, "Connect by WebSocket to get events").write(&mut stream);
+            return;
+        }
+    };
+    let filter: Vec<String> = request.get_param("events")
+        .map(|events| events.split(',').filter(|name| !name.is_empty()).map(str::to_owned).collect())
+        .unwrap_or_default();
+    let head = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        get_accept_key(&key)
+    );
+    if stream.write_all(head.as_bytes()).is_err() || stream.set_nonblocking(true).is_err() {
+        return;
+    }
+
+    let (sender, receiver) = channel();
+    let uuid = {
+        // Events are converted in the thread of this client, not to slow down the bus
+        let sender = Mutex::new(sender);
+        context.lock().unwrap().bus.register(move |_uuid, e| sender.lock().unwrap().send(e).is_ok())
+    };
+    let mut last_sent = Instant::now();
+    let result: io::Result<()> = loop {
+        if client_closed(&mut stream) {
+            let _ = stream.write_all(&make_frame(OPCODE_CLOSE, &[]));
+            break Ok(());
+        }
+        let frame = match receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(event) => match event_to_json(&event, private) {
+                Some(value) if is_wanted(&value, &filter) => make_frame(OPCODE_TEXT, value.to_string().as_bytes()),
+                _ => continue
+            },
+            Err(RecvTimeoutError::Timeout) if last_sent.elapsed().as_secs() >= PING_INTERVAL_SEC => make_frame(OPCODE_PING, &[]),
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break Ok(())
+        };
+        // A client that doesn't take events as fast as they come is dropped, not to keep them in memory
+        if let Err(e) = stream.write_all(&frame) {
+            break Err(e);
+        }
+        last_sent = Instant::now();
+    };
+    if let Err(e) = result {
+        debug!("Events client is gone: {}", e);
+    }
+    context.lock().unwrap().bus.unregister(&uuid);
+}
+
+/// Reads what the client has sent, it is closed if it has sent a close frame or has closed the connection.
+/// Messages of clients are not needed, so the rest of them is dropped.
+fn client_closed(stream: &mut TcpStream) -> bool {
+    let mut buf = [0u8; 1024];
+    match stream.read(&mut buf) {
+        Ok(0) => true,
+        Ok(_) => buf[0] & 0x0F == OPCODE_CLOSE,
+        Err(e) => e.kind() != ErrorKind::WouldBlock
+    }
+}
+
+/// Gets the value of `Sec-WebSocket-Accept` header for the key of the client
+fn get_accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.trim().as_bytes());
+    hasher.update(WEBSOCKET_GUID.as_bytes());
+    base64(&hasher.finalize())
+}
+
+/// Makes a final unmasked frame, as servers send them
+fn make_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Checks that the page is opened from this machine, the origin is like `http://127.0.0.1:4244`
+fn is_local_origin(origin: &str) -> bool {
+    let host = match origin.split_once("://") {
+        Some((_, host)) => host,
+        None => return false
+    };
+    let host = match host.strip_prefix('[') {
+        Some(host) => host.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default()
+    };
+    host.eq_ignore_ascii_case("localhost") || host.parse::<IpAddr>().map_or(false, |ip| ip.is_loopback())
+}
+
+fn is_wanted(value: &Value, filter: &[String]) -> bool {
+    filter.is_empty() || filter.iter().any(|name| value["event"] == name.as_str())
+}
+
+/// Converts events that are interesting outside of the node, keys and actions of the UI are not given away.
+/// Mining and watched domains are private, they are converted only for clients with the token.
+fn event_to_json(event: &Event, private: bool) -> Option<Value> {
+    let value = match event {
+        Event::MinerStarted if private => json!({ "event": "minerStarted" }),
+        Event::MinerStopped { success, full } if private => json!({ "event": "minerStopped", "success": success, "full": full }),
+        Event::MinerStats { thread, speed, max_diff, target_diff } if private => {
+            json!({ "event": "minerStats", "thread": thread, "speed": speed, "maxDiff": max_diff, "targetDiff": target_diff })
+        }
+        Event::ZonesChanged => json!({ "event": "zonesChanged" }),
+        Event::DomainChanged { zone, identity, serial } => {
+            json!({ "event": "domainChanged", "zone": zone, "identity": identity, "serial": serial })
+        }
+        Event::NewBlockReceived => json!({ "event": "newBlockReceived" }),
+        Event::BlockchainChanged { index } => json!({ "event": "blockchainChanged", "index": index }),
+        Event::ChainReorged { index, removed, added } => {
+            json!({ "event": "chainReorged", "index": index, "removed": removed, "added": added })
+        }
+        Event::NetworkStatus { nodes, blocks } => json!({ "event": "networkStatus", "nodes": nodes, "blocks": blocks }),
+        Event::Syncing { have, height } => json!({ "event": "syncing", "have": have, "height": height }),
+        Event::SyncFinished => json!({ "event": "syncFinished" }),
+        Event::ChainCheckProgress { checked, height, finished } => {
+            json!({ "event": "chainCheckProgress", "checked": checked, "height": height, "finished": finished })
+        }
+        Event::UpdateAvailable { version, url, required } => {
+            json!({ "event": "updateAvailable", "version": version, "url": url, "required": required })
+        }
+        Event::WatchedDomain { domain, kind, expires } if private => {
+            json!({ "event": "watchedDomain", "domain": domain, "kind": kind, "expires": expires })
+        }
+        _ => return None
+    };
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handshake() {
+        // The example from RFC 6455
+        assert_eq!("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=", get_accept_key("dGhlIHNhbXBsZSBub25jZQ=="));
+    }
+
+    #[test]
+    fn frames() {
+        assert_eq!(vec![0x81, 2, b'h', b'i'], make_frame(OPCODE_TEXT, b"hi"));
+        assert_eq!(vec![0x89, 0], make_frame(OPCODE_PING, &[]));
+        let frame = make_frame(OPCODE_TEXT, &[0u8; 300]);
+        assert_eq!(&[0x81, 126, 1, 44], &frame[..4]);
+        assert_eq!(304, frame.len());
+        let frame = make_frame(OPCODE_TEXT, &[0u8; 70000]);
+        assert_eq!(&[0x81, 127, 0, 0, 0, 0, 0, 1, 0x11, 0x70], &frame[..10]);
+    }
+
+    #[test]
+    fn events() {
+        let value = event_to_json(&Event::BlockchainChanged { index: 100 }, false).unwrap();
+        assert_eq!(r#"{"event":"blockchainChanged","index":100}"#, value.to_string());
+        assert!(is_wanted(&value, &[]));
+        assert!(is_wanted(&value, &[String::from("minerStats"), String::from("blockchainChanged")]));
+        assert!(!is_wanted(&value, &[String::from("minerStats")]));
+        assert!(event_to_json(&Event::ActionQuit, true).is_none());
+        // Only clients with the token know about mining and watched domains
+        assert!(event_to_json(&Event::MinerStarted, false).is_none());
+        assert!(event_to_json(&Event::MinerStarted, true).is_some());
+        let watched = Event::WatchedDomain { domain: String::from("example.ygg"), kind: String::from("expiring"), expires: Some(0) };
+        assert!(event_to_json(&watched, false).is_none());
+        assert!(event_to_json(&watched, true).is_some());
+    }
+
+    #[test]
+    fn origins() {
+        assert!(is_local_origin("http://localhost:4244"));
+        assert!(is_local_origin("http://127.0.0.1"));
+        assert!(is_local_origin("https://[::1]:8080"));
+        assert!(!is_local_origin("https://example.com"));
+        assert!(!is_local_origin("http://127.0.0.1.example.com"));
+        assert!(!is_local_origin("null"));
+    }
+}
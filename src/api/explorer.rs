@@ -0,0 +1,241 @@
+//! Read-only JSON feed for third-party block explorers.
+//! Pages are continued by giving the `next` value of previous page as `from`.
+//! Changes of zones are continued by giving the `serial` value of previous answer.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::{Block, Context};
+use crate::api::http::{HttpRequest, HttpResponse};
+use crate::blockchain::types::DomainEntry;
+use crate::blockchain::zone_stats::ZoneStats;
+use crate::confusables::to_ascii;
+use crate::fingerprint::get_fingerprint;
+
+/// Default number of items in one page
+const DEFAULT_LIMIT: u64 = 20;
+/// Maximum number of items in one page
+const MAX_LIMIT: u64 = 100;
+/// Maximum time to wait for changes in a zone
+const MAX_WAIT_SEC: u64 = 30;
+
+#[derive(Serialize)]
+struct BlocksPage {
+    height: u64,
+    blocks: Vec<BlockEntry>,
+    /// Index to start the next page from, absent on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next: Option<u64>,
+}
+
+/// Block with human-readable fingerprint of its miner key
+#[derive(Serialize)]
+struct BlockEntry {
+    #[serde(flatten)]
+    block: Block,
+    fingerprint: String,
+}
+
+#[derive(Serialize)]
+struct DomainsPage {
+    zone: String,
+    domains: Vec<DomainEntry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct DomainInfo {
+    domain: String,
+    /// Data of the last transaction of the domain, its name is encrypted there
+    data: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct ChangesPage {
+    zone: String,
+    /// Serial to ask the next changes since
+    serial: u64,
+    changes: Vec<DomainEntry>,
+    /// There are more changes right after this page
+    more: bool,
+}
+
+/// Last known serials of zones, that wakes up clients waiting for changes
+#[derive(Default)]
+pub struct ChangeFeed {
+    serials: Mutex<HashMap<String, u64>>,
+    cond: Condvar,
+}
+
+impl ChangeFeed {
+    pub fn update(&self, zone: String, serial: u64) {
+        self.serials.lock().unwrap().insert(zone, serial);
+        self.cond.notify_all();
+    }
+
+    /// Waits until the zone gets a serial bigger than `serial`, returns false on timeout
+    pub fn wait(&self, zone: &str, serial: u64, timeout: Duration) -> bool {
+        let serials = self.serials.lock().unwrap();
+        let (_serials, result) = self.cond
+            .wait_timeout_while(serials, timeout, |serials| serials.get(zone).map_or(true, |s| *s <= serial))
+            .unwrap();
+        !result.timed_out()
+    }
+}
+
+/// `GET /api/blocks?from=&limit=&full_only=`
+pub fn blocks(context: &Arc<Mutex<Context>>, request: &HttpRequest) -> HttpResponse {
+    let (from, limit) = match get_page(request) {
+        Ok(page) => page,
+        Err(e) => return HttpResponse::error(400, &e)
+    };
+    let full_only = request.get_flag("full_only");
+    let (height, blocks) = {
+        let context = context.lock().unwrap();
+        (context.chain.get_height(), context.chain.get_blocks(from, limit, full_only))
+    };
+    let next = match blocks.last() {
+        Some(block) if blocks.len() as u64 == limit && block.index < height => Some(block.index + 1),
+        _ => None
+    };
+    let blocks = blocks.into_iter()
+        .map(|block| BlockEntry { fingerprint: get_fingerprint(&block.pub_key), block })
+        .collect();
+    HttpResponse::json(&BlocksPage { height, blocks, next })
+}
+
+/// `GET /api/orphans?from=&limit=`, blocks removed by forks or as bad, with indexes from `from` to `from + limit - 1`.
+/// They are kept only with `archive_orphans` in `[db]` settings.
+pub fn orphans(context: &Arc<Mutex<Context>>, request: &HttpRequest) -> HttpResponse {
+    let (from, limit) = match get_page(request) {
+        Ok(page) => page,
+        Err(e) => return HttpResponse::error(400, &e)
+    };
+    let orphans = context.lock().unwrap().chain.get_orphan_blocks(from, from.saturating_add(limit - 1));
+    HttpResponse::json(&orphans)
+}
+
+/// `GET /api/domains?zone=&from=&limit=`
+pub fn domains(context: &Arc<Mutex<Context>>, request: &HttpRequest) -> HttpResponse {
+    let zone = match request.get_param("zone") {
+        Some(zone) if !zone.is_empty() => zone.trim_matches('.').to_lowercase(),
+        _ => return HttpResponse::error(400, "Parameter 'zone' is required")
+    };
+    let (from, limit) = match get_page(request) {
+        Ok(page) => page,
+        Err(e) => return HttpResponse::error(400, &e)
+    };
+    let domains = context.lock().unwrap().chain.get_zone_domains(&zone, from, limit as usize);
+    let next = match domains.last() {
+        Some(entry) if domains.len() as u64 == limit => Some(entry.index + 1),
+        _ => None
+    };
+    HttpResponse::json(&DomainsPage { zone, domains, next })
+}
+
+/// `GET /api/confusables?domain=`, live domains that look like this one, it can be given in Unicode or punycode
+pub fn confusables(context: &Arc<Mutex<Context>>, request: &HttpRequest) -> HttpResponse {
+    let domain = match request.get_param("domain") {
+        Some(domain) if !domain.is_empty() => domain.trim_matches('.').to_lowercase(),
+        _ => return HttpResponse::error(400, "Parameter 'domain' is required")
+    };
+    let report = context.lock().unwrap().chain.find_confusables(&domain);
+    HttpResponse::json(&report)
+}
+
+/// `GET /api/zonestats?zone=`, registrations in the zone by weeks, without `zone` for all zones of our key
+pub fn zone_stats(context: &Arc<Mutex<Context>>, request: &HttpRequest) -> HttpResponse {
+    let context = context.lock().unwrap();
+    let zones = match request.get_param("zone") {
+        Some(zone) if !zone.is_empty() => vec![zone.trim_matches('.').to_lowercase()],
+        _ => match context.get_keystore() {
+            Some(keystore) => context.chain.get_owned_zones(&keystore.get_public()),
+            None => return HttpResponse::error(400, "Parameter 'zone' is required, this node has no key")
+        }
+    };
+    let stats: Vec<ZoneStats> = zones.iter().map(|zone| context.chain.get_zone_stats(zone)).collect();
+    HttpResponse::json(&stats)
+}
+
+/// `GET /api/changes?zone=&serial=&limit=&wait=`, changes of domains in a zone since the serial.
+/// If there are no changes yet, the answer can wait up to `wait` seconds for them.
+pub fn changes(context: &Arc<Mutex<Context>>, feed: &ChangeFeed, request: &HttpRequest) -> HttpResponse {
+    let zone = match request.get_param("zone") {
+        Some(zone) if !zone.is_empty() => zone.trim_matches('.').to_lowercase(),
+        _ => return HttpResponse::error(400, "Parameter 'zone' is required")
+    };
+    let (serial, limit, wait) = match get_changes_params(request) {
+        Ok(params) => params,
+        Err(e) => return HttpResponse::error(400, &e)
+    };
+    let started = Instant::now();
+    loop {
+        let (current, changes) = {
+            let context = context.lock().unwrap();
+            let current = context.chain.get_zone_serial(&zone);
+            if serial > current {
+                return HttpResponse::error(409, "Serial is newer than the zone, the chain was rewound, start from 0");
+            }
+            (current, context.chain.get_zone_domains(&zone, serial + 1, limit as usize))
+        };
+        let left = wait.saturating_sub(started.elapsed());
+        if !changes.is_empty() || left.is_zero() || !feed.wait(&zone, current, left) {
+            let more = changes.len() as u64 == limit;
+            let serial = match changes.last() {
+                Some(entry) if more => entry.index,
+                _ => current
+            };
+            return HttpResponse::json(&ChangesPage { zone, serial, changes, more });
+        }
+    }
+}
+
+/// `GET /api/v1/domain/{name}`, the current data of a live domain, the name can be given in Unicode or punycode
+pub fn domain_v1(context: &Arc<Mutex<Context>>, request: &HttpRequest) -> HttpResponse {
+    let domain = match request.get_path_param("/api/v1/domain/").and_then(|name| to_ascii(name.trim_matches('.'))) {
+        Some(domain) if !domain.is_empty() => domain,
+        _ => return HttpResponse::error(400, "Wrong domain name")
+    };
+    let info = context.lock().unwrap().chain.get_domain_info(&domain);
+    match info.and_then(|data| serde_json::from_str(&data).ok()) {
+        Some(data) => HttpResponse::json(&DomainInfo { domain, data }),
+        None => HttpResponse::error(404, "Domain not found")
+    }
+}
+
+/// `GET /api/v1/zones`, all zones sorted by name
+pub fn zones_v1(context: &Arc<Mutex<Context>>) -> HttpResponse {
+    let mut zones = context.lock().unwrap().chain.get_zones();
+    zones.sort_by(|a, b| a.name.cmp(&b.name));
+    HttpResponse::json(&zones)
+}
+
+/// `GET /api/v1/block/{index}`
+pub fn block_v1(context: &Arc<Mutex<Context>>, request: &HttpRequest) -> HttpResponse {
+    let index = match request.get_path_param("/api/v1/block/").and_then(|index| index.parse::<u64>().ok()) {
+        Some(index) => index,
+        None => return HttpResponse::error(400, "Wrong block index")
+    };
+    let block = context.lock().unwrap().chain.get_block(index);
+    match block {
+        Some(block) => HttpResponse::json(&BlockEntry { fingerprint: get_fingerprint(&block.pub_key), block }),
+        None => HttpResponse::error(404, "Block not found")
+    }
+}
+
+fn get_changes_params(request: &HttpRequest) -> Result<(u64, u64, Duration), String> {
+    let serial = request.get_number("serial")?.unwrap_or(0);
+    let limit = request.get_number("limit")?.unwrap_or(DEFAULT_LIMIT);
+    let wait = request.get_number("wait")?.unwrap_or(0);
+    Ok((serial, limit.clamp(1, MAX_LIMIT), Duration::from_secs(wait.min(MAX_WAIT_SEC))))
+}
+
+fn get_page(request: &HttpRequest) -> Result<(u64, u64), String> {
+    let from = request.get_number("from")?.unwrap_or(1);
+    let limit = request.get_number("limit")?.unwrap_or(DEFAULT_LIMIT);
+    Ok((from, limit.clamp(1, MAX_LIMIT)))
+}
@@ -0,0 +1,325 @@
+//! Minimal HTTP/1.1 support for the API, one request per connection.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// Maximum size of request line and headers
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+/// Maximum size of request body
+pub const MAX_BODY_SIZE: usize = 64 * 1024;
+/// Time to wait for the answer of running node
+const CLIENT_TIMEOUT_SEC: u64 = 5;
+
+#[derive(Debug, Default)]
+pub struct HttpRequest {
+    pub method: String,
+    /// Path without query string, like `/api/blocks`
+    pub path: String,
+    pub query: HashMap<String, String>,
+    /// Headers with lowercase names
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    /// Reads and parses request from the stream, returns None if it is malformed or too big
+    pub fn read<R: Read>(stream: R) -> Option<HttpRequest> {
+        let mut reader = BufReader::new(stream.take((MAX_HEAD_SIZE + MAX_BODY_SIZE) as u64));
+        let mut head_size = 0;
+        let mut line = String::new();
+        head_size += reader.read_line(&mut line).ok()?;
+        let mut parts = line.split_whitespace();
+        let method = parts.next()?.to_owned();
+        let target = parts.next()?;
+        if !parts.next()?.starts_with("HTTP/1.") {
+            return None;
+        }
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path.to_owned(), parse_query(query)),
+            None => (target.to_owned(), HashMap::new())
+        };
+
+        let mut headers = HashMap::new();
+        loop {
+            line.clear();
+            let size = reader.read_line(&mut line).ok()?;
+            head_size += size;
+            if size == 0 || head_size > MAX_HEAD_SIZE {
+                return None;
+            }
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            let (name, value) = header.split_once(':')?;
+            headers.insert(name.trim().to_lowercase(), value.trim().to_owned());
+        }
+
+        let length = match headers.get("content-length") {
+            Some(length) => length.parse::<usize>().ok()?,
+            None => 0
+        };
+        if length > MAX_BODY_SIZE {
+            return None;
+        }
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body).ok()?;
+        Some(HttpRequest { method, path, query, headers, body })
+    }
+
+    pub fn get_param(&self, name: &str) -> Option<&str> {
+        self.query.get(name).map(|s| s.as_str())
+    }
+
+    /// Gets numeric parameter, None if it is absent, Err if it is malformed
+    pub fn get_number(&self, name: &str) -> Result<Option<u64>, String> {
+        match self.query.get(name) {
+            None => Ok(None),
+            Some(value) => value.parse::<u64>().map(Some).map_err(|_| format!("Wrong value of '{}'", name))
+        }
+    }
+
+    /// Gets the last segment of path that starts with `prefix`, like the name in `/api/v1/domain/{name}`, decoded
+    pub fn get_path_param(&self, prefix: &str) -> Option<String> {
+        match self.path.strip_prefix(prefix) {
+            Some(param) if !param.is_empty() && !param.contains('/') => Some(url_decode(param)),
+            _ => None
+        }
+    }
+
+    /// Gets boolean parameter, `1`, `true` and empty value (like `?full_only`) are true
+    pub fn get_flag(&self, name: &str) -> bool {
+        matches!(self.get_param(name), Some("") | Some("1") | Some("true"))
+    }
+
+    /// Checks `Authorization: Bearer <token>` header, in the same time for all tokens of the same length, not to give it away by timing
+    pub fn has_token(&self, token: &str) -> bool {
+        let given = match self.headers.get("authorization").and_then(|value| value.strip_prefix("Bearer ")) {
+            Some(given) => given.trim().as_bytes(),
+            None => return false
+        };
+        let token = token.as_bytes();
+        given.len() == token.len() && given.iter().zip(token).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+    }
+}
+
+#[derive(Debug)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+    /// Lets web pages of other sites read the response, only for read-only data
+    pub cors: bool,
+}
+
+impl HttpResponse {
+    pub fn json<T: Serialize>(value: &T) -> Self {
+        HttpResponse { status: 200, content_type: "application/json", body: serde_json::to_vec(value).unwrap(), cors: false }
+    }
+
+    /// Plain text response, in the text format of Prometheus metrics
+    pub fn text(text: String) -> Self {
+        HttpResponse { status: 200, content_type: "text/plain; version=0.0.4", body: text.into_bytes(), cors: false }
+    }
+
+    /// Error response with JSON body like `{"error":"Not found"}`
+    pub fn error(status: u16, message: &str) -> Self {
+        let body = serde_json::json!({ "error": message });
+        HttpResponse { status, content_type: "application/json", body: serde_json::to_vec(&body).unwrap(), cors: false }
+    }
+
+    /// Allows any site to read this response
+    pub fn with_cors(mut self) -> Self {
+        self.cors = true;
+        self
+    }
+
+    pub fn write<W: Write>(&self, stream: &mut W) -> io::Result<()> {
+        let cors = if self.cors { "Access-Control-Allow-Origin: *\r\n" } else { "" };
+        let head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n",
+            self.status, reason(self.status), self.content_type, self.body.len(), cors
+        );
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(&self.body)?;
+        stream.flush()
+    }
+}
+
+/// Sends request without body to the API of running node with its token, returns status and body of the answer
+pub fn send_request(listen: &str, token: &str, method: &str, target: &str) -> io::Result<(u16, String)> {
+    let mut addr: SocketAddr = listen.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Wrong API address"))?;
+    // Node that listens on all interfaces is reachable on loopback
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST)
+        });
+    }
+    let mut stream = TcpStream::connect_timeout(&addr, Duration::from_secs(CLIENT_TIMEOUT_SEC))?;
+    stream.set_read_timeout(Some(Duration::from_secs(CLIENT_TIMEOUT_SEC)))?;
+    write!(stream, "{} {} HTTP/1.1\r\nHost: {}\r\nAuthorization: Bearer {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", method, target, &addr, token)?;
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer)?;
+    let (head, body) = answer.split_once("\r\n\r\n").ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Wrong HTTP answer"))?;
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Wrong HTTP status"))?;
+    Ok((status, body.to_owned()))
+}
+
+/// Sends JSON body by POST request to plain HTTP URL, like a webhook, returns status of the answer
+pub fn post_json(url: &str, body: &[u8]) -> io::Result<u16> {
+    let wrong_url = || io::Error::new(io::ErrorKind::InvalidInput, "Wrong URL, only http:// is supported");
+    let rest = url.strip_prefix("http://").ok_or_else(wrong_url)?;
+    let (host, path) = match rest.find('/') {
+        Some(pos) => (&rest[..pos], &rest[pos..]),
+        None => (rest, "/")
+    };
+    let addr = match host.contains(':') && !host.ends_with(']') {
+        true => host.to_socket_addrs()?.next(),
+        false => (host.trim_matches(|c| c == '[' || c == ']'), 80).to_socket_addrs()?.next()
+    };
+    let addr = addr.ok_or_else(wrong_url)?;
+    let mut stream = TcpStream::connect_timeout(&addr, Duration::from_secs(CLIENT_TIMEOUT_SEC))?;
+    stream.set_read_timeout(Some(Duration::from_secs(CLIENT_TIMEOUT_SEC)))?;
+    write!(stream, "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", path, host, body.len())?;
+    stream.write_all(body)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    line.split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Wrong HTTP status"))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error"
+    }
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((name, value)) => (url_decode(name), url_decode(value)),
+            None => (url_decode(pair), String::new())
+        })
+        .collect()
+}
+
+fn url_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => result.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        result.push(byte);
+                        i += 2;
+                    }
+                    None => result.push(b'%')
+                }
+            }
+            byte => result.push(byte)
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_request() {
+        let text = "GET /api/blocks?from=10&limit=5&full_only&zone=ygg%2E HTTP/1.1\r\nHost: localhost\r\nX-Test: a:b\r\n\r\n";
+        let request = HttpRequest::read(text.as_bytes()).unwrap();
+        assert_eq!("GET", request.method);
+        assert_eq!("/api/blocks", request.path);
+        assert_eq!(Ok(Some(10)), request.get_number("from"));
+        assert_eq!(Ok(None), request.get_number("to"));
+        assert!(request.get_flag("full_only"));
+        assert!(!request.get_flag("other"));
+        assert_eq!(Some("ygg."), request.get_param("zone"));
+        assert_eq!("a:b", request.headers.get("x-test").unwrap());
+        assert_eq!(None, request.get_path_param("/api/v1/domain/"));
+
+        let request = HttpRequest::read("GET /api/v1/domain/%D0%BF%D1%80.ygg HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        assert_eq!(Some(String::from("пр.ygg")), request.get_path_param("/api/v1/domain/"));
+        let request = HttpRequest::read("GET /api/v1/domain/a/b HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        assert_eq!(None, request.get_path_param("/api/v1/domain/"));
+
+        let text = "POST /api HTTP/1.1\r\nContent-Length: 4\r\n\r\ntest";
+        let request = HttpRequest::read(text.as_bytes()).unwrap();
+        assert_eq!(b"test", request.body.as_slice());
+
+        assert!(HttpRequest::read("GET /api\r\n\r\n".as_bytes()).is_none());
+        assert!(HttpRequest::read("GET /api HTTP/1.1\r\nHost: localhost\r\n".as_bytes()).is_none());
+        assert!(HttpRequest::read("GET /api?limit=x HTTP/1.1\r\n\r\n".as_bytes()).unwrap().get_number("limit").is_err());
+    }
+
+    #[test]
+    fn token() {
+        let text = "POST /api/jobs HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n";
+        let request = HttpRequest::read(text.as_bytes()).unwrap();
+        assert!(request.has_token("secret"));
+        assert!(!request.has_token("secret2"));
+        assert!(!request.has_token("other!"));
+
+        let request = HttpRequest::read("POST /api/jobs HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        assert!(!request.has_token("secret"));
+    }
+
+    #[test]
+    fn cors() {
+        let mut buf = Vec::new();
+        HttpResponse::json(&"ok").write(&mut buf).unwrap();
+        assert!(!String::from_utf8_lossy(&buf).contains("Access-Control-Allow-Origin"));
+        buf.clear();
+        HttpResponse::json(&"ok").with_cors().write(&mut buf).unwrap();
+        assert!(String::from_utf8_lossy(&buf).contains("Access-Control-Allow-Origin: *\r\n"));
+    }
+
+    #[test]
+    fn post() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = HttpRequest::read(&stream).unwrap();
+            HttpResponse::json(&"ok").write(&mut stream).unwrap();
+            request
+        });
+        assert_eq!(200, post_json(&url, b"{\"zone\":\"ygg\"}").unwrap());
+        let request = server.join().unwrap();
+        assert_eq!("POST", request.method);
+        assert_eq!("/hook", request.path);
+        assert_eq!(b"{\"zone\":\"ygg\"}", request.body.as_slice());
+        assert!(post_json("https://example.com", b"").is_err());
+    }
+}
@@ -0,0 +1,102 @@
+//! Mining of domain transactions, that are built somewhere else, by the library or FFI, on machines without a miner.
+//! Blocks are signed by the key of this node after mining, so transactions must be built for this key,
+//! and domains of other keys are changed with delegation tokens from their owners.
+//! Transactions for keys of other miners are relayed to peers through the mempool.
+//! Requests must have `Authorization: Bearer <token>` header with the token from `[api]` settings.
+
+use std::sync::{Arc, Mutex};
+
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::{Block, Bytes, Context, Miner, Transaction, CLASS_DOMAIN, is_yggdrasil_record};
+use crate::api::http::{HttpRequest, HttpResponse};
+use crate::blockchain::mempool::check_transaction;
+use crate::fingerprint::get_fingerprint;
+
+/// What clients need to build transactions for this node
+#[derive(Serialize)]
+struct MinerInfo {
+    pub_key: Bytes,
+    fingerprint: String,
+    mining: bool,
+}
+
+#[derive(Serialize)]
+struct Accepted {
+    identity: Bytes,
+    zone: String,
+    difficulty: u32,
+}
+
+/// `GET /api/jobs` gives the key of the miner, `POST /api/jobs` with transaction JSON in body starts mining it
+pub fn jobs(context: &Arc<Mutex<Context>>, miner: &Arc<Mutex<Miner>>, token: &str, request: &HttpRequest) -> HttpResponse {
+    if token.is_empty() {
+        return HttpResponse::error(404, "Not found");
+    }
+    if !request.has_token(token) {
+        return HttpResponse::error(401, "Wrong or absent token");
+    }
+    match request.method.as_str() {
+        "GET" => {
+            let keystore = match context.lock().unwrap().get_keystore() {
+                Some(keystore) => keystore,
+                None => return HttpResponse::error(503, "Node has no keys loaded")
+            };
+            let pub_key = keystore.get_public();
+            let fingerprint = get_fingerprint(&pub_key);
+            HttpResponse::json(&MinerInfo { pub_key, fingerprint, mining: miner.lock().unwrap().is_mining() })
+        }
+        "POST" => match serde_json::from_slice::<Transaction>(&request.body) {
+            Ok(transaction) => submit(context, miner, transaction),
+            Err(e) => HttpResponse::error(400, &format!("Wrong transaction: {}", e))
+        },
+        _ => HttpResponse::error(405, "Only GET and POST requests are allowed")
+    }
+}
+
+/// Checks the transaction the same way as blocks are checked, and gives it to the miner
+fn submit(context: &Arc<Mutex<Context>>, miner: &Arc<Mutex<Miner>>, transaction: Transaction) -> HttpResponse {
+    let mut context = context.lock().unwrap();
+    if context.light.is_some() {
+        return HttpResponse::error(503, "Light nodes can't mine domains");
+    }
+    let keystore = match context.get_keystore() {
+        Some(keystore) => keystore,
+        None => return HttpResponse::error(503, "Node has no keys loaded")
+    };
+    if transaction.pub_key != keystore.get_public() {
+        // Transactions for other miners are passed on to peers through the mempool
+        let now = Utc::now().timestamp();
+        if let Err(e) = check_transaction(&context.chain, &transaction, now) {
+            return HttpResponse::error(403, &e);
+        }
+        if !context.mempool.add(transaction, now) {
+            return HttpResponse::error(503, "Mempool is full or has this transaction already");
+        }
+        return HttpResponse::json(&serde_json::json!({ "relayed": true }));
+    }
+    let data = match transaction.get_domain_data() {
+        Some(data) if transaction.class == CLASS_DOMAIN => data,
+        _ => return HttpResponse::error(400, "Only domain transactions can be mined")
+    };
+    let zone = match context.chain.get_zones().into_iter().find(|zone| zone.name == data.zone) {
+        Some(zone) => zone,
+        None => return HttpResponse::error(400, "Unknown zone")
+    };
+    if zone.yggdrasil && !data.records.iter().all(is_yggdrasil_record) {
+        return HttpResponse::error(400, "Zone is Yggdrasil only");
+    }
+    if context.chain.is_waiting_signers() {
+        return HttpResponse::error(503, "Waiting for last full block to be signed");
+    }
+    if let Err(e) = context.chain.check_domain_change(u64::MAX, &transaction.identity, &transaction.pub_key, &data, Utc::now().timestamp()) {
+        return HttpResponse::error(403, &e);
+    }
+    let difficulty = context.chain.get_zone_difficulty(&data.zone);
+    let accepted = Accepted { identity: transaction.identity.clone(), zone: data.zone, difficulty };
+    let block = Block::new(Some(transaction), keystore.get_public(), Bytes::default(), difficulty);
+    std::mem::drop(context);
+    miner.lock().unwrap().add_block(block, keystore);
+    HttpResponse::json(&accepted)
+}
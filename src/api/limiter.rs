@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Length of the window in which requests are counted
+const WINDOW: Duration = Duration::from_secs(60);
+/// Stale entries are removed when there are more of them
+const MAX_CLIENTS: usize = 10000;
+
+/// Counts requests of every client IP in fixed one minute windows
+pub struct RateLimiter {
+    limit: u32,
+    clients: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    /// Creates limiter allowing `limit` requests per minute, zero means no limit
+    pub fn new(limit: u32) -> Self {
+        RateLimiter { limit, clients: Mutex::new(HashMap::new()) }
+    }
+
+    /// Counts the request and returns false if this client has made too many of them
+    pub fn check(&self, addr: IpAddr) -> bool {
+        if self.limit == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() > MAX_CLIENTS {
+            clients.retain(|_, (start, _)| now.duration_since(*start) < WINDOW);
+        }
+        let (start, count) = clients.entry(addr).or_insert((now, 0));
+        if now.duration_since(*start) >= WINDOW {
+            *start = now;
+            *count = 0;
+        }
+        *count += 1;
+        *count <= self.limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn limits() {
+        let limiter = RateLimiter::new(2);
+        let first = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let second = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        assert!(limiter.check(first));
+        assert!(limiter.check(first));
+        assert!(!limiter.check(first));
+        assert!(limiter.check(second));
+
+        let unlimited = RateLimiter::new(0);
+        assert!((0..100).all(|_| unlimited.check(first)));
+    }
+}
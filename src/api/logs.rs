@@ -0,0 +1,52 @@
+//! Changing log levels of the running node, to trace intermittent issues without restart.
+//! It is allowed only from the same machine and with the token of the API,
+//! so web pages that the owner opens can't change them.
+
+use std::net::IpAddr;
+
+use serde_json::{json, Value};
+
+use crate::api::http::{HttpRequest, HttpResponse};
+use crate::logging::{get_levels, LogLevels, parse_level, set_level};
+
+/// `GET /api/loglevel` gives current levels, `POST /api/loglevel?level=&target=` changes them
+pub fn log_level(request: &HttpRequest, ip: &IpAddr, token: &str) -> HttpResponse {
+    if !ip.is_loopback() {
+        return HttpResponse::error(403, "Only local requests are allowed");
+    }
+    if token.is_empty() {
+        return HttpResponse::error(403, "Token of the API is not set");
+    }
+    if !request.has_token(token) {
+        return HttpResponse::error(401, "Wrong or absent token");
+    }
+    let levels = match request.method.as_str() {
+        "GET" => get_levels(),
+        "POST" => {
+            let level = match request.get_param("level") {
+                Some(level) => level,
+                None => return HttpResponse::error(400, "No level given")
+            };
+            let (target, level) = match parse_level(level) {
+                Ok((None, level)) => (request.get_param("target").map(str::to_owned), level),
+                Ok((target, level)) => (target, level),
+                Err(e) => return HttpResponse::error(400, &e)
+            };
+            set_level(target.as_deref(), level)
+        }
+        _ => return HttpResponse::error(405, "Only GET and POST requests are allowed")
+    };
+    match levels {
+        Some(levels) => HttpResponse::json(&levels_to_json(&levels)),
+        None => HttpResponse::error(503, "Logger is not initialized")
+    }
+}
+
+/// Makes an object like `{"default":"INFO","targets":{"gis::p2p":"TRACE"}}`
+fn levels_to_json(levels: &LogLevels) -> Value {
+    let targets: serde_json::Map<String, Value> = levels.targets
+        .iter()
+        .map(|(target, level)| (target.clone(), Value::from(level.as_str())))
+        .collect();
+    json!({ "default": levels.default.as_str(), "targets": targets })
+}
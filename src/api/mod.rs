@@ -0,0 +1,171 @@
+//! HTTP API of the node, for explorers and other tools that don't speak DNS or P2P protocol.
+
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use crate::{Context, Miner};
+use crate::api::explorer::ChangeFeed;
+use crate::api::http::{HttpRequest, HttpResponse};
+use crate::api::limiter::RateLimiter;
+use crate::api::watchlist::Watchlist;
+use crate::event::Event;
+use crate::memory;
+use crate::dns::pool;
+use crate::p2p::metrics::NetworkMetrics;
+use crate::settings::Api;
+
+pub mod http;
+pub mod limiter;
+pub mod events;
+pub mod explorer;
+pub mod jobs;
+pub mod logs;
+pub mod watchlist;
+pub mod webhooks;
+
+/// Maximum number of connections handled at once
+const MAX_CONNECTIONS: usize = 64;
+/// Time to wait for the client to send its request
+const READ_TIMEOUT_SEC: u64 = 5;
+
+struct ApiServer {
+    context: Arc<Mutex<Context>>,
+    limiter: RateLimiter,
+    connections: AtomicUsize,
+    /// Clients that get events by WebSocket, they hold their connections
+    subscribers: AtomicUsize,
+    /// The last metrics, that network has posted
+    network: Arc<Mutex<NetworkMetrics>>,
+    /// Serials of changed zones, for clients waiting for changes
+    feed: Arc<ChangeFeed>,
+    miner: Arc<Mutex<Miner>>,
+    /// Secret for mining jobs, changing log levels, watched domains and private events, they are disabled if it is empty
+    token: String,
+    watchlist: Arc<Watchlist>,
+}
+
+/// Starts API server in a separate thread if it is enabled in settings, and webhooks if there are any
+pub fn start_api_server(context: &Arc<Mutex<Context>>, miner: &Arc<Mutex<Miner>>, settings: &Api) {
+    webhooks::start_webhooks(context, &settings.webhooks);
+    if settings.listen.is_empty() {
+        return;
+    }
+    let listener = match TcpListener::bind(&settings.listen) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Unable to start API server on {}: {}", &settings.listen, e);
+            return;
+        }
+    };
+    info!("API server is listening on {}", &settings.listen);
+    let network = Arc::new(Mutex::new(NetworkMetrics::default()));
+    let feed = Arc::new(ChangeFeed::default());
+    {
+        let network = Arc::clone(&network);
+        let feed = Arc::clone(&feed);
+        context.lock().unwrap().bus.register(move |_uuid, e| {
+            match e {
+                Event::NetworkMetrics { metrics } => *network.lock().unwrap() = metrics,
+                Event::DomainChanged { zone, serial, .. } => feed.update(zone, serial),
+                _ => {}
+            }
+            true
+        });
+    }
+    let limiter = RateLimiter::new(settings.rate_limit);
+    let miner = Arc::clone(miner);
+    let token = settings.token.clone();
+    let watchlist = watchlist::start_watchlist(context, &settings.watchlist_file);
+    let server = Arc::new(ApiServer { context: Arc::clone(context), limiter, connections: AtomicUsize::new(0), subscribers: AtomicUsize::new(0), network, feed, miner, token, watchlist });
+    let _ = thread::Builder::new().name(String::from("API server")).spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("Error accepting API connection: {}", e);
+                    continue;
+                }
+            };
+            if server.connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                server.connections.fetch_sub(1, Ordering::SeqCst);
+                let _ = HttpResponse::error(503, "Too many connections").write(&mut &stream);
+                continue;
+            }
+            let server = Arc::clone(&server);
+            let _ = thread::Builder::new().name(String::from("API connection")).spawn(move || {
+                server.handle_connection(stream);
+                server.connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+}
+
+impl ApiServer {
+    fn handle_connection(&self, mut stream: TcpStream) {
+        let addr = match stream.peer_addr() {
+            Ok(addr) => addr,
+            Err(_) => return
+        };
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(READ_TIMEOUT_SEC)));
+        let response = match HttpRequest::read(&stream) {
+            None => HttpResponse::error(400, "Bad request"),
+            Some(_) if !self.limiter.check(addr.ip()) => HttpResponse::error(429, "Too many requests"),
+            Some(request) if request.path == "/api/events" => {
+                if self.subscribers.fetch_add(1, Ordering::SeqCst) < events::MAX_SUBSCRIBERS {
+                    trace!("Events client connected from {}", &addr);
+                    events::serve_events(&self.context, &request, stream, &addr.ip(), &self.token);
+                    self.subscribers.fetch_sub(1, Ordering::SeqCst);
+                    return;
+                }
+                self.subscribers.fetch_sub(1, Ordering::SeqCst);
+                HttpResponse::error(503, "Too many clients of events")
+            }
+            Some(request) => {
+                trace!("API request from {}: {} {}", &addr, &request.method, &request.path);
+                self.route(&request, &addr.ip())
+            }
+        };
+        if let Err(e) = response.write(&mut stream) {
+            debug!("Error sending API response to {}: {}", &addr, e);
+        }
+    }
+
+    fn route(&self, request: &HttpRequest, ip: &IpAddr) -> HttpResponse {
+        // The only places that change something, they check the method and access themselves
+        if request.path == "/api/loglevel" {
+            return logs::log_level(request, ip, &self.token);
+        }
+        if request.path == "/api/jobs" {
+            return jobs::jobs(&self.context, &self.miner, &self.token, request);
+        }
+        if request.path == "/api/watchlist" {
+            return watchlist::watchlist(&self.context, &self.watchlist, request, ip, &self.token);
+        }
+        if request.method != "GET" {
+            return HttpResponse::error(405, "Only GET requests are allowed");
+        }
+        // Everything below only reads public data, so web pages of explorers can show it
+        let response = match request.path.as_str() {
+            "/api/blocks" => explorer::blocks(&self.context, request),
+            "/api/domains" => explorer::domains(&self.context, request),
+            "/api/orphans" => explorer::orphans(&self.context, request),
+            "/api/confusables" => explorer::confusables(&self.context, request),
+            "/api/zonestats" => explorer::zone_stats(&self.context, request),
+            "/api/changes" => explorer::changes(&self.context, &self.feed, request),
+            "/api/network" => HttpResponse::json(&*self.network.lock().unwrap()),
+            "/api/disk" => HttpResponse::json(&self.context.lock().unwrap().disk),
+            "/api/v1/zones" => explorer::zones_v1(&self.context),
+            path if path.starts_with("/api/v1/domain/") => explorer::domain_v1(&self.context, request),
+            path if path.starts_with("/api/v1/block/") => explorer::block_v1(&self.context, request),
+            "/metrics" => HttpResponse::text(memory::format_metrics() + &pool::format_metrics()),
+            _ => HttpResponse::error(404, "Not found")
+        };
+        response.with_cors()
+    }
+}
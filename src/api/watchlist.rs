@@ -0,0 +1,312 @@
+//! Watchlist of any domains, not only ours, for users waiting to register a lapsed name or depending on some service.
+//! Watched domains are checked on every change of domains and every minute, their registration, changes, coming
+//! expiration and expiration are posted to the bus as `WatchedDomain` events and sent to the webhook of the watch.
+
+use std::fs;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use chrono::Utc;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::Context;
+use crate::api::http::{HttpRequest, HttpResponse};
+use crate::api::webhooks::send;
+use crate::confusables::to_ascii;
+use crate::event::Event;
+
+/// How many domains can be watched at once
+const MAX_WATCHED: usize = 100;
+/// How long before expiration of a domain to warn about it
+const EXPIRY_WARNING_SEC: i64 = 7 * 86400;
+/// How often to check watched domains for expiration
+const WATCH_CHECK_INTERVAL_SEC: u64 = 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchKind {
+    /// The domain was free and is taken now
+    Registered,
+    /// Records of the domain were changed, or it was renewed
+    Changed,
+    /// The domain expires in less than a week
+    Expiring,
+    /// The domain has expired and can be registered by anyone
+    Expired,
+}
+
+impl WatchKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WatchKind::Registered => "registered",
+            WatchKind::Changed => "changed",
+            WatchKind::Expiring => "expiring",
+            WatchKind::Expired => "expired"
+        }
+    }
+}
+
+/// Body of webhook requests
+#[derive(Debug, Serialize)]
+struct WatchNotice {
+    domain: String,
+    kind: WatchKind,
+    /// Time of expiration, absent if the domain is free
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires: Option<i64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Watch {
+    domain: String,
+    /// Plain HTTP URL to send notices to, they are posted to the bus only if it is empty
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    webhook: String,
+    /// Time of expiration at the last check, None if the domain was free
+    #[serde(default)]
+    expires: Option<i64>,
+    /// Coming expiration is notified already
+    #[serde(default)]
+    warned: bool,
+}
+
+impl Watch {
+    /// Remembers the new expiration time of the domain, returns what has happened to it since the last check
+    fn update(&mut self, expires: Option<i64>, now: i64) -> Option<WatchKind> {
+        let kind = match (self.expires, expires) {
+            (None, Some(_)) => Some(WatchKind::Registered),
+            (Some(_), None) => Some(WatchKind::Expired),
+            (Some(old), Some(new)) if old != new => Some(WatchKind::Changed),
+            _ => None
+        };
+        if self.expires != expires {
+            self.expires = expires;
+            self.warned = false;
+        }
+        match expires {
+            Some(expires) if kind.is_none() && !self.warned && expires - now <= EXPIRY_WARNING_SEC => {
+                self.warned = true;
+                Some(WatchKind::Expiring)
+            }
+            _ => kind
+        }
+    }
+}
+
+pub struct Watchlist {
+    watches: Mutex<Vec<Watch>>,
+    /// File to keep watches in, empty to not keep them
+    filename: String,
+    /// Some domain has changed since the last check
+    changed: AtomicBool,
+}
+
+impl Watchlist {
+    fn load(filename: &str) -> Self {
+        let mut watches = Vec::new();
+        if !filename.is_empty() {
+            if let Ok(text) = fs::read_to_string(filename) {
+                match serde_json::from_str::<Vec<Watch>>(&text) {
+                    Ok(loaded) => {
+                        info!("Loaded {} watched domains from '{}'", loaded.len(), filename);
+                        watches = loaded;
+                    }
+                    Err(e) => warn!("Unable to parse watched domains from '{}': {}", filename, e)
+                }
+            }
+        }
+        Watchlist { watches: Mutex::new(watches), filename: filename.to_owned(), changed: AtomicBool::new(false) }
+    }
+
+    fn save(&self, watches: &[Watch]) {
+        if self.filename.is_empty() {
+            return;
+        }
+        match fs::write(&self.filename, serde_json::to_string(watches).unwrap()) {
+            Ok(_) => debug!("Saved {} watched domains to '{}'", watches.len(), &self.filename),
+            Err(e) => warn!("Unable to save watched domains to '{}': {}", &self.filename, e)
+        }
+    }
+
+    /// Checks all watched domains in the chain, and notifies about those that have changed
+    fn check(&self, context: &Arc<Mutex<Context>>) {
+        // The bus is posted to with locked context, so the watches are never locked together with it
+        let domains: Vec<String> = self.watches.lock().unwrap().iter().map(|watch| watch.domain.clone()).collect();
+        if domains.is_empty() {
+            return;
+        }
+        let expiries: Vec<Option<i64>> = {
+            let context = context.lock().unwrap();
+            domains.iter().map(|domain| context.chain.get_domain_expiry(domain)).collect()
+        };
+        let now = Utc::now().timestamp();
+        let mut notices = Vec::new();
+        {
+            let mut watches = self.watches.lock().unwrap();
+            for (domain, expires) in domains.into_iter().zip(expiries) {
+                // The watch could be removed while the chain was checked
+                if let Some(watch) = watches.iter_mut().find(|watch| watch.domain == domain) {
+                    if let Some(kind) = watch.update(expires, now) {
+                        notices.push((watch.webhook.clone(), WatchNotice { domain, kind, expires }));
+                    }
+                }
+            }
+            if !notices.is_empty() {
+                self.save(&watches);
+            }
+        }
+        for (webhook, notice) in notices {
+            info!("Watched domain {} is {}", &notice.domain, notice.kind.as_str());
+            let event = Event::WatchedDomain { domain: notice.domain.clone(), kind: notice.kind.as_str().to_owned(), expires: notice.expires };
+            context.lock().unwrap().bus.post(event);
+            if !webhook.is_empty() {
+                send(&webhook, &serde_json::to_vec(&notice).unwrap());
+            }
+        }
+    }
+}
+
+/// Loads watched domains and starts a thread that checks them
+pub fn start_watchlist(context: &Arc<Mutex<Context>>, filename: &str) -> Arc<Watchlist> {
+    let watchlist = Arc::new(Watchlist::load(filename));
+    {
+        let watchlist = Arc::clone(&watchlist);
+        context.lock().unwrap().bus.register(move |_uuid, e| {
+            if let Event::DomainChanged { .. } = e {
+                watchlist.changed.store(true, Ordering::SeqCst);
+            }
+            true
+        });
+    }
+    let context = Arc::clone(context);
+    let result = Arc::clone(&watchlist);
+    let _ = thread::Builder::new().name(String::from("Watchlist")).spawn(move || {
+        let mut seconds = WATCH_CHECK_INTERVAL_SEC;
+        loop {
+            thread::sleep(Duration::from_secs(1));
+            seconds += 1;
+            if !watchlist.changed.swap(false, Ordering::SeqCst) && seconds < WATCH_CHECK_INTERVAL_SEC {
+                continue;
+            }
+            seconds = 0;
+            watchlist.check(&context);
+        }
+    });
+    result
+}
+
+/// `GET /api/watchlist` gives watched domains, `POST /api/watchlist?domain=&webhook=` watches a domain,
+/// and `DELETE /api/watchlist?domain=` stops watching it
+pub fn watchlist(context: &Arc<Mutex<Context>>, watchlist: &Watchlist, request: &HttpRequest, ip: &IpAddr, token: &str) -> HttpResponse {
+    // Webhooks make the node send requests anywhere, so only the owner of the node can change them,
+    // and the token keeps web pages that the owner opens from doing it
+    if !ip.is_loopback() {
+        return HttpResponse::error(403, "Only local requests are allowed");
+    }
+    if token.is_empty() {
+        return HttpResponse::error(403, "Token of the API is not set");
+    }
+    if !request.has_token(token) {
+        return HttpResponse::error(401, "Wrong or absent token");
+    }
+    if request.method == "GET" {
+        return HttpResponse::json(&*watchlist.watches.lock().unwrap());
+    }
+    let domain = match request.get_param("domain").and_then(|domain| to_ascii(domain.trim_matches('.'))) {
+        Some(domain) if !domain.is_empty() => domain,
+        _ => return HttpResponse::error(400, "Parameter 'domain' is required")
+    };
+    match request.method.as_str() {
+        "POST" => {
+            let webhook = request.get_param("webhook").unwrap_or_default().to_owned();
+            if !webhook.is_empty() {
+                if let Err(e) = check_webhook(&webhook) {
+                    return HttpResponse::error(400, e);
+                }
+            }
+            let expires = context.lock().unwrap().chain.get_domain_expiry(&domain);
+            let mut watches = watchlist.watches.lock().unwrap();
+            match watches.iter().position(|watch| watch.domain == domain) {
+                Some(index) => watches[index].webhook = webhook,
+                None if watches.len() >= MAX_WATCHED => return HttpResponse::error(409, "Too many watched domains"),
+                None => watches.push(Watch { domain, webhook, expires, warned: false })
+            }
+            watchlist.save(&watches);
+            // Coming expiration of a new watch is told at the next check
+            watchlist.changed.store(true, Ordering::SeqCst);
+            HttpResponse::json(&*watches)
+        }
+        "DELETE" => {
+            let mut watches = watchlist.watches.lock().unwrap();
+            let count = watches.len();
+            watches.retain(|watch| watch.domain != domain);
+            if watches.len() == count {
+                return HttpResponse::error(404, "Domain is not watched");
+            }
+            watchlist.save(&watches);
+            HttpResponse::json(&*watches)
+        }
+        _ => HttpResponse::error(405, "Only GET, POST and DELETE requests are allowed")
+    }
+}
+
+/// Checks that the webhook is a plain HTTP URL with a host name or a unicast address
+fn check_webhook(url: &str) -> Result<(), &'static str> {
+    let rest = url.strip_prefix("http://").ok_or("Only plain HTTP webhooks are supported")?;
+    let host = rest.split('/').next().unwrap_or_default();
+    let valid = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '[' | ']');
+    if host.is_empty() || !host.chars().all(valid) {
+        return Err("Wrong host of webhook");
+    }
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default()
+    };
+    if let Ok(ip) = name.parse::<IpAddr>() {
+        let broadcast = matches!(ip, IpAddr::V4(ip) if ip.is_broadcast());
+        if ip.is_unspecified() || ip.is_multicast() || broadcast {
+            return Err("Wrong address of webhook");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transitions() {
+        let now = 1_700_000_000;
+        let mut watch = Watch { domain: String::from("lapsed.ygg"), webhook: String::new(), expires: None, warned: false };
+        assert_eq!(None, watch.update(None, now));
+        assert_eq!(Some(WatchKind::Registered), watch.update(Some(now + 86400 * 30), now));
+        assert_eq!(None, watch.update(Some(now + 86400 * 30), now));
+        assert_eq!(Some(WatchKind::Changed), watch.update(Some(now + 86400 * 10), now));
+        // Expiration is warned about only once
+        assert_eq!(Some(WatchKind::Expiring), watch.update(Some(now + 86400 * 10), now + 86400 * 4));
+        assert_eq!(None, watch.update(Some(now + 86400 * 10), now + 86400 * 5));
+        assert_eq!(Some(WatchKind::Expired), watch.update(None, now + 86400 * 10));
+        assert_eq!(None, watch.expires);
+        assert!(!watch.warned);
+    }
+
+    #[test]
+    fn webhooks() {
+        assert!(check_webhook("http://127.0.0.1:8080/hook").is_ok());
+        assert!(check_webhook("http://[::1]:8080/").is_ok());
+        assert!(check_webhook("http://hooks.example.com").is_ok());
+        assert!(check_webhook("https://hooks.example.com/").is_err());
+        assert!(check_webhook("file:///etc/passwd").is_err());
+        assert!(check_webhook("http://user@127.0.0.1/").is_err());
+        assert!(check_webhook("http:///hook").is_err());
+        assert!(check_webhook("http://0.0.0.0:80/").is_err());
+        assert!(check_webhook("http://255.255.255.255/").is_err());
+        assert!(check_webhook("http://[ff02::1]/").is_err());
+    }
+}
@@ -0,0 +1,143 @@
+//! Push notifications about changes of domains, for services like reverse proxies that reconfigure themselves.
+//! Every change is sent by POST request with JSON body to all webhooks that watch its zone or domain.
+
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde::Serialize;
+
+use crate::{Bytes, Context};
+use crate::api::http::post_json;
+use crate::blockchain::hash_utils::hash_identity;
+use crate::dns::protocol::DnsRecord;
+use crate::event::Event;
+use crate::settings::Webhook;
+
+/// How many times to send a change if the webhook doesn't answer
+const WEBHOOK_ATTEMPTS: u32 = 3;
+/// Pause between attempts
+const WEBHOOK_RETRY_SEC: u64 = 5;
+
+/// Body of webhook requests
+#[derive(Debug, Serialize)]
+struct DomainChange {
+    zone: String,
+    /// Name of the domain, if the webhook watches it, blocks have only hashes of names
+    #[serde(skip_serializing_if = "Option::is_none")]
+    domain: Option<String>,
+    identity: Bytes,
+    /// Index of the block with this change, it is the new serial of the zone
+    serial: u64,
+    records: Vec<DnsRecord>,
+}
+
+/// Webhook with hashes of its domains, to match them with blocks
+struct Subscription {
+    url: String,
+    zones: Vec<String>,
+    domains: Vec<(String, Bytes)>,
+}
+
+impl Subscription {
+    fn new(webhook: &Webhook) -> Self {
+        let zones = webhook.zones.iter().map(|zone| zone.trim_matches('.').to_lowercase()).collect();
+        let domains = webhook.domains
+            .iter()
+            .map(|domain| domain.trim_matches('.').to_lowercase())
+            .map(|domain| {
+                let identity = hash_identity(&domain, None);
+                (domain, identity)
+            })
+            .collect();
+        Subscription { url: webhook.url.clone(), zones, domains }
+    }
+
+    /// Checks if this change is watched, returns the name of the domain if it is known
+    fn matches(&self, zone: &str, identity: &Bytes) -> Option<Option<String>> {
+        if let Some((domain, _)) = self.domains.iter().find(|(_, id)| id == identity) {
+            return Some(Some(domain.clone()));
+        }
+        let all = self.zones.is_empty() && self.domains.is_empty();
+        if all || self.zones.iter().any(|z| z == zone) {
+            return Some(None);
+        }
+        None
+    }
+}
+
+/// Sends changes of domains to webhooks from settings, in a separate thread
+pub fn start_webhooks(context: &Arc<Mutex<Context>>, webhooks: &[Webhook]) {
+    if webhooks.is_empty() {
+        return;
+    }
+    let subscriptions: Vec<Subscription> = webhooks.iter().map(Subscription::new).collect();
+    let (sender, receiver) = channel();
+    {
+        // The context is locked while events are posted, so the block is read in the other thread
+        let sender = Mutex::new(sender);
+        context.lock().unwrap().bus.register(move |_uuid, e| {
+            if let Event::DomainChanged { zone, identity, serial } = e {
+                let _ = sender.lock().unwrap().send((zone, identity, serial));
+            }
+            true
+        });
+    }
+    let context = Arc::clone(context);
+    let _ = thread::Builder::new().name(String::from("Webhooks")).spawn(move || {
+        for (zone, identity, serial) in receiver {
+            let records = {
+                let context = context.lock().unwrap();
+                context.chain.get_block(serial)
+                    .and_then(|block| block.transaction)
+                    .and_then(|transaction| transaction.get_domain_data())
+                    .map(|data| data.records)
+            };
+            let records = match records {
+                Some(records) => records,
+                None => continue
+            };
+            for subscription in subscriptions.iter() {
+                if let Some(domain) = subscription.matches(&zone, &identity) {
+                    let change = DomainChange { zone: zone.clone(), domain, identity: identity.clone(), serial, records: records.clone() };
+                    send(&subscription.url, &serde_json::to_vec(&change).unwrap());
+                }
+            }
+        }
+    });
+}
+
+pub(crate) fn send(url: &str, body: &[u8]) {
+    for attempt in 1..=WEBHOOK_ATTEMPTS {
+        match post_json(url, body) {
+            Ok(status) if (200..300).contains(&status) => return,
+            Ok(status) => warn!("Webhook {} answered with status {}", url, status),
+            Err(e) => warn!("Unable to send change to webhook {}: {}", url, e)
+        }
+        if attempt < WEBHOOK_ATTEMPTS {
+            thread::sleep(Duration::from_secs(WEBHOOK_RETRY_SEC));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subscriptions() {
+        let webhook = Webhook { url: String::from("http://127.0.0.1/"), zones: vec![String::from("ygg")], domains: vec![String::from("example.anon.")] };
+        let subscription = Subscription::new(&webhook);
+        let example = hash_identity("example.anon", None);
+        let other = hash_identity("other.anon", None);
+        assert_eq!(Some(Some(String::from("example.anon"))), subscription.matches("anon", &example));
+        assert_eq!(None, subscription.matches("anon", &other));
+        assert_eq!(Some(None), subscription.matches("ygg", &other));
+
+        let all = Subscription::new(&Webhook { url: String::from("http://127.0.0.1/"), ..Webhook::default() });
+        assert_eq!(Some(None), all.matches("anon", &other));
+    }
+}
@@ -5,6 +5,7 @@ use std::fmt::Debug;
 use serde::{Serialize, Deserialize};
 use crate::bytes::Bytes;
 use crate::Transaction;
+use crate::blockchain::encoding::{encode_block, is_binary};
 use crate::blockchain::hash_utils::{hash_difficulty, key_hash_difficulty};
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
@@ -64,7 +65,12 @@ impl Block {
         self.index == 1 && self.transaction.is_none() && self.prev_block_hash == Bytes::default()
     }
 
+    /// Gets bytes for hashing and signing, they are binary for new blocks and JSON for old ones.
+    /// Blocks with too long hashes or keys can't be encoded, their empty bytes fail the hash and signature checks.
     pub fn as_bytes(&self) -> Vec<u8> {
+        if is_binary(self.version) {
+            return encode_block(self).unwrap_or_default();
+        }
         Vec::from(serde_json::to_string(&self).unwrap().as_bytes())
     }
 
@@ -0,0 +1,78 @@
+//! Bloom filter over identity hashes of domains and zones, most DNS queries are for names that are not in the chain,
+//! and the filter answers them without going to DB. It can say "maybe" for absent identity, but never "no" for a present one.
+
+/// Bits for every identity, with `HASHES` it gives about 1% of false positives
+const BITS_PER_ITEM: usize = 10;
+const HASHES: u64 = 7;
+const MIN_CAPACITY: usize = 1024;
+
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    /// How many identities are added
+    count: usize,
+    /// Beyond this count false positives become frequent, and the filter should be rebuilt bigger
+    capacity: usize,
+}
+
+impl BloomFilter {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(MIN_CAPACITY);
+        let words = (capacity * BITS_PER_ITEM + 63) / 64;
+        BloomFilter { bits: vec![0u64; words], count: 0, capacity }
+    }
+
+    /// Identities are hashes already, so their bytes are used instead of hashing them again
+    fn positions(&self, identity: &[u8]) -> impl Iterator<Item = usize> {
+        let mut buf = [0u8; 16];
+        let len = identity.len().min(16);
+        buf[..len].copy_from_slice(&identity[..len]);
+        let first = u64::from_le_bytes([buf[0], buf[1], buf[2], buf[3], buf[4], buf[5], buf[6], buf[7]]);
+        let second = u64::from_le_bytes([buf[8], buf[9], buf[10], buf[11], buf[12], buf[13], buf[14], buf[15]]) | 1;
+        let size = (self.bits.len() * 64) as u64;
+        (0..HASHES).map(move |i| (first.wrapping_add(i.wrapping_mul(second)) % size) as usize)
+    }
+
+    pub fn insert(&mut self, identity: &[u8]) {
+        let positions: Vec<usize> = self.positions(identity).collect();
+        for position in positions {
+            self.bits[position / 64] |= 1 << (position % 64);
+        }
+        self.count += 1;
+    }
+
+    /// Returns false if the identity is surely absent
+    pub fn may_contain(&self, identity: &[u8]) -> bool {
+        self.positions(identity).all(|position| self.bits[position / 64] & (1 << (position % 64)) != 0)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.count > self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blockchain::hash_utils::hash_identity;
+
+    use super::*;
+
+    #[test]
+    fn filter() {
+        let mut filter = BloomFilter::new(1000);
+        for i in 0..1000 {
+            filter.insert(&hash_identity(&format!("domain{}.ygg", i), None));
+        }
+        assert!(!filter.is_full());
+        assert!((0..1000).all(|i| filter.may_contain(&hash_identity(&format!("domain{}.ygg", i), None))));
+        let false_positives = (0..10000).filter(|i| filter.may_contain(&hash_identity(&format!("other{}.ygg", i), None))).count();
+        assert!(false_positives < 300, "Too many false positives: {}", false_positives);
+    }
+}
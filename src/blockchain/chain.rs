@@ -1,67 +1,108 @@
 use std::cell::RefCell;
-use std::collections::{HashSet, HashMap};
-use std::fs;
-use std::path::Path;
+use std::collections::{HashSet, HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::sync::Arc;
 
 use chrono::Utc;
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
-use sqlite::{Connection, State, Statement};
 
 use crate::{Block, Bytes, Keystore, Transaction, check_domain, get_domain_zone, is_yggdrasil_record};
 use crate::commons::constants::*;
-use crate::blockchain::types::{BlockQuality, MineResult, Options};
+use crate::blockchain::types::{BlockQuality, ChainCheck, MineResult, Options, BlockTemplate, OrphanBlock, DomainEntry, Reorg};
+use crate::blockchain::proof::{ChainProof, DomainProof, MAX_PROOF_BLOCKS};
 use crate::blockchain::types::BlockQuality::*;
 use crate::blockchain::hash_utils::*;
 use crate::settings::Settings;
+use crate::blockchain::consensus::{ConsensusParams, Schedule, get_upgrade_flag};
+use crate::blockchain::checkpoints::Checkpoints;
+use crate::blockchain::bloom::BloomFilter;
+use crate::blockchain::dns_view::DnsView;
+use crate::blockchain::encoding::is_binary;
+use crate::blockchain::schema::validate_data;
+use crate::blockchain::reorg::{Branches, MAX_REORG_DEPTH, is_better_branch};
+use crate::blockchain::storage::{BlockStorage, DomainReader, IdentityRow, open_storage};
+use crate::blockchain::snapshot::{read_block, read_header, write_block, write_header, SnapshotHeader};
+use crate::failure::fatal;
+use crate::failure::FailureKind::{Config, Database};
 use crate::keys::check_public_key_strength;
+use crate::confusables::{Confusable, ConfusableReport, get_confusable_variants, is_mixed_script, to_ascii, to_unicode};
+use crate::fingerprint::get_fingerprint;
 use std::cmp::max;
 use crate::blockchain::transaction::{ZoneData, DomainData};
+use crate::blockchain::transfer::Handover;
+use crate::blockchain::zone_stats::{get_zone_stats, ZoneStats, STATS_WEEKS};
 use std::ops::Deref;
 use crate::blockchain::types::MineResult::*;
 
-const TEMP_DB_NAME: &str = "temp.db";
-const SQL_CREATE_TABLES: &str = include_str!("sql/create_db.sql");
-const SQL_ADD_BLOCK: &str = "INSERT INTO blocks (id, timestamp, version, difficulty, random, nonce, 'transaction',\
-                          prev_block_hash, hash, pub_key, signature) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);";
-const SQL_GET_LAST_BLOCK: &str = "SELECT * FROM blocks ORDER BY id DESC LIMIT 1;";
-const SQL_TRUNCATE_BLOCKS: &str = "DELETE FROM blocks WHERE id >= ?;";
-const SQL_TRUNCATE_DOMAINS: &str = "DELETE FROM domains WHERE id >= ?;";
-const SQL_TRUNCATE_ZONES: &str = "DELETE FROM zones WHERE id >= ?;";
-
-const SQL_ADD_DOMAIN: &str = "INSERT INTO domains (id, timestamp, identity, confirmation, data, pub_key) VALUES (?, ?, ?, ?, ?, ?)";
-const SQL_ADD_ZONE: &str = "INSERT INTO zones (id, timestamp, identity, confirmation, data, pub_key) VALUES (?, ?, ?, ?, ?, ?)";
-const SQL_GET_BLOCK_BY_ID: &str = "SELECT * FROM blocks WHERE id=? LIMIT 1;";
-const SQL_GET_LAST_FULL_BLOCK: &str = "SELECT * FROM blocks WHERE id < ? AND `transaction`<>'' ORDER BY id DESC LIMIT 1;";
-const SQL_GET_LAST_FULL_BLOCK_FOR_KEY: &str = "SELECT * FROM blocks WHERE id < ? AND `transaction`<>'' AND pub_key = ? ORDER BY id DESC LIMIT 1;";
-const SQL_GET_DOMAIN_PUBLIC_KEY_BY_ID: &str = "SELECT pub_key FROM domains WHERE id < ? AND identity = ? LIMIT 1;";
-const SQL_GET_ZONE_PUBLIC_KEY_BY_ID: &str = "SELECT pub_key FROM zones WHERE id < ? AND identity = ? LIMIT 1;";
-const SQL_GET_DOMAIN_BY_ID: &str = "SELECT * FROM domains WHERE identity = ? ORDER BY id DESC LIMIT 1;";
-const SQL_GET_DOMAINS_BY_KEY: &str = "SELECT * FROM domains WHERE pub_key = ?;";
-const SQL_GET_ZONES: &str = "SELECT data FROM zones;";
-
-const SQL_GET_OPTIONS: &str = "SELECT * FROM options;";
+/// How many blocks are read from DB at once for snapshots
+const SNAPSHOT_BATCH: u64 = 1000;
 
 /// Max possible block index
 const MAX:u64 = i64::MAX as u64;
+/// How often the progress of `check_chain` is saved to DB
+const CHECK_PROGRESS_INTERVAL: u64 = 100;
+/// How many last blocks are checked for upgrade signals
+const UPGRADE_SIGNALS_WINDOW: u64 = 100;
+/// How many pseudo-random block indexes are looked up in one query when choosing signers
+const SIGNERS_BATCH: u64 = 16;
+/// How many signer sets of full blocks are kept in memory
+const SIGNERS_CACHE_SIZE: usize = 32;
+/// How many blocks are committed at once while syncing
+const SYNC_BATCH: u64 = 100;
+/// How many verdicts of hash and signature checks are kept
+const VERIFIED_CACHE_SIZE: usize = 1024;
 
 pub struct Chain {
     origin: Bytes,
     last_block: Option<Block>,
     last_full_block: Option<Block>,
     max_height: u64,
-    db: Connection,
+    storage: Box<dyn BlockStorage>,
+    /// Count of added blocks in the open transaction, see `commit_blocks`
+    uncommitted: u64,
+    /// Identities of all domains and zones, to answer about absent ones without DB, None if it couldn't be loaded
+    identities: Option<BloomFilter>,
     zones: RefCell<HashSet<String>>,
     signers: RefCell<SignersCache>,
+    /// Blocks come again from other peers and forks, their hashes and signatures are checked only once
+    verified: RefCell<VerifiedBlocks>,
+    /// Names of our domains are decrypted once, see `get_my_domains`
+    my_domains: RefCell<MyDomains>,
+    /// Whether our key signs the last full block, see `update_signers`
+    sign_duty: Option<SignDuty>,
+    /// Domains and zones for DNS threads, kept in memory after the first request, see `get_dns_view`
+    dns_view: Option<Arc<DnsView>>,
+    /// Serials of zones that were asked for, see `get_zone_serial`
+    zone_serials: RefCell<HashMap<String, u64>>,
+    archive_orphans: bool,
+    auto_recover: bool,
+    db_name: String,
+    params: ConsensusParams,
+    /// Parameters for every height where upgrades change them
+    schedule: Schedule,
+    checkpoints: Checkpoints,
+    /// Blocks that don't follow ours, they can make a better branch, see `reorg`
+    branches: Branches,
 }
 
 impl Chain {
     pub fn new(settings: &Settings, db_name: &str) -> Self {
         let origin = settings.get_origin();
 
-        let db = sqlite::open(db_name).expect("Unable to open blockchain DB");
+        let storage = open_storage(settings, db_name);
         let zones = RefCell::new(HashSet::new());
-        let mut chain = Chain { origin, last_block: None, last_full_block: None, max_height: 0, db, zones, signers: SignersCache::new() };
+        let archive_orphans = settings.db.archive_orphans;
+        let auto_recover = settings.db.auto_recover;
+        let db_name = db_name.to_owned();
+        let params = settings.chain.get_params().unwrap_or_else(|e| fatal(Config, &format!("Wrong [chain] settings: {}", e)));
+        let schedule = params.get_schedule().unwrap_or_else(|e| fatal(Config, &format!("Wrong [chain] settings: {}", e)));
+        let checkpoints = settings.chain.get_checkpoints(&origin);
+        if !checkpoints.is_empty() {
+            debug!("Using checkpoints up to block {}", checkpoints.get_last_index());
+        }
+        let mut chain = Chain { origin, last_block: None, last_full_block: None, max_height: 0, storage, uncommitted: 0, identities: None, zones, signers: SignersCache::new(), verified: RefCell::new(VerifiedBlocks::new(VERIFIED_CACHE_SIZE)), my_domains: RefCell::new(MyDomains::default()), sign_duty: None, dns_view: None, zone_serials: RefCell::new(HashMap::new()), archive_orphans, auto_recover, db_name, params, schedule, checkpoints, branches: Branches::default() };
         chain.init_db();
         chain
     }
@@ -70,7 +111,8 @@ impl Chain {
     fn init_db(&mut self) {
         let options = self.get_options();
         if !self.origin.is_zero() && !options.origin.is_empty() && self.origin.to_string() != options.origin {
-            self.clear_db();
+            let origin = self.origin.clone();
+            self.reset(origin).expect("Error resetting DB");
         }
         if options.version < DB_VERSION {
             self.migrate_db(options.version, DB_VERSION);
@@ -87,17 +129,58 @@ impl Chain {
                 self.last_full_block = self.get_last_full_block(MAX, None);
             }
         }
+        self.load_identities();
+    }
+
+    /// Fills the filter of identities from DB, with the room for as many new ones
+    fn load_identities(&mut self) {
+        self.identities = match self.storage.get_identities() {
+            Ok(identities) => {
+                let mut filter = BloomFilter::new(identities.len() * 2);
+                for identity in &identities {
+                    filter.insert(identity);
+                }
+                debug!("Loaded {} identities to filter", filter.len());
+                Some(filter)
+            }
+            Err(e) => {
+                warn!("Unable to load identities, every lookup goes to DB: {}", e);
+                None
+            }
+        };
+    }
+
+    /// Returns false if there is surely no domain or zone with this identity
+    fn may_have_id(&self, identity: &Bytes) -> bool {
+        match &self.identities {
+            Some(filter) => filter.may_contain(identity),
+            None => true
+        }
     }
 
+    /// Checks last `count` blocks, truncating the chain from the first bad one.
+    /// If previous check was interrupted it is resumed from the last verified block.
     pub fn check_chain(&mut self, count: u64) {
+        let mut check = self.start_check(count);
+        while !self.continue_check(&mut check, CHECK_PROGRESS_INTERVAL) {}
+    }
+
+    /// Prepares the check of last `count` blocks, that is done by parts with `continue_check`.
+    /// If previous check was interrupted it is resumed from the last verified block.
+    pub fn start_check(&mut self, count: u64) -> ChainCheck {
         let height = self.get_height();
-        let start = if height > count {
+        let mut start = if height > count {
             info!("Checking last {} blocks...", count);
             height - count + 1
         } else {
             info!("Local blockchain height is {}, starting full blockchain check...", height);
             1
         };
+        let checked = self.get_options().checked;
+        if checked >= start && checked <= height {
+            info!("Resuming interrupted check after block {}", checked);
+            start = checked + 1;
+        }
         let mut last_block: Option<Block> = None;
         let mut last_full_block: Option<Block> = None;
         if start > 1 {
@@ -109,155 +192,479 @@ impl Chain {
                 };
             }
         }
+        ChainCheck { next: start, height, last_block, last_full_block }
+    }
 
-        for id in start..=height {
+    /// Checks up to `count` next blocks of the `check`, returns true when the check is finished.
+    /// Blocks that are added meanwhile are not checked, they have been checked on arrival.
+    pub fn continue_check(&mut self, check: &mut ChainCheck, count: u64) -> bool {
+        let end = check.height.min(check.next.saturating_add(count) - 1);
+        for id in check.next..=end {
             debug!("Checking block {}", id);
-            let block = self.get_block(id);
-            match block {
+            let block = match self.get_block(id) {
+                Some(block) => block,
+                // The chain was rewound by a fork while we were checking, the rest is checked already
+                None if id > self.get_height() => break,
                 None => {
-                    panic!("Blockchain is corrupted! Please, delete 'guachain.db' and restart.");
+                    self.recover_db("Blockchain is corrupted!");
+                    check.next = check.height + 1;
+                    return true;
                 }
-                Some(block) => {
-                    if block.index == 1 {
-                        if block.hash != self.origin {
-                            panic!("Loaded DB is not of origin {:?}! Please, delete 'guachain.db' and restart.", &self.origin);
-                        }
-                        debug!("Block {} with hash {:?} is good!", block.index, &block.hash);
-                        last_block = Some(block);
-                        continue;
-                    }
+            };
+            check.next = id + 1;
+            if block.index == 1 {
+                if block.hash != self.origin {
+                    self.recover_db(&format!("Loaded DB is not of origin {:?}!", &self.origin));
+                    check.next = check.height + 1;
+                    return true;
+                }
+                debug!("Block {} with hash {:?} is good!", block.index, &block.hash);
+                check.last_block = Some(block);
+                continue;
+            }
 
-                    //let last = self.last_block.clone().unwrap();
-                    if self.check_block(&block, &last_block, &last_full_block) != BlockQuality::Good {
-                        error!("Block {} is bad:\n{:?}", block.index, &block);
-                        info!("Truncating database from block {}...", block.index);
-                        match self.truncate_db_from_block(block.index) {
-                            Ok(_) => {}
-                            Err(e) => {
-                                error!("{}", e);
-                                panic!("Error truncating database! Please, delete 'guachain.db' and restart.");
-                            }
-                        }
-                        break;
-                    }
-                    debug!("Block {} with hash {:?} is good!", block.index, &block.hash);
-                    if block.transaction.is_some() {
-                        self.last_full_block = Some(block.clone());
-                    }
-                    if block.transaction.is_some() {
-                        last_full_block = Some(block.clone());
-                    }
-                    last_block = Some(block);
+            if self.check_block(&block, &check.last_block, &check.last_full_block) != BlockQuality::Good {
+                error!("Block {} is bad:\n{:?}", block.index, &block);
+                info!("Truncating database from block {}...", block.index);
+                if let Err(e) = self.truncate_db_from_block(block.index, "bad block") {
+                    error!("{}", e);
+                    self.recover_db("Error truncating database!");
+                    check.next = check.height + 1;
+                    return true;
                 }
+                check.next = check.height + 1;
+                break;
+            }
+            debug!("Block {} with hash {:?} is good!", block.index, &block.hash);
+            if block.index % CHECK_PROGRESS_INTERVAL == 0 {
+                self.set_option("checked", Some(&block.index.to_string()));
+            }
+            if block.transaction.is_some() {
+                check.last_full_block = Some(block.clone());
             }
+            check.last_block = Some(block);
+        }
+        if check.next <= check.height && check.next <= self.get_height() {
+            return false;
         }
+        check.next = check.height + 1;
+        self.set_option("checked", None);
+        self.log_upgrades();
         self.last_block = self.load_last_block();
         self.last_full_block = self.get_last_full_block(MAX, None);
         debug!("Last block after chain check: {:?}", &self.last_block);
+        true
     }
 
-    fn truncate_db_from_block(&mut self, index: u64) -> sqlite::Result<State> {
-        let mut statement = self.db.prepare(SQL_TRUNCATE_BLOCKS)?;
-        statement.bind(1, index as i64)?;
-        statement.next()?;
+    /// Shows pending upgrades and how many of recent blocks are mined by nodes that know about them
+    fn log_upgrades(&self) {
+        let height = self.get_height();
+        for upgrade in self.params.upgrades.iter().filter(|upgrade| upgrade.height > height) {
+            let signals = self.count_upgrade_signals(upgrade.bit, UPGRADE_SIGNALS_WINDOW);
+            info!("Upgrade '{}' activates at block {}, {} of last {} blocks support it", &upgrade.name, upgrade.height, signals, UPGRADE_SIGNALS_WINDOW.min(height));
+        }
+    }
 
-        let mut statement = self.db.prepare(SQL_TRUNCATE_DOMAINS)?;
-        statement.bind(1, index as i64)?;
-        statement.next()?;
+    /// Moves corrupted DB aside (for diagnostics) and starts with an empty one, to resync from peers.
+    /// If `db.auto_recover` is not enabled it just panics with the `message`, memory DB is always recovered.
+    fn recover_db(&mut self, message: &str) {
+        if !self.auto_recover && !self.is_in_memory() {
+            fatal(Database, &format!("{} Please, delete '{}' and restart.", message, &self.db_name));
+        }
+        self.uncommitted = 0;
+        match self.is_in_memory() {
+            true => error!("{} Resyncing from scratch...", message),
+            false => error!("{} Moving database aside and resyncing from scratch...", message)
+        }
+        match self.storage.recover() {
+            Ok(Some(backup)) => warn!("Corrupted database is saved as '{}'", &backup),
+            Ok(None) => {}
+            Err(e) => fatal(Database, &format!("Unable to recover database! {}", e))
+        }
+        self.last_block = None;
+        self.last_full_block = None;
+        self.identities = Some(BloomFilter::new(0));
+        self.zones.borrow_mut().clear();
+        self.signers.borrow_mut().clear();
+        self.zone_serials.borrow_mut().clear();
+        if let Some(view) = &self.dns_view {
+            view.clear();
+        }
+    }
 
-        let mut statement = self.db.prepare(SQL_TRUNCATE_ZONES)?;
-        statement.bind(1, index as i64)?;
-        statement.next()
+    fn truncate_db_from_block(&mut self, index: u64, reason: &str) -> Result<(), String> {
+        self.signers.borrow_mut().truncate(index);
+        self.zone_serials.borrow_mut().clear();
+        self.storage.truncate(index, reason)?;
+        if let Some(view) = &self.dns_view {
+            view.truncate(index, &*self.storage);
+        }
+        Ok(())
     }
 
     fn load_last_block(&mut self) -> Option<Block> {
-        match self.db.prepare(SQL_GET_LAST_BLOCK) {
-            Ok(mut statement) => {
-                let mut result = None;
-                while statement.next().unwrap() == State::Row {
-                    match Self::get_block_from_statement(&mut statement) {
-                        None => {
-                            error!("Something wrong with block in DB!");
-                            panic!();
-                        }
-                        Some(block) => {
-                            debug!("Loaded last block: {:?}", &block);
-                            result = Some(block);
-                            break;
-                        }
-                    }
-                }
-                result
-            }
-            Err(e) => {
-                info!("No blockchain database found. Creating new. {}", e);
-                self.db.execute(SQL_CREATE_TABLES).expect("Error creating DB tables");
-                None
-            }
+        let block = self.storage.get_last_block();
+        if let Some(block) = &block {
+            debug!("Loaded last block: {:?}", block);
         }
+        block
     }
 
     fn migrate_db(&mut self, from: u32, to: u32) {
         debug!("Migrating DB from {} to {}", from, to);
     }
 
-    fn clear_db(&mut self) {
-        warn!("Clearing DB");
-        // We cannot close DB connection and recreate file,
-        // therefore we switch our db to temporary file, delete main DB and switch back.
-        // I know that this is a crutch, but this way I don't need to use Option<db> :)
-        self.db = sqlite::open(TEMP_DB_NAME).expect("Unable to open temporary blockchain DB");
-        let file = Path::new(DB_NAME);
-        if fs::remove_file(&file).is_err() {
-            panic!("Unable to remove database!");
+    /// Deletes all blocks, domains and zones, and starts an empty chain with this origin.
+    pub fn reset(&mut self, origin: Bytes) -> Result<(), String> {
+        warn!("Resetting DB to origin {:?}", &origin);
+        self.commit_blocks();
+        self.storage.reset(&origin.to_string())?;
+        // Bundled checkpoints are only for the origin from settings
+        if self.origin != origin {
+            self.checkpoints = Checkpoints::default();
         }
-        self.db = sqlite::open(DB_NAME).expect("Unable to open blockchain DB");
-        let file = Path::new(TEMP_DB_NAME);
-        let _ = fs::remove_file(&file).is_err();
+        self.origin = origin;
+        self.last_block = None;
+        self.last_full_block = None;
+        self.max_height = 0;
+        self.identities = Some(BloomFilter::new(0));
+        self.zones.borrow_mut().clear();
+        self.signers.borrow_mut().clear();
+        self.zone_serials.borrow_mut().clear();
+        if let Some(view) = &self.dns_view {
+            view.clear();
+        }
+        Ok(())
+    }
+
+    /// Memory DB has no files to move or delete
+    fn is_in_memory(&self) -> bool {
+        self.db_name == MEMORY_DB_NAME
     }
 
     fn get_options(&self) -> Options {
         let mut options = Options::empty();
-        if let Ok(mut statement) = self.db.prepare(SQL_GET_OPTIONS) {
-            while let State::Row = statement.next().unwrap() {
-                let name = statement.read::<String>(0).unwrap();
-                let value = statement.read::<String>(1).unwrap();
-                match name.as_ref() {
-                    "origin" => options.origin = value,
-                    "version" => options.version = value.parse().unwrap(),
-                    _ => {}
-                }
+        for (name, value) in self.storage.get_options() {
+            match name.as_ref() {
+                "origin" => options.origin = value,
+                "version" => options.version = value.parse().unwrap(),
+                "checked" => options.checked = value.parse().unwrap_or(0),
+                _ => {}
             }
         }
         options
     }
 
+    /// Replaces the value of option, or removes it if `value` is None
+    fn set_option(&self, name: &str, value: Option<&str>) {
+        if let Err(e) = self.storage.set_option(name, value) {
+            warn!("Error saving option '{}': {}", name, e);
+        }
+    }
+
     pub fn add_block(&mut self, block: Block) {
         debug!("Adding block:\n{:?}", &block);
         let index = block.index;
-        let timestamp = block.timestamp;
-        self.last_block = Some(block.clone());
+        // Block and its transaction are written in one transaction, and while syncing many blocks are committed at once
+        if self.uncommitted == 0 {
+            if let Err(e) = self.storage.begin() {
+                warn!("Unable to begin DB transaction: {}", e);
+            }
+        }
+        self.uncommitted += 1;
+        self.write_block(block);
+        if self.uncommitted >= SYNC_BATCH || index >= self.max_height {
+            self.commit_blocks();
+        }
+    }
+
+    /// Writes the block to DB in the open transaction and updates caches
+    fn write_block(&mut self, block: Block) {
+        let index = block.index;
+        if let Err(e) = self.storage.add_block(&block) {
+            error!("Error adding block {} to DB: {}", index, e);
+        } else if let Some(transaction) = &block.transaction {
+            if let Some(data) = transaction.get_domain_data() {
+                self.zone_serials.borrow_mut().insert(data.zone, index);
+            }
+            self.my_domains.borrow_mut().invalidate(&block.pub_key, &transaction.pub_key);
+            if let Some(view) = &self.dns_view {
+                view.add_transaction(index, block.timestamp, transaction, self.get_params().domain_lifetime);
+            }
+            if let Some(filter) = &mut self.identities {
+                filter.insert(&transaction.identity);
+                if filter.is_full() {
+                    self.load_identities();
+                }
+            }
+        }
         if block.transaction.is_some() {
             self.last_full_block = Some(block.clone());
         }
-        let transaction = block.transaction.clone();
-        if self.add_block_to_table(block).is_ok() {
-            if let Some(transaction) = transaction {
-                self.add_transaction_to_table(index, timestamp, &transaction).expect("Error adding transaction");
+        self.last_block = Some(block);
+    }
+
+    /// Commits blocks that are added while syncing, it is called periodically in case the sync has stalled
+    pub fn commit_blocks(&mut self) {
+        if self.uncommitted == 0 {
+            return;
+        }
+        debug!("Committing {} blocks to DB", self.uncommitted);
+        if let Err(e) = self.storage.commit() {
+            error!("Unable to commit blocks to DB: {}", e);
+        }
+        self.uncommitted = 0;
+    }
+
+    /// Keeps the block that doesn't follow our chain, it can be a part of a better branch, see `reorg`
+    pub fn add_branch_block(&mut self, block: Block) {
+        let height = self.get_height();
+        if block.index + MAX_REORG_DEPTH > height {
+            self.branches.forget_below(height.saturating_sub(MAX_REORG_DEPTH));
+            self.branches.add(block);
+        }
+    }
+
+    /// Switches to the branch that starts from this forked block, if it has more work than our blocks from the same index.
+    /// Blocks of the branch are checked on top of our blocks before the fork, and if some of them is bad, our blocks are restored.
+    /// Everything is done in one DB transaction, so readers of DB see either our blocks or the whole branch.
+    /// Until `branch_reorgs` is enabled at the index of the fork, the original rule of `replace_block` is used.
+    pub fn reorg(&mut self, block: Block) -> Result<Option<Reorg>, String> {
+        let index = block.index;
+        let height = self.get_height();
+        if !self.params_at(index).branch_reorgs {
+            return self.replace_block(block);
+        }
+        if index == 0 || index + MAX_REORG_DEPTH <= height {
+            warn!("Ignoring fork at block {}, it is deeper than {} blocks", index, MAX_REORG_DEPTH);
+            return Ok(None);
+        }
+        let branch = self.branches.get_branch(block);
+        let ours = self.get_blocks(index, height + 1 - index, false);
+        if !is_better_branch(&branch, &ours) {
+            debug!("Keeping our {} blocks from {} instead of the branch of {} blocks", ours.len(), index, branch.len());
+            return Ok(None);
+        }
+        info!("Switching to the branch of {} blocks from block {}, replacing {} blocks", branch.len(), index, ours.len());
+        self.commit_blocks();
+        self.storage.begin()?;
+        let result = self.switch_to_branch(index, &branch, &ours);
+        self.branches.remove(&branch);
+        let added = match result {
+            Ok(Some(added)) => added,
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                self.rollback_blocks(&e);
+                return Err(e);
             }
+        };
+        self.forget_my_domains(&ours);
+        Ok(Some(Reorg { index, removed: ours, added }))
+    }
+
+    /// The original rule of forks, until branches are compared by their work:
+    /// the forked block replaces our blocks from its index, if it is better than our last block
+    fn replace_block(&mut self, block: Block) -> Result<Option<Reorg>, String> {
+        let last = match &self.last_block {
+            Some(last) if block.is_better_than(last) => last.clone(),
+            _ => return Ok(None)
+        };
+        let index = block.index;
+        let ours = self.get_blocks(index, last.index + 1 - index, false);
+        warn!("Replacing {} blocks from block {} with:\n{:?}", ours.len(), index, &block);
+        self.commit_blocks();
+        self.storage.begin()?;
+        let result = self.rewind_to(index, "fork").and_then(|_| {
+            self.write_block(block.clone());
+            self.storage.commit()
+        });
+        if let Err(e) = result {
+            self.rollback_blocks(&e);
+            return Err(e);
         }
+        self.forget_my_domains(&ours);
+        Ok(Some(Reorg { index, removed: ours, added: vec![block] }))
     }
 
-    pub fn replace_block(&mut self, block: Block) -> sqlite::Result<()> {
-        warn!("Replacing block {} with:\n{:?}", block.index, &block);
+    /// Our domains that were in removed blocks are not ours anymore, added blocks have done it in `write_block`
+    fn forget_my_domains(&self, removed: &[Block]) {
+        for block in removed {
+            if let Some(transaction) = &block.transaction {
+                self.my_domains.borrow_mut().invalidate(&block.pub_key, &transaction.pub_key);
+            }
+        }
+    }
+
+    /// Replaces our blocks from `index` with the branch in the open transaction, and commits it.
+    /// Returns added blocks, or nothing if our blocks are restored because the branch has bad blocks.
+    fn switch_to_branch(&mut self, index: u64, branch: &[Block], ours: &[Block]) -> Result<Option<Vec<Block>>, String> {
+        self.rewind_to(index, "fork")?;
+        let mut added = Vec::new();
+        for block in branch {
+            if self.check_new_block(block) != Good {
+                warn!("Block {} of the branch is bad", block.index);
+                break;
+            }
+            self.write_block(block.clone());
+            added.push(block.clone());
+        }
+        // The part of the branch before its bad block is taken only if it is still better than ours
+        if !is_better_branch(&added, ours) {
+            warn!("Branch from block {} is not better without its bad blocks, restoring our blocks", index);
+            self.rewind_to(index, "bad branch")?;
+            for block in ours {
+                self.write_block(block.clone());
+            }
+            self.storage.commit()?;
+            return Ok(None);
+        }
+        self.storage.commit()?;
+        Ok(Some(added))
+    }
+
+    /// Drops the open transaction after an error, and reloads everything that was changed in memory along with it
+    fn rollback_blocks(&mut self, error: &str) {
+        error!("Error switching to another branch: {}, rolling back", error);
+        if let Err(e) = self.storage.rollback() {
+            self.recover_db(&format!("Unable to roll back database transaction! {}", e));
+            return;
+        }
+        self.last_block = self.load_last_block();
+        self.last_full_block = self.get_last_full_block(MAX, None);
         self.signers.borrow_mut().clear();
-        self.truncate_db_from_block(block.index)?;
-        self.add_block(block);
+        self.zone_serials.borrow_mut().clear();
+        if let Some(view) = &self.dns_view {
+            view.rebuild(&*self.storage, self.get_params().domain_lifetime);
+        }
+    }
+
+    /// Removes blocks from `index` in the open transaction, and takes the last ones that are left
+    fn rewind_to(&mut self, index: u64, reason: &str) -> Result<(), String> {
+        self.truncate_db_from_block(index, reason)?;
+        self.last_block = self.load_last_block();
+        self.last_full_block = self.get_last_full_block(MAX, None);
         Ok(())
     }
 
+    /// Gets archived blocks with indexes in range [from; to], see `Db::archive_orphans` setting
+    pub fn get_orphan_blocks(&self, from: u64, to: u64) -> Vec<OrphanBlock> {
+        if !self.archive_orphans {
+            return Vec::new();
+        }
+        self.storage.get_orphans(from, to)
+    }
+
+    /// Gets up to `limit` blocks starting from index `from`, only blocks with transactions if `full_only` is set
+    pub fn get_blocks(&self, from: u64, limit: u64, full_only: bool) -> Vec<Block> {
+        self.storage.get_blocks(from, limit, full_only)
+    }
+
+    /// Writes all blocks to a snapshot file, returns their count
+    pub fn export_snapshot(&self, path: &str) -> Result<u64, String> {
+        let count = self.get_height();
+        let origin = match self.get_block(1) {
+            Some(block) => block.hash,
+            None => return Err(String::from("There are no blocks to export"))
+        };
+        let file = File::create(path).map_err(|e| format!("Unable to create '{}': {}", path, e))?;
+        let mut writer = BufWriter::new(file);
+        let error = |e: std::io::Error| format!("Unable to write '{}': {}", path, e);
+        write_header(&mut writer, &SnapshotHeader { origin, count }).map_err(error)?;
+        let mut next = 1;
+        while next <= count {
+            let blocks = self.get_blocks(next, SNAPSHOT_BATCH.min(count - next + 1), false);
+            if blocks.is_empty() {
+                break;
+            }
+            for block in blocks {
+                if block.index != next {
+                    return Err(format!("Block {} is missing in DB", next));
+                }
+                write_block(&mut writer, &block).map_err(error)?;
+                next += 1;
+            }
+        }
+        if next <= count {
+            return Err(format!("Block {} is missing in DB", next));
+        }
+        writer.flush().map_err(error)?;
+        Ok(count)
+    }
+
+    /// Adds blocks from a snapshot file after the same checks as blocks from peers, returns the count of added blocks.
+    /// Blocks that we already have must be the same, snapshots don't rewind the chain.
+    pub fn import_snapshot(&mut self, path: &str) -> Result<u64, String> {
+        let file = File::open(path).map_err(|e| format!("Unable to open '{}': {}", path, e))?;
+        let mut reader = BufReader::new(file);
+        let header = read_header(&mut reader).map_err(|e| format!("Unable to read '{}': {}", path, e))?;
+        if !self.origin.is_zero() && header.origin != self.origin {
+            return Err(format!("Snapshot is of another chain with origin {}", header.origin.to_string()));
+        }
+        let mut added = 0;
+        for _ in 0..header.count {
+            let block = read_block(&mut reader).map_err(|e| format!("Unable to read '{}': {}", path, e))?;
+            if block.index == 1 && block.hash != header.origin {
+                return Err(String::from("First block is not the origin of the snapshot"));
+            }
+            if block.index <= self.get_height() {
+                match self.get_block(block.index) {
+                    Some(ours) if ours.hash == block.hash => continue,
+                    _ => return Err(format!("Block {} differs from ours", block.index))
+                }
+            }
+            if self.check_new_block(&block) != Good {
+                return Err(format!("Block {} is not valid", block.index));
+            }
+            self.add_block(block);
+            added += 1;
+            if added % SNAPSHOT_BATCH == 0 {
+                info!("Imported {} blocks", added);
+            }
+        }
+        Ok(added)
+    }
+
+    /// Gets up to `limit` domain transactions in some zone, starting from block index `from`.
+    /// Every change of a domain is a separate entry, expired ones are included too.
+    pub fn get_zone_domains(&self, zone: &str, from: u64, limit: usize) -> Vec<DomainEntry> {
+        let mut result = Vec::new();
+        self.storage.for_each_domain(Some(from), &mut |row| {
+            if result.len() >= limit {
+                return false;
+            }
+            if let Ok(data) = serde_json::from_str::<DomainData>(&row.transaction.data) {
+                if data.zone == zone {
+                    let transaction = &row.transaction;
+                    result.push(DomainEntry { index: row.index, timestamp: row.timestamp, identity: transaction.identity.clone(), pub_key: transaction.pub_key.clone(), data });
+                }
+            }
+            result.len() < limit
+        });
+        result
+    }
+
+    /// Gets the serial of some zone, it is the index of the last block with a domain in this zone, or 0 if there are none.
+    /// It grows with every change in the zone, so consumers can ask for changes since the serial they have.
+    pub fn get_zone_serial(&self, zone: &str) -> u64 {
+        if let Some(serial) = self.zone_serials.borrow().get(zone) {
+            return *serial;
+        }
+        let mut serial = 0;
+        self.storage.for_each_domain(None, &mut |row| {
+            match serde_json::from_str::<DomainData>(&row.transaction.data) {
+                Ok(data) if data.zone == zone => {
+                    serial = row.index;
+                    false
+                }
+                _ => true
+            }
+        });
+        self.zone_serials.borrow_mut().insert(zone.to_owned(), serial);
+        serial
+    }
+
     pub fn get_sign_block(&self, keystore: &Option<Keystore>) -> Option<Block> {
-        if self.get_height() < BLOCK_SIGNERS_START {
+        if self.get_height() < self.get_params().signers_start {
             trace!("Too early to start block signings");
             return None;
         }
@@ -276,7 +683,7 @@ impl Chain {
         };
         // TODO maybe make some config option to mine signing blocks above?
         let sign_count = self.get_height() - block.index;
-        if sign_count >= BLOCK_SIGNERS_MIN {
+        if sign_count >= self.get_params().signers_min {
             trace!("Block {} has enough signing blocks", block.index);
             return None;
         }
@@ -291,31 +698,56 @@ impl Chain {
             None => { return None; }
         };
 
-        let keystore = keystore.clone().unwrap().clone();
-        let signers: HashSet<Bytes> = self.get_block_signers(&block).into_iter().collect();
-        if signers.contains(&keystore.get_public()) {
+        let pub_key = keystore.as_ref().unwrap().get_public();
+        let signer = match &self.sign_duty {
+            Some(duty) if duty.hash == block.hash && duty.pub_key == pub_key => duty.signer,
+            _ => self.get_block_signers(&block).contains(&pub_key)
+        };
+        if signer {
             for index in block.index..=self.get_height() {
                 let b = self.get_block(index).unwrap();
-                if b.pub_key == keystore.get_public() {
+                if b.pub_key == pub_key {
                     info!("We already mined signing block for block {}", block.index);
                     return None;
                 }
             }
 
             info!("We have an honor to mine signing block!");
-            let mut block = Block::new(None, Bytes::default(), last_hash, SIGNER_DIFFICULTY);
+            let mut block = Block::new(None, Bytes::default(), last_hash, self.get_params().signer_difficulty);
             block.index = last_index + 1;
             return Some(block);
-        } else if !signers.is_empty() {
-            info!("Signing block must be mined by other nodes");
         }
+        trace!("Signing block must be mined by other nodes");
         None
     }
 
+    /// Finds signers of the last full block once, when it comes, so that the miner doesn't look for them again and again.
+    /// Returns the index of that block and whether our key is among its signers, if it wasn't known before.
+    pub fn update_signers(&mut self, keystore: &Option<Keystore>) -> Option<(u64, bool)> {
+        let pub_key = keystore.as_ref()?.get_public();
+        let block = self.last_full_block.clone()?;
+        if block.index < self.get_params().signers_start || self.get_height() < self.max_height() {
+            return None;
+        }
+        if let Some(duty) = &self.sign_duty {
+            if duty.hash == block.hash && duty.pub_key == pub_key {
+                return None;
+            }
+        }
+        let signer = self.get_block_signers(&block).contains(&pub_key);
+        if signer {
+            info!("We are chosen to sign block {}", block.index);
+        } else {
+            info!("Signing block for block {} must be mined by other nodes", block.index);
+        }
+        self.sign_duty = Some(SignDuty { hash: block.hash, pub_key, signer });
+        Some((block.index, signer))
+    }
+
     pub fn update_sign_block_for_mining(&self, mut block: Block) -> Option<Block> {
         if let Some(full_block) = &self.last_full_block {
             let sign_count = self.get_height() - full_block.index;
-            if sign_count >= BLOCK_SIGNERS_MIN {
+            if sign_count >= self.get_params().signers_min {
                 return None;
             }
             if let Some(last) = &self.last_block {
@@ -330,7 +762,7 @@ impl Chain {
     pub fn is_waiting_signers(&self) -> bool {
         if let Some(full_block) = &self.last_full_block {
             let sign_count = self.get_height() - full_block.index;
-            if sign_count < BLOCK_SIGNERS_MIN {
+            if sign_count < self.get_params().signers_min {
                 return true;
             }
         }
@@ -338,69 +770,8 @@ impl Chain {
         false
     }
 
-    /// Adds block to blocks table
-    fn add_block_to_table(&mut self, block: Block) -> sqlite::Result<State> {
-        let mut statement = self.db.prepare(SQL_ADD_BLOCK)?;
-        statement.bind(1, block.index as i64)?;
-        statement.bind(2, block.timestamp as i64)?;
-        statement.bind(3, block.version as i64)?;
-        statement.bind(4, block.difficulty as i64)?;
-        statement.bind(5, block.random as i64)?;
-        statement.bind(6, block.nonce as i64)?;
-        match &block.transaction {
-            None => { statement.bind(7, "")?; }
-            Some(transaction) => {
-                statement.bind(7, transaction.to_string().as_str())?;
-            }
-        }
-        statement.bind(8, &**block.prev_block_hash)?;
-        statement.bind(9, &**block.hash)?;
-        statement.bind(10, &**block.pub_key)?;
-        statement.bind(11, &**block.signature)?;
-        statement.next()
-    }
-
-    /// Adds transaction to transactions table
-    fn add_transaction_to_table(&mut self, index: u64, timestamp: i64, t: &Transaction) -> sqlite::Result<State> {
-        let sql = match t.class.as_ref() {
-            "domain" => SQL_ADD_DOMAIN,
-            "zone" => SQL_ADD_ZONE,
-            _ => return Err(sqlite::Error { code: None, message: None })
-        };
-
-        let mut statement = self.db.prepare(sql)?;
-        statement.bind(1, index as i64)?;
-        statement.bind(2, timestamp)?;
-        statement.bind(3, &**t.identity)?;
-        statement.bind(4, &**t.confirmation)?;
-        statement.bind(5, t.data.as_ref() as &str)?;
-        statement.bind(6, &**t.pub_key)?;
-        statement.next()
-    }
-
     pub fn get_block(&self, index: u64) -> Option<Block> {
-        match self.db.prepare(SQL_GET_BLOCK_BY_ID) {
-            Ok(mut statement) => {
-                statement.bind(1, index as i64).expect("Error in bind");
-                while statement.next().unwrap() == State::Row {
-                    return match Self::get_block_from_statement(&mut statement) {
-                        None => {
-                            error!("Something wrong with block in DB!");
-                            None
-                        }
-                        Some(block) => {
-                            //trace!("Loaded block: {:?}", &block);
-                            Some(block)
-                        }
-                    };
-                }
-                None
-            }
-            Err(_) => {
-                warn!("Can't find requested block {}", index);
-                None
-            }
-        }
+        self.storage.get_block(index)
     }
 
     /// Gets last block that has a Transaction within
@@ -418,32 +789,7 @@ impl Chain {
             }
         }
 
-        let mut statement = match pub_key {
-            None => {
-                let mut statement = self.db.prepare(SQL_GET_LAST_FULL_BLOCK).expect("Unable to prepare");
-                statement.bind(1, before as i64).expect("Unable to bind");
-                statement
-            }
-            Some(pub_key) => {
-                let mut statement = self.db.prepare(SQL_GET_LAST_FULL_BLOCK_FOR_KEY).expect("Unable to prepare");
-                statement.bind(1, before as i64).expect("Unable to bind");
-                statement.bind(2, pub_key).expect("Unable to bind");
-                statement
-            }
-        };
-        while statement.next().unwrap() == State::Row {
-            return match Self::get_block_from_statement(&mut statement) {
-                None => {
-                    error!("Something wrong with block in DB!");
-                    None
-                }
-                Some(block) => {
-                    //trace!("Got last full block: {:?}", &block);
-                    Some(block)
-                }
-            };
-        }
-        None
+        self.storage.get_last_full_block(before, pub_key)
     }
 
     /// Checks if any domain is available to mine for this client (pub_key)
@@ -469,37 +815,25 @@ impl Chain {
 
     /// Checks if this identity is free or is owned by the same pub_key
     pub fn is_id_available(&self, height: u64, identity: &Bytes, public_key: &Bytes, zone: bool) -> bool {
-        let sql = match zone {
-            true => { SQL_GET_ZONE_PUBLIC_KEY_BY_ID }
-            false => { SQL_GET_DOMAIN_PUBLIC_KEY_BY_ID }
+        if !self.may_have_id(identity) {
+            return true;
+        }
+        // Domains can change owners, zones stay with the first one
+        let owner = match zone {
+            true => self.storage.get_identity_owner(height, identity, true),
+            false => self.get_domain_owner(height, identity)
         };
-
-        let mut statement = self.db.prepare(sql).unwrap();
-        statement.bind(1, height as i64).expect("Error in bind");
-        statement.bind(2, &***identity).expect("Error in bind");
-        while let State::Row = statement.next().unwrap() {
-            let pub_key = Bytes::from_bytes(&statement.read::<Vec<u8>>(0).unwrap());
-            if !pub_key.eq(public_key) {
-                return false;
-            }
+        match owner {
+            Some(pub_key) => pub_key.eq(public_key),
+            None => true
         }
-        true
     }
 
     pub fn get_zones(&self) -> Vec<ZoneData> {
         let mut map = HashMap::new();
-        match self.db.prepare(SQL_GET_ZONES) {
-            Ok(mut statement) => {
-                while statement.next().unwrap() == State::Row {
-                    let data = statement.read::<String>(0).unwrap();
-                    //debug!("Got zone data {}", &data);
-                    if let Ok(zone_data) = serde_json::from_str::<ZoneData>(&data) {
-                        map.insert(zone_data.name.clone(), zone_data);
-                    }
-                }
-            }
-            Err(e) => {
-                warn!("Can't get zones from DB {}", e);
+        for data in self.storage.get_zones() {
+            if let Ok(zone_data) = serde_json::from_str::<ZoneData>(&data) {
+                map.insert(zone_data.name.clone(), zone_data);
             }
         }
         let result: Vec<ZoneData> = map.drain().map(|(_, value)| value).collect();
@@ -524,22 +858,196 @@ impl Chain {
 
     /// Checks if some id exists in our blockchain
     pub fn is_id_in_blockchain(&self, height: u64, id: &Bytes, zone: bool) -> bool {
-        let sql = match zone {
-            true => { SQL_GET_ZONE_PUBLIC_KEY_BY_ID }
-            false => { SQL_GET_DOMAIN_PUBLIC_KEY_BY_ID }
+        if !self.may_have_id(id) {
+            return false;
+        }
+        self.storage.get_identity_owner(height, id, zone).is_some()
+    }
+
+    /// Gets the key that owns this identity: the one that has registered it, or the last buyer
+    pub fn get_domain_owner(&self, height: u64, identity: &Bytes) -> Option<Bytes> {
+        if !self.may_have_id(identity) {
+            return None;
+        }
+        let mut owner = None;
+        for row in self.storage.get_domain_changes(height, identity) {
+            if owner.is_none() {
+                owner = Some(row.transaction.pub_key);
+                continue;
+            }
+            // Transfers are checked when blocks are added, so every transfer here is valid.
+            // Blocks mined before they were enabled can have such fields too, but they meant nothing then.
+            let params = self.params_at(row.index);
+            match serde_json::from_str::<DomainData>(&row.transaction.data) {
+                Ok(DomainData { transfer: Some(_), .. }) if params.sales => owner = Some(row.transaction.pub_key),
+                Ok(DomainData { handover: Some(handover), .. }) if params.transfers => owner = Some(handover.new_owner),
+                _ => {}
+            }
+        }
+        owner
+    }
+
+    /// Gets the index of the block with the last change of this identity below `height`, and its data
+    fn get_last_domain_change(&self, height: u64, identity: &Bytes) -> Option<(u64, DomainData)> {
+        let row = self.storage.get_domain_changes(height, identity).pop()?;
+        serde_json::from_str(&row.transaction.data).ok().map(|data| (row.index, data))
+    }
+
+    /// Checks that `pub_key` owned the domain at `height` by the change in block `index`:
+    /// it must be the last change up to `height`, and the domain must not be expired at that height
+    pub fn check_domain_ownership(&self, height: u64, identity: &Bytes, index: u64, pub_key: &Bytes) -> Result<(), String> {
+        let block = self.get_block(height).ok_or_else(|| format!("We don't have block {} yet", height))?;
+        let last = self.storage.get_domain_changes(height + 1, identity).pop().ok_or_else(|| String::from("There is no such domain"))?;
+        if last.index != index {
+            return Err(format!("Domain is changed in block {} after the proven one", last.index));
+        }
+        if last.timestamp + self.params_at(height).domain_lifetime <= block.timestamp {
+            return Err(String::from("Domain has expired already"));
+        }
+        match self.get_domain_owner(height + 1, identity) {
+            Some(owner) if &owner == pub_key => Ok(()),
+            _ => Err(String::from("Domain is owned by another key"))
+        }
+    }
+
+    /// Checks if `pub_key` can change the domain with this data at `timestamp`:
+    /// it owns the domain, has a delegation token from the owner, or buys the domain by accepted offer
+    pub fn check_domain_change(&self, height: u64, identity: &Bytes, pub_key: &Bytes, data: &DomainData, timestamp: i64) -> Result<(), String> {
+        let params = self.params_at(height);
+        // Before features are enabled their fields are unknown to older nodes, they ignore them, and so do we
+        if data.handover.is_some() && params.transfers {
+            return Err(String::from("Domains are given only by transfer transactions"));
+        }
+        let delegation = data.delegation.as_ref().filter(|_| params.delegations);
+        let transfer = data.transfer.as_ref().filter(|_| params.sales);
+        let owner = match self.get_domain_owner(height, identity) {
+            Some(owner) => owner,
+            None if delegation.is_some() || transfer.is_some() => return Err(String::from("There is no such domain")),
+            None => return Ok(())
         };
-        // Checking for existing zone in DB
-        let mut statement = self.db.prepare(sql).unwrap();
-        statement.bind(1, height as i64).expect("Error in bind");
-        statement.bind(2, &***id).expect("Error in bind");
-        while let State::Row = statement.next().unwrap() {
-            // If there is such a zone
-            return true;
+        if let Some(transfer) = transfer {
+            let (last_block, _) = self.get_last_domain_change(height, identity).ok_or_else(|| String::from("There is no such domain"))?;
+            return transfer.verify(identity, &owner, pub_key, last_block, timestamp);
+        }
+        if &owner == pub_key {
+            return Ok(());
+        }
+        let delegation = delegation.ok_or_else(|| String::from("Domain is owned by another key"))?;
+        if delegation.owner != owner {
+            return Err(String::from("Delegation is not from the owner"));
+        }
+        delegation.verify(identity, pub_key, timestamp)?;
+        // Delegates can change records, but not the owners
+        match self.get_last_domain_change(height, identity) {
+            Some((_, last)) if last.owners == data.owners => Ok(()),
+            _ => Err(String::from("Delegates can't change owners"))
         }
-        false
     }
 
-    pub fn can_mine_domain(&self, height: u64, domain: &str, pub_key: &Bytes) -> MineResult {
+    /// Checks if `pub_key` can renew the domain at `timestamp`: renewals must be enabled at `height`,
+    /// the domain must not be expired yet, it must be renewed by its owner and `data` must be the same as the last one
+    pub fn check_renewal(&self, height: u64, identity: &Bytes, pub_key: &Bytes, data: &str, timestamp: i64) -> Result<(), String> {
+        let params = self.params_at(height);
+        if !params.renewals {
+            return Err(String::from("Renewals are not enabled yet"));
+        }
+        let data = serde_json::from_str::<DomainData>(data).map_err(|_| String::from("Wrong domain data"))?;
+        if data.delegation.is_some() || data.transfer.is_some() || data.handover.is_some() {
+            return Err(String::from("Renewals can't be delegated or transferred"));
+        }
+        match self.get_domain_owner(height, identity) {
+            Some(owner) if &owner == pub_key => {}
+            Some(_) => return Err(String::from("Only the owner can renew the domain")),
+            None => return Err(String::from("There is no such domain"))
+        }
+        let last = self.storage.get_domain_changes(height, identity).pop().ok_or_else(|| String::from("There is no such domain"))?;
+        if last.timestamp + params.domain_lifetime <= timestamp {
+            return Err(String::from("Domain has expired already"));
+        }
+        let last_data = serde_json::from_str::<DomainData>(&last.transaction.data).map_err(|_| String::from("Wrong domain data"))?;
+        if last_data.zone != data.zone || !last_data.diff(&data).is_empty() {
+            return Err(String::from("Renewal can't change the domain"));
+        }
+        Ok(())
+    }
+
+    /// Checks if the domain can be given to another key at `timestamp`: transfers must be enabled at `height`,
+    /// `data` must be the same as the last one, with a handover signed by the owner to a strong key
+    pub fn check_transfer(&self, height: u64, identity: &Bytes, data: &str, timestamp: i64) -> Result<(), String> {
+        let params = self.params_at(height);
+        if !params.transfers {
+            return Err(String::from("Transfers are not enabled yet"));
+        }
+        let data = serde_json::from_str::<DomainData>(data).map_err(|_| String::from("Wrong domain data"))?;
+        if data.delegation.is_some() || data.transfer.is_some() {
+            return Err(String::from("Transfers can't be delegated or sold"));
+        }
+        let handover = data.handover.as_ref().ok_or_else(|| String::from("There is no handover"))?;
+        let owner = self.get_domain_owner(height, identity).ok_or_else(|| String::from("There is no such domain"))?;
+        let last = self.storage.get_domain_changes(height, identity).pop().ok_or_else(|| String::from("There is no such domain"))?;
+        if last.timestamp + params.domain_lifetime <= timestamp {
+            return Err(String::from("Domain has expired already"));
+        }
+        handover.verify(identity, &owner, last.index)?;
+        if !check_public_key_strength(&handover.new_owner, KEYSTORE_DIFFICULTY) {
+            return Err(String::from("The key of new owner is weak"));
+        }
+        let last_data = serde_json::from_str::<DomainData>(&last.transaction.data).map_err(|_| String::from("Wrong domain data"))?;
+        if last_data.zone != data.zone || !last_data.diff(&data).is_empty() {
+            return Err(String::from("Transfer can't change the domain"));
+        }
+        Ok(())
+    }
+
+    /// Gets current data of the domain, if `pub_key` can renew it now
+    pub fn get_renewal_data(&self, domain: &str, pub_key: &Bytes) -> Result<DomainData, String> {
+        let (_, _, transaction) = self.get_domain_row(domain).ok_or_else(|| String::from("There is no such domain"))?;
+        let data = serde_json::from_str::<DomainData>(&transaction.data).map_err(|_| String::from("Wrong domain data"))?;
+        let identity = hash_identity(domain, None);
+        self.check_renewal(self.get_height() + 1, &identity, pub_key, &transaction.data, Utc::now().timestamp())?;
+        Ok(data)
+    }
+
+    /// Gets current data of our domain with a signed handover to `new_owner`, if it can be given now
+    pub fn get_transfer_data(&self, domain: &str, keystore: &Keystore, new_owner: Bytes) -> Result<DomainData, String> {
+        let (index, _, transaction) = self.get_domain_row(domain).ok_or_else(|| String::from("There is no such domain"))?;
+        let mut data = serde_json::from_str::<DomainData>(&transaction.data).map_err(|_| String::from("Wrong domain data"))?;
+        let identity = hash_identity(domain, None);
+        data.handover = Some(Handover::create(keystore, identity.clone(), new_owner, index));
+        self.check_transfer(self.get_height() + 1, &identity, &serde_json::to_string(&data).unwrap(), Utc::now().timestamp())?;
+        Ok(data)
+    }
+
+    /// Finds live domains that look like this one, see `confusables`
+    pub fn find_confusables(&self, domain: &str) -> ConfusableReport {
+        let domain = to_ascii(domain).unwrap_or_else(|| domain.to_lowercase());
+        let confusables = get_confusable_variants(&domain)
+            .into_iter()
+            .filter(|variant| self.get_domain_transaction(variant).is_some())
+            .map(|variant| Confusable { unicode: to_unicode(&variant), domain: variant })
+            .collect();
+        ConfusableReport { unicode: to_unicode(&domain), mixed_scripts: is_mixed_script(&domain), domain, confusables }
+    }
+
+    /// Gets registration statistics of the zone, see `zone_stats`
+    pub fn get_zone_stats(&self, zone: &str) -> ZoneStats {
+        let entries = self.get_zone_domains(zone, 0, usize::MAX);
+        get_zone_stats(zone, &entries, Utc::now().timestamp(), self.get_params().domain_lifetime, STATS_WEEKS)
+    }
+
+    /// Gets names of zones that this key has registered, or is one of their owners
+    pub fn get_owned_zones(&self, pub_key: &Bytes) -> Vec<String> {
+        let height = self.get_height();
+        let mut zones: Vec<String> = self.get_zones()
+            .into_iter()
+            .filter(|zone| zone.owners.contains(pub_key) || self.get_domain_owner(height, &hash_identity(&zone.name, None)).as_ref() == Some(pub_key))
+            .map(|zone| zone.name)
+            .collect();
+        zones.sort();
+        zones
+    }
+
+    pub fn can_mine_domain(&self, height: u64, domain: &str, pub_key: &Bytes, data: &DomainData) -> MineResult {
         let name = domain.to_lowercase();
         if !check_domain(&name, true) {
             return WrongName;
@@ -548,50 +1056,131 @@ impl Chain {
         if !self.is_zone_in_blockchain(height, &zone) {
             return WrongZone;
         }
-        if let Some(transaction) = self.get_domain_transaction(&name) {
-            if transaction.pub_key.ne(pub_key) {
-                return NotOwned;
-            }
-        }
         let identity_hash = hash_identity(&name, None);
-        if let Some(last) = self.get_last_full_block(MAX, Some(&pub_key)) {
+        if let Err(e) = self.check_domain_change(MAX, &identity_hash, pub_key, data, Utc::now().timestamp()) {
+            debug!("Unable to change domain {}: {}", &name, e);
+            return NotOwned;
+        }
+        if let Some(next) = self.next_allowed_domain_time(pub_key) {
             let new_id = !self.is_id_in_blockchain(height, &identity_hash, false);
-            let time = last.timestamp + NEW_DOMAINS_INTERVAL - Utc::now().timestamp();
-            if new_id && time > 0 {
-                return Cooldown { time }
+            if new_id {
+                return Cooldown { time: next - Utc::now().timestamp() }
             }
         }
 
         Fine
     }
 
+    /// Gets the time when this key can register a new domain, or None if it can do it now.
+    /// Updates of own domains are not limited by this cooldown.
+    pub fn next_allowed_domain_time(&self, pub_key: &Bytes) -> Option<i64> {
+        let last = self.get_last_full_block(MAX, Some(pub_key))?;
+        let next = last.timestamp + self.get_params().new_domains_interval;
+        if next > Utc::now().timestamp() {
+            Some(next)
+        } else {
+            None
+        }
+    }
+
     /// Gets full Transaction info for any domain. Used by DNS part.
     pub fn get_domain_transaction(&self, domain: &str) -> Option<Transaction> {
+        self.get_domain_row(domain).map(|(_, _, transaction)| transaction)
+    }
+
+    /// Gets the time of last change of some domain, used as zone serial for secondary servers
+    pub fn get_domain_timestamp(&self, domain: &str) -> Option<i64> {
+        self.get_domain_row(domain).map(|(_, timestamp, _)| timestamp)
+    }
+
+    /// Gets the time when some domain expires if it is not renewed, None if it is free
+    pub fn get_domain_expiry(&self, domain: &str) -> Option<i64> {
+        self.get_domain_timestamp(domain).map(|timestamp| timestamp + self.get_params().domain_lifetime)
+    }
+
+    /// Gets the index of the block with last change of some domain
+    pub fn get_domain_block_index(&self, domain: &str) -> Option<u64> {
+        self.get_domain_row(domain).map(|(index, _, _)| index)
+    }
+
+    fn get_domain_row(&self, domain: &str) -> Option<(u64, i64, Transaction)> {
         if domain.is_empty() {
             return None;
         }
         let identity_hash = hash_identity(domain, None);
+        if !self.may_have_id(&identity_hash) {
+            return None;
+        }
 
-        let mut statement = self.db.prepare(SQL_GET_DOMAIN_BY_ID).unwrap();
-        statement.bind(1, &**identity_hash).expect("Error in bind");
-        while let State::Row = statement.next().unwrap() {
-            let timestamp = statement.read::<i64>(1).unwrap();
-            if timestamp < Utc::now().timestamp() - DOMAIN_LIFETIME {
-                // This domain is too old
-                return None;
-            }
-            let identity = Bytes::from_bytes(&statement.read::<Vec<u8>>(2).unwrap());
-            let confirmation = Bytes::from_bytes(&statement.read::<Vec<u8>>(3).unwrap());
-            let class = String::from("domain");
-            let data = statement.read::<String>(4).unwrap();
-            let pub_key = Bytes::from_bytes(&statement.read::<Vec<u8>>(5).unwrap());
-            let transaction = Transaction { identity, confirmation, class, data, pub_key };
-            debug!("Found transaction for domain {}: {:?}", domain, &transaction);
-            if transaction.check_identity(domain) {
-                return Some(transaction);
+        let row = self.storage.get_last_domain(&identity_hash)?;
+        debug!("Found transaction for domain {}: {:?}", domain, &row.transaction);
+        check_domain_row(domain, row, self.get_params().domain_lifetime).map(|row| (row.index, row.timestamp, row.transaction))
+    }
+
+    /// Returns false if there is surely no such domain
+    pub fn may_have_domain(&self, domain: &str) -> bool {
+        self.may_have_id(&hash_identity(domain, None))
+    }
+
+    /// Gets the lookup of domains, that works without locking the chain.
+    /// There is none if the storage has no readers, or if some blocks are not committed yet, readers don't see them.
+    pub fn get_domain_lookup(&self) -> Option<DomainLookup> {
+        if self.uncommitted > 0 {
+            return None;
+        }
+        let reader = self.storage.get_reader()?;
+        Some(DomainLookup { reader, domain_lifetime: self.get_params().domain_lifetime })
+    }
+
+    /// Gets the view of domains and zones for DNS threads, it is built on the first call and then follows the chain
+    pub fn get_dns_view(&mut self) -> Arc<DnsView> {
+        if let Some(view) = &self.dns_view {
+            return Arc::clone(view);
+        }
+        self.commit_blocks();
+        let view = Arc::new(DnsView::new(&*self.storage, self.get_params().domain_lifetime));
+        self.dns_view = Some(Arc::clone(&view));
+        view
+    }
+
+    /// Gets the proof of domain data for thin clients by identity hash, so that we don't know the name.
+    /// If `to` is given, the proof continues up to the block with that index, that the client trusts.
+    pub fn get_domain_proof(&self, identity: &Bytes, to: Option<u64>) -> Option<DomainProof> {
+        if !self.may_have_id(identity) {
+            return None;
+        }
+        let row = self.storage.get_last_domain(identity)?;
+        let index = row.index;
+        if row.timestamp < Utc::now().timestamp() - self.get_params().domain_lifetime {
+            return None;
+        }
+        let block = self.get_block(index)?;
+        let mut signers = Vec::new();
+        let mut next = index + 1;
+        while (signers.len() as u64) < self.get_params().signers_min {
+            match self.get_block(next) {
+                Some(block) if block.transaction.is_none() => signers.push(block),
+                _ => break
             }
+            next += 1;
         }
-        None
+        let chain = match to {
+            Some(to) if to >= next => self.get_chain_proof(next, to)?.blocks,
+            _ => Vec::new()
+        };
+        Some(DomainProof { block, signers, chain })
+    }
+
+    /// Gets consecutive blocks from `from` to `to` inclusive, or None if there are no such blocks or too many of them
+    pub fn get_chain_proof(&self, from: u64, to: u64) -> Option<ChainProof> {
+        if from > to || to - from >= MAX_PROOF_BLOCKS || to > self.get_height() {
+            return None;
+        }
+        let mut blocks = Vec::new();
+        for index in from..=to {
+            blocks.push(self.get_block(index)?);
+        }
+        Some(ChainProof { blocks })
     }
 
     pub fn get_domain_info(&self, domain: &str) -> Option<String> {
@@ -609,35 +1198,22 @@ impl Chain {
         let mut result = HashMap::new();
         let keystore = keystore.clone().unwrap();
         let pub_key = keystore.get_public();
-        let mut statement = self.db.prepare(SQL_GET_DOMAINS_BY_KEY).unwrap();
-        statement.bind(1, &**pub_key).expect("Error in bind");
-        while let State::Row = statement.next().unwrap() {
-            let index = statement.read::<i64>(0).unwrap() as u64;
-            let timestamp = statement.read::<i64>(1).unwrap();
-            let identity = Bytes::from_bytes(&statement.read::<Vec<u8>>(2).unwrap());
-            let confirmation = Bytes::from_bytes(&statement.read::<Vec<u8>>(3).unwrap());
-            let class = String::from("domain");
-            let data = statement.read::<String>(4).unwrap();
-            let pub_key = Bytes::from_bytes(&statement.read::<Vec<u8>>(5).unwrap());
-            let transaction = Transaction { identity: identity.clone(), confirmation: confirmation.clone(), class, data, pub_key };
-            //debug!("Found transaction for domain {}: {:?}", domain, &transaction);
-            if let Some(data) = transaction.get_domain_data() {
-                let mut domain = keystore.decrypt(data.domain.as_slice(), &confirmation.as_slice()[..12]);
-                if domain.is_empty() {
-                    // Legacy encryption scheme
-                    for i in 1..=10 {
-                        let b = self.get_block(index - i).unwrap();
-                        domain = keystore.decrypt(data.domain.as_slice(), &b.hash.as_slice()[..12]);
-                        if !domain.is_empty() {
-                            break;
-                        }
+        let mut cache = self.my_domains.borrow_mut();
+        cache.set_key(&pub_key);
+        for row in self.storage.get_domains_by_key(&pub_key) {
+            let (index, timestamp) = (row.index, row.timestamp);
+            let identity = row.transaction.identity.clone();
+            let confirmation = row.transaction.confirmation.clone();
+            if let Some(data) = row.transaction.get_domain_data() {
+                let key = (identity.clone(), confirmation);
+                let domain = match cache.names.get(&key) {
+                    Some(domain) => domain.clone(),
+                    None => {
+                        let domain = self.decrypt_domain(&keystore, &data, &key.1, index);
+                        cache.names.insert(key, domain.clone());
+                        domain
                     }
-                }
-
-                let mut domain = String::from_utf8(domain.to_vec()).unwrap();
-                if domain.is_empty() {
-                    domain = String::from("unknown");
-                }
+                };
                 trace!("Found my domain {}", domain);
                 result.insert(identity, (domain, timestamp, data));
             }
@@ -645,6 +1221,26 @@ impl Chain {
         result
     }
 
+    fn decrypt_domain(&self, keystore: &Keystore, data: &DomainData, confirmation: &Bytes, index: u64) -> String {
+        let mut domain = keystore.decrypt(data.domain.as_slice(), &confirmation.as_slice()[..12]);
+        if domain.is_empty() {
+            // Legacy encryption scheme
+            for i in 1..=10 {
+                let b = self.get_block(index - i).unwrap();
+                domain = keystore.decrypt(data.domain.as_slice(), &b.hash.as_slice()[..12]);
+                if !domain.is_empty() {
+                    break;
+                }
+            }
+        }
+
+        let domain = String::from_utf8(domain.to_vec()).unwrap();
+        if domain.is_empty() {
+            return String::from("unknown");
+        }
+        domain
+    }
+
     pub fn get_zone_difficulty(&self, zone: &str) -> u32 {
         let zones = self.get_zones();
         for z in zones.iter() {
@@ -679,10 +1275,10 @@ impl Chain {
         match self.last_full_block {
             None => { self.get_height() + 1 }
             Some(ref block) => {
-                if block.index < BLOCK_SIGNERS_START {
+                if block.index < self.get_params().signers_start {
                     self.get_height() + 1
                 } else {
-                    max(block.index + BLOCK_SIGNERS_MIN, self.get_height() + 1)
+                    max(block.index + self.get_params().signers_min, self.get_height() + 1)
                 }
             }
         }
@@ -696,18 +1292,60 @@ impl Chain {
         self.max_height = height;
     }
 
+    /// Consensus parameters for the next block
+    pub fn get_params(&self) -> &ConsensusParams {
+        self.params_at(self.get_height() + 1)
+    }
+
+    /// Consensus parameters for every height, for checks of blocks and proofs out of the chain
+    pub fn get_schedule(&self) -> &Schedule {
+        &self.schedule
+    }
+
+    /// Consensus parameters for the block with this index, with all upgrades activated before it
+    fn params_at(&self, index: u64) -> &ConsensusParams {
+        self.schedule.at(index)
+    }
+
+    /// Block version for new blocks, it is the version of chain rules
+    pub fn get_block_version(&self) -> u32 {
+        match self.params_at(self.get_height() + 1).binary_transactions {
+            true => BINARY_RULES_VERSION,
+            false => CHAIN_VERSION
+        }
+    }
+
+    /// Bits of pending upgrades that we support, miners put them into the random value of new blocks
+    pub fn get_signal_bits(&self) -> u32 {
+        self.params.get_signal_bits(self.get_height() + 1)
+    }
+
+    /// Counts last `count` blocks that signal readiness for the upgrade with this bit
+    pub fn count_upgrade_signals(&self, bit: u8, count: u64) -> u64 {
+        self.storage.count_upgrade_signals(count, get_upgrade_flag(bit))
+    }
+
     pub fn check_new_block(&self, block: &Block) -> BlockQuality {
         self.check_block(block, &self.last_block, &self.last_full_block)
     }
 
     /// Check if this block can be added to our blockchain
     pub fn check_block(&self, block: &Block, last_block: &Option<Block>, last_full_block: &Option<Block>) -> BlockQuality {
-        if block.version > CHAIN_VERSION {
+        if block.version > BINARY_RULES_VERSION {
             warn!("Ignoring block from unsupported version:\n{:?}", &block);
             return Bad;
         }
+        // Encoding can't be chosen by miners, otherwise one block would have two hashes
+        if is_binary(block.version) != self.params_at(block.index).binary_transactions {
+            warn!("Ignoring block with wrong encoding for its height:\n{:?}", &block);
+            return Bad;
+        }
+        if !self.checkpoints.check(block.index, &block.hash) {
+            warn!("Ignoring block {} that doesn't match the checkpoint:\n{:?}", block.index, &block);
+            return Bad;
+        }
         let timestamp = Utc::now().timestamp();
-        if block.timestamp > timestamp + 60 {
+        if block.timestamp > timestamp + self.params_at(block.index).max_future_time {
             warn!("Ignoring block from the future:\n{:?}", &block);
             return Bad;
         }
@@ -717,19 +1355,15 @@ impl Chain {
                 return Future;
             }
         }
-        if !check_public_key_strength(&block.pub_key, KEYSTORE_DIFFICULTY) {
-            warn!("Ignoring block with weak public key:\n{:?}", &block);
-            return Bad;
-        }
         let difficulty = match &block.transaction {
             None => {
                 if block.index == 1 {
-                    ZONE_DIFFICULTY
+                    self.params_at(block.index).zone_difficulty
                 } else {
-                    SIGNER_DIFFICULTY
+                    self.params_at(block.index).signer_difficulty
                 }
             }
-            Some(t) => { self.get_difficulty_for_transaction(&t) }
+            Some(t) => { self.get_difficulty_for_transaction(&t, block.index) }
         };
         if block.difficulty < difficulty {
             warn!("Block difficulty is lower than needed");
@@ -739,12 +1373,7 @@ impl Chain {
             warn!("Ignoring block with low difficulty:\n{:?}", &block);
             return Bad;
         }
-        if !check_block_hash(block) {
-            warn!("Block {:?} has wrong hash! Ignoring!", &block);
-            return Bad;
-        }
-        if !check_block_signature(&block) {
-            warn!("Block {:?} has wrong signature! Ignoring!", &block);
+        if !self.check_block_crypto(block) {
             return Bad;
         }
         if let Some(prev_block) = self.get_block(block.index - 1) {
@@ -755,12 +1384,45 @@ impl Chain {
         }
 
         if let Some(transaction) = &block.transaction {
+            if self.params_at(block.index).strict_data {
+                if let Err(e) = validate_data(transaction) {
+                    warn!("Block {} has wrong data: {}", block.index, e);
+                    return Bad;
+                }
+            }
             let current_height = match last_block {
                 None => { 0 }
                 Some(block) => { block.index }
             };
             // TODO check for zone transaction
-            let is_domain_available = self.is_id_available(current_height, &transaction.identity, &block.pub_key, false);
+            let is_domain_available = if transaction.class == CLASS_RENEW {
+                match self.check_renewal(block.index, &transaction.identity, &block.pub_key, &transaction.data, block.timestamp) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        warn!("Block {} can't renew the domain: {}", block.index, e);
+                        false
+                    }
+                }
+            } else if transaction.class == CLASS_TRANSFER {
+                match self.check_transfer(block.index, &transaction.identity, &transaction.data, block.timestamp) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        warn!("Block {} can't transfer the domain: {}", block.index, e);
+                        false
+                    }
+                }
+            } else {
+                match transaction.get_domain_data() {
+                    Some(data) => match self.check_domain_change(block.index, &transaction.identity, &block.pub_key, &data, block.timestamp) {
+                        Ok(()) => true,
+                        Err(e) => {
+                            warn!("Block {} can't change the domain: {}", block.index, e);
+                            false
+                        }
+                    },
+                    None => self.is_id_available(current_height, &transaction.identity, &block.pub_key, false)
+                }
+            };
             let is_zone_available = self.is_id_available(current_height, &transaction.identity, &block.pub_key, true);
             if !is_domain_available || !is_zone_available {
                 warn!("Block {:?} is trying to spoof an identity!", &block);
@@ -769,7 +1431,7 @@ impl Chain {
             if let Some(last) = self.get_last_full_block(block.index, Some(&block.pub_key)) {
                 if last.index < block.index {
                     let new_id = !self.is_id_in_blockchain(block.index, &transaction.identity, false);
-                    if new_id && last.timestamp + NEW_DOMAINS_INTERVAL > block.timestamp {
+                    if new_id && last.timestamp + self.params_at(block.index).new_domains_interval > block.timestamp {
                         warn!("Block {:?} is mined too early!", &block);
                         return Bad;
                     }
@@ -804,15 +1466,21 @@ impl Chain {
                 }
             }
             Some(last_block) => {
-                if block.timestamp < last_block.timestamp && block.index > last_block.index {
-                    warn!("Ignoring block with timestamp/index collision:\n{:?}", &block);
-                    return Bad;
+                // Only new blocks on top are checked, as originally. Blocks of branches are on top when they are checked
+                // again in `switch_to_branch`, and single forked blocks are replaced by the original rule, without it.
+                if block.index > 1 && block.index > last_block.index {
+                    if let Some(median) = self.get_median_timestamp(block.index) {
+                        if block.timestamp < median {
+                            warn!("Ignoring block older than median time {} of previous blocks:\n{:?}", median, &block);
+                            return Bad;
+                        }
+                    }
                 }
                 if last_block.index + 1 < block.index {
                     warn!("Block {} arrived too early.", block.index);
                     return Future;
                 }
-                if block.index > BLOCK_SIGNERS_START {
+                if block.index > self.params_at(block.index).signers_start {
                     // If this block is main, signed part of blockchain
                     if !self.is_good_sign_block(&block, last_full_block) {
                         return Bad;
@@ -843,6 +1511,11 @@ impl Chain {
         Good
     }
 
+    /// Gets the median time of `median_blocks` blocks before this index
+    fn get_median_timestamp(&self, index: u64) -> Option<i64> {
+        median(self.storage.get_last_timestamps(index, self.params_at(index).median_blocks.max(1)))
+    }
+
     /// Checks if this block is a good signature block
     fn is_good_sign_block(&self, block: &Block, last_full_block: &Option<Block>) -> bool {
         // If this is not a signing block
@@ -850,8 +1523,9 @@ impl Chain {
             return true;
         }
         if let Some(full_block) = &last_full_block {
+            let params = self.params_at(block.index);
             let sign_count = self.get_height() - full_block.index;
-            if sign_count < BLOCK_SIGNERS_MIN {
+            if sign_count < params.signers_min {
                 // Last full block is not locked enough
                 if block.index > full_block.index && block.transaction.is_some() {
                     warn!("Not enough signing blocks over full {} block!", full_block.index);
@@ -861,7 +1535,7 @@ impl Chain {
                         return false;
                     }
                 }
-            } else if sign_count < BLOCK_SIGNERS_ALL && block.transaction.is_none() {
+            } else if sign_count < params.signers_all && block.transaction.is_none() {
                 if !self.is_good_signer_for_block(&block, full_block) {
                     return false;
                 }
@@ -875,23 +1549,47 @@ impl Chain {
         // If we got a signing block
         let signers: HashSet<Bytes> = self.get_block_signers(full_block).into_iter().collect();
         if !signers.contains(&block.pub_key) {
-            warn!("Ignoring block {} from '{:?}', as wrong signer!", block.index, &block.pub_key);
+            warn!("Ignoring block {} from '{:?}' ({}), as wrong signer!", block.index, &block.pub_key, get_fingerprint(&block.pub_key));
             return false;
         }
         // If this signers' public key has already locked/signed that block we return error
-        for i in (full_block.index + 1)..block.index {
-            let signer = self.get_block(i).expect("Error in DB!");
-            if signer.pub_key == block.pub_key {
-                warn!("Ignoring block {} from '{:?}', already signed by this key", block.index, &block.pub_key);
-                return false;
-            }
+        if self.count_blocks_by_key(full_block.index, block.index, &block.pub_key) > 0 {
+            warn!("Ignoring block {} from '{:?}' ({}), already signed by this key", block.index, &block.pub_key, get_fingerprint(&block.pub_key));
+            return false;
         }
         true
     }
 
-    fn get_difficulty_for_transaction(&self, transaction: &Transaction) -> u32 {
+    /// Counts blocks with indexes in range (from; to) mined by this key
+    fn count_blocks_by_key(&self, from: u64, to: u64, pub_key: &Bytes) -> u64 {
+        self.storage.count_blocks_by_key(from, to, pub_key)
+    }
+
+    /// Checks the key strength, hash and signature of the block, or takes the verdict from the last check of the same block
+    fn check_block_crypto(&self, block: &Block) -> bool {
+        let digest = hash_sha256(&block.as_bytes());
+        if let Some(verdict) = self.verified.borrow().get(&block.hash, &digest) {
+            return verdict;
+        }
+        let verdict = if !check_public_key_strength(&block.pub_key, KEYSTORE_DIFFICULTY) {
+            warn!("Ignoring block with weak public key:\n{:?}", &block);
+            false
+        } else if !check_block_hash(block) {
+            warn!("Block {:?} has wrong hash! Ignoring!", &block);
+            false
+        } else if !check_block_signature(&block) {
+            warn!("Block {:?} has wrong signature! Ignoring!", &block);
+            false
+        } else {
+            true
+        };
+        self.verified.borrow_mut().insert(block.hash.clone(), digest, verdict);
+        verdict
+    }
+
+    fn get_difficulty_for_transaction(&self, transaction: &Transaction, index: u64) -> u32 {
         match transaction.class.as_ref() {
-            "domain" => {
+            "domain" | "renew" | "transfer" => {
                 return match serde_json::from_str::<DomainData>(&transaction.data) {
                     Ok(data) => {
                         for zone in self.get_zones().iter() {
@@ -907,88 +1605,255 @@ impl Chain {
                     }
                 }
             }
-            "zone" => { ZONE_DIFFICULTY }
+            "zone" => { self.params_at(index).zone_difficulty }
             _ => { u32::MAX }
         }
     }
 
+    /// Builds the block that the miner would start mining for this transaction right now.
+    /// Random and nonce are left zero, they are changed by miner on every iteration.
+    pub fn get_block_template(&self, transaction: Transaction, pub_key: Bytes) -> BlockTemplate {
+        let (index, prev_block_hash) = match &self.last_block {
+            // Genesis block has index 1
+            None => (1, Bytes::default()),
+            Some(block) => (block.index + 1, block.hash.clone())
+        };
+        let difficulty = self.get_difficulty_for_transaction(&transaction, index);
+        let mut block = Block::new(Some(transaction), pub_key, prev_block_hash.clone(), difficulty);
+        block.index = index;
+        block.version = self.get_block_version();
+        block.timestamp = Utc::now().timestamp();
+        let bytes = Bytes::from_bytes(&block.as_bytes());
+        BlockTemplate { index, prev_block_hash, difficulty, waiting_signers: self.is_waiting_signers(), block, bytes }
+    }
+
     /// Gets public keys of a node that needs to mine "signature" block above this block
     /// block - last full block
     pub fn get_block_signers(&self, block: &Block) -> Vec<Bytes> {
         let mut result = Vec::new();
-        if block.index < BLOCK_SIGNERS_START || self.get_height() < block.index {
+        let params = self.params_at(block.index);
+        if block.index < params.signers_start || self.get_height() < block.index {
             return result;
         }
 
         assert!(block.transaction.is_some());
-        if self.signers.borrow().has_signers_for(block.index) {
-            return self.signers.borrow().signers.clone();
+        if let Some(signers) = self.signers.borrow_mut().get(block) {
+            return signers;
         }
 
         let mut set = HashSet::new();
         let tail = block.signature.get_tail_u64();
         let mut count = 1;
         let window = block.index - 1; // Without the last block
-        while set.len() < BLOCK_SIGNERS_ALL as usize {
-            let index = (tail.wrapping_mul(count) % window) + 1; // We want it to start from 1
-            if let Some(b) = self.get_block(index) {
-                if b.pub_key != block.pub_key && !set.contains(&b.pub_key) {
-                    result.push(b.pub_key.clone());
-                    set.insert(b.pub_key);
+        // Indexes are fetched in batches, so that a whole signer set takes one or two queries instead of one per block
+        while set.len() < params.signers_all as usize {
+            let indexes: Vec<u64> = (count..count + SIGNERS_BATCH)
+                .map(|count| (tail.wrapping_mul(count) % window) + 1) // We want it to start from 1
+                .collect();
+            let keys = self.get_block_keys(&indexes);
+            for index in indexes {
+                if let Some(pub_key) = keys.get(&index) {
+                    if pub_key != &block.pub_key && !set.contains(pub_key) {
+                        result.push(pub_key.clone());
+                        set.insert(pub_key.clone());
+                        if set.len() >= params.signers_all as usize {
+                            break;
+                        }
+                    }
                 }
             }
-            count += 1;
+            count += SIGNERS_BATCH;
         }
-        trace!("Got signers for block {}: {:?}", block.index, &result);
-        let mut signers = self.signers.borrow_mut();
-        signers.index = block.index;
-        signers.signers = result.clone();
+        trace!("Got signers for block {}: {:?}", block.index, result.iter().map(|key| get_fingerprint(key)).collect::<Vec<_>>());
+        self.signers.borrow_mut().insert(block, result.clone());
         result
     }
 
-    fn get_block_from_statement(statement: &mut Statement) -> Option<Block> {
-        let index = statement.read::<i64>(0).unwrap() as u64;
-        let timestamp = statement.read::<i64>(1).unwrap();
-        let version = statement.read::<i64>(2).unwrap() as u32;
-        let difficulty = statement.read::<i64>(3).unwrap() as u32;
-        let random = statement.read::<i64>(4).unwrap() as u32;
-        let nonce = statement.read::<i64>(5).unwrap() as u64;
-        let transaction = Transaction::from_json(&statement.read::<String>(6).unwrap());
-        let prev_block_hash = Bytes::from_bytes(statement.read::<Vec<u8>>(7).unwrap().as_slice());
-        let hash = Bytes::from_bytes(statement.read::<Vec<u8>>(8).unwrap().as_slice());
-        let pub_key = Bytes::from_bytes(statement.read::<Vec<u8>>(9).unwrap().as_slice());
-        let signature = Bytes::from_bytes(statement.read::<Vec<u8>>(10).unwrap().as_slice());
-        Some(Block::from_all_params(index, timestamp, version, difficulty, random, nonce, prev_block_hash, hash, pub_key, signature, transaction))
+    /// Gets public keys of blocks with these indexes in one query
+    fn get_block_keys(&self, indexes: &[u64]) -> HashMap<u64, Bytes> {
+        self.storage.get_block_keys(indexes)
+    }
+}
+
+/// Skips expired domains and the ones with other names, that have the same identity
+fn check_domain_row(domain: &str, row: IdentityRow, domain_lifetime: i64) -> Option<IdentityRow> {
+    if row.timestamp < Utc::now().timestamp() - domain_lifetime {
+        // This domain is too old
+        return None;
+    }
+    match row.transaction.check_identity(domain) {
+        true => Some(row),
+        false => None
     }
 }
 
+/// Looks up domains for DNS threads in parallel, see `Chain::get_domain_lookup`
+pub struct DomainLookup {
+    reader: Arc<dyn DomainReader>,
+    domain_lifetime: i64,
+}
+
+impl DomainLookup {
+    pub fn get_domain_info(&self, domain: &str) -> Result<Option<String>, String> {
+        let row = self.reader.get_last_domain(&hash_identity(domain, None))?;
+        Ok(row.and_then(|row| check_domain_row(domain, row, self.domain_lifetime)).map(|row| row.transaction.data))
+    }
+}
+
+fn median(mut values: Vec<i64>) -> Option<i64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_unstable();
+    Some(values[values.len() / 2])
+}
+
+impl Drop for Chain {
+    fn drop(&mut self) {
+        self.commit_blocks();
+    }
+}
+
+/// Signer sets of recent full blocks, so that all signing blocks of a synced span reuse them.
+/// Forks switch between several full blocks, so the least recently used set is evicted, not the oldest one.
 struct SignersCache {
-    index: u64,
-    signers: Vec<Bytes>
+    /// Full block index -> (full block hash, signers, time of last use)
+    sets: HashMap<u64, (Bytes, Vec<Bytes>, u64)>,
+    /// Counts uses of sets, instead of real time
+    clock: u64,
+    /// How many sets were loaded from DB
+    loads: u64,
 }
 
 impl SignersCache {
     pub fn new() -> RefCell<SignersCache> {
-        let cache = SignersCache { index: 0, signers: Vec::new() };
+        let cache = SignersCache { sets: HashMap::new(), clock: 0, loads: 0 };
         RefCell::new(cache)
     }
 
-    pub fn has_signers_for(&self, index: u64) -> bool {
-        self.index == index && !self.signers.is_empty()
+    pub fn get(&mut self, block: &Block) -> Option<Vec<Bytes>> {
+        self.clock += 1;
+        match self.sets.get_mut(&block.index) {
+            Some((hash, signers, used)) if hash == &block.hash && !signers.is_empty() => {
+                *used = self.clock;
+                Some(signers.clone())
+            }
+            _ => None
+        }
+    }
+
+    /// Adds the set that was loaded from DB
+    pub fn insert(&mut self, block: &Block, signers: Vec<Bytes>) {
+        self.loads += 1;
+        self.clock += 1;
+        if self.sets.len() >= SIGNERS_CACHE_SIZE && !self.sets.contains_key(&block.index) {
+            let unused = self.sets.iter().min_by_key(|(_, (_, _, used))| *used).map(|(index, _)| *index);
+            if let Some(index) = unused {
+                self.sets.remove(&index);
+            }
+        }
+        self.sets.insert(block.index, (block.hash.clone(), signers, self.clock));
+    }
+
+    /// Forgets signers of full blocks from this index and above, as they are going to be replaced
+    pub fn truncate(&mut self, index: u64) {
+        self.sets.retain(|full_index, _| *full_index < index);
     }
 
     pub fn clear(&mut self) {
-        self.index = 0;
-        self.signers.clear();
+        self.sets.clear();
+    }
+}
+
+/// Verdicts of checks by block hash, with the digest of the whole block,
+/// because another block can have the same hash in its field, and it must be checked again
+struct VerifiedBlocks {
+    verdicts: HashMap<Bytes, (Vec<u8>, bool)>,
+    /// Hashes in order of insertion, the first ones are evicted when the cache is full
+    order: VecDeque<Bytes>,
+    size: usize,
+}
+
+impl VerifiedBlocks {
+    pub fn new(size: usize) -> Self {
+        VerifiedBlocks { verdicts: HashMap::new(), order: VecDeque::new(), size }
+    }
+
+    pub fn get(&self, hash: &Bytes, digest: &[u8]) -> Option<bool> {
+        match self.verdicts.get(hash) {
+            Some((known, verdict)) if known.as_slice() == digest => Some(*verdict),
+            _ => None
+        }
+    }
+
+    pub fn insert(&mut self, hash: Bytes, digest: Vec<u8>, verdict: bool) {
+        if self.verdicts.insert(hash.clone(), (digest, verdict)).is_some() {
+            return;
+        }
+        self.order.push_back(hash);
+        while self.order.len() > self.size {
+            if let Some(hash) = self.order.pop_front() {
+                self.verdicts.remove(&hash);
+            }
+        }
+    }
+}
+
+/// Signers of a full block are chosen once, and we remember if our key is among them
+struct SignDuty {
+    hash: Bytes,
+    pub_key: Bytes,
+    signer: bool,
+}
+
+/// Decrypted names of domains of one key, by identity and confirmation of their transactions
+#[derive(Default)]
+struct MyDomains {
+    pub_key: Bytes,
+    names: HashMap<(Bytes, Bytes), String>,
+}
+
+impl MyDomains {
+    /// Forgets names when another key is loaded
+    pub fn set_key(&mut self, pub_key: &Bytes) {
+        if &self.pub_key != pub_key {
+            self.pub_key = pub_key.clone();
+            self.names.clear();
+        }
+    }
+
+    /// Forgets names when a block of our key comes, its domains could be changed or given to somebody
+    pub fn invalidate(&mut self, miner: &Bytes, owner: &Bytes) {
+        if !self.names.is_empty() && (&self.pub_key == miner || &self.pub_key == owner) {
+            self.names.clear();
+        }
     }
 }
 
 #[cfg(test)]
 pub mod tests {
-    use crate::{Chain, Settings};
+    use crate::{Chain, Settings, Transaction, Bytes, Keystore};
+    use crate::blockchain::delegation::Delegation;
+    use crate::blockchain::transfer::{DomainTransfer, Handover, SaleOffer};
+    use super::{median, MyDomains, SignersCache, SIGNERS_CACHE_SIZE, VerifiedBlocks};
+    use crate::Block;
+    use crate::blockchain::snapshot::verify_snapshot;
+    use crate::blockchain::encoding::is_binary;
+    use crate::blockchain::types::BlockQuality::Bad;
     use simplelog::{ConfigBuilder, TermLogger, TerminalMode, ColorChoice};
     use log::LevelFilter;
 
+    /// Copies the test DB to a temp file, as opening it adds tables and switches the journal to WAL
+    pub fn test_db(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("gis_{}.db", name));
+        let path = path.to_str().unwrap().to_owned();
+        for suffix in &["-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", &path, suffix));
+        }
+        std::fs::copy("./tests/guachain.db", &path).unwrap();
+        path
+    }
+
     fn init_logger() {
         let config = ConfigBuilder::new()
             .add_filter_ignore_str("mio::poll")
@@ -1007,8 +1872,409 @@ pub mod tests {
     pub fn load_and_check() {
         init_logger();
         let settings = Settings::default();
-        let mut chain = Chain::new(&settings, "./tests/guachain.db");
+        let mut chain = Chain::new(&settings, &test_db("load_and_check"));
         chain.check_chain(u64::MAX);
         assert_eq!(chain.get_height(), 214);
     }
+
+    #[test]
+    pub fn check_by_parts() {
+        let settings = Settings::default();
+        let mut chain = Chain::new(&settings, &test_db("check_by_parts"));
+        let mut check = chain.start_check(100);
+        let start = check.next;
+        assert!(!chain.continue_check(&mut check, 10));
+        assert_eq!(start + 9, check.checked());
+        while !chain.continue_check(&mut check, 50) {}
+        assert_eq!(214, check.checked());
+        assert_eq!(chain.get_height(), 214);
+    }
+
+    #[test]
+    pub fn block_template() {
+        let settings = Settings::default();
+        let chain = Chain::new(&settings, &test_db("block_template"));
+        let last = chain.last_block().unwrap();
+        let transaction = Transaction::from_str("test.ygg".to_owned(), "domain".to_owned(), "{}".to_owned(), Bytes::zero32());
+        let template = chain.get_block_template(transaction, Bytes::zero32());
+        assert_eq!(template.index, last.index + 1);
+        assert_eq!(template.prev_block_hash, last.hash);
+        assert_eq!(template.bytes.as_slice(), template.block.as_bytes().as_slice());
+    }
+
+    #[test]
+    pub fn get_blocks() {
+        let settings = Settings::default();
+        let chain = Chain::new(&settings, &test_db("get_blocks"));
+        let blocks = chain.get_blocks(5, 10, false);
+        assert_eq!(10, blocks.len());
+        assert_eq!(5, blocks[0].index);
+        assert_eq!(14, blocks[9].index);
+        let full = chain.get_blocks(1, 10, true);
+        assert!(!full.is_empty());
+        assert!(full.iter().all(|block| block.transaction.is_some()));
+        assert!(chain.get_blocks(chain.get_height() + 1, 10, false).is_empty());
+    }
+
+    #[test]
+    pub fn zone_serial() {
+        let settings = Settings::default();
+        let chain = Chain::new(&settings, &test_db("zone_serial"));
+        for zone in chain.get_zones() {
+            let domains = chain.get_zone_domains(&zone.name, 1, usize::MAX);
+            let expected = domains.last().map(|entry| entry.index).unwrap_or(0);
+            assert_eq!(expected, chain.get_zone_serial(&zone.name));
+            // The second time it is taken from cache
+            assert_eq!(expected, chain.get_zone_serial(&zone.name));
+        }
+        assert_eq!(0, chain.get_zone_serial("unknown"));
+    }
+
+    #[test]
+    pub fn foreign_changes() {
+        let mut settings = Settings::default();
+        settings.chain.params.insert(String::from("delegations"), toml::Value::Boolean(true));
+        settings.chain.params.insert(String::from("sales"), toml::Value::Boolean(true));
+        let chain = Chain::new(&settings, &test_db("foreign_changes"));
+        let zone = chain.get_zones().pop().unwrap();
+        let entry = chain.get_zone_domains(&zone.name, 1, 1).pop().unwrap();
+        assert_eq!(Some(entry.pub_key.clone()), chain.get_domain_owner(u64::MAX, &entry.identity));
+        assert_eq!(None, chain.get_domain_owner(entry.index, &entry.identity));
+
+        // Tokens signed by a key that does not own the domain are refused
+        let owner = Keystore::new();
+        let provider = Keystore::new();
+        let delegation = Delegation::create(&owner, entry.identity.clone(), provider.get_public(), i64::MAX);
+        let mut data = entry.data.clone();
+        data.delegation = Some(delegation);
+        let now = chrono::Utc::now().timestamp();
+        assert_eq!(Err(String::from("Delegation is not from the owner")), chain.check_domain_change(u64::MAX, &entry.identity, &provider.get_public(), &data, now));
+        // Before delegations are enabled their tokens are ignored, as older nodes do
+        let mainnet = Chain::new(&Settings::default(), &test_db("foreign_changes_mainnet"));
+        assert_eq!(Err(String::from("Domain is owned by another key")), mainnet.check_domain_change(u64::MAX, &entry.identity, &provider.get_public(), &data, now));
+        assert_eq!(Ok(()), mainnet.check_domain_change(u64::MAX, &entry.identity, &entry.pub_key, &data, now));
+
+        // The same for offers to sell the domain
+        let offer = SaleOffer::create(&owner, entry.identity.clone(), provider.get_public(), entry.index, i64::MAX);
+        data.delegation = None;
+        data.transfer = Some(DomainTransfer::accept(&provider, offer).unwrap());
+        assert!(chain.check_domain_change(u64::MAX, &entry.identity, &provider.get_public(), &data, now).is_err());
+        assert_eq!(Err(String::from("Domain is owned by another key")), mainnet.check_domain_change(u64::MAX, &entry.identity, &provider.get_public(), &data, now));
+    }
+
+    #[test]
+    pub fn domain_ownership() {
+        let chain = Chain::new(&Settings::default(), &test_db("domain_ownership"));
+        let zone = chain.get_zones().pop().unwrap();
+        let entry = chain.get_zone_domains(&zone.name, 1, 1).pop().unwrap();
+        assert_eq!(Ok(()), chain.check_domain_ownership(entry.index, &entry.identity, entry.index, &entry.pub_key));
+        assert!(chain.check_domain_ownership(entry.index, &entry.identity, entry.index, &Keystore::new().get_public()).is_err());
+        assert!(chain.check_domain_ownership(entry.index, &entry.identity, entry.index - 1, &entry.pub_key).is_err());
+        assert_eq!(Err(String::from("There is no such domain")), chain.check_domain_ownership(entry.index - 1, &entry.identity, entry.index, &entry.pub_key));
+    }
+
+    #[test]
+    pub fn block_signers() {
+        let settings = Settings::default();
+        let chain = Chain::new(&settings, &test_db("block_signers"));
+        let full_block = chain.get_last_full_block(chain.get_height() + 1, None).unwrap();
+        let signers = chain.get_block_signers(&full_block);
+        assert_eq!(chain.get_params().signers_all as usize, signers.len());
+
+        // The same signers as if they were picked block by block
+        let mut expected = Vec::new();
+        let tail = full_block.signature.get_tail_u64();
+        let mut count = 1;
+        while expected.len() < chain.get_params().signers_all as usize {
+            let block = chain.get_block((tail.wrapping_mul(count) % (full_block.index - 1)) + 1).unwrap();
+            if block.pub_key != full_block.pub_key && !expected.contains(&block.pub_key) {
+                expected.push(block.pub_key);
+            }
+            count += 1;
+        }
+        assert_eq!(expected, signers);
+        // Second time they are taken from cache
+        assert_eq!(signers, chain.get_block_signers(&full_block));
+    }
+
+    #[test]
+    pub fn median_timestamp() {
+        assert_eq!(None, median(Vec::new()));
+        assert_eq!(Some(5), median(vec![5]));
+        assert_eq!(Some(3), median(vec![9, 1, 3, 2, 100]));
+
+        // The public chain keeps the original rule until an upgrade, blocks can't be older than the previous one
+        let mut settings = Settings::default();
+        let chain = Chain::new(&settings, &test_db("median_timestamp"));
+        let last = chain.last_block().unwrap();
+        assert_eq!(Some(last.timestamp), chain.get_median_timestamp(last.index + 1));
+        drop(chain);
+
+        settings.chain.preset = String::from("devnet");
+        let chain = Chain::new(&settings, &test_db("median_timestamp_2"));
+        let median = chain.get_median_timestamp(last.index + 1).unwrap();
+        assert!(median <= last.timestamp);
+        assert!(median >= chain.get_block(last.index - chain.get_params().median_blocks + 1).unwrap().timestamp);
+    }
+
+    #[test]
+    pub fn memory_db() {
+        let mut settings = Settings::default();
+        settings.db.in_memory = true;
+        let mut chain = Chain::new(&settings, settings.get_db_name());
+        assert_eq!(0, chain.get_height());
+        chain.set_option("checked", Some("100"));
+        assert_eq!(100, chain.get_options().checked);
+        let origin = Bytes::new(vec![1u8; 32]);
+        chain.reset(origin.clone()).unwrap();
+        assert!(chain.load_last_block().is_none());
+        assert_eq!(0, chain.get_options().checked);
+        assert_eq!(origin.to_string(), chain.get_options().origin);
+        chain.recover_db("Test");
+        assert_eq!(0, chain.get_height());
+    }
+
+    #[test]
+    pub fn snapshot() {
+        let path = std::env::temp_dir().join("gis_snapshot.bin");
+        let path = path.to_str().unwrap();
+        let settings = Settings::default();
+        let chain = Chain::new(&settings, &test_db("snapshot"));
+        assert_eq!(214, chain.export_snapshot(path).unwrap());
+
+        let mut settings = Settings::default();
+        settings.db.in_memory = true;
+        let mut file = std::fs::File::open(path).unwrap();
+        let tip = verify_snapshot(&mut file, &chain.origin, &chain.checkpoints).unwrap();
+        assert_eq!((214, chain.get_last_hash()), (tip.index, tip.hash));
+        let mut copy = Chain::new(&settings, settings.get_db_name());
+        assert_eq!(214, copy.import_snapshot(path).unwrap());
+        assert_eq!(chain.get_last_hash(), copy.get_last_hash());
+        // Known blocks are skipped
+        assert_eq!(0, copy.import_snapshot(path).unwrap());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    pub fn check_progress() {
+        let db_name = test_db("check_progress");
+        let settings = Settings::default();
+        let chain = Chain::new(&settings, &db_name);
+        assert_eq!(0, chain.get_options().checked);
+        chain.set_option("checked", Some("100"));
+        chain.set_option("checked", Some("200"));
+        assert_eq!(200, chain.get_options().checked);
+        chain.set_option("checked", None);
+        assert_eq!(0, chain.get_options().checked);
+        drop(chain);
+        let _ = std::fs::remove_file(&db_name);
+    }
+
+    #[test]
+    pub fn renewals_need_params() {
+        let settings = Settings::default();
+        let chain = Chain::new(&settings, &test_db("renewals_need_params"));
+        let identity = Bytes::from_bytes(&[1u8; 32]);
+        let pub_key = Bytes::from_bytes(&[2u8; 32]);
+        assert_eq!(Err(String::from("Renewals are not enabled yet")), chain.check_renewal(215, &identity, &pub_key, "{}", 0));
+        assert!(chain.get_renewal_data("nonexistent.ygg", &pub_key).is_err());
+    }
+
+    #[test]
+    pub fn transfers_need_params() {
+        let mut settings = Settings::default();
+        let chain = Chain::new(&settings, &test_db("transfers_need_params"));
+        let identity = Bytes::from_bytes(&[1u8; 32]);
+        assert_eq!(Err(String::from("Transfers are not enabled yet")), chain.check_transfer(215, &identity, "{}", 0));
+        settings.chain.preset = String::from("devnet");
+        settings.db.in_memory = true;
+        let chain = Chain::new(&settings, settings.get_db_name());
+        assert_eq!(Err(String::from("Wrong domain data")), chain.check_transfer(1, &identity, "{}", 0));
+    }
+
+    #[test]
+    pub fn forks_by_original_rule() {
+        let settings = Settings::default();
+        let mut chain = Chain::new(&settings, &test_db("forks_by_original_rule"));
+        let height = chain.get_height();
+        let mut fork = chain.get_block(height - 2).unwrap();
+        fork.hash = Bytes::from_bytes(&[0xFFu8; 32]);
+        assert!(chain.reorg(fork.clone()).unwrap().is_none());
+        assert_eq!(height, chain.get_height());
+
+        // One better block replaces all our blocks from its index
+        fork.hash = Bytes::from_bytes(&[0u8; 32]);
+        let reorg = chain.reorg(fork.clone()).unwrap().unwrap();
+        assert_eq!((height - 2, 3, 1), (reorg.index, reorg.removed.len(), reorg.added.len()));
+        assert_eq!(height - 2, chain.get_height());
+        assert_eq!(fork, chain.get_block(height - 2).unwrap());
+    }
+
+    #[test]
+    pub fn handovers_need_params() {
+        let mut settings = Settings::default();
+        let db_name = test_db("handovers_need_params");
+        let mut chain = Chain::new(&settings, &db_name);
+        let zone = chain.get_zones().pop().unwrap();
+        let entry = chain.get_zone_domains(&zone.name, 1, 1).pop().unwrap();
+        // Older nodes took a change with a handover like any other change of the owner
+        let owner = Keystore::new();
+        let mut data = entry.data.clone();
+        data.handover = Some(Handover::create(&owner, entry.identity.clone(), Keystore::new().get_public(), entry.index));
+        let transaction = Transaction::new(entry.identity.clone(), Bytes::default(), String::from("domain"), serde_json::to_string(&data).unwrap(), entry.pub_key.clone());
+        let last = chain.get_block(chain.get_height()).unwrap();
+        let mut block = Block::new(Some(transaction), entry.pub_key.clone(), last.hash.clone(), 0);
+        block.index = last.index + 1;
+        block.timestamp = last.timestamp + 1;
+        let now = chrono::Utc::now().timestamp();
+        assert_eq!(Ok(()), chain.check_domain_change(block.index, &entry.identity, &entry.pub_key, &data, now));
+        chain.storage.add_block(&block).unwrap();
+        assert_eq!(Some(entry.pub_key.clone()), chain.get_domain_owner(u64::MAX, &entry.identity));
+        drop(chain);
+
+        // The same block gives the domain away where transfers are enabled at its height
+        settings.chain.params.insert(String::from("transfers"), toml::Value::Boolean(true));
+        let chain = Chain::new(&settings, &db_name);
+        assert_eq!(data.handover.clone().map(|handover| handover.new_owner), chain.get_domain_owner(u64::MAX, &entry.identity));
+        // And new blocks give domains only by transfer transactions then
+        assert_eq!(Err(String::from("Domains are given only by transfer transactions")), chain.check_domain_change(u64::MAX, &entry.identity, &entry.pub_key, &data, now));
+    }
+
+    #[test]
+    pub fn binary_transactions_by_version() {
+        let mut settings = Settings::default();
+        settings.chain.preset = String::from("devnet");
+        settings.db.in_memory = true;
+        let chain = Chain::new(&settings, settings.get_db_name());
+        assert!(!is_binary(chain.get_block_version()));
+        settings.chain.params.insert(String::from("binary_transactions"), toml::Value::Boolean(true));
+        let chain = Chain::new(&settings, settings.get_db_name());
+        assert!(is_binary(chain.get_block_version()));
+        // Blocks in JSON are not accepted after the switch
+        let mut block = Block::new(None, Bytes::default(), Bytes::default(), 0);
+        block.index = 1;
+        assert!(chain.check_new_block(&block) == Bad);
+    }
+
+    fn full_block(index: u64, hash: u8) -> Block {
+        let mut block = Block::new(None, Bytes::default(), Bytes::default(), 0);
+        block.index = index;
+        block.hash = Bytes::from_bytes(&[hash; 32]);
+        block
+    }
+
+    #[test]
+    pub fn signers_cache_evicts_unused() {
+        let cache = SignersCache::new();
+        let mut cache = cache.borrow_mut();
+        let signers = vec![Bytes::from_bytes(&[1u8; 32])];
+        for index in 1..=SIGNERS_CACHE_SIZE as u64 {
+            cache.insert(&full_block(index, 1), signers.clone());
+        }
+        // The oldest block is used by a fork, so the next one is evicted instead
+        assert!(cache.get(&full_block(1, 1)).is_some());
+        cache.insert(&full_block(100, 1), signers.clone());
+        assert!(cache.get(&full_block(1, 1)).is_some());
+        assert!(cache.get(&full_block(2, 1)).is_none());
+        // Another block with the same index is not mistaken for the cached one
+        assert!(cache.get(&full_block(100, 2)).is_none());
+
+        cache.truncate(50);
+        assert!(cache.get(&full_block(100, 1)).is_none());
+        assert!(cache.get(&full_block(3, 1)).is_some());
+    }
+
+    #[test]
+    pub fn signers_are_loaded_once() {
+        let settings = Settings::default();
+        let chain = Chain::new(&settings, &test_db("signers_are_loaded_once"));
+        let full: Vec<Block> = chain.storage.get_blocks(1, 214, true).into_iter().rev().take(3).collect();
+        assert_eq!(3, full.len());
+        // Switching between blocks like forks do doesn't go to DB again
+        for _ in 0..5 {
+            for block in &full {
+                assert!(!chain.get_block_signers(block).is_empty());
+            }
+        }
+        assert_eq!(3, chain.signers.borrow().loads);
+        chain.signers.borrow_mut().truncate(full[0].index);
+        chain.get_block_signers(&full[0]);
+        chain.get_block_signers(&full[1]);
+        assert_eq!(4, chain.signers.borrow().loads);
+    }
+
+    #[test]
+    pub fn verdicts_are_bounded() {
+        let mut verified = VerifiedBlocks::new(2);
+        let hash = Bytes::from_bytes(&[1u8; 32]);
+        verified.insert(hash.clone(), vec![1], true);
+        assert_eq!(Some(true), verified.get(&hash, &[1]));
+        // The same hash in a block with other content is not trusted
+        assert_eq!(None, verified.get(&hash, &[2]));
+        verified.insert(Bytes::from_bytes(&[2u8; 32]), vec![2], false);
+        verified.insert(Bytes::from_bytes(&[3u8; 32]), vec![3], true);
+        assert_eq!(None, verified.get(&hash, &[1]));
+        assert_eq!(Some(false), verified.get(&Bytes::from_bytes(&[2u8; 32]), &[2]));
+        assert_eq!(2, verified.verdicts.len());
+    }
+
+    #[test]
+    pub fn blocks_are_verified_once() {
+        let settings = Settings::default();
+        let chain = Chain::new(&settings, &test_db("blocks_are_verified_once"));
+        let block = chain.get_block(100).unwrap();
+        assert!(chain.check_block_crypto(&block));
+        assert!(chain.check_block_crypto(&block));
+        assert_eq!(1, chain.verified.borrow().verdicts.len());
+        let mut forged = block.clone();
+        forged.nonce += 1;
+        assert!(!chain.check_block_crypto(&forged));
+        assert_eq!(Some(false), chain.verified.borrow().get(&forged.hash, &super::hash_sha256(&forged.as_bytes())));
+    }
+
+    #[test]
+    pub fn signers_are_chosen_once() {
+        let settings = Settings::default();
+        let mut chain = Chain::new(&settings, &test_db("signers_are_chosen_once"));
+        let keystore = Some(Keystore::new());
+        let index = chain.last_full_block.as_ref().unwrap().index;
+        assert_eq!(Some((index, false)), chain.update_signers(&keystore));
+        assert_eq!(None, chain.update_signers(&keystore));
+        // Another key has to know about it too
+        assert_eq!(Some((index, false)), chain.update_signers(&Some(Keystore::new())));
+        assert_eq!(None, chain.update_signers(&None));
+    }
+
+    #[test]
+    pub fn dns_view_has_zones() {
+        let settings = Settings::default();
+        let mut chain = Chain::new(&settings, &test_db("dns_view_has_zones"));
+        let view = chain.get_dns_view();
+        let zones = chain.get_zones();
+        assert!(!zones.is_empty());
+        for zone in zones {
+            assert!(view.has_zone(&zone.name));
+            assert_eq!(chain.get_zone_serial(&zone.name), view.get_serial(&zone.name));
+        }
+        assert!(!view.has_zone("unknown"));
+        assert!(view.get_domain("unknown.unknown").is_none());
+    }
+
+    #[test]
+    pub fn my_domains_cache() {
+        let (ours, other) = (Bytes::from_bytes(&[1u8; 32]), Bytes::from_bytes(&[2u8; 32]));
+        let mut cache = MyDomains::default();
+        cache.set_key(&ours);
+        cache.names.insert((Bytes::from_bytes(&[3u8; 32]), Bytes::from_bytes(&[4u8; 32])), String::from("example.ygg"));
+        cache.invalidate(&other, &other);
+        assert_eq!(1, cache.names.len());
+        cache.invalidate(&other, &ours);
+        assert!(cache.names.is_empty());
+
+        cache.names.insert((Bytes::from_bytes(&[3u8; 32]), Bytes::from_bytes(&[4u8; 32])), String::from("example.ygg"));
+        cache.set_key(&ours);
+        assert_eq!(1, cache.names.len());
+        cache.set_key(&other);
+        assert!(cache.names.is_empty());
+    }
 }
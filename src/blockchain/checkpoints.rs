@@ -0,0 +1,110 @@
+//! Hashes of some blocks of the public chain, bundled with every release.
+//! A new node can't be fed a fabricated chain by its only reachable peer, as that chain won't pass these blocks.
+
+use std::collections::BTreeMap;
+
+use crate::{from_hex, Bytes};
+
+#[derive(Clone, Debug, Default)]
+pub struct Checkpoints {
+    points: BTreeMap<u64, Bytes>,
+}
+
+impl Checkpoints {
+    /// Gets checkpoints of this release, if they are of the chain with this origin
+    pub fn bundled(origin: &Bytes) -> Self {
+        let checkpoints = Self::parse(include_str!("../../checkpoints.txt")).expect("Wrong bundled checkpoints");
+        match checkpoints.points.get(&1) {
+            Some(hash) if hash == origin => checkpoints,
+            _ => Self::default()
+        }
+    }
+
+    /// Parses lines with block index and hash, skipping empty lines and comments
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut points = BTreeMap::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() != 2 {
+                return Err(format!("Wrong checkpoint '{}'", line));
+            }
+            let index = parts[0].parse::<u64>().map_err(|_| format!("Wrong index in checkpoint '{}'", line))?;
+            let hash = from_hex(parts[1]).map_err(|_| format!("Wrong hash in checkpoint '{}'", line))?;
+            if hash.len() != 32 {
+                return Err(format!("Wrong hash length in checkpoint '{}'", line));
+            }
+            points.insert(index, Bytes::from_bytes(&hash));
+        }
+        Ok(Checkpoints { points })
+    }
+
+    /// Checks that block with this index has the hash of checkpoint, if there is one
+    pub fn check(&self, index: u64, hash: &Bytes) -> bool {
+        match self.points.get(&index) {
+            Some(point) => point == hash,
+            None => true
+        }
+    }
+
+    /// Gets the index of the last checkpoint, or zero if there are none
+    pub fn get_last_index(&self) -> u64 {
+        self.points.keys().next_back().cloned().unwrap_or(0)
+    }
+
+    /// Counts checkpoints with indexes up to this one
+    pub fn count_up_to(&self, index: u64) -> usize {
+        self.points.range(..=index).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Bytes;
+    use crate::blockchain::chain::tests::test_db;
+    use crate::blockchain::checkpoints::Checkpoints;
+    use crate::blockchain::hash_utils::blakeout_data;
+    use crate::blockchain::storage::BlockStorage;
+    use crate::blockchain::storage::sqlite::SqliteStorage;
+
+    #[test]
+    fn checkpoints() {
+        let origin = Bytes::new(vec![1u8; 32]);
+        let text = format!("# comment\n1 {}\n\n100 {}\n", origin.to_string(), Bytes::new(vec![2u8; 32]).to_string());
+        let checkpoints = Checkpoints::parse(&text).unwrap();
+        assert_eq!(100, checkpoints.get_last_index());
+        assert!(checkpoints.check(1, &origin));
+        assert!(!checkpoints.check(100, &origin));
+        assert!(checkpoints.check(50, &origin));
+
+        assert!(Checkpoints::parse("1 00FF").is_err());
+        assert!(Checkpoints::parse("one 00FF").is_err());
+        assert!(Checkpoints::bundled(&origin).is_empty());
+        let mainnet = crate::from_hex("0AE588D62D710422A7972EA1E8A659CC8E93DB59489ACE32C499CD279B000000").unwrap();
+        assert!(!Checkpoints::bundled(&Bytes::from_bytes(&mainnet)).is_empty());
+    }
+
+    #[test]
+    fn bundled() {
+        let storage = SqliteStorage::open(&test_db("bundled"), false, 0).unwrap();
+        let origin = storage.get_block(1).unwrap().hash;
+        let checkpoints = Checkpoints::bundled(&origin);
+        let index = checkpoints.get_last_index();
+        assert!(index > 1);
+        // Blocks of the public chain pass all checkpoints
+        for block in storage.get_blocks(1, index, false) {
+            assert!(checkpoints.check(block.index, &block.hash));
+        }
+        // A block of another chain with the same origin doesn't
+        let mut forged = storage.get_block(index).unwrap();
+        forged.nonce += 1;
+        forged.hash = blakeout_data(&forged.as_bytes());
+        assert!(!checkpoints.check(forged.index, &forged.hash));
+    }
+}
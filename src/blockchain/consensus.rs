@@ -0,0 +1,269 @@
+//! Parameters of block validation, that must be the same for all nodes of one network.
+//!
+//! Parameters can be changed by upgrades, activating at some block height. Upgrades are built into
+//! presets of releases, and miners signal that they know about pending upgrades by setting upgrade
+//! bits in the random value of blocks. Old nodes hash it like any other value, but don't check it.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::commons::constants::*;
+
+/// The highest byte of block random is this marker in blocks with signals, so that random values
+/// of nodes without signals rarely look like signals. The next byte has bits of upgrades.
+pub const SIGNAL_MARKER: u32 = 0x47 << 24;
+pub const SIGNAL_MARKER_MASK: u32 = 0xFF << 24;
+const UPGRADE_BITS_SHIFT: u32 = 16;
+const MAX_UPGRADE_BITS: u8 = 8;
+
+/// Gets the flag of upgrade bit in block random
+pub fn get_upgrade_flag(bit: u8) -> u32 {
+    1 << (UPGRADE_BITS_SHIFT + bit as u32)
+}
+
+/// Puts upgrade bits into the random value of a new block, the lower half stays random
+pub fn add_signals(random: u32, bits: u32) -> u32 {
+    match bits {
+        0 => random,
+        _ => SIGNAL_MARKER | bits | (random & 0xFFFF)
+    }
+}
+
+/// Checks if the random value of a block signals the upgrade with this flag
+pub fn has_signal(random: u32, flag: u32) -> bool {
+    random & SIGNAL_MARKER_MASK == SIGNAL_MARKER && random & flag != 0
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConsensusParams {
+    /// Difficulty of zones and of the genesis block
+    pub zone_difficulty: u32,
+    /// Zones can't set lower difficulty for their domains
+    pub zone_min_difficulty: u32,
+    /// Difficulty of signing blocks
+    pub signer_difficulty: u32,
+    /// Blocks start to be signed starting from this index
+    pub signers_start: u64,
+    /// How many signers are chosen for every full block
+    pub signers_all: u64,
+    /// Minimal signatures needed
+    pub signers_min: u64,
+    /// Seconds that chosen signers have to sign a block
+    pub signers_time: i64,
+    /// How often one key can mine new domains, in seconds
+    pub new_domains_interval: i64,
+    /// Domains expire after this many seconds without renewal
+    pub domain_lifetime: i64,
+    /// Owners can extend the lifetime of their domains by "renew" transactions
+    pub renewals: bool,
+    /// Owners can give their domains to other keys by "transfer" transactions
+    pub transfers: bool,
+    /// Other keys can change records of domains with a delegation token from the owner
+    pub delegations: bool,
+    /// Buyers can get domains by mining them with an offer signed by the owner
+    pub sales: bool,
+    /// Blocks are hashed and stored with binary encoding of transactions, see `BINARY_RULES_VERSION`
+    pub binary_transactions: bool,
+    /// Data of transactions must be in canonical form and pass all checks of `blockchain::schema`
+    pub strict_data: bool,
+    /// New block must not be older than the median time of this many previous blocks,
+    /// 1 is the original rule, that a block can't be older than the previous one
+    pub median_blocks: u64,
+    /// How many seconds block time can be ahead of our clock, originally 60
+    pub max_future_time: i64,
+    /// Forks are resolved by cumulative work of branches, see `blockchain::reorg`. Originally one forked block
+    /// replaces our blocks from its index, if it is better than our last block
+    pub branch_reorgs: bool,
+    /// Scheduled changes of these parameters, they are a part of the preset and can't be set in settings
+    #[serde(skip)]
+    pub upgrades: Vec<Upgrade>,
+}
+
+/// Change of consensus parameters starting from some block
+#[derive(Clone, Debug, PartialEq)]
+pub struct Upgrade {
+    pub name: String,
+    /// Bit of block random that miners set before activation, from 0 to 7
+    pub bit: u8,
+    /// The first block with changed parameters
+    pub height: u64,
+    /// Changed parameters, like `zone_difficulty = 26`
+    pub params: HashMap<String, toml::Value>,
+}
+
+/// Consensus parameters for every height where they change, sorted by height, starting from zero
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    steps: Vec<(u64, ConsensusParams)>,
+}
+
+impl Schedule {
+    /// Parameters for the block with this index, with all upgrades activated before it
+    pub fn at(&self, index: u64) -> &ConsensusParams {
+        match self.steps.iter().rev().find(|(height, _)| *height <= index) {
+            Some((_, params)) => params,
+            None => &self.steps[0].1
+        }
+    }
+}
+
+/// Parameters of the public network, for clients that don't have settings
+impl Default for Schedule {
+    fn default() -> Self {
+        Schedule { steps: vec![(0, ConsensusParams::mainnet())] }
+    }
+}
+
+impl ConsensusParams {
+    /// Parameters of the public network
+    pub fn mainnet() -> Self {
+        ConsensusParams {
+            zone_difficulty: ZONE_DIFFICULTY,
+            zone_min_difficulty: ZONE_MIN_DIFFICULTY,
+            signer_difficulty: SIGNER_DIFFICULTY,
+            signers_start: BLOCK_SIGNERS_START,
+            signers_all: BLOCK_SIGNERS_ALL,
+            signers_min: BLOCK_SIGNERS_MIN,
+            signers_time: BLOCK_SIGNERS_TIME,
+            new_domains_interval: NEW_DOMAINS_INTERVAL,
+            domain_lifetime: DOMAIN_LIFETIME,
+            renewals: false,
+            transfers: false,
+            delegations: false,
+            sales: false,
+            binary_transactions: false,
+            strict_data: false,
+            // The original time rules, until an upgrade changes them for all nodes at once
+            median_blocks: 1,
+            max_future_time: 60,
+            branch_reorgs: false,
+            upgrades: Vec::new(),
+        }
+    }
+
+    /// Parameters for development and private networks, blocks are mined in seconds
+    pub fn devnet() -> Self {
+        ConsensusParams {
+            zone_difficulty: 16,
+            zone_min_difficulty: 12,
+            signer_difficulty: 8,
+            signers_all: 3,
+            signers_min: 1,
+            signers_time: 60,
+            new_domains_interval: 60,
+            renewals: true,
+            transfers: true,
+            delegations: true,
+            sales: true,
+            median_blocks: 11,
+            max_future_time: 300,
+            branch_reorgs: true,
+            ..Self::mainnet()
+        }
+    }
+
+    /// Gets parameters by preset name, empty name is for mainnet
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "" | "mainnet" => Some(Self::mainnet()),
+            "devnet" => Some(Self::devnet()),
+            _ => None
+        }
+    }
+
+    /// Gets parameters for every height where they change
+    pub fn get_schedule(&self) -> Result<Schedule, String> {
+        let mut upgrades = self.upgrades.clone();
+        upgrades.sort_by_key(|upgrade| upgrade.height);
+        let mut current = ConsensusParams { upgrades: Vec::new(), ..self.clone() };
+        let mut result = vec![(0, current.clone())];
+        let mut bits = 0u32;
+        for upgrade in &upgrades {
+            if upgrade.bit >= MAX_UPGRADE_BITS {
+                return Err(format!("Bit of upgrade '{}' must be less than {}", &upgrade.name, MAX_UPGRADE_BITS));
+            }
+            if bits & get_upgrade_flag(upgrade.bit) != 0 {
+                return Err(format!("Bit {} is used by several upgrades", upgrade.bit));
+            }
+            bits |= get_upgrade_flag(upgrade.bit);
+            let mut value = toml::Value::try_from(&current).map_err(|e| e.to_string())?;
+            if let Some(table) = value.as_table_mut() {
+                for (name, param) in &upgrade.params {
+                    table.insert(name.clone(), param.clone());
+                }
+            }
+            current = value.try_into().map_err(|e: toml::de::Error| format!("Wrong upgrade '{}': {}", &upgrade.name, e))?;
+            result.push((upgrade.height, current.clone()));
+        }
+        Ok(Schedule { steps: result })
+    }
+
+    /// Gets upgrade bits that miners set in blocks with this index, for upgrades that are not active yet
+    pub fn get_signal_bits(&self, index: u64) -> u32 {
+        self.upgrades.iter()
+            .filter(|upgrade| upgrade.height > index)
+            .fold(0, |bits, upgrade| bits | get_upgrade_flag(upgrade.bit))
+    }
+}
+
+impl Default for ConsensusParams {
+    fn default() -> Self {
+        Self::mainnet()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upgrade(name: &str, bit: u8, height: u64, params: &[(&str, toml::Value)]) -> Upgrade {
+        let params = params.iter().map(|(name, value)| (name.to_string(), value.clone())).collect();
+        Upgrade { name: name.to_owned(), bit, height, params }
+    }
+
+    #[test]
+    fn schedule() {
+        let mut params = ConsensusParams::mainnet();
+        params.upgrades.push(upgrade("harder zones", 1, 2000, &[("zone_difficulty", toml::Value::Integer(30))]));
+        params.upgrades.push(upgrade("faster domains", 0, 1000, &[("new_domains_interval", toml::Value::Integer(3600))]));
+        let schedule = params.get_schedule().unwrap();
+        assert_eq!((ZONE_DIFFICULTY, NEW_DOMAINS_INTERVAL), (schedule.at(999).zone_difficulty, schedule.at(999).new_domains_interval));
+        assert_eq!((ZONE_DIFFICULTY, 3600), (schedule.at(1000).zone_difficulty, schedule.at(1000).new_domains_interval));
+        assert_eq!((30, 3600), (schedule.at(2000).zone_difficulty, schedule.at(2000).new_domains_interval));
+        assert!(schedule.at(2000).upgrades.is_empty());
+
+        assert_eq!(get_upgrade_flag(0) | get_upgrade_flag(1), params.get_signal_bits(999));
+        assert_eq!(get_upgrade_flag(1), params.get_signal_bits(1000));
+        assert_eq!(0, params.get_signal_bits(2000));
+
+        let mut params = ConsensusParams::mainnet();
+        params.upgrades.push(upgrade("median time", 2, 500, &[("median_blocks", toml::Value::Integer(11)), ("max_future_time", toml::Value::Integer(300))]));
+        let schedule = params.get_schedule().unwrap();
+        // Blocks before the upgrade are checked by the original time rules
+        assert_eq!((1, 60), (schedule.at(499).median_blocks, schedule.at(499).max_future_time));
+        assert_eq!((11, 300), (schedule.at(500).median_blocks, schedule.at(500).max_future_time));
+
+        let mut params = ConsensusParams::mainnet();
+        params.upgrades.push(upgrade("typo", 0, 10, &[("zone_dificulty", toml::Value::Integer(30))]));
+        assert!(params.get_schedule().is_err());
+        params.upgrades[0] = upgrade("too high", MAX_UPGRADE_BITS, 10, &[]);
+        assert!(params.get_schedule().is_err());
+
+        // Upgrades can't be planned by local settings
+        assert!(toml::from_str::<ConsensusParams>("[[upgrades]]\nname = \"local\"\nbit = 0\nheight = 10").is_err());
+    }
+
+    #[test]
+    fn signals() {
+        let bits = get_upgrade_flag(0) | get_upgrade_flag(7);
+        let random = add_signals(0x12345678, bits);
+        assert_eq!(0x5678, random & 0xFFFF);
+        assert!(has_signal(random, get_upgrade_flag(0)) && has_signal(random, get_upgrade_flag(7)));
+        assert!(!has_signal(random, get_upgrade_flag(3)));
+        assert_eq!(0x12345678, add_signals(0x12345678, 0));
+        // Random values without the marker are not signals
+        assert!(!has_signal(0xFFFFFFFF, get_upgrade_flag(0)));
+    }
+}
@@ -0,0 +1,74 @@
+//! Signed tokens that let another key change records of a domain until some time.
+//! Owners give them to hosting providers, that manage records for their customers,
+//! and the token is put to the domain data of every update mined by the delegate.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Bytes, Keystore};
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Delegation {
+    /// Hash of the domain name, blocks don't have names
+    pub identity: Bytes,
+    pub owner: Bytes,
+    pub delegate: Bytes,
+    /// Time after which the delegate can't mine updates anymore
+    pub expires: i64,
+    pub signature: Bytes,
+}
+
+impl Delegation {
+    /// Signs the token by the key of the owner
+    pub fn create(keystore: &Keystore, identity: Bytes, delegate: Bytes, expires: i64) -> Self {
+        let mut result = Delegation { identity, owner: keystore.get_public(), delegate, expires, signature: Bytes::default() };
+        result.signature = Bytes::from_bytes(&keystore.sign(result.get_statement().as_bytes()));
+        result
+    }
+
+    /// Gets the text that is signed by the owner
+    pub fn get_statement(&self) -> String {
+        format!("Key {} allows key {} to change records of domain {} until {}",
+                self.owner.to_string(), self.delegate.to_string(), self.identity.to_string(), self.expires)
+    }
+
+    /// Checks that this token lets `delegate` change the domain with `identity` at time `timestamp`.
+    /// The caller needs to check that `owner` really owns the domain.
+    pub fn verify(&self, identity: &Bytes, delegate: &Bytes, timestamp: i64) -> Result<(), String> {
+        if &self.identity != identity {
+            return Err(String::from("Token is for another domain"));
+        }
+        if &self.delegate != delegate {
+            return Err(String::from("Token is for another key"));
+        }
+        if timestamp > self.expires {
+            return Err(String::from("Token has expired"));
+        }
+        if !Keystore::check(self.get_statement().as_bytes(), &self.owner, &self.signature) {
+            return Err(String::from("Wrong signature"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blockchain::hash_utils::hash_identity;
+
+    use super::*;
+
+    #[test]
+    fn delegation() {
+        let owner = Keystore::new();
+        let provider = Keystore::new();
+        let identity = hash_identity("test.ygg", None);
+        let token = Delegation::create(&owner, identity.clone(), provider.get_public(), 1600001000);
+        assert!(token.verify(&identity, &provider.get_public(), 1600000000).is_ok());
+        assert!(token.verify(&identity, &provider.get_public(), 1600002000).is_err());
+        assert!(token.verify(&identity, &owner.get_public(), 1600000000).is_err());
+        assert!(token.verify(&hash_identity("other.ygg", None), &provider.get_public(), 1600000000).is_err());
+
+        let mut extended = token.clone();
+        extended.expires = 1700000000;
+        assert!(extended.verify(&identity, &provider.get_public(), 1600002000).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! Comparison of two blockchain DBs, to find out why two nodes disagree or to verify a backup.
+//! Blocks are linked by hashes, so the chains are the same up to the first differing block,
+//! and only domains and zones changed after it can have different state.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::{Bytes, Chain};
+
+/// How many blocks are read from DB at once
+const DIFF_BATCH: u64 = 1000;
+
+/// Domain or zone with different state in two chains
+#[derive(Clone, Debug, PartialEq)]
+pub struct StateDiff {
+    pub identity: Bytes,
+    pub class: String,
+    /// Last block that changed it in the first chain after divergence, none if it is the same as before divergence
+    pub block_a: Option<u64>,
+    pub block_b: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChainDiff {
+    pub height_a: u64,
+    pub height_b: u64,
+    /// First block that differs or is absent in one of the chains, none if the chains are equal
+    pub divergence: Option<u64>,
+    pub states: Vec<StateDiff>,
+}
+
+/// Compares blocks and the state of domains and zones in two chains
+pub fn diff_chains(a: &Chain, b: &Chain) -> ChainDiff {
+    let height_a = a.get_height();
+    let height_b = b.get_height();
+    let divergence = find_divergence(a, b);
+    let states = match divergence {
+        Some(from) => diff_states(&get_changes(a, from), &get_changes(b, from)),
+        None => Vec::new()
+    };
+    ChainDiff { height_a, height_b, divergence, states }
+}
+
+/// Finds the first block that differs by binary search, as equal blocks have equal history
+fn find_divergence(a: &Chain, b: &Chain) -> Option<u64> {
+    let height = a.get_height().min(b.get_height());
+    let same = |index: u64| match (a.get_block(index), b.get_block(index)) {
+        (Some(block_a), Some(block_b)) => block_a.hash == block_b.hash,
+        _ => false
+    };
+    // The first differing block is in (low, high]
+    let (mut low, mut high) = (0u64, height + 1);
+    while high - low > 1 {
+        let middle = low + (high - low) / 2;
+        if same(middle) {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    if high <= height || a.get_height() != b.get_height() {
+        Some(high)
+    } else {
+        None
+    }
+}
+
+/// Gets last change of every identity from block `from` to the end of chain
+fn get_changes(chain: &Chain, from: u64) -> HashMap<Bytes, (u64, String, String)> {
+    let mut changes = HashMap::new();
+    let mut next = from;
+    loop {
+        let blocks = chain.get_blocks(next, DIFF_BATCH, true);
+        let last = match blocks.last() {
+            Some(block) => block.index,
+            None => break
+        };
+        for block in blocks {
+            if let Some(transaction) = block.transaction {
+                changes.insert(transaction.identity, (block.index, transaction.class, transaction.data));
+            }
+        }
+        next = last + 1;
+    }
+    changes
+}
+
+fn diff_states(a: &HashMap<Bytes, (u64, String, String)>, b: &HashMap<Bytes, (u64, String, String)>) -> Vec<StateDiff> {
+    let mut states = Vec::new();
+    for (identity, (index, class, data)) in a {
+        match b.get(identity) {
+            Some((_, _, other)) if other == data => {}
+            other => states.push(StateDiff { identity: identity.clone(), class: class.clone(), block_a: Some(*index), block_b: other.map(|(index, _, _)| *index) })
+        }
+    }
+    for (identity, (index, class, _)) in b {
+        if !a.contains_key(identity) {
+            states.push(StateDiff { identity: identity.clone(), class: class.clone(), block_a: None, block_b: Some(*index) });
+        }
+    }
+    states.sort_by_key(|state| state.block_a.unwrap_or(u64::MAX).min(state.block_b.unwrap_or(u64::MAX)));
+    states
+}
+
+impl fmt::Display for ChainDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Heights: {} and {}", self.height_a, self.height_b)?;
+        match self.divergence {
+            None => return writeln!(f, "Chains are equal"),
+            Some(1) => writeln!(f, "Chains have different origins")?,
+            Some(index) if index > self.height_a.min(self.height_b) => writeln!(f, "Chains are equal up to block {}, one of them is longer", index - 1)?,
+            Some(index) => writeln!(f, "Chains diverge at block {}", index)?
+        }
+        if self.states.is_empty() {
+            return writeln!(f, "State of domains and zones is the same");
+        }
+        writeln!(f, "{} domains and zones have different state:", self.states.len())?;
+        let block = |index: Option<u64>| index.map(|index| index.to_string()).unwrap_or_else(|| String::from("-"));
+        for state in &self.states {
+            writeln!(f, "{} {:?}, changed in block {} and {}", &state.class, &state.identity, block(state.block_a), block(state.block_b))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blockchain::chain::tests::test_db;
+    use crate::Settings;
+
+    use super::*;
+
+    #[test]
+    fn diff() {
+        let mut settings = Settings::default();
+        settings.origin = String::new();
+        let chain = Chain::new(&settings, &test_db("diff"));
+        let diff = diff_chains(&chain, &chain);
+        assert_eq!(None, diff.divergence);
+        assert!(diff.states.is_empty());
+
+        settings.db.in_memory = true;
+        let mut short = Chain::new(&settings, settings.get_db_name());
+        for block in chain.get_blocks(1, 150, false) {
+            short.add_block(block);
+        }
+        let diff = diff_chains(&chain, &short);
+        assert_eq!(Some(151), diff.divergence);
+        assert_eq!((214, 150), (diff.height_a, diff.height_b));
+        let changed = chain.get_blocks(151, 100, true).len();
+        assert!(diff.states.len() <= changed);
+        assert!(diff.states.iter().all(|state| state.block_a.unwrap() > 150 && state.block_b.is_none()));
+    }
+}
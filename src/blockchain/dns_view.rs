@@ -0,0 +1,190 @@
+//! Read-only view of the chain for DNS threads: live domains by identity with their parsed data, zones and their serials.
+//! Chain keeps it up to date when blocks are added or removed, and DNS threads read it without locking the context,
+//! so that queries don't wait for new blocks to be checked and written.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+use chrono::Utc;
+
+use crate::{Bytes, CLASS_ZONE, Transaction};
+use crate::blockchain::hash_utils::hash_identity;
+use crate::blockchain::storage::{BlockStorage, IdentityRow};
+use crate::blockchain::transaction::{DomainData, ZoneData};
+
+pub struct DnsView {
+    state: RwLock<ViewState>,
+}
+
+#[derive(Default)]
+struct ViewState {
+    domains: HashMap<Bytes, ViewDomain>,
+    zones: HashSet<String>,
+    /// Index of the last block that has changed some domain in the zone
+    serials: HashMap<String, u64>,
+    domain_lifetime: i64,
+}
+
+struct ViewDomain {
+    index: u64,
+    timestamp: i64,
+    /// Key and confirmation of the last change, to check the name itself, not only its hash
+    pub_key: Bytes,
+    confirmation: Bytes,
+    data: Arc<DomainData>,
+}
+
+impl ViewState {
+    /// Puts the domain change in place of the previous one
+    fn add_domain(&mut self, row: &IdentityRow) {
+        let data = match serde_json::from_str::<DomainData>(&row.transaction.data) {
+            Ok(data) => data,
+            Err(_) => return
+        };
+        let serial = self.serials.entry(data.zone.clone()).or_insert(0);
+        *serial = (*serial).max(row.index);
+        let transaction = &row.transaction;
+        let domain = ViewDomain {
+            index: row.index,
+            timestamp: row.timestamp,
+            pub_key: transaction.pub_key.clone(),
+            confirmation: transaction.confirmation.clone(),
+            data: Arc::new(data)
+        };
+        self.domains.insert(transaction.identity.clone(), domain);
+    }
+
+    fn load_zones(&mut self, storage: &dyn BlockStorage) {
+        self.zones = storage.get_zones()
+            .iter()
+            .filter_map(|data| serde_json::from_str::<ZoneData>(data).ok())
+            .map(|zone| zone.name)
+            .collect();
+    }
+
+    /// Finds the serial of the zone in DB, going back from the last domain change
+    fn load_serial(&mut self, storage: &dyn BlockStorage, zone: &str) {
+        let mut serial = None;
+        storage.for_each_domain(None, &mut |row| {
+            match serde_json::from_str::<DomainData>(&row.transaction.data) {
+                Ok(data) if data.zone == zone => {
+                    serial = Some(row.index);
+                    false
+                }
+                _ => true
+            }
+        });
+        match serial {
+            Some(serial) => self.serials.insert(zone.to_owned(), serial),
+            None => self.serials.remove(zone)
+        };
+    }
+}
+
+impl DnsView {
+    /// Builds the view from all domains and zones in DB
+    pub fn new(storage: &dyn BlockStorage, domain_lifetime: i64) -> Self {
+        let view = DnsView { state: RwLock::new(ViewState::default()) };
+        view.rebuild(storage, domain_lifetime);
+        view
+    }
+
+    pub fn rebuild(&self, storage: &dyn BlockStorage, domain_lifetime: i64) {
+        let mut state = ViewState { domain_lifetime, ..ViewState::default() };
+        let oldest = Utc::now().timestamp() - domain_lifetime;
+        let mut seen = HashSet::new();
+        // Going back from the last change, only the first change of every domain is the current one
+        storage.for_each_domain(None, &mut |row| {
+            if seen.insert(row.transaction.identity.clone()) {
+                if row.timestamp >= oldest {
+                    state.add_domain(row);
+                } else if let Ok(data) = serde_json::from_str::<DomainData>(&row.transaction.data) {
+                    // Expired domains are not kept, but they still have changed their zones
+                    state.serials.entry(data.zone).or_insert(row.index);
+                }
+            }
+            true
+        });
+        state.load_zones(storage);
+        if let Ok(mut current) = self.state.write() {
+            *current = state;
+        }
+    }
+
+    /// Adds the change from a new block
+    pub fn add_transaction(&self, index: u64, timestamp: i64, transaction: &Transaction, domain_lifetime: i64) {
+        let mut state = match self.state.write() {
+            Ok(state) => state,
+            Err(_) => return
+        };
+        state.domain_lifetime = domain_lifetime;
+        if transaction.class == CLASS_ZONE {
+            if let Ok(zone) = serde_json::from_str::<ZoneData>(&transaction.data) {
+                state.zones.insert(zone.name);
+            }
+            return;
+        }
+        state.add_domain(&IdentityRow { index, timestamp, transaction: transaction.clone() });
+    }
+
+    /// Removes changes of blocks from `index`, the domains get their previous changes from DB, that is already truncated
+    pub fn truncate(&self, index: u64, storage: &dyn BlockStorage) {
+        let mut state = match self.state.write() {
+            Ok(state) => state,
+            Err(_) => return
+        };
+        let removed: Vec<Bytes> = state.domains.iter()
+            .filter(|(_, domain)| domain.index >= index)
+            .map(|(identity, _)| identity.clone())
+            .collect();
+        for identity in removed {
+            state.domains.remove(&identity);
+            if let Some(row) = storage.get_last_domain(&identity) {
+                state.add_domain(&row);
+            }
+        }
+        let zones: Vec<String> = state.serials.iter()
+            .filter(|(_, serial)| **serial >= index)
+            .map(|(zone, _)| zone.clone())
+            .collect();
+        for zone in zones {
+            state.load_serial(storage, &zone);
+        }
+        state.load_zones(storage);
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut state) = self.state.write() {
+            let domain_lifetime = state.domain_lifetime;
+            *state = ViewState { domain_lifetime, ..ViewState::default() };
+        }
+    }
+
+    /// Gets data of the domain, if it is registered and not expired
+    pub fn get_domain(&self, domain: &str) -> Option<Arc<DomainData>> {
+        let state = self.state.read().ok()?;
+        let entry = state.domains.get(&hash_identity(domain, None))?;
+        if entry.timestamp < Utc::now().timestamp() - state.domain_lifetime {
+            return None;
+        }
+        if entry.confirmation != hash_identity(domain, Some(&entry.pub_key)) {
+            return None;
+        }
+        Some(Arc::clone(&entry.data))
+    }
+
+    pub fn has_zone(&self, zone: &str) -> bool {
+        match self.state.read() {
+            Ok(state) => state.zones.contains(zone),
+            Err(_) => false
+        }
+    }
+
+    /// Serial of the zone is the index of the last block that has changed it
+    pub fn get_serial(&self, zone: &str) -> u64 {
+        match self.state.read() {
+            Ok(state) => state.serials.get(zone).cloned().unwrap_or(0),
+            Err(_) => 0
+        }
+    }
+}
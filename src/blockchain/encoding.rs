@@ -0,0 +1,167 @@
+//! Compact binary encoding of blocks and transactions, for blocks of rules version `BINARY_RULES_VERSION` and above.
+//! Hashes and keys take their raw bytes instead of hex strings of JSON, so such blocks are smaller in DB and faster to hash and parse.
+//! Older blocks keep their JSON, and stored transactions are told apart by the first byte, so DB doesn't need a migration.
+
+use std::io::{self, Read, Write};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{Block, Bytes, Transaction};
+use crate::commons::constants::BINARY_RULES_VERSION;
+
+/// JSON of transaction always starts with '{', binary one starts with this byte
+const TRANSACTION_MAGIC: u8 = 0xB1;
+/// Longer strings are considered as damage
+pub const MAX_STRING_SIZE: u32 = 1024 * 1024;
+
+/// Tells if blocks of this version have their transactions in binary encoding
+pub fn is_binary(version: u32) -> bool {
+    version >= BINARY_RULES_VERSION
+}
+
+/// Encodes transaction for DB, in binary for new blocks, and in JSON for old ones.
+/// Fails on too long hashes or keys.
+pub fn encode_transaction(transaction: &Transaction, version: u32) -> io::Result<Vec<u8>> {
+    if !is_binary(version) {
+        return Ok(transaction.get_bytes());
+    }
+    let mut buf = Vec::with_capacity(transaction.data.len() + 128);
+    buf.push(TRANSACTION_MAGIC);
+    write_transaction(&mut buf, transaction)?;
+    Ok(buf)
+}
+
+/// Decodes transaction in any of the encodings
+pub fn decode_transaction(data: &[u8]) -> Option<Transaction> {
+    match data.first() {
+        None => None,
+        Some(&TRANSACTION_MAGIC) => read_transaction(&mut &data[1..]).ok(),
+        Some(_) => serde_json::from_slice(data).ok()
+    }
+}
+
+/// Gets bytes of block for hashing and signing.
+/// Fails on too long hashes, keys or signatures, that can come in blocks from peers.
+pub fn encode_block(block: &Block) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(256);
+    write_block(&mut buf, block)?;
+    Ok(buf)
+}
+
+pub(crate) fn write_block<W: Write>(writer: &mut W, block: &Block) -> io::Result<()> {
+    writer.write_u64::<BigEndian>(block.index)?;
+    writer.write_i64::<BigEndian>(block.timestamp)?;
+    writer.write_u32::<BigEndian>(block.version)?;
+    writer.write_u32::<BigEndian>(block.difficulty)?;
+    writer.write_u32::<BigEndian>(block.random)?;
+    writer.write_u64::<BigEndian>(block.nonce)?;
+    write_bytes(writer, &block.prev_block_hash)?;
+    write_bytes(writer, &block.hash)?;
+    write_bytes(writer, &block.pub_key)?;
+    write_bytes(writer, &block.signature)?;
+    match &block.transaction {
+        None => writer.write_u8(0),
+        Some(transaction) => {
+            writer.write_u8(1)?;
+            write_transaction(writer, transaction)
+        }
+    }
+}
+
+fn write_transaction<W: Write>(writer: &mut W, transaction: &Transaction) -> io::Result<()> {
+    write_bytes(writer, &transaction.identity)?;
+    write_bytes(writer, &transaction.confirmation)?;
+    write_string(writer, &transaction.class)?;
+    write_string(writer, &transaction.data)?;
+    write_bytes(writer, &transaction.pub_key)
+}
+
+pub(crate) fn read_transaction<R: Read>(reader: &mut R) -> io::Result<Transaction> {
+    let identity = read_bytes(reader)?;
+    let confirmation = read_bytes(reader)?;
+    let class = read_string(reader)?;
+    let data = read_string(reader)?;
+    let pub_key = read_bytes(reader)?;
+    Ok(Transaction::new(identity, confirmation, class, data, pub_key))
+}
+
+/// Hashes and keys are short, they have one byte of length
+pub(crate) fn write_bytes<W: Write>(writer: &mut W, bytes: &Bytes) -> io::Result<()> {
+    if bytes.length() > u8::MAX as usize {
+        return Err(invalid("Too long hash or key"));
+    }
+    writer.write_u8(bytes.length() as u8)?;
+    writer.write_all(bytes.as_slice())
+}
+
+pub(crate) fn read_bytes<R: Read>(reader: &mut R) -> io::Result<Bytes> {
+    let size = reader.read_u8()?;
+    let mut buf = vec![0u8; size as usize];
+    reader.read_exact(&mut buf)?;
+    Ok(Bytes::new(buf))
+}
+
+pub(crate) fn write_string<W: Write>(writer: &mut W, text: &str) -> io::Result<()> {
+    writer.write_u32::<BigEndian>(text.len() as u32)?;
+    writer.write_all(text.as_bytes())
+}
+
+pub(crate) fn read_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let size = reader.read_u32::<BigEndian>()?;
+    if size > MAX_STRING_SIZE {
+        return Err(invalid("Too long string"));
+    }
+    let mut buf = vec![0u8; size as usize];
+    reader.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(|_| invalid("Wrong UTF-8 in string"))
+}
+
+pub(crate) fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CHAIN_VERSION;
+    use crate::blockchain::hash_utils::{check_block_hash, check_block_signature};
+
+    #[test]
+    fn both_encodings() {
+        let transaction = Transaction::from_str(String::from("test.ygg"), String::from("domain"), String::from("{\"zone\":\"ygg\"}"), Bytes::from_bytes(&[1u8; 32]));
+        let json = encode_transaction(&transaction, CHAIN_VERSION).unwrap();
+        let binary = encode_transaction(&transaction, BINARY_RULES_VERSION).unwrap();
+        assert_eq!(b'{', json[0]);
+        assert_eq!(TRANSACTION_MAGIC, binary[0]);
+        assert!(binary.len() < json.len() / 2);
+        assert_eq!(Some(transaction.clone()), decode_transaction(&json));
+        assert_eq!(Some(transaction), decode_transaction(&binary));
+        assert_eq!(None, decode_transaction(&binary[..binary.len() - 1]));
+        assert_eq!(None, decode_transaction(&[]));
+    }
+
+    #[test]
+    fn block_bytes() {
+        let transaction = Transaction::from_str(String::from("test.ygg"), String::from("domain"), String::from("{}"), Bytes::from_bytes(&[1u8; 32]));
+        let mut block = Block::new(Some(transaction), Bytes::from_bytes(&[1u8; 32]), Bytes::from_bytes(&[2u8; 32]), 24);
+        assert_eq!(serde_json::to_vec(&block).unwrap(), block.as_bytes());
+        block.version = BINARY_RULES_VERSION;
+        let bytes = block.as_bytes();
+        assert_eq!(encode_block(&block).unwrap(), bytes);
+        block.nonce = 1;
+        assert_ne!(bytes, block.as_bytes());
+    }
+
+    #[test]
+    fn too_long_key() {
+        let transaction = Transaction::from_str(String::from("test.ygg"), String::from("domain"), String::from("{}"), Bytes::from_bytes(&[1u8; 300]));
+        assert!(encode_transaction(&transaction, BINARY_RULES_VERSION).is_err());
+        let mut block = Block::new(Some(transaction), Bytes::from_bytes(&[1u8; 300]), Bytes::from_bytes(&[2u8; 32]), 24);
+        block.version = BINARY_RULES_VERSION;
+        block.hash = Bytes::from_bytes(&[3u8; 32]);
+        assert!(encode_block(&block).is_err());
+        assert!(block.as_bytes().is_empty());
+        assert!(!check_block_hash(&block));
+        assert!(!check_block_signature(&block));
+    }
+}
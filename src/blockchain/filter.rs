@@ -5,20 +5,45 @@ use crate::dns::protocol::{DnsPacket, QueryType, DnsRecord, DnsQuestion, ResultC
 #[allow(unused_imports)]
 use log::{trace, debug, info, warn, error};
 use crate::blockchain::transaction::DomainData;
-use chrono::Utc;
+use crate::blockchain::dns_view::DnsView;
+use crate::confusables::{is_mixed_script, to_unicode};
 
 pub struct BlockchainFilter {
-    context: Arc<Mutex<Context>>
+    context: Arc<Mutex<Context>>,
+    /// Name of additional chain, None for the main one
+    chain: Option<String>,
+    /// Domains and zones of full nodes, they are read without locking the context
+    view: Option<Arc<DnsView>>,
+    /// Warn about answers for names of mixed scripts, see `dns.flag_confusables`
+    flag_confusables: bool,
 }
 
 impl BlockchainFilter {
     pub fn new(context: Arc<Mutex<Context>>) -> Self {
-        BlockchainFilter { context }
+        let view = get_view(&context);
+        let flag_confusables = context.lock().unwrap().settings.dns.flag_confusables;
+        BlockchainFilter { context, chain: None, view, flag_confusables }
+    }
+
+    /// Filter for additional chain, it differs only by name in traces
+    pub fn for_chain(context: Arc<Mutex<Context>>, name: &str) -> Self {
+        let view = get_view(&context);
+        let flag_confusables = context.lock().unwrap().settings.dns.flag_confusables;
+        BlockchainFilter { context, chain: Some(name.to_owned()), view, flag_confusables }
+    }
+}
+
+/// Light nodes have no domains to keep in memory
+fn get_view(context: &Arc<Mutex<Context>>) -> Option<Arc<DnsView>> {
+    let mut context = context.lock().unwrap();
+    match context.light {
+        Some(_) => None,
+        None => Some(context.chain.get_dns_view())
     }
 }
 
-const NAME_SERVER: & str = "ns.guasha.su";
-const SERVER_ADMIN: & str = "admin.guasha.su";
+pub(crate) const NAME_SERVER: &str = "ns.guasha.su";
+pub(crate) const SERVER_ADMIN: &str = "admin.guasha.su";
 
 impl DnsFilter for BlockchainFilter {
     fn lookup(&self, qname: &str, qtype: QueryType) -> Option<DnsPacket> {
@@ -26,13 +51,7 @@ impl DnsFilter for BlockchainFilter {
         let subdomain;
         let parts: Vec<&str> = qname.rsplitn(3, ".").collect();
         match parts.len() {
-            1 => {
-                let mut packet = DnsPacket::new();
-                if self.get_zone_response(parts[0], &mut packet) {
-                    return Some(packet);
-                }
-                return None;
-            }
+            1 => return self.get_zone_response(parts[0], qtype),
             2 => {
                 search = format!("{}.{}", parts[1], parts[0]);
                 subdomain = String::new();
@@ -44,28 +63,27 @@ impl DnsFilter for BlockchainFilter {
         }
         trace!("Searching record type '{:?}', name '{}' for domain '{}'", &qtype, &subdomain, &search);
 
-        let data = self.context.lock().unwrap().chain.get_domain_info(&search);
+        let data = self.get_domain_data(&search);
         let zone = parts[0].to_owned();
         match data {
             None => {
-                if self.context.lock().unwrap().chain.is_zone_in_blockchain(i64::MAX as u64, &zone) {
+                if self.has_zone(&zone) {
                     trace!("Not found data for domain {}", &search);
                     // Create DnsPacket
                     let mut packet = DnsPacket::new();
                     packet.questions.push(DnsQuestion::new(String::from(qname), qtype));
                     packet.header.rescode = ResultCode::NXDOMAIN;
                     packet.header.authoritative_answer = true;
-                    BlockchainFilter::add_soa_record(zone, &mut packet);
+                    packet.authorities.push(make_soa(&zone, self.get_serial(&zone)));
                     //trace!("Returning packet: {:?}", &packet);
                     return Some(packet);
                 }
             }
-            Some(data) => {
+            Some(mut data) => {
                 trace!("Found data for domain {}", &search);
-                let mut data: DomainData = match serde_json::from_str(&data) {
-                    Err(_) => { return None; }
-                    Ok(data) => { data }
-                };
+                if self.flag_confusables && is_mixed_script(&search) {
+                    warn!("Domain {} is shown as '{}', it mixes letters of several scripts", &search, to_unicode(&search));
+                }
                 let mut answers: Vec<DnsRecord> = Vec::new();
                 let a_record = qtype == QueryType::A || qtype == QueryType::AAAA;
                 for mut record in data.records.iter_mut() {
@@ -79,7 +97,8 @@ impl DnsFilter for BlockchainFilter {
                             | DnsRecord::MX { domain, .. }
                             | DnsRecord::UNKNOWN { domain, .. }
                             | DnsRecord::SOA { domain, .. }
-                            | DnsRecord::TXT { domain, .. } if domain == "@" => {
+                            | DnsRecord::TXT { domain, .. }
+                            | DnsRecord::TLSA { domain, .. } if domain == "@" => {
                                 *domain = String::from(qname);
                             }
                             _ => ()
@@ -100,7 +119,8 @@ impl DnsFilter for BlockchainFilter {
                                         | DnsRecord::MX { domain, .. }
                                         | DnsRecord::UNKNOWN { domain, .. }
                                         | DnsRecord::SOA { domain, .. }
-                                        | DnsRecord::TXT { domain, .. } => {
+                                        | DnsRecord::TXT { domain, .. }
+                                        | DnsRecord::TLSA { domain, .. } => {
                                             *domain = String::from(qname);
                                         }
                                         _ => ()
@@ -130,7 +150,8 @@ impl DnsFilter for BlockchainFilter {
                                             | DnsRecord::MX { domain, .. }
                                             | DnsRecord::UNKNOWN { domain, .. }
                                             | DnsRecord::SOA { domain, .. }
-                                            | DnsRecord::TXT { domain, .. } => {
+                                            | DnsRecord::TXT { domain, .. }
+                                            | DnsRecord::TLSA { domain, .. } => {
                                                 *domain = String::from(qname);
                                             }
                                             _ => ()
@@ -152,11 +173,7 @@ impl DnsFilter for BlockchainFilter {
                     for answer in answers {
                         packet.answers.push(answer);
                     }
-                    packet.authorities.push( DnsRecord::NS {
-                        domain: zone,
-                        host: String::from(NAME_SERVER),
-                        ttl: TransientTtl(600)
-                    });
+                    packet.authorities.push(make_ns(&zone));
                     //trace!("Returning packet: {:?}", &packet);
                     Some(packet)
                 } else {
@@ -165,7 +182,7 @@ impl DnsFilter for BlockchainFilter {
                     packet.header.authoritative_answer = true;
                     packet.header.rescode = ResultCode::NOERROR;
                     packet.questions.push(DnsQuestion::new(String::from(qname), qtype));
-                    BlockchainFilter::add_soa_record(zone, &mut packet);
+                    packet.authorities.push(make_soa(&zone, self.get_serial(&zone)));
                     //trace!("Returning packet: {:?}", &packet);
                     Some(packet)
                 }
@@ -174,28 +191,128 @@ impl DnsFilter for BlockchainFilter {
 
         None
     }
+
+    fn name(&self) -> String {
+        match &self.chain {
+            Some(name) => format!("blockchain '{}'", name),
+            None => String::from("blockchain")
+        }
+    }
+
+    fn explain(&self, qname: &str) -> Option<String> {
+        let parts: Vec<&str> = qname.rsplitn(3, ".").collect();
+        if parts.len() < 2 {
+            return None;
+        }
+        let search = format!("{}.{}", parts[1], parts[0]);
+        let context = self.context.lock().unwrap();
+        if context.light.is_some() {
+            return Some(String::from("Light mode, domains are asked from other nodes with proofs"));
+        }
+        let chain = context.get_chain();
+        if !chain.is_zone_in_blockchain(i64::MAX as u64, parts[0]) {
+            return Some(format!("Zone '{}' is not in blockchain", parts[0]));
+        }
+        match (chain.get_domain_transaction(&search), chain.get_domain_block_index(&search)) {
+            (Some(transaction), Some(index)) => {
+                Some(format!("Domain '{}' is found in block {}, identity {:?}", &search, index, &transaction.identity))
+            }
+            _ => Some(format!("Domain '{}' is not found or has expired", &search))
+        }
+    }
+}
+
+/// SOA record of blockchain zone, the serial must be the same on all synced nodes
+pub fn make_soa(zone: &str, serial: u32) -> DnsRecord {
+    DnsRecord::SOA {
+        domain: zone.to_owned(),
+        m_name: String::from(NAME_SERVER),
+        r_name: String::from(SERVER_ADMIN),
+        serial,
+        refresh: 3600,
+        retry: 300,
+        expire: 604800,
+        minimum: 60,
+        ttl: TransientTtl(60),
+    }
+}
+
+pub fn make_ns(zone: &str) -> DnsRecord {
+    DnsRecord::NS { domain: zone.to_owned(), host: String::from(NAME_SERVER), ttl: TransientTtl(600) }
 }
 
 impl BlockchainFilter {
-    fn add_soa_record(zone: String, packet: &mut DnsPacket) {
-        packet.authorities.push(DnsRecord::SOA {
-            domain: zone,
-            m_name: String::from(NAME_SERVER),
-            r_name: String::from(SERVER_ADMIN),
-            serial: Utc::now().timestamp() as u32,
-            refresh: 3600,
-            retry: 300,
-            expire: 604800,
-            minimum: 60,
-            ttl: TransientTtl(60),
-        });
+    /// Serial of blockchain zone is the index of the last block that has changed it.
+    /// Light nodes don't have domains, so they use the height of their headers.
+    fn get_serial(&self, zone: &str) -> u32 {
+        if let Some(view) = &self.view {
+            return view.get_serial(zone) as u32;
+        }
+        let context = self.context.lock().unwrap();
+        match &context.light {
+            Some(light) => light.get_height_and_hash().0 as u32,
+            None => context.chain.get_zone_serial(zone) as u32
+        }
+    }
+
+    /// Full nodes take domains from the view of their chain, light nodes ask other nodes
+    fn get_domain_data(&self, domain: &str) -> Option<DomainData> {
+        if let Some(view) = &self.view {
+            return view.get_domain(domain).map(|data| data.as_ref().clone());
+        }
+        let data = self.get_domain_info(domain)?;
+        serde_json::from_str(&data).ok()
+    }
+
+    /// Light nodes ask other nodes for domains, full nodes look in their DB
+    fn get_domain_info(&self, domain: &str) -> Option<String> {
+        // Light queries wait for the network, and DB lookups can go in parallel, so the context must not be locked meanwhile
+        let (light, lookup) = {
+            let context = self.context.lock().unwrap();
+            if context.light.is_none() && !context.chain.may_have_domain(domain) {
+                return None;
+            }
+            (context.light.clone(), context.chain.get_domain_lookup())
+        };
+        if let Some(light) = light {
+            return light.get_domain_info(domain);
+        }
+        if let Some(lookup) = lookup {
+            match lookup.get_domain_info(domain) {
+                Ok(data) => return data,
+                Err(e) => warn!("Error looking up domain {}: {}", domain, e)
+            }
+        }
+        self.context.lock().unwrap().chain.get_domain_info(domain)
     }
 
-    fn get_zone_response(&self, zone: &str, mut packet: &mut DnsPacket) -> bool {
-        let have_zone = self.context.lock().unwrap().chain.is_zone_in_blockchain(i64::MAX as u64, zone);
-        if have_zone {
-            BlockchainFilter::add_soa_record(zone.to_owned(), &mut packet);
+    fn has_zone(&self, zone: &str) -> bool {
+        if let Some(view) = &self.view {
+            return view.has_zone(zone);
+        }
+        let context = self.context.lock().unwrap();
+        match &context.light {
+            Some(light) => light.has_zone(zone),
+            None => context.chain.is_zone_in_blockchain(i64::MAX as u64, zone)
+        }
+    }
+
+    /// Answers for the zone itself, it has SOA and NS records, and no others
+    fn get_zone_response(&self, zone: &str, qtype: QueryType) -> Option<DnsPacket> {
+        if !self.has_zone(zone) {
+            return None;
+        }
+        let mut packet = DnsPacket::new();
+        packet.header.authoritative_answer = true;
+        packet.questions.push(DnsQuestion::new(String::from(zone), qtype));
+        match qtype {
+            QueryType::SOA => {
+                packet.answers.push(make_soa(zone, self.get_serial(zone)));
+                packet.authorities.push(make_ns(zone));
+            }
+            QueryType::NS => packet.answers.push(make_ns(zone)),
+            _ => packet.authorities.push(make_soa(zone, self.get_serial(zone)))
         }
-        have_zone
+        Some(packet)
     }
 }
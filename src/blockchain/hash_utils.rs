@@ -1,6 +1,7 @@
-use blakeout::blakeout;
+use blakeout::Blakeout as blakeout;
 
 use crate::{Block, Bytes, Keystore};
+use crate::blockchain::pow::get_hasher;
 use sha2::{Sha256, Digest};
 use std::convert::TryInto;
 
@@ -9,8 +10,7 @@ pub fn check_block_hash(block: &Block) -> bool {
     let mut copy: Block = block.clone();
     copy.hash = Bytes::default();
     copy.signature = Bytes::default();
-    let data = serde_json::to_string(&copy).unwrap();
-    blakeout_data(data.as_bytes()) == block.hash
+    get_hasher(block.version).hash(&copy.as_bytes()) == block.hash.as_slice()
 }
 
 /// Hashes data by given hasher
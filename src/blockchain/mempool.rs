@@ -0,0 +1,122 @@
+//! Domain transactions from other nodes, that wait for their miner. Like transactions for `/api/jobs`, they are built
+//! for the key of some miner, and public nodes pass them on to their peers until that miner gets them.
+//! Transactions are checked on every node, kept for some time, and the pool has a size limit.
+
+use std::collections::HashMap;
+
+use crate::{Bytes, Chain, Transaction, is_yggdrasil_record};
+use crate::blockchain::hash_utils::hash_sha256;
+use crate::blockchain::schema::validate_domain_data;
+use crate::commons::{CLASS_DOMAIN, KEYSTORE_DIFFICULTY};
+use crate::keys::check_public_key_strength;
+
+pub struct Mempool {
+    /// Hash of transaction -> transaction
+    pending: HashMap<Bytes, Pending>,
+    /// New transactions, that are not sent to peers yet
+    outgoing: Vec<Transaction>,
+    size: usize,
+    /// How many seconds transactions are kept
+    ttl: i64,
+}
+
+struct Pending {
+    transaction: Transaction,
+    received: i64,
+    /// Taken by our miner, it is kept until expiration to not mine it again when it comes from another peer
+    taken: bool,
+}
+
+impl Mempool {
+    pub fn new(size: usize, ttl: i64) -> Self {
+        Mempool { pending: HashMap::new(), outgoing: Vec::new(), size, ttl }
+    }
+
+    /// Adds checked transaction, it is sent to peers later. Returns false if it is known already, or there is no room for it.
+    pub fn add(&mut self, transaction: Transaction, now: i64) -> bool {
+        self.expire(now);
+        let hash = Bytes::from_bytes(&hash_sha256(&transaction.get_bytes()));
+        if self.pending.contains_key(&hash) || self.pending.len() >= self.size {
+            return false;
+        }
+        self.outgoing.push(transaction.clone());
+        self.pending.insert(hash, Pending { transaction, received: now, taken: false });
+        true
+    }
+
+    pub fn expire(&mut self, now: i64) {
+        let ttl = self.ttl;
+        self.pending.retain(|_, pending| pending.received + ttl > now);
+    }
+
+    /// Takes transactions for the miner with this key
+    pub fn take_for_key(&mut self, pub_key: &Bytes, now: i64) -> Vec<Transaction> {
+        self.expire(now);
+        self.pending.values_mut()
+            .filter(|pending| !pending.taken && &pending.transaction.pub_key == pub_key)
+            .map(|pending| {
+                pending.taken = true;
+                pending.transaction.clone()
+            })
+            .collect()
+    }
+
+    /// Takes new transactions to send them to peers
+    pub fn take_outgoing(&mut self) -> Vec<Transaction> {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// Checks the transaction the same way as blocks with it are checked, except the proof of work
+pub fn check_transaction(chain: &Chain, transaction: &Transaction, now: i64) -> Result<(), String> {
+    if transaction.class != CLASS_DOMAIN {
+        return Err(String::from("Only domain transactions are relayed"));
+    }
+    if !check_public_key_strength(&transaction.pub_key, KEYSTORE_DIFFICULTY) {
+        return Err(String::from("Key of the miner is weak"));
+    }
+    // Relayed transactions are checked strictly, even if the chain doesn't require it yet
+    let data = validate_domain_data(&transaction.data)?;
+    let zone = chain.get_zones().into_iter().find(|zone| zone.name == data.zone).ok_or_else(|| String::from("Unknown zone"))?;
+    if zone.yggdrasil && !data.records.iter().all(is_yggdrasil_record) {
+        return Err(String::from("Zone is Yggdrasil only"));
+    }
+    chain.check_domain_change(u64::MAX, &transaction.identity, &transaction.pub_key, &data, now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(domain: &str, key: u8) -> Transaction {
+        Transaction::from_str(domain.to_owned(), CLASS_DOMAIN.to_owned(), String::from("{}"), Bytes::from_bytes(&[key; 32]))
+    }
+
+    #[test]
+    fn pool() {
+        let mut mempool = Mempool::new(2, 60);
+        assert!(mempool.add(transaction("one.ygg", 1), 1000));
+        assert!(!mempool.add(transaction("one.ygg", 1), 1000));
+        assert!(mempool.add(transaction("two.ygg", 2), 1010));
+        assert!(!mempool.add(transaction("three.ygg", 1), 1020));
+        assert_eq!(2, mempool.take_outgoing().len());
+        assert!(mempool.take_outgoing().is_empty());
+
+        assert_eq!(1, mempool.take_for_key(&Bytes::from_bytes(&[1u8; 32]), 1030).len());
+        assert!(mempool.take_for_key(&Bytes::from_bytes(&[1u8; 32]), 1030).is_empty());
+        // Taken transactions are not added again until they expire
+        assert!(!mempool.add(transaction("one.ygg", 1), 1040));
+
+        mempool.expire(1065);
+        assert_eq!(1, mempool.len());
+        assert!(mempool.add(transaction("one.ygg", 1), 1065));
+    }
+}
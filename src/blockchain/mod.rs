@@ -1,11 +1,43 @@
 pub use block::Block;
+#[cfg(feature = "node")]
 pub use chain::Chain;
 pub use transaction::Transaction;
 
 pub mod transaction;
 pub mod block;
+#[cfg(feature = "node")]
 pub mod chain;
+#[cfg(feature = "node")]
 pub mod filter;
+#[cfg(feature = "node")]
+pub mod statements;
+#[cfg(feature = "node")]
+pub mod storage;
+#[cfg(feature = "node")]
+pub mod mempool;
+#[cfg(feature = "node")]
+pub mod dns_view;
+#[cfg(feature = "node")]
+pub mod primary;
 pub mod hash_utils;
+pub mod pow;
+#[cfg(feature = "node")]
+pub mod profile;
+#[cfg(feature = "node")]
+pub mod diff;
+#[cfg(feature = "node")]
+pub mod reorg;
+pub mod proof;
+pub mod ownership;
+pub mod delegation;
+pub mod transfer;
 pub mod types;
+pub mod consensus;
+pub mod checkpoints;
+pub mod snapshot;
+pub mod encoding;
+pub mod schema;
+pub mod vectors;
+pub mod bloom;
+pub mod zone_stats;
 
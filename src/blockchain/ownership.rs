@@ -0,0 +1,113 @@
+//! Signed statements that some key owns a domain at some height of the blockchain.
+//! Owners give them to third parties, that check them offline with public chain data,
+//! for example to issue a certificate for the domain.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Bytes, Keystore, Transaction};
+use crate::blockchain::consensus::Schedule;
+use crate::blockchain::proof::DomainProof;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OwnershipProof {
+    pub domain: String,
+    pub pub_key: Bytes,
+    pub height: u64,
+    /// Hash of the block at `height`, it must be the same in the public chain
+    pub block_hash: Bytes,
+    pub timestamp: i64,
+    /// Any text from the party that asks for the proof, to prevent replays
+    #[serde(default)]
+    pub challenge: String,
+    pub signature: Bytes,
+    /// The last transaction of the domain with its signing blocks
+    pub proof: DomainProof,
+}
+
+impl OwnershipProof {
+    /// Signs the statement by the key of the owner
+    pub fn create(keystore: &Keystore, domain: &str, height: u64, block_hash: Bytes, timestamp: i64, challenge: &str, proof: DomainProof) -> Self {
+        let mut result = OwnershipProof {
+            domain: domain.to_owned(),
+            pub_key: keystore.get_public(),
+            height,
+            block_hash,
+            timestamp,
+            challenge: challenge.to_owned(),
+            signature: Bytes::default(),
+            proof
+        };
+        result.signature = Bytes::from_bytes(&keystore.sign(result.get_statement().as_bytes()));
+        result
+    }
+
+    /// Gets the text that is signed by the owner
+    pub fn get_statement(&self) -> String {
+        format!("Key {} owns domain {} at height {} with block {}, time {}, challenge '{}'",
+                self.pub_key.to_string(), &self.domain, self.height, self.block_hash.to_string(), self.timestamp, &self.challenge)
+    }
+
+    /// Checks the signature and the domain proof, returns the domain transaction if the key owns it.
+    /// The caller needs to compare `block_hash` and the hash of the domain block with public chain,
+    /// if the proof doesn't contain the chain up to `height`, and to check that the domain block
+    /// is its last change up to `height` and that the domain is not expired there.
+    pub fn verify(&self, schedule: &Schedule) -> Result<Transaction, String> {
+        if !Keystore::check(self.get_statement().as_bytes(), &self.pub_key, &self.signature) {
+            return Err(String::from("Wrong signature"));
+        }
+        let params = schedule.at(self.proof.block.index);
+        let transaction = match self.proof.chain.last() {
+            None => self.proof.verify(&self.domain, params),
+            Some(last) if last.index == self.height => self.proof.verify_to(&self.domain, params, &self.block_hash),
+            Some(_) => None
+        };
+        let transaction = transaction.ok_or_else(|| String::from("Wrong domain proof"))?;
+        if transaction.pub_key != self.pub_key {
+            return Err(String::from("Domain is owned by another key"));
+        }
+        if self.proof.block.index > self.height {
+            return Err(String::from("Domain block is above the height of statement"));
+        }
+        Ok(transaction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Block;
+    use crate::blockchain::hash_utils::blakeout_data;
+
+    use super::*;
+
+    fn make_block(keystore: &Keystore, index: u64, prev_block_hash: Bytes, transaction: Option<Transaction>) -> Block {
+        let mut block = Block::new(transaction, keystore.get_public(), prev_block_hash, 0);
+        block.index = index;
+        block.timestamp = 1600000000 + index as i64;
+        block.hash = blakeout_data(&block.as_bytes());
+        block.signature = Bytes::from_bytes(&keystore.sign(&block.as_bytes()));
+        block
+    }
+
+    #[test]
+    fn ownership() {
+        let owner = Keystore::new();
+        let signer = Keystore::new();
+        let transaction = Transaction::from_str(String::from("test.ygg"), String::from("domain"), String::from("{}"), owner.get_public());
+        let block = make_block(&owner, 10, Bytes::zero32(), Some(transaction));
+        let first = make_block(&signer, 11, block.hash.clone(), None);
+        let second = make_block(&signer, 12, first.hash.clone(), None);
+        let block_hash = second.hash.clone();
+        let proof = DomainProof { block, signers: vec![first, second], chain: Vec::new() };
+
+        let schedule = Schedule::default();
+        let statement = OwnershipProof::create(&owner, "test.ygg", 12, block_hash.clone(), 1600000100, "nonce", proof.clone());
+        assert!(statement.verify(&schedule).is_ok());
+
+        let mut replayed = statement.clone();
+        replayed.challenge = String::from("other nonce");
+        assert!(replayed.verify(&schedule).is_err());
+
+        let stolen = OwnershipProof::create(&signer, "test.ygg", 12, block_hash, 1600000100, "nonce", proof);
+        assert!(stolen.verify(&schedule).is_err());
+    }
+}
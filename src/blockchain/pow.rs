@@ -0,0 +1,70 @@
+//! Hash functions of proof of work. Blocks are hashed by the algorithm that their rules version gives, now it is
+//! Blakeout for all of them. A new algorithm is added as another `PowHasher` with a rules version that activates it,
+//! and faster backends of the same algorithm (SIMD, GPU) must give the same hashes as the default one,
+//! compare them with `cargo bench --bench pow`.
+
+use blakeout::Blakeout as blakeout;
+
+pub trait PowHasher: Send {
+    /// Name of the algorithm and backend, for logs and benchmarks
+    fn name(&self) -> &'static str;
+
+    /// Hashes the data, the result is kept until the next call, so that miners don't allocate for every nonce
+    fn hash(&mut self, data: &[u8]) -> &[u8];
+}
+
+pub struct BlakeoutHasher {
+    digest: blakeout,
+}
+
+impl BlakeoutHasher {
+    pub fn new() -> Self {
+        BlakeoutHasher { digest: blakeout::new() }
+    }
+}
+
+impl Default for BlakeoutHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PowHasher for BlakeoutHasher {
+    fn name(&self) -> &'static str {
+        "blakeout"
+    }
+
+    fn hash(&mut self, data: &[u8]) -> &[u8] {
+        self.digest.reset();
+        self.digest.update(data);
+        self.digest.result()
+    }
+}
+
+/// Gets the hasher for blocks of this version
+pub fn get_hasher(_version: u32) -> Box<dyn PowHasher> {
+    Box::new(BlakeoutHasher::new())
+}
+
+/// Gets all backends, to compare their speed and results
+pub fn get_hashers() -> Vec<Box<dyn PowHasher>> {
+    vec![Box::new(BlakeoutHasher::new())]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::hash_utils::blakeout_data;
+
+    #[test]
+    fn backends_agree() {
+        let data = b"{\"index\":1,\"timestamp\":1600000000}";
+        let expected = blakeout_data(data);
+        for mut hasher in get_hashers() {
+            let name = hasher.name();
+            assert_eq!(expected.as_slice(), hasher.hash(data), "Backend {} gives another hash", name);
+            // The state of previous hash must not leak into the next one
+            assert_eq!(expected.as_slice(), hasher.hash(data));
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! Zones made of blockchain domains, to be transferred to conventional secondary servers or exported to files
+
+use std::sync::{Arc, Mutex};
+
+use crate::{Chain, Context, Keystore};
+use crate::blockchain::filter::{make_ns, make_soa};
+use crate::dns::protocol::{DnsRecord, QueryType};
+use crate::dns::transfer::ZoneProvider;
+use crate::dns::zonefile::{make_absolute, write_zone};
+
+pub struct BlockchainZoneProvider {
+    context: Arc<Mutex<Context>>,
+    domains: Vec<String>
+}
+
+impl BlockchainZoneProvider {
+    pub fn new(context: Arc<Mutex<Context>>, domains: &Vec<String>) -> Self {
+        let domains = domains.iter().map(|d| d.trim_end_matches('.').to_lowercase()).collect();
+        BlockchainZoneProvider { context, domains }
+    }
+}
+
+impl ZoneProvider for BlockchainZoneProvider {
+    fn get_zones(&self) -> Vec<String> {
+        self.domains.clone()
+    }
+
+    fn get_serial(&self, zone: &str) -> Option<u32> {
+        if !self.domains.iter().any(|d| d == zone) {
+            return None;
+        }
+        self.context.lock().unwrap().chain.get_domain_timestamp(zone).map(|t| t as u32)
+    }
+
+    fn get_records(&self, zone: &str) -> Option<Vec<DnsRecord>> {
+        if !self.domains.iter().any(|d| d == zone) {
+            return None;
+        }
+        let (timestamp, data) = {
+            let context = self.context.lock().unwrap();
+            let timestamp = context.chain.get_domain_timestamp(zone)?;
+            let data = context.chain.get_domain_transaction(zone)?.get_domain_data()?;
+            (timestamp, data)
+        };
+        let mut result = vec![make_soa(zone, timestamp as u32)];
+        let records = make_absolute(data.records, zone);
+        let has_ns = records.iter().any(|r| r.get_querytype() == QueryType::NS && r.get_domain().as_deref() == Some(zone));
+        if !has_ns {
+            result.push(make_ns(zone));
+        }
+        result.extend(records);
+        Some(result)
+    }
+}
+
+/// Makes zone file of all our domains in some zone, for backups and mirrors
+pub fn export_zone(chain: &Chain, keystore: &Option<Keystore>, zone: &str) -> String {
+    let zone = zone.trim_end_matches('.').to_lowercase();
+    let mut domains: Vec<_> = chain.get_my_domains(keystore)
+        .into_iter()
+        .map(|(_, domain)| domain)
+        .filter(|(name, _, data)| data.zone == zone && name.ends_with(&format!(".{}", &zone)))
+        .collect();
+    domains.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut records = vec![make_soa(&zone, chain.get_zone_serial(&zone) as u32), make_ns(&zone)];
+    for (name, _, data) in domains {
+        records.extend(make_absolute(data.records, &name));
+    }
+    write_zone(&zone, &records)
+}
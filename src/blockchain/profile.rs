@@ -0,0 +1,85 @@
+//! Simple profiling of blockchain domain lookups, to see what part of DNS answer takes the most time
+
+use std::time::{Duration, Instant};
+
+use crate::Chain;
+use crate::blockchain::hash_utils::hash_identity;
+use crate::blockchain::transaction::DomainData;
+use crate::dns::buffer::VectorPacketBuffer;
+use crate::dns::protocol::{DnsPacket, DnsQuestion, QueryType};
+
+/// Average time of every step of domain lookup
+#[derive(Clone, Debug, Default)]
+pub struct LookupProfile {
+    pub iterations: u32,
+    pub found: bool,
+    /// Hashing of domain name to get its identity
+    pub hash: Duration,
+    /// Getting domain transaction from DB, without hashing
+    pub sql: Duration,
+    /// Parsing of domain data from JSON
+    pub parse: Duration,
+    /// Making of DNS packet with all domain records
+    pub serialize: Duration,
+}
+
+impl LookupProfile {
+    pub fn total(&self) -> Duration {
+        self.hash + self.sql + self.parse + self.serialize
+    }
+}
+
+impl std::fmt::Display for LookupProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "found: {}, iterations: {}, hash: {:?}, sql: {:?}, parse: {:?}, serialize: {:?}, total: {:?}",
+               self.found, self.iterations, self.hash, self.sql, self.parse, self.serialize, self.total())
+    }
+}
+
+/// Looks up the domain `iterations` times and measures average time of every step
+pub fn profile_lookup(chain: &Chain, domain: &str, iterations: u32) -> LookupProfile {
+    let iterations = iterations.max(1);
+    let mut profile = LookupProfile { iterations, ..LookupProfile::default() };
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        let _ = hash_identity(domain, None);
+    }
+    profile.hash = start.elapsed() / iterations;
+
+    let start = Instant::now();
+    let mut transaction = None;
+    for _ in 0..iterations {
+        transaction = chain.get_domain_transaction(domain);
+    }
+    // Getting transaction includes hashing, so we subtract it
+    profile.sql = (start.elapsed() / iterations).checked_sub(profile.hash).unwrap_or_default();
+
+    let transaction = match transaction {
+        Some(transaction) => transaction,
+        None => return profile
+    };
+    profile.found = true;
+
+    let start = Instant::now();
+    let mut data = None;
+    for _ in 0..iterations {
+        data = serde_json::from_str::<DomainData>(&transaction.data).ok();
+    }
+    profile.parse = start.elapsed() / iterations;
+
+    if let Some(data) = data {
+        let start = Instant::now();
+        for _ in 0..iterations {
+            let mut packet = DnsPacket::new();
+            packet.header.authoritative_answer = true;
+            packet.questions.push(DnsQuestion::new(domain.to_owned(), QueryType::A));
+            packet.answers = data.records.clone();
+            let mut buffer = VectorPacketBuffer::new();
+            let _ = packet.write(&mut buffer, 0xFFFF);
+        }
+        profile.serialize = start.elapsed() / iterations;
+    }
+
+    profile
+}
@@ -0,0 +1,142 @@
+//! Hash-chain inclusion proofs, so that light clients can check domain data without the whole blockchain.
+//!
+//! Every block commits to its transaction by its hash, and to all previous blocks by `prev_block_hash`.
+//! So a list of consecutive blocks, that ends with a block that the client already trusts, proves
+//! that the first of them is a part of the same chain.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Block, Bytes, Transaction};
+use crate::blockchain::hash_utils::{check_block_hash, check_block_signature};
+use crate::blockchain::consensus::ConsensusParams;
+
+/// Maximum number of blocks in one proof, clients should trust some recent block
+pub const MAX_PROOF_BLOCKS: u64 = 1000;
+
+/// Consecutive blocks, from the block with some transaction up to the block that client trusts
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChainProof {
+    pub blocks: Vec<Block>,
+}
+
+impl ChainProof {
+    /// Checks hashes and links of all blocks, returns the first block if the last one has the trusted hash
+    pub fn verify(&self, trusted_hash: &Bytes) -> Option<&Block> {
+        let (first, rest) = self.blocks.split_first()?;
+        if !check_block_hash(first) || !check_links(first, rest) {
+            return None;
+        }
+        match self.blocks.last() {
+            Some(last) if &last.hash == trusted_hash => Some(first),
+            _ => None
+        }
+    }
+}
+
+/// Block with the last transaction of some domain, and signing blocks after it.
+/// Thin clients get it from trusted full nodes instead of keeping the whole blockchain.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DomainProof {
+    pub block: Block,
+    pub signers: Vec<Block>,
+    /// Blocks after the signers, up to the block that client has asked for
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chain: Vec<Block>,
+}
+
+impl DomainProof {
+    /// Checks hashes, signatures and links of all blocks, returns the transaction of this domain if everything is fine.
+    /// It doesn't check if signers were chosen right, as it needs the whole blockchain.
+    /// Parameters must be the ones for the index of the domain block.
+    pub fn verify(&self, domain: &str, params: &ConsensusParams) -> Option<Transaction> {
+        if !check_block_hash(&self.block) || !check_block_signature(&self.block) {
+            return None;
+        }
+        let transaction = self.block.transaction.clone()?;
+        if !transaction.check_identity(domain) {
+            return None;
+        }
+        if (self.signers.len() as u64) < params.signers_min {
+            return None;
+        }
+        if self.signers.iter().any(|block| block.transaction.is_some() || !check_block_signature(block)) {
+            return None;
+        }
+        if !check_links(&self.block, &self.signers) {
+            return None;
+        }
+        Some(transaction)
+    }
+
+    /// Verifies the proof like `verify` does, and checks that it leads to the block with trusted hash
+    pub fn verify_to(&self, domain: &str, params: &ConsensusParams, trusted_hash: &Bytes) -> Option<Transaction> {
+        let transaction = self.verify(domain, params)?;
+        let prev = self.signers.last().unwrap_or(&self.block);
+        if !check_links(prev, &self.chain) {
+            return None;
+        }
+        let last = self.chain.last().unwrap_or(prev);
+        match &last.hash == trusted_hash {
+            true => Some(transaction),
+            false => None
+        }
+    }
+}
+
+/// Checks that every block has right hash, and follows the previous one
+pub fn check_links(prev: &Block, blocks: &[Block]) -> bool {
+    let mut prev = prev;
+    for block in blocks {
+        if block.index != prev.index + 1 || block.prev_block_hash != prev.hash || !check_block_hash(block) {
+            return false;
+        }
+        prev = block;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blockchain::hash_utils::blakeout_data;
+    use crate::Keystore;
+
+    use super::*;
+
+    fn make_block(keystore: &Keystore, index: u64, prev_block_hash: Bytes) -> Block {
+        let mut block = Block::new(None, keystore.get_public(), prev_block_hash, 0);
+        block.index = index;
+        block.timestamp = 1600000000 + index as i64;
+        block.hash = blakeout_data(&block.as_bytes());
+        block.signature = Bytes::from_bytes(&keystore.sign(&block.as_bytes()));
+        block
+    }
+
+    fn make_chain(count: u64) -> Vec<Block> {
+        let keystore = Keystore::new();
+        let mut blocks: Vec<Block> = Vec::new();
+        for index in 1..=count {
+            let prev = blocks.last().map(|b| b.hash.clone()).unwrap_or_default();
+            blocks.push(make_block(&keystore, index, prev));
+        }
+        blocks
+    }
+
+    #[test]
+    fn chain_proof() {
+        let blocks = make_chain(5);
+        let trusted = blocks[4].hash.clone();
+        let proof = ChainProof { blocks: blocks[1..].to_vec() };
+        assert_eq!(2, proof.verify(&trusted).unwrap().index);
+        assert!(proof.verify(&blocks[3].hash).is_none());
+
+        let mut broken = proof.clone();
+        broken.blocks.remove(1);
+        assert!(broken.verify(&trusted).is_none());
+
+        let mut forged = proof.clone();
+        forged.blocks[0].timestamp += 1;
+        assert!(forged.verify(&trusted).is_none());
+
+        assert!(ChainProof { blocks: Vec::new() }.verify(&trusted).is_none());
+    }
+}
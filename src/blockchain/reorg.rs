@@ -0,0 +1,147 @@
+//! Competing branches of the chain. Blocks that don't follow our chain are kept here until one of them links
+//! to our blocks, then the branch that grows from it is compared with our blocks from the same index by their
+//! cumulative difficulty, and the chain switches to it if it has more work, see `Chain::reorg`.
+//! It is enabled by `branch_reorgs` parameter, networks change to it from the original rule by an upgrade.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::{Block, Bytes};
+
+/// Forks deeper than this are not resolved, older blocks are never replaced
+pub const MAX_REORG_DEPTH: u64 = 100;
+/// How many blocks of branches are kept in memory
+const MAX_BRANCH_BLOCKS: usize = 500;
+
+/// Work of one block, every bit of its difficulty doubles it.
+/// Hashes can be luckily harder than needed, but it is the difficulty that the miner was working for.
+pub fn block_work(block: &Block) -> u128 {
+    1u128 << block.difficulty.min(127)
+}
+
+pub fn chain_work(blocks: &[Block]) -> u128 {
+    blocks.iter().fold(0u128, |work, block| work.saturating_add(block_work(block)))
+}
+
+/// Tells if the branch has more work than our blocks from the same index.
+/// Equal work is decided by the first blocks, like single forked blocks were, so that all nodes choose the same branch.
+pub fn is_better_branch(branch: &[Block], ours: &[Block]) -> bool {
+    match chain_work(branch).cmp(&chain_work(ours)) {
+        Ordering::Greater => true,
+        Ordering::Less => false,
+        Ordering::Equal => match (branch.first(), ours.first()) {
+            (Some(theirs), Some(mine)) => theirs.is_better_than(mine),
+            _ => false
+        }
+    }
+}
+
+/// Blocks that don't follow our chain, by hashes of their previous blocks
+#[derive(Default)]
+pub struct Branches {
+    blocks: HashMap<Bytes, Block>,
+}
+
+impl Branches {
+    pub fn add(&mut self, block: Block) {
+        if self.blocks.len() >= MAX_BRANCH_BLOCKS && !self.blocks.contains_key(&block.prev_block_hash) {
+            // The lowest blocks are the least likely to be linked to our chain, it is too deep already
+            if let Some(lowest) = self.blocks.values().min_by_key(|block| block.index).map(|block| block.prev_block_hash.clone()) {
+                self.blocks.remove(&lowest);
+            }
+        }
+        self.blocks.insert(block.prev_block_hash.clone(), block);
+    }
+
+    /// Gets the branch that starts from this block, following it by hashes of previous blocks
+    pub fn get_branch(&self, first: Block) -> Vec<Block> {
+        let mut branch = vec![first];
+        while let Some(next) = self.blocks.get(&branch.last().unwrap().hash) {
+            if next.index != branch.last().unwrap().index + 1 {
+                break;
+            }
+            branch.push(next.clone());
+        }
+        branch
+    }
+
+    /// Forgets blocks that are below `index`, they can't replace our blocks anymore
+    pub fn forget_below(&mut self, index: u64) {
+        self.blocks.retain(|_, block| block.index >= index);
+    }
+
+    /// Forgets blocks of the branch that is taken or rejected
+    pub fn remove(&mut self, branch: &[Block]) {
+        for block in branch {
+            self.blocks.remove(&block.prev_block_hash);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Makes blocks that follow each other from `prev`, hashes have `difficulty` zero bits with odd `seed`
+    fn make_branch(from: u64, prev: &Bytes, count: u64, difficulty: u32, seed: u8) -> Vec<Block> {
+        let mut blocks: Vec<Block> = Vec::new();
+        for index in from..from + count {
+            let prev = blocks.last().map(|b| b.hash.clone()).unwrap_or_else(|| prev.clone());
+            let mut block = Block::new(None, Bytes::default(), prev, difficulty);
+            block.index = index;
+            let mut hash = [0xFFu8; 32];
+            hash[..(difficulty / 8) as usize].iter_mut().for_each(|byte| *byte = 0);
+            hash[31] = seed;
+            hash[30] = index as u8;
+            block.hash = Bytes::from_bytes(&hash);
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    #[test]
+    fn more_work_wins() {
+        let prev = Bytes::from_bytes(&[9u8; 32]);
+        let ours = make_branch(10, &prev, 3, 16, 1);
+        let longer = make_branch(10, &prev, 4, 16, 5);
+        let harder = make_branch(10, &prev, 2, 24, 3);
+        assert!(is_better_branch(&longer, &ours));
+        assert!(!is_better_branch(&ours, &longer));
+        assert!(is_better_branch(&harder, &ours));
+        assert!(!is_better_branch(&ours, &ours));
+        assert!(!is_better_branch(&[], &ours));
+
+        // Hashes that are harder than their difficulty by luck don't add work
+        let mut lucky = ours.clone();
+        lucky[0].hash = harder[0].hash.clone();
+        assert_eq!(chain_work(&ours), chain_work(&lucky));
+    }
+
+    #[test]
+    fn branches() {
+        let prev = Bytes::from_bytes(&[9u8; 32]);
+        let branch = make_branch(10, &prev, 5, 16, 5);
+        let mut branches = Branches::default();
+        // Blocks of the branch come from the top, asking for previous ones
+        for block in branch.iter().skip(1).rev() {
+            branches.add(block.clone());
+        }
+        assert_eq!(4, branches.len());
+        let found = branches.get_branch(branch[0].clone());
+        assert_eq!(branch.iter().map(|b| b.index).collect::<Vec<u64>>(), found.iter().map(|b| b.index).collect::<Vec<u64>>());
+
+        branches.forget_below(12);
+        assert_eq!(3, branches.len());
+        assert_eq!(1, branches.get_branch(branch[0].clone()).len());
+        branches.remove(&branch);
+        assert!(branches.is_empty());
+    }
+}
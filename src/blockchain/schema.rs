@@ -0,0 +1,138 @@
+//! The exact form of transaction data, that all implementations must produce and accept.
+//!
+//! Data of transactions is hashed and signed as it is, so its bytes must be the same as serde gives for parsed data:
+//! fields in the order of declaration, no spaces, integers without fractions, and optional fields left out when empty.
+//! Any other form of the same data would make nodes that build it again disagree about the block, so it is rejected here.
+//! All checks of fields that don't need the chain are here too, in one place for blocks, mempool and mining.
+
+use crate::{Bytes, Transaction, check_domain};
+use crate::blockchain::transaction::{DomainData, ZoneData};
+use crate::commons::constants::*;
+
+/// Length of public keys of owners
+const KEY_LENGTH: usize = 32;
+/// How many records one domain can have
+pub const MAX_RECORDS: usize = 100;
+/// How many contacts one domain can have, and how long they can be
+pub const MAX_CONTACTS: usize = 10;
+pub const MAX_CONTACT_LENGTH: usize = 256;
+/// How many owners one domain or zone can have
+pub const MAX_OWNERS: usize = 10;
+
+/// Checks data of a transaction by its class
+pub fn validate_data(transaction: &Transaction) -> Result<(), String> {
+    match transaction.class.as_str() {
+        CLASS_DOMAIN | CLASS_RENEW | CLASS_TRANSFER => validate_domain_data(&transaction.data).map(|_| ()),
+        CLASS_ZONE => validate_zone_data(&transaction.data).map(|_| ()),
+        class => Err(format!("Unknown transaction class '{}'", class))
+    }
+}
+
+pub fn validate_domain_data(data: &str) -> Result<DomainData, String> {
+    let domain = serde_json::from_str::<DomainData>(data).map_err(|e| format!("Wrong domain data: {}", e))?;
+    check_canonical(data, &domain)?;
+    if domain.domain.is_empty() {
+        return Err(String::from("Domain data has no encrypted name"));
+    }
+    if domain.zone.len() > ZONE_MAX_LENGTH || !check_domain(&domain.zone, false) {
+        return Err(format!("Wrong zone '{}'", &domain.zone));
+    }
+    if domain.records.len() > MAX_RECORDS {
+        return Err(format!("Domain has more than {} records", MAX_RECORDS));
+    }
+    if domain.contacts.len() > MAX_CONTACTS {
+        return Err(format!("Domain has more than {} contacts", MAX_CONTACTS));
+    }
+    for contact in &domain.contacts {
+        if contact.name.is_empty() || contact.name.len() > MAX_CONTACT_LENGTH || contact.value.len() > MAX_CONTACT_LENGTH {
+            return Err(format!("Wrong contact '{}'", &contact.name));
+        }
+    }
+    check_owners(&domain.owners)?;
+    Ok(domain)
+}
+
+pub fn validate_zone_data(data: &str) -> Result<ZoneData, String> {
+    let zone = serde_json::from_str::<ZoneData>(data).map_err(|e| format!("Wrong zone data: {}", e))?;
+    check_canonical(data, &zone)?;
+    if zone.name.len() > ZONE_MAX_LENGTH || !check_domain(&zone.name, false) {
+        return Err(format!("Wrong zone name '{}'", &zone.name));
+    }
+    check_owners(&zone.owners)?;
+    Ok(zone)
+}
+
+/// Data must be exactly the same as serialization of what is parsed from it
+fn check_canonical<T: serde::Serialize>(data: &str, parsed: &T) -> Result<(), String> {
+    match serde_json::to_string(parsed) {
+        Ok(canonical) if canonical == data => Ok(()),
+        _ => Err(String::from("Data is not in canonical form"))
+    }
+}
+
+fn check_owners(owners: &[Bytes]) -> Result<(), String> {
+    if owners.len() > MAX_OWNERS {
+        return Err(format!("More than {} owners", MAX_OWNERS));
+    }
+    if owners.iter().any(|owner| owner.length() != KEY_LENGTH) {
+        return Err(String::from("Wrong key of owner"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Block;
+    use crate::blockchain::transaction::ContactsData;
+    use crate::dns::protocol::{DnsRecord, TransientTtl};
+
+    fn domain_data() -> DomainData {
+        let a = DnsRecord::A { domain: String::from("test.ygg"), addr: "10.0.0.1".parse().unwrap(), ttl: TransientTtl(300) };
+        let contacts = vec![ContactsData { name: String::from("email"), value: String::from("admin@test.ygg") }];
+        DomainData::new(Bytes::from_bytes(&[1u8; 16]), String::from("ygg"), vec![a], contacts, vec![Bytes::from_bytes(&[2u8; 32])])
+    }
+
+    #[test]
+    fn domain() {
+        let data = serde_json::to_string(&domain_data()).unwrap();
+        assert_eq!(Ok(domain_data()), validate_domain_data(&data));
+        // The same data in other form
+        let value: serde_json::Value = serde_json::from_str(&data).unwrap();
+        assert!(validate_domain_data(&serde_json::to_string_pretty(&value).unwrap()).is_err());
+        assert!(validate_domain_data(&data.replace("\"zone\":\"ygg\"", "\"zone\":\"y_g\"")).is_err());
+
+        let mut wrong = domain_data();
+        wrong.owners.push(Bytes::from_bytes(&[3u8; 31]));
+        assert!(validate_domain_data(&serde_json::to_string(&wrong).unwrap()).is_err());
+        let mut wrong = domain_data();
+        wrong.contacts[0].value = "x".repeat(MAX_CONTACT_LENGTH + 1);
+        assert!(validate_domain_data(&serde_json::to_string(&wrong).unwrap()).is_err());
+    }
+
+    #[test]
+    fn zone() {
+        let data = r#"{"name":"ygg","difficulty":24,"yggdrasil":true,"owners":[]}"#;
+        assert!(validate_zone_data(data).is_ok());
+        assert!(validate_zone_data(r#"{"name":"ygg","difficulty":24.0,"yggdrasil":true,"owners":[]}"#).is_err());
+        assert!(validate_zone_data(r#"{"difficulty":24,"name":"ygg","yggdrasil":true,"owners":[]}"#).is_err());
+        assert!(validate_zone_data(r#"{"name":"ygg","difficulty":24,"yggdrasil":true}"#).is_err());
+        let transaction = Transaction::from_str(String::from("ygg"), String::from(CLASS_ZONE), String::from(data), Bytes::from_bytes(&[1u8; 32]));
+        assert!(validate_data(&transaction).is_ok());
+    }
+
+    /// Bytes of blocks that are hashed must never change, or nodes of different versions would split
+    #[test]
+    fn block_form() {
+        let transaction = Transaction::new(Bytes::from_bytes(&[1u8; 2]), Bytes::from_bytes(&[2u8; 2]), String::from("zone"), String::from("{}"), Bytes::from_bytes(&[3u8; 2]));
+        let mut block = Block::new(Some(transaction), Bytes::from_bytes(&[4u8; 2]), Bytes::default(), 20);
+        block.index = 5;
+        block.timestamp = 1600000000;
+        let expected = concat!(
+            r#"{"index":5,"timestamp":1600000000,"version":0,"difficulty":20,"random":0,"nonce":0,"#,
+            r#""transaction":{"identity":"0101","confirmation":"0202","class":"zone","data":"{}","pub_key":"0303"},"#,
+            r#""pub_key":"0404"}"#
+        );
+        assert_eq!(expected.as_bytes(), block.as_bytes().as_slice());
+    }
+}
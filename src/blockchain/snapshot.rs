@@ -0,0 +1,166 @@
+//! Portable files with blocks, for new nodes to bootstrap from a snapshot instead of syncing block by block.
+//! After the header every block is written as its length, binary form and checksum, so a damaged file is noticed at once.
+//! Blocks from snapshots are checked like blocks from peers, the file is trusted only to be complete.
+
+use std::io::{self, Read, Write};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{Block, Bytes};
+use crate::blockchain::checkpoints::Checkpoints;
+use crate::blockchain::encoding::{self, invalid, read_bytes, read_transaction, write_bytes};
+use crate::blockchain::hash_utils::{check_block_hash, check_block_signature, hash_sha256};
+
+const MAGIC: &[u8; 8] = b"GISSNAP\0";
+const FORMAT_VERSION: u32 = 1;
+/// Bytes of SHA-256 of every block, that are kept as its checksum
+const CHECKSUM_SIZE: usize = 4;
+/// Bigger blocks are considered as damage of the file
+const MAX_BLOCK_SIZE: u32 = 1024 * 1024;
+
+#[derive(Debug, PartialEq)]
+pub struct SnapshotHeader {
+    /// Hash of the first block, snapshots of other chains are not imported
+    pub origin: Bytes,
+    pub count: u64,
+}
+
+/// Last block of a snapshot that is consistent, and how many checkpoints it has passed
+#[derive(Debug, PartialEq)]
+pub struct SnapshotTip {
+    pub index: u64,
+    pub hash: Bytes,
+    pub checkpoints: usize,
+}
+
+pub fn write_header<W: Write>(writer: &mut W, header: &SnapshotHeader) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_u32::<BigEndian>(FORMAT_VERSION)?;
+    write_bytes(writer, &header.origin)?;
+    writer.write_u64::<BigEndian>(header.count)
+}
+
+pub fn read_header<R: Read>(reader: &mut R) -> io::Result<SnapshotHeader> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("Not a snapshot file"));
+    }
+    let version = reader.read_u32::<BigEndian>()?;
+    if version != FORMAT_VERSION {
+        return Err(invalid(&format!("Unsupported snapshot version {}", version)));
+    }
+    let origin = read_bytes(reader)?;
+    let count = reader.read_u64::<BigEndian>()?;
+    Ok(SnapshotHeader { origin, count })
+}
+
+pub fn write_block<W: Write>(writer: &mut W, block: &Block) -> io::Result<()> {
+    let mut body = Vec::new();
+    encoding::write_block(&mut body, block)?;
+    writer.write_u32::<BigEndian>(body.len() as u32)?;
+    writer.write_all(&body)?;
+    writer.write_all(&hash_sha256(&body)[..CHECKSUM_SIZE])
+}
+
+pub fn read_block<R: Read>(reader: &mut R) -> io::Result<Block> {
+    let size = reader.read_u32::<BigEndian>()?;
+    if size > MAX_BLOCK_SIZE {
+        return Err(invalid("Block is too big, the file is damaged"));
+    }
+    let mut body = vec![0u8; size as usize];
+    reader.read_exact(&mut body)?;
+    let mut checksum = [0u8; CHECKSUM_SIZE];
+    reader.read_exact(&mut checksum)?;
+    if checksum[..] != hash_sha256(&body)[..CHECKSUM_SIZE] {
+        return Err(invalid("Wrong checksum of block, the file is damaged"));
+    }
+    decode_block(&mut body.as_slice())
+}
+
+/// Reads the whole snapshot and checks that its blocks go in order, are linked by hashes, have right hashes and signatures,
+/// and match the checkpoints. Rules of the chain are not checked here, that is done on import.
+pub fn verify_snapshot<R: Read>(reader: &mut R, origin: &Bytes, checkpoints: &Checkpoints) -> Result<SnapshotTip, String> {
+    let header = read_header(reader).map_err(|e| e.to_string())?;
+    if !origin.is_zero() && &header.origin != origin {
+        return Err(format!("Snapshot is of another chain with origin {}", header.origin.to_string()));
+    }
+    if header.count == 0 {
+        return Err(String::from("Snapshot has no blocks"));
+    }
+    let mut last_hash = Bytes::default();
+    for index in 1..=header.count {
+        let block = read_block(reader).map_err(|e| format!("Unable to read block {}: {}", index, e))?;
+        if block.index != index {
+            return Err(format!("Block {} is found instead of {}", block.index, index));
+        }
+        if index == 1 && block.hash != header.origin {
+            return Err(String::from("First block is not the origin of the snapshot"));
+        }
+        if index > 1 && block.prev_block_hash != last_hash {
+            return Err(format!("Block {} is not linked to the previous one", index));
+        }
+        if !check_block_hash(&block) || !check_block_signature(&block) {
+            return Err(format!("Block {} has wrong hash or signature", index));
+        }
+        if !checkpoints.check(index, &block.hash) {
+            return Err(format!("Block {} doesn't match the checkpoint", index));
+        }
+        last_hash = block.hash;
+    }
+    Ok(SnapshotTip { index: header.count, hash: last_hash, checkpoints: checkpoints.count_up_to(header.count) })
+}
+
+fn decode_block<R: Read>(reader: &mut R) -> io::Result<Block> {
+    let index = reader.read_u64::<BigEndian>()?;
+    let timestamp = reader.read_i64::<BigEndian>()?;
+    let version = reader.read_u32::<BigEndian>()?;
+    let difficulty = reader.read_u32::<BigEndian>()?;
+    let random = reader.read_u32::<BigEndian>()?;
+    let nonce = reader.read_u64::<BigEndian>()?;
+    let prev_block_hash = read_bytes(reader)?;
+    let hash = read_bytes(reader)?;
+    let pub_key = read_bytes(reader)?;
+    let signature = read_bytes(reader)?;
+    let transaction = match reader.read_u8()? {
+        0 => None,
+        1 => Some(read_transaction(reader)?),
+        _ => return Err(invalid("Wrong transaction flag"))
+    };
+    Ok(Block::from_all_params(index, timestamp, version, difficulty, random, nonce, prev_block_hash, hash, pub_key, signature, transaction))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Transaction;
+
+    #[test]
+    fn blocks() {
+        let transaction = Transaction::from_str(String::from("test.ygg"), String::from("domain"), String::from("{}"), Bytes::from_bytes(&[1u8; 32]));
+        let mut full = Block::new(Some(transaction), Bytes::from_bytes(&[1u8; 32]), Bytes::from_bytes(&[2u8; 32]), 24);
+        full.index = 2;
+        full.hash = Bytes::from_bytes(&[3u8; 32]);
+        full.signature = Bytes::from_bytes(&[4u8; 64]);
+        let signing = Block::new(None, Bytes::from_bytes(&[5u8; 32]), Bytes::from_bytes(&[3u8; 32]), 16);
+
+        let header = SnapshotHeader { origin: Bytes::from_bytes(&[6u8; 32]), count: 2 };
+        let mut file = Vec::new();
+        write_header(&mut file, &header).unwrap();
+        let header_size = file.len();
+        write_block(&mut file, &full).unwrap();
+        write_block(&mut file, &signing).unwrap();
+
+        let mut reader = file.as_slice();
+        assert_eq!(header, read_header(&mut reader).unwrap());
+        assert_eq!(full, read_block(&mut reader).unwrap());
+        assert_eq!(signing, read_block(&mut reader).unwrap());
+        assert!(read_block(&mut reader).is_err());
+
+        // Damaged byte in the first block
+        file[header_size + 10] ^= 1;
+        let mut reader = file.as_slice();
+        read_header(&mut reader).unwrap();
+        assert!(read_block(&mut reader).is_err());
+    }
+}
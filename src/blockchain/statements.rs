@@ -0,0 +1,100 @@
+//! Cache of prepared SQL statements for `Chain`, preparing the same SQL for every lookup takes a good part of its time.
+//! Statements are taken from the cache for one call and are put back after reset, so nested calls get their own ones.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+use sqlite::{Connection, Statement};
+
+pub struct StatementCache {
+    statements: RefCell<HashMap<&'static str, Statement<'static>>>,
+}
+
+// Statements are used only along with their connection, that is moved between threads with the whole `Chain`
+unsafe impl Send for StatementCache {}
+
+impl StatementCache {
+    pub fn new() -> Self {
+        StatementCache { statements: RefCell::new(HashMap::new()) }
+    }
+
+    /// Gets a statement for this SQL from the cache, or prepares a new one in `db`.
+    /// The cache must be cleared before `db` is closed or replaced, and it must be dropped before `db`.
+    pub fn prepare<'a>(&'a self, db: &'a Connection, sql: &'static str) -> sqlite::Result<CachedStatement<'a>> {
+        let cached = self.statements.borrow_mut().remove(sql);
+        let statement = match cached {
+            Some(statement) => statement,
+            None => {
+                let statement = db.prepare(sql)?;
+                // The lifetime is guarded by the rules above, the borrow checker can't see them
+                unsafe { std::mem::transmute::<Statement<'a>, Statement<'static>>(statement) }
+            }
+        };
+        Ok(CachedStatement { cache: self, sql, statement: Some(statement) })
+    }
+
+    /// Finalizes all statements
+    pub fn clear(&self) {
+        self.statements.borrow_mut().clear();
+    }
+}
+
+/// Statement that goes back to the cache when it is dropped
+pub struct CachedStatement<'a> {
+    cache: &'a StatementCache,
+    sql: &'static str,
+    statement: Option<Statement<'static>>,
+}
+
+impl<'a> Deref for CachedStatement<'a> {
+    type Target = Statement<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        self.statement.as_ref().unwrap()
+    }
+}
+
+impl<'a> DerefMut for CachedStatement<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.statement.as_mut().unwrap()
+    }
+}
+
+impl<'a> Drop for CachedStatement<'a> {
+    fn drop(&mut self) {
+        if let Some(mut statement) = self.statement.take() {
+            // Unfinished statements keep read transactions open, the error is from the last step and it is known already
+            let _ = statement.reset();
+            self.cache.statements.borrow_mut().insert(self.sql, statement);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlite::State;
+
+    use super::*;
+
+    #[test]
+    fn reuse() {
+        let db = sqlite::open(":memory:").unwrap();
+        db.execute("CREATE TABLE items (id INTEGER); INSERT INTO items VALUES (1); INSERT INTO items VALUES (2);").unwrap();
+        let cache = StatementCache::new();
+        const SQL: &str = "SELECT id FROM items WHERE id >= ? ORDER BY id;";
+        for _ in 0..3 {
+            let mut statement = cache.prepare(&db, SQL).unwrap();
+            statement.bind(1, 1i64).unwrap();
+            assert_eq!(State::Row, statement.next().unwrap());
+            assert_eq!(1, statement.read::<i64>(0).unwrap());
+            // Nested use of the same SQL gets another statement
+            let mut nested = cache.prepare(&db, SQL).unwrap();
+            nested.bind(1, 2i64).unwrap();
+            assert_eq!(State::Row, nested.next().unwrap());
+            assert_eq!(2, nested.read::<i64>(0).unwrap());
+        }
+        assert_eq!(1, cache.statements.borrow().len());
+        cache.clear();
+    }
+}
@@ -0,0 +1,110 @@
+//! Storage of blocks, domains and zones behind `Chain`. SQLite is used by default,
+//! builds with `sled` feature can keep the chain in sled, that has no file locks and suits small devices better.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::{Block, Bytes, Transaction};
+use crate::blockchain::types::OrphanBlock;
+use crate::failure::fatal;
+use crate::failure::FailureKind::{Config, Database};
+use crate::settings::Settings;
+
+pub mod sqlite;
+#[cfg(feature = "sled")]
+pub mod sled;
+
+pub const BACKEND_SQLITE: &str = "sqlite";
+pub const BACKEND_SLED: &str = "sled";
+
+/// A change of some domain or zone, as it is kept in storage
+#[derive(Clone, Debug)]
+pub struct IdentityRow {
+    /// Index of the block with this change
+    pub index: u64,
+    pub timestamp: i64,
+    pub transaction: Transaction,
+}
+
+/// Looks up domains on its own connections, so that DNS threads don't wait for `Chain` and for each other
+pub trait DomainReader: Send + Sync {
+    fn get_last_domain(&self, identity: &Bytes) -> Result<Option<IdentityRow>, String>;
+}
+
+pub trait BlockStorage: Send {
+    /// Adds block along with its domain or zone
+    fn add_block(&mut self, block: &Block) -> Result<(), String>;
+    fn get_block(&self, index: u64) -> Option<Block>;
+    fn get_last_block(&self) -> Option<Block>;
+    /// Gets up to `limit` blocks starting from index `from`, only blocks with transactions if `full_only` is set
+    fn get_blocks(&self, from: u64, limit: u64, full_only: bool) -> Vec<Block>;
+    /// Gets last block with transaction below `before`, mined by `pub_key` if it is given
+    fn get_last_full_block(&self, before: u64, pub_key: Option<&[u8]>) -> Option<Block>;
+    /// Gets timestamps of up to `count` blocks below `before`, from the last one
+    fn get_last_timestamps(&self, before: u64, count: u64) -> Vec<i64>;
+    /// Counts last `count` blocks that signal the upgrade with this flag in their random value
+    fn count_upgrade_signals(&self, count: u64, flag: u32) -> u64;
+    /// Counts blocks with indexes in range (from; to) mined by this key
+    fn count_blocks_by_key(&self, from: u64, to: u64, pub_key: &[u8]) -> u64;
+    /// Gets public keys of blocks with these indexes
+    fn get_block_keys(&self, indexes: &[u64]) -> HashMap<u64, Bytes>;
+    /// Removes blocks from this index with their domains and zones, archiving the blocks if it is enabled
+    fn truncate(&mut self, from: u64, reason: &str) -> Result<(), String>;
+    /// Gets archived blocks with indexes in range [from; to]
+    fn get_orphans(&self, from: u64, to: u64) -> Vec<OrphanBlock>;
+
+    /// Gets the key of the first change of this domain or zone below `height`
+    fn get_identity_owner(&self, height: u64, identity: &Bytes, zone: bool) -> Option<Bytes>;
+    /// Gets all changes of this domain below `height`, from the first one
+    fn get_domain_changes(&self, height: u64, identity: &Bytes) -> Vec<IdentityRow>;
+    fn get_last_domain(&self, identity: &Bytes) -> Option<IdentityRow>;
+    fn get_domains_by_key(&self, pub_key: &[u8]) -> Vec<IdentityRow>;
+    /// Calls `f` for domain changes from block `from` in order of blocks, or backwards from the last one if `from` is None,
+    /// until `f` returns false
+    fn for_each_domain(&self, from: Option<u64>, f: &mut dyn FnMut(&IdentityRow) -> bool);
+    /// Gets data of all zone changes
+    fn get_zones(&self) -> Vec<String>;
+    /// Gets identities of all domains and zones
+    fn get_identities(&self) -> Result<Vec<Bytes>, String>;
+
+    fn get_options(&self) -> Vec<(String, String)>;
+    /// Replaces the value of option, or removes it if `value` is None
+    fn set_option(&self, name: &str, value: Option<&str>) -> Result<(), String>;
+    /// Deletes everything and starts an empty chain with this origin
+    fn reset(&mut self, origin: &str) -> Result<(), String>;
+    /// Moves broken storage aside and starts an empty one, returns the name of the moved one, if it is kept
+    fn recover(&mut self) -> Result<Option<String>, String>;
+
+    /// Starts a transaction for several blocks
+    fn begin(&mut self) -> Result<(), String>;
+    fn commit(&mut self) -> Result<(), String>;
+    /// Drops changes of the transaction started by `begin`
+    fn rollback(&mut self) -> Result<(), String>;
+
+    /// Gets the reader of domains, that works in parallel with this storage, if it has one.
+    /// It sees only committed blocks.
+    fn get_reader(&self) -> Option<Arc<dyn DomainReader>> {
+        None
+    }
+}
+
+/// Opens the storage from `[storage]` settings
+pub fn open_storage(settings: &Settings, db_name: &str) -> Box<dyn BlockStorage> {
+    let archive = settings.db.archive_orphans;
+    match settings.storage.backend.as_str() {
+        BACKEND_SQLITE => {
+            let storage = sqlite::SqliteStorage::open(db_name, archive, settings.db.read_connections)
+                .unwrap_or_else(|e| fatal(Database, &format!("Unable to open blockchain DB '{}': {}", db_name, e)));
+            Box::new(storage)
+        }
+        #[cfg(feature = "sled")]
+        BACKEND_SLED => {
+            let storage = sled::SledStorage::open(db_name, archive)
+                .unwrap_or_else(|e| fatal(Database, &format!("Unable to open blockchain DB '{}': {}", db_name, e)));
+            Box::new(storage)
+        }
+        #[cfg(not(feature = "sled"))]
+        BACKEND_SLED => fatal(Config, "This build has no sled storage, build it with 'sled' feature"),
+        backend => fatal(Config, &format!("Unknown storage backend '{}'", backend))
+    }
+}
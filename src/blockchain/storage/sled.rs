@@ -0,0 +1,568 @@
+//! Storage in sled key-value trees, for builds with `sled` feature.
+//! Blocks are kept by big-endian index, so that trees are ordered like the chain,
+//! and lookups by identity or key go through index trees with keys like `identity ++ index`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use chrono::Utc;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde::{Deserialize, Serialize};
+use sled::{Config, Db, IVec, Tree};
+
+use crate::{Block, Bytes, Transaction};
+use crate::blockchain::consensus::has_signal;
+use crate::blockchain::storage::{BlockStorage, IdentityRow};
+use crate::blockchain::types::OrphanBlock;
+use crate::commons::constants::MEMORY_DB_NAME;
+
+const EMPTY: &[u8] = &[];
+
+/// Domain or zone change as it is kept in `domains` and `zones` trees
+#[derive(Serialize, Deserialize)]
+struct StoredRow {
+    timestamp: i64,
+    transaction: Transaction,
+}
+
+/// Block removed from the chain, as it is kept in `orphans` tree
+#[derive(Serialize, Deserialize)]
+struct StoredOrphan {
+    block: Block,
+    reason: String,
+}
+
+struct Trees {
+    /// index -> block
+    blocks: Tree,
+    /// index -> key of blocks with transactions
+    full_blocks: Tree,
+    /// key ++ index of blocks with transactions
+    full_keys: Tree,
+    /// key ++ index of all blocks
+    block_keys: Tree,
+    /// index -> domain change
+    domains: Tree,
+    /// identity ++ index of domain changes
+    domain_ids: Tree,
+    /// key ++ index of domain changes
+    domain_keys: Tree,
+    /// index -> zone change
+    zones: Tree,
+    /// identity ++ index of zone changes
+    zone_ids: Tree,
+    options: Tree,
+    /// archived ++ index -> orphan block
+    orphans: Tree,
+}
+
+impl Trees {
+    fn open(db: &Db) -> sled::Result<Self> {
+        Ok(Trees {
+            blocks: db.open_tree("blocks")?,
+            full_blocks: db.open_tree("full_blocks")?,
+            full_keys: db.open_tree("full_keys")?,
+            block_keys: db.open_tree("block_keys")?,
+            domains: db.open_tree("domains")?,
+            domain_ids: db.open_tree("domain_ids")?,
+            domain_keys: db.open_tree("domain_keys")?,
+            zones: db.open_tree("zones")?,
+            zone_ids: db.open_tree("zone_ids")?,
+            options: db.open_tree("options")?,
+            orphans: db.open_tree("orphans")?,
+        })
+    }
+
+    fn all(&self) -> [&Tree; 11] {
+        [&self.blocks, &self.full_blocks, &self.full_keys, &self.block_keys, &self.domains, &self.domain_ids,
+            &self.domain_keys, &self.zones, &self.zone_ids, &self.options, &self.orphans]
+    }
+}
+
+/// Blocks that are changed after `begin`, sled has no transactions over several trees, so they are undone by hand
+struct Journal {
+    /// Blocks from this index are replaced by `removed` on rollback
+    from: u64,
+    /// Blocks that were in the DB at `begin`, from `from`, and are truncated since then
+    removed: Vec<Block>,
+    /// Keys of blocks that are archived as orphans since `begin`
+    orphans: Vec<Vec<u8>>,
+}
+
+pub struct SledStorage {
+    db: Db,
+    trees: Trees,
+    /// Directory of the DB, it is made from DB file name with `.sled` extension
+    path: String,
+    in_memory: bool,
+    archive_orphans: bool,
+    journal: Option<Journal>,
+}
+
+impl SledStorage {
+    pub fn open(db_name: &str, archive_orphans: bool) -> Result<Self, String> {
+        let path = Path::new(db_name).with_extension("sled").to_string_lossy().to_string();
+        let in_memory = db_name == MEMORY_DB_NAME;
+        let db = match in_memory {
+            true => Config::new().temporary(true).open(),
+            false => Config::new().path(&path).open()
+        }.map_err(|e| e.to_string())?;
+        let trees = Trees::open(&db).map_err(|e| e.to_string())?;
+        Ok(SledStorage { db, trees, path, in_memory, archive_orphans, journal: None })
+    }
+
+    fn get_row(tree: &Tree, index: &[u8]) -> Option<IdentityRow> {
+        let value = tree.get(index).ok()??;
+        let row: StoredRow = serde_json::from_slice(&value).ok()?;
+        let index = read_index(index);
+        Some(IdentityRow { index, timestamp: row.timestamp, transaction: row.transaction })
+    }
+
+    /// Gets changes of identities from index tree, the keys of which end with block index
+    fn get_rows(&self, rows: &Tree, ids: &Tree, prefix: &[u8], height: u64) -> Vec<IdentityRow> {
+        ids.scan_prefix(prefix)
+            .keys()
+            .filter_map(|key| key.ok())
+            .map(|key| key[key.len() - 8..].to_vec())
+            .filter(|index| read_index(index) < height)
+            .filter_map(|index| Self::get_row(rows, &index))
+            .collect()
+    }
+
+    fn add_row(&self, index: u64, timestamp: i64, transaction: &Transaction) -> sled::Result<()> {
+        let (rows, ids, class) = match transaction.class.as_ref() {
+            // Renewals and transfers are kept as domain changes, that have the same data
+            "domain" | "renew" | "transfer" => (&self.trees.domains, &self.trees.domain_ids, "domain"),
+            "zone" => (&self.trees.zones, &self.trees.zone_ids, "zone"),
+            _ => return Err(sled::Error::Unsupported(format!("Unknown transaction class '{}'", &transaction.class)))
+        };
+        let mut transaction = transaction.clone();
+        transaction.class = class.to_owned();
+        rows.insert(index_key(index), serde_json::to_vec(&StoredRow { timestamp, transaction: transaction.clone() }).unwrap())?;
+        ids.insert(composite_key(&transaction.identity, index), EMPTY)?;
+        if class == "domain" {
+            self.trees.domain_keys.insert(composite_key(&transaction.pub_key, index), EMPTY)?;
+        }
+        Ok(())
+    }
+
+    fn remove_rows(&self, from: u64, zones: bool) -> sled::Result<()> {
+        let (rows, ids) = match zones {
+            true => (&self.trees.zones, &self.trees.zone_ids),
+            false => (&self.trees.domains, &self.trees.domain_ids)
+        };
+        for item in rows.range(index_key(from)..) {
+            let (key, _) = item?;
+            if let Some(row) = Self::get_row(rows, &key) {
+                ids.remove(composite_key(&row.transaction.identity, row.index))?;
+                self.trees.domain_keys.remove(composite_key(&row.transaction.pub_key, row.index))?;
+            }
+            rows.remove(key)?;
+        }
+        Ok(())
+    }
+
+    /// Removes blocks from this index, and archives them with the `reason` if it is enabled and given.
+    /// Returns keys of archived blocks.
+    fn truncate_trees(&self, from: u64, reason: Option<&str>) -> sled::Result<Vec<Vec<u8>>> {
+        let archived = Utc::now().timestamp();
+        let mut orphans = Vec::new();
+        for item in self.trees.blocks.range(index_key(from)..) {
+            let (key, value) = item?;
+            if let Ok(block) = serde_json::from_slice::<Block>(&value) {
+                if let Some(reason) = reason.filter(|_| self.archive_orphans) {
+                    let mut orphan_key = (archived as u64).to_be_bytes().to_vec();
+                    orphan_key.extend_from_slice(&key);
+                    let orphan = StoredOrphan { block: block.clone(), reason: reason.to_owned() };
+                    self.trees.orphans.insert(orphan_key.clone(), serde_json::to_vec(&orphan).unwrap())?;
+                    orphans.push(orphan_key);
+                }
+                self.trees.block_keys.remove(composite_key(&block.pub_key, block.index))?;
+                self.trees.full_keys.remove(composite_key(&block.pub_key, block.index))?;
+            }
+            self.trees.full_blocks.remove(&key)?;
+            self.trees.blocks.remove(key)?;
+        }
+        self.remove_rows(from, false)?;
+        self.remove_rows(from, true)?;
+        Ok(orphans)
+    }
+
+    fn clear(&self) -> sled::Result<()> {
+        for tree in self.trees.all().iter() {
+            tree.clear()?;
+        }
+        Ok(())
+    }
+}
+
+fn index_key(index: u64) -> [u8; 8] {
+    index.to_be_bytes()
+}
+
+fn read_index(key: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&key[key.len() - 8..]);
+    u64::from_be_bytes(bytes)
+}
+
+/// Key for index trees, block index goes last to keep the keys with same prefix in the order of chain
+fn composite_key(prefix: &[u8], index: u64) -> Vec<u8> {
+    let mut key = prefix.to_vec();
+    key.extend_from_slice(&index_key(index));
+    key
+}
+
+fn parse_block(value: &IVec) -> Option<Block> {
+    match serde_json::from_slice(value) {
+        Ok(block) => Some(block),
+        Err(e) => {
+            error!("Something wrong with block in DB! {}", e);
+            None
+        }
+    }
+}
+
+impl BlockStorage for SledStorage {
+    fn add_block(&mut self, block: &Block) -> Result<(), String> {
+        let key = index_key(block.index);
+        let result = self.trees.blocks.insert(key, serde_json::to_vec(block).unwrap())
+            .and_then(|_| self.trees.block_keys.insert(composite_key(&block.pub_key, block.index), EMPTY))
+            .and_then(|_| match &block.transaction {
+                None => Ok(()),
+                Some(transaction) => {
+                    self.trees.full_blocks.insert(key, block.pub_key.as_slice())?;
+                    self.trees.full_keys.insert(composite_key(&block.pub_key, block.index), EMPTY)?;
+                    self.add_row(block.index, block.timestamp, transaction)
+                }
+            });
+        result.map_err(|e| e.to_string())
+    }
+
+    fn get_block(&self, index: u64) -> Option<Block> {
+        match self.trees.blocks.get(index_key(index)) {
+            Ok(value) => value.as_ref().and_then(parse_block),
+            Err(e) => {
+                warn!("Can't find requested block {}: {}", index, e);
+                None
+            }
+        }
+    }
+
+    fn get_last_block(&self) -> Option<Block> {
+        let (_, value) = self.trees.blocks.last().ok()??;
+        parse_block(&value)
+    }
+
+    fn get_blocks(&self, from: u64, limit: u64, full_only: bool) -> Vec<Block> {
+        let tree = match full_only {
+            true => &self.trees.full_blocks,
+            false => &self.trees.blocks
+        };
+        tree.range(index_key(from)..)
+            .keys()
+            .take(limit.min(usize::MAX as u64) as usize)
+            .filter_map(|key| key.ok())
+            .filter_map(|key| self.get_block(read_index(&key)))
+            .collect()
+    }
+
+    fn get_last_full_block(&self, before: u64, pub_key: Option<&[u8]>) -> Option<Block> {
+        let index = match pub_key {
+            None => self.trees.full_blocks.range(..index_key(before)).keys().next_back()?.ok()?,
+            Some(pub_key) => {
+                let range = composite_key(pub_key, 0)..composite_key(pub_key, before);
+                self.trees.full_keys.range(range).keys().next_back()?.ok()?
+            }
+        };
+        self.get_block(read_index(&index))
+    }
+
+    fn get_last_timestamps(&self, before: u64, count: u64) -> Vec<i64> {
+        self.trees.blocks.range(..index_key(before))
+            .values()
+            .rev()
+            .take(count.min(usize::MAX as u64) as usize)
+            .filter_map(|value| value.ok())
+            .filter_map(|value| parse_block(&value))
+            .map(|block| block.timestamp)
+            .collect()
+    }
+
+    fn count_upgrade_signals(&self, count: u64, flag: u32) -> u64 {
+        self.trees.blocks.iter()
+            .values()
+            .rev()
+            .take(count.min(usize::MAX as u64) as usize)
+            .filter_map(|value| value.ok())
+            .filter_map(|value| parse_block(&value))
+            .filter(|block| has_signal(block.random, flag))
+            .count() as u64
+    }
+
+    fn count_blocks_by_key(&self, from: u64, to: u64, pub_key: &[u8]) -> u64 {
+        if to <= from + 1 {
+            return 0;
+        }
+        self.trees.block_keys.range(composite_key(pub_key, from + 1)..composite_key(pub_key, to)).count() as u64
+    }
+
+    fn get_block_keys(&self, indexes: &[u64]) -> HashMap<u64, Bytes> {
+        indexes.iter()
+            .filter_map(|index| self.get_block(*index))
+            .map(|block| (block.index, block.pub_key))
+            .collect()
+    }
+
+    fn truncate(&mut self, from: u64, reason: &str) -> Result<(), String> {
+        // Blocks that were there before `begin` are kept in the journal before they are removed
+        let kept = match &self.journal {
+            Some(journal) if from < journal.from => Some(self.get_blocks(from, journal.from - from, false)),
+            _ => None
+        };
+        if let (Some(journal), Some(mut blocks)) = (&mut self.journal, kept) {
+            blocks.append(&mut journal.removed);
+            journal.removed = blocks;
+            journal.from = from;
+        }
+        let orphans = self.truncate_trees(from, Some(reason)).map_err(|e| e.to_string())?;
+        if let Some(journal) = &mut self.journal {
+            journal.orphans.extend(orphans);
+        }
+        Ok(())
+    }
+
+    fn get_orphans(&self, from: u64, to: u64) -> Vec<OrphanBlock> {
+        let mut result = Vec::new();
+        if !self.archive_orphans {
+            return result;
+        }
+        for (key, value) in self.trees.orphans.iter().filter_map(|item| item.ok()) {
+            let index = read_index(&key);
+            if index < from || index > to {
+                continue;
+            }
+            if let Ok(orphan) = serde_json::from_slice::<StoredOrphan>(&value) {
+                let archived = read_index(&key[..8]) as i64;
+                result.push(OrphanBlock { block: orphan.block, reason: orphan.reason, archived });
+            }
+        }
+        result
+    }
+
+    fn get_identity_owner(&self, height: u64, identity: &Bytes, zone: bool) -> Option<Bytes> {
+        let (rows, ids) = match zone {
+            true => (&self.trees.zones, &self.trees.zone_ids),
+            false => (&self.trees.domains, &self.trees.domain_ids)
+        };
+        let key = ids.scan_prefix(identity.as_slice()).keys().next()?.ok()?;
+        let index = read_index(&key);
+        if index >= height {
+            return None;
+        }
+        Self::get_row(rows, &index_key(index)).map(|row| row.transaction.pub_key)
+    }
+
+    fn get_domain_changes(&self, height: u64, identity: &Bytes) -> Vec<IdentityRow> {
+        self.get_rows(&self.trees.domains, &self.trees.domain_ids, identity, height)
+    }
+
+    fn get_last_domain(&self, identity: &Bytes) -> Option<IdentityRow> {
+        let key = self.trees.domain_ids.scan_prefix(identity.as_slice()).keys().next_back()?.ok()?;
+        Self::get_row(&self.trees.domains, &key[key.len() - 8..])
+    }
+
+    fn get_domains_by_key(&self, pub_key: &[u8]) -> Vec<IdentityRow> {
+        self.get_rows(&self.trees.domains, &self.trees.domain_keys, pub_key, u64::MAX)
+    }
+
+    fn for_each_domain(&self, from: Option<u64>, f: &mut dyn FnMut(&IdentityRow) -> bool) {
+        let keys: Box<dyn Iterator<Item = sled::Result<IVec>>> = match from {
+            Some(from) => Box::new(self.trees.domains.range(index_key(from)..).keys()),
+            None => Box::new(self.trees.domains.iter().keys().rev())
+        };
+        for key in keys.filter_map(|key| key.ok()) {
+            if let Some(row) = Self::get_row(&self.trees.domains, &key) {
+                if !f(&row) {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn get_zones(&self) -> Vec<String> {
+        self.trees.zones.iter()
+            .keys()
+            .filter_map(|key| key.ok())
+            .filter_map(|key| Self::get_row(&self.trees.zones, &key))
+            .map(|row| row.transaction.data)
+            .collect()
+    }
+
+    fn get_identities(&self) -> Result<Vec<Bytes>, String> {
+        let mut identities = Vec::new();
+        for tree in [&self.trees.domain_ids, &self.trees.zone_ids].iter() {
+            for key in tree.iter().keys() {
+                let key = key.map_err(|e| e.to_string())?;
+                identities.push(Bytes::from_bytes(&key[..key.len() - 8]));
+            }
+        }
+        Ok(identities)
+    }
+
+    fn get_options(&self) -> Vec<(String, String)> {
+        self.trees.options.iter()
+            .filter_map(|item| item.ok())
+            .map(|(name, value)| (String::from_utf8_lossy(&name).to_string(), String::from_utf8_lossy(&value).to_string()))
+            .collect()
+    }
+
+    fn set_option(&self, name: &str, value: Option<&str>) -> Result<(), String> {
+        let result = match value {
+            None => self.trees.options.remove(name).map(|_| ()),
+            Some(value) => self.trees.options.insert(name, value).map(|_| ())
+        };
+        result.map_err(|e| e.to_string())
+    }
+
+    fn reset(&mut self, origin: &str) -> Result<(), String> {
+        self.journal = None;
+        self.clear()
+            .and_then(|_| self.trees.options.insert("origin", origin).map(|_| ()))
+            .and_then(|_| self.db.flush().map(|_| ()))
+            .map_err(|e| e.to_string())
+    }
+
+    fn recover(&mut self) -> Result<Option<String>, String> {
+        self.journal = None;
+        if self.in_memory {
+            self.clear().map_err(|e| e.to_string())?;
+            return Ok(None);
+        }
+        // Trees keep the DB open, so they are switched to a temporary DB before moving the directory
+        let temp = Config::new().temporary(true).open().map_err(|e| e.to_string())?;
+        self.trees = Trees::open(&temp).map_err(|e| e.to_string())?;
+        self.db = temp;
+        let backup = format!("{}.corrupt.{}", &self.path, Utc::now().timestamp());
+        fs::rename(&self.path, &backup).map_err(|e| format!("Unable to move corrupted database aside! {}", e))?;
+        self.db = Config::new().path(&self.path).open().map_err(|e| e.to_string())?;
+        self.trees = Trees::open(&self.db).map_err(|e| e.to_string())?;
+        Ok(Some(backup))
+    }
+
+    /// Sled writes are atomic only one by one, so blocks that are removed after this are kept in the journal
+    fn begin(&mut self) -> Result<(), String> {
+        if self.journal.is_none() {
+            let from = self.get_last_block().map(|block| block.index + 1).unwrap_or(1);
+            self.journal = Some(Journal { from, removed: Vec::new(), orphans: Vec::new() });
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), String> {
+        self.journal = None;
+        self.db.flush().map(|_| ()).map_err(|e| e.to_string())
+    }
+
+    /// Removes blocks that are added after `begin`, and puts back the ones that are removed, from the journal
+    fn rollback(&mut self) -> Result<(), String> {
+        let journal = match self.journal.take() {
+            Some(journal) => journal,
+            None => return Ok(())
+        };
+        self.truncate_trees(journal.from, None).map_err(|e| e.to_string())?;
+        for key in journal.orphans {
+            self.trees.orphans.remove(key).map_err(|e| e.to_string())?;
+        }
+        for block in &journal.removed {
+            self.add_block(block)?;
+        }
+        self.db.flush().map(|_| ()).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blockchain::chain::tests::test_db;
+    use crate::blockchain::consensus::get_upgrade_flag;
+    use crate::blockchain::storage::sqlite::SqliteStorage;
+
+    use super::*;
+
+    #[test]
+    fn same_as_sqlite() {
+        let sqlite = SqliteStorage::open(&test_db("same_as_sqlite"), false, 0).unwrap();
+        let mut sled = SledStorage::open(MEMORY_DB_NAME, false).unwrap();
+        let height = sqlite.get_last_block().unwrap().index;
+        for block in sqlite.get_blocks(1, height, false) {
+            sled.add_block(&block).unwrap();
+        }
+        assert_eq!(sqlite.get_last_block(), sled.get_last_block());
+        assert_eq!(sqlite.get_blocks(1, 50, true), sled.get_blocks(1, 50, true));
+        assert_eq!(sqlite.get_last_full_block(height, None), sled.get_last_full_block(height, None));
+        assert_eq!(sqlite.get_last_timestamps(100, 11), sled.get_last_timestamps(100, 11));
+        for bit in 0..8 {
+            assert_eq!(sqlite.count_upgrade_signals(height, get_upgrade_flag(bit)), sled.count_upgrade_signals(height, get_upgrade_flag(bit)));
+        }
+        assert_eq!(sqlite.get_identities().unwrap().len(), sled.get_identities().unwrap().len());
+        let mut zones = sqlite.get_zones();
+        zones.sort();
+        let mut sled_zones = sled.get_zones();
+        sled_zones.sort();
+        assert_eq!(zones, sled_zones);
+        let block = sqlite.get_last_full_block(height, None).unwrap();
+        let transaction = block.transaction.clone().unwrap();
+        assert_eq!(1, sled.count_blocks_by_key(block.index - 1, block.index + 1, &block.pub_key));
+        assert_eq!(sqlite.get_identity_owner(u64::MAX, &transaction.identity, transaction.class == "zone"),
+                   sled.get_identity_owner(u64::MAX, &transaction.identity, transaction.class == "zone"));
+
+        // The last block with the first change of some domain, that domain is gone after truncating
+        let block = sqlite.get_blocks(1, height, true).into_iter().rev()
+            .find(|block| {
+                let transaction = block.transaction.as_ref().unwrap();
+                sqlite.get_identity_owner(block.index, &transaction.identity, transaction.class == "zone").is_none()
+            })
+            .unwrap();
+        let transaction = block.transaction.clone().unwrap();
+        sled.truncate(block.index, "test").unwrap();
+        assert_eq!(block.index - 1, sled.get_last_block().unwrap().index);
+        assert!(sled.get_identity_owner(u64::MAX, &transaction.identity, transaction.class == "zone").is_none());
+        sled.set_option("checked", Some("100")).unwrap();
+        assert_eq!(vec![(String::from("checked"), String::from("100"))], sled.get_options());
+        sled.reset("origin").unwrap();
+        assert!(sled.get_last_block().is_none());
+    }
+
+    #[test]
+    fn rollback() {
+        let sqlite = SqliteStorage::open(&test_db("sled_rollback"), false, 0).unwrap();
+        let mut sled = SledStorage::open(MEMORY_DB_NAME, true).unwrap();
+        let blocks = sqlite.get_blocks(1, 50, false);
+        for block in &blocks {
+            sled.add_block(block).unwrap();
+        }
+        // The same as a reorg that fails in the middle
+        sled.begin().unwrap();
+        sled.truncate(45, "fork").unwrap();
+        let mut other = blocks[44].clone();
+        other.nonce += 1;
+        sled.add_block(&other).unwrap();
+        sled.truncate(40, "bad branch").unwrap();
+        assert_eq!(39, sled.get_last_block().unwrap().index);
+        sled.rollback().unwrap();
+        assert_eq!(blocks, sled.get_blocks(1, 100, false));
+        assert!(sled.get_orphans(1, 100).is_empty());
+        let last = blocks.last().unwrap();
+        assert_eq!(1, sled.count_blocks_by_key(last.index - 1, last.index + 1, &last.pub_key));
+
+        // Nothing is undone after commit
+        sled.begin().unwrap();
+        sled.truncate(45, "fork").unwrap();
+        sled.commit().unwrap();
+        sled.rollback().unwrap();
+        assert_eq!(44, sled.get_last_block().unwrap().index);
+        assert_eq!(6, sled.get_orphans(1, 100).len());
+    }
+}
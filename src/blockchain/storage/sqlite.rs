@@ -0,0 +1,683 @@
+//! Default storage, blocks, domains and zones are kept in SQLite tables.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+
+use chrono::Utc;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use sqlite::{Connection, OpenFlags, State, Statement};
+
+use crate::{Block, Bytes, Transaction};
+use crate::blockchain::consensus::{SIGNAL_MARKER, SIGNAL_MARKER_MASK};
+use crate::blockchain::encoding::{decode_transaction, encode_transaction, is_binary};
+use crate::blockchain::statements::{CachedStatement, StatementCache};
+use crate::blockchain::storage::{BlockStorage, DomainReader, IdentityRow};
+use crate::blockchain::types::OrphanBlock;
+use crate::commons::constants::MEMORY_DB_NAME;
+
+const TEMP_DB_NAME: &str = "temp.db";
+const SQL_CREATE_TABLES: &str = include_str!("../sql/create_db.sql");
+const SQL_DROP_TABLES: &str = "DROP TABLE IF EXISTS blocks; DROP TABLE IF EXISTS domains; DROP TABLE IF EXISTS zones;\
+                               DROP TABLE IF EXISTS options; DROP TABLE IF EXISTS orphans;";
+const SQL_ADD_BLOCK: &str = "INSERT INTO blocks (id, timestamp, version, difficulty, random, nonce, 'transaction',\
+                          prev_block_hash, hash, pub_key, signature) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);";
+const SQL_GET_LAST_BLOCK: &str = "SELECT * FROM blocks ORDER BY id DESC LIMIT 1;";
+const SQL_TRUNCATE_BLOCKS: &str = "DELETE FROM blocks WHERE id >= ?;";
+const SQL_TRUNCATE_DOMAINS: &str = "DELETE FROM domains WHERE id >= ?;";
+const SQL_TRUNCATE_ZONES: &str = "DELETE FROM zones WHERE id >= ?;";
+
+const SQL_ADD_DOMAIN: &str = "INSERT INTO domains (id, timestamp, identity, confirmation, data, pub_key) VALUES (?, ?, ?, ?, ?, ?)";
+const SQL_ADD_ZONE: &str = "INSERT INTO zones (id, timestamp, identity, confirmation, data, pub_key) VALUES (?, ?, ?, ?, ?, ?)";
+const SQL_GET_BLOCK_BY_ID: &str = "SELECT * FROM blocks WHERE id=? LIMIT 1;";
+const SQL_GET_BLOCK_KEYS_BY_IDS: &str = "SELECT id, pub_key FROM blocks WHERE id IN ({});";
+const SQL_GET_LAST_TIMESTAMPS: &str = "SELECT timestamp FROM blocks WHERE id < ? ORDER BY id DESC LIMIT ?;";
+const SQL_COUNT_UPGRADE_SIGNALS: &str = "SELECT COUNT(*) FROM (SELECT random FROM blocks ORDER BY id DESC LIMIT ?) WHERE (random & ?) = ? AND (random & ?) <> 0;";
+const SQL_COUNT_BLOCKS_BY_KEY: &str = "SELECT COUNT(*) FROM blocks WHERE id > ? AND id < ? AND pub_key = ?;";
+const SQL_GET_LAST_FULL_BLOCK: &str = "SELECT * FROM blocks WHERE id < ? AND `transaction`<>'' ORDER BY id DESC LIMIT 1;";
+const SQL_GET_LAST_FULL_BLOCK_FOR_KEY: &str = "SELECT * FROM blocks WHERE id < ? AND `transaction`<>'' AND pub_key = ? ORDER BY id DESC LIMIT 1;";
+const SQL_GET_DOMAIN_PUBLIC_KEY_BY_ID: &str = "SELECT pub_key FROM domains WHERE id < ? AND identity = ? LIMIT 1;";
+const SQL_GET_ZONE_PUBLIC_KEY_BY_ID: &str = "SELECT pub_key FROM zones WHERE id < ? AND identity = ? LIMIT 1;";
+const SQL_GET_DOMAIN_CHANGES_BY_ID: &str = "SELECT * FROM domains WHERE id < ? AND identity = ? ORDER BY id;";
+const SQL_GET_DOMAIN_BY_ID: &str = "SELECT * FROM domains WHERE identity = ? ORDER BY id DESC LIMIT 1;";
+const SQL_GET_DOMAINS_BY_KEY: &str = "SELECT * FROM domains WHERE pub_key = ?;";
+const SQL_GET_ZONES: &str = "SELECT data FROM zones;";
+const SQL_GET_BLOCKS_FROM: &str = "SELECT * FROM blocks WHERE id >= ? ORDER BY id LIMIT ?;";
+const SQL_GET_FULL_BLOCKS_FROM: &str = "SELECT * FROM blocks WHERE id >= ? AND `transaction`<>'' ORDER BY id LIMIT ?;";
+const SQL_GET_DOMAINS_FROM: &str = "SELECT * FROM domains WHERE id >= ? ORDER BY id;";
+const SQL_GET_DOMAINS_DESC: &str = "SELECT * FROM domains ORDER BY id DESC;";
+const SQL_GET_IDENTITIES: &str = "SELECT identity FROM domains UNION ALL SELECT identity FROM zones;";
+
+const SQL_GET_OPTIONS: &str = "SELECT * FROM options;";
+const SQL_ADD_OPTION: &str = "INSERT INTO options (name, value) VALUES (?, ?);";
+const SQL_DELETE_OPTION: &str = "DELETE FROM options WHERE name = ?;";
+/// Indices that are absent in DBs of older versions
+const SQL_CREATE_INDICES: &str = "CREATE INDEX IF NOT EXISTS zone_ids ON zones ('identity'); CREATE INDEX IF NOT EXISTS domain_keys ON domains (pub_key);";
+/// WAL is much faster on slow disks, and readers don't wait for writes
+const SQL_TUNE_DB: &str = "PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;";
+
+const SQL_CREATE_ORPHANS: &str = "CREATE TABLE IF NOT EXISTS orphans (
+    'id' BIGINT NOT NULL,
+    'timestamp' BIGINT NOT NULL,
+    'version' INT,
+    'difficulty' INTEGER,
+    'random' INTEGER,
+    'nonce' INTEGER,
+    'transaction' TEXT,
+    'prev_block_hash' BINARY,
+    'hash' BINARY,
+    'pub_key' BINARY,
+    'signature' BINARY,
+    'reason' TEXT,
+    'archived' BIGINT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS orphan_index ON orphans (id);";
+const SQL_ARCHIVE_BLOCKS: &str = "INSERT INTO orphans SELECT *, ?, ? FROM blocks WHERE id >= ?;";
+const SQL_GET_ORPHANS: &str = "SELECT * FROM orphans WHERE id >= ? AND id <= ? ORDER BY archived, id;";
+
+/// Max possible block index
+const MAX: u64 = i64::MAX as u64;
+
+pub struct SqliteStorage {
+    /// Must be dropped before `db`, and cleared when `db` is replaced
+    statements: StatementCache,
+    db: Connection,
+    db_name: String,
+    archive_orphans: bool,
+    /// Connections for DNS lookups, there are none for in-memory DB, it can't be shared
+    readers: Option<Arc<ReadPool>>,
+}
+
+impl SqliteStorage {
+    pub fn open(db_name: &str, archive_orphans: bool, read_connections: usize) -> Result<Self, String> {
+        let db = sqlite::open(db_name).map_err(|e| e.to_string())?;
+        let readers = match read_connections > 0 && db_name != MEMORY_DB_NAME {
+            true => Some(Arc::new(ReadPool::new(db_name, read_connections))),
+            false => None
+        };
+        let storage = SqliteStorage { statements: StatementCache::new(), db, db_name: db_name.to_owned(), archive_orphans, readers };
+        storage.tune_db();
+        if let Err(e) = storage.db.prepare(SQL_GET_LAST_BLOCK) {
+            info!("No blockchain database found. Creating new. {}", e);
+            storage.db.execute(SQL_CREATE_TABLES).map_err(|e| e.to_string())?;
+        }
+        if archive_orphans {
+            storage.db.execute(SQL_CREATE_ORPHANS).map_err(|e| e.to_string())?;
+        }
+        if let Err(e) = storage.db.execute(SQL_CREATE_INDICES) {
+            warn!("Error creating DB indices: {}", e);
+        }
+        Ok(storage)
+    }
+
+    fn is_in_memory(&self) -> bool {
+        self.db_name == MEMORY_DB_NAME
+    }
+
+    fn tune_db(&self) {
+        if self.is_in_memory() {
+            return;
+        }
+        if let Err(e) = self.db.execute(SQL_TUNE_DB) {
+            warn!("Error setting DB options: {}", e);
+        }
+    }
+
+    /// Prepares a statement, or takes already prepared one
+    fn prepare(&self, sql: &'static str) -> sqlite::Result<CachedStatement<'_>> {
+        self.statements.prepare(&self.db, sql)
+    }
+
+    /// Closes connections of readers, they are opened again with the new DB
+    fn clear_readers(&self) {
+        if let Some(readers) = &self.readers {
+            readers.clear();
+        }
+    }
+
+    fn create_tables(&self) -> sqlite::Result<()> {
+        self.db.execute(SQL_CREATE_TABLES)?;
+        self.db.execute(SQL_CREATE_INDICES)?;
+        if self.archive_orphans {
+            self.db.execute(SQL_CREATE_ORPHANS)?;
+        }
+        Ok(())
+    }
+
+    fn add_block_row(&mut self, block: &Block) -> sqlite::Result<State> {
+        let mut statement = self.prepare(SQL_ADD_BLOCK)?;
+        statement.bind(1, block.index as i64)?;
+        statement.bind(2, block.timestamp as i64)?;
+        statement.bind(3, block.version as i64)?;
+        statement.bind(4, block.difficulty as i64)?;
+        statement.bind(5, block.random as i64)?;
+        statement.bind(6, block.nonce as i64)?;
+        match &block.transaction {
+            None => { statement.bind(7, "")?; }
+            // Old blocks keep JSON text, new ones are stored in binary
+            Some(transaction) if is_binary(block.version) => {
+                let bytes = encode_transaction(transaction, block.version)
+                    .map_err(|e| sqlite::Error { code: None, message: Some(e.to_string()) })?;
+                statement.bind(7, bytes.as_slice())?;
+            }
+            Some(transaction) => {
+                statement.bind(7, transaction.to_string().as_str())?;
+            }
+        }
+        statement.bind(8, &**block.prev_block_hash)?;
+        statement.bind(9, &**block.hash)?;
+        statement.bind(10, &**block.pub_key)?;
+        statement.bind(11, &**block.signature)?;
+        statement.next()
+    }
+
+    fn add_transaction(&mut self, index: u64, timestamp: i64, t: &Transaction) -> sqlite::Result<State> {
+        let sql = match t.class.as_ref() {
+            // Renewals and transfers are kept as domain changes, that have the same data
+            "domain" | "renew" | "transfer" => SQL_ADD_DOMAIN,
+            "zone" => SQL_ADD_ZONE,
+            _ => return Err(sqlite::Error { code: None, message: Some(format!("Unknown transaction class '{}'", &t.class)) })
+        };
+
+        let mut statement = self.prepare(sql)?;
+        statement.bind(1, index as i64)?;
+        statement.bind(2, timestamp)?;
+        statement.bind(3, &**t.identity)?;
+        statement.bind(4, &**t.confirmation)?;
+        statement.bind(5, t.data.as_ref() as &str)?;
+        statement.bind(6, &**t.pub_key)?;
+        statement.next()
+    }
+
+    fn truncate_tables(&mut self, index: u64, reason: &str) -> sqlite::Result<State> {
+        if self.archive_orphans {
+            let mut statement = self.db.prepare(SQL_ARCHIVE_BLOCKS)?;
+            statement.bind(1, reason)?;
+            statement.bind(2, Utc::now().timestamp())?;
+            statement.bind(3, index as i64)?;
+            statement.next()?;
+        }
+
+        let mut statement = self.db.prepare(SQL_TRUNCATE_BLOCKS)?;
+        statement.bind(1, index as i64)?;
+        statement.next()?;
+
+        let mut statement = self.db.prepare(SQL_TRUNCATE_DOMAINS)?;
+        statement.bind(1, index as i64)?;
+        statement.next()?;
+
+        let mut statement = self.db.prepare(SQL_TRUNCATE_ZONES)?;
+        statement.bind(1, index as i64)?;
+        statement.next()
+    }
+
+    /// Reads the first found block from statement, logging broken ones
+    fn read_block(statement: &mut Statement) -> Option<Block> {
+        while statement.next().unwrap() == State::Row {
+            return match Self::get_block_from_statement(statement) {
+                None => {
+                    error!("Something wrong with block in DB!");
+                    None
+                }
+                Some(block) => Some(block)
+            };
+        }
+        None
+    }
+
+    fn get_block_from_statement(statement: &mut Statement) -> Option<Block> {
+        let index = statement.read::<i64>(0).unwrap() as u64;
+        let timestamp = statement.read::<i64>(1).unwrap();
+        let version = statement.read::<i64>(2).unwrap() as u32;
+        let difficulty = statement.read::<i64>(3).unwrap() as u32;
+        let random = statement.read::<i64>(4).unwrap() as u32;
+        let nonce = statement.read::<i64>(5).unwrap() as u64;
+        let transaction = decode_transaction(&statement.read::<Vec<u8>>(6).unwrap());
+        let prev_block_hash = Bytes::from_bytes(statement.read::<Vec<u8>>(7).unwrap().as_slice());
+        let hash = Bytes::from_bytes(statement.read::<Vec<u8>>(8).unwrap().as_slice());
+        let pub_key = Bytes::from_bytes(statement.read::<Vec<u8>>(9).unwrap().as_slice());
+        let signature = Bytes::from_bytes(statement.read::<Vec<u8>>(10).unwrap().as_slice());
+        Some(Block::from_all_params(index, timestamp, version, difficulty, random, nonce, prev_block_hash, hash, pub_key, signature, transaction))
+    }
+
+    /// Reads a row of domains table
+    fn get_domain_from_statement(statement: &mut Statement) -> IdentityRow {
+        let index = statement.read::<i64>(0).unwrap() as u64;
+        let timestamp = statement.read::<i64>(1).unwrap();
+        let identity = Bytes::from_bytes(&statement.read::<Vec<u8>>(2).unwrap());
+        let confirmation = Bytes::from_bytes(&statement.read::<Vec<u8>>(3).unwrap());
+        let class = String::from("domain");
+        let data = statement.read::<String>(4).unwrap();
+        let pub_key = Bytes::from_bytes(&statement.read::<Vec<u8>>(5).unwrap());
+        IdentityRow { index, timestamp, transaction: Transaction { identity, confirmation, class, data, pub_key } }
+    }
+}
+
+impl BlockStorage for SqliteStorage {
+    fn add_block(&mut self, block: &Block) -> Result<(), String> {
+        self.add_block_row(block).map_err(|e| e.to_string())?;
+        if let Some(transaction) = &block.transaction {
+            self.add_transaction(block.index, block.timestamp, transaction).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn get_block(&self, index: u64) -> Option<Block> {
+        match self.prepare(SQL_GET_BLOCK_BY_ID) {
+            Ok(mut statement) => {
+                statement.bind(1, index as i64).expect("Error in bind");
+                Self::read_block(&mut statement)
+            }
+            Err(_) => {
+                warn!("Can't find requested block {}", index);
+                None
+            }
+        }
+    }
+
+    fn get_last_block(&self) -> Option<Block> {
+        let mut statement = self.prepare(SQL_GET_LAST_BLOCK).ok()?;
+        Self::read_block(&mut statement)
+    }
+
+    fn get_blocks(&self, from: u64, limit: u64, full_only: bool) -> Vec<Block> {
+        let sql = match full_only {
+            true => SQL_GET_FULL_BLOCKS_FROM,
+            false => SQL_GET_BLOCKS_FROM
+        };
+        let mut result = Vec::new();
+        let mut statement = self.prepare(sql).unwrap();
+        statement.bind(1, from.min(MAX) as i64).expect("Error in bind");
+        statement.bind(2, limit.min(MAX) as i64).expect("Error in bind");
+        while let State::Row = statement.next().unwrap() {
+            if let Some(block) = Self::get_block_from_statement(&mut statement) {
+                result.push(block);
+            }
+        }
+        result
+    }
+
+    fn get_last_full_block(&self, before: u64, pub_key: Option<&[u8]>) -> Option<Block> {
+        let mut statement = match pub_key {
+            None => {
+                let mut statement = self.prepare(SQL_GET_LAST_FULL_BLOCK).expect("Unable to prepare");
+                statement.bind(1, before.min(MAX) as i64).expect("Unable to bind");
+                statement
+            }
+            Some(pub_key) => {
+                let mut statement = self.prepare(SQL_GET_LAST_FULL_BLOCK_FOR_KEY).expect("Unable to prepare");
+                statement.bind(1, before.min(MAX) as i64).expect("Unable to bind");
+                statement.bind(2, pub_key).expect("Unable to bind");
+                statement
+            }
+        };
+        Self::read_block(&mut statement)
+    }
+
+    fn get_last_timestamps(&self, before: u64, count: u64) -> Vec<i64> {
+        let mut timestamps = Vec::new();
+        let result = self.prepare(SQL_GET_LAST_TIMESTAMPS).and_then(|mut statement| {
+            statement.bind(1, before.min(MAX) as i64)?;
+            statement.bind(2, count.min(MAX) as i64)?;
+            while let State::Row = statement.next()? {
+                timestamps.push(statement.read::<i64>(0)?);
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            warn!("Can't get timestamps of blocks: {}", e);
+        }
+        timestamps
+    }
+
+    fn count_upgrade_signals(&self, count: u64, flag: u32) -> u64 {
+        let mut statement = self.prepare(SQL_COUNT_UPGRADE_SIGNALS).expect("Error in DB!");
+        statement.bind(1, count.min(MAX) as i64).expect("Error in bind");
+        statement.bind(2, SIGNAL_MARKER_MASK as i64).expect("Error in bind");
+        statement.bind(3, SIGNAL_MARKER as i64).expect("Error in bind");
+        statement.bind(4, flag as i64).expect("Error in bind");
+        match statement.next() {
+            Ok(State::Row) => statement.read::<i64>(0).unwrap_or(0) as u64,
+            _ => 0
+        }
+    }
+
+    fn count_blocks_by_key(&self, from: u64, to: u64, pub_key: &[u8]) -> u64 {
+        let mut statement = self.prepare(SQL_COUNT_BLOCKS_BY_KEY).expect("Error in DB!");
+        statement.bind(1, from.min(MAX) as i64).expect("Error in bind");
+        statement.bind(2, to.min(MAX) as i64).expect("Error in bind");
+        statement.bind(3, pub_key).expect("Error in bind");
+        match statement.next() {
+            Ok(State::Row) => statement.read::<i64>(0).unwrap_or(0) as u64,
+            _ => 0
+        }
+    }
+
+    fn get_block_keys(&self, indexes: &[u64]) -> HashMap<u64, Bytes> {
+        let mut result = HashMap::new();
+        let ids = indexes.iter().map(|index| index.to_string()).collect::<Vec<_>>().join(",");
+        match self.db.prepare(SQL_GET_BLOCK_KEYS_BY_IDS.replace("{}", &ids)) {
+            Ok(mut statement) => {
+                while statement.next().unwrap() == State::Row {
+                    let index = statement.read::<i64>(0).unwrap() as u64;
+                    let pub_key = Bytes::from_bytes(statement.read::<Vec<u8>>(1).unwrap().as_slice());
+                    result.insert(index, pub_key);
+                }
+            }
+            Err(e) => warn!("Can't get keys of blocks {}: {}", &ids, e)
+        }
+        result
+    }
+
+    fn truncate(&mut self, from: u64, reason: &str) -> Result<(), String> {
+        self.truncate_tables(from, reason).map(|_| ()).map_err(|e| e.to_string())
+    }
+
+    fn get_orphans(&self, from: u64, to: u64) -> Vec<OrphanBlock> {
+        let mut result = Vec::new();
+        if !self.archive_orphans {
+            return result;
+        }
+        let mut statement = self.prepare(SQL_GET_ORPHANS).unwrap();
+        statement.bind(1, from.min(MAX) as i64).expect("Error in bind");
+        statement.bind(2, to.min(MAX) as i64).expect("Error in bind");
+        while let State::Row = statement.next().unwrap() {
+            if let Some(block) = Self::get_block_from_statement(&mut statement) {
+                let reason = statement.read::<String>(11).unwrap();
+                let archived = statement.read::<i64>(12).unwrap();
+                result.push(OrphanBlock { block, reason, archived });
+            }
+        }
+        result
+    }
+
+    fn get_identity_owner(&self, height: u64, identity: &Bytes, zone: bool) -> Option<Bytes> {
+        let sql = match zone {
+            true => SQL_GET_ZONE_PUBLIC_KEY_BY_ID,
+            false => SQL_GET_DOMAIN_PUBLIC_KEY_BY_ID
+        };
+        let mut statement = self.prepare(sql).unwrap();
+        statement.bind(1, height.min(MAX) as i64).expect("Error in bind");
+        statement.bind(2, &***identity).expect("Error in bind");
+        if let State::Row = statement.next().unwrap() {
+            return Some(Bytes::from_bytes(&statement.read::<Vec<u8>>(0).unwrap()));
+        }
+        None
+    }
+
+    fn get_domain_changes(&self, height: u64, identity: &Bytes) -> Vec<IdentityRow> {
+        let mut result = Vec::new();
+        let mut statement = self.prepare(SQL_GET_DOMAIN_CHANGES_BY_ID).unwrap();
+        statement.bind(1, height.min(MAX) as i64).expect("Error in bind");
+        statement.bind(2, &***identity).expect("Error in bind");
+        while let State::Row = statement.next().unwrap() {
+            result.push(Self::get_domain_from_statement(&mut statement));
+        }
+        result
+    }
+
+    fn get_last_domain(&self, identity: &Bytes) -> Option<IdentityRow> {
+        let mut statement = self.prepare(SQL_GET_DOMAIN_BY_ID).unwrap();
+        statement.bind(1, &***identity).expect("Error in bind");
+        if let State::Row = statement.next().unwrap() {
+            return Some(Self::get_domain_from_statement(&mut statement));
+        }
+        None
+    }
+
+    fn get_domains_by_key(&self, pub_key: &[u8]) -> Vec<IdentityRow> {
+        let mut result = Vec::new();
+        let mut statement = self.prepare(SQL_GET_DOMAINS_BY_KEY).unwrap();
+        statement.bind(1, pub_key).expect("Error in bind");
+        while let State::Row = statement.next().unwrap() {
+            result.push(Self::get_domain_from_statement(&mut statement));
+        }
+        result
+    }
+
+    fn for_each_domain(&self, from: Option<u64>, f: &mut dyn FnMut(&IdentityRow) -> bool) {
+        let mut statement = match from {
+            Some(from) => {
+                let mut statement = self.prepare(SQL_GET_DOMAINS_FROM).unwrap();
+                statement.bind(1, from.min(MAX) as i64).expect("Error in bind");
+                statement
+            }
+            None => self.prepare(SQL_GET_DOMAINS_DESC).unwrap()
+        };
+        while let State::Row = statement.next().unwrap() {
+            if !f(&Self::get_domain_from_statement(&mut statement)) {
+                break;
+            }
+        }
+    }
+
+    fn get_zones(&self) -> Vec<String> {
+        let mut result = Vec::new();
+        match self.prepare(SQL_GET_ZONES) {
+            Ok(mut statement) => {
+                while statement.next().unwrap() == State::Row {
+                    result.push(statement.read::<String>(0).unwrap());
+                }
+            }
+            Err(e) => {
+                warn!("Can't get zones from DB {}", e);
+            }
+        }
+        result
+    }
+
+    fn get_identities(&self) -> Result<Vec<Bytes>, String> {
+        let mut identities = Vec::new();
+        let mut statement = self.prepare(SQL_GET_IDENTITIES).map_err(|e| e.to_string())?;
+        while let State::Row = statement.next().map_err(|e| e.to_string())? {
+            let identity = statement.read::<Vec<u8>>(0).map_err(|e| e.to_string())?;
+            identities.push(Bytes::from_bytes(&identity));
+        }
+        Ok(identities)
+    }
+
+    fn get_options(&self) -> Vec<(String, String)> {
+        let mut options = Vec::new();
+        if let Ok(mut statement) = self.prepare(SQL_GET_OPTIONS) {
+            while let State::Row = statement.next().unwrap() {
+                let name = statement.read::<String>(0).unwrap();
+                let value = statement.read::<String>(1).unwrap();
+                options.push((name, value));
+            }
+        }
+        options
+    }
+
+    fn set_option(&self, name: &str, value: Option<&str>) -> Result<(), String> {
+        self.prepare(SQL_DELETE_OPTION)
+            .and_then(|mut statement| {
+                statement.bind(1, name)?;
+                statement.next()
+            })
+            .and_then(|_| match value {
+                None => Ok(State::Done),
+                Some(value) => {
+                    let mut statement = self.prepare(SQL_ADD_OPTION)?;
+                    statement.bind(1, name)?;
+                    statement.bind(2, value)?;
+                    statement.next()
+                }
+            })
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    /// Tables are recreated in one transaction, so the DB is not left half-cleared on errors
+    fn reset(&mut self, origin: &str) -> Result<(), String> {
+        self.statements.clear();
+        self.clear_readers();
+        self.db.execute("BEGIN;").map_err(|e| e.to_string())?;
+        let result = self.db.execute(SQL_DROP_TABLES)
+            .and_then(|_| self.create_tables())
+            .and_then(|_| {
+                let mut statement = self.db.prepare(SQL_ADD_OPTION)?;
+                statement.bind(1, "origin")?;
+                statement.bind(2, origin)?;
+                statement.next().map(|_| ())
+            });
+        match result {
+            Ok(_) => self.db.execute("COMMIT;").map_err(|e| e.to_string()),
+            Err(e) => {
+                let _ = self.db.execute("ROLLBACK;");
+                Err(e.to_string())
+            }
+        }
+    }
+
+    fn recover(&mut self) -> Result<Option<String>, String> {
+        self.statements.clear();
+        self.clear_readers();
+        let mut backup = None;
+        if self.is_in_memory() {
+            self.db = sqlite::open(MEMORY_DB_NAME).map_err(|e| e.to_string())?;
+        } else {
+            // We can't close our connection otherwise, so we switch it to temporary file and back
+            self.db = sqlite::open(TEMP_DB_NAME).map_err(|e| format!("Unable to open temporary DB: {}", e))?;
+            let name = format!("{}.corrupt.{}", &self.db_name, Utc::now().timestamp());
+            fs::rename(&self.db_name, &name).map_err(|e| format!("Unable to move corrupted database aside! {}", e))?;
+            backup = Some(name);
+            self.db = sqlite::open(&self.db_name).map_err(|e| e.to_string())?;
+            let _ = fs::remove_file(Path::new(TEMP_DB_NAME));
+            self.tune_db();
+        }
+        self.create_tables().map_err(|e| format!("Error creating DB tables: {}", e))?;
+        Ok(backup)
+    }
+
+    fn begin(&mut self) -> Result<(), String> {
+        self.db.execute("BEGIN;").map_err(|e| e.to_string())
+    }
+
+    fn commit(&mut self) -> Result<(), String> {
+        self.db.execute("COMMIT;").map_err(|e| e.to_string())
+    }
+
+    fn rollback(&mut self) -> Result<(), String> {
+        self.db.execute("ROLLBACK;").map_err(|e| e.to_string())
+    }
+
+    fn get_reader(&self) -> Option<Arc<dyn DomainReader>> {
+        self.readers.clone().map(|readers| readers as Arc<dyn DomainReader>)
+    }
+}
+
+/// Read-only connections to the DB file, WAL lets them read while the main connection writes
+pub struct ReadPool {
+    db_name: String,
+    size: usize,
+    state: Mutex<PoolState>,
+    cond_var: Condvar,
+}
+
+struct PoolState {
+    /// Changes when the DB is replaced, older connections are closed when they are returned
+    generation: u64,
+    /// Count of open connections, idle and taken ones
+    open: usize,
+    idle: Vec<Reader>,
+}
+
+/// Read-only connection with its own statements, they are dropped before the connection
+struct Reader {
+    statements: StatementCache,
+    db: Connection,
+    generation: u64,
+}
+
+impl Reader {
+    fn get_last_domain(&self, identity: &Bytes) -> sqlite::Result<Option<IdentityRow>> {
+        let mut statement = self.statements.prepare(&self.db, SQL_GET_DOMAIN_BY_ID)?;
+        statement.bind(1, &***identity)?;
+        match statement.next()? {
+            State::Row => Ok(Some(SqliteStorage::get_domain_from_statement(&mut statement))),
+            State::Done => Ok(None)
+        }
+    }
+}
+
+impl ReadPool {
+    pub fn new(db_name: &str, size: usize) -> Self {
+        let state = PoolState { generation: 0, open: 0, idle: Vec::new() };
+        ReadPool { db_name: db_name.to_owned(), size, state: Mutex::new(state), cond_var: Condvar::new() }
+    }
+
+    /// Takes an idle connection, or opens a new one if there are less than `size` of them, or waits for a free one
+    fn take(&self) -> sqlite::Result<Reader> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(reader) = state.idle.pop() {
+                return Ok(reader);
+            }
+            if state.open < self.size {
+                let db = Connection::open_with_flags(&self.db_name, OpenFlags::new().set_read_only())?;
+                state.open += 1;
+                return Ok(Reader { statements: StatementCache::new(), db, generation: state.generation });
+            }
+            state = self.cond_var.wait(state).unwrap();
+        }
+    }
+
+    fn put(&self, reader: Reader) {
+        let mut state = self.state.lock().unwrap();
+        if reader.generation == state.generation {
+            state.idle.push(reader);
+        } else {
+            state.open -= 1;
+        }
+        self.cond_var.notify_one();
+    }
+
+    fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        let idle = state.idle.len();
+        state.generation += 1;
+        state.open -= idle;
+        state.idle.clear();
+        self.cond_var.notify_all();
+    }
+}
+
+impl DomainReader for ReadPool {
+    fn get_last_domain(&self, identity: &Bytes) -> Result<Option<IdentityRow>, String> {
+        let reader = self.take().map_err(|e| format!("Unable to open DB for reading: {}", e))?;
+        let result = reader.get_last_domain(identity).map_err(|e| e.to_string());
+        self.put(reader);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blockchain::chain::tests::test_db;
+
+    use super::*;
+
+    #[test]
+    fn readers_see_the_same_domains() {
+        let storage = SqliteStorage::open(&test_db("readers_see_the_same_domains"), false, 2).unwrap();
+        let reader = storage.get_reader().unwrap();
+        let mut rows = Vec::new();
+        storage.for_each_domain(None, &mut |row| {
+            rows.push(row.clone());
+            rows.len() < 10
+        });
+        assert!(!rows.is_empty());
+        for row in rows {
+            let identity = &row.transaction.identity;
+            let expected = storage.get_last_domain(identity).unwrap();
+            let found = reader.get_last_domain(identity).unwrap().unwrap();
+            assert_eq!((expected.index, expected.transaction), (found.index, found.transaction));
+        }
+        assert!(reader.get_last_domain(&Bytes::from_bytes(&[0u8; 32])).unwrap().is_none());
+
+        let memory = SqliteStorage::open(MEMORY_DB_NAME, false, 2).unwrap();
+        assert!(memory.get_reader().is_none());
+    }
+}
@@ -3,7 +3,9 @@ use std::fmt;
 use serde::{Deserialize, Serialize, Serializer};
 use serde::ser::SerializeStruct;
 
+use crate::blockchain::delegation::Delegation;
 use crate::blockchain::hash_utils::*;
+use crate::blockchain::transfer::{DomainTransfer, Handover};
 use crate::bytes::Bytes;
 use crate::dns::protocol::DnsRecord;
 use std::fmt::{Display, Formatter};
@@ -69,6 +71,12 @@ impl Transaction {
         match what {
             None => { TransactionType::Signing }
             Some(transaction) => {
+                if transaction.class == "renew" {
+                    return TransactionType::Renewal;
+                }
+                if transaction.class == "transfer" {
+                    return TransactionType::Transfer;
+                }
                 if let Some(_) = transaction.get_domain_data() {
                     return TransactionType::Domain;
                 }
@@ -110,6 +118,8 @@ pub enum TransactionType {
     Signing,
     Domain,
     Zone,
+    Renewal,
+    Transfer,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -119,12 +129,55 @@ pub struct DomainData {
     pub records: Vec<DnsRecord>,
     pub contacts: Vec<ContactsData>,
     #[serde(default)]
-    pub owners: Vec<Bytes>
+    pub owners: Vec<Bytes>,
+    /// Token from the owner, if this data is mined by another key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delegation: Option<Delegation>,
+    /// Offer of the seller accepted by the buyer, if this data is mined by the buyer to get the domain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transfer: Option<DomainTransfer>,
+    /// Gift of the domain from the owner, only in "transfer" transactions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handover: Option<Handover>
 }
 
 impl DomainData {
     pub fn new(domain: Bytes, zone: String, records: Vec<DnsRecord>, contacts: Vec<ContactsData>, owners: Vec<Bytes>) -> Self {
-        Self { domain, zone, records, contacts, owners }
+        Self { domain, zone, records, contacts, owners, delegation: None, transfer: None, handover: None }
+    }
+
+    /// Compares this (current) data with the new one, the order of records doesn't matter.
+    /// Records are compared as JSON, because equality of `DnsRecord` ignores TTL.
+    pub fn diff(&self, new: &DomainData) -> RecordsDiff {
+        let key = |record: &DnsRecord| serde_json::to_string(record).unwrap_or_default();
+        let mut added: Vec<(String, &DnsRecord)> = new.records.iter().map(|r| (key(r), r)).collect();
+        let mut removed = Vec::new();
+        for record in &self.records {
+            let record_key = key(record);
+            match added.iter().position(|(k, _)| k == &record_key) {
+                Some(pos) => { added.remove(pos); }
+                None => removed.push(record.clone())
+            }
+        }
+        let added = added.into_iter().map(|(_, r)| r.clone()).collect();
+        let other = self.contacts != new.contacts || self.owners != new.owners;
+        RecordsDiff { added, removed, other }
+    }
+}
+
+/// Difference between current data of a domain and the new one, to be shown before mining an update
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct RecordsDiff {
+    pub added: Vec<DnsRecord>,
+    pub removed: Vec<DnsRecord>,
+    /// Contacts or owners have changed
+    pub other: bool,
+}
+
+impl RecordsDiff {
+    /// Nothing is changed, mining such update is a waste of time
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && !self.other
     }
 }
 
@@ -154,3 +207,31 @@ impl Display for ContactsData {
         f.write_str(&format!("{}: {}", self.name, self.value))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::dns::protocol::TransientTtl;
+
+    use super::*;
+
+    #[test]
+    fn records_diff() {
+        let a = DnsRecord::A { domain: String::from("www.test.ygg"), addr: "10.0.0.1".parse().unwrap(), ttl: TransientTtl(300) };
+        let txt = DnsRecord::TXT { domain: String::from("test.ygg"), data: String::from("hello"), ttl: TransientTtl(300) };
+        let current = DomainData::new(Bytes::default(), String::from("ygg"), vec![a.clone(), txt.clone()], vec![], vec![]);
+
+        let same = DomainData::new(Bytes::default(), String::from("ygg"), vec![txt.clone(), a.clone()], vec![], vec![]);
+        assert!(current.diff(&same).is_empty());
+
+        let new_ttl = DnsRecord::A { domain: String::from("www.test.ygg"), addr: "10.0.0.1".parse().unwrap(), ttl: TransientTtl(60) };
+        let changed = DomainData::new(Bytes::default(), String::from("ygg"), vec![new_ttl.clone()], vec![], vec![]);
+        let diff = current.diff(&changed);
+        assert_eq!(vec![new_ttl], diff.added);
+        assert_eq!(vec![a, txt], diff.removed);
+        assert!(!diff.other);
+
+        let contacts = vec![ContactsData { name: String::from("email"), value: String::from("admin@test.ygg") }];
+        let with_contacts = DomainData::new(Bytes::default(), String::from("ygg"), current.records.clone(), contacts, vec![]);
+        assert!(current.diff(&with_contacts).other);
+    }
+}
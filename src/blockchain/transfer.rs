@@ -0,0 +1,184 @@
+//! Sales of domains in two steps: the seller signs an offer to the key of the buyer,
+//! and the buyer signs its acceptance and mines them both in one block, that makes the buyer the owner.
+//! Nobody has to go first: the seller can't take the domain back after the block, and the buyer can't get it without the offer.
+//! Domains can also be given away in one step, by a handover signed by the owner and mined in a "transfer" block.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Bytes, Keystore};
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SaleOffer {
+    /// Hash of the domain name, blocks don't have names
+    pub identity: Bytes,
+    pub seller: Bytes,
+    pub buyer: Bytes,
+    /// Index of the block with the last change of the domain, any later change cancels the offer
+    pub block: u64,
+    pub expires: i64,
+    pub signature: Bytes,
+}
+
+impl SaleOffer {
+    /// Signs the offer by the key of the seller
+    pub fn create(keystore: &Keystore, identity: Bytes, buyer: Bytes, block: u64, expires: i64) -> Self {
+        let mut result = SaleOffer { identity, seller: keystore.get_public(), buyer, block, expires, signature: Bytes::default() };
+        result.signature = Bytes::from_bytes(&keystore.sign(result.get_statement().as_bytes()));
+        result
+    }
+
+    /// Gets the text that is signed by the seller
+    pub fn get_statement(&self) -> String {
+        format!("Key {} offers domain {} changed at block {} to key {} until {}",
+                self.seller.to_string(), self.identity.to_string(), self.block, self.buyer.to_string(), self.expires)
+    }
+
+    pub fn check_signature(&self) -> bool {
+        Keystore::check(self.get_statement().as_bytes(), &self.seller, &self.signature)
+    }
+}
+
+/// Offer with acceptance of the buyer, it is put to the domain data of the block that transfers the domain
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct DomainTransfer {
+    pub offer: SaleOffer,
+    pub acceptance: Bytes,
+}
+
+impl DomainTransfer {
+    /// Signs the acceptance by the key of the buyer
+    pub fn accept(keystore: &Keystore, offer: SaleOffer) -> Result<Self, String> {
+        if offer.buyer != keystore.get_public() {
+            return Err(String::from("Offer is for another key"));
+        }
+        if !offer.check_signature() {
+            return Err(String::from("Wrong signature of the offer"));
+        }
+        let mut result = DomainTransfer { offer, acceptance: Bytes::default() };
+        result.acceptance = Bytes::from_bytes(&keystore.sign(result.get_statement().as_bytes()));
+        Ok(result)
+    }
+
+    /// Gets the text that is signed by the buyer
+    pub fn get_statement(&self) -> String {
+        format!("Key {} accepts offer {}", self.offer.buyer.to_string(), self.offer.signature.to_string())
+    }
+
+    /// Checks that the block from `buyer` with this transfer can move the domain with `identity` from `owner`.
+    /// `last_block` is the index of the last change of the domain before this block, `timestamp` is the time of this block.
+    pub fn verify(&self, identity: &Bytes, owner: &Bytes, buyer: &Bytes, last_block: u64, timestamp: i64) -> Result<(), String> {
+        let offer = &self.offer;
+        if &offer.identity != identity {
+            return Err(String::from("Offer is for another domain"));
+        }
+        if &offer.seller != owner {
+            return Err(String::from("Offer is not from the owner"));
+        }
+        if &offer.buyer != buyer {
+            return Err(String::from("Offer is for another key"));
+        }
+        if offer.block != last_block {
+            return Err(String::from("Domain has changed after the offer"));
+        }
+        if timestamp > offer.expires {
+            return Err(String::from("Offer has expired"));
+        }
+        if !offer.check_signature() {
+            return Err(String::from("Wrong signature of the offer"));
+        }
+        if !Keystore::check(self.get_statement().as_bytes(), &offer.buyer, &self.acceptance) {
+            return Err(String::from("Wrong signature of the acceptance"));
+        }
+        Ok(())
+    }
+}
+
+/// Gift of a domain to another key, it is put to the unchanged domain data of a "transfer" block
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Handover {
+    /// Hash of the domain name, blocks don't have names
+    pub identity: Bytes,
+    pub owner: Bytes,
+    pub new_owner: Bytes,
+    /// Index of the block with the last change of the domain, so that the handover can't be mined again later
+    pub block: u64,
+    pub signature: Bytes,
+}
+
+impl Handover {
+    /// Signs the handover by the key of the owner
+    pub fn create(keystore: &Keystore, identity: Bytes, new_owner: Bytes, block: u64) -> Self {
+        let mut result = Handover { identity, owner: keystore.get_public(), new_owner, block, signature: Bytes::default() };
+        result.signature = Bytes::from_bytes(&keystore.sign(result.get_statement().as_bytes()));
+        result
+    }
+
+    /// Gets the text that is signed by the owner
+    pub fn get_statement(&self) -> String {
+        format!("Key {} gives domain {} changed at block {} to key {}",
+                self.owner.to_string(), self.identity.to_string(), self.block, self.new_owner.to_string())
+    }
+
+    /// Checks that this handover moves the domain with `identity` from `owner`, and that the domain was last changed at `last_block`
+    pub fn verify(&self, identity: &Bytes, owner: &Bytes, last_block: u64) -> Result<(), String> {
+        if &self.identity != identity {
+            return Err(String::from("Handover is for another domain"));
+        }
+        if &self.owner != owner {
+            return Err(String::from("Handover is not from the owner"));
+        }
+        if self.new_owner == self.owner {
+            return Err(String::from("Domain is given to the same key"));
+        }
+        if self.block != last_block {
+            return Err(String::from("Domain has changed after the handover"));
+        }
+        if !Keystore::check(self.get_statement().as_bytes(), &self.owner, &self.signature) {
+            return Err(String::from("Wrong signature of the handover"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blockchain::hash_utils::hash_identity;
+
+    use super::*;
+
+    #[test]
+    fn transfer() {
+        let seller = Keystore::new();
+        let buyer = Keystore::new();
+        let identity = hash_identity("test.ygg", None);
+        let offer = SaleOffer::create(&seller, identity.clone(), buyer.get_public(), 100, 1600001000);
+        assert!(DomainTransfer::accept(&seller, offer.clone()).is_err());
+
+        let transfer = DomainTransfer::accept(&buyer, offer).unwrap();
+        let (owner, key) = (seller.get_public(), buyer.get_public());
+        assert!(transfer.verify(&identity, &owner, &key, 100, 1600000000).is_ok());
+        assert!(transfer.verify(&identity, &owner, &key, 101, 1600000000).is_err());
+        assert!(transfer.verify(&identity, &owner, &key, 100, 1600002000).is_err());
+        assert!(transfer.verify(&identity, &key, &key, 100, 1600000000).is_err());
+
+        let mut cheaper = transfer.clone();
+        cheaper.offer.expires = 1700000000;
+        assert!(cheaper.verify(&identity, &owner, &key, 100, 1600002000).is_err());
+    }
+
+    #[test]
+    fn handover() {
+        let owner = Keystore::new();
+        let receiver = Keystore::new();
+        let identity = hash_identity("test.ygg", None);
+        let handover = Handover::create(&owner, identity.clone(), receiver.get_public(), 100);
+        assert!(handover.verify(&identity, &owner.get_public(), 100).is_ok());
+        assert!(handover.verify(&identity, &owner.get_public(), 101).is_err());
+        assert!(handover.verify(&identity, &receiver.get_public(), 100).is_err());
+        assert!(handover.verify(&hash_identity("other.ygg", None), &owner.get_public(), 100).is_err());
+
+        let mut stolen = handover.clone();
+        stolen.new_owner = Keystore::new().get_public();
+        assert!(stolen.verify(&identity, &owner.get_public(), 100).is_err());
+    }
+}
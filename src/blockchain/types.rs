@@ -1,3 +1,8 @@
+use serde::Serialize;
+
+use crate::{Block, Bytes};
+use crate::blockchain::transaction::DomainData;
+
 /// Represents a result of block check on block's arrival
 #[derive(PartialEq)]
 pub enum BlockQuality {
@@ -9,6 +14,13 @@ pub enum BlockQuality {
     Fork,
 }
 
+/// Our blocks from `index` that were replaced by a branch with more work, see `Chain::reorg`
+pub struct Reorg {
+    pub index: u64,
+    pub removed: Vec<Block>,
+    pub added: Vec<Block>,
+}
+
 #[derive(Debug)]
 pub enum MineResult {
     Fine,
@@ -24,14 +36,66 @@ pub enum MineResult {
 pub struct Options {
     pub origin: String,
     pub version: u32,
+    /// Last block verified by unfinished `check_chain`, 0 if it has finished
+    pub checked: u64,
 }
 
 impl Options {
     pub fn new(origin: String, version: u32) -> Self {
-        Options { origin, version }
+        Options { origin, version, checked: 0 }
     }
 
     pub fn empty() -> Self {
-        Options { origin: String::new(), version: 0 }
+        Options { origin: String::new(), version: 0, checked: 0 }
+    }
+}
+/// State of the check of blocks in DB, it is done by parts with `Chain::continue_check`
+#[derive(Debug)]
+pub struct ChainCheck {
+    /// Next block to check
+    pub next: u64,
+    /// Last block to check, newer blocks are checked on arrival
+    pub height: u64,
+    pub last_block: Option<Block>,
+    pub last_full_block: Option<Block>,
+}
+
+impl ChainCheck {
+    /// How many blocks are checked already, for progress
+    pub fn checked(&self) -> u64 {
+        self.next.saturating_sub(1).min(self.height)
     }
-}
\ No newline at end of file
+}
+
+/// Exact block that would be mined for some transaction, given current chain state
+#[derive(Debug, Serialize)]
+pub struct BlockTemplate {
+    pub index: u64,
+    pub prev_block_hash: Bytes,
+    pub difficulty: u32,
+    /// If true, the miner will have to wait until the last full block is signed
+    pub waiting_signers: bool,
+    pub block: Block,
+    /// Serialized block, exactly as it gets hashed (with zero nonce)
+    pub bytes: Bytes,
+}
+
+/// A block that was removed from the chain, with the reason of removal
+#[derive(Debug, Serialize)]
+pub struct OrphanBlock {
+    pub block: Block,
+    pub reason: String,
+    /// Timestamp of removal
+    pub archived: i64,
+}
+
+/// Domain transaction as it is kept in DB, for explorers
+#[derive(Debug, Serialize)]
+pub struct DomainEntry {
+    /// Index of the block with this transaction
+    pub index: u64,
+    pub timestamp: i64,
+    pub identity: Bytes,
+    pub pub_key: Bytes,
+    pub data: DomainData,
+}
@@ -0,0 +1,140 @@
+//! Test vectors for other implementations and auditors: hashes of identities, encrypted names, and bytes, hashes and
+//! signatures of sample blocks, made by the same code that checks blocks. Everything is made from fixed keys and data,
+//! so the vectors are the same on every run, and any difference in another implementation shows a consensus mismatch.
+
+use serde::Serialize;
+
+use crate::{Block, Bytes, CLASS_DOMAIN, Keystore, Transaction};
+use crate::blockchain::encoding::encode_transaction;
+use crate::blockchain::hash_utils::{blakeout_data, hash_difficulty, hash_identity, key_hash_difficulty};
+use crate::blockchain::transaction::DomainData;
+use crate::commons::constants::{BINARY_RULES_VERSION, CHAIN_VERSION};
+
+/// Secret key of sample blocks, it is not used anywhere else
+const SECRET: [u8; 32] = [7u8; 32];
+const NAMES: [&str; 3] = ["test.ygg", "xn--80aswg.ygg", "a-b.c.anon"];
+
+#[derive(Serialize)]
+pub struct TestVectors {
+    pub version: String,
+    pub key: KeyVector,
+    pub identities: Vec<IdentityVector>,
+    pub blocks: Vec<BlockVector>,
+}
+
+#[derive(Serialize)]
+pub struct KeyVector {
+    pub secret: Bytes,
+    pub public: Bytes,
+}
+
+#[derive(Serialize)]
+pub struct IdentityVector {
+    pub name: String,
+    /// Hash of the name, the identity of domain in transactions
+    pub identity: Bytes,
+    /// Hash of the name with the key of owner
+    pub confirmation: Bytes,
+    /// Name encrypted by the key, with first 12 bytes of confirmation as nonce
+    pub encrypted_name: Bytes,
+}
+
+#[derive(Serialize)]
+pub struct BlockVector {
+    pub description: String,
+    pub block: Block,
+    /// Transaction as it is kept in DB
+    pub stored_transaction: Option<Bytes>,
+    /// Bytes that are hashed, with empty hash and signature
+    pub hash_bytes: Bytes,
+    pub hash: Bytes,
+    pub hash_difficulty: u32,
+    pub key_hash_difficulty: u32,
+    /// Bytes that are signed, with the hash and empty signature
+    pub sign_bytes: Bytes,
+    pub signature: Bytes,
+}
+
+pub fn make_test_vectors() -> TestVectors {
+    let keystore = Keystore::from_random_bytes(&SECRET);
+    let pub_key = keystore.get_public();
+    let key = KeyVector { secret: Bytes::from_bytes(&SECRET), public: pub_key.clone() };
+    let identities = NAMES.iter()
+        .map(|name| {
+            let confirmation = hash_identity(name, Some(&pub_key));
+            IdentityVector {
+                name: name.to_string(),
+                identity: hash_identity(name, None),
+                encrypted_name: keystore.encrypt(name.as_bytes(), &confirmation.as_slice()[..12]),
+                confirmation
+            }
+        })
+        .collect();
+
+    let mut data = DomainData::new(Bytes::default(), String::from("ygg"), Vec::new(), Vec::new(), vec![pub_key.clone()]);
+    let confirmation = hash_identity(NAMES[0], Some(&pub_key));
+    data.domain = keystore.encrypt(NAMES[0].as_bytes(), &confirmation.as_slice()[..12]);
+    let transaction = Transaction::from_str(NAMES[0].to_owned(), CLASS_DOMAIN.to_owned(), serde_json::to_string(&data).unwrap(), pub_key.clone());
+
+    let mut signing = Block::new(None, pub_key.clone(), Bytes::from_bytes(&[1u8; 32]), 16);
+    signing.index = 2;
+    signing.timestamp = 1600000000;
+    signing.version = CHAIN_VERSION;
+    let mut domain = Block::new(Some(transaction), pub_key.clone(), Bytes::from_bytes(&[2u8; 32]), 24);
+    domain.index = 3;
+    domain.timestamp = 1600000060;
+    domain.random = 12345;
+    domain.nonce = 67890;
+    domain.version = CHAIN_VERSION;
+    let mut binary = domain.clone();
+    binary.version = BINARY_RULES_VERSION;
+
+    let blocks = vec![
+        make_block_vector("Signing block, JSON encoding", signing, &keystore),
+        make_block_vector("Domain block, JSON encoding", domain, &keystore),
+        make_block_vector("Domain block, binary encoding", binary, &keystore),
+    ];
+    TestVectors { version: env!("CARGO_PKG_VERSION").to_owned(), key, identities, blocks }
+}
+
+/// Hashes and signs the block like miners do, without proof of work
+fn make_block_vector(description: &str, mut block: Block, keystore: &Keystore) -> BlockVector {
+    block.hash = Bytes::default();
+    block.signature = Bytes::default();
+    let hash_bytes = block.as_bytes();
+    block.hash = blakeout_data(&hash_bytes);
+    let sign_bytes = block.as_bytes();
+    block.signature = Bytes::from_bytes(&keystore.sign(&sign_bytes));
+    BlockVector {
+        description: description.to_owned(),
+        stored_transaction: block.transaction.as_ref().and_then(|transaction| encode_transaction(transaction, block.version).ok()).map(|bytes| Bytes::from_bytes(&bytes)),
+        hash_bytes: Bytes::from_bytes(&hash_bytes),
+        hash_difficulty: hash_difficulty(block.hash.as_slice()),
+        key_hash_difficulty: key_hash_difficulty(block.hash.as_slice()),
+        hash: block.hash.clone(),
+        sign_bytes: Bytes::from_bytes(&sign_bytes),
+        signature: block.signature.clone(),
+        block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::hash_utils::{check_block_hash, check_block_signature};
+
+    #[test]
+    fn vectors_are_stable() {
+        let vectors = make_test_vectors();
+        let json = serde_json::to_string(&vectors).unwrap();
+        assert_eq!(json, serde_json::to_string(&make_test_vectors()).unwrap());
+        assert_eq!(NAMES.len(), vectors.identities.len());
+        for vector in &vectors.blocks {
+            assert!(check_block_hash(&vector.block));
+            assert!(check_block_signature(&vector.block));
+        }
+        let identity = &vectors.identities[0];
+        let decrypted = Keystore::from_random_bytes(&SECRET).decrypt(identity.encrypted_name.as_slice(), &identity.confirmation.as_slice()[..12]);
+        assert_eq!(identity.name.as_bytes(), decrypted.as_slice());
+    }
+}
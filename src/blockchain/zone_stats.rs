@@ -0,0 +1,104 @@
+//! Registration statistics of a zone, for its owners to see how their namespace is adopted.
+//! They are counted from domain transactions: the first one of every identity is a new registration,
+//! and a domain is active until its lifetime passes after its last change or renewal.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::Bytes;
+use crate::blockchain::types::DomainEntry;
+
+/// How many last weeks are shown
+pub const STATS_WEEKS: usize = 12;
+const WEEK: i64 = 7 * 86400;
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct WeekStats {
+    /// Start of the week, they are counted back from now
+    pub from: i64,
+    /// Domains that were registered in this week
+    pub new_domains: u64,
+    /// All domain transactions in this week, with changes of records and renewals
+    pub changes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ZoneStats {
+    pub zone: String,
+    /// All domains ever registered in the zone
+    pub domains: u64,
+    pub active: u64,
+    pub expired: u64,
+    /// Last weeks, the oldest first
+    pub weeks: Vec<WeekStats>,
+}
+
+/// Counts statistics of the zone from its domain transactions, they must be in order of blocks
+pub fn get_zone_stats(zone: &str, entries: &[DomainEntry], now: i64, domain_lifetime: i64, weeks: usize) -> ZoneStats {
+    // First and last transaction time of every domain
+    let mut times: HashMap<&Bytes, (i64, i64)> = HashMap::new();
+    for entry in entries {
+        times.entry(&entry.identity)
+            .and_modify(|(_, last)| *last = entry.timestamp)
+            .or_insert((entry.timestamp, entry.timestamp));
+    }
+    let active = times.values().filter(|(_, last)| last + domain_lifetime > now).count() as u64;
+
+    let mut result: Vec<WeekStats> = (0..weeks as i64).rev()
+        .map(|week| WeekStats { from: now - (week + 1) * WEEK, new_domains: 0, changes: 0 })
+        .collect();
+    let get_week = |timestamp: i64| -> Option<usize> {
+        if timestamp > now {
+            return None;
+        }
+        let back = ((now - timestamp) / WEEK) as usize;
+        (back < weeks).then(|| weeks - 1 - back)
+    };
+    for entry in entries {
+        if let Some(week) = get_week(entry.timestamp) {
+            result[week].changes += 1;
+        }
+    }
+    for (first, _) in times.values() {
+        if let Some(week) = get_week(*first) {
+            result[week].new_domains += 1;
+        }
+    }
+
+    let domains = times.len() as u64;
+    ZoneStats { zone: zone.to_owned(), domains, active, expired: domains - active, weeks: result }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::transaction::DomainData;
+
+    fn make_entry(index: u64, timestamp: i64, id: u8) -> DomainEntry {
+        let data = DomainData::new(Bytes::default(), String::from("ygg"), Vec::new(), Vec::new(), Vec::new());
+        DomainEntry { index, timestamp, identity: Bytes::from_bytes(&[id; 32]), pub_key: Bytes::default(), data }
+    }
+
+    #[test]
+    fn counts_weeks() {
+        let now = 100 * WEEK;
+        let lifetime = 10 * WEEK;
+        let entries = vec![
+            // Registered long ago and expired
+            make_entry(1, now - 20 * WEEK, 1),
+            // Registered long ago and renewed in this week
+            make_entry(2, now - 15 * WEEK, 2),
+            make_entry(3, now - 3 * WEEK - 10, 3),
+            make_entry(4, now - 100, 2),
+            make_entry(5, now - 50, 4),
+        ];
+        let stats = get_zone_stats("ygg", &entries, now, lifetime, 4);
+        assert_eq!(4, stats.domains);
+        assert_eq!(3, stats.active);
+        assert_eq!(1, stats.expired);
+        assert_eq!(vec![now - 4 * WEEK, now - 3 * WEEK, now - 2 * WEEK, now - WEEK], stats.weeks.iter().map(|w| w.from).collect::<Vec<i64>>());
+        assert_eq!(vec![1, 0, 0, 1], stats.weeks.iter().map(|w| w.new_domains).collect::<Vec<u64>>());
+        assert_eq!(vec![1, 0, 0, 2], stats.weeks.iter().map(|w| w.changes).collect::<Vec<u64>>());
+    }
+}
@@ -0,0 +1,57 @@
+//! Additional chains with other origins, served by the same node along with the main one.
+//! Every chain has its own DB and P2P network, and DNS resolver looks for domains in all of them.
+
+use std::sync::{Arc, Mutex};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use crate::{Chain, Context, Network, Settings};
+use crate::event::Event;
+
+/// Context of additional chain with its name from settings
+pub struct ExtraChainContext {
+    pub name: String,
+    pub context: Arc<Mutex<Context>>,
+}
+
+/// Opens DBs of additional chains and starts their networks, they are stopped along with the main one
+pub fn start_chains(context: &Arc<Mutex<Context>>, settings: &Settings) -> Vec<ExtraChainContext> {
+    let mut chains: Vec<ExtraChainContext> = Vec::new();
+    for extra in &settings.chains {
+        if extra.name.is_empty() || chains.iter().any(|chain| chain.name == extra.name) {
+            error!("Additional chain must have a unique name, skipping chain with DB '{}'", &extra.db_file);
+            continue;
+        }
+        if extra.net.listen == settings.net.listen {
+            error!("Chain '{}' must listen on other address than the main one, skipping it", &extra.name);
+            continue;
+        }
+        let settings = settings.for_chain(extra);
+        info!("Starting chain '{}' with origin '{}'", &extra.name, &settings.origin);
+        let mut chain = Chain::new(&settings, &extra.db_file);
+        chain.check_chain(settings.check_blocks);
+        let app_version = context.lock().unwrap().app_version.clone();
+        let chain_context = Arc::new(Mutex::new(Context::new(app_version, settings, None, chain)));
+        let mut network = Network::new(Arc::clone(&chain_context));
+        if let Err(e) = network.start() {
+            error!("Error starting network of chain '{}': {}", &extra.name, e);
+            continue;
+        }
+        chains.push(ExtraChainContext { name: extra.name.clone(), context: chain_context });
+    }
+
+    if !chains.is_empty() {
+        let contexts: Vec<Arc<Mutex<Context>>> = chains.iter().map(|chain| Arc::clone(&chain.context)).collect();
+        context.lock().unwrap().bus.register(move |_uuid, e| {
+            if let Event::ActionQuit = e {
+                for context in &contexts {
+                    context.lock().unwrap().bus.post(Event::ActionQuit);
+                }
+                return false;
+            }
+            true
+        });
+    }
+    chains
+}
@@ -0,0 +1,47 @@
+//! Check of blocks in DB in background, so that the node serves DNS and syncs while deep checks take their minutes.
+//! The context is locked only for small batches of blocks, and mining waits until the check is finished.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use crate::{Context, Miner};
+use crate::blockchain::types::ChainCheck;
+use crate::event::Event;
+
+/// How many blocks are checked while the context is locked
+const CHECK_BATCH: u64 = 100;
+/// Pause between batches, to let other threads use the context
+const CHECK_PAUSE_MS: u64 = 10;
+
+/// Continues the check prepared by `Chain::start_check` in a separate thread, posting `ChainCheckProgress` events
+pub fn start_chain_check(context: &Arc<Mutex<Context>>, miner: &Arc<Mutex<Miner>>, mut check: ChainCheck) {
+    // Paused by the user before, we don't resume it after the check
+    let paused = miner.lock().unwrap().is_paused();
+    if !paused {
+        miner.lock().unwrap().pause();
+    }
+    let context = Arc::clone(context);
+    let miner = Arc::clone(miner);
+    let _ = thread::Builder::new().name(String::from("chain check")).spawn(move || {
+        loop {
+            let finished = {
+                let mut context = context.lock().unwrap();
+                let finished = context.chain.continue_check(&mut check, CHECK_BATCH);
+                context.bus.post(Event::ChainCheckProgress { checked: check.checked(), height: check.height, finished });
+                finished
+            };
+            if finished {
+                break;
+            }
+            thread::sleep(Duration::from_millis(CHECK_PAUSE_MS));
+        }
+        info!("Blockchain check has finished");
+        if !paused {
+            miner.lock().unwrap().resume();
+        }
+    });
+}
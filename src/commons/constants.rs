@@ -2,6 +2,8 @@ use std::time::Duration;
 
 pub const DB_VERSION: u32 = 0;
 pub const CHAIN_VERSION: u32 = 0;
+/// Rules version of blocks that have their transactions in binary encoding, they are mined when `binary_transactions` is on
+pub const BINARY_RULES_VERSION: u32 = 1;
 
 pub const ZONE_DIFFICULTY: u32 = 28;
 pub const ZONE_MIN_DIFFICULTY: u32 = 22;
@@ -33,17 +35,27 @@ pub const ZONE_MAX_LENGTH: usize = 10;
 pub const MAX_RECONNECTS: u32 = 5;
 
 pub const DB_NAME: &str = "guachain.db";
+/// SQLite name of a DB that is kept only in memory
+pub const MEMORY_DB_NAME: &str = ":memory:";
 pub const CLASS_ZONE: &str = "zone";
 pub const CLASS_DOMAIN: &str = "domain";
+/// Transaction with unchanged data of a domain, that extends its lifetime
+pub const CLASS_RENEW: &str = "renew";
+/// Transaction with unchanged data of a domain, that gives it to another key
+pub const CLASS_TRANSFER: &str = "transfer";
 pub const GIS_DEBUG: &str = "GIS_DEBUG";
 
 /// Public nodes listen port
 pub const LISTEN_PORT: u16 = 46866;
 pub const UI_REFRESH_DELAY_MS: u128 = 600;
 pub const LOG_REFRESH_DELAY_SEC: u64 = 60;
+/// How often addresses of good peers are saved to file
+pub const KNOWN_PEERS_SAVE_SEC: u64 = 300;
 
 pub const POLL_TIMEOUT: Option<Duration> = Some(Duration::from_millis(250));
 pub const MAX_PACKET_SIZE: usize = 2 * 1024 * 1024; // 2 Mb
 pub const MAX_READ_BLOCK_TIME: u128 = 500;
 pub const MAX_IDLE_SECONDS: u64 = 180;
 pub const MAX_NODES: usize = 20;
+/// To how many peers every new transaction of mempool is sent
+pub const MEMPOOL_RELAY_PEERS: usize = 3;
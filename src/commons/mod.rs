@@ -1,9 +1,10 @@
 use std::net::IpAddr;
 use std::num;
 
+#[cfg(feature = "node")]
 use mio::Token;
 use rand::Rng;
-#[cfg(not(target_os = "macos"))]
+#[cfg(all(feature = "node", any(target_os = "windows", target_os = "linux")))]
 use thread_priority::*;
 
 pub use constants::*;
@@ -88,6 +89,17 @@ pub fn random_string(length: usize) -> String {
     result
 }
 
+/// Formats time to wait for humans, like `7h 12m`, the last minute is rounded up
+pub fn format_wait_time(seconds: i64) -> String {
+    let minutes = (seconds.max(0) + 59) / 60;
+    let (days, hours, minutes) = (minutes / 1440, minutes / 60 % 24, minutes % 60);
+    match (days, hours) {
+        (0, 0) => format!("{}m", minutes),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h {}m", days, hours, minutes)
+    }
+}
+
 /// Checks if this IP is from Yggdrasil network
 /// https://yggdrasil-network.github.io
 pub fn is_yggdrasil(addr: &IpAddr) -> bool {
@@ -99,6 +111,7 @@ pub fn is_yggdrasil(addr: &IpAddr) -> bool {
 }
 
 /// Gets new token from old token, mutating the last
+#[cfg(feature = "node")]
 pub fn next(current: &mut Token) -> Token {
     let next = current.0;
     current.0 += 1;
@@ -119,24 +132,25 @@ pub fn is_yggdrasil_record(record: &DnsRecord) -> bool {
         DnsRecord::AAAA { addr, .. } => { return is_yggdrasil(&IpAddr::from(*addr))}
         DnsRecord::SRV { .. } => {}
         DnsRecord::OPT { .. } => {}
+        DnsRecord::TLSA { .. } => {}
     }
     true
 }
 
-#[cfg(target_os = "windows")]
+#[cfg(all(feature = "node", target_os = "windows"))]
 #[allow(unused_variables)]
 pub fn setup_miner_thread(cpu: u32) {
     let _ = set_current_thread_priority(ThreadPriority::Min);
     //let _ = set_current_thread_ideal_processor(IdealProcessor::from(cpu));
 }
 
-#[cfg(target_os = "linux")]
+#[cfg(all(feature = "node", target_os = "linux"))]
 #[allow(unused_variables)]
 pub fn setup_miner_thread(cpu: u32) {
     let _ = set_current_thread_priority(ThreadPriority::Min);
 }
 
-#[cfg(target_os = "macos")]
+#[cfg(all(feature = "node", target_os = "macos"))]
 #[allow(unused_variables)]
 pub fn setup_miner_thread(cpu: u32) {
     // MacOS is not supported by thread_priority crate
@@ -145,7 +159,7 @@ pub fn setup_miner_thread(cpu: u32) {
 #[cfg(test)]
 mod test {
     use std::net::IpAddr;
-    use crate::{check_domain, is_yggdrasil};
+    use crate::{check_domain, format_wait_time, is_yggdrasil};
 
     #[test]
     fn test_check_domain() {
@@ -162,6 +176,14 @@ mod test {
         assert!(!check_domain("ab.c-", true));
     }
 
+    #[test]
+    fn test_format_wait_time() {
+        assert_eq!("0m", format_wait_time(0));
+        assert_eq!("1m", format_wait_time(1));
+        assert_eq!("7h 12m", format_wait_time(7 * 3600 + 12 * 60));
+        assert_eq!("1d 0h 1m", format_wait_time(86400 + 30));
+    }
+
     #[test]
     fn test_is_yggdrasil() {
         let addr: IpAddr = "200::1".parse().unwrap();
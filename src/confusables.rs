@@ -0,0 +1,317 @@
+//! Names that look the same as other names. Domains are kept in ASCII, international ones in punycode (RFC 3492),
+//! so `xn--pple-43d.ygg` is shown by browsers as `аpple.ygg` with Cyrillic 'а', and looks like `apple.ygg`.
+//! Names of domains are encrypted in blocks, so we can't compare them with all names, instead we make names that
+//! look like the given one, and look for their identities in blockchain, see `Chain::find_confusables`.
+
+use serde::Serialize;
+
+/// How many lookalike names are checked for one name
+const MAX_VARIANTS: usize = 256;
+const PUNYCODE_PREFIX: &str = "xn--";
+
+/// Latin letters and digits with characters of other scripts that look like them
+const HOMOGLYPHS: &[(char, &[char])] = &[
+    ('a', &['а', 'α']),
+    ('c', &['с', 'ϲ']),
+    ('d', &['ԁ']),
+    ('e', &['е']),
+    ('g', &['ɡ']),
+    ('h', &['һ']),
+    ('i', &['і', 'ι']),
+    ('j', &['ј']),
+    ('k', &['κ']),
+    ('l', &['1', 'ӏ']),
+    ('o', &['0', 'о', 'ο']),
+    ('p', &['р', 'ρ']),
+    ('q', &['ԛ']),
+    ('s', &['ѕ']),
+    ('v', &['ν']),
+    ('w', &['ԝ']),
+    ('x', &['х', 'χ']),
+    ('y', &['у']),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Script {
+    Latin,
+    Cyrillic,
+    Greek,
+    Other,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Confusable {
+    /// Name as it is in blockchain
+    pub domain: String,
+    /// Name as browsers show it
+    pub unicode: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConfusableReport {
+    pub domain: String,
+    pub unicode: String,
+    /// The name has letters of several scripts, a usual sign of spoofing
+    pub mixed_scripts: bool,
+    /// Domains in blockchain that look like this one
+    pub confusables: Vec<Confusable>,
+}
+
+/// Converts the name to ASCII form for blockchain, labels with other characters are encoded by punycode
+pub fn to_ascii(domain: &str) -> Option<String> {
+    let labels: Option<Vec<String>> = domain.to_lowercase()
+        .split('.')
+        .map(|label| match label.is_ascii() {
+            true => Some(label.to_owned()),
+            false => punycode_encode(label).map(|encoded| format!("{}{}", PUNYCODE_PREFIX, encoded))
+        })
+        .collect();
+    labels.map(|labels| labels.join("."))
+}
+
+/// Converts the name to the form that browsers show, labels that are not valid punycode are left as they are
+pub fn to_unicode(domain: &str) -> String {
+    domain.split('.')
+        .map(|label| match label.strip_prefix(PUNYCODE_PREFIX).and_then(punycode_decode) {
+            Some(decoded) => decoded,
+            None => label.to_owned()
+        })
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+/// Tells if some label of the name has letters of several scripts
+pub fn is_mixed_script(domain: &str) -> bool {
+    to_unicode(domain).split('.').any(|label| {
+        let mut scripts = label.chars().filter_map(get_script);
+        match scripts.next() {
+            Some(first) => scripts.any(|script| script != first),
+            None => false
+        }
+    })
+}
+
+/// Gets names in ASCII form that look like the given one: the first label is made of Latin letters only,
+/// of lookalikes of one script, or with one of its characters replaced. The name itself is not included.
+pub fn get_confusable_variants(domain: &str) -> Vec<String> {
+    let domain = match to_ascii(domain) {
+        Some(domain) => domain,
+        None => return Vec::new()
+    };
+    let unicode = to_unicode(&domain);
+    let (label, rest) = match unicode.find('.') {
+        Some(pos) => (&unicode[..pos], &unicode[pos..]),
+        None => (unicode.as_str(), "")
+    };
+    let skeleton: Vec<char> = label.chars().map(get_skeleton).collect();
+
+    let mut labels: Vec<String> = vec![skeleton.iter().collect()];
+    for script in &[Script::Cyrillic, Script::Greek] {
+        let swapped: String = skeleton.iter()
+            .map(|c| get_homoglyphs(*c).iter().find(|h| get_script(**h) == Some(*script)).cloned().unwrap_or(*c))
+            .collect();
+        labels.push(swapped);
+    }
+    for (pos, c) in skeleton.iter().enumerate() {
+        for homoglyph in get_homoglyphs(*c) {
+            let mut chars = skeleton.clone();
+            chars[pos] = *homoglyph;
+            labels.push(chars.into_iter().collect());
+        }
+    }
+
+    let mut result = Vec::new();
+    for label in labels {
+        if let Some(variant) = to_ascii(&format!("{}{}", label, rest)) {
+            if variant != domain && !result.contains(&variant) {
+                result.push(variant);
+            }
+        }
+        if result.len() >= MAX_VARIANTS {
+            break;
+        }
+    }
+    result
+}
+
+fn get_homoglyphs(c: char) -> &'static [char] {
+    HOMOGLYPHS.iter().find(|(latin, _)| *latin == c).map(|(_, homoglyphs)| *homoglyphs).unwrap_or(&[])
+}
+
+/// Gets the Latin letter that this character looks like, or the character itself
+fn get_skeleton(c: char) -> char {
+    HOMOGLYPHS.iter().find(|(_, homoglyphs)| homoglyphs.contains(&c)).map(|(latin, _)| *latin).unwrap_or(c)
+}
+
+/// Digits and hyphens are in all scripts
+fn get_script(c: char) -> Option<Script> {
+    match c {
+        '0'..='9' | '-' => None,
+        'a'..='z' | 'A'..='Z' | '\u{0250}'..='\u{02AF}' => Some(Script::Latin),
+        '\u{0400}'..='\u{052F}' => Some(Script::Cyrillic),
+        '\u{0370}'..='\u{03FF}' => Some(Script::Greek),
+        _ => Some(Script::Other)
+    }
+}
+
+const BASE: u32 = 36;
+const T_MIN: u32 = 1;
+const T_MAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+
+/// Encodes the label by punycode, without the `xn--` prefix
+pub fn punycode_encode(label: &str) -> Option<String> {
+    let chars: Vec<u32> = label.chars().map(|c| c as u32).collect();
+    let mut output: String = label.chars().filter(|c| c.is_ascii()).collect();
+    let basic = output.len() as u32;
+    if basic > 0 {
+        output.push('-');
+    }
+    let (mut n, mut delta, mut bias, mut handled) = (INITIAL_N, 0u32, INITIAL_BIAS, basic);
+    while (handled as usize) < chars.len() {
+        let m = *chars.iter().filter(|c| **c >= n).min()?;
+        delta = delta.checked_add((m - n).checked_mul(handled + 1)?)?;
+        n = m;
+        for c in &chars {
+            if *c < n {
+                delta = delta.checked_add(1)?;
+            }
+            if *c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = get_threshold(k, bias);
+                    if q < t {
+                        break;
+                    }
+                    output.push(encode_digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(encode_digit(q));
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+        delta = delta.checked_add(1)?;
+        n += 1;
+    }
+    Some(output)
+}
+
+/// Decodes the label from punycode, without the `xn--` prefix
+pub fn punycode_decode(label: &str) -> Option<String> {
+    let (basic, extended) = match label.rfind('-') {
+        Some(pos) => (&label[..pos], &label[pos + 1..]),
+        None => ("", label)
+    };
+    if !basic.is_ascii() || extended.is_empty() {
+        return None;
+    }
+    let mut output: Vec<char> = basic.chars().collect();
+    let (mut n, mut i, mut bias) = (INITIAL_N, 0u32, INITIAL_BIAS);
+    let mut digits = extended.chars();
+    loop {
+        let old_i = i;
+        let mut w = 1u32;
+        let mut k = BASE;
+        let mut first = true;
+        loop {
+            let digit = match (digits.next(), first) {
+                (None, true) => return Some(output.into_iter().collect()),
+                (None, false) => return None,
+                (Some(c), _) => decode_digit(c)?
+            };
+            first = false;
+            i = i.checked_add(digit.checked_mul(w)?)?;
+            let t = get_threshold(k, bias);
+            if digit < t {
+                break;
+            }
+            w = w.checked_mul(BASE - t)?;
+            k += BASE;
+        }
+        let length = output.len() as u32 + 1;
+        bias = adapt(i - old_i, length, old_i == 0);
+        n = n.checked_add(i / length)?;
+        i %= length;
+        output.insert(i as usize, std::char::from_u32(n)?);
+        i += 1;
+    }
+}
+
+fn get_threshold(k: u32, bias: u32) -> u32 {
+    if k <= bias {
+        T_MIN
+    } else if k >= bias + T_MAX {
+        T_MAX
+    } else {
+        k - bias
+    }
+}
+
+fn adapt(delta: u32, points: u32, first: bool) -> u32 {
+    let mut delta = if first { delta / DAMP } else { delta / 2 };
+    delta += delta / points;
+    let mut k = 0;
+    while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+        delta /= BASE - T_MIN;
+        k += BASE;
+    }
+    k + (BASE - T_MIN + 1) * delta / (delta + SKEW)
+}
+
+fn encode_digit(digit: u32) -> char {
+    match digit {
+        0..=25 => (b'a' + digit as u8) as char,
+        _ => (b'0' + (digit - 26) as u8) as char
+    }
+}
+
+fn decode_digit(c: char) -> Option<u32> {
+    match c {
+        'a'..='z' => Some(c as u32 - 'a' as u32),
+        'A'..='Z' => Some(c as u32 - 'A' as u32),
+        '0'..='9' => Some(c as u32 - '0' as u32 + 26),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn punycode() {
+        for (unicode, ascii) in &[("münchen", "mnchen-3ya"), ("пример", "e1afmkfd"), ("bücher", "bcher-kva"), ("аpple", "pple-43d")] {
+            assert_eq!(Some(ascii.to_string()), punycode_encode(unicode));
+            assert_eq!(Some(unicode.to_string()), punycode_decode(ascii));
+        }
+        assert_eq!(None, punycode_decode("abc-"));
+        assert_eq!(None, punycode_decode("99999999999"));
+        assert_eq!(Some(String::from("xn--e1afmkfd.ygg")), to_ascii("Пример.ygg"));
+        assert_eq!("пример.ygg", to_unicode("xn--e1afmkfd.ygg"));
+        assert_eq!("xn--zz.ygg", to_unicode("xn--zz.ygg"));
+    }
+
+    #[test]
+    fn variants() {
+        assert!(is_mixed_script("xn--pple-43d.ygg"));
+        assert!(!is_mixed_script("apple.ygg"));
+        assert!(!is_mixed_script("xn--e1afmkfd.ygg"));
+        assert!(!is_mixed_script("a-1.ygg"));
+
+        let variants = get_confusable_variants("xn--pple-43d.ygg");
+        assert!(variants.contains(&String::from("apple.ygg")));
+        assert!(!variants.contains(&String::from("xn--pple-43d.ygg")));
+        let variants = get_confusable_variants("apple.ygg");
+        assert!(variants.contains(&String::from("xn--pple-43d.ygg")));
+        assert!(variants.contains(&String::from("app1e.ygg")));
+        assert!(variants.iter().all(|variant| variant.ends_with(".ygg")));
+        assert!(get_confusable_variants("zzz.ygg").is_empty());
+    }
+}
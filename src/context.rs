@@ -3,6 +3,10 @@ use crate::event::Event;
 #[allow(unused_imports)]
 use log::{trace, debug, info, warn, error};
 use crate::miner::MinerState;
+use crate::light::LightClient;
+use crate::disk::DiskStatus;
+use crate::blockchain::mempool::Mempool;
+use std::sync::Arc;
 
 pub struct Context {
     pub app_version: String,
@@ -12,11 +16,18 @@ pub struct Context {
     pub x_zones: ExternalZones,
     pub bus: Bus<Event>,
     pub miner_state: MinerState,
+    /// Headers and domain queries in light mode, the chain stays empty then
+    pub light: Option<Arc<LightClient>>,
+    /// Free space on the DB volume, blocks are not accepted when it is critically low
+    pub disk: DiskStatus,
+    /// Transactions from other nodes, that wait for their miners
+    pub mempool: Mempool,
 }
 
 impl Context {
     /// Creating an essential context to work with
     pub fn new(app_version: String, settings: Settings, keystore: Option<Keystore>, chain: Chain) -> Context {
+        let mempool = Mempool::new(settings.mempool.size, settings.mempool.ttl_minutes * 60);
         Context {
             app_version,
             settings,
@@ -24,7 +35,10 @@ impl Context {
             chain,
             x_zones: ExternalZones::new(),
             bus: Bus::new(),
-            miner_state: MinerState { mining: false, full: false }
+            miner_state: MinerState { mining: false, full: false },
+            light: None,
+            disk: DiskStatus::default(),
+            mempool
         }
     }
 
@@ -50,6 +64,13 @@ impl Context {
         self.keystore = keystore;
     }
 
+    /// Finds out if our key signs the last full block, and tells about it when that block is new
+    pub fn update_signers(&mut self) {
+        if let Some((index, signer)) = self.chain.update_signers(&self.keystore) {
+            self.bus.post(Event::SignersChosen { index, signer });
+        }
+    }
+
     pub fn get_chain(&self) -> &Chain {
         &self.chain
     }
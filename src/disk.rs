@@ -0,0 +1,146 @@
+//! Monitoring of free space on the volume with blockchain DB.
+//! SQLite can leave the DB broken if the disk gets full in the middle of a write,
+//! so the node stops accepting blocks a bit earlier, and continues when there is space again.
+
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde::Serialize;
+
+use crate::Context;
+use crate::event::Event;
+use crate::settings::Db;
+
+/// How often to check free space
+const DISK_CHECK_INTERVAL_SEC: u64 = 60;
+const MEGABYTE: u64 = 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiskState {
+    Normal,
+    /// Less free space than `low_space_mb`, operator must free some
+    Low,
+    /// Less free space than `critical_space_mb`, new blocks are not accepted
+    Critical,
+}
+
+impl DiskState {
+    pub fn from_free(free_mb: u64, settings: &Db) -> Self {
+        if free_mb < settings.critical_space_mb {
+            DiskState::Critical
+        } else if free_mb < settings.low_space_mb {
+            DiskState::Low
+        } else {
+            DiskState::Normal
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DiskStatus {
+    pub state: DiskState,
+    /// Free megabytes on the DB volume, absent until the first check, or if it is unknown
+    pub free_mb: Option<u64>,
+}
+
+impl Default for DiskStatus {
+    fn default() -> Self {
+        DiskStatus { state: DiskState::Normal, free_mb: None }
+    }
+}
+
+impl DiskStatus {
+    pub fn is_critical(&self) -> bool {
+        self.state == DiskState::Critical
+    }
+}
+
+/// Checks free space of the volume with DB file periodically, puts the status to context and posts `DiskSpace` events
+pub fn start_disk_monitor(context: &Arc<Mutex<Context>>, db_name: &str, settings: &Db) {
+    let dir = match Path::new(db_name).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => Path::new(".").to_path_buf()
+    };
+    let context = Arc::clone(context);
+    let settings = settings.clone();
+    let _ = thread::Builder::new().name(String::from("disk monitor")).spawn(move || {
+        loop {
+            match get_free_space(&dir) {
+                Ok(free) => update_status(&context, free / MEGABYTE, &settings),
+                Err(e) => {
+                    warn!("Unable to check free space in '{}': {}", dir.display(), e);
+                    return;
+                }
+            }
+            thread::sleep(Duration::from_secs(DISK_CHECK_INTERVAL_SEC));
+        }
+    });
+}
+
+fn update_status(context: &Mutex<Context>, free_mb: u64, settings: &Db) {
+    let state = DiskState::from_free(free_mb, settings);
+    let mut context = context.lock().unwrap();
+    if state != context.disk.state {
+        match state {
+            DiskState::Normal => info!("Free disk space is {} MB, accepting blocks as usual", free_mb),
+            DiskState::Low => warn!("Free disk space is low: {} MB, free some space for blockchain DB", free_mb),
+            DiskState::Critical => error!("Free disk space is critically low: {} MB, new blocks are not accepted until there is more space", free_mb)
+        }
+        context.bus.post(Event::DiskSpace { state, free_mb });
+    }
+    context.disk = DiskStatus { state, free_mb: Some(free_mb) };
+}
+
+/// Gets free space in bytes, that is available for us on the volume with this path
+#[cfg(unix)]
+pub fn get_free_space(path: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Gets free space in bytes, that is available for us on the volume with this path
+#[cfg(windows)]
+pub fn get_free_space(path: &Path) -> io::Result<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    use winapi::shared::ntdef::ULARGE_INTEGER;
+    use winapi::um::fileapi::GetDiskFreeSpaceExW;
+
+    let path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut free: ULARGE_INTEGER = unsafe { std::mem::zeroed() };
+    if unsafe { GetDiskFreeSpaceExW(path.as_ptr(), &mut free, std::ptr::null_mut(), std::ptr::null_mut()) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { *free.QuadPart() })
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn get_free_space(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::new(io::ErrorKind::Other, "Not supported on this platform"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disk_state() {
+        let settings = Db { low_space_mb: 500, critical_space_mb: 50, ..Db::default() };
+        assert_eq!(DiskState::Normal, DiskState::from_free(500, &settings));
+        assert_eq!(DiskState::Low, DiskState::from_free(499, &settings));
+        assert_eq!(DiskState::Critical, DiskState::from_free(49, &settings));
+        assert!(get_free_space(Path::new(".")).unwrap() > 0);
+    }
+}
@@ -3,7 +3,10 @@
 extern crate serde;
 use std::clone::Clone;
 use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
 use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
+use std::mem::size_of;
 use std::sync::{Arc, RwLock};
 
 use chrono::*;
@@ -11,15 +14,34 @@ use derive_more::{Display, Error, From};
 use serde::{Deserialize, Serialize};
 
 use crate::dns::protocol::{DnsPacket, DnsRecord, QueryType, ResultCode};
+use crate::memory::DNS_CACHE;
+
+/// Rough size of one record in memory, besides its domain name
+const RECORD_SIZE: usize = size_of::<RecordEntry>() + 32;
+/// Rough size of one domain entry in memory, besides its name and records
+const DOMAIN_SIZE: usize = size_of::<DomainEntry>() + 64;
 
 #[derive(Debug, Display, From, Error)]
 pub enum CacheError {
     Io(std::io::Error),
+    Json(serde_json::Error),
     PoisonedLock,
 }
 
 type Result<T> = std::result::Result<T, CacheError>;
 
+/// Counters of the cache, they are posted to the bus from time to time
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    /// Names with types of records
+    pub entries: usize,
+    /// Estimated bytes
+    pub size: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
 pub enum CacheState {
     PositiveCache,
     NegativeCache,
@@ -50,6 +72,26 @@ pub enum RecordSet {
     Records { qtype: QueryType, records: HashSet<RecordEntry> },
 }
 
+impl RecordSet {
+    /// Removes expired records, returns true if nothing is left
+    fn remove_expired(&mut self, now: DateTime<Local>) -> bool {
+        match self {
+            RecordSet::NoRecords { ttl, timestamp, .. } => *timestamp + Duration::seconds(*ttl as i64) < now,
+            RecordSet::Records { records, .. } => {
+                records.retain(|entry| entry.timestamp + Duration::seconds(entry.record.get_ttl() as i64) >= now);
+                records.is_empty()
+            }
+        }
+    }
+}
+
+/// The form in which domain entries are saved to disk
+#[derive(Serialize, Deserialize)]
+struct SavedEntry {
+    domain: String,
+    sets: Vec<RecordSet>,
+}
+
 #[derive(Clone, Debug)]
 pub struct DomainEntry {
     pub domain: String,
@@ -132,6 +174,15 @@ impl DomainEntry {
         }
     }
 
+    /// Estimated bytes that this entry takes in memory
+    pub fn get_size(&self) -> usize {
+        let records: usize = self.record_types.values().map(|set| match set {
+            RecordSet::NoRecords { .. } => RECORD_SIZE,
+            RecordSet::Records { records, .. } => records.len() * (RECORD_SIZE + self.domain.len())
+        }).sum();
+        DOMAIN_SIZE + self.domain.len() * 2 + records
+    }
+
     pub fn fill_queryresult(&self, qtype: QueryType, result_vec: &mut Vec<DnsRecord>) {
         let now = Local::now();
 
@@ -159,11 +210,111 @@ impl DomainEntry {
 #[derive(Default)]
 pub struct Cache {
     domain_entries: BTreeMap<String, Arc<DomainEntry>>,
+    /// Estimated bytes of all entries, it grows with every stored record, and is counted exactly on eviction
+    size: usize,
+    /// Maximum bytes to take, zero for no limit
+    budget: usize,
+    /// Maximum count of names with types of records, zero for no limit
+    max_entries: usize,
+    /// Last use of records by name and type
+    used: HashMap<(String, QueryType), u64>,
+    /// The same uses in order, the least recently used records are evicted first
+    order: BTreeMap<u64, (String, QueryType)>,
+    clock: u64,
+    stats: CacheStats,
 }
 
 impl Cache {
     pub fn new() -> Cache {
-        Cache { domain_entries: BTreeMap::new() }
+        Cache::default()
+    }
+
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.evict_if_needed();
+    }
+
+    pub fn set_max_entries(&mut self, max_entries: usize) {
+        self.max_entries = max_entries;
+        self.evict_if_needed();
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+
+    pub fn get_stats(&self) -> CacheStats {
+        CacheStats { entries: self.used.len(), size: self.size, ..self.stats }
+    }
+
+    fn touch(&mut self, qname: &str, qtype: QueryType) {
+        self.clock += 1;
+        if let Some(last) = self.used.insert((qname.to_owned(), qtype), self.clock) {
+            self.order.remove(&last);
+        }
+        self.order.insert(self.clock, (qname.to_owned(), qtype));
+    }
+
+    /// Forgets uses of records that are not in the cache anymore
+    fn forget_removed(&mut self) {
+        let entries = &self.domain_entries;
+        self.used.retain(|(qname, qtype), _| entries.get(qname).map_or(false, |entry| entry.record_types.contains_key(qtype)));
+        let used = &self.used;
+        self.order.retain(|_, key| used.contains_key(key));
+    }
+
+    /// Removes the least recently used records, returns false if there is nothing to remove
+    fn evict_oldest(&mut self) -> bool {
+        let last = match self.order.keys().next() {
+            Some(last) => *last,
+            None => return false
+        };
+        let (qname, qtype) = self.order.remove(&last).unwrap();
+        self.used.remove(&(qname.clone(), qtype));
+        let (freed, empty) = match self.domain_entries.get_mut(&qname) {
+            Some(entry) => {
+                let before = entry.get_size();
+                let entry = Arc::make_mut(entry);
+                entry.record_types.remove(&qtype);
+                match entry.record_types.is_empty() {
+                    true => (before, true),
+                    false => (before - entry.get_size(), false)
+                }
+            }
+            None => (0, false)
+        };
+        if empty {
+            self.domain_entries.remove(&qname);
+        }
+        self.size = self.size.saturating_sub(freed);
+        self.stats.evictions += 1;
+        true
+    }
+
+    /// Removes the least recently used records if there are too many of them.
+    /// If the cache takes more than its budget, removes expired records, and then the least recently used ones, until it takes 90% of its budget.
+    fn evict_if_needed(&mut self) {
+        while self.max_entries > 0 && self.used.len() > self.max_entries && self.evict_oldest() {}
+        if self.budget == 0 || self.size <= self.budget {
+            DNS_CACHE.set_used(self.size);
+            return;
+        }
+        let now = Local::now();
+        self.domain_entries.retain(|_, entry| {
+            match Arc::get_mut(entry) {
+                Some(entry) => {
+                    entry.record_types.retain(|_, set| !set.remove_expired(now));
+                    !entry.record_types.is_empty()
+                }
+                None => true
+            }
+        });
+        self.forget_removed();
+        self.size = self.domain_entries.values().map(|entry| entry.get_size()).sum();
+
+        let target = self.budget / 10 * 9;
+        while self.size > target && self.evict_oldest() {}
+        DNS_CACHE.set_used(self.size);
     }
 
     fn get_cache_state(&mut self, qname: &str, qtype: QueryType) -> CacheState {
@@ -184,6 +335,18 @@ impl Cache {
     }
 
     pub fn lookup(&mut self, qname: &str, qtype: QueryType) -> Option<DnsPacket> {
+        let result = self.lookup_state(qname, qtype);
+        match result {
+            Some(_) => {
+                self.stats.hits += 1;
+                self.touch(qname, qtype);
+            }
+            None => self.stats.misses += 1
+        }
+        result
+    }
+
+    fn lookup_state(&mut self, qname: &str, qtype: QueryType) -> Option<DnsPacket> {
         match self.get_cache_state(qname, qtype) {
             CacheState::PositiveCache => {
                 let mut qr = DnsPacket::new();
@@ -209,26 +372,57 @@ impl Cache {
                 None => continue,
             };
 
+            self.touch(&domain, rec.get_querytype());
             if let Some(ref mut rs) = self.domain_entries.get_mut(&domain).and_then(Arc::get_mut) {
+                let before = rs.get_size();
                 rs.store_record(rec);
+                self.size = self.size.saturating_sub(before) + rs.get_size();
                 continue;
             }
 
             let mut rs = DomainEntry::new(domain.clone());
             rs.store_record(rec);
-            self.domain_entries.insert(domain.clone(), Arc::new(rs));
+            self.size += rs.get_size();
+            // The entry that is used by readers is replaced, and its size is freed
+            if let Some(old) = self.domain_entries.insert(domain.clone(), Arc::new(rs)) {
+                self.size = self.size.saturating_sub(old.get_size());
+            }
         }
+        self.evict_if_needed();
+    }
+
+    /// Removes all domains that match, returns how many were removed
+    pub fn remove_matching<F: Fn(&str) -> bool>(&mut self, matches: F) -> usize {
+        let count = self.domain_entries.len();
+        let mut removed_size = 0;
+        self.domain_entries.retain(|domain, entry| {
+            if matches(domain) {
+                removed_size += entry.get_size();
+                return false;
+            }
+            true
+        });
+        self.size = self.size.saturating_sub(removed_size);
+        self.forget_removed();
+        DNS_CACHE.set_used(self.size);
+        count - self.domain_entries.len()
     }
 
     pub fn store_nxdomain(&mut self, qname: &str, qtype: QueryType, ttl: u32) {
+        self.touch(qname, qtype);
         if let Some(ref mut rs) = self.domain_entries.get_mut(qname).and_then(Arc::get_mut) {
+            let before = rs.get_size();
             rs.store_nxdomain(qtype, ttl);
-            return;
+            self.size = self.size.saturating_sub(before) + rs.get_size();
+        } else {
+            let mut rs = DomainEntry::new(qname.to_string());
+            rs.store_nxdomain(qtype, ttl);
+            self.size += rs.get_size();
+            if let Some(old) = self.domain_entries.insert(qname.to_string(), Arc::new(rs)) {
+                self.size = self.size.saturating_sub(old.get_size());
+            }
         }
-
-        let mut rs = DomainEntry::new(qname.to_string());
-        rs.store_nxdomain(qtype, ttl);
-        self.domain_entries.insert(qname.to_string(), Arc::new(rs));
+        self.evict_if_needed();
     }
 }
 
@@ -254,6 +448,25 @@ impl SynchronizedCache {
         Ok(list)
     }
 
+    /// Sets maximum bytes for the cache to take, zero for no limit
+    pub fn set_budget(&self, budget: usize) -> Result<()> {
+        let mut cache = self.cache.write().map_err(|_| CacheError::PoisonedLock)?;
+        cache.set_budget(budget);
+        Ok(())
+    }
+
+    /// Sets maximum count of names with types of records, zero for no limit
+    pub fn set_max_entries(&self, max_entries: usize) -> Result<()> {
+        let mut cache = self.cache.write().map_err(|_| CacheError::PoisonedLock)?;
+        cache.set_max_entries(max_entries);
+        Ok(())
+    }
+
+    pub fn get_stats(&self) -> Result<CacheStats> {
+        let cache = self.cache.read().map_err(|_| CacheError::PoisonedLock)?;
+        Ok(cache.get_stats())
+    }
+
     pub fn lookup(&self, qname: &str, qtype: QueryType) -> Option<DnsPacket> {
         let mut cache = match self.cache.write() {
             Ok(x) => x,
@@ -278,6 +491,73 @@ impl SynchronizedCache {
 
         Ok(())
     }
+
+    /// Removes all domains that match, like names of a domain that has changed in blockchain
+    pub fn remove_matching<F: Fn(&str) -> bool>(&self, matches: F) -> Result<usize> {
+        let mut cache = self.cache.write().map_err(|_| CacheError::PoisonedLock)?;
+        Ok(cache.remove_matching(matches))
+    }
+
+    /// Saves all records that are not expired yet to file, returns count of saved domains.
+    /// Records keep their original timestamps, so their TTLs continue to count down after loading.
+    pub fn save(&self, filename: &str) -> Result<usize> {
+        let now = Local::now();
+        let mut entries = Vec::new();
+        {
+            let cache = self.cache.read().map_err(|_| CacheError::PoisonedLock)?;
+            for entry in cache.domain_entries.values() {
+                let sets = valid_sets(entry.record_types.values().cloned(), now);
+                if !sets.is_empty() {
+                    entries.push(SavedEntry { domain: entry.domain.clone(), sets });
+                }
+            }
+        }
+
+        // Writing to temporary file first to not lose old file if something goes wrong
+        let temp_name = format!("{}.tmp", filename);
+        let mut file = File::create(&temp_name)?;
+        file.write_all(serde_json::to_string(&entries)?.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp_name, filename)?;
+        Ok(entries.len())
+    }
+
+    /// Loads records saved by `save()`, skipping expired ones, returns count of loaded domains
+    pub fn load(&self, filename: &str) -> Result<usize> {
+        let mut text = String::new();
+        File::open(filename)?.read_to_string(&mut text)?;
+        let entries: Vec<SavedEntry> = serde_json::from_str(&text)?;
+
+        let now = Local::now();
+        let mut cache = self.cache.write().map_err(|_| CacheError::PoisonedLock)?;
+        let mut count = 0;
+        for saved in entries {
+            let sets = valid_sets(saved.sets, now);
+            if sets.is_empty() {
+                continue;
+            }
+            let mut entry = DomainEntry::new(saved.domain.clone());
+            for set in sets {
+                let qtype = match &set {
+                    RecordSet::NoRecords { qtype, .. } | RecordSet::Records { qtype, .. } => *qtype
+                };
+                entry.record_types.insert(qtype, set);
+                cache.touch(&saved.domain, qtype);
+            }
+            cache.size += entry.get_size();
+            cache.domain_entries.insert(saved.domain, Arc::new(entry));
+            count += 1;
+        }
+        cache.evict_if_needed();
+        Ok(count)
+    }
+}
+
+/// Leaves only not expired records
+fn valid_sets<I: IntoIterator<Item = RecordSet>>(sets: I, now: DateTime<Local>) -> Vec<RecordSet> {
+    sets.into_iter()
+        .filter_map(|mut set| if set.remove_expired(now) { None } else { Some(set) })
+        .collect()
 }
 
 #[cfg(test)]
@@ -421,4 +701,125 @@ mod tests {
                 .hits
         );
     }
+
+    #[test]
+    fn test_save_and_load() {
+        let cache = SynchronizedCache::new();
+        let mut records = Vec::new();
+        records.push(DnsRecord::A {
+            domain: "www.google.com".to_string(),
+            addr: "127.0.0.1".parse().unwrap(),
+            ttl: TransientTtl(3600),
+        });
+        records.push(DnsRecord::A {
+            domain: "www.yahoo.com".to_string(),
+            addr: "127.0.0.2".parse().unwrap(),
+            ttl: TransientTtl(0),
+        });
+        cache.store(&records).unwrap();
+        cache.store_nxdomain("www.bing.com", QueryType::A, 3600).unwrap();
+
+        let filename = std::env::temp_dir().join("gis-cache-test.json");
+        let filename = filename.to_str().unwrap();
+        // Expired record of yahoo is not saved
+        assert_eq!(2, cache.save(filename).unwrap());
+
+        let cache = SynchronizedCache::new();
+        assert_eq!(2, cache.load(filename).unwrap());
+        let _ = std::fs::remove_file(filename);
+
+        assert_eq!(1, cache.lookup("www.google.com", QueryType::A).unwrap().answers.len());
+        assert_eq!(ResultCode::NXDOMAIN, cache.lookup("www.bing.com", QueryType::A).unwrap().header.rescode);
+        assert!(cache.lookup("www.yahoo.com", QueryType::A).is_none());
+    }
+
+    #[test]
+    fn test_remove_matching() {
+        let cache = SynchronizedCache::new();
+        let records: Vec<DnsRecord> = ["example.ygg", "www.example.ygg", "other.ygg"].iter()
+            .map(|domain| DnsRecord::A { domain: domain.to_string(), addr: "127.0.0.1".parse().unwrap(), ttl: TransientTtl(3600) })
+            .collect();
+        cache.store(&records).unwrap();
+        let size = cache.cache.read().unwrap().get_size();
+
+        let removed = cache.remove_matching(|name| name == "example.ygg" || name.ends_with(".example.ygg")).unwrap();
+        assert_eq!(2, removed);
+        assert!(cache.lookup("www.example.ygg", QueryType::A).is_none());
+        assert!(cache.lookup("other.ygg", QueryType::A).is_some());
+        assert!(cache.cache.read().unwrap().get_size() < size);
+    }
+
+    #[test]
+    fn test_budget() {
+        let mut cache = Cache::new();
+        let domain = |i: usize| format!("host{}.example.com", i);
+        for i in 0..100 {
+            cache.store(&[DnsRecord::A { domain: domain(i), addr: "127.0.0.1".parse().unwrap(), ttl: TransientTtl(3600) }]);
+        }
+        assert_eq!(100, cache.domain_entries.len());
+        // Popular domain must survive the eviction
+        cache.lookup(&domain(50), QueryType::A);
+
+        let size = cache.get_size();
+        cache.set_budget(size / 2);
+        assert!(cache.get_size() <= size / 2 / 10 * 9);
+        assert!(cache.domain_entries.len() < 50);
+        assert!(cache.lookup(&domain(50), QueryType::A).is_some());
+
+        cache.store(&[DnsRecord::A { domain: domain(100), addr: "127.0.0.1".parse().unwrap(), ttl: TransientTtl(3600) }]);
+        assert!(cache.get_size() <= size / 2);
+    }
+
+    #[test]
+    fn test_size() {
+        let mut cache = Cache::new();
+        let record = DnsRecord::A { domain: String::from("one.com"), addr: "127.0.0.1".parse().unwrap(), ttl: TransientTtl(3600) };
+        cache.store(&[record.clone()]);
+        let size = cache.get_size();
+        assert_eq!(cache.domain_entries["one.com"].get_size(), size);
+        cache.store(&[record.clone()]);
+        assert_eq!(size, cache.get_size());
+
+        // Entry that is held by a reader is replaced, without counting the old one
+        let entry = Arc::clone(&cache.domain_entries["one.com"]);
+        cache.store(&[record]);
+        assert_eq!(size, cache.get_size());
+        drop(entry);
+
+        cache.store_nxdomain("one.com", QueryType::AAAA, 3600);
+        let size = cache.get_size();
+        cache.store_nxdomain("one.com", QueryType::AAAA, 3600);
+        assert_eq!(size, cache.get_size());
+        let entry = Arc::clone(&cache.domain_entries["one.com"]);
+        cache.store_nxdomain("two.com", QueryType::A, 3600);
+        cache.store_nxdomain("one.com", QueryType::AAAA, 3600);
+        assert_eq!(cache.domain_entries.values().map(|entry| entry.get_size()).sum::<usize>(), cache.get_size());
+        drop(entry);
+    }
+
+    #[test]
+    fn test_max_entries() {
+        let mut cache = Cache::new();
+        cache.set_max_entries(2);
+        let record = |domain: &str| DnsRecord::A { domain: domain.to_string(), addr: "127.0.0.1".parse().unwrap(), ttl: TransientTtl(3600) };
+        cache.store(&[record("one.com")]);
+        cache.store(&[record("two.com")]);
+        assert!(cache.lookup("one.com", QueryType::A).is_some());
+        // The least recently used one goes away
+        cache.store(&[record("three.com")]);
+        assert!(cache.lookup("two.com", QueryType::A).is_none());
+        assert!(cache.lookup("one.com", QueryType::A).is_some());
+        assert!(cache.lookup("three.com", QueryType::A).is_some());
+
+        // Other types of the same name are counted separately
+        cache.store_nxdomain("one.com", QueryType::AAAA, 3600);
+        assert!(cache.lookup("one.com", QueryType::A).is_none());
+        assert_eq!(ResultCode::NXDOMAIN, cache.lookup("one.com", QueryType::AAAA).unwrap().header.rescode);
+
+        let stats = cache.get_stats();
+        assert_eq!(2, stats.entries);
+        assert_eq!(2, stats.evictions);
+        assert_eq!(4, stats.hits);
+        assert_eq!(2, stats.misses);
+    }
 }
@@ -32,6 +32,9 @@ pub trait DnsClient {
     fn get_failed_count(&self) -> usize;
 
     fn run(&self) -> Result<()>;
+    /// Sets the time in milliseconds to wait for response, must be called before `run()`
+    fn set_timeout(&mut self, _timeout: u64) {}
+    /// Sends a new query with only the name and type, EDNS options of clients like their subnet are never passed on
     fn send_query(&self, qname: &str, qtype: QueryType, server: &str, recursive: bool) -> Result<DnsPacket>;
 }
 
@@ -57,6 +60,9 @@ pub struct DnsNetworkClient {
 
     /// Queries in progress
     pending_queries: Arc<Mutex<Vec<PendingQuery>>>,
+
+    /// Time to wait for response
+    timeout: Duration,
 }
 
 /// A query in progress. This struct holds the `id` if the request, and a channel
@@ -81,6 +87,7 @@ impl DnsNetworkClient {
             socket_ipv4: UdpSocket::bind(format!("0.0.0.0:{}", port)).expect("Error binding IPv4"),
             socket_ipv6: UdpSocket::bind(format!("[::]:{}", port + 1)).expect("Error binding IPv6"),
             pending_queries: Arc::new(Mutex::new(Vec::new())),
+            timeout: Duration::seconds(10),
         }
     }
 
@@ -190,6 +197,10 @@ impl DnsClient for DnsNetworkClient {
         self.total_failed.load(Ordering::Acquire)
     }
 
+    fn set_timeout(&mut self, timeout: u64) {
+        self.timeout = Duration::milliseconds(timeout as i64);
+    }
+
     /// The run method launches a worker thread. Unless this thread is running, no
     /// responses will ever be generated, and clients will just block indefinitely.
     fn run(&self) -> Result<()> {
@@ -302,11 +313,11 @@ impl DnsClient for DnsNetworkClient {
         // Start the thread for timing out requests
         {
             let pending_queries_lock = self.pending_queries.clone();
+            let timeout = self.timeout;
 
             Builder::new()
                 .name("DnsNetworkClient-timeout-thread".into())
                 .spawn(move || {
-                    let timeout = Duration::seconds(10);
                     loop {
                         if let Ok(mut pending_queries) = pending_queries_lock.lock() {
                             let mut finished_queries = Vec::new();
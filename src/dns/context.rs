@@ -1,7 +1,7 @@
 //! The `ServerContext in this thread holds the common state across the server
 
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 use derive_more::{Display, Error, From};
 
@@ -10,6 +10,11 @@ use crate::dns::cache::SynchronizedCache;
 use crate::dns::client::{DnsClient, DnsNetworkClient};
 use crate::dns::resolve::{DnsResolver, ForwardingDnsResolver, RecursiveDnsResolver};
 use crate::dns::filter::DnsFilter;
+use crate::dns::inflight::InFlight;
+use crate::dns::health::ForwarderHealth;
+use crate::dns::routes::{RouteTable, RouteTarget};
+use crate::dns::views::ClientView;
+use crate::dns::transfer::ZoneTransfer;
 
 #[derive(Debug, Display, From, Error)]
 pub enum ContextError {
@@ -23,6 +28,7 @@ type Result<T> = std::result::Result<T, ContextError>;
 pub struct ServerStatistics {
     pub tcp_query_count: AtomicUsize,
     pub udp_query_count: AtomicUsize,
+    pub https_query_count: AtomicUsize,
 }
 
 impl ServerStatistics {
@@ -33,22 +39,51 @@ impl ServerStatistics {
     pub fn get_udp_query_count(&self) -> usize {
         self.udp_query_count.load(Ordering::Acquire)
     }
+
+    pub fn get_https_query_count(&self) -> usize {
+        self.https_query_count.load(Ordering::Acquire)
+    }
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub enum ResolveStrategy {
     Recursive,
-    Forward { upstreams: Vec<String> },
+    Forward { upstreams: Vec<String>, retries: usize, parallel: bool },
 }
 
 pub struct ServerContext {
     pub authority: Authority,
     pub cache: SynchronizedCache,
+    /// Queries that are being resolved now, identical ones wait for them
+    pub in_flight: InFlight,
+    /// Failed forwarders are asked last for some time
+    pub forwarders: ForwarderHealth,
     pub filters: Vec<Box<dyn DnsFilter + Sync + Send>>,
+    /// Named sources of domains, like blockchains, names without a route are looked up in all of them in this order
+    pub sources: Vec<(String, Box<dyn DnsFilter + Sync + Send>)>,
+    /// Which source serves which zone, it is reloaded while running
+    pub routes: RwLock<RouteTable>,
+    /// Per-client views, the first matching one is applied before any resolving
+    pub views: Vec<ClientView>,
     pub client: Box<dyn DnsClient + Sync + Send>,
-    pub dns_listen: String,
     pub api_port: u16,
-    pub resolve_strategy: ResolveStrategy,
+    /// Forwarders can be changed while running, when settings are reloaded
+    resolve_strategy: RwLock<ResolveStrategy>,
     pub allow_recursive: bool,
+    /// Send only needed parts of names to authoritative servers in recursive mode
+    pub qname_minimization: bool,
+    /// Send only answers, and authority records that are needed for referrals and negative answers
+    pub minimal_responses: bool,
+    /// Remove EDNS Client Subnet from queries to other servers and their answers
+    pub strip_client_subnet: bool,
+    /// Refuse queries with EDNS Client Subnet
+    pub refuse_client_subnet: bool,
+    /// Use built-in root servers in recursive mode, when there are no name servers in cache
+    pub root_hints: bool,
+    /// Seconds to cache absent names from forwarders, when their answers have no SOA, zero to not cache them
+    pub negative_ttl: u32,
+    /// Zone transfers to secondary servers, if we are a primary for some zones
+    pub transfer: Option<ZoneTransfer>,
     pub enable_udp: bool,
     pub enable_tcp: bool,
     pub enable_api: bool,
@@ -67,18 +102,30 @@ impl ServerContext {
         ServerContext {
             authority: Authority::new(),
             cache: SynchronizedCache::new(),
+            in_flight: InFlight::new(),
+            forwarders: ForwarderHealth::new(),
             filters: Vec::new(),
+            sources: Vec::new(),
+            routes: RwLock::new(RouteTable::default()),
+            views: Vec::new(),
             client: Box::new(DnsNetworkClient::new(10000 + (rand::random::<u16>() % 20000))),
-            dns_listen: String::from("0.0.0.0:53"),
             api_port: 5395,
-            resolve_strategy: ResolveStrategy::Recursive,
+            resolve_strategy: RwLock::new(ResolveStrategy::Recursive),
             allow_recursive: true,
+            qname_minimization: true,
+            minimal_responses: false,
+            strip_client_subnet: true,
+            refuse_client_subnet: false,
+            root_hints: true,
+            negative_ttl: 0,
+            transfer: None,
             enable_udp: true,
             enable_tcp: true,
             enable_api: false,
             statistics: ServerStatistics {
                 tcp_query_count: AtomicUsize::new(0),
                 udp_query_count: AtomicUsize::new(0),
+                https_query_count: AtomicUsize::new(0),
             },
             zones_dir: "zones",
         }
@@ -94,11 +141,29 @@ impl ServerContext {
         Ok(())
     }
 
+    pub fn get_source(&self, name: &str) -> Option<&(dyn DnsFilter + Sync + Send)> {
+        self.sources.iter().find(|(n, _)| n == name).map(|(_, source)| source.as_ref())
+    }
+
+    /// Finds the route for the name, returns its zone and target
+    pub fn get_route(&self, qname: &str) -> Option<(String, RouteTarget)> {
+        let routes = self.routes.read().ok()?;
+        routes.find(qname).map(|(zone, target)| (zone.to_owned(), target.clone()))
+    }
+
+    pub fn get_resolve_strategy(&self) -> ResolveStrategy {
+        self.resolve_strategy.read().unwrap().clone()
+    }
+
+    pub fn set_resolve_strategy(&self, strategy: ResolveStrategy) {
+        *self.resolve_strategy.write().unwrap() = strategy;
+    }
+
     pub fn create_resolver(&self, ptr: Arc<ServerContext>) -> Box<dyn DnsResolver> {
-        match self.resolve_strategy {
+        match self.get_resolve_strategy() {
             ResolveStrategy::Recursive => Box::new(RecursiveDnsResolver::new(ptr)),
-            ResolveStrategy::Forward { ref upstreams } => {
-                Box::new(ForwardingDnsResolver::new(ptr, upstreams.clone()))
+            ResolveStrategy::Forward { upstreams, retries, parallel } => {
+                Box::new(ForwardingDnsResolver::new(ptr, upstreams, retries, parallel))
             }
         }
     }
@@ -108,7 +173,7 @@ impl ServerContext {
 pub mod tests {
 
     use std::sync::atomic::AtomicUsize;
-    use std::sync::Arc;
+    use std::sync::{Arc, RwLock};
 
     use crate::dns::authority::Authority;
     use crate::dns::cache::SynchronizedCache;
@@ -121,18 +186,30 @@ pub mod tests {
         Arc::new(ServerContext {
             authority: Authority::new(),
             cache: SynchronizedCache::new(),
+            in_flight: InFlight::new(),
+            forwarders: ForwarderHealth::new(),
             filters: Vec::new(),
+            sources: Vec::new(),
+            routes: RwLock::new(RouteTable::default()),
+            views: Vec::new(),
             client: Box::new(DnsStubClient::new(callback)),
-            dns_listen: String::from("0.0.0.0:53"),
             api_port: 5395,
-            resolve_strategy: ResolveStrategy::Recursive,
+            resolve_strategy: RwLock::new(ResolveStrategy::Recursive),
             allow_recursive: true,
+            qname_minimization: false,
+            minimal_responses: false,
+            strip_client_subnet: true,
+            refuse_client_subnet: false,
+            root_hints: false,
+            negative_ttl: 0,
+            transfer: None,
             enable_udp: true,
             enable_tcp: true,
             enable_api: false,
             statistics: ServerStatistics {
                 tcp_query_count: AtomicUsize::new(0),
                 udp_query_count: AtomicUsize::new(0),
+                https_query_count: AtomicUsize::new(0),
             },
             zones_dir: "zones",
         })
@@ -0,0 +1,256 @@
+//! DNS-over-HTTPS server (RFC 8484), browsers can use the node as their resolver for blockchain zones and all other names.
+//! Queries come by GET with `?dns=` in base64url or by POST with `application/dns-message` body, one query per connection.
+
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::net::{IpAddr, Shutdown, TcpStream};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread::Builder;
+use std::time::Duration;
+
+use log::{debug, warn};
+use rustls::{NoClientAuth, ServerConfig, ServerSession, Session, StreamOwned};
+use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+
+use crate::api::http::{HttpRequest, HttpResponse};
+use crate::dns::buffer::{BytePacketBuffer, VectorPacketBuffer};
+use crate::dns::context::ServerContext;
+use crate::dns::pool::WorkerPool;
+use crate::dns::protocol::DnsPacket;
+use crate::dns::server::{execute_query, DnsServer, Listener, ServerError};
+use crate::settings::Doh;
+use crate::sockets::bind_tcp;
+
+/// Media type of DNS messages in requests and responses
+const DNS_MESSAGE: &str = "application/dns-message";
+/// Queries are parsed in buffers of this size, like UDP queries
+const MAX_QUERY_SIZE: usize = 512;
+/// Queries shorter than DNS header are malformed
+const MIN_QUERY_SIZE: usize = 12;
+/// Time to wait for TLS handshake and the request
+const READ_TIMEOUT_SEC: u64 = 5;
+
+/// DNS-over-HTTPS server
+pub struct DnsHttpsServer {
+    context: Arc<ServerContext>,
+    listener: Listener,
+    pool: Arc<WorkerPool>,
+    config: Arc<ServerConfig>,
+    path: String,
+}
+
+impl DnsHttpsServer {
+    /// Loads certificate and key from the files in settings, fails if they are absent or broken
+    pub fn new(context: Arc<ServerContext>, listener: Listener, pool: Arc<WorkerPool>, settings: &Doh) -> Result<DnsHttpsServer, String> {
+        let config = load_config(&settings.cert_file, &settings.key_file)?;
+        Ok(DnsHttpsServer { context, listener, pool, config: Arc::new(config), path: settings.path.clone() })
+    }
+}
+
+fn load_config(cert_file: &str, key_file: &str) -> Result<ServerConfig, String> {
+    let open = |name: &str| File::open(name).map(BufReader::new).map_err(|e| format!("Unable to open '{}': {}", name, e));
+    let chain = certs(&mut open(cert_file)?).map_err(|_| format!("Wrong certificate in '{}'", cert_file))?;
+    if chain.is_empty() {
+        return Err(format!("No certificates in '{}'", cert_file));
+    }
+    let mut keys = pkcs8_private_keys(&mut open(key_file)?).unwrap_or_default();
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut open(key_file)?).unwrap_or_default();
+    }
+    let key = keys.into_iter().next().ok_or_else(|| format!("No private key in '{}'", key_file))?;
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.set_single_cert(chain, key).map_err(|e| format!("Wrong certificate or key: {}", e))?;
+    config.set_protocols(&[b"http/1.1".to_vec()]);
+    Ok(config)
+}
+
+/// Reads one request from TLS connection and answers it, it is called in a worker thread
+fn handle_https_connection(context: Arc<ServerContext>, config: Arc<ServerConfig>, path: &str, stream: TcpStream) {
+    let client = match stream.peer_addr() {
+        Ok(addr) => addr.ip(),
+        Err(_) => return
+    };
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(READ_TIMEOUT_SEC)));
+    let mut tls = StreamOwned::new(ServerSession::new(&config), stream);
+    let response = match HttpRequest::read(&mut tls) {
+        Some(request) => answer_request(&context, &request, path, client),
+        None => {
+            debug!("Failed to read DoH request from {}", client);
+            return;
+        }
+    };
+    if response.write(&mut tls).is_err() {
+        debug!("Failed to send DoH response to {}", client);
+        return;
+    }
+    tls.sess.send_close_notify();
+    let _ = tls.flush();
+    let _ = tls.sock.shutdown(Shutdown::Both);
+}
+
+/// Makes HTTP response with the answer to the query in the request
+pub fn answer_request(context: &Arc<ServerContext>, request: &HttpRequest, path: &str, client: IpAddr) -> HttpResponse {
+    if request.path != path {
+        return HttpResponse::error(404, "Not found");
+    }
+    let query = match request.method.as_str() {
+        "GET" => match request.get_param("dns").and_then(decode_base64url) {
+            Some(query) => query,
+            None => return HttpResponse::error(400, "Parameter 'dns' is absent or malformed")
+        },
+        "POST" => {
+            if request.headers.get("content-type").map(|s| s.as_str()) != Some(DNS_MESSAGE) {
+                return HttpResponse::error(415, "Content type must be application/dns-message");
+            }
+            request.body.clone()
+        }
+        _ => return HttpResponse::error(405, "Only GET and POST requests are allowed")
+    };
+    if query.len() > MAX_QUERY_SIZE {
+        return HttpResponse::error(413, "Query is too big");
+    }
+    if query.len() < MIN_QUERY_SIZE {
+        return HttpResponse::error(400, "Wrong DNS message");
+    }
+
+    let mut buffer = BytePacketBuffer::new();
+    buffer.buf[..query.len()].copy_from_slice(&query);
+    let packet = match DnsPacket::from_buffer(&mut buffer) {
+        Ok(packet) => packet,
+        Err(_) => return HttpResponse::error(400, "Wrong DNS message")
+    };
+    let _ = context.statistics.https_query_count.fetch_add(1, Ordering::Release);
+
+    let mut answer = execute_query(Arc::clone(context), &packet, client);
+    let mut res_buffer = VectorPacketBuffer::new();
+    if answer.write(&mut res_buffer, 0xFFFF).is_err() {
+        return HttpResponse::error(500, "Failed to write the answer");
+    }
+    HttpResponse { status: 200, content_type: DNS_MESSAGE, body: res_buffer.buffer, cors: false }
+}
+
+/// Decodes base64url without padding, as it is used in `dns` parameter
+fn decode_base64url(text: &str) -> Option<Vec<u8>> {
+    let mut result = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for c in text.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return None
+        };
+        bits = (bits << 6) | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            result.push((bits >> count) as u8);
+        }
+    }
+    Some(result)
+}
+
+impl DnsServer for DnsHttpsServer {
+    fn run_server(self) -> Result<(), ServerError> {
+        let socket = bind_tcp(&self.listener.address, self.listener.fallback_port)?;
+
+        let _ = Builder::new()
+            .name("DnsHttpsServer-incoming".into())
+            .spawn(move || {
+                for wrap_stream in socket.incoming() {
+                    let stream = match wrap_stream {
+                        Ok(stream) => stream,
+                        Err(err) => {
+                            warn!("Failed to accept DoH connection: {:?}", err);
+                            continue;
+                        }
+                    };
+
+                    match stream.peer_addr() {
+                        Ok(addr) if self.listener.allows(&addr.ip()) => {}
+                        _ => {
+                            debug!("Closing DoH connection from a client that is not allowed on {}", &self.listener.address);
+                            let _ = stream.shutdown(Shutdown::Both);
+                            continue;
+                        }
+                    }
+
+                    let context = Arc::clone(&self.context);
+                    let config = Arc::clone(&self.config);
+                    let path = self.path.clone();
+                    if !self.pool.submit(move || handle_https_connection(context, config, &path, stream)) {
+                        debug!("DNS workers are overloaded, closing DoH connection");
+                    }
+                }
+            })?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::dns::context::ResolveStrategy;
+    use crate::dns::context::tests::create_test_context;
+    use crate::dns::protocol::{DnsQuestion, DnsRecord, QueryType, TransientTtl};
+
+    use super::*;
+
+    const LOCAL: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    #[test]
+    fn base64url() {
+        assert_eq!(Some(b"foobar".to_vec()), decode_base64url("Zm9vYmFy"));
+        assert_eq!(Some(b"fo".to_vec()), decode_base64url("Zm8"));
+        assert_eq!(Some(vec![0xFB, 0xFF]), decode_base64url("-_8"));
+        assert_eq!(None, decode_base64url("Zm9v+mFy"));
+    }
+
+    #[test]
+    fn answer() {
+        let mut context = create_test_context(Box::new(|qname, _, _, _| {
+            let mut packet = DnsPacket::new();
+            packet.answers.push(DnsRecord::A { domain: qname.to_string(), addr: Ipv4Addr::new(127, 0, 0, 1), ttl: TransientTtl(3600) });
+            Ok(packet)
+        }));
+        Arc::get_mut(&mut context).unwrap().set_resolve_strategy(ResolveStrategy::Forward {
+            upstreams: vec![String::from("127.0.0.1:53")],
+            retries: 0,
+            parallel: false
+        });
+        let mut query = DnsPacket::new();
+        query.header.id = 1234;
+        query.header.recursion_desired = true;
+        query.questions.push(DnsQuestion::new(String::from("example.com"), QueryType::A));
+        let mut buffer = VectorPacketBuffer::new();
+        query.write(&mut buffer, 0xFFFF).unwrap();
+
+        let text = format!("POST /dns-query HTTP/1.1\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n", DNS_MESSAGE, buffer.buffer.len());
+        let mut raw = text.into_bytes();
+        raw.extend_from_slice(&buffer.buffer);
+        let request = HttpRequest::read(raw.as_slice()).unwrap();
+        let response = answer_request(&context, &request, "/dns-query", LOCAL);
+        assert_eq!(200, response.status);
+        assert_eq!(DNS_MESSAGE, response.content_type);
+        let mut buffer = VectorPacketBuffer::new();
+        buffer.buffer = response.body;
+        let packet = DnsPacket::from_buffer(&mut buffer).unwrap();
+        assert_eq!(1234, packet.header.id);
+        assert_eq!(1, packet.answers.len());
+        assert_eq!(1, context.statistics.get_https_query_count());
+
+        let request = HttpRequest::read("GET /dns-query?dns=AAAA HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        assert_eq!(400, answer_request(&context, &request, "/dns-query", LOCAL).status);
+        let request = HttpRequest::read("POST /dns-query HTTP/1.1\r\nContent-Type: text/plain\r\n\r\n".as_bytes()).unwrap();
+        assert_eq!(415, answer_request(&context, &request, "/dns-query", LOCAL).status);
+        let request = HttpRequest::read("GET /other HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        assert_eq!(404, answer_request(&context, &request, "/dns-query", LOCAL).status);
+    }
+}
@@ -2,6 +2,16 @@ use crate::dns::protocol::{QueryType, DnsPacket};
 
 pub trait DnsFilter {
     fn lookup(&self, qname: &str, qtype: QueryType) -> Option<DnsPacket>;
+
+    /// Human readable name of the filter, used in resolve traces
+    fn name(&self) -> String {
+        String::from("filter")
+    }
+
+    /// Explains why the filter has (or hasn't) answered for this name, used in resolve traces
+    fn explain(&self, _qname: &str) -> Option<String> {
+        None
+    }
 }
 
 pub struct DummyFilter {
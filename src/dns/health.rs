@@ -0,0 +1,122 @@
+//! Health of forwarders. A forwarder that fails is asked last for some time, and this time grows with every failure in a row,
+//! so that queries go to working servers instead of waiting for timeouts of unreachable ones.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+/// How long a failed forwarder is asked last after its first failure, it doubles with every next one
+const DOWN_MIN: Duration = Duration::from_secs(5);
+const DOWN_MAX: Duration = Duration::from_secs(300);
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Health {
+    /// Failures in a row
+    failures: u32,
+    down_until: Option<Instant>,
+    /// Smoothed time of answers in milliseconds
+    latency: u64,
+}
+
+#[derive(Default)]
+pub struct ForwarderHealth {
+    servers: Mutex<HashMap<String, Health>>,
+}
+
+impl ForwarderHealth {
+    pub fn new() -> Self {
+        ForwarderHealth::default()
+    }
+
+    /// Orders forwarders for a query: working ones go first, starting from `start` to spread the load,
+    /// and then failed ones, the one that is to be tried again sooner goes first.
+    pub fn order(&self, upstreams: &[String], start: usize) -> Vec<String> {
+        let now = Instant::now();
+        let servers = match self.servers.lock() {
+            Ok(servers) => servers,
+            Err(_) => return upstreams.to_vec()
+        };
+        let count = upstreams.len();
+        let mut result: Vec<(Option<Instant>, usize, String)> = (0..count)
+            .map(|i| {
+                let upstream = &upstreams[(start + i) % count];
+                let down_until = servers.get(upstream).and_then(|health| health.down_until).filter(|until| *until > now);
+                (down_until, i, upstream.clone())
+            })
+            .collect();
+        // None is less than Some, so working servers stay first in their order
+        result.sort();
+        result.into_iter().map(|(_, _, upstream)| upstream).collect()
+    }
+
+    /// Tells if the forwarder has failed recently, and is not asked first
+    pub fn is_down(&self, upstream: &str) -> bool {
+        match self.servers.lock() {
+            Ok(servers) => servers.get(upstream).and_then(|health| health.down_until).map_or(false, |until| until > Instant::now()),
+            Err(_) => false
+        }
+    }
+
+    pub fn success(&self, upstream: &str, latency: Duration) {
+        if let Ok(mut servers) = self.servers.lock() {
+            let health = servers.entry(upstream.to_owned()).or_default();
+            if health.failures > 0 {
+                info!("Forwarder {} is working again", upstream);
+            }
+            let latency = latency.as_millis() as u64;
+            health.latency = match health.latency {
+                0 => latency,
+                old => (old * 7 + latency) / 8
+            };
+            health.failures = 0;
+            health.down_until = None;
+        }
+    }
+
+    pub fn failure(&self, upstream: &str) {
+        if let Ok(mut servers) = self.servers.lock() {
+            let health = servers.entry(upstream.to_owned()).or_default();
+            let down = DOWN_MIN.checked_mul(1 << health.failures.min(16)).unwrap_or(DOWN_MAX).min(DOWN_MAX);
+            health.failures += 1;
+            health.down_until = Some(Instant::now() + down);
+            if health.failures == 1 {
+                warn!("Forwarder {} has failed, other ones are asked first for {} seconds", upstream, down.as_secs());
+            }
+        }
+    }
+
+    /// Smoothed time of answers of the forwarder, zero if it hasn't answered yet
+    pub fn get_latency(&self, upstream: &str) -> Duration {
+        match self.servers.lock() {
+            Ok(servers) => Duration::from_millis(servers.get(upstream).map_or(0, |health| health.latency)),
+            Err(_) => Duration::from_millis(0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_go_last() {
+        let health = ForwarderHealth::new();
+        let upstreams: Vec<String> = vec![String::from("1.1.1.1:53"), String::from("8.8.8.8:53"), String::from("9.9.9.9:53")];
+        assert_eq!(upstreams, health.order(&upstreams, 0));
+        assert_eq!(vec!["8.8.8.8:53", "9.9.9.9:53", "1.1.1.1:53"], health.order(&upstreams, 1));
+
+        health.failure("8.8.8.8:53");
+        assert!(health.is_down("8.8.8.8:53"));
+        assert_eq!(vec!["9.9.9.9:53", "1.1.1.1:53", "8.8.8.8:53"], health.order(&upstreams, 1));
+        health.failure("1.1.1.1:53");
+        // The one that has failed earlier is tried again sooner
+        assert_eq!(vec!["9.9.9.9:53", "8.8.8.8:53", "1.1.1.1:53"], health.order(&upstreams, 1));
+
+        health.success("8.8.8.8:53", Duration::from_millis(40));
+        assert!(!health.is_down("8.8.8.8:53"));
+        assert_eq!(Duration::from_millis(40), health.get_latency("8.8.8.8:53"));
+        assert_eq!(vec!["8.8.8.8:53", "9.9.9.9:53", "1.1.1.1:53"], health.order(&upstreams, 1));
+    }
+}
@@ -8,6 +8,7 @@ use crate::dns::protocol::{DnsPacket, QueryType, DnsRecord, TransientTtl, DnsQue
 const NAME_SERVER: & str = "hosts";
 
 pub struct HostsFilter {
+    filename: String,
     hosts: HashMap<String, Vec<IpAddr>>
 }
 
@@ -44,7 +45,7 @@ impl HostsFilter {
                 HashMap::new()
             }
         };
-        HostsFilter { hosts }
+        HostsFilter { filename: filename.to_owned(), hosts }
     }
 
     pub fn size(&self) -> usize {
@@ -76,6 +77,10 @@ impl DnsFilter for HostsFilter {
 
         None
     }
+
+    fn name(&self) -> String {
+        format!("hosts '{}'", &self.filename)
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,144 @@
+//! Coalescing of identical queries that are resolved at the same time.
+//! When a popular name expires from cache, many clients ask for it at once,
+//! then only the first query goes to blockchain or upstream servers, and others wait for its answer.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, ThreadId};
+use std::time::Duration;
+
+use crate::dns::protocol::{DnsPacket, QueryType};
+
+/// How long to wait for the answer of other query, it is a bit longer than usual forwarder timeout
+const MAX_WAIT: Duration = Duration::from_secs(15);
+
+type Key = (String, QueryType);
+
+struct Pending {
+    /// Thread that resolves the name, it must not wait for itself in recursive lookups
+    leader: ThreadId,
+    /// Answer, or None if resolving has failed
+    result: Mutex<Option<Option<DnsPacket>>>,
+    cond: Condvar,
+}
+
+#[derive(Default)]
+pub struct InFlight {
+    pending: Mutex<HashMap<Key, Arc<Pending>>>,
+    coalesced: AtomicUsize,
+}
+
+/// Publishes the result and forgets the query when the leader is done, even if it panics
+struct Finish<'a> {
+    in_flight: &'a InFlight,
+    key: Key,
+    pending: Arc<Pending>,
+    result: Option<DnsPacket>,
+}
+
+impl Drop for Finish<'_> {
+    fn drop(&mut self) {
+        self.in_flight.pending.lock().unwrap().remove(&self.key);
+        *self.pending.result.lock().unwrap() = Some(self.result.take());
+        self.pending.cond.notify_all();
+    }
+}
+
+impl InFlight {
+    pub fn new() -> Self {
+        InFlight::default()
+    }
+
+    /// Resolves the name by `resolve`, or waits for the same query that is already being resolved.
+    /// Returns `Err(None)` if that other query has failed, the caller doesn't get its error.
+    pub fn resolve<F, E>(&self, qname: &str, qtype: QueryType, resolve: F) -> Result<DnsPacket, Option<E>>
+        where F: FnOnce() -> Result<DnsPacket, E>
+    {
+        let key = (qname.to_owned(), qtype);
+        let me = thread::current().id();
+        let (pending, leader) = {
+            let mut map = self.pending.lock().unwrap();
+            match map.get(&key) {
+                Some(pending) if pending.leader != me => (Arc::clone(pending), false),
+                Some(_) => return resolve().map_err(Some),
+                None => {
+                    let pending = Arc::new(Pending { leader: me, result: Mutex::new(None), cond: Condvar::new() });
+                    map.insert(key.clone(), Arc::clone(&pending));
+                    (pending, true)
+                }
+            }
+        };
+
+        if leader {
+            let mut finish = Finish { in_flight: self, key, pending, result: None };
+            let result = resolve();
+            if let Ok(packet) = &result {
+                finish.result = Some(packet.clone());
+            }
+            return result.map_err(Some);
+        }
+
+        self.coalesced.fetch_add(1, Ordering::Relaxed);
+        let result = pending.result.lock().unwrap();
+        let (result, _) = pending.cond.wait_timeout_while(result, MAX_WAIT, |result| result.is_none()).unwrap();
+        match &*result {
+            Some(Some(packet)) => Ok(packet.clone()),
+            _ => Err(None)
+        }
+    }
+
+    /// How many queries got the answer of other identical query
+    pub fn get_coalesced_count(&self) -> usize {
+        self.coalesced.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Barrier;
+
+    use crate::dns::protocol::ResultCode;
+
+    use super::*;
+
+    #[test]
+    fn coalescing() {
+        let in_flight = Arc::new(InFlight::new());
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(5));
+        let mut threads = Vec::new();
+        for _ in 0..5 {
+            let in_flight = Arc::clone(&in_flight);
+            let lookups = Arc::clone(&lookups);
+            let barrier = Arc::clone(&barrier);
+            threads.push(thread::spawn(move || {
+                barrier.wait();
+                in_flight.resolve("popular.ygg", QueryType::A, || -> Result<DnsPacket, ()> {
+                    lookups.fetch_add(1, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(300));
+                    let mut packet = DnsPacket::new();
+                    packet.header.rescode = ResultCode::NXDOMAIN;
+                    Ok(packet)
+                })
+            }));
+        }
+        for thread in threads {
+            assert_eq!(ResultCode::NXDOMAIN, thread.join().unwrap().unwrap().header.rescode);
+        }
+        assert_eq!(1, lookups.load(Ordering::SeqCst));
+        assert_eq!(4, in_flight.get_coalesced_count());
+        assert!(in_flight.pending.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn same_thread() {
+        let in_flight = InFlight::new();
+        let result = in_flight.resolve("loop.ygg", QueryType::A, || {
+            // Recursive lookup of the same name must not wait for itself
+            in_flight.resolve("loop.ygg", QueryType::A, || Err::<DnsPacket, &str>("no servers"))
+        });
+        assert!(matches!(result, Err(Some(Some("no servers")))));
+    }
+}
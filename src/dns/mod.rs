@@ -24,5 +24,18 @@ pub mod resolve;
 pub mod server;
 pub mod filter;
 pub mod hosts;
+pub mod hints;
+pub mod views;
+pub mod zonefile;
+pub mod templates;
+pub mod rpz;
+pub mod transfer;
+pub mod trace;
+pub mod routes;
+pub mod pool;
+pub mod inflight;
+pub mod health;
+#[cfg(feature = "node")]
+pub mod doh;
 
 mod netutil;
\ No newline at end of file
@@ -8,12 +8,12 @@ pub fn read_packet_length(stream: &mut TcpStream) -> Result<u16> {
     Ok(((len_buffer[0] as u16) << 8) | (len_buffer[1] as u16))
 }
 
-pub fn write_packet_length(stream: &mut TcpStream, len: usize) -> Result<()> {
+pub fn write_packet_length<W: Write>(stream: &mut W, len: usize) -> Result<()> {
     let mut len_buffer = [0; 2];
     len_buffer[0] = (len >> 8) as u8;
     len_buffer[1] = (len & 0xFF) as u8;
 
-    stream.write(&len_buffer)?;
+    stream.write_all(&len_buffer)?;
 
     Ok(())
 }
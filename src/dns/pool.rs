@@ -0,0 +1,198 @@
+//! Pool of worker threads for DNS queries of all listeners, with a bounded queue.
+//! When the queue is full, or a query waits in it for too long, the query is dropped (shed),
+//! as the client has already asked again or gave up, and answering it only makes the overload worse.
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::Builder;
+use std::time::{Duration, Instant};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// Clients usually repeat their queries after 2-5 seconds, older queries are not needed anymore
+pub const MAX_QUEUE_TIME: Duration = Duration::from_secs(3);
+/// Upper bounds of latency buckets, in microseconds, the last bucket is for everything above
+const BUCKETS: [u64; 12] = [100, 250, 500, 1000, 2500, 5000, 10000, 25000, 50000, 100000, 250000, 1000000];
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Histogram of query latencies, from the moment the query is queued until it is answered
+pub struct Latency {
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+    sum_us: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Latency {
+    pub const fn new() -> Self {
+        // Arrays of atomics can't be made by `[AtomicU64::new(0); N]`, as they are not Copy
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: AtomicU64 = AtomicU64::new(0);
+        Latency { buckets: [ZERO; BUCKETS.len() + 1], sum_us: AtomicU64::new(0), count: AtomicU64::new(0) }
+    }
+
+    pub fn add(&self, latency: Duration) {
+        let us = latency.as_micros() as u64;
+        let bucket = BUCKETS.iter().position(|bound| us <= *bound).unwrap_or(BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(us, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get_count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Gets upper bound of the bucket where this quantile (0.5, 0.99) of queries fits, in microseconds
+    pub fn get_quantile(&self, quantile: f64) -> Option<u64> {
+        let count = self.get_count();
+        if count == 0 {
+            return None;
+        }
+        let needed = ((count as f64) * quantile).ceil() as u64;
+        let mut seen = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket.load(Ordering::Relaxed);
+            if seen >= needed {
+                return Some(*BUCKETS.get(i).unwrap_or(&u64::MAX));
+            }
+        }
+        None
+    }
+}
+
+/// Latencies of all queries, that were answered by workers
+pub static QUERY_LATENCY: Latency = Latency::new();
+/// How many queries were dropped because of overload
+pub static SHED_QUERIES: AtomicU64 = AtomicU64::new(0);
+
+struct Queue {
+    jobs: VecDeque<(Instant, Job)>,
+    limit: usize,
+}
+
+pub struct WorkerPool {
+    queue: Mutex<Queue>,
+    cond: Condvar,
+}
+
+impl WorkerPool {
+    /// Starts `threads` workers, that take jobs from the queue of `queue_size`
+    pub fn start(threads: usize, queue_size: usize) -> Arc<WorkerPool> {
+        let pool = Arc::new(WorkerPool {
+            queue: Mutex::new(Queue { jobs: VecDeque::new(), limit: queue_size.max(1) }),
+            cond: Condvar::new(),
+        });
+        for thread_id in 0..threads.max(1) {
+            let pool = Arc::clone(&pool);
+            let name = format!("DNS worker {}", thread_id);
+            if let Err(e) = Builder::new().name(name).spawn(move || pool.work()) {
+                warn!("Unable to start DNS worker: {}", e);
+            }
+        }
+        pool
+    }
+
+    /// Puts the job to the queue, returns false if it is full and the job is dropped
+    pub fn submit<F: FnOnce() + Send + 'static>(&self, job: F) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.jobs.len() >= queue.limit {
+            SHED_QUERIES.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        queue.jobs.push_back((Instant::now(), Box::new(job)));
+        self.cond.notify_one();
+        true
+    }
+
+    pub fn get_queue_len(&self) -> usize {
+        self.queue.lock().unwrap().jobs.len()
+    }
+
+    fn work(&self) {
+        loop {
+            let (queued, job) = {
+                let mut queue = self.queue.lock().unwrap();
+                loop {
+                    match queue.jobs.pop_front() {
+                        Some(job) => break job,
+                        None => queue = self.cond.wait(queue).unwrap()
+                    }
+                }
+            };
+            if queued.elapsed() > MAX_QUEUE_TIME {
+                trace!("Dropping DNS query that waited for {:?}", queued.elapsed());
+                SHED_QUERIES.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            job();
+            QUERY_LATENCY.add(queued.elapsed());
+        }
+    }
+}
+
+/// Makes latency and shed queries in Prometheus text format, for `/metrics`
+pub fn format_metrics() -> String {
+    let mut result = String::new();
+    let _ = writeln!(result, "# HELP gis_dns_query_seconds Time from receiving DNS query to answering it");
+    let _ = writeln!(result, "# TYPE gis_dns_query_seconds histogram");
+    let mut total = 0;
+    for (i, bucket) in QUERY_LATENCY.buckets.iter().enumerate() {
+        total += bucket.load(Ordering::Relaxed);
+        let bound = match BUCKETS.get(i) {
+            Some(us) => format!("{}", *us as f64 / 1000000.0),
+            None => String::from("+Inf")
+        };
+        let _ = writeln!(result, "gis_dns_query_seconds_bucket{{le=\"{}\"}} {}", bound, total);
+    }
+    let _ = writeln!(result, "gis_dns_query_seconds_sum {}", QUERY_LATENCY.sum_us.load(Ordering::Relaxed) as f64 / 1000000.0);
+    let _ = writeln!(result, "gis_dns_query_seconds_count {}", QUERY_LATENCY.get_count());
+    let _ = writeln!(result, "# HELP gis_dns_shed_queries_total DNS queries dropped because of overload");
+    let _ = writeln!(result, "# TYPE gis_dns_shed_queries_total counter");
+    let _ = writeln!(result, "gis_dns_shed_queries_total {}", SHED_QUERIES.load(Ordering::Relaxed));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use super::*;
+
+    #[test]
+    fn latency() {
+        let latency = Latency::new();
+        assert_eq!(None, latency.get_quantile(0.5));
+        for _ in 0..98 {
+            latency.add(Duration::from_micros(80));
+        }
+        latency.add(Duration::from_millis(20));
+        latency.add(Duration::from_secs(2));
+        assert_eq!(Some(100), latency.get_quantile(0.5));
+        assert_eq!(Some(25000), latency.get_quantile(0.99));
+        assert_eq!(Some(u64::MAX), latency.get_quantile(1.0));
+    }
+
+    #[test]
+    fn shedding() {
+        let pool = WorkerPool::start(1, 2);
+        let (block_tx, block_rx) = channel::<()>();
+        let (done_tx, done_rx) = channel();
+        // The only worker waits here, so other jobs stay in the queue
+        assert!(pool.submit(move || { let _ = block_rx.recv(); }));
+        while pool.get_queue_len() > 0 {
+            std::thread::yield_now();
+        }
+        for i in 0..2 {
+            let done_tx = done_tx.clone();
+            assert!(pool.submit(move || done_tx.send(i).unwrap()));
+        }
+        assert!(!pool.submit(|| {}));
+
+        block_tx.send(()).unwrap();
+        assert_eq!(vec![0, 1], done_rx.iter().take(2).collect::<Vec<_>>());
+    }
+}
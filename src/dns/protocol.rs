@@ -38,6 +38,7 @@ pub enum QueryType {
     AAAA,  // 28
     SRV,   // 33
     OPT,   // 41
+    TLSA,  // 52
 }
 
 impl QueryType {
@@ -53,6 +54,7 @@ impl QueryType {
             QueryType::AAAA => 28,
             QueryType::SRV => 33,
             QueryType::OPT => 41,
+            QueryType::TLSA => 52,
         }
     }
 
@@ -67,9 +69,26 @@ impl QueryType {
             28 => QueryType::AAAA,
             33 => QueryType::SRV,
             41 => QueryType::OPT,
+            52 => QueryType::TLSA,
             _ => QueryType::UNKNOWN(num),
         }
     }
+
+    /// Parses type name like "AAAA", case insensitive
+    pub fn from_name(name: &str) -> Option<QueryType> {
+        match name.to_uppercase().as_str() {
+            "A" => Some(QueryType::A),
+            "NS" => Some(QueryType::NS),
+            "CNAME" => Some(QueryType::CNAME),
+            "SOA" => Some(QueryType::SOA),
+            "MX" => Some(QueryType::MX),
+            "TXT" => Some(QueryType::TXT),
+            "AAAA" => Some(QueryType::AAAA),
+            "SRV" => Some(QueryType::SRV),
+            "TLSA" => Some(QueryType::TLSA),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, Eq, Ord, Serialize, Deserialize)]
@@ -162,8 +181,17 @@ pub enum DnsRecord {
     OPT {
         packet_len: u16,
         flags: u32,
-        data: String,
+        data: Vec<u8>,
     }, // 41
+    TLSA {
+        domain: String,
+        usage: u8,
+        selector: u8,
+        matching: u8,
+        /// Certificate association data in hex
+        data: String,
+        ttl: TransientTtl,
+    }, // 52
 }
 
 impl DnsRecord {
@@ -305,13 +333,28 @@ impl DnsRecord {
                     ttl: TransientTtl(ttl),
                 })
             }
-            QueryType::OPT => {
-                let mut data = String::new();
+            QueryType::TLSA => {
+                let usage = buffer.read()?;
+                let selector = buffer.read()?;
+                let matching = buffer.read()?;
 
                 let cur_pos = buffer.pos();
-                data.push_str(&String::from_utf8_lossy(
-                    buffer.get_range(cur_pos, data_len as usize)?,
-                ));
+                let len = (data_len as usize).saturating_sub(3);
+                let data = crate::commons::to_hex(buffer.get_range(cur_pos, len)?);
+                buffer.step(len)?;
+
+                Ok(DnsRecord::TLSA {
+                    domain,
+                    usage,
+                    selector,
+                    matching,
+                    data,
+                    ttl: TransientTtl(ttl),
+                })
+            }
+            QueryType::OPT => {
+                let cur_pos = buffer.pos();
+                let data = buffer.get_range(cur_pos, data_len as usize)?.to_vec();
                 buffer.step(data_len as usize)?;
 
                 Ok(DnsRecord::OPT {
@@ -494,6 +537,28 @@ impl DnsRecord {
                     buffer.write_u8(*b)?;
                 }
             }
+            DnsRecord::TLSA {
+                ref domain,
+                usage,
+                selector,
+                matching,
+                ref data,
+                ttl: TransientTtl(ttl),
+            } => {
+                let data = crate::commons::from_hex(data).unwrap_or_default();
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::TLSA.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(3 + data.len() as u16)?;
+
+                buffer.write_u8(usage)?;
+                buffer.write_u8(selector)?;
+                buffer.write_u8(matching)?;
+                for b in &data {
+                    buffer.write_u8(*b)?;
+                }
+            }
             DnsRecord::OPT { .. } => {}
             DnsRecord::UNKNOWN { .. } => {
                 println!("Skipping record: {:?}", self);
@@ -514,6 +579,7 @@ impl DnsRecord {
             DnsRecord::UNKNOWN { qtype, .. } => QueryType::UNKNOWN(qtype),
             DnsRecord::SOA { .. } => QueryType::SOA,
             DnsRecord::TXT { .. } => QueryType::TXT,
+            DnsRecord::TLSA { .. } => QueryType::TLSA,
             DnsRecord::OPT { .. } => QueryType::OPT,
         }
     }
@@ -528,11 +594,28 @@ impl DnsRecord {
             | DnsRecord::MX { ref domain, .. }
             | DnsRecord::UNKNOWN { ref domain, .. }
             | DnsRecord::SOA { ref domain, .. }
-            | DnsRecord::TXT { ref domain, .. } => Some(domain.clone()),
+            | DnsRecord::TXT { ref domain, .. }
+            | DnsRecord::TLSA { ref domain, .. } => Some(domain.clone()),
             DnsRecord::OPT { .. } => None,
         }
     }
 
+    pub fn set_domain(&mut self, name: &str) {
+        match self {
+            DnsRecord::A { domain, .. }
+            | DnsRecord::AAAA { domain, .. }
+            | DnsRecord::NS { domain, .. }
+            | DnsRecord::CNAME { domain, .. }
+            | DnsRecord::SRV { domain, .. }
+            | DnsRecord::MX { domain, .. }
+            | DnsRecord::UNKNOWN { domain, .. }
+            | DnsRecord::SOA { domain, .. }
+            | DnsRecord::TXT { domain, .. }
+            | DnsRecord::TLSA { domain, .. } => *domain = name.to_owned(),
+            DnsRecord::OPT { .. } => {}
+        }
+    }
+
     pub fn get_ttl(&self) -> u32 {
         match *self {
             DnsRecord::A {
@@ -570,6 +653,10 @@ impl DnsRecord {
             | DnsRecord::TXT {
                 ttl: TransientTtl(ttl),
                 ..
+            }
+            | DnsRecord::TLSA {
+                ttl: TransientTtl(ttl),
+                ..
             } => ttl,
             DnsRecord::OPT { .. } => 0,
         }
@@ -794,6 +881,27 @@ impl fmt::Display for DnsQuestion {
     }
 }
 
+/// Option code of EDNS Client Subnet
+pub const EDNS_CLIENT_SUBNET: u16 = 8;
+
+/// Splits the data of OPT record to options by their codes, a truncated option ends the list
+fn get_edns_options(data: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut options = Vec::new();
+    let mut pos = 0;
+    while pos + 4 <= data.len() {
+        let code = u16::from_be_bytes([data[pos], data[pos + 1]]);
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        pos += 4;
+        if pos + len > data.len() {
+            break;
+        }
+        options.push((code, &data[pos..pos + len]));
+        pos += len;
+    }
+    options
+}
+
+
 /// Representation of a complete DNS packet
 ///
 /// This is the work horse of the server. A DNS packet can be read and written
@@ -880,6 +988,14 @@ impl DnsPacket {
         None
     }
 
+    /// How long to cache the negative answer (RFC 2308), the lesser of TTL of SOA record and its MINIMUM field
+    pub fn get_negative_ttl(&self) -> Option<u32> {
+        self.authorities.iter().find_map(|record| match record {
+            DnsRecord::SOA { minimum, ttl, .. } => Some((*minimum).min(ttl.0)),
+            _ => None
+        })
+    }
+
     pub fn get_random_a(&self) -> Option<String> {
         if !self.answers.is_empty() {
             let idx = random::<usize>() % self.answers.len();
@@ -915,6 +1031,31 @@ impl DnsPacket {
         unresolved
     }
 
+    /// Tells if the packet has EDNS Client Subnet option (RFC 7871), a part of the address of the client
+    pub fn has_client_subnet(&self) -> bool {
+        self.resources.iter().any(|rec| match rec {
+            DnsRecord::OPT { data, .. } => get_edns_options(data).iter().any(|(code, _)| *code == EDNS_CLIENT_SUBNET),
+            _ => false
+        })
+    }
+
+    /// Removes EDNS Client Subnet options, other options are kept
+    pub fn strip_client_subnet(&mut self) {
+        for rec in self.resources.iter_mut() {
+            if let DnsRecord::OPT { data, .. } = rec {
+                let mut stripped = Vec::with_capacity(data.len());
+                for (code, value) in get_edns_options(data) {
+                    if code != EDNS_CLIENT_SUBNET {
+                        stripped.extend_from_slice(&code.to_be_bytes());
+                        stripped.extend_from_slice(&(value.len() as u16).to_be_bytes());
+                        stripped.extend_from_slice(value);
+                    }
+                }
+                *data = stripped;
+            }
+        }
+    }
+
     pub fn get_resolved_ns(&self, qname: &str) -> Option<String> {
         let mut new_authorities = Vec::new();
         for auth in &self.authorities {
@@ -1090,4 +1231,20 @@ mod tests {
         assert_eq!(packet.answers[2], parsed_packet.answers[2]);
         assert_eq!(packet.answers[3], parsed_packet.answers[3]);
     }
+
+    #[test]
+    fn test_strip_client_subnet() {
+        let mut packet = DnsPacket::new();
+        // Cookie option, then client subnet 192.168.1.0/24 with a non-ASCII byte
+        let data = vec![0, 10, 0, 2, 0xAB, 0xCD, 0, 8, 0, 7, 0, 1, 24, 0, 192, 168, 1];
+        packet.resources.push(DnsRecord::OPT { packet_len: 1232, flags: 0, data });
+        assert!(packet.has_client_subnet());
+
+        packet.strip_client_subnet();
+        assert!(!packet.has_client_subnet());
+        assert_eq!(DnsRecord::OPT { packet_len: 1232, flags: 0, data: vec![0, 10, 0, 2, 0xAB, 0xCD] }, packet.resources[0]);
+
+        // Truncated options are ignored
+        assert!(get_edns_options(&[0, 8, 0, 7, 0, 1]).is_empty());
+    }
 }
@@ -2,13 +2,20 @@
 //! incoming queries
 
 use std::sync::Arc;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Instant;
 use std::vec::Vec;
 
 use derive_more::{Display, Error, From};
 
 use crate::dns::context::ServerContext;
+use crate::dns::hints::get_root_hints;
 use crate::dns::protocol::{DnsPacket, QueryType, ResultCode};
-use rand::seq::IteratorRandom;
+use crate::dns::routes::{nxdomain, RouteTarget};
+use rand::Rng;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
 
 #[derive(Debug, Display, From, Error)]
 pub enum ResolveError {
@@ -16,6 +23,8 @@ pub enum ResolveError {
     Cache(crate::dns::cache::CacheError),
     Io(std::io::Error),
     NoServerFound,
+    /// The same query of other client has failed, and this one was waiting for it
+    InFlightFailed,
 }
 
 type Result<T> = std::result::Result<T, ResolveError>;
@@ -52,12 +61,41 @@ pub trait DnsResolver {
             }
         }
 
+        // Identical queries of other clients wait for this one, and get the same answer
+        context.in_flight
+            .resolve(qname, qtype, || self.resolve_uncached(qname, qtype))
+            .map_err(|e| e.unwrap_or(ResolveError::InFlightFailed))
+    }
+
+    /// Looks for the name in filters and sources of domains, and then performs the query
+    fn resolve_uncached(&mut self, qname: &str, qtype: QueryType) -> Result<DnsPacket> {
+        let context = self.get_context();
+
         for filter in context.filters.iter() {
             if let Some(packet) = filter.lookup(qname, qtype) {
                 return Ok(packet);
             }
         }
 
+        match context.get_route(qname) {
+            Some((_, RouteTarget::Source(name))) => {
+                let packet = context.get_source(&name).and_then(|source| source.lookup(qname, qtype));
+                return Ok(packet.unwrap_or_else(|| nxdomain(qname, qtype)));
+            }
+            Some((_, RouteTarget::Zone)) => return Ok(nxdomain(qname, qtype)),
+            Some((_, RouteTarget::Forward(upstreams))) => {
+                let retries = upstreams.len() - 1;
+                return ForwardingDnsResolver::new(Arc::clone(&context), upstreams, retries, false).perform(qname, qtype);
+            }
+            None => {
+                for (_, source) in context.sources.iter() {
+                    if let Some(packet) = source.lookup(qname, qtype) {
+                        return Ok(packet);
+                    }
+                }
+            }
+        }
+
         self.perform(qname, qtype)
     }
 
@@ -66,15 +104,70 @@ pub trait DnsResolver {
 
 /// A Forwarding DNS Resolver
 ///
-/// This resolver uses an external DNS server to service a query
+/// This resolver uses an external DNS server to service a query.
+/// Names are not minimized here: a forwarder resolves the whole name for us, so it has to get it anyway,
+/// and RFC 9156 is about queries to authoritative servers, that are made by `RecursiveDnsResolver`.
 pub struct ForwardingDnsResolver {
     context: Arc<ServerContext>,
     upstreams: Vec<String>,
+    /// How many times to repeat failed query, every time with next upstream
+    retries: usize,
+    /// Send every query to all upstreams at once and take the first answer
+    parallel: bool,
 }
 
 impl ForwardingDnsResolver {
-    pub fn new(context: Arc<ServerContext>, upstreams: Vec<String>) -> ForwardingDnsResolver {
-        ForwardingDnsResolver { context, upstreams }
+    pub fn new(context: Arc<ServerContext>, upstreams: Vec<String>, retries: usize, parallel: bool) -> ForwardingDnsResolver {
+        ForwardingDnsResolver { context, upstreams, retries, parallel }
+    }
+
+    /// Sends the query to working upstreams in separate threads, or to all of them if none works, and returns first successful response
+    fn race(&self, qname: &str, qtype: QueryType) -> Result<DnsPacket> {
+        let (tx, rx) = channel();
+        let health = &self.context.forwarders;
+        let mut upstreams: Vec<&String> = self.upstreams.iter().filter(|upstream| !health.is_down(upstream)).collect();
+        if upstreams.is_empty() {
+            upstreams = self.upstreams.iter().collect();
+        }
+        for upstream in upstreams {
+            let tx = tx.clone();
+            let context = Arc::clone(&self.context);
+            let qname = qname.to_owned();
+            let upstream = upstream.clone();
+            thread::spawn(move || {
+                let _ = tx.send(ask_upstream(&context, &qname, qtype, &upstream));
+            });
+        }
+        drop(tx);
+
+        let mut result = Err(ResolveError::NoServerFound);
+        // This loop ends when all threads have sent their results
+        for answer in rx.iter() {
+            match answer {
+                Ok(packet) if packet.header.rescode != ResultCode::SERVFAIL => return Ok(packet),
+                answer => result = answer
+            }
+        }
+        result
+    }
+}
+
+/// Sends the query to the forwarder, and remembers if it works. SERVFAIL answers count as failures, and the next forwarder is asked.
+fn ask_upstream(context: &ServerContext, qname: &str, qtype: QueryType, upstream: &str) -> Result<DnsPacket> {
+    let start = Instant::now();
+    match context.client.send_query(qname, qtype, upstream, true) {
+        Ok(packet) if packet.header.rescode == ResultCode::SERVFAIL => {
+            context.forwarders.failure(upstream);
+            Ok(packet)
+        }
+        Ok(packet) => {
+            context.forwarders.success(upstream, start.elapsed());
+            Ok(packet)
+        }
+        Err(e) => {
+            context.forwarders.failure(upstream);
+            Err(e.into())
+        }
     }
 }
 
@@ -84,16 +177,41 @@ impl DnsResolver for ForwardingDnsResolver {
     }
 
     fn perform(&mut self, qname: &str, qtype: QueryType) -> Result<DnsPacket> {
-        let mut random = rand::thread_rng();
-        let upstream = self.upstreams.iter().choose(&mut random).unwrap();
-        let result = match self.context.cache.lookup(qname, qtype) {
-            None => {
-                self.context.client.send_query(qname, qtype, upstream, true)?
+        if self.upstreams.is_empty() {
+            return Err(ResolveError::NoServerFound);
+        }
+        // Cached answers are not stored again, their TTLs continue to count down
+        if let Some(packet) = self.context.cache.lookup(qname, qtype) {
+            return Ok(packet);
+        }
+        let start = rand::thread_rng().gen_range(0..self.upstreams.len());
+        // Working forwarders go first, failed ones are asked only when others fail too
+        let upstreams = self.context.forwarders.order(&self.upstreams, start);
+        let mut result = Err(ResolveError::NoServerFound);
+        for attempt in 0..=self.retries {
+            result = match self.parallel {
+                true => self.race(qname, qtype),
+                false => ask_upstream(&self.context, qname, qtype, &upstreams[attempt % upstreams.len()])
+            };
+            match &result {
+                Ok(packet) if packet.header.rescode == ResultCode::SERVFAIL => debug!("Attempt {} to resolve {} got SERVFAIL", attempt + 1, qname),
+                Ok(_) => break,
+                Err(e) => debug!("Attempt {} to resolve {} failed: {}", attempt + 1, qname, e)
             }
-            Some(packet) => packet
-        };
+        }
+        let result = result?;
+        // Failures are not cached, the next query asks forwarders again
+        if result.header.rescode == ResultCode::SERVFAIL {
+            return Ok(result);
+        }
 
         self.context.cache.store(&result.answers)?;
+        if result.header.rescode == ResultCode::NXDOMAIN {
+            let ttl = result.get_negative_ttl().unwrap_or(self.context.negative_ttl);
+            if ttl > 0 {
+                self.context.cache.store_nxdomain(qname, qtype, ttl)?;
+            }
+        }
 
         Ok(result)
     }
@@ -122,6 +240,8 @@ impl DnsResolver for RecursiveDnsResolver {
         // moving towards the root servers. I.e. check "google.com", then "com",
         // and finally "".
         let mut tentative_ns = None;
+        // How many labels of the qname are already known to the current name server
+        let mut known = 0;
 
         let labels = qname.split('.').collect::<Vec<&str>>();
         for lbl_idx in 0..labels.len() + 1 {
@@ -137,21 +257,70 @@ impl DnsResolver for RecursiveDnsResolver {
             {
                 Some(addr) => {
                     tentative_ns = Some(addr);
+                    known = labels.len() - lbl_idx;
                     break;
                 }
                 None => continue,
             }
         }
 
+        // Root hints could expire from cache, or may never been there
+        if tentative_ns.is_none() && self.context.root_hints {
+            let hints = get_root_hints();
+            let _ = self.context.cache.store(&hints);
+            tentative_ns = self.context.cache.lookup("", QueryType::NS)
+                .and_then(|qr| qr.get_unresolved_ns(""))
+                .and_then(|ns| self.context.cache.lookup(&ns, QueryType::A))
+                .and_then(|qr| qr.get_random_a());
+            known = 0;
+        }
+
         let mut ns = tentative_ns.ok_or_else(|| ResolveError::NoServerFound)?;
+        let mut minimize = self.context.qname_minimization;
 
         // Start querying name servers
         loop {
-            println!("attempting lookup of {:?} {} with ns {}", qtype, qname, ns);
-
             let ns_copy = ns.clone();
-
             let server = format!("{}:{}", ns_copy.as_str(), 53);
+
+            // With QNAME minimization (RFC 9156) we reveal only one more label than the server already knows
+            if minimize && known + 1 < labels.len() {
+                let name = labels[labels.len() - known - 1..].join(".");
+                trace!("attempting minimized lookup of {} for {} with ns {}", &name, qname, ns);
+                let response = match self.context.client.send_query(&name, QueryType::A, &server, false) {
+                    Ok(response) => response,
+                    Err(e) => {
+                        debug!("Minimized query {} failed: {}, sending full name", &name, e);
+                        minimize = false;
+                        continue;
+                    }
+                };
+                known += 1;
+                match response.header.rescode {
+                    ResultCode::NOERROR => {}
+                    _ => {
+                        // Some servers answer badly for empty non-terminals, so we just ask the full name
+                        minimize = false;
+                        continue;
+                    }
+                }
+                if let Some(new_ns) = response.get_resolved_ns(&name) {
+                    ns = new_ns.clone();
+                    let _ = self.context.cache.store(&response.authorities);
+                    let _ = self.context.cache.store(&response.resources);
+                    continue;
+                }
+                if let Some(new_ns_name) = response.get_unresolved_ns(&name) {
+                    let recursive_response = self.resolve(&new_ns_name, QueryType::A, true)?;
+                    if let Some(new_ns) = recursive_response.get_random_a() {
+                        ns = new_ns.clone();
+                    }
+                }
+                // No delegation, the same server is authoritative for the next label
+                continue;
+            }
+
+            trace!("attempting lookup of {:?} {} with ns {}", qtype, qname, ns);
             let response = self
                 .context
                 .client
@@ -166,7 +335,7 @@ impl DnsResolver for RecursiveDnsResolver {
             }
 
             if response.header.rescode == ResultCode::NXDOMAIN {
-                if let Some(ttl) = response.get_ttl_from_soa() {
+                if let Some(ttl) = response.get_negative_ttl() {
                     let _ = self.context.cache.store_nxdomain(qname, qtype, ttl);
                 }
                 return Ok(response.clone());
@@ -235,9 +404,11 @@ mod tests {
 
         match Arc::get_mut(&mut context) {
             Some(mut ctx) => {
-                ctx.resolve_strategy = ResolveStrategy::Forward {
-                    upstreams: vec![String::from("127.0.0.1:53")]
-                };
+                ctx.set_resolve_strategy(ResolveStrategy::Forward {
+                    upstreams: vec![String::from("127.0.0.1:53")],
+                    retries: 0,
+                    parallel: false
+                });
             }
             None => panic!(),
         }
@@ -295,6 +466,86 @@ mod tests {
         };
     }
 
+    #[test]
+    fn test_forwarding_resolver_retries() {
+        let mut context = create_test_context(Box::new(|qname, _, server, _| {
+            if server == "127.0.0.1:1" {
+                return Err(crate::dns::client::ClientError::TimeOut);
+            }
+
+            let mut packet = DnsPacket::new();
+            packet.answers.push(DnsRecord::A {
+                domain: qname.to_string(),
+                addr: "127.0.0.1".parse().unwrap(),
+                ttl: TransientTtl(3600),
+            });
+            Ok(packet)
+        }));
+
+        match Arc::get_mut(&mut context) {
+            Some(ctx) => {
+                ctx.set_resolve_strategy(ResolveStrategy::Forward {
+                    upstreams: vec![String::from("127.0.0.1:1"), String::from("127.0.0.1:53")],
+                    retries: 1,
+                    parallel: false
+                });
+            }
+            None => panic!(),
+        }
+
+        // Whichever upstream is chosen first, the second attempt goes to another one
+        let mut resolver = context.create_resolver(Arc::clone(&context));
+        for _ in 0..5 {
+            let res = match resolver.resolve("google.com", QueryType::A, true) {
+                Ok(x) => x,
+                Err(_) => panic!(),
+            };
+            assert_eq!(1, res.answers.len());
+        }
+    }
+
+    #[test]
+    fn test_forwarding_resolver_skips_failed() {
+        let mut context = create_test_context(Box::new(|qname, _, server, _| {
+            let mut packet = DnsPacket::new();
+            if server == "127.0.0.1:1" {
+                return Err(crate::dns::client::ClientError::TimeOut);
+            } else if server == "127.0.0.1:2" {
+                packet.header.rescode = ResultCode::SERVFAIL;
+            } else {
+                packet.answers.push(DnsRecord::A {
+                    domain: qname.to_string(),
+                    addr: "127.0.0.1".parse().unwrap(),
+                    ttl: TransientTtl(3600),
+                });
+            }
+            Ok(packet)
+        }));
+
+        match Arc::get_mut(&mut context) {
+            Some(ctx) => {
+                ctx.set_resolve_strategy(ResolveStrategy::Forward {
+                    upstreams: vec![String::from("127.0.0.1:1"), String::from("127.0.0.1:2"), String::from("127.0.0.1:53")],
+                    retries: 0,
+                    parallel: false
+                });
+            }
+            None => panic!(),
+        }
+
+        // Every failed forwarder is asked at most once, then queries go to the working one
+        let mut resolver = context.create_resolver(Arc::clone(&context));
+        let mut failures = 0;
+        for i in 0..10 {
+            match resolver.resolve(&format!("host{}.example.com", i), QueryType::A, true) {
+                Ok(packet) if packet.answers.len() == 1 => {}
+                _ => failures += 1
+            }
+        }
+        assert!(failures <= 2);
+        assert!(!context.forwarders.is_down("127.0.0.1:53"));
+    }
+
     #[test]
     fn test_recursive_resolver_with_no_nameserver() {
         let context = create_test_context(Box::new(|_, _, _, _| {
@@ -311,6 +562,36 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_recursive_resolver_with_root_hints() {
+        let mut context = create_test_context(Box::new(|qname, _, server, _| {
+            let mut packet = DnsPacket::new();
+            if server.ends_with(":53") {
+                packet.answers.push(DnsRecord::A {
+                    domain: qname.to_string(),
+                    addr: "127.0.0.1".parse().unwrap(),
+                    ttl: TransientTtl(3600),
+                });
+            }
+            Ok(packet)
+        }));
+
+        match Arc::get_mut(&mut context) {
+            Some(ctx) => {
+                ctx.root_hints = true;
+            }
+            None => panic!(),
+        }
+
+        // No name servers in cache, but root servers are known anyway
+        let mut resolver = context.create_resolver(Arc::clone(&context));
+        let res = match resolver.resolve("google.com", QueryType::A, true) {
+            Ok(x) => x,
+            Err(_) => panic!(),
+        };
+        assert_eq!(1, res.answers.len());
+    }
+
     #[test]
     fn test_recursive_resolver_with_missing_a_record() {
         let context = create_test_context(Box::new(|_, _, _, _| {
@@ -463,6 +744,52 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_recursive_resolver_qname_minimization() {
+        let queried = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let queried_copy = Arc::clone(&queried);
+        let mut context = create_test_context(Box::new(move |qname, _, _, _| {
+            queried_copy.lock().unwrap().push(qname.to_string());
+            let mut packet = DnsPacket::new();
+            if qname == "www.example.com" {
+                packet.answers.push(DnsRecord::A {
+                    domain: "www.example.com".to_string(),
+                    addr: "127.0.0.1".parse().unwrap(),
+                    ttl: TransientTtl(3600),
+                });
+            }
+            Ok(packet)
+        }));
+
+        match Arc::get_mut(&mut context) {
+            Some(ctx) => {
+                ctx.qname_minimization = true;
+            }
+            None => panic!(),
+        }
+
+        let mut nameservers = Vec::new();
+        nameservers.push(DnsRecord::NS {
+            domain: "".to_string(),
+            host: "a.root-servers.net".to_string(),
+            ttl: TransientTtl(3600),
+        });
+        nameservers.push(DnsRecord::A {
+            domain: "a.root-servers.net".to_string(),
+            addr: "127.0.0.1".parse().unwrap(),
+            ttl: TransientTtl(3600),
+        });
+        let _ = context.cache.store(&nameservers);
+
+        let mut resolver = context.create_resolver(Arc::clone(&context));
+        let res = match resolver.resolve("www.example.com", QueryType::A, true) {
+            Ok(x) => x,
+            Err(_) => panic!(),
+        };
+        assert_eq!(1, res.answers.len());
+        assert_eq!(vec!["com", "example.com", "www.example.com"], *queried.lock().unwrap());
+    }
+
     #[test]
     fn test_recursive_resolver_successfully() {
         let context = create_test_context(Box::new(|qname, _, _, _| {
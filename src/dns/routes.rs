@@ -0,0 +1,134 @@
+//! Routing of zones to sources of domains: blockchains, local zones or other DNS servers.
+//! Names without a route are looked up in all sources in turn, and then resolved as usual.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::dns::protocol::{DnsPacket, DnsQuestion, QueryType, ResultCode};
+
+/// Name of the source with the main blockchain
+pub const MAIN_SOURCE: &str = "main";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RouteTarget {
+    /// Named source of domains, like a blockchain
+    Source(String),
+    /// Only local zones, everything else doesn't exist
+    Zone,
+    /// Other DNS servers
+    Forward(Vec<String>),
+}
+
+impl FromStr for RouteTarget {
+    type Err = String;
+
+    /// Parses targets like `chain`, `chain:corp`, `zone` or `forward:127.0.0.1:9053,[::1]:9053`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = match s.split_once(':') {
+            Some((kind, value)) => (kind, Some(value.trim())),
+            None => (s, None)
+        };
+        match (kind.trim(), value) {
+            ("chain", None) => Ok(RouteTarget::Source(String::from(MAIN_SOURCE))),
+            ("chain", Some(name)) if !name.is_empty() => Ok(RouteTarget::Source(name.to_owned())),
+            ("zone", None) => Ok(RouteTarget::Zone),
+            ("forward", Some(upstreams)) if !upstreams.is_empty() => {
+                Ok(RouteTarget::Forward(upstreams.split(',').map(|u| u.trim().to_owned()).collect()))
+            }
+            _ => Err(format!("Wrong route target '{}'", s))
+        }
+    }
+}
+
+impl fmt::Display for RouteTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteTarget::Source(name) => write!(f, "chain '{}'", name),
+            RouteTarget::Zone => write!(f, "local zones"),
+            RouteTarget::Forward(upstreams) => write!(f, "forwarders {}", upstreams.join(", "))
+        }
+    }
+}
+
+/// Zones with their targets, the most specific zone wins
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RouteTable {
+    routes: Vec<(String, RouteTarget)>,
+}
+
+impl RouteTable {
+    /// Makes the table from settings like `{ ygg = "chain", corp = "chain:corp" }`, all sources must be known
+    pub fn parse(routes: &HashMap<String, String>, sources: &[&str]) -> Result<Self, String> {
+        let mut result = Vec::new();
+        for (zone, target) in routes {
+            let zone = zone.trim_matches('.').to_lowercase();
+            if zone.is_empty() {
+                return Err(String::from("Empty zone in routes"));
+            }
+            let target = RouteTarget::from_str(target)?;
+            if let RouteTarget::Source(name) = &target {
+                if !sources.contains(&name.as_str()) {
+                    return Err(format!("Unknown chain '{}' in route for '{}'", name, &zone));
+                }
+            }
+            result.push((zone, target));
+        }
+        // Longer zones go first, so the first match is the most specific one
+        result.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then(a.cmp(b)));
+        Ok(RouteTable { routes: result })
+    }
+
+    /// Finds the route for the name, returns its zone and target
+    pub fn find(&self, qname: &str) -> Option<(&str, &RouteTarget)> {
+        self.routes
+            .iter()
+            .find(|(zone, _)| qname == zone || (qname.ends_with(zone.as_str()) && qname[..qname.len() - zone.len()].ends_with('.')))
+            .map(|(zone, target)| (zone.as_str(), target))
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}
+
+/// Answer for names in routed zones, that their sources don't have
+pub fn nxdomain(qname: &str, qtype: QueryType) -> DnsPacket {
+    let mut packet = DnsPacket::new();
+    packet.questions.push(DnsQuestion::new(String::from(qname), qtype));
+    packet.header.rescode = ResultCode::NXDOMAIN;
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes() {
+        let mut settings = HashMap::new();
+        settings.insert(String::from("ygg"), String::from("chain"));
+        settings.insert(String::from("corp"), String::from("chain:corp"));
+        settings.insert(String::from("lan."), String::from("zone"));
+        settings.insert(String::from("dev.corp"), String::from("forward:10.0.0.1:53, 10.0.0.2:53"));
+        let table = RouteTable::parse(&settings, &[MAIN_SOURCE, "corp"]).unwrap();
+        assert_eq!(4, table.len());
+
+        assert_eq!(Some(("ygg", &RouteTarget::Source(String::from(MAIN_SOURCE)))), table.find("www.example.ygg"));
+        assert_eq!(Some(("corp", &RouteTarget::Source(String::from("corp")))), table.find("wiki.corp"));
+        assert_eq!(Some(("lan", &RouteTarget::Zone)), table.find("lan"));
+        let forward = RouteTarget::Forward(vec![String::from("10.0.0.1:53"), String::from("10.0.0.2:53")]);
+        assert_eq!(Some(("dev.corp", &forward)), table.find("git.dev.corp"));
+        assert_eq!(None, table.find("example.com"));
+        assert_eq!(None, table.find("notygg"));
+
+        settings.insert(String::from("test"), String::from("chain:unknown"));
+        assert!(RouteTable::parse(&settings, &[MAIN_SOURCE, "corp"]).is_err());
+        assert!(RouteTarget::from_str("forward:").is_err());
+        assert!(RouteTarget::from_str("cache").is_err());
+    }
+}
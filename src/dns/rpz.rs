@@ -0,0 +1,190 @@
+//! Response policy zones (RPZ) support, only QNAME triggers are implemented.
+//! See https://datatracker.ietf.org/doc/html/draft-vixie-dnsop-dns-rpz
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use crate::dns::filter::DnsFilter;
+use crate::dns::protocol::{DnsPacket, DnsQuestion, DnsRecord, QueryType, ResultCode};
+use crate::dns::zonefile::parse_zone;
+
+/// What to do with the query that matched some trigger
+#[derive(Debug, Clone, PartialEq)]
+pub enum PolicyAction {
+    /// `CNAME .`
+    NxDomain,
+    /// `CNAME *.`
+    NoData,
+    /// `CNAME rpz-passthru.`
+    PassThru,
+    /// `CNAME rpz-drop.`, we answer with REFUSED
+    Drop,
+    /// Any other records, they are returned instead of real ones
+    LocalData(Vec<DnsRecord>),
+}
+
+pub struct RpzFilter {
+    /// Zone name, just for logging
+    name: String,
+    rules: HashMap<String, PolicyAction>,
+    /// Rules for `*.domain` triggers, stored by `domain`
+    wildcards: HashMap<String, PolicyAction>,
+}
+
+impl RpzFilter {
+    pub fn from_file(filename: &str) -> Self {
+        let mut text = String::new();
+        match File::open(filename) {
+            Ok(mut file) => {
+                if let Err(e) = file.read_to_string(&mut text) {
+                    warn!("Error reading RPZ file '{}': {}", filename, e);
+                }
+            }
+            Err(e) => {
+                warn!("Error opening RPZ file '{}': {}", filename, e);
+            }
+        }
+        Self::from_text(filename, &text)
+    }
+
+    pub fn from_text(name: &str, text: &str) -> Self {
+        let records = parse_zone(text, "");
+        // Triggers are relative to the policy zone name, we take it from SOA
+        let origin = records.iter().find_map(|r| match r {
+            DnsRecord::SOA { domain, .. } => Some(domain.clone()),
+            _ => None
+        }).unwrap_or_default();
+        let suffix = format!(".{}", &origin);
+
+        let mut rules = HashMap::new();
+        let mut wildcards = HashMap::new();
+        for record in records {
+            let owner = match record.get_domain() {
+                Some(domain) => domain.to_lowercase(),
+                None => continue
+            };
+            let trigger = match owner.strip_suffix(&suffix) {
+                Some(trigger) if !origin.is_empty() => trigger.to_owned(),
+                _ if owner == origin => continue,
+                _ => owner.clone()
+            };
+            if trigger.ends_with(".rpz-ip") || trigger.ends_with(".rpz-nsdname") || trigger.ends_with(".rpz-nsip") || trigger.ends_with(".rpz-client-ip") {
+                debug!("Skipping unsupported RPZ trigger {}", &trigger);
+                continue;
+            }
+            let action = match &record {
+                DnsRecord::SOA { .. } | DnsRecord::NS { .. } => continue,
+                DnsRecord::CNAME { host, .. } if host.is_empty() => PolicyAction::NxDomain,
+                DnsRecord::CNAME { host, .. } if host == "*" => PolicyAction::NoData,
+                DnsRecord::CNAME { host, .. } if host == "rpz-passthru" => PolicyAction::PassThru,
+                DnsRecord::CNAME { host, .. } if host == "rpz-drop" => PolicyAction::Drop,
+                DnsRecord::CNAME { host, .. } if host == "rpz-tcp-only" => continue,
+                _ => PolicyAction::LocalData(vec![record.clone()])
+            };
+            let (map, key) = match trigger.strip_prefix("*.") {
+                Some(base) => (&mut wildcards, base.to_owned()),
+                None => (&mut rules, trigger)
+            };
+            match (map.get_mut(&key), action) {
+                (Some(PolicyAction::LocalData(list)), PolicyAction::LocalData(mut new)) => list.append(&mut new),
+                (_, action) => { map.insert(key, action); }
+            }
+        }
+        info!("Loaded {} rules and {} wildcard rules from RPZ '{}'", rules.len(), wildcards.len(), name);
+        RpzFilter { name: name.to_owned(), rules, wildcards }
+    }
+
+    fn find_action(&self, qname: &str) -> Option<&PolicyAction> {
+        if let Some(action) = self.rules.get(qname) {
+            return Some(action);
+        }
+        let mut name = qname;
+        while let Some(pos) = name.find('.') {
+            name = &name[pos + 1..];
+            if let Some(action) = self.wildcards.get(name) {
+                return Some(action);
+            }
+        }
+        None
+    }
+}
+
+impl DnsFilter for RpzFilter {
+    fn lookup(&self, qname: &str, qtype: QueryType) -> Option<DnsPacket> {
+        let qname = qname.to_lowercase();
+        let action = self.find_action(&qname)?;
+        trace!("RPZ '{}' matched {} with {:?}", &self.name, &qname, action);
+        let mut packet = DnsPacket::new();
+        packet.questions.push(DnsQuestion::new(qname.clone(), qtype));
+        match action {
+            PolicyAction::PassThru => return None,
+            PolicyAction::NxDomain => packet.header.rescode = ResultCode::NXDOMAIN,
+            PolicyAction::NoData => {}
+            PolicyAction::Drop => packet.header.rescode = ResultCode::REFUSED,
+            PolicyAction::LocalData(records) => {
+                for record in records {
+                    let record_type = record.get_querytype();
+                    if record_type == qtype || record_type == QueryType::CNAME {
+                        let mut record = record.clone();
+                        record.set_domain(&qname);
+                        packet.answers.push(record);
+                    }
+                }
+            }
+        }
+        Some(packet)
+    }
+
+    fn name(&self) -> String {
+        format!("RPZ '{}'", &self.name)
+    }
+
+    fn explain(&self, qname: &str) -> Option<String> {
+        self.find_action(&qname.to_lowercase()).map(|action| format!("Policy action {:?}", action))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dns::filter::DnsFilter;
+    use crate::dns::protocol::{DnsRecord, QueryType, ResultCode};
+    use crate::dns::rpz::RpzFilter;
+
+    const ZONE: &str = r#"
+$TTL 300
+$ORIGIN rpz.local.
+@ SOA localhost. root.localhost. 1 3600 300 604800 60
+  NS localhost.
+bad.com CNAME .
+*.bad.com CNAME .
+empty.org CNAME *.
+good.bad.com CNAME rpz-passthru.
+walled.net A 10.0.0.1
+walled.net AAAA fd00::1
+32.1.0.0.127.rpz-ip CNAME .
+"#;
+
+    #[test]
+    pub fn rpz_actions() {
+        let filter = RpzFilter::from_text("test", ZONE);
+        assert_eq!(ResultCode::NXDOMAIN, filter.lookup("bad.com", QueryType::A).unwrap().header.rescode);
+        assert_eq!(ResultCode::NXDOMAIN, filter.lookup("www.bad.com", QueryType::A).unwrap().header.rescode);
+        assert!(filter.lookup("good.bad.com", QueryType::A).is_none());
+        assert!(filter.lookup("notbad.com", QueryType::A).is_none());
+
+        let packet = filter.lookup("empty.org", QueryType::A).unwrap();
+        assert_eq!(ResultCode::NOERROR, packet.header.rescode);
+        assert!(packet.answers.is_empty());
+
+        let packet = filter.lookup("walled.net", QueryType::AAAA).unwrap();
+        assert_eq!(1, packet.answers.len());
+        match &packet.answers[0] {
+            DnsRecord::AAAA { domain, .. } => assert_eq!("walled.net", domain),
+            _ => panic!()
+        }
+    }
+}
@@ -1,23 +1,25 @@
 //! UDP and TCP server implementations for DNS
 
-use std::collections::VecDeque;
 use std::io::Write;
-use std::net::SocketAddr;
-use std::net::{Shutdown, TcpListener, TcpStream, UdpSocket};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::net::{Shutdown, TcpStream};
 use std::sync::atomic::Ordering;
-use std::sync::mpsc::{channel, Sender};
-use std::sync::{Arc, Condvar, Mutex};
+use std::sync::Arc;
 use std::thread::Builder;
+use std::time::Duration;
 
 use derive_more::{Display, Error, From};
-use rand::random;
 use log::{error, warn, debug};
 
 use crate::dns::buffer::{BytePacketBuffer, PacketBuffer, StreamPacketBuffer, VectorPacketBuffer};
 use crate::dns::context::ServerContext;
 use crate::dns::netutil::{read_packet_length, write_packet_length};
+use crate::dns::pool::WorkerPool;
 use crate::dns::protocol::{DnsPacket, DnsRecord, QueryType, ResultCode};
 use crate::dns::resolve::DnsResolver;
+use crate::dns::transfer::send_transfer;
+use crate::dns::views::Cidr;
+use crate::sockets::{bind_tcp, bind_udp};
 
 #[derive(Debug, Display, From, Error)]
 pub enum ServerError {
@@ -26,6 +28,9 @@ pub enum ServerError {
 
 type Result<T> = std::result::Result<T, ServerError>;
 
+/// Time to wait for TCP clients to send the query or to take a part of the answer, they hold a worker of the common pool
+const READ_TIMEOUT_SEC: u64 = 5;
+
 macro_rules! return_or_report {
     ( $x:expr, $message:expr ) => {
         match $x {
@@ -50,6 +55,35 @@ macro_rules! ignore_or_report {
     };
 }
 
+/// Address to listen on, and networks of clients that are allowed to send queries there
+#[derive(Clone, Debug)]
+pub struct Listener {
+    pub address: String,
+    /// Everyone is allowed if empty
+    pub allowed: Vec<Cidr>,
+    /// Port to use if we are not allowed to bind privileged port, zero to fail instead
+    pub fallback_port: u16,
+}
+
+impl Listener {
+    pub fn new(address: &str, allowed: &[String], fallback_port: u16) -> Self {
+        let allowed = allowed.iter()
+            .filter_map(|net| {
+                let cidr = Cidr::parse(net);
+                if cidr.is_none() {
+                    warn!("Wrong network '{}' in allowed clients of {}", net, address);
+                }
+                cidr
+            })
+            .collect();
+        Listener { address: address.to_owned(), allowed, fallback_port }
+    }
+
+    pub fn allows(&self, client: &IpAddr) -> bool {
+        self.allowed.is_empty() || self.allowed.iter().any(|net| net.contains(client))
+    }
+}
+
 /// Common trait for DNS servers
 pub trait DnsServer {
     /// Initialize the server and start listenening
@@ -96,7 +130,8 @@ fn resolve_cnames(lookup_list: &[DnsRecord], results: &mut Vec<DnsPacket>, resol
 ///
 /// This function will always return a valid packet, even if the request could not
 /// be performed, since we still want to send something back to the client.
-pub fn execute_query(context: Arc<ServerContext>, request: &DnsPacket) -> DnsPacket {
+/// The `client` address is used to select a view, if there are any.
+pub fn execute_query(context: Arc<ServerContext>, request: &DnsPacket, client: IpAddr) -> DnsPacket {
     let mut packet = DnsPacket::new();
     packet.header.id = request.header.id;
     packet.header.recursion_available = context.allow_recursive;
@@ -105,6 +140,9 @@ pub fn execute_query(context: Arc<ServerContext>, request: &DnsPacket) -> DnsPac
 
     if request.header.recursion_desired && !context.allow_recursive {
         packet.header.rescode = ResultCode::REFUSED;
+    } else if context.refuse_client_subnet && request.has_client_subnet() {
+        debug!("Refused query from {} with client subnet", client);
+        packet.header.rescode = ResultCode::REFUSED;
     } else if request.questions.is_empty() {
         packet.header.rescode = ResultCode::FORMERR;
     } else {
@@ -114,7 +152,14 @@ pub fn execute_query(context: Arc<ServerContext>, request: &DnsPacket) -> DnsPac
         packet.questions.push(question.clone());
 
         let mut resolver = context.create_resolver(Arc::clone(&context));
-        let rescode = match resolver.resolve(&question.name, question.qtype, request.header.recursion_desired) {
+        let view_result = context.views.iter()
+            .find(|view| view.matches(&client))
+            .and_then(|view| view.lookup(&question.name, question.qtype));
+        let result = match view_result {
+            Some(packet) => Ok(packet),
+            None => resolver.resolve(&question.name, question.qtype, request.header.recursion_desired)
+        };
+        let rescode = match result {
             Ok(result) => {
                 let rescode = result.header.rescode;
                 if result.header.authoritative_answer {
@@ -136,7 +181,10 @@ pub fn execute_query(context: Arc<ServerContext>, request: &DnsPacket) -> DnsPac
 
         packet.header.rescode = rescode;
 
-        for result in results {
+        for mut result in results {
+            if context.strip_client_subnet {
+                result.strip_client_subnet();
+            }
             for rec in result.answers {
                 packet.answers.push(rec);
             }
@@ -149,30 +197,105 @@ pub fn execute_query(context: Arc<ServerContext>, request: &DnsPacket) -> DnsPac
         }
     }
 
+    if context.minimal_responses {
+        minimize_response(&mut packet);
+    }
+
     packet
 }
 
+/// Removes records that clients don't need from the answer, like NS of the zone with their addresses.
+/// Referrals keep their NS records and glue addresses, and negative answers keep SOA for negative caching.
+/// The OPT record is always kept, as it tells the size of packets that we accept.
+fn minimize_response(packet: &mut DnsPacket) {
+    let mut answers = Vec::with_capacity(packet.answers.len());
+    for rec in packet.answers.drain(..) {
+        if !answers.contains(&rec) {
+            answers.push(rec);
+        }
+    }
+    packet.answers = answers;
+
+    let has_soa = packet.authorities.iter().any(|rec| matches!(rec, DnsRecord::SOA { .. }));
+    let has_ns = packet.authorities.iter().any(|rec| matches!(rec, DnsRecord::NS { .. }));
+    let referral = packet.answers.is_empty() && packet.header.rescode == ResultCode::NOERROR && has_ns && !has_soa;
+    if referral {
+        packet.authorities.retain(|rec| matches!(rec, DnsRecord::NS { .. }));
+        let hosts: Vec<String> = packet.authorities.iter()
+            .filter_map(|rec| match rec {
+                DnsRecord::NS { host, .. } => Some(host.to_lowercase()),
+                _ => None
+            })
+            .collect();
+        packet.resources.retain(|rec| match rec {
+            DnsRecord::A { domain, .. } | DnsRecord::AAAA { domain, .. } => hosts.contains(&domain.to_lowercase()),
+            DnsRecord::OPT { .. } => true,
+            _ => false
+        });
+        return;
+    }
+
+    if packet.answers.is_empty() {
+        packet.authorities.retain(|rec| matches!(rec, DnsRecord::SOA { .. }));
+    } else {
+        packet.authorities.clear();
+    }
+    packet.resources.retain(|rec| matches!(rec, DnsRecord::OPT { .. }));
+}
+
 /// The UDP server
 ///
 /// Accepts DNS queries through UDP, and uses the `ServerContext` to determine
-/// how to service the request. Packets are read on a single thread, after which
-/// a new thread is spawned to service the request asynchronously.
+/// how to service the request. Packets are read on a single thread, and then
+/// they are answered by workers of the common pool.
 pub struct DnsUdpServer {
     context: Arc<ServerContext>,
-    request_queue: Arc<Mutex<VecDeque<(SocketAddr, DnsPacket)>>>,
-    request_cond: Arc<Condvar>,
-    thread_count: usize,
+    listener: Listener,
+    pool: Arc<WorkerPool>,
 }
 
 impl DnsUdpServer {
-    pub fn new(context: Arc<ServerContext>, thread_count: usize) -> DnsUdpServer {
-        DnsUdpServer {
-            context,
-            request_queue: Arc::new(Mutex::new(VecDeque::new())),
-            request_cond: Arc::new(Condvar::new()),
-            thread_count,
+    pub fn new(context: Arc<ServerContext>, listener: Listener, pool: Arc<WorkerPool>) -> DnsUdpServer {
+        DnsUdpServer { context, listener, pool }
+    }
+}
+
+/// Answers one UDP query, it is called in a worker thread
+fn handle_udp_query(context: Arc<ServerContext>, socket: &UdpSocket, src: SocketAddr, request: DnsPacket) {
+    let mut size_limit = 512;
+
+    // Check for EDNS
+    if request.resources.len() == 1 {
+        if let DnsRecord::OPT { packet_len, .. } = request.resources[0] {
+            size_limit = packet_len as usize;
         }
     }
+
+    // Create a response buffer, and ask the context for an appropriate resolver
+    let mut res_buffer = VectorPacketBuffer::new();
+
+    let mut packet = execute_query(context, &request, src.ip());
+    let _ = packet.write(&mut res_buffer, size_limit);
+
+    // Fire off the response
+    let len = res_buffer.pos();
+    let data = return_or_report!(res_buffer.get_range(0, len), "Failed to get buffer data");
+    ignore_or_report!(socket.send_to(data, src), "Failed to send response packet");
+}
+
+/// Tells the client right away that we can't answer now, so it can ask another server
+fn send_overloaded(socket: &UdpSocket, src: SocketAddr, request: &DnsPacket) {
+    let mut packet = DnsPacket::new();
+    packet.header.id = request.header.id;
+    packet.header.response = true;
+    packet.header.recursion_desired = request.header.recursion_desired;
+    packet.header.rescode = ResultCode::SERVFAIL;
+    packet.questions = request.questions.clone();
+    let mut res_buffer = VectorPacketBuffer::new();
+    ignore_or_report!(packet.write(&mut res_buffer, 512), "Failed to write packet to buffer");
+    let len = res_buffer.pos();
+    let data = return_or_report!(res_buffer.get_range(0, len), "Failed to get buffer data");
+    ignore_or_report!(socket.send_to(data, src), "Failed to send response packet");
 }
 
 impl DnsServer for DnsUdpServer {
@@ -181,63 +304,7 @@ impl DnsServer for DnsUdpServer {
     /// This method takes ownership of the server, preventing the method from being called multiple times.
     fn run_server(self) -> Result<()> {
         // Bind the socket
-        let socket = UdpSocket::bind(self.context.dns_listen.as_str())
-            .expect(&format!("Cannot start DNS server on {}! Change listen address in config!", self.context.dns_listen.as_str()));
-
-        // Spawn threads for handling requests
-        for thread_id in 0..self.thread_count {
-            let socket_clone = match socket.try_clone() {
-                Ok(x) => x,
-                Err(e) => {
-                    warn!("Failed to clone socket when starting UDP server: {:?}", e);
-                    continue;
-                }
-            };
-
-            let context = Arc::clone(&self.context);
-            let request_cond = self.request_cond.clone();
-            let request_queue = self.request_queue.clone();
-
-            let name = "DnsUdpServer-request-".to_string() + &thread_id.to_string();
-            let _ = Builder::new().name(name).spawn(move || {
-                loop {
-                    // Acquire lock, and wait on the condition until data is
-                    // available. Then proceed with popping an entry of the queue.
-                    let (src, request) = match request_queue
-                        .lock()
-                        .ok()
-                        .and_then(|x| request_cond.wait(x).ok())
-                        .and_then(|mut x| x.pop_front())
-                    {
-                        Some(x) => x,
-                        None => {
-                            debug!("Not expected to happen!");
-                            continue;
-                        }
-                    };
-
-                    let mut size_limit = 512;
-
-                    // Check for EDNS
-                    if request.resources.len() == 1 {
-                        if let DnsRecord::OPT { packet_len, .. } = request.resources[0] {
-                            size_limit = packet_len as usize;
-                        }
-                    }
-
-                    // Create a response buffer, and ask the context for an appropriate resolver
-                    let mut res_buffer = VectorPacketBuffer::new();
-
-                    let mut packet = execute_query(Arc::clone(&context), &request);
-                    let _ = packet.write(&mut res_buffer, size_limit);
-
-                    // Fire off the response
-                    let len = res_buffer.pos();
-                    let data = return_or_report!(res_buffer.get_range(0, len), "Failed to get buffer data");
-                    ignore_or_report!(socket_clone.send_to(data, src), "Failed to send response packet");
-                }
-            })?;
-        }
+        let socket = Arc::new(bind_udp(&self.listener.address, self.listener.fallback_port)?);
 
         // Start servicing requests
         let _ = Builder::new()
@@ -262,6 +329,12 @@ impl DnsServer for DnsUdpServer {
                         }
                     };
 
+                    // Answering to strangers makes us an amplifier for DDoS, so we just drop their queries
+                    if !self.listener.allows(&src.ip()) {
+                        debug!("Dropping UDP query from {} on {}", &src, &self.listener.address);
+                        continue;
+                    }
+
                     // Parse it
                     let request = match DnsPacket::from_buffer(&mut req_buffer) {
                         Ok(x) => x,
@@ -271,15 +344,13 @@ impl DnsServer for DnsUdpServer {
                         }
                     };
 
-                    // Acquire lock, add request to queue, and notify waiting threads using the condition.
-                    match self.request_queue.lock() {
-                        Ok(mut queue) => {
-                            queue.push_back((src, request));
-                            self.request_cond.notify_one();
-                        }
-                        Err(e) => {
-                            debug!("Failed to send UDP request for processing: {}", e);
-                        }
+                    // The request is kept for the case of overload, the job takes its copy
+                    let context = Arc::clone(&self.context);
+                    let socket_clone = Arc::clone(&socket);
+                    let job_request = request.clone();
+                    if !self.pool.submit(move || handle_udp_query(context, &socket_clone, src, job_request)) {
+                        debug!("DNS workers are overloaded, refusing query from {}", &src);
+                        send_overloaded(&socket, src, &request);
                     }
                 }
             })?;
@@ -291,67 +362,60 @@ impl DnsServer for DnsUdpServer {
 /// TCP DNS server
 pub struct DnsTcpServer {
     context: Arc<ServerContext>,
-    senders: Vec<Sender<TcpStream>>,
-    thread_count: usize,
+    listener: Listener,
+    pool: Arc<WorkerPool>,
 }
 
 impl DnsTcpServer {
-    pub fn new(context: Arc<ServerContext>, thread_count: usize) -> DnsTcpServer {
-        DnsTcpServer { context, senders: Vec::new(), thread_count }
+    pub fn new(context: Arc<ServerContext>, listener: Listener, pool: Arc<WorkerPool>) -> DnsTcpServer {
+        DnsTcpServer { context, listener, pool }
     }
 }
 
-impl DnsServer for DnsTcpServer {
-    fn run_server(mut self) -> Result<()> {
-        let socket = TcpListener::bind(self.context.dns_listen.as_str())
-            .expect(&format!("Cannot start DNS server on {}! Change listen address in config!", self.context.dns_listen.as_str()));
-
-        // Spawn threads for handling requests, and create the channels
-        for thread_id in 0..self.thread_count {
-            let (tx, rx) = channel();
-            self.senders.push(tx);
+/// Reads one query from TCP connection and answers it, it is called in a worker thread
+fn handle_tcp_connection(context: Arc<ServerContext>, mut stream: TcpStream) {
+    let _ = context.statistics.tcp_query_count.fetch_add(1, Ordering::Release);
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(READ_TIMEOUT_SEC)));
+    let _ = stream.set_write_timeout(Some(Duration::from_secs(READ_TIMEOUT_SEC)));
 
-            let context = Arc::clone(&self.context);
+    // When DNS packets are sent over TCP, they're prefixed with a two byte
+    // length. We don't really need to know the length in advance, so we
+    // just move past it and continue reading as usual
+    ignore_or_report!(read_packet_length(&mut stream), "Failed to read query packet length");
 
-            let name = "DnsTcpServer-request-".to_string() + &thread_id.to_string();
-            let _ = Builder::new().name(name).spawn(move || {
-                loop {
-                    let mut stream = match rx.recv() {
-                        Ok(x) => x,
-                        Err(_) => continue,
-                    };
-
-                    let _ = context.statistics.tcp_query_count.fetch_add(1, Ordering::Release);
-
-                    // When DNS packets are sent over TCP, they're prefixed with a two byte
-                    // length. We don't really need to know the length in advance, so we
-                    // just move past it and continue reading as usual
-                    ignore_or_report!(read_packet_length(&mut stream), "Failed to read query packet length");
+    let request = {
+        let mut stream_buffer = StreamPacketBuffer::new(&mut stream);
+        return_or_report!(DnsPacket::from_buffer(&mut stream_buffer), "Failed to read query packet")
+    };
 
-                    let request = {
-                        let mut stream_buffer = StreamPacketBuffer::new(&mut stream);
-                        return_or_report!(DnsPacket::from_buffer(&mut stream_buffer), "Failed to read query packet")
-                    };
+    let mut res_buffer = VectorPacketBuffer::new();
 
-                    let mut res_buffer = VectorPacketBuffer::new();
+    let client = return_or_report!(stream.peer_addr(), "Failed to get client address").ip();
+    // Zone transfers are served only over TCP, big zones take many messages
+    if let Some(packet) = context.transfer.as_ref().and_then(|transfer| transfer.handle_transfer(&request, client)) {
+        ignore_or_report!(send_transfer(&mut stream, packet), "Failed to send zone transfer");
+        ignore_or_report!(stream.shutdown(Shutdown::Both), "Failed to shutdown socket");
+        return;
+    }
+    let mut packet = execute_query(Arc::clone(&context), &request, client);
+    ignore_or_report!(packet.write(&mut res_buffer, 0xFFFF), "Failed to write packet to buffer");
 
-                    let mut packet = execute_query(Arc::clone(&context), &request);
-                    ignore_or_report!(packet.write(&mut res_buffer, 0xFFFF), "Failed to write packet to buffer");
+    // As is the case for incoming queries, we need to send a 2 byte length
+    // value before handing of the actual packet.
+    let len = res_buffer.pos();
+    ignore_or_report!(write_packet_length(&mut stream, len), "Failed to write packet size");
 
-                    // As is the case for incoming queries, we need to send a 2 byte length
-                    // value before handing of the actual packet.
-                    let len = res_buffer.pos();
-                    ignore_or_report!(write_packet_length(&mut stream, len), "Failed to write packet size");
+    // Now we can go ahead and write the actual packet
+    let data = return_or_report!(res_buffer.get_range(0, len), "Failed to get packet data");
 
-                    // Now we can go ahead and write the actual packet
-                    let data = return_or_report!(res_buffer.get_range(0, len), "Failed to get packet data");
+    ignore_or_report!(stream.write_all(data), "Failed to write response packet");
 
-                    ignore_or_report!(stream.write(data), "Failed to write response packet");
+    ignore_or_report!(stream.shutdown(Shutdown::Both), "Failed to shutdown socket");
+}
 
-                    ignore_or_report!(stream.shutdown(Shutdown::Both), "Failed to shutdown socket");
-                }
-            })?;
-        }
+impl DnsServer for DnsTcpServer {
+    fn run_server(self) -> Result<()> {
+        let socket = bind_tcp(&self.listener.address, self.listener.fallback_port)?;
 
         let _ = Builder::new()
             .name("DnsTcpServer-incoming".into())
@@ -371,14 +435,20 @@ impl DnsServer for DnsTcpServer {
                         }
                     };
 
-                    // Hand it off to a worker thread
-                    let thread_no = random::<usize>() % self.thread_count;
-                    match self.senders[thread_no].send(stream) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            warn!("Failed to send TCP request for processing on thread {}: {}", thread_no, e);
+                    match stream.peer_addr() {
+                        Ok(addr) if self.listener.allows(&addr.ip()) => {}
+                        _ => {
+                            debug!("Closing TCP connection from a client that is not allowed on {}", &self.listener.address);
+                            let _ = stream.shutdown(Shutdown::Both);
+                            continue;
                         }
                     }
+
+                    // Hand it off to a worker, if the queue is full the connection is closed by dropping the job
+                    let context = Arc::clone(&self.context);
+                    if !self.pool.submit(move || handle_tcp_connection(context, stream)) {
+                        debug!("DNS workers are overloaded, closing TCP connection");
+                    }
                 }
             })?;
 
@@ -401,6 +471,8 @@ mod tests {
     use crate::dns::context::tests::create_test_context;
     use crate::dns::context::ResolveStrategy;
 
+    const LOCAL: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
     fn build_query(qname: &str, qtype: QueryType) -> DnsPacket {
         let mut query_packet = DnsPacket::new();
         query_packet.header.recursion_desired = true;
@@ -456,16 +528,18 @@ mod tests {
 
         match Arc::get_mut(&mut context) {
             Some(mut ctx) => {
-                ctx.resolve_strategy = ResolveStrategy::Forward {
-                    upstreams: vec![String::from("127.0.0.1:53")]
-                };
+                ctx.set_resolve_strategy(ResolveStrategy::Forward {
+                    upstreams: vec![String::from("127.0.0.1:53")],
+                    retries: 0,
+                    parallel: false
+                });
             }
             None => panic!(),
         }
 
         // A successful resolve
         {
-            let res = execute_query(Arc::clone(&context), &build_query("google.com", QueryType::A));
+            let res = execute_query(Arc::clone(&context), &build_query("google.com", QueryType::A), LOCAL);
             assert_eq!(1, res.answers.len());
 
             match res.answers[0] {
@@ -478,10 +552,7 @@ mod tests {
 
         // A successful resolve, that also resolves a CNAME without recursive lookup
         {
-            let res = execute_query(
-                Arc::clone(&context),
-                &build_query("www.facebook.com", QueryType::CNAME),
-            );
+            let res = execute_query(Arc::clone(&context), &build_query("www.facebook.com", QueryType::CNAME), LOCAL);
             assert_eq!(2, res.answers.len());
 
             match res.answers[0] {
@@ -501,10 +572,7 @@ mod tests {
 
         // A successful resolve, that also resolves a CNAME through recursive lookup
         {
-            let res = execute_query(
-                Arc::clone(&context),
-                &build_query("www.microsoft.com", QueryType::CNAME),
-            );
+            let res = execute_query(Arc::clone(&context), &build_query("www.microsoft.com", QueryType::CNAME), LOCAL);
             assert_eq!(2, res.answers.len());
 
             match res.answers[0] {
@@ -524,7 +592,7 @@ mod tests {
 
         // An unsuccessful resolve, but without any error
         {
-            let res = execute_query(Arc::clone(&context), &build_query("yahoo.com", QueryType::A));
+            let res = execute_query(Arc::clone(&context), &build_query("yahoo.com", QueryType::A), LOCAL);
             assert_eq!(ResultCode::NXDOMAIN, res.header.rescode);
             assert_eq!(0, res.answers.len());
         };
@@ -540,7 +608,7 @@ mod tests {
         // This should generate an error code, since recursive resolves are
         // no longer allowed
         {
-            let res = execute_query(Arc::clone(&context), &build_query("yahoo.com", QueryType::A));
+            let res = execute_query(Arc::clone(&context), &build_query("yahoo.com", QueryType::A), LOCAL);
             assert_eq!(ResultCode::REFUSED, res.header.rescode);
             assert_eq!(0, res.answers.len());
         };
@@ -548,7 +616,7 @@ mod tests {
         // Send a query without a question, which should fail with an error code
         {
             let query_packet = DnsPacket::new();
-            let res = execute_query(Arc::clone(&context), &query_packet);
+            let res = execute_query(Arc::clone(&context), &query_packet, LOCAL);
             assert_eq!(ResultCode::FORMERR, res.header.rescode);
             assert_eq!(0, res.answers.len());
         };
@@ -563,18 +631,81 @@ mod tests {
 
         match Arc::get_mut(&mut context2) {
             Some(mut ctx) => {
-                ctx.resolve_strategy = ResolveStrategy::Forward {
-                    upstreams: vec![String::from("127.0.0.1:53")]
-                };
+                ctx.set_resolve_strategy(ResolveStrategy::Forward {
+                    upstreams: vec![String::from("127.0.0.1:53")],
+                    retries: 0,
+                    parallel: false
+                });
             }
             None => panic!(),
         }
 
         // We expect this to set the server failure rescode
         {
-            let res = execute_query(context2.clone(), &build_query("yahoo.com", QueryType::A));
+            let res = execute_query(context2.clone(), &build_query("yahoo.com", QueryType::A), LOCAL);
             assert_eq!(ResultCode::SERVFAIL, res.header.rescode);
             assert_eq!(0, res.answers.len());
         };
     }
+
+    #[test]
+    fn test_minimize_response() {
+        let ns = |host: &str| DnsRecord::NS { domain: "ygg".to_string(), host: host.to_string(), ttl: TransientTtl(3600) };
+        let a = |domain: &str| DnsRecord::A { domain: domain.to_string(), addr: Ipv4Addr::new(10, 0, 0, 1), ttl: TransientTtl(3600) };
+        let opt = DnsRecord::OPT { packet_len: 1232, flags: 0, data: Vec::new() };
+
+        // Referral keeps name servers with their addresses
+        let mut packet = DnsPacket::new();
+        packet.authorities.push(ns("ns1.ygg"));
+        packet.resources.push(a("ns1.ygg"));
+        packet.resources.push(a("other.ygg"));
+        packet.resources.push(opt.clone());
+        minimize_response(&mut packet);
+        assert_eq!(vec![ns("ns1.ygg")], packet.authorities);
+        assert_eq!(vec![a("ns1.ygg"), opt.clone()], packet.resources);
+
+        // Positive answer loses everything but answers
+        let mut packet = DnsPacket::new();
+        packet.answers.push(a("www.ygg"));
+        packet.answers.push(a("www.ygg"));
+        packet.authorities.push(ns("ns1.ygg"));
+        packet.resources.push(a("ns1.ygg"));
+        minimize_response(&mut packet);
+        assert_eq!(vec![a("www.ygg")], packet.answers);
+        assert!(packet.authorities.is_empty());
+        assert!(packet.resources.is_empty());
+
+        // Negative answer keeps SOA
+        let soa = DnsRecord::SOA {
+            domain: "ygg".to_string(),
+            m_name: "ns1.ygg".to_string(),
+            r_name: "hostmaster.ygg".to_string(),
+            serial: 1,
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: 300,
+            ttl: TransientTtl(300),
+        };
+        let mut packet = DnsPacket::new();
+        packet.header.rescode = ResultCode::NXDOMAIN;
+        packet.authorities.push(soa.clone());
+        packet.authorities.push(ns("ns1.ygg"));
+        packet.resources.push(a("ns1.ygg"));
+        minimize_response(&mut packet);
+        assert_eq!(vec![soa], packet.authorities);
+        assert!(packet.resources.is_empty());
+    }
+
+    #[test]
+    fn test_listener_acl() {
+        let open = Listener::new("127.0.0.1:53", &[], 0);
+        assert!(open.allows(&LOCAL));
+
+        let lan = Listener::new("192.168.1.1:5353", &[String::from("192.168.1.0/24"), String::from("wrong")], 0);
+        assert_eq!(1, lan.allowed.len());
+        assert!(lan.allows(&IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20))));
+        assert!(!lan.allows(&IpAddr::V4(Ipv4Addr::new(192, 168, 2, 20))));
+        assert!(!lan.allows(&LOCAL));
+    }
 }
@@ -0,0 +1,86 @@
+//! Placeholders in records, that are expanded when the domain is submitted for mining,
+//! to point the domain at this node without copying its address by hand:
+//! `{ygg}` in addresses of AAAA records is the Yggdrasil address of this node, and `@` in hosts is the domain itself.
+
+use std::net::Ipv6Addr;
+
+use serde_json::Value;
+
+use crate::dns::protocol::DnsRecord;
+
+/// Yggdrasil address of this node
+pub const NODE_ADDRESS: &str = "{ygg}";
+/// The domain itself
+pub const APEX: &str = "@";
+
+/// Checks if records or zone file need the address of this node, to look for it only when it is needed
+pub fn needs_node_address(text: &str) -> bool {
+    text.contains(NODE_ADDRESS)
+}
+
+/// Expands placeholders in one record in JSON form, `node` is needed only if the record has `{ygg}`
+pub fn expand_record(record: &mut Value, domain: &str, node: Option<Ipv6Addr>) -> Result<(), String> {
+    let is_aaaa = record.get("type").and_then(Value::as_str) == Some("AAAA");
+    if let Some(addr) = record.get_mut("addr") {
+        if addr.as_str() == Some(NODE_ADDRESS) {
+            if !is_aaaa {
+                return Err(format!("{} can be used only in AAAA records", NODE_ADDRESS));
+            }
+            let node = node.ok_or_else(|| String::from("This node has no Yggdrasil address"))?;
+            *addr = Value::from(node.to_string());
+        }
+    }
+    if let Some(host) = record.get_mut("host") {
+        if host.as_str() == Some(APEX) {
+            *host = Value::from(domain);
+        }
+    }
+    Ok(())
+}
+
+/// Expands placeholders in records and checks that the result is valid records
+pub fn expand_records(records: Vec<Value>, domain: &str, node: Option<Ipv6Addr>) -> Result<Vec<DnsRecord>, String> {
+    let mut result = Vec::with_capacity(records.len());
+    for mut record in records {
+        expand_record(&mut record, domain, node)?;
+        let record = serde_json::from_value::<DnsRecord>(record).map_err(|e| format!("Wrong record: {}", e))?;
+        result.push(record);
+    }
+    Ok(result)
+}
+
+/// Expands `{ygg}` in zone file text, `@` is a part of zone file format already
+pub fn expand_zone_text(text: &str, node: Option<Ipv6Addr>) -> Result<String, String> {
+    if !needs_node_address(text) {
+        return Ok(text.to_owned());
+    }
+    let node = node.ok_or_else(|| String::from("This node has no Yggdrasil address"))?;
+    Ok(text.replace(NODE_ADDRESS, &node.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn expand() {
+        let node: Ipv6Addr = "200::1".parse().unwrap();
+        let records = vec![
+            json!({ "type": "AAAA", "domain": "@", "ttl": 3600, "addr": "{ygg}" }),
+            json!({ "type": "CNAME", "domain": "www", "ttl": 3600, "host": "@" }),
+        ];
+        let result = expand_records(records.clone(), "site.ygg", Some(node)).unwrap();
+        assert_eq!(serde_json::to_value(&result[0]).unwrap()["addr"], "200::1");
+        assert_eq!(serde_json::to_value(&result[1]).unwrap()["host"], "site.ygg");
+        assert!(expand_records(records, "site.ygg", None).is_err());
+
+        let a = vec![json!({ "type": "A", "domain": "@", "ttl": 3600, "addr": "{ygg}" })];
+        assert!(expand_records(a, "site.ygg", Some(node)).is_err());
+
+        assert_eq!("@ AAAA 200::1\n", expand_zone_text("@ AAAA {ygg}\n", Some(node)).unwrap());
+        assert!(expand_zone_text("@ AAAA {ygg}\n", None).is_err());
+        assert_eq!("@ A 1.2.3.4\n", expand_zone_text("@ A 1.2.3.4\n", None).unwrap());
+    }
+}
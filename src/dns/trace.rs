@@ -0,0 +1,189 @@
+//! Step by step resolving of some name, to find out why it resolves (or doesn't) the way it does
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use serde::Serialize;
+
+use crate::dns::context::{ResolveStrategy, ServerContext};
+use crate::dns::protocol::{DnsPacket, DnsRecord, QueryType};
+use crate::dns::resolve::{DnsResolver, ForwardingDnsResolver};
+use crate::dns::routes::{nxdomain, RouteTarget};
+
+/// One step of resolving, every source of answers that was checked
+#[derive(Clone, Debug, Serialize)]
+pub struct TraceStep {
+    pub source: String,
+    /// Short result, "miss" if this source had no answer
+    pub result: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+    pub elapsed_us: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ResolveTrace {
+    pub name: String,
+    pub qtype: QueryType,
+    pub rescode: String,
+    pub answers: Vec<DnsRecord>,
+    pub steps: Vec<TraceStep>,
+    pub elapsed_us: u64,
+}
+
+impl ResolveTrace {
+    fn add_step(&mut self, source: String, result: &Option<DnsPacket>, details: Option<String>, start: Instant) {
+        let result = match result {
+            None => String::from("miss"),
+            Some(packet) => format!("{:?}, {} answers", packet.header.rescode, packet.answers.len())
+        };
+        let elapsed_us = start.elapsed().as_micros() as u64;
+        self.steps.push(TraceStep { source, result, details, elapsed_us });
+    }
+
+    fn finish(mut self, packet: DnsPacket, start: Instant) -> Self {
+        self.rescode = format!("{:?}", packet.header.rescode);
+        self.answers = packet.answers;
+        self.elapsed_us = start.elapsed().as_micros() as u64;
+        self
+    }
+}
+
+/// Resolves the name going through the same sources as the resolver does, and records every step
+pub fn trace_resolve(context: Arc<ServerContext>, qname: &str, qtype: QueryType) -> ResolveTrace {
+    let qname = qname.trim_end_matches('.').to_lowercase();
+    let total = Instant::now();
+    let mut trace = ResolveTrace { name: qname.clone(), qtype, rescode: String::new(), answers: Vec::new(), steps: Vec::new(), elapsed_us: 0 };
+
+    let start = Instant::now();
+    let result = context.authority.query(&qname, qtype);
+    trace.add_step(String::from("local zones"), &result, None, start);
+    if let Some(packet) = result {
+        return trace.finish(packet, total);
+    }
+
+    let start = Instant::now();
+    let mut result = context.cache.lookup(&qname, qtype);
+    if result.is_none() && (qtype == QueryType::A || qtype == QueryType::AAAA) {
+        result = context.cache.lookup(&qname, QueryType::CNAME);
+    }
+    trace.add_step(String::from("cache"), &result, None, start);
+    if let Some(packet) = result {
+        return trace.finish(packet, total);
+    }
+
+    for filter in context.filters.iter() {
+        let start = Instant::now();
+        let result = filter.lookup(&qname, qtype);
+        let details = filter.explain(&qname);
+        trace.add_step(filter.name(), &result, details, start);
+        if let Some(packet) = result {
+            return trace.finish(packet, total);
+        }
+    }
+
+    let (source, details, mut resolver) = match context.get_route(&qname) {
+        Some((zone, RouteTarget::Source(name))) => {
+            let start = Instant::now();
+            let source = context.get_source(&name);
+            let result = source.and_then(|source| source.lookup(&qname, qtype));
+            let details = Some(format!("Route for '{}'", zone));
+            let name = source.map(|source| source.name()).unwrap_or(name);
+            trace.add_step(name, &result, details, start);
+            return trace.finish(result.unwrap_or_else(|| nxdomain(&qname, qtype)), total);
+        }
+        Some((zone, RouteTarget::Zone)) => {
+            let details = Some(format!("Route for '{}' has only local zones", zone));
+            trace.add_step(String::from("route"), &None, details, Instant::now());
+            return trace.finish(nxdomain(&qname, qtype), total);
+        }
+        Some((zone, RouteTarget::Forward(upstreams))) => {
+            let retries = upstreams.len() - 1;
+            let source = format!("forwarders {}", upstreams.join(", "));
+            let details = Some(format!("Route for '{}'", zone));
+            let resolver: Box<dyn DnsResolver> = Box::new(ForwardingDnsResolver::new(Arc::clone(&context), upstreams, retries, false));
+            (source, details, resolver)
+        }
+        None => {
+            for (_, source) in context.sources.iter() {
+                let start = Instant::now();
+                let result = source.lookup(&qname, qtype);
+                let details = source.explain(&qname);
+                trace.add_step(source.name(), &result, details, start);
+                if let Some(packet) = result {
+                    return trace.finish(packet, total);
+                }
+            }
+            let source = match &context.get_resolve_strategy() {
+                ResolveStrategy::Recursive => String::from("recursive"),
+                ResolveStrategy::Forward { upstreams, .. } => format!("forwarders {}", upstreams.join(", "))
+            };
+            (source, None, context.create_resolver(Arc::clone(&context)))
+        }
+    };
+    let start = Instant::now();
+    match resolver.perform(&qname, qtype) {
+        Ok(packet) => {
+            let result = Some(packet);
+            trace.add_step(source, &result, details, start);
+            trace.finish(result.unwrap(), total)
+        }
+        Err(e) => {
+            trace.add_step(source, &None, Some(format!("Error: {}", e)), start);
+            trace.rescode = String::from("SERVFAIL");
+            trace.elapsed_us = total.elapsed().as_micros() as u64;
+            trace
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::dns::context::tests::create_test_context;
+    use crate::dns::hosts::HostsFilter;
+    use crate::dns::protocol::ResultCode;
+    use crate::dns::routes::RouteTable;
+
+    use super::*;
+
+    #[test]
+    fn trace_steps() {
+        let context = create_test_context(Box::new(|_, _, _, _| {
+            let mut packet = DnsPacket::new();
+            packet.header.rescode = ResultCode::NXDOMAIN;
+            Ok(packet)
+        }));
+        match Arc::try_unwrap(context) {
+            Ok(mut ctx) => {
+                ctx.filters.push(Box::new(HostsFilter::new("/nonexistent/hosts")));
+                ctx.set_resolve_strategy(ResolveStrategy::Forward { upstreams: vec![String::from("127.0.0.1:53")], retries: 0, parallel: false });
+                let context = Arc::new(ctx);
+                let trace = trace_resolve(context, "Example.COM.", QueryType::A);
+                assert_eq!("example.com", trace.name);
+                assert_eq!("NXDOMAIN", trace.rescode);
+                let sources: Vec<&str> = trace.steps.iter().map(|s| s.source.as_str()).collect();
+                assert_eq!(vec!["local zones", "cache", "hosts '/nonexistent/hosts'", "forwarders 127.0.0.1:53"], sources);
+                assert_eq!("miss", trace.steps[2].result);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn trace_routes() {
+        let context = create_test_context(Box::new(|_, _, _, _| {
+            let mut packet = DnsPacket::new();
+            packet.header.rescode = ResultCode::SERVFAIL;
+            Ok(packet)
+        }));
+        let mut routes = HashMap::new();
+        routes.insert(String::from("lan"), String::from("zone"));
+        *context.routes.write().unwrap() = RouteTable::parse(&routes, &[]).unwrap();
+        let trace = trace_resolve(context, "printer.lan", QueryType::A);
+        assert_eq!("NXDOMAIN", trace.rescode);
+        let sources: Vec<&str> = trace.steps.iter().map(|s| s.source.as_str()).collect();
+        assert_eq!(vec!["local zones", "cache", "route"], sources);
+    }
+}
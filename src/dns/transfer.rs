@@ -0,0 +1,461 @@
+//! Zone transfers with conventional DNS servers: AXFR in both directions, IXFR to secondaries and NOTIFY messages
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use derive_more::{Display, Error, From};
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use crate::dns::authority::Zone;
+use crate::dns::buffer::{BytePacketBuffer, PacketBuffer, VectorPacketBuffer};
+use crate::dns::netutil::write_packet_length;
+use crate::dns::protocol::{DnsPacket, DnsQuestion, DnsRecord, QueryType, ResultCode};
+
+#[derive(Debug, Display, From, Error)]
+pub enum TransferError {
+    Buffer(crate::dns::buffer::BufferError),
+    Protocol(crate::dns::protocol::ProtocolError),
+    Io(std::io::Error),
+    Refused,
+    Incomplete,
+}
+
+type Result<T> = std::result::Result<T, TransferError>;
+
+/// Query type number of full zone transfer request
+pub const QTYPE_AXFR: u16 = 252;
+/// Query type number of incremental zone transfer request, RFC 1995
+pub const QTYPE_IXFR: u16 = 251;
+/// Opcode of NOTIFY messages, RFC 1996
+pub const OPCODE_NOTIFY: u8 = 4;
+/// Largest DNS message over TCP, its length is sent in two bytes
+const MAX_TCP_MESSAGE: usize = 0xFFFF;
+
+/// Source of zones, that we serve as a (hidden) primary server
+pub trait ZoneProvider {
+    /// Returns names of all zones, that can be transferred
+    fn get_zones(&self) -> Vec<String>;
+    /// Returns current serial of the zone, or None if there is no such zone
+    fn get_serial(&self, zone: &str) -> Option<u32>;
+    /// Returns all records of the zone, the first one must be SOA
+    fn get_records(&self, zone: &str) -> Option<Vec<DnsRecord>>;
+}
+
+pub struct ZoneTransfer {
+    provider: Box<dyn ZoneProvider + Sync + Send>,
+    /// Secondary servers to notify, only they are allowed to transfer zones
+    secondaries: Vec<SocketAddr>,
+    /// Last serials that we have notified secondaries about
+    serials: Mutex<HashMap<String, u32>>,
+}
+
+impl ZoneTransfer {
+    pub fn new(provider: Box<dyn ZoneProvider + Sync + Send>, secondaries: &Vec<String>) -> Self {
+        let mut addrs = Vec::new();
+        for secondary in secondaries {
+            let resolved = match secondary.parse::<IpAddr>() {
+                Ok(ip) => Ok(vec![SocketAddr::new(ip, 53)]),
+                Err(_) => secondary.to_socket_addrs().map(|addrs| addrs.collect())
+            };
+            match resolved {
+                Ok(mut resolved) => addrs.append(&mut resolved),
+                Err(e) => warn!("Wrong secondary server address '{}': {}", secondary, e)
+            }
+        }
+        ZoneTransfer { provider, secondaries: addrs, serials: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn is_allowed(&self, client: &IpAddr) -> bool {
+        self.secondaries.iter().any(|addr| &addr.ip() == client)
+    }
+
+    /// Makes a response for AXFR or IXFR request, or returns None if this is some other request
+    pub fn handle_transfer(&self, request: &DnsPacket, client: IpAddr) -> Option<DnsPacket> {
+        let question = request.questions.first()?;
+        let incremental = match question.qtype {
+            QueryType::UNKNOWN(QTYPE_AXFR) => false,
+            QueryType::UNKNOWN(QTYPE_IXFR) => true,
+            _ => return None
+        };
+
+        let mut packet = DnsPacket::new();
+        packet.header.id = request.header.id;
+        packet.header.response = true;
+        packet.questions.push(question.clone());
+        if !self.is_allowed(&client) {
+            warn!("Refused zone transfer of '{}' to {}", &question.name, &client);
+            packet.header.rescode = ResultCode::REFUSED;
+            return Some(packet);
+        }
+
+        let records = self.provider.get_records(&question.name.to_lowercase()).unwrap_or_default();
+        match records.first().cloned() {
+            Some(soa) => {
+                packet.header.authoritative_answer = true;
+                // Old versions of blockchain domains are not kept, so IXFR gets the whole zone as RFC 1995 allows,
+                // or only the SOA record if the secondary has the current version already
+                let client_serial = request.authorities.first().and_then(get_soa_serial);
+                if let (true, Some(current), Some(known)) = (incremental, get_soa_serial(&soa), client_serial) {
+                    if !is_serial_newer(current, known) {
+                        debug!("Zone '{}' of {} is up to date", &question.name, &client);
+                        packet.answers.push(soa);
+                        return Some(packet);
+                    }
+                }
+                info!("Transferring zone '{}' to {}", &question.name, &client);
+                // The transfer must start and end with the SOA record
+                packet.answers = records;
+                packet.answers.push(soa);
+            }
+            None => {
+                packet.header.rescode = ResultCode::REFUSED;
+            }
+        }
+        Some(packet)
+    }
+
+    /// Checks serials of all zones, and notifies secondaries about changed ones
+    pub fn check_serials(&self) {
+        let mut serials = self.serials.lock().unwrap();
+        for zone in self.provider.get_zones() {
+            if let Some(serial) = self.provider.get_serial(&zone) {
+                if serials.get(&zone) != Some(&serial) {
+                    self.notify(&zone, serial);
+                    serials.insert(zone, serial);
+                }
+            }
+        }
+    }
+
+    /// Sends NOTIFY message for some zone to all secondary servers
+    pub fn notify(&self, zone: &str, serial: u32) {
+        for secondary in &self.secondaries {
+            debug!("Sending NOTIFY for '{}' with serial {} to {}", zone, serial, secondary);
+            if let Err(e) = send_notify(zone, secondary) {
+                warn!("Failed to send NOTIFY to {}: {}", secondary, e);
+            }
+        }
+    }
+}
+
+/// Sends the response to AXFR or IXFR request, records of big zones are split to as many messages as needed, RFC 5936
+pub fn send_transfer<W: Write>(stream: &mut W, packet: DnsPacket) -> Result<()> {
+    let mut empty = packet;
+    let mut records = std::mem::take(&mut empty.answers);
+    loop {
+        // The first write only counts the records that fit, it marks the message as truncated
+        let mut message = empty.clone();
+        message.answers = records;
+        message.write(&mut VectorPacketBuffer::new(), MAX_TCP_MESSAGE)?;
+        let count = message.header.answers as usize;
+        if count == 0 && !message.answers.is_empty() {
+            return Err(TransferError::Incomplete);
+        }
+        records = message.answers.split_off(count);
+        message.header = empty.header.clone();
+
+        let mut buffer = VectorPacketBuffer::new();
+        message.write(&mut buffer, MAX_TCP_MESSAGE)?;
+        write_packet_length(stream, buffer.pos())?;
+        stream.write_all(buffer.get_range(0, buffer.pos())?)?;
+        if records.is_empty() {
+            break;
+        }
+    }
+    stream.flush()?;
+    Ok(())
+}
+
+/// Requests full zone transfer (AXFR) from the primary server, returns all records of the zone
+pub fn request_zone(zone: &str, primary: &str, timeout: Duration) -> Result<Vec<DnsRecord>> {
+    let mut packet = DnsPacket::new();
+    packet.header.id = rand::random::<u16>();
+    packet.questions.push(DnsQuestion::new(zone.to_owned(), QueryType::UNKNOWN(QTYPE_AXFR)));
+    let mut req_buffer = BytePacketBuffer::new();
+    packet.write(&mut req_buffer, 512)?;
+
+    let mut socket = TcpStream::connect(primary)?;
+    socket.set_read_timeout(Some(timeout))?;
+    write_packet_length(&mut socket, req_buffer.pos())?;
+    socket.write_all(&req_buffer.buf[0..req_buffer.pos])?;
+    socket.flush()?;
+
+    // The zone can come in many messages, the last one ends with the same SOA as the first one
+    let mut records: Vec<DnsRecord> = Vec::new();
+    loop {
+        let mut len_buffer = [0u8; 2];
+        socket.read_exact(&mut len_buffer)?;
+        let len = ((len_buffer[0] as usize) << 8) | (len_buffer[1] as usize);
+        let mut buffer = VectorPacketBuffer::new();
+        buffer.buffer.resize(len, 0);
+        socket.read_exact(&mut buffer.buffer)?;
+
+        let response = DnsPacket::from_buffer(&mut buffer)?;
+        if response.header.rescode != ResultCode::NOERROR {
+            return Err(TransferError::Refused);
+        }
+        if response.answers.is_empty() {
+            return Err(TransferError::Incomplete);
+        }
+        for record in response.answers {
+            if record.get_querytype() == QueryType::SOA && !records.is_empty() {
+                return Ok(records);
+            }
+            if records.is_empty() && record.get_querytype() != QueryType::SOA {
+                return Err(TransferError::Incomplete);
+            }
+            records.push(record);
+        }
+    }
+}
+
+/// Makes local zone from transferred records, the first of them must be SOA
+pub fn make_zone(records: Vec<DnsRecord>) -> Option<Zone> {
+    let mut iter = records.into_iter();
+    let mut zone = match iter.next()? {
+        DnsRecord::SOA { domain, m_name, r_name, serial, refresh, retry, expire, minimum, .. } => {
+            let mut zone = Zone::new(domain.to_lowercase(), m_name, r_name);
+            zone.serial = serial;
+            zone.refresh = refresh;
+            zone.retry = retry;
+            zone.expire = expire;
+            zone.minimum = minimum;
+            zone
+        }
+        _ => return None
+    };
+    for record in iter {
+        zone.add_record(&record);
+    }
+    Some(zone)
+}
+
+fn get_soa_serial(record: &DnsRecord) -> Option<u32> {
+    match record {
+        DnsRecord::SOA { serial, .. } => Some(*serial),
+        _ => None
+    }
+}
+
+/// Compares serials by RFC 1982 arithmetic, as secondaries do, they can wrap around
+fn is_serial_newer(serial: u32, than: u32) -> bool {
+    serial != than && serial.wrapping_sub(than) < 0x8000_0000
+}
+
+fn send_notify(zone: &str, secondary: &SocketAddr) -> std::io::Result<()> {
+    let mut packet = DnsPacket::new();
+    packet.header.id = rand::random::<u16>();
+    packet.header.opcode = OPCODE_NOTIFY;
+    packet.header.authoritative_answer = true;
+    packet.questions.push(DnsQuestion::new(zone.to_owned(), QueryType::SOA));
+
+    let mut buffer = BytePacketBuffer::new();
+    packet.write(&mut buffer, 512).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+
+    let bind = match secondary {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0"
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.send_to(&buffer.buf[0..buffer.pos()], secondary)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::dns::protocol::TransientTtl;
+
+    use super::*;
+
+    struct StubProvider;
+
+    impl ZoneProvider for StubProvider {
+        fn get_zones(&self) -> Vec<String> {
+            vec![String::from("example.tld")]
+        }
+
+        fn get_serial(&self, _zone: &str) -> Option<u32> {
+            Some(1)
+        }
+
+        fn get_records(&self, zone: &str) -> Option<Vec<DnsRecord>> {
+            if zone != "example.tld" {
+                return None;
+            }
+            Some(vec![
+                DnsRecord::SOA {
+                    domain: zone.to_owned(),
+                    m_name: String::from("ns.example.tld"),
+                    r_name: String::from("admin.example.tld"),
+                    serial: 1,
+                    refresh: 3600,
+                    retry: 300,
+                    expire: 604800,
+                    minimum: 60,
+                    ttl: TransientTtl(60),
+                },
+                DnsRecord::A { domain: zone.to_owned(), addr: Ipv4Addr::new(127, 0, 0, 1), ttl: TransientTtl(3600) },
+            ])
+        }
+    }
+
+    fn build_request(name: &str, qtype: QueryType) -> DnsPacket {
+        let mut packet = DnsPacket::new();
+        packet.header.id = 123;
+        packet.questions.push(DnsQuestion::new(name.to_owned(), qtype));
+        packet
+    }
+
+    #[test]
+    fn zone_from_records() {
+        let records = StubProvider.get_records("example.tld").unwrap();
+        let zone = make_zone(records).unwrap();
+        assert_eq!("example.tld", zone.domain);
+        assert_eq!("ns.example.tld", zone.m_name);
+        assert_eq!(1, zone.serial);
+        assert_eq!(1, zone.records.len());
+
+        let records = vec![DnsRecord::A { domain: String::from("example.tld"), addr: Ipv4Addr::LOCALHOST, ttl: TransientTtl(3600) }];
+        assert!(make_zone(records).is_none());
+    }
+
+    #[test]
+    fn axfr_requests() {
+        let transfer = ZoneTransfer::new(Box::new(StubProvider), &vec![String::from("10.0.0.2")]);
+        let secondary = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let stranger = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 3));
+
+        assert!(transfer.handle_transfer(&build_request("example.tld", QueryType::A), secondary).is_none());
+
+        let packet = transfer.handle_transfer(&build_request("example.tld", QueryType::UNKNOWN(QTYPE_AXFR)), secondary).unwrap();
+        assert_eq!(ResultCode::NOERROR, packet.header.rescode);
+        assert_eq!(123, packet.header.id);
+        assert_eq!(3, packet.answers.len());
+        assert_eq!(QueryType::SOA, packet.answers[0].get_querytype());
+        assert_eq!(QueryType::SOA, packet.answers[2].get_querytype());
+
+        let packet = transfer.handle_transfer(&build_request("example.tld", QueryType::UNKNOWN(QTYPE_AXFR)), stranger).unwrap();
+        assert_eq!(ResultCode::REFUSED, packet.header.rescode);
+        assert!(packet.answers.is_empty());
+
+        let packet = transfer.handle_transfer(&build_request("other.tld", QueryType::UNKNOWN(QTYPE_AXFR)), secondary).unwrap();
+        assert_eq!(ResultCode::REFUSED, packet.header.rescode);
+    }
+
+    /// Zone that has no records, not even SOA
+    struct EmptyProvider;
+
+    impl ZoneProvider for EmptyProvider {
+        fn get_zones(&self) -> Vec<String> {
+            StubProvider.get_zones()
+        }
+
+        fn get_serial(&self, _zone: &str) -> Option<u32> {
+            None
+        }
+
+        fn get_records(&self, _zone: &str) -> Option<Vec<DnsRecord>> {
+            Some(Vec::new())
+        }
+    }
+
+    #[test]
+    fn empty_zone() {
+        let transfer = ZoneTransfer::new(Box::new(EmptyProvider), &vec![String::from("10.0.0.2")]);
+        let secondary = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let packet = transfer.handle_transfer(&build_request("example.tld", QueryType::UNKNOWN(QTYPE_AXFR)), secondary).unwrap();
+        assert_eq!(ResultCode::REFUSED, packet.header.rescode);
+        assert!(packet.answers.is_empty());
+    }
+
+    #[test]
+    fn ixfr_requests() {
+        let transfer = ZoneTransfer::new(Box::new(StubProvider), &vec![String::from("10.0.0.2")]);
+        let secondary = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let with_serial = |serial: u32| {
+            let mut request = build_request("example.tld", QueryType::UNKNOWN(QTYPE_IXFR));
+            let mut soa = StubProvider.get_records("example.tld").unwrap().remove(0);
+            if let DnsRecord::SOA { serial: ref mut s, .. } = soa {
+                *s = serial;
+            }
+            request.authorities.push(soa);
+            request
+        };
+
+        // The secondary has the current zone already
+        let packet = transfer.handle_transfer(&with_serial(1), secondary).unwrap();
+        assert_eq!(ResultCode::NOERROR, packet.header.rescode);
+        assert_eq!(1, packet.answers.len());
+
+        // Older zone and requests without SOA get the whole zone
+        let packet = transfer.handle_transfer(&with_serial(0), secondary).unwrap();
+        assert_eq!(3, packet.answers.len());
+        let packet = transfer.handle_transfer(&build_request("example.tld", QueryType::UNKNOWN(QTYPE_IXFR)), secondary).unwrap();
+        assert_eq!(3, packet.answers.len());
+
+        assert!(is_serial_newer(1, 0));
+        assert!(is_serial_newer(0, u32::MAX));
+        assert!(!is_serial_newer(1, 1));
+        assert!(!is_serial_newer(0, 1));
+    }
+
+    /// Zone that takes more than one TCP message
+    struct BigProvider;
+
+    impl ZoneProvider for BigProvider {
+        fn get_zones(&self) -> Vec<String> {
+            StubProvider.get_zones()
+        }
+
+        fn get_serial(&self, zone: &str) -> Option<u32> {
+            StubProvider.get_serial(zone)
+        }
+
+        fn get_records(&self, zone: &str) -> Option<Vec<DnsRecord>> {
+            let mut records = StubProvider.get_records(zone)?;
+            for i in 0..5000 {
+                let domain = format!("host-with-a-long-name-{}.{}", i, zone);
+                records.push(DnsRecord::A { domain, addr: Ipv4Addr::new(10, 0, 0, 1), ttl: TransientTtl(3600) });
+            }
+            Some(records)
+        }
+    }
+
+    /// Parses length-prefixed messages, as they are sent over TCP
+    fn read_messages(data: &[u8]) -> Vec<DnsPacket> {
+        let mut messages = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let len = ((data[pos] as usize) << 8) | (data[pos + 1] as usize);
+            let mut buffer = VectorPacketBuffer::new();
+            buffer.buffer = data[pos + 2..pos + 2 + len].to_vec();
+            messages.push(DnsPacket::from_buffer(&mut buffer).unwrap());
+            pos += 2 + len;
+        }
+        messages
+    }
+
+    #[test]
+    fn big_zone_in_many_messages() {
+        let transfer = ZoneTransfer::new(Box::new(BigProvider), &vec![String::from("10.0.0.2")]);
+        let secondary = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        for qtype in &[QTYPE_AXFR, QTYPE_IXFR] {
+            let packet = transfer.handle_transfer(&build_request("example.tld", QueryType::UNKNOWN(*qtype)), secondary).unwrap();
+            let mut data = Vec::new();
+            send_transfer(&mut data, packet).unwrap();
+
+            let messages = read_messages(&data);
+            assert!(messages.len() > 1);
+            assert!(messages.iter().all(|message| message.header.id == 123 && !message.header.truncated_message));
+            let records: Vec<&DnsRecord> = messages.iter().flat_map(|message| message.answers.iter()).collect();
+            assert_eq!(5003, records.len());
+            assert_eq!(QueryType::SOA, records[records.len() - 1].get_querytype());
+        }
+    }
+}
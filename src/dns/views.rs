@@ -0,0 +1,137 @@
+//! Per-client views, allowing to resolve names differently for different groups of clients
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use crate::dns::filter::DnsFilter;
+use crate::dns::hosts::HostsFilter;
+use crate::dns::protocol::{DnsPacket, DnsQuestion, DnsRecord, QueryType, TransientTtl};
+
+/// A network given in CIDR notation, like `192.168.1.0/24` or `fd00::/8`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(string: &str) -> Option<Cidr> {
+        let mut parts = string.trim().splitn(2, '/');
+        let addr: IpAddr = parts.next()?.parse().ok()?;
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = match parts.next() {
+            None => max,
+            Some(prefix) => prefix.parse().ok()?,
+        };
+        if prefix > max {
+            return None;
+        }
+        Some(Cidr { addr, prefix })
+    }
+
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (&self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(*net) & mask == u32::from(*addr) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(*net) & mask == u128::from(*addr) & mask
+            }
+            (IpAddr::V4(_), IpAddr::V6(addr)) => {
+                // IPv4 clients can come to dual-stack sockets as mapped addresses
+                match addr.to_ipv4() {
+                    Some(addr) if addr.octets()[..] != [0, 0, 0, 1] => self.contains(&IpAddr::V4(addr)),
+                    _ => false
+                }
+            }
+            _ => false
+        }
+    }
+}
+
+/// A set of filters and rewrites that is applied to queries of some clients
+pub struct ClientView {
+    pub name: String,
+    networks: Vec<Cidr>,
+    filters: Vec<Box<dyn DnsFilter + Sync + Send>>,
+    rewrites: HashMap<String, String>,
+}
+
+impl ClientView {
+    pub fn new(name: &str, clients: &[String], hosts: &[String], rewrites: &HashMap<String, String>) -> Self {
+        let networks = clients.iter().filter_map(|c| Cidr::parse(c)).collect();
+        let mut filters: Vec<Box<dyn DnsFilter + Sync + Send>> = Vec::new();
+        for host in hosts {
+            filters.push(Box::new(HostsFilter::new(host)));
+        }
+        let rewrites = rewrites.iter()
+            .map(|(from, to)| (from.trim_end_matches('.').to_lowercase(), to.trim_end_matches('.').to_lowercase()))
+            .collect();
+        ClientView { name: name.to_owned(), networks, filters, rewrites }
+    }
+
+    pub fn matches(&self, addr: &IpAddr) -> bool {
+        self.networks.iter().any(|net| net.contains(addr))
+    }
+
+    /// Answers the query if some rewrite or filter of this view applies to it
+    pub fn lookup(&self, qname: &str, qtype: QueryType) -> Option<DnsPacket> {
+        if let Some(host) = self.rewrites.get(&qname.to_lowercase()) {
+            let mut packet = DnsPacket::new();
+            packet.questions.push(DnsQuestion::new(String::from(qname), qtype));
+            packet.answers.push(DnsRecord::CNAME { domain: String::from(qname), host: host.clone(), ttl: TransientTtl(60) });
+            return Some(packet);
+        }
+
+        for filter in self.filters.iter() {
+            if let Some(packet) = filter.lookup(qname, qtype) {
+                return Some(packet);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::IpAddr;
+
+    use crate::dns::protocol::{DnsRecord, QueryType};
+    use crate::dns::views::{Cidr, ClientView};
+
+    #[test]
+    pub fn cidr_contains() {
+        let net = Cidr::parse("192.168.1.64/26").unwrap();
+        assert!(net.contains(&"192.168.1.100".parse::<IpAddr>().unwrap()));
+        assert!(!net.contains(&"192.168.1.10".parse::<IpAddr>().unwrap()));
+        assert!(net.contains(&"::ffff:192.168.1.70".parse::<IpAddr>().unwrap()));
+
+        let net = Cidr::parse("fd00::/8").unwrap();
+        assert!(net.contains(&"fd12:3456::1".parse::<IpAddr>().unwrap()));
+        assert!(!net.contains(&"fe80::1".parse::<IpAddr>().unwrap()));
+
+        assert!(Cidr::parse("0.0.0.0/0").unwrap().contains(&"8.8.8.8".parse::<IpAddr>().unwrap()));
+        assert!(Cidr::parse("10.0.0.0/33").is_none());
+    }
+
+    #[test]
+    pub fn view_rewrites() {
+        let mut rewrites = HashMap::new();
+        rewrites.insert(String::from("www.youtube.com"), String::from("restrict.youtube.com."));
+        let view = ClientView::new("kids", &[String::from("10.0.0.0/8")], &[], &rewrites);
+        assert!(view.matches(&"10.1.2.3".parse::<IpAddr>().unwrap()));
+
+        let packet = view.lookup("WWW.YouTube.com", QueryType::A).unwrap();
+        match &packet.answers[0] {
+            DnsRecord::CNAME { host, .. } => assert_eq!(host, "restrict.youtube.com"),
+            _ => panic!()
+        }
+        assert!(view.lookup("example.com", QueryType::A).is_none());
+    }
+}
@@ -0,0 +1,399 @@
+//! Minimal parser and writer of DNS master files (RFC 1035, section 5), used for RPZ, zone imports and exports
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use crate::dns::protocol::{DnsRecord, QueryType, TransientTtl};
+
+const DEFAULT_TTL: u32 = 3600;
+
+/// Parses records from zone text. Domain names are returned without trailing dots,
+/// relative names are completed with `origin` (or `$ORIGIN` from the text).
+/// Lines that can not be parsed are skipped with a warning.
+pub fn parse_zone(text: &str, origin: &str) -> Vec<DnsRecord> {
+    let mut errors = Vec::new();
+    let records = parse_lines(text, origin, &mut errors);
+    for error in errors {
+        warn!("{}", error);
+    }
+    records
+}
+
+/// Parses zone file of some domain to records for domain data, with names relative to the domain ("@", "www").
+/// Fails if there are any unsupported records, or records out of this domain.
+pub fn parse_domain_records(text: &str, domain: &str) -> Result<Vec<DnsRecord>, String> {
+    let domain = domain.trim_end_matches('.').to_lowercase();
+    let mut errors = Vec::new();
+    let records = parse_lines(text, &domain, &mut errors);
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+
+    let suffix = format!(".{}", &domain);
+    let mut result = Vec::new();
+    for mut record in records {
+        // SOA is made by the resolver itself
+        if record.get_querytype() == QueryType::SOA {
+            continue;
+        }
+        let name = record.get_domain().unwrap_or_default().to_lowercase();
+        if name == domain {
+            record.set_domain("@");
+        } else if name.ends_with(&suffix) {
+            record.set_domain(&name[..name.len() - suffix.len()]);
+        } else {
+            return Err(format!("Record for '{}' is out of domain '{}'", &name, &domain));
+        }
+        result.push(record);
+    }
+    Ok(result)
+}
+
+fn parse_lines(text: &str, origin: &str, errors: &mut Vec<String>) -> Vec<DnsRecord> {
+    let mut records = Vec::new();
+    let mut origin = origin.trim_end_matches('.').to_owned();
+    let mut default_ttl = DEFAULT_TTL;
+    let mut last_owner = origin.clone();
+
+    for (number, line) in join_lines(text).iter().enumerate() {
+        let tokens = tokenize(line);
+        if tokens.is_empty() {
+            continue;
+        }
+        match tokens[0].to_uppercase().as_str() {
+            "$ORIGIN" if tokens.len() > 1 => {
+                origin = tokens[1].trim_end_matches('.').to_owned();
+                continue;
+            }
+            "$TTL" if tokens.len() > 1 => {
+                default_ttl = tokens[1].parse().unwrap_or(DEFAULT_TTL);
+                continue;
+            }
+            _ => {}
+        }
+
+        let mut index = 0;
+        let owner = if line.starts_with(' ') || line.starts_with('\t') {
+            last_owner.clone()
+        } else {
+            index += 1;
+            absolute_name(&tokens[0], &origin)
+        };
+        last_owner = owner.clone();
+
+        // TTL and class can go in any order before type
+        let mut ttl = default_ttl;
+        while index < tokens.len() {
+            let token = tokens[index].to_uppercase();
+            if let Ok(value) = token.parse::<u32>() {
+                ttl = value;
+            } else if token != "IN" && token != "CH" && token != "HS" {
+                break;
+            }
+            index += 1;
+        }
+        if index >= tokens.len() {
+            errors.push(format!("Wrong record in line {}: {}", number + 1, line));
+            continue;
+        }
+
+        match parse_record(&owner, ttl, &tokens[index].to_uppercase(), &tokens[index + 1..], &origin) {
+            Some(record) => records.push(record),
+            None => errors.push(format!("Unsupported or wrong record in line {}: {}", number + 1, line))
+        }
+    }
+    records
+}
+
+/// Makes domain name absolute (without trailing dot, as everywhere in our code)
+pub fn absolute_name(name: &str, origin: &str) -> String {
+    if name == "@" {
+        return origin.to_owned();
+    }
+    if name.ends_with('.') {
+        return name.trim_end_matches('.').to_owned();
+    }
+    if origin.is_empty() {
+        return name.to_owned();
+    }
+    format!("{}.{}", name, origin)
+}
+
+/// Converts record names of domain data ("@", "www", "*") to full names, skipping SOA records
+pub fn make_absolute(records: Vec<DnsRecord>, domain: &str) -> Vec<DnsRecord> {
+    let suffix = format!(".{}", domain);
+    records.into_iter()
+        .filter(|record| record.get_querytype() != QueryType::SOA)
+        .filter_map(|mut record| {
+            let name = record.get_domain()?;
+            let name = name.trim_end_matches('.').to_lowercase();
+            if name == "@" || name.is_empty() {
+                record.set_domain(domain);
+            } else if name != domain && !name.ends_with(&suffix) {
+                record.set_domain(&format!("{}{}", name, suffix));
+            }
+            Some(record)
+        })
+        .collect()
+}
+
+/// Writes records as zone text, all names are written as absolute (with trailing dots).
+/// Records of types that can't be written are skipped.
+pub fn write_zone(origin: &str, records: &[DnsRecord]) -> String {
+    let mut text = format!("$ORIGIN {}.\n$TTL {}\n", origin.trim_end_matches('.'), DEFAULT_TTL);
+    for record in records {
+        if let Some(line) = write_record(record) {
+            text.push_str(&line);
+            text.push('\n');
+        }
+    }
+    text
+}
+
+/// Makes one line of zone file from the record
+pub fn write_record(record: &DnsRecord) -> Option<String> {
+    let line = match record {
+        DnsRecord::A { domain, addr, ttl } => format!("{}. {} IN A {}", domain, ttl.0, addr),
+        DnsRecord::AAAA { domain, addr, ttl } => format!("{}. {} IN AAAA {}", domain, ttl.0, addr),
+        DnsRecord::NS { domain, host, ttl } => format!("{}. {} IN NS {}.", domain, ttl.0, host.trim_end_matches('.')),
+        DnsRecord::CNAME { domain, host, ttl } => format!("{}. {} IN CNAME {}.", domain, ttl.0, host.trim_end_matches('.')),
+        DnsRecord::MX { domain, priority, host, ttl } => {
+            format!("{}. {} IN MX {} {}.", domain, ttl.0, priority, host.trim_end_matches('.'))
+        }
+        DnsRecord::TXT { domain, data, ttl } => {
+            format!("{}. {} IN TXT \"{}\"", domain, ttl.0, data.replace('\\', "\\\\").replace('"', "\\\""))
+        }
+        DnsRecord::SRV { domain, priority, weight, port, host, ttl } => {
+            format!("{}. {} IN SRV {} {} {} {}.", domain, ttl.0, priority, weight, port, host.trim_end_matches('.'))
+        }
+        DnsRecord::TLSA { domain, usage, selector, matching, data, ttl } => {
+            format!("{}. {} IN TLSA {} {} {} {}", domain, ttl.0, usage, selector, matching, data)
+        }
+        DnsRecord::SOA { domain, m_name, r_name, serial, refresh, retry, expire, minimum, ttl } => {
+            format!("{}. {} IN SOA {}. {}. {} {} {} {} {}", domain, ttl.0, m_name.trim_end_matches('.'), r_name.trim_end_matches('.'),
+                    serial, refresh, retry, expire, minimum)
+        }
+        _ => return None
+    };
+    Some(line)
+}
+
+fn parse_record(domain: &str, ttl: u32, qtype: &str, data: &[String], origin: &str) -> Option<DnsRecord> {
+    let domain = domain.to_owned();
+    let ttl = TransientTtl(ttl);
+    let record = match qtype {
+        "A" => DnsRecord::A { domain, addr: data.get(0)?.parse::<Ipv4Addr>().ok()?, ttl },
+        "AAAA" => DnsRecord::AAAA { domain, addr: data.get(0)?.parse::<Ipv6Addr>().ok()?, ttl },
+        "NS" => DnsRecord::NS { domain, host: absolute_name(data.get(0)?, origin), ttl },
+        "CNAME" => DnsRecord::CNAME { domain, host: absolute_name(data.get(0)?, origin), ttl },
+        "MX" => DnsRecord::MX { domain, priority: data.get(0)?.parse().ok()?, host: absolute_name(data.get(1)?, origin), ttl },
+        "TXT" if !data.is_empty() => DnsRecord::TXT { domain, data: data.concat(), ttl },
+        "SRV" => DnsRecord::SRV {
+            domain,
+            priority: data.get(0)?.parse().ok()?,
+            weight: data.get(1)?.parse().ok()?,
+            port: data.get(2)?.parse().ok()?,
+            host: absolute_name(data.get(3)?, origin),
+            ttl
+        },
+        "TLSA" if data.len() > 3 => DnsRecord::TLSA {
+            domain,
+            usage: data.get(0)?.parse().ok()?,
+            selector: data.get(1)?.parse().ok()?,
+            matching: data.get(2)?.parse().ok()?,
+            data: data[3..].concat().to_uppercase(),
+            ttl
+        },
+        "SOA" => DnsRecord::SOA {
+            domain,
+            m_name: absolute_name(data.get(0)?, origin),
+            r_name: absolute_name(data.get(1)?, origin),
+            serial: data.get(2)?.parse().ok()?,
+            refresh: data.get(3)?.parse().ok()?,
+            retry: data.get(4)?.parse().ok()?,
+            expire: data.get(5)?.parse().ok()?,
+            minimum: data.get(6)?.parse().ok()?,
+            ttl
+        },
+        _ => return None
+    };
+    Some(record)
+}
+
+/// Removes comments and joins records that span several lines in parentheses
+fn join_lines(text: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    for line in text.lines() {
+        let mut quoted = false;
+        let mut escaped = false;
+        let mut clean = String::with_capacity(line.len());
+        for c in line.chars() {
+            if escaped {
+                escaped = false;
+                clean.push(c);
+                continue;
+            }
+            match c {
+                '\\' if quoted => { escaped = true; clean.push(c); }
+                '"' => { quoted = !quoted; clean.push(c); }
+                ';' if !quoted => break,
+                '(' if !quoted => { depth += 1; clean.push(' '); }
+                ')' if !quoted => { depth -= 1; clean.push(' '); }
+                _ => clean.push(c)
+            }
+        }
+        current.push_str(&clean);
+        if depth <= 0 {
+            depth = 0;
+            if !current.trim().is_empty() {
+                result.push(current.trim_end().to_owned());
+            }
+            current = String::new();
+        } else {
+            current.push(' ');
+        }
+    }
+    if !current.trim().is_empty() {
+        result.push(current.trim_end().to_owned());
+    }
+    result
+}
+
+/// Splits line by whitespace, keeping quoted strings together (without quotes and escapes)
+fn tokenize(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in line.chars() {
+        if escaped {
+            escaped = false;
+            current.push(c);
+            continue;
+        }
+        match c {
+            '\\' if quoted => escaped = true,
+            '"' => {
+                if quoted {
+                    tokens.push(current.clone());
+                    current.clear();
+                }
+                quoted = !quoted;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    tokens.push(current.clone());
+                    current.clear();
+                }
+            }
+            _ => current.push(c)
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::dns::protocol::{DnsRecord, TransientTtl};
+    use crate::dns::zonefile::{make_absolute, parse_domain_records, parse_zone, write_zone};
+
+    #[test]
+    fn absolute_names() {
+        let addr = Ipv4Addr::new(10, 0, 0, 1);
+        let records = vec![
+            DnsRecord::A { domain: String::from("@"), addr, ttl: TransientTtl(3600) },
+            DnsRecord::A { domain: String::from("www"), addr, ttl: TransientTtl(3600) },
+            DnsRecord::A { domain: String::from("*"), addr, ttl: TransientTtl(3600) },
+            DnsRecord::A { domain: String::from("mail.example.tld"), addr, ttl: TransientTtl(3600) },
+        ];
+        let names: Vec<String> = make_absolute(records, "example.tld").iter().map(|r| r.get_domain().unwrap()).collect();
+        assert_eq!(vec!["example.tld", "www.example.tld", "*.example.tld", "mail.example.tld"], names);
+    }
+
+    #[test]
+    fn domain_records() {
+        let text = "$ORIGIN site.ygg.\n@ SOA ns admin 1 3600 300 604800 60\n@ AAAA 200::1\nwww CNAME site.ygg.\n";
+        let records = parse_domain_records(text, "site.ygg").unwrap();
+        assert_eq!(2, records.len());
+        assert_eq!(Some(String::from("@")), records[0].get_domain());
+        assert_eq!(Some(String::from("www")), records[1].get_domain());
+
+        assert!(parse_domain_records("@ AAAA 200::1\n@ HINFO PC Linux\n", "site.ygg").is_err());
+        assert!(parse_domain_records("other.ygg. AAAA 200::1\n", "site.ygg").is_err());
+    }
+
+    #[test]
+    fn write_and_parse() {
+        let records = vec![
+            DnsRecord::A { domain: String::from("example.tld"), addr: Ipv4Addr::new(10, 0, 0, 1), ttl: TransientTtl(600) },
+            DnsRecord::MX { domain: String::from("example.tld"), priority: 10, host: String::from("mail.example.tld"), ttl: TransientTtl(3600) },
+            DnsRecord::TXT { domain: String::from("www.example.tld"), data: String::from("say \"hi\"; ok"), ttl: TransientTtl(60) },
+            DnsRecord::TLSA { domain: String::from("_443._tcp.example.tld"), usage: 3, selector: 1, matching: 1, data: String::from("0BADC0DE"), ttl: TransientTtl(3600) },
+        ];
+        let text = write_zone("tld", &records);
+        assert_eq!(records, parse_zone(&text, ""));
+    }
+
+    #[test]
+    pub fn parse_simple_zone() {
+        let text = r#"
+$ORIGIN example.com.
+$TTL 600
+@   IN  SOA ns1 admin (
+        2021040101 ; serial
+        3600 300 604800 60 )
+    IN  NS  ns1.example.com.
+www     A   127.0.0.1
+www 60  IN  AAAA ::1
+txt     TXT "hello; world" "again"
+mail.example.com. MX 10 mx
+"#;
+        let records = parse_zone(text, "");
+        assert_eq!(6, records.len());
+        match &records[0] {
+            DnsRecord::SOA { domain, m_name, serial, minimum, .. } => {
+                assert_eq!("example.com", domain);
+                assert_eq!("ns1.example.com", m_name);
+                assert_eq!(2021040101, *serial);
+                assert_eq!(60, *minimum);
+            }
+            _ => panic!()
+        }
+        match &records[1] {
+            DnsRecord::NS { domain, host, ttl } => {
+                assert_eq!("example.com", domain);
+                assert_eq!("ns1.example.com", host);
+                assert_eq!(600, ttl.0);
+            }
+            _ => panic!()
+        }
+        match &records[3] {
+            DnsRecord::AAAA { domain, ttl, .. } => {
+                assert_eq!("www.example.com", domain);
+                assert_eq!(60, ttl.0);
+            }
+            _ => panic!()
+        }
+        match &records[4] {
+            DnsRecord::TXT { data, .. } => assert_eq!("hello; worldagain", data),
+            _ => panic!()
+        }
+        match &records[5] {
+            DnsRecord::MX { domain, priority, host, .. } => {
+                assert_eq!("mail.example.com", domain);
+                assert_eq!(10, *priority);
+                assert_eq!("mx.example.com", host);
+            }
+            _ => panic!()
+        }
+    }
+}
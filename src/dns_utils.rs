@@ -1,42 +1,303 @@
 use std::sync::{Arc, Mutex};
-use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::HashMap;
+use std::{env, thread};
+use std::time::Duration;
 
 use crate::{Context, Settings};
 use crate::blockchain::filter::BlockchainFilter;
-use crate::dns::server::{DnsServer, DnsUdpServer, DnsTcpServer};
+use crate::blockchain::hash_utils::hash_identity;
+use crate::blockchain::primary::BlockchainZoneProvider;
+use crate::dns::server::{DnsServer, DnsUdpServer, DnsTcpServer, Listener, ServerError};
+use crate::dns::doh::DnsHttpsServer;
 use crate::dns::context::{ServerContext, ResolveStrategy};
+use crate::dns::pool::WorkerPool;
 #[allow(unused_imports)]
 use log::{debug, error, info, LevelFilter, trace, warn};
 use crate::dns::hosts::HostsFilter;
+use crate::dns::views::ClientView;
+use crate::dns::rpz::RpzFilter;
+use crate::dns::transfer::{ZoneTransfer, request_zone, make_zone};
+use crate::dns::protocol::{DnsRecord, QueryType};
+use crate::dns::routes::{RouteTable, MAIN_SOURCE};
+use crate::settings::{Dns, ZoneImport};
+use crate::event::Event;
+use crate::chains::ExtraChainContext;
+use crate::memory::MEGABYTE;
+use crate::port_conflict::explain_bind_error;
 
-/// Starts UDP and TCP DNS-servers
-pub fn start_dns_server(context: &Arc<Mutex<Context>>, settings: &Settings) {
-    let server_context = create_server_context(Arc::clone(&context), &settings);
+/// How often to save DNS cache to disk, if enabled
+const CACHE_SAVE_INTERVAL_SEC: u64 = 300;
+/// How often to post counters of DNS cache to the bus
+const CACHE_STATS_INTERVAL_SEC: u64 = 60;
+/// How often to check if our primary zones have changed
+const NOTIFY_CHECK_INTERVAL_SEC: u64 = 5;
+/// How often to check imported zones if the primary server is unavailable, or doesn't tell us
+const IMPORT_RETRY_INTERVAL_SEC: u64 = 300;
+/// Timeout for zone transfers
+const IMPORT_TIMEOUT_SEC: u64 = 30;
 
-    if server_context.enable_udp {
-        let udp_server = DnsUdpServer::new(Arc::clone(&server_context), settings.dns.threads);
-        if let Err(e) = udp_server.run_server() {
-            error!("Failed to bind UDP listener: {:?}", e);
+/// Starts UDP and TCP DNS-servers on every listen address, returns their common context
+pub fn start_dns_server(context: &Arc<Mutex<Context>>, chains: &[ExtraChainContext], settings: &Settings) -> Arc<ServerContext> {
+    let server_context = create_server_context(Arc::clone(&context), chains, &settings);
+    // All listeners share one pool of workers
+    let pool = WorkerPool::start(settings.dns.threads, settings.dns.queue_size);
+
+    for listen in &settings.dns.listen {
+        let listener = Listener::new(&listen.address, &listen.allow, settings.dns.fallback_port);
+        if server_context.enable_udp {
+            let udp_server = DnsUdpServer::new(Arc::clone(&server_context), listener.clone(), Arc::clone(&pool));
+            if let Err(e) = udp_server.run_server() {
+                error!("Cannot start UDP DNS server on {}: {}! Change listen address in config!", &listen.address, e);
+                report_port_conflict(&listen.address, true, &e);
+            }
+        }
+
+        if server_context.enable_tcp {
+            let tcp_server = DnsTcpServer::new(Arc::clone(&server_context), listener, Arc::clone(&pool));
+            if let Err(e) = tcp_server.run_server() {
+                error!("Cannot start TCP DNS server on {}: {}! Change listen address in config!", &listen.address, e);
+                report_port_conflict(&listen.address, false, &e);
+            }
         }
     }
 
-    if server_context.enable_tcp {
-        let tcp_server = DnsTcpServer::new(Arc::clone(&server_context), settings.dns.threads);
-        if let Err(e) = tcp_server.run_server() {
-            error!("Failed to bind TCP listener: {:?}", e);
+    let doh = &settings.dns.doh;
+    if !doh.listen.is_empty() {
+        let listener = Listener::new(&doh.listen, &doh.allow, 0);
+        match DnsHttpsServer::new(Arc::clone(&server_context), listener, Arc::clone(&pool), doh) {
+            Ok(server) => match server.run_server() {
+                Ok(_) => info!("DNS-over-HTTPS server is listening on {}{}", &doh.listen, &doh.path),
+                Err(e) => error!("Cannot start DNS-over-HTTPS server on {}: {}! Change listen address in config!", &doh.listen, e)
+            },
+            Err(e) => error!("Cannot start DNS-over-HTTPS server: {}", e)
+        }
+    }
+
+    start_cache_invalidator(context, Arc::clone(&server_context));
+    start_cache_stats(context, Arc::clone(&server_context));
+
+    if !settings.dns.cache_file.is_empty() {
+        start_cache_saver(context, Arc::clone(&server_context), &settings.dns.cache_file);
+    }
+
+    for import in &settings.dns.imports {
+        start_zone_import(Arc::clone(&server_context), import.clone());
+    }
+
+    if server_context.transfer.is_some() {
+        start_notifier(context, Arc::clone(&server_context));
+    }
+
+    server_context
+}
+
+fn load_routes(server_context: &ServerContext, routes: &HashMap<String, String>) -> Result<RouteTable, String> {
+    let sources: Vec<&str> = server_context.sources.iter().map(|(name, _)| name.as_str()).collect();
+    RouteTable::parse(routes, &sources)
+}
+
+/// Applies forwarders and routes of reloaded settings, other DNS settings need restart
+pub fn apply_dns_settings(server_context: &ServerContext, dns: &Dns) {
+    let strategy = get_resolve_strategy(dns);
+    if server_context.get_resolve_strategy() != strategy {
+        info!("DNS resolving is changed to {:?}", &strategy);
+        server_context.set_resolve_strategy(strategy);
+    }
+    match load_routes(server_context, &dns.routes) {
+        Ok(routes) => {
+            if *server_context.routes.read().unwrap() != routes {
+                info!("Reloaded {} DNS routes", routes.len());
+                *server_context.routes.write().unwrap() = routes;
+            }
+        }
+        Err(e) => warn!("Unable to reload DNS routes: {}", e)
+    }
+}
+
+fn get_resolve_strategy(dns: &Dns) -> ResolveStrategy {
+    match dns.recursive || dns.forwarders.is_empty() {
+        true => ResolveStrategy::Recursive,
+        false => ResolveStrategy::Forward {
+            upstreams: dns.forwarders.clone(),
+            retries: dns.forwarder_retries,
+            parallel: dns.forwarder_parallel
+        }
+    }
+}
+
+/// Transfers some zone from primary server to our local zones, and then keeps it up to date
+fn start_zone_import(server_context: Arc<ServerContext>, import: ZoneImport) {
+    let name = format!("DNS import {}", &import.zone);
+    let _ = thread::Builder::new().name(name).spawn(move || {
+        let zone = import.zone.trim_end_matches('.').to_lowercase();
+        loop {
+            let interval = match import_zone(&server_context, &zone, &import.primary) {
+                Some(refresh) if refresh > 0 => refresh as u64,
+                _ => IMPORT_RETRY_INTERVAL_SEC
+            };
+            thread::sleep(Duration::from_secs(interval));
+        }
+    });
+}
+
+/// Imports the zone if its serial has changed, returns refresh interval of the zone
+fn import_zone(server_context: &ServerContext, zone: &str, primary: &str) -> Option<u32> {
+    let current = server_context.authority.read().ok()?.get_zone(zone).map(|z| (z.serial, z.refresh));
+    if let Some((serial, refresh)) = current {
+        if let Ok(packet) = server_context.client.send_query(zone, QueryType::SOA, primary, false) {
+            let same = packet.answers.iter().any(|r| match r {
+                DnsRecord::SOA { serial: s, .. } => *s == serial,
+                _ => false
+            });
+            if same {
+                trace!("Zone '{}' has not changed", zone);
+                return Some(refresh);
+            }
+        }
+    }
+
+    match request_zone(zone, primary, Duration::from_secs(IMPORT_TIMEOUT_SEC)) {
+        Ok(records) => {
+            let zone = make_zone(records)?;
+            info!("Imported zone '{}' with {} records from {}", &zone.domain, zone.records.len(), primary);
+            let refresh = zone.refresh;
+            server_context.authority.write().ok()?.add_zone(zone);
+            Some(refresh)
+        }
+        Err(e) => {
+            warn!("Unable to import zone '{}' from {}: {}", zone, primary, e);
+            None
         }
     }
 }
 
-/// Creates DNS-context with all needed settings
-fn create_server_context(context: Arc<Mutex<Context>>, settings: &Settings) -> Arc<ServerContext> {
+/// Tells what program has taken the port, and how to move it away, instead of a bare bind error
+fn report_port_conflict(address: &str, udp: bool, error: &ServerError) {
+    let ServerError::Io(e) = error;
+    if let Some(explanation) = explain_bind_error(address, udp, e) {
+        error!("{}", explanation);
+    }
+}
+
+/// Sends NOTIFY messages to secondary servers on start and on every change of our zones
+fn start_notifier(context: &Arc<Mutex<Context>>, server_context: Arc<ServerContext>) {
+    let changed = Arc::new(AtomicBool::new(true));
+    {
+        let changed = Arc::clone(&changed);
+        // The context is locked while events are posted, so we only mark the change here
+        context.lock().unwrap().bus.register(move |_uuid, e| {
+            if let Event::BlockchainChanged { .. } = e {
+                changed.store(true, Ordering::Relaxed);
+            }
+            true
+        });
+    }
+
+    let _ = thread::Builder::new().name(String::from("DNS notifier")).spawn(move || {
+        loop {
+            if changed.swap(false, Ordering::Relaxed) {
+                if let Some(transfer) = &server_context.transfer {
+                    transfer.check_serials();
+                }
+            }
+            thread::sleep(Duration::from_secs(NOTIFY_CHECK_INTERVAL_SEC));
+        }
+    });
+}
+
+/// Removes cached names of domains that were changed in blockchain, so clients get new records right away.
+/// Blocks have only hashes of domain names, so cached names in the zone are hashed to find the changed one.
+fn start_cache_invalidator(context: &Arc<Mutex<Context>>, server_context: Arc<ServerContext>) {
+    context.lock().unwrap().bus.register(move |_uuid, e| {
+        if let Event::DomainChanged { zone, identity, .. } = e {
+            let suffix = format!(".{}", &zone);
+            let result = server_context.cache.remove_matching(|name| {
+                let name = name.to_lowercase();
+                if !name.ends_with(&suffix) {
+                    return false;
+                }
+                // Only the last two labels are registered in blockchain
+                let parts: Vec<&str> = name.rsplitn(3, '.').collect();
+                hash_identity(&format!("{}.{}", parts[1], parts[0]), None) == identity
+            });
+            match result {
+                Ok(0) => {}
+                Ok(count) => debug!("Removed {} names of changed domain from DNS cache", count),
+                Err(e) => warn!("Unable to clean DNS cache: {}", e)
+            }
+        }
+        true
+    });
+}
+
+/// Posts counters of DNS cache to the bus periodically, for UI and logs
+fn start_cache_stats(context: &Arc<Mutex<Context>>, server_context: Arc<ServerContext>) {
+    let context = Arc::clone(context);
+    let _ = thread::Builder::new().name(String::from("DNS cache stats")).spawn(move || {
+        loop {
+            thread::sleep(Duration::from_secs(CACHE_STATS_INTERVAL_SEC));
+            if let Ok(stats) = server_context.cache.get_stats() {
+                trace!("DNS cache: {:?}", &stats);
+                context.lock().unwrap().bus.post(Event::DnsCacheStats { stats });
+            }
+        }
+    });
+}
+
+/// Loads saved DNS cache, and then saves it periodically and on exit
+fn start_cache_saver(context: &Arc<Mutex<Context>>, server_context: Arc<ServerContext>, filename: &str) {
+    match server_context.cache.load(filename) {
+        Ok(count) => info!("Loaded {} cached domains from '{}'", count, filename),
+        Err(e) => warn!("Unable to load DNS cache from '{}': {}", filename, e)
+    }
+
+    let filename = filename.to_owned();
+    {
+        let server_context = Arc::clone(&server_context);
+        let filename = filename.clone();
+        context.lock().unwrap().bus.register(move |_uuid, e| {
+            if let Event::ActionQuit = e {
+                save_cache(&server_context, &filename);
+                return false;
+            }
+            true
+        });
+    }
+
+    let _ = thread::Builder::new().name(String::from("DNS cache saver")).spawn(move || {
+        loop {
+            thread::sleep(Duration::from_secs(CACHE_SAVE_INTERVAL_SEC));
+            save_cache(&server_context, &filename);
+        }
+    });
+}
+
+fn save_cache(server_context: &ServerContext, filename: &str) {
+    match server_context.cache.save(filename) {
+        Ok(count) => debug!("Saved {} cached domains to '{}'", count, filename),
+        Err(e) => warn!("Unable to save DNS cache to '{}': {}", filename, e)
+    }
+}
+
+/// Creates DNS-context with all needed settings, domains are looked up by routes, or in the main chain and then in additional ones
+pub fn create_server_context(context: Arc<Mutex<Context>>, chains: &[ExtraChainContext], settings: &Settings) -> Arc<ServerContext> {
     let mut server_context = ServerContext::new();
     server_context.allow_recursive = true;
-    server_context.dns_listen = settings.dns.listen.clone();
-    server_context.resolve_strategy = match settings.dns.forwarders.is_empty() {
-        true => { ResolveStrategy::Recursive }
-        false => { ResolveStrategy::Forward { upstreams: settings.dns.forwarders.clone() } }
-    };
+    server_context.set_resolve_strategy(get_resolve_strategy(&settings.dns));
+    server_context.client.set_timeout(settings.dns.forwarder_timeout);
+    let _ = server_context.cache.set_budget(settings.memory.dns_cache_mb * MEGABYTE);
+    let _ = server_context.cache.set_max_entries(settings.dns.cache.entries);
+    server_context.negative_ttl = settings.dns.cache.negative_ttl;
+    server_context.qname_minimization = settings.dns.qname_minimization;
+    server_context.minimal_responses = settings.dns.minimal_responses;
+    server_context.strip_client_subnet = settings.dns.strip_client_subnet;
+    server_context.refuse_client_subnet = settings.dns.refuse_client_subnet;
+    // Policies go first, they must override everything
+    for rpz in &settings.dns.rpz {
+        debug!("Loading RPZ from '{}'", rpz);
+        server_context.filters.push(Box::new(RpzFilter::from_file(rpz)));
+    }
     // Add host filters
     for host in &settings.dns.hosts {
         if host == "system" {
@@ -56,7 +317,24 @@ fn create_server_context(context: Arc<Mutex<Context>>, settings: &Settings) -> A
             server_context.filters.push(Box::new(HostsFilter::new(host)));
         }
     }
-    server_context.filters.push(Box::new(BlockchainFilter::new(context)));
+    server_context.sources.push((String::from(MAIN_SOURCE), Box::new(BlockchainFilter::new(Arc::clone(&context)))));
+    for chain in chains {
+        server_context.sources.push((chain.name.clone(), Box::new(BlockchainFilter::for_chain(Arc::clone(&chain.context), &chain.name))));
+    }
+    match load_routes(&server_context, &settings.dns.routes) {
+        Ok(routes) => *server_context.routes.write().unwrap() = routes,
+        Err(e) => error!("Unable to use DNS routes: {}", e)
+    }
+    for view in &settings.dns.views {
+        debug!("Loading view '{}' for {:?}", &view.name, &view.clients);
+        server_context.views.push(ClientView::new(&view.name, &view.clients, &view.hosts, &view.rewrites));
+    }
+    let primary = &settings.dns.primary;
+    if !primary.domains.is_empty() {
+        debug!("Serving zones {:?} to secondaries {:?}", &primary.domains, &primary.secondaries);
+        let provider = BlockchainZoneProvider::new(Arc::clone(&context), &primary.domains);
+        server_context.transfer = Some(ZoneTransfer::new(Box::new(provider), &primary.secondaries));
+    }
     match server_context.initialize() {
         Ok(_) => {}
         Err(e) => { panic!("DNS server failed to initialize: {:?}", e); }
@@ -1,3 +1,8 @@
+use crate::Bytes;
+use crate::disk::DiskState;
+use crate::p2p::metrics::NetworkMetrics;
+use crate::dns::cache::CacheStats;
+
 #[derive(Clone, PartialEq, Debug)]
 pub enum Event {
     MinerStarted,
@@ -9,11 +14,31 @@ pub enum Event {
     KeyLoaded { path: String, public: String, hash: String },
     KeySaved { path: String, public: String, hash: String },
     ZonesChanged,
+    /// Some domain in the zone has changed, serial is the index of the block with this change
+    DomainChanged { zone: String, identity: Bytes, serial: u64 },
     NewBlockReceived,
     BlockchainChanged { index: u64 },
+    /// Blocks from `index` were replaced by a branch with more work, BlockchainChanged is posted too
+    ChainReorged { index: u64, removed: u64, added: u64 },
+    /// Signers of the new full block are known, `signer` is true if our key is one of them
+    SignersChosen { index: u64, signer: bool },
     ActionStopMining,
     ActionQuit,
     NetworkStatus { nodes: usize, blocks: u64 },
+    /// Peers and traffic per transport, posted along with NetworkStatus
+    NetworkMetrics { metrics: NetworkMetrics },
+    /// Counters of DNS cache, posted every minute
+    DnsCacheStats { stats: CacheStats },
     Syncing { have: u64, height: u64 },
     SyncFinished,
+    /// Progress of the check of blocks in DB, mining is paused until it is `finished`
+    ChainCheckProgress { checked: u64, height: u64, finished: bool },
+    /// Free space on the DB volume has changed its state
+    DiskSpace { state: DiskState, free_mb: u64 },
+    /// New release is published, `required` if this version can't follow the network after some block
+    UpdateAvailable { version: String, url: String, required: bool },
+    /// Settings file was reloaded, `changed` has names of settings that are applied, like "dns" or "net.peers"
+    SettingsChanged { changed: Vec<String> },
+    /// Something has happened to a watched domain, `kind` is "registered", "changed", "expiring" or "expired"
+    WatchedDomain { domain: String, kind: String, expires: Option<i64> },
 }
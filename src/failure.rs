@@ -0,0 +1,121 @@
+//! Fatal errors with distinct exit codes, and a report about them in JSON for supervisors and support scripts.
+//! Library code panics with a [Failure], so embedding apps can catch it, and the binary turns it into the exit code.
+
+use std::fs;
+use std::panic;
+use std::process;
+use std::thread;
+
+use chrono::Utc;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde::Serialize;
+
+/// File in working directory, where the report about the last fatal error is written
+pub const FAILURE_REPORT: &str = "gis-failure.json";
+/// Exit code of panics, the same as Rust uses
+pub const PANIC_EXIT_CODE: i32 = 101;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailureKind {
+    /// Wrong settings or command line
+    Config,
+    /// Blockchain DB can't be opened or is corrupted
+    Database,
+    /// Some port is taken or not allowed
+    Bind,
+    /// Key file can't be loaded
+    Keys,
+}
+
+impl FailureKind {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            FailureKind::Config => 2,
+            FailureKind::Database => 3,
+            FailureKind::Bind => 4,
+            FailureKind::Keys => 5,
+        }
+    }
+}
+
+/// Payload of fatal panics
+#[derive(Debug)]
+pub struct Failure {
+    pub kind: FailureKind,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+struct FailureReport<'a> {
+    /// Kind of failure, or "panic" for unexpected errors
+    kind: &'a str,
+    code: i32,
+    message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    location: Option<String>,
+    thread: Option<&'a str>,
+    version: &'a str,
+    timestamp: i64,
+}
+
+/// Stops with the error of this kind
+pub fn fatal(kind: FailureKind, message: &str) -> ! {
+    panic::panic_any(Failure { kind, message: message.to_owned() })
+}
+
+/// Writes failure reports on panics, and exits with the code of the failure if it is fatal.
+/// Other panics are reported and handled as usual, so a panic in some thread doesn't stop the node.
+pub fn set_failure_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let location = info.location().map(|l| format!("{}:{}", l.file(), l.line()));
+        match info.payload().downcast_ref::<Failure>() {
+            Some(failure) => {
+                error!("{}", &failure.message);
+                write_report(failure.kind.exit_code(), &format!("{:?}", failure.kind).to_lowercase(), &failure.message, location);
+                log::logger().flush();
+                process::exit(failure.kind.exit_code());
+            }
+            None => {
+                let message = info.payload().downcast_ref::<&str>().map(|s| s.to_string())
+                    .or_else(|| info.payload().downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+                write_report(PANIC_EXIT_CODE, "panic", &message, location);
+                default_hook(info);
+            }
+        }
+    }));
+}
+
+fn write_report(code: i32, kind: &str, message: &str, location: Option<String>) {
+    let thread = thread::current();
+    let report = FailureReport {
+        kind,
+        code,
+        message,
+        location,
+        thread: thread.name(),
+        version: env!("CARGO_PKG_VERSION"),
+        timestamp: Utc::now().timestamp(),
+    };
+    if let Err(e) = fs::write(FAILURE_REPORT, serde_json::to_string_pretty(&report).unwrap()) {
+        warn!("Unable to write failure report '{}': {}", FAILURE_REPORT, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failure_payload() {
+        let result = panic::catch_unwind(|| fatal(FailureKind::Database, "Broken DB"));
+        let payload = result.unwrap_err();
+        let failure = payload.downcast_ref::<Failure>().unwrap();
+        assert_eq!(FailureKind::Database, failure.kind);
+        assert_eq!(3, failure.kind.exit_code());
+        assert_eq!("Broken DB", failure.message);
+    }
+}
@@ -0,0 +1,278 @@
+//! C-compatible interface to embed GIS resolver or light client into mobile apps and VPN-based resolvers,
+//! without spawning the daemon binary. On Android it can be called through JNA, or a tiny JNI wrapper.
+//!
+//! All strings are UTF-8 and null-terminated. Strings returned by these functions must be freed by `gis_free_string`.
+//! Callbacks are called from GIS threads, and they must not call GIS functions synchronously.
+
+use std::ffi::{CStr, CString};
+use std::net::{IpAddr, Ipv4Addr};
+use std::os::raw::{c_char, c_void};
+use std::path::Path;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::{env, thread};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde::Serialize;
+
+use crate::{Chain, Context, Network, Settings, Keystore, dns_utils, MEMORY_DB_NAME};
+use crate::dns::buffer::{BytePacketBuffer, PacketBuffer, VectorPacketBuffer};
+use crate::dns::context::ServerContext;
+use crate::dns::protocol::{DnsPacket, DnsRecord, QueryType};
+use crate::dns::server::execute_query;
+use crate::event::Event;
+use crate::memory;
+use crate::chains::start_chains;
+use crate::disk::start_disk_monitor;
+use crate::light::LightClient;
+use crate::reload::start_settings_watcher;
+use crate::keys::KEY_PASSPHRASE_ENV;
+
+/// Running instance of GIS, opaque for C code
+pub struct GisHandle {
+    context: Arc<Mutex<Context>>,
+    server_context: Arc<ServerContext>,
+}
+
+/// Gets some event as text, like `SyncFinished` or `NetworkStatus { nodes: 5, blocks: 1234 }`
+pub type EventCallback = extern "C" fn(user_data: *mut c_void, event: *const c_char);
+/// Gets the result of resolving as JSON with `rescode` and `answers`
+pub type ResolveCallback = extern "C" fn(user_data: *mut c_void, result: *const c_char);
+
+/// Pointer that was given by C code, we only pass it back to callbacks
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
+#[derive(Serialize)]
+struct ResolveResult {
+    rescode: String,
+    answers: Vec<DnsRecord>,
+}
+
+/// Returns version of the library, this string must not be freed
+#[no_mangle]
+pub extern "C" fn gis_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+/// Starts GIS with settings from `config` file, `work_dir` can be null.
+/// Node mode (full or light) is taken from settings. DNS sockets are not opened if `dns.listen` is empty.
+/// Returns null if something went wrong.
+#[no_mangle]
+pub unsafe extern "C" fn gis_start(config: *const c_char, work_dir: *const c_char) -> *mut GisHandle {
+    if let Some(path) = from_c_str(work_dir) {
+        if let Err(e) = env::set_current_dir(Path::new(&path)) {
+            error!("Unable to change working directory to '{}': {}", &path, e);
+            return ptr::null_mut();
+        }
+    }
+    let config = match from_c_str(config) {
+        Some(config) => config,
+        None => return ptr::null_mut()
+    };
+    let settings = match Settings::load(&config) {
+        Some(settings) => settings,
+        None => {
+            error!("Cannot load settings from {}", &config);
+            return ptr::null_mut();
+        }
+    };
+
+    memory::set_budgets(&settings.memory);
+    // Apps can't answer prompts, they give the passphrase of encrypted key in environment
+    let passphrase = env::var(KEY_PASSPHRASE_ENV).unwrap_or_default();
+    let keystore = Keystore::from_file(&settings.key_file, &passphrase);
+    let light = settings.node.is_light();
+    let mut chain = Chain::new(&settings, settings.get_db_name());
+    chain.check_chain(settings.check_blocks);
+    let mut context = Context::new(env!("CARGO_PKG_VERSION").to_owned(), settings.clone(), keystore, chain);
+    if light {
+        let schedule = context.chain.get_schedule().clone();
+        context.light = Some(Arc::new(LightClient::load(&settings.node.headers_file, settings.chain.get_checkpoints(&settings.get_origin()), schedule)));
+    }
+    let context = Arc::new(Mutex::new(context));
+    if settings.get_db_name() != MEMORY_DB_NAME {
+        start_disk_monitor(&context, settings.get_db_name(), &settings.db);
+    }
+
+    let chains = start_chains(&context, &settings);
+    let server_context = match settings.dns.listen.is_empty() {
+        true => dns_utils::create_server_context(Arc::clone(&context), &chains, &settings),
+        false => dns_utils::start_dns_server(&context, &chains, &settings)
+    };
+    let mut network = Network::new(Arc::clone(&context));
+    if let Err(e) = network.start() {
+        error!("Error starting network: {}", e);
+        return ptr::null_mut();
+    }
+    start_settings_watcher(&context, Some(Arc::clone(&server_context)));
+    Box::into_raw(Box::new(GisHandle { context, server_context }))
+}
+
+/// Stops network and other threads, and frees the handle
+#[no_mangle]
+pub unsafe extern "C" fn gis_stop(handle: *mut GisHandle) {
+    if handle.is_null() {
+        return;
+    }
+    let handle = Box::from_raw(handle);
+    handle.context.lock().unwrap().bus.post(Event::ActionQuit);
+}
+
+/// Sets callback for events of network, sync and blockchain changes
+#[no_mangle]
+pub unsafe extern "C" fn gis_set_event_callback(handle: *mut GisHandle, callback: EventCallback, user_data: *mut c_void) {
+    let handle = match handle.as_ref() {
+        Some(handle) => handle,
+        None => return
+    };
+    let user_data = UserData(user_data);
+    handle.context.lock().unwrap().bus.register(move |_uuid, e| {
+        let text = CString::new(format!("{:?}", &e)).unwrap_or_default();
+        callback(user_data.0, text.as_ptr());
+        !matches!(e, Event::ActionQuit)
+    });
+}
+
+/// Resolves the name and returns JSON with result code and answers, or null on wrong arguments.
+/// It blocks until the name is resolved, use `gis_resolve_async` from UI threads.
+#[no_mangle]
+pub unsafe extern "C" fn gis_resolve(handle: *mut GisHandle, name: *const c_char, qtype: u16) -> *mut c_char {
+    let (handle, name) = match (handle.as_ref(), from_c_str(name)) {
+        (Some(handle), Some(name)) => (handle, name),
+        _ => return ptr::null_mut()
+    };
+    let result = resolve(Arc::clone(&handle.server_context), &name, QueryType::from_num(qtype));
+    CString::new(result).map(|s| s.into_raw()).unwrap_or(ptr::null_mut())
+}
+
+/// Resolves the name in another thread and gives the same JSON as `gis_resolve` to the callback
+#[no_mangle]
+pub unsafe extern "C" fn gis_resolve_async(handle: *mut GisHandle, name: *const c_char, qtype: u16, callback: ResolveCallback, user_data: *mut c_void) {
+    let (handle, name) = match (handle.as_ref(), from_c_str(name)) {
+        (Some(handle), Some(name)) => (handle, name),
+        _ => return
+    };
+    let server_context = Arc::clone(&handle.server_context);
+    let user_data = UserData(user_data);
+    thread::spawn(move || {
+        let result = resolve(server_context, &name, QueryType::from_num(qtype));
+        let result = CString::new(result).unwrap_or_default();
+        callback(user_data.0, result.as_ptr());
+    });
+}
+
+/// Answers raw DNS query, as VPN-based resolvers get them from TUN device.
+/// Writes the response to `response` buffer and returns its length, or -1 on error.
+#[no_mangle]
+pub unsafe extern "C" fn gis_handle_query(handle: *mut GisHandle, query: *const u8, query_len: usize, response: *mut u8, response_len: usize) -> isize {
+    let handle = match handle.as_ref() {
+        Some(handle) if !query.is_null() && !response.is_null() => handle,
+        _ => return -1
+    };
+    let query = std::slice::from_raw_parts(query, query_len);
+    let mut buffer = BytePacketBuffer::new();
+    if query.len() > buffer.buf.len() {
+        return -1;
+    }
+    buffer.buf[..query.len()].copy_from_slice(query);
+    let request = match DnsPacket::from_buffer(&mut buffer) {
+        Ok(request) => request,
+        Err(_) => return -1
+    };
+
+    let mut packet = execute_query(Arc::clone(&handle.server_context), &request, IpAddr::V4(Ipv4Addr::LOCALHOST));
+    let mut res_buffer = VectorPacketBuffer::new();
+    if packet.write(&mut res_buffer, response_len).is_err() {
+        return -1;
+    }
+    let len = res_buffer.pos();
+    if len > response_len {
+        return -1;
+    }
+    match res_buffer.get_range(0, len) {
+        Ok(data) => {
+            ptr::copy_nonoverlapping(data.as_ptr(), response, len);
+            len as isize
+        }
+        Err(_) => -1
+    }
+}
+
+/// Frees the string, that was returned by GIS
+#[no_mangle]
+pub unsafe extern "C" fn gis_free_string(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+fn resolve(server_context: Arc<ServerContext>, name: &str, qtype: QueryType) -> String {
+    let mut resolver = server_context.create_resolver(Arc::clone(&server_context));
+    let result = match resolver.resolve(name, qtype, true) {
+        Ok(packet) => ResolveResult { rescode: format!("{:?}", packet.header.rescode), answers: packet.answers },
+        Err(e) => {
+            debug!("Error resolving '{}': {}", name, e);
+            ResolveResult { rescode: String::from("SERVFAIL"), answers: Vec::new() }
+        }
+    };
+    serde_json::to_string(&result).unwrap()
+}
+
+unsafe fn from_c_str(string: *const c_char) -> Option<String> {
+    if string.is_null() {
+        return None;
+    }
+    CStr::from_ptr(string).to_str().ok().map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::MEMORY_DB_NAME;
+    use crate::dns::context::ResolveStrategy;
+    use crate::dns::context::tests::create_test_context;
+    use crate::dns::protocol::{DnsQuestion, ResultCode, TransientTtl};
+
+    use super::*;
+
+    #[test]
+    fn handle_query() {
+        let mut server_context = create_test_context(Box::new(|_, _, _, _| {
+            let mut packet = DnsPacket::new();
+            packet.answers.push(DnsRecord::A { domain: String::from("example.com"), addr: Ipv4Addr::LOCALHOST, ttl: TransientTtl(60) });
+            Ok(packet)
+        }));
+        Arc::get_mut(&mut server_context).unwrap().set_resolve_strategy(ResolveStrategy::Forward {
+            upstreams: vec![String::from("127.0.0.1:53")],
+            retries: 0,
+            parallel: false
+        });
+        let chain = Chain::new(&Settings::default(), MEMORY_DB_NAME);
+        let context = Arc::new(Mutex::new(Context::new(String::from("test"), Settings::default(), None, chain)));
+        let mut handle = GisHandle { context, server_context };
+
+        let mut request = DnsPacket::new();
+        request.header.id = 1234;
+        request.header.recursion_desired = true;
+        request.questions.push(DnsQuestion::new(String::from("example.com"), QueryType::A));
+        let mut buffer = BytePacketBuffer::new();
+        request.write(&mut buffer, 512).unwrap();
+
+        let mut response = [0u8; 512];
+        let len = unsafe { gis_handle_query(&mut handle, buffer.buf.as_ptr(), buffer.pos(), response.as_mut_ptr(), response.len()) };
+        assert!(len > 0);
+
+        let mut buffer = BytePacketBuffer::new();
+        buffer.buf[..len as usize].copy_from_slice(&response[..len as usize]);
+        let packet = DnsPacket::from_buffer(&mut buffer).unwrap();
+        assert_eq!(1234, packet.header.id);
+        assert_eq!(ResultCode::NOERROR, packet.header.rescode);
+        assert_eq!(1, packet.answers.len());
+
+        let len = unsafe { gis_handle_query(&mut handle, ptr::null(), 0, response.as_mut_ptr(), response.len()) };
+        assert_eq!(-1, len);
+    }
+}
@@ -0,0 +1,59 @@
+//! Human-readable fingerprints of public keys, to recognize keys in logs, explorer and UI.
+//! A fingerprint is four words, chosen by the bytes of SHA-256 hash of the key.
+
+use crate::blockchain::hash_utils::hash_sha256;
+
+/// How many words are in a fingerprint
+const FINGERPRINT_WORDS: usize = 4;
+
+const WORDS: [&str; 256] = [
+    "acid", "acorn", "actor", "adobe", "agent", "album", "alien", "alpha", "amber", "angel", "ankle", "apple", "apron",
+    "arch", "arena", "arrow", "aspen", "atlas", "attic", "audio", "autumn", "avenue", "badge", "bagel", "baker",
+    "bamboo", "banjo", "barn", "basil", "basin", "beach", "beacon", "beard", "beaver", "berry", "bison", "blade",
+    "blaze", "bloom", "board", "boat", "bonus", "border", "bottle", "brain", "brave", "bread", "brick", "bridge",
+    "brook", "broom", "brush", "bubble", "bucket", "buffalo", "bugle", "cabin", "cable", "cactus", "camel", "camera",
+    "candle", "canoe", "canyon", "carbon", "cargo", "carpet", "castle", "cedar", "cello", "chalk", "cherry", "chess",
+    "chief", "cider", "cinema", "circle", "citrus", "clay", "cliff", "clock", "cloud", "clover", "coast", "cobra",
+    "cocoa", "comet", "coral", "cotton", "cougar", "crane", "crater", "crayon", "cricket", "crown", "cubic", "cycle",
+    "daisy", "dancer", "delta", "denim", "desert", "diesel", "dingo", "disco", "dock", "dolphin", "donkey", "dragon",
+    "drum", "eagle", "echo", "eclipse", "elbow", "elder", "ember", "emerald", "engine", "fabric", "falcon", "feather",
+    "fern", "ferry", "fiddle", "film", "flame", "flute", "forest", "fossil", "fox", "frost", "galaxy", "garden",
+    "garlic", "gecko", "geyser", "ginger", "glacier", "globe", "goose", "grape", "gravel", "guitar", "hammer",
+    "harbor", "harp", "hazel", "helmet", "heron", "hill", "honey", "hornet", "hotel", "husky", "igloo", "island",
+    "ivory", "jacket", "jaguar", "jasmine", "jelly", "jewel", "jungle", "kayak", "kettle", "kiwi", "koala", "ladder",
+    "lagoon", "lamp", "lantern", "laser", "lava", "lemon", "lily", "lion", "lizard", "lobster", "locket", "lotus",
+    "lunar", "magnet", "mango", "maple", "marble", "meadow", "melon", "meteor", "mint", "mirror", "mocha", "molar",
+    "monkey", "moose", "mosaic", "motor", "mountain", "mural", "nectar", "needle", "nickel", "noodle", "oasis",
+    "ocean", "olive", "onion", "opal", "orbit", "orchid", "otter", "owl", "oyster", "paddle", "panda", "paper",
+    "parrot", "peach", "pearl", "pebble", "pepper", "piano", "pilot", "pine", "planet", "plum", "polar", "pony",
+    "poppy", "prism", "pumpkin", "puzzle", "quartz", "quill", "rabbit", "radar", "radio", "raven", "reef", "rhino",
+    "ribbon", "river", "robin", "rocket", "rose", "ruby", "saddle", "salmon", "satin", "scarf", "shark", "shell",
+    "silver", "sketch", "sloth", "snail", "sonic"
+];
+
+/// Gets the fingerprint of public key, like "elder-onion-eagle-garden"
+pub fn get_fingerprint(key: &[u8]) -> String {
+    let hash = hash_sha256(key);
+    hash.iter()
+        .take(FINGERPRINT_WORDS)
+        .map(|byte| WORDS[*byte as usize])
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprints() {
+        let first = get_fingerprint(&[1u8; 32]);
+        assert_eq!("elder-onion-eagle-garden", first);
+        assert_eq!(FINGERPRINT_WORDS, first.split('-').count());
+        assert_eq!(first, get_fingerprint(&[1u8; 32]));
+        assert_ne!(first, get_fingerprint(&[2u8; 32]));
+        let mut words = WORDS.to_vec();
+        words.dedup();
+        assert_eq!(256, words.len());
+    }
+}
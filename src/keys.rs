@@ -3,24 +3,32 @@ extern crate ed25519_dalek;
 extern crate serde;
 extern crate serde_json;
 
+#[cfg(feature = "node")]
 use std::thread;
 use std::fs;
-use std::fs::File;
-use std::io::Write;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
 use std::path::Path;
+#[cfg(feature = "node")]
 use std::sync::{Arc, atomic, Mutex};
+#[cfg(feature = "node")]
 use std::sync::atomic::{AtomicBool, AtomicUsize};
+#[cfg(feature = "node")]
+use std::sync::mpsc;
 
 use ed25519_dalek::Keypair;
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
 
 use crate::blockchain::hash_utils::*;
+#[cfg(feature = "node")]
 use crate::{Context, setup_miner_thread};
+#[cfg(feature = "node")]
 use crate::event::Event;
 use crate::commons::KEYSTORE_DIFFICULTY;
 use crate::bytes::Bytes;
-use blakeout::blakeout;
+use blakeout::Blakeout as blakeout;
+#[cfg(feature = "node")]
 use std::time::Instant;
 use std::cell::RefCell;
 use self::ed25519_dalek::{Signer, PublicKey, Verifier, SecretKey};
@@ -28,6 +36,23 @@ use self::ed25519_dalek::ed25519::signature::Signature;
 use rand_old::{CryptoRng, RngCore};
 use rand_old::rngs::OsRng;
 use crate::crypto::Chacha;
+use crate::fingerprint::get_fingerprint;
+use argon2::{Algorithm, Argon2, Params, Version};
+use byteorder::{ByteOrder, LittleEndian};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use chacha20poly1305::aead::{Aead, NewAead};
+
+/// Passphrase of encrypted key, to load it without asking in console
+pub const KEY_PASSPHRASE_ENV: &str = "GIS_KEY_PASSPHRASE";
+/// Key files encrypted by passphrase start with it, plain ones have only the bytes of keys
+const ENCRYPTED_KEY_MAGIC: &[u8] = b"GISKEY2";
+const KEY_SALT_SIZE: usize = 16;
+const KEY_NONCE_SIZE: usize = 24;
+/// Argon2id memory in KiB, passes and lanes, they are saved in the file to be raised later without breaking old keys
+const KEY_ARGON_MEMORY: u32 = 65536;
+const KEY_ARGON_PASSES: u32 = 3;
+const KEY_ARGON_LANES: u32 = 1;
+const KEY_ARGON_PARAMS_SIZE: usize = 12;
 
 #[derive(Debug)]
 pub struct Keystore {
@@ -65,10 +90,15 @@ impl Keystore {
         Keystore { keypair, hash: RefCell::new(Bytes::default()), path: String::new(), chacha }
     }
 
-    pub fn from_file(filename: &str, _password: &str) -> Option<Self> {
+    /// Loads the key, `password` is needed only for keys that are saved with it
+    pub fn from_file(filename: &str, password: &str) -> Option<Self> {
         let path = Path::new(filename);
         match fs::read(&path) {
             Ok(key) => {
+                let key = match is_encrypted_data(&key) {
+                    true => decrypt_key(&key, password)?,
+                    false => key
+                };
                 if key.len() == 32 {
                     let mut keystore = Keystore::from_random_bytes(key.as_slice());
                     keystore.path = path.to_str().unwrap().to_owned();
@@ -94,22 +124,32 @@ impl Keystore {
         }
     }
 
-    pub fn save(&mut self, filename: &str, _password: &str) {
-        match File::create(Path::new(filename)) {
-            Ok(mut f) => {
-                //TODO implement key encryption
-                let bytes = self.keypair.to_bytes();
-                f.write_all(&bytes).expect("Error saving keystore");
-                self.path = filename.to_owned();
-            }
-            Err(_) => { error!("Error saving key file!"); }
+    /// Saves the key, encrypted by `password` if it is not empty.
+    /// It is written to a temp file that replaces the old one, so the old key stays if something fails.
+    pub fn save(&mut self, filename: &str, password: &str) -> io::Result<()> {
+        let bytes = match password.is_empty() {
+            true => self.keypair.to_bytes().to_vec(),
+            false => encrypt_key(&self.keypair.secret.to_bytes(), password)
+        };
+        let temp = format!("{}.tmp", filename);
+        let result = write_secret_file(&temp, &bytes).and_then(|_| fs::rename(&temp, filename));
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
         }
+        result?;
+        self.path = filename.to_owned();
+        Ok(())
     }
 
     pub fn get_public(&self) -> Bytes {
         Bytes::from_bytes(&self.keypair.public.to_bytes())
     }
 
+    /// Gets human-readable fingerprint of public key
+    pub fn get_fingerprint(&self) -> String {
+        get_fingerprint(&self.keypair.public.to_bytes())
+    }
+
     pub fn get_private(&self) -> Bytes {
         Bytes::from_bytes(&self.keypair.secret.to_bytes())
     }
@@ -130,8 +170,10 @@ impl Keystore {
     }
 
     pub fn check(message: &[u8], public_key: &[u8], signature: &[u8]) -> bool {
-        let key = PublicKey::from_bytes(public_key).expect("Wrong public key!");
-        let signature = Signature::from_bytes(signature).unwrap();
+        let (key, signature) = match (PublicKey::from_bytes(public_key), Signature::from_bytes(signature)) {
+            (Ok(key), Ok(signature)) => (key, signature),
+            _ => return false
+        };
         match key.verify(message, &signature) {
             Ok(_) => { true }
             Err(_) => { false }
@@ -162,6 +204,91 @@ impl PartialEq for Keystore {
     }
 }
 
+/// Tells if the key file needs a password to load
+pub fn is_encrypted_key(filename: &str) -> bool {
+    match fs::read(filename) {
+        Ok(key) => is_encrypted_data(&key),
+        Err(_) => false
+    }
+}
+
+fn is_encrypted_data(data: &[u8]) -> bool {
+    data.starts_with(ENCRYPTED_KEY_MAGIC)
+}
+
+/// Encrypts the secret key by XChaCha20-Poly1305 with the key derived from password by Argon2id.
+/// The result has parameters of Argon2id, random salt and nonce after the magic.
+fn encrypt_key(secret: &[u8], password: &str) -> Vec<u8> {
+    let mut salt = [0u8; KEY_SALT_SIZE];
+    let mut nonce = [0u8; KEY_NONCE_SIZE];
+    OsRng::default().fill_bytes(&mut salt);
+    OsRng::default().fill_bytes(&mut nonce);
+    let mut params = [0u8; KEY_ARGON_PARAMS_SIZE];
+    LittleEndian::write_u32_into(&[KEY_ARGON_MEMORY, KEY_ARGON_PASSES, KEY_ARGON_LANES], &mut params);
+    let key = derive_key(password, &salt, &params).expect("Wrong Argon2 parameters");
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(&key));
+    let encrypted = cipher.encrypt(XNonce::from_slice(&nonce), secret).expect("Error encrypting key");
+    let mut result = ENCRYPTED_KEY_MAGIC.to_vec();
+    result.extend_from_slice(&params);
+    result.extend_from_slice(&salt);
+    result.extend_from_slice(&nonce);
+    result.extend_from_slice(&encrypted);
+    result
+}
+
+/// Gets the secret key back, None if the password is wrong or the file is damaged
+fn decrypt_key(data: &[u8], password: &str) -> Option<Vec<u8>> {
+    let data = data.strip_prefix(ENCRYPTED_KEY_MAGIC)?;
+    if data.len() <= KEY_ARGON_PARAMS_SIZE + KEY_SALT_SIZE + KEY_NONCE_SIZE {
+        return None;
+    }
+    let (params, data) = data.split_at(KEY_ARGON_PARAMS_SIZE);
+    let (salt, data) = data.split_at(KEY_SALT_SIZE);
+    let (nonce, data) = data.split_at(KEY_NONCE_SIZE);
+    let key = derive_key(password, salt, params)?;
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(&key));
+    match cipher.decrypt(XNonce::from_slice(nonce), data) {
+        Ok(secret) if secret.len() == 32 => Some(secret),
+        _ => {
+            warn!("Unable to decrypt key, wrong password");
+            None
+        }
+    }
+}
+
+/// Argon2id over password and salt, with memory, passes and lanes from `params`.
+/// Too big parameters are refused, so that damaged files don't take all memory.
+fn derive_key(password: &str, salt: &[u8], params: &[u8]) -> Option<[u8; 32]> {
+    let mut values = [0u32; 3];
+    LittleEndian::read_u32_into(params, &mut values);
+    let [memory, passes, lanes] = values;
+    if memory > KEY_ARGON_MEMORY * 16 || passes > KEY_ARGON_PASSES * 16 || lanes > 64 {
+        warn!("Key file has too big Argon2 parameters");
+        return None;
+    }
+    let params = Params::new(memory, passes, lanes, Some(32)).ok()?;
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .ok()?;
+    Some(key)
+}
+
+/// Writes a new file that only its owner can read on Unix, as it has a secret key
+fn write_secret_file(filename: &str, bytes: &[u8]) -> io::Result<()> {
+    let _ = fs::remove_file(filename);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(filename)?;
+    file.write_all(bytes)?;
+    file.sync_all()
+}
+
 /// Checks if some public key is "strong" enough to mine domains
 /// TODO Optimize by caching Blakeout somewhere
 pub fn check_public_key_strength(key: &Bytes, strength: u32) -> bool {
@@ -169,6 +296,7 @@ pub fn check_public_key_strength(key: &Bytes, strength: u32) -> bool {
     key_hash_difficulty(&bytes) >= strength
 }
 
+#[cfg(feature = "node")]
 pub fn create_key(context: Arc<Mutex<Context>>) {
     let mining = Arc::new(AtomicBool::new(true));
     let miners_count = Arc::new(AtomicUsize::new(0));
@@ -196,7 +324,7 @@ pub fn create_key(context: Arc<Mutex<Context>>) {
                     mining.store(false, atomic::Ordering::SeqCst);
                     let mut context = context.lock().unwrap();
                     let hash = keystore.get_hash().to_string();
-                    info!("Key mined successfully: {:?}, hash: {}", &keystore.get_public(), &hash);
+                    info!("Key mined successfully: {:?} ({}), hash: {}", &keystore.get_public(), keystore.get_fingerprint(), &hash);
                     context.bus.post(Event::KeyCreated { path: keystore.get_path().to_owned(), public: keystore.get_public().to_string(), hash });
                     context.set_keystore(Some(keystore));
                 }
@@ -218,6 +346,26 @@ pub fn create_key(context: Arc<Mutex<Context>>) {
     });
 }
 
+/// Mines a key with hash of `difficulty` in several threads, without context, for command line
+#[cfg(feature = "node")]
+pub fn mine_key(difficulty: u32, threads: usize) -> Keystore {
+    let mining = Arc::new(AtomicBool::new(true));
+    let (sender, receiver) = mpsc::channel();
+    for _ in 0..threads.max(1) {
+        let mining = Arc::clone(&mining);
+        let sender = sender.clone();
+        thread::spawn(move || {
+            if let Some(keystore) = generate_key(difficulty, mining) {
+                let _ = sender.send(keystore);
+            }
+        });
+    }
+    let keystore = receiver.recv().expect("Key generators have stopped");
+    mining.store(false, atomic::Ordering::SeqCst);
+    keystore
+}
+
+#[cfg(feature = "node")]
 fn generate_key(difficulty: u32, mining: Arc<AtomicBool>) -> Option<Keystore> {
     use self::rand::RngCore;
     let mut rng = rand::thread_rng();
@@ -257,6 +405,44 @@ fn get_chacha(keypair: &Keypair) -> Chacha {
 #[cfg(test)]
 mod tests {
     use crate::Keystore;
+    use super::{decrypt_key, encrypt_key, is_encrypted_key};
+
+    #[test]
+    pub fn test_encrypted_key() {
+        let keystore = Keystore::from_random_bytes(&[5u8; 32]);
+        let secret = keystore.get_private();
+        let data = encrypt_key(secret.as_slice(), "passphrase");
+        assert_ne!(data, encrypt_key(secret.as_slice(), "passphrase"), "Salt and nonce must be random");
+        assert_eq!(Some(secret.to_vec()), decrypt_key(&data, "passphrase"));
+        assert_eq!(None, decrypt_key(&data, "other"));
+        assert_eq!(None, decrypt_key(&data[..60], "passphrase"));
+        let mut damaged = data.clone();
+        let last = damaged.len() - 1;
+        damaged[last] ^= 1;
+        assert_eq!(None, decrypt_key(&damaged, "passphrase"));
+    }
+
+    #[test]
+    pub fn test_save() {
+        let path = std::env::temp_dir().join("gis_test_save.key");
+        let path = path.to_str().unwrap();
+        let mut keystore = Keystore::from_random_bytes(&[5u8; 32]);
+        keystore.save(path, "").unwrap();
+        assert!(!is_encrypted_key(path));
+        keystore.save(path, "passphrase").unwrap();
+        assert!(is_encrypted_key(path));
+        assert_eq!(Some(keystore.get_private().to_vec()), decrypt_key(&std::fs::read(path).unwrap(), "passphrase"));
+        assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(0o600, std::fs::metadata(path).unwrap().permissions().mode() & 0o777);
+        }
+        // The old key stays if the new one can't be written
+        assert!(keystore.save(&format!("{}/absent/dir.key", path), "").is_err());
+        assert_eq!(path, keystore.get_path());
+        let _ = std::fs::remove_file(path);
+    }
 
     #[test]
     pub fn test_signature() {
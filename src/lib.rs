@@ -1,29 +1,78 @@
 pub use blockchain::block::Block;
 pub use blockchain::transaction::Transaction;
 
+#[cfg(feature = "node")]
 pub use crate::blockchain::Chain;
+#[cfg(feature = "node")]
 pub use crate::context::Context;
+#[cfg(feature = "node")]
 pub use crate::miner::Miner;
+#[cfg(feature = "node")]
 pub use crate::p2p::Network;
 pub use crate::settings::Settings;
 pub use crate::bytes::Bytes;
 pub use crate::keys::Keystore;
 pub use crate::x_zones::ExternalZones;
+#[cfg(feature = "node")]
 pub use crate::simplebus::*;
 pub use crate::commons::*;
 
 pub mod blockchain;
 pub mod commons;
-pub mod simplebus;
 pub mod keys;
-pub mod miner;
-pub mod context;
-pub mod event;
-pub mod p2p;
 pub mod dns;
-pub mod dns_utils;
 pub mod settings;
 pub mod bytes;
 pub mod x_zones;
 pub mod crypto;
+pub mod sockets;
+pub mod fingerprint;
+pub mod confusables;
+pub mod memory;
+
+// Everything below needs threads, sockets and DB, it is not built for browsers
+#[cfg(feature = "node")]
+pub mod event;
+#[cfg(feature = "node")]
+pub mod simplebus;
+#[cfg(feature = "node")]
+pub mod miner;
+#[cfg(feature = "node")]
+pub mod context;
+#[cfg(feature = "node")]
+pub mod p2p;
+#[cfg(feature = "node")]
+pub mod dns_utils;
+#[cfg(feature = "node")]
+pub mod light;
+#[cfg(feature = "node")]
+pub mod ffi;
+#[cfg(feature = "node")]
+pub mod api;
+#[cfg(feature = "node")]
+pub mod cert;
+#[cfg(feature = "node")]
+pub mod logging;
+#[cfg(feature = "node")]
+pub mod chains;
+#[cfg(feature = "node")]
+pub mod disk;
+#[cfg(feature = "node")]
+pub mod proxy;
+#[cfg(feature = "node")]
+pub mod address;
+#[cfg(feature = "node")]
+pub mod failure;
+#[cfg(feature = "node")]
+pub mod rpc;
+#[cfg(feature = "node")]
+pub mod port_conflict;
+#[cfg(feature = "node")]
+pub mod updates;
+#[cfg(feature = "node")]
+pub mod checker;
+#[cfg(feature = "node")]
+pub mod reload;
 
+#[cfg(feature = "wasm")]
+pub mod wasm;
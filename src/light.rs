@@ -0,0 +1,379 @@
+//! Light node mode: only block headers are kept, and domains are asked from full nodes with proofs.
+//! It is suitable for phones and routers, that have just a few megabytes of storage.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{Block, Bytes, Transaction};
+use crate::blockchain::checkpoints::Checkpoints;
+use crate::blockchain::consensus::{ConsensusParams, Schedule};
+use crate::blockchain::hash_utils::{check_block_hash, check_block_signature, hash_difficulty, hash_identity};
+use crate::blockchain::proof::DomainProof;
+use crate::blockchain::transaction::{DomainData, TransactionType, ZoneData};
+use crate::blockchain::types::BlockQuality;
+use crate::commons::constants::*;
+use crate::keys::check_public_key_strength;
+use crate::p2p::Message;
+
+/// How long to wait for answer from full nodes
+const QUERY_TIMEOUT_MS: u64 = 3000;
+/// How often to save headers to disk
+const SAVE_INTERVAL_SEC: u64 = 30;
+
+/// Everything we keep from a block in light mode
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Header {
+    pub index: u64,
+    pub timestamp: i64,
+    pub hash: Bytes,
+    pub prev_block_hash: Bytes,
+    /// Needed to check work of headers in headers-first sync of full nodes
+    #[serde(default)]
+    pub difficulty: u32,
+}
+
+impl Header {
+    pub fn from_block(block: &Block) -> Self {
+        Header { index: block.index, timestamp: block.timestamp, hash: block.hash.clone(), prev_block_hash: block.prev_block_hash.clone(), difficulty: block.difficulty }
+    }
+}
+
+/// Chain of block headers, and names of zones that were seen in blocks
+#[derive(Default, Serialize, Deserialize)]
+pub struct HeaderChain {
+    headers: Vec<Header>,
+    #[serde(default)]
+    zones: HashSet<String>,
+    /// Difficulty of domains in every zone, as it was set in the zone block
+    #[serde(default)]
+    difficulties: HashMap<String, u32>,
+}
+
+impl HeaderChain {
+    pub fn get_height(&self) -> u64 {
+        self.headers.len() as u64
+    }
+
+    pub fn get_header(&self, index: u64) -> Option<&Header> {
+        if index == 0 {
+            return None;
+        }
+        self.headers.get(index as usize - 1)
+    }
+
+    pub fn get_last_hash(&self) -> Bytes {
+        match self.headers.last() {
+            None => Bytes::default(),
+            Some(header) => header.hash.clone()
+        }
+    }
+
+    pub fn has_zone(&self, zone: &str) -> bool {
+        self.zones.contains(zone)
+    }
+
+    /// Checks the block as much as it is possible without transactions, and adds its header
+    pub fn add_block(&mut self, block: &Block, origin: &Bytes, params: &ConsensusParams) -> BlockQuality {
+        if !check_block(block, origin, params, self.get_difficulty(block, params)) {
+            return BlockQuality::Bad;
+        }
+        self.add_checked(block)
+    }
+
+    /// Gets the minimal difficulty of the block by the type of its transaction
+    fn get_difficulty(&self, block: &Block, params: &ConsensusParams) -> u32 {
+        match Transaction::get_type(&block.transaction) {
+            TransactionType::Signing if block.index == 1 => params.zone_difficulty,
+            TransactionType::Signing => params.signer_difficulty,
+            TransactionType::Zone => params.zone_difficulty,
+            _ => {
+                let zone = block.transaction.as_ref().and_then(|t| serde_json::from_str::<DomainData>(&t.data).ok()).map(|data| data.zone);
+                // Headers saved by older versions don't have difficulties of zones
+                match zone.and_then(|zone| self.difficulties.get(&zone).cloned()) {
+                    Some(difficulty) => difficulty,
+                    None => params.zone_min_difficulty
+                }
+            }
+        }
+    }
+
+    fn add_checked(&mut self, block: &Block) -> BlockQuality {
+        let height = self.get_height();
+        if block.index > height + 1 {
+            return BlockQuality::Future;
+        }
+        if let Some(prev) = self.get_header(block.index - 1) {
+            if prev.hash != block.prev_block_hash {
+                return BlockQuality::Rewind;
+            }
+        }
+        if block.index <= height {
+            let current = self.get_header(block.index).unwrap();
+            if current.hash == block.hash {
+                return BlockQuality::Twin;
+            }
+            // Without transactions we can't compare work of branches, so only the last header
+            // can be replaced by a header with a better hash, deeper ones are kept
+            if block.index < height || hash_difficulty(&block.hash) <= hash_difficulty(&current.hash) {
+                return BlockQuality::Twin;
+            }
+            self.headers.truncate(block.index as usize - 1);
+            self.push(block);
+            return BlockQuality::Fork;
+        }
+        self.push(block);
+        BlockQuality::Good
+    }
+
+    fn push(&mut self, block: &Block) {
+        if let Some(transaction) = &block.transaction {
+            if let Ok(zone) = serde_json::from_str::<ZoneData>(&transaction.data) {
+                self.difficulties.insert(zone.name.clone(), zone.difficulty);
+                self.zones.insert(zone.name);
+            }
+        }
+        self.headers.push(Header::from_block(block));
+    }
+}
+
+/// Checks everything that doesn't need previous blocks: version, time, difficulty, hash and signature
+fn check_block(block: &Block, origin: &Bytes, params: &ConsensusParams, difficulty: u32) -> bool {
+    if block.index == 0 || block.version > BINARY_RULES_VERSION || block.timestamp > Utc::now().timestamp() + params.max_future_time {
+        return false;
+    }
+    if !check_public_key_strength(&block.pub_key, KEYSTORE_DIFFICULTY) {
+        return false;
+    }
+    if block.difficulty < difficulty || hash_difficulty(&block.hash) < block.difficulty {
+        return false;
+    }
+    if !check_block_hash(block) || !check_block_signature(block) {
+        return false;
+    }
+    block.index != 1 || origin.is_zero() || &block.hash == origin
+}
+
+pub struct LightClient {
+    headers: Mutex<HeaderChain>,
+    filename: String,
+    /// Messages to send to random full nodes
+    requests: Mutex<Vec<Message>>,
+    /// Answers from full nodes by domain identity
+    answers: Mutex<HashMap<Bytes, Option<String>>>,
+    answered: Condvar,
+    /// Time of last save and the last hash at that time
+    saved: Mutex<(Instant, Bytes)>,
+    checkpoints: Checkpoints,
+    schedule: Schedule,
+}
+
+impl LightClient {
+    /// Loads headers from file, or starts from scratch if there is no such file
+    pub fn load(filename: &str, checkpoints: Checkpoints, schedule: Schedule) -> Self {
+        let headers = match fs::read_to_string(filename) {
+            Ok(text) => serde_json::from_str::<HeaderChain>(&text).unwrap_or_else(|e| {
+                warn!("Unable to parse headers from '{}': {}", filename, e);
+                HeaderChain::default()
+            }),
+            Err(_) => HeaderChain::default()
+        };
+        info!("Loaded {} block headers", headers.get_height());
+        let last_hash = headers.get_last_hash();
+        LightClient {
+            headers: Mutex::new(headers),
+            filename: filename.to_owned(),
+            requests: Mutex::new(Vec::new()),
+            answers: Mutex::new(HashMap::new()),
+            answered: Condvar::new(),
+            saved: Mutex::new((Instant::now(), last_hash)),
+            checkpoints,
+            schedule
+        }
+    }
+
+    /// Saves headers to file if they have changed, but not too often
+    pub fn save(&self, force: bool) {
+        let mut saved = self.saved.lock().unwrap();
+        let headers = self.headers.lock().unwrap();
+        let last_hash = headers.get_last_hash();
+        if saved.1 == last_hash && !force {
+            return;
+        }
+        if !force && saved.0.elapsed().as_secs() < SAVE_INTERVAL_SEC {
+            return;
+        }
+        match fs::write(&self.filename, serde_json::to_string(&*headers).unwrap()) {
+            Ok(_) => debug!("Saved {} block headers to '{}'", headers.get_height(), &self.filename),
+            Err(e) => warn!("Unable to save block headers to '{}': {}", &self.filename, e)
+        }
+        *saved = (Instant::now(), last_hash);
+    }
+
+    pub fn get_height_and_hash(&self) -> (u64, Bytes) {
+        let headers = self.headers.lock().unwrap();
+        (headers.get_height(), headers.get_last_hash())
+    }
+
+    pub fn add_block(&self, block: &Block, origin: &Bytes) -> BlockQuality {
+        if !self.checkpoints.check(block.index, &block.hash) {
+            warn!("Ignoring block {} that doesn't match the checkpoint", block.index);
+            return BlockQuality::Bad;
+        }
+        self.headers.lock().unwrap().add_block(block, origin, self.schedule.at(block.index))
+    }
+
+    pub fn has_zone(&self, zone: &str) -> bool {
+        self.headers.lock().unwrap().has_zone(zone)
+    }
+
+    /// Takes all messages, that network needs to send
+    pub fn take_requests(&self) -> Vec<Message> {
+        std::mem::take(&mut *self.requests.lock().unwrap())
+    }
+
+    /// Puts the answer of a full node and wakes up waiting queries
+    pub fn handle_domain(&self, identity: Bytes, proof: Option<String>) {
+        self.answers.lock().unwrap().insert(identity, proof);
+        self.answered.notify_all();
+    }
+
+    /// Asks full nodes for the domain and verifies their proof up to our last header
+    pub fn get_domain_transaction(&self, domain: &str) -> Option<Transaction> {
+        let identity = hash_identity(domain, None);
+        let (to, trusted) = self.get_height_and_hash();
+        if to == 0 {
+            return None;
+        }
+        self.answers.lock().unwrap().remove(&identity);
+        self.requests.lock().unwrap().push(Message::GetDomain { identity: identity.clone(), to: Some(to) });
+
+        let answers = self.answers.lock().unwrap();
+        let timeout = Duration::from_millis(QUERY_TIMEOUT_MS);
+        let (mut answers, _) = self.answered.wait_timeout_while(answers, timeout, |a| !a.contains_key(&identity)).unwrap();
+        let proof = answers.remove(&identity)??;
+        let proof: DomainProof = serde_json::from_str(&proof).ok()?;
+        let lifetime = self.schedule.at(to + 1).domain_lifetime;
+        match proof.verify_to(domain, self.schedule.at(proof.block.index), &trusted) {
+            Some(transaction) if proof.block.timestamp >= Utc::now().timestamp() - lifetime => Some(transaction),
+            Some(_) => None,
+            None => {
+                warn!("Got wrong proof for domain '{}'", domain);
+                None
+            }
+        }
+    }
+
+    pub fn get_domain_info(&self, domain: &str) -> Option<String> {
+        self.get_domain_transaction(domain).map(|transaction| transaction.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blockchain::hash_utils::blakeout_data;
+    use crate::Keystore;
+
+    use super::*;
+
+    fn make_block(keystore: &Keystore, index: u64, prev_block_hash: Bytes) -> Block {
+        let mut block = Block::new(None, keystore.get_public(), prev_block_hash, 0);
+        block.index = index;
+        block.timestamp = 1600000000 + index as i64;
+        block.hash = blakeout_data(&block.as_bytes());
+        block.signature = Bytes::from_bytes(&keystore.sign(&block.as_bytes()));
+        block
+    }
+
+    #[test]
+    fn header_chain() {
+        let keystore = Keystore::new();
+        let mut chain = HeaderChain::default();
+        let first = make_block(&keystore, 1, Bytes::default());
+        let second = make_block(&keystore, 2, first.hash.clone());
+        assert!(matches!(chain.add_checked(&second), BlockQuality::Future));
+        assert!(matches!(chain.add_checked(&first), BlockQuality::Good));
+        assert!(matches!(chain.add_checked(&first), BlockQuality::Twin));
+        assert!(matches!(chain.add_checked(&second), BlockQuality::Good));
+        assert_eq!(2, chain.get_height());
+        assert_eq!(second.hash, chain.get_last_hash());
+
+        let other = make_block(&keystore, 3, Bytes::from_bytes(&[1u8; 32]));
+        assert!(matches!(chain.add_checked(&other), BlockQuality::Rewind));
+        assert_eq!(2, chain.get_height());
+    }
+
+    #[test]
+    fn header_forks() {
+        let keystore = Keystore::new();
+        let mut chain = HeaderChain::default();
+        let first = make_block(&keystore, 1, Bytes::default());
+        let second = make_block(&keystore, 2, first.hash.clone());
+        let third = make_block(&keystore, 3, second.hash.clone());
+        for block in [&first, &second, &third].iter() {
+            assert!(matches!(chain.add_checked(block), BlockQuality::Good));
+        }
+        // A deep header with a better hash doesn't throw away the headers above it
+        let better = make_better_block(&keystore, &first);
+        assert!(matches!(chain.add_checked(&better), BlockQuality::Twin));
+        assert_eq!(3, chain.get_height());
+        assert_eq!(third.hash, chain.get_last_hash());
+        // The last one is replaced
+        let better = make_better_block(&keystore, &third);
+        assert!(matches!(chain.add_checked(&better), BlockQuality::Fork));
+        assert_eq!(3, chain.get_height());
+        assert_eq!(better.hash, chain.get_last_hash());
+    }
+
+    /// Makes the same block with other nonce and a better hash
+    fn make_better_block(keystore: &Keystore, block: &Block) -> Block {
+        let mut result = block.clone();
+        loop {
+            result.nonce += 1;
+            result.hash = Bytes::default();
+            result.signature = Bytes::default();
+            result.hash = blakeout_data(&result.as_bytes());
+            if hash_difficulty(&result.hash) > hash_difficulty(&block.hash) {
+                result.signature = Bytes::from_bytes(&keystore.sign(&result.as_bytes()));
+                return result;
+            }
+        }
+    }
+
+    #[test]
+    fn zone_difficulty() {
+        let keystore = Keystore::new();
+        let params = ConsensusParams::mainnet();
+        let mut chain = HeaderChain::default();
+        let zone = Transaction::from_str(String::from("ygg"), String::from("zone"), String::from(r#"{"name":"ygg","difficulty":28,"yggdrasil":false}"#), keystore.get_public());
+        let mut block = make_block(&keystore, 1, Bytes::default());
+        block.transaction = Some(zone);
+        chain.push(&block);
+        assert!(chain.has_zone("ygg"));
+
+        let data = DomainData::new(Bytes::default(), String::from("ygg"), Vec::new(), Vec::new(), Vec::new());
+        let domain = Transaction::from_str(String::from("test.ygg"), String::from("domain"), serde_json::to_string(&data).unwrap(), keystore.get_public());
+        block.transaction = Some(domain.clone());
+        assert_eq!(28, chain.get_difficulty(&block, &params));
+        // Zones that we haven't seen are checked with the lowest possible difficulty
+        let data = DomainData::new(Bytes::default(), String::from("anon"), Vec::new(), Vec::new(), Vec::new());
+        block.transaction = Some(Transaction { data: serde_json::to_string(&data).unwrap(), ..domain });
+        assert_eq!(params.zone_min_difficulty, chain.get_difficulty(&block, &params));
+    }
+
+    #[test]
+    fn block_checks() {
+        let keystore = Keystore::new();
+        let block = make_block(&keystore, 1, Bytes::default());
+        let params = ConsensusParams::mainnet();
+        // Without proof of work
+        assert!(!check_block(&block, &Bytes::default(), &params, params.zone_difficulty));
+        assert!(matches!(HeaderChain::default().add_block(&block, &Bytes::default(), &params), BlockQuality::Bad));
+    }
+}
@@ -0,0 +1,342 @@
+//! Log wrapper that suppresses repeated identical messages, and reports how many times they were repeated.
+//! During sync storms a node can write the same warning hundreds of times a second, it makes logs unreadable.
+//! Also there is a logger writing JSON lines, for log collectors like Loki or Elasticsearch.
+//! Levels of the running logger can be changed for every target, without restart.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use serde_json::{json, Map, Value};
+
+/// Identical messages are written once in this time, others are counted
+const THROTTLE_TIME: Duration = Duration::from_secs(10);
+/// How often we look for counted messages, whose time is over
+const SWEEP_TIME: Duration = Duration::from_secs(1);
+
+/// Levels of the running logger, absent until it is initialized
+static LEVELS: RwLock<Option<LogLevels>> = RwLock::new(None);
+
+/// Level for all messages, and levels for some targets, like `gis::p2p`
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogLevels {
+    pub default: LevelFilter,
+    pub targets: BTreeMap<String, LevelFilter>,
+}
+
+impl LogLevels {
+    pub fn new(default: LevelFilter) -> Self {
+        LogLevels { default, targets: BTreeMap::new() }
+    }
+
+    /// Gets the level of the most specific target that this one is in
+    pub fn get(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .filter(|(name, _)| target == name.as_str() || (target.starts_with(name.as_str()) && target[name.len()..].starts_with("::")))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    /// Sets level for the target, or the default level if there is no target
+    pub fn set(&mut self, target: Option<&str>, level: LevelFilter) {
+        match target {
+            Some(target) => { self.targets.insert(target.to_owned(), level); }
+            None => self.default = level
+        }
+    }
+
+    pub fn max(&self) -> LevelFilter {
+        self.targets.values().cloned().fold(self.default, std::cmp::max)
+    }
+}
+
+/// Parses level setting like `trace` or `gis::p2p=debug`
+pub fn parse_level(text: &str) -> Result<(Option<String>, LevelFilter), String> {
+    let (target, level) = match text.split_once('=') {
+        Some((target, level)) if !target.is_empty() => (Some(target.to_owned()), level),
+        Some(_) => return Err(format!("Wrong log level '{}'", text)),
+        None => (None, text)
+    };
+    let level = LevelFilter::from_str(level).map_err(|_| format!("Wrong log level '{}'", level))?;
+    Ok((target, level))
+}
+
+/// Parses levels like `info,gis::p2p=debug`, the default level is `info` if it is not given
+pub fn parse_levels(text: &str) -> Result<LogLevels, String> {
+    let mut levels = LogLevels::new(LevelFilter::Info);
+    for item in text.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let (target, level) = parse_level(item)?;
+        levels.set(target.as_deref(), level);
+    }
+    Ok(levels)
+}
+
+/// Gets levels of the running logger, if it was initialized
+pub fn get_levels() -> Option<LogLevels> {
+    LEVELS.read().unwrap().clone()
+}
+
+/// Changes level of the running logger for the target, or for all messages, returns new levels
+pub fn set_level(target: Option<&str>, level: LevelFilter) -> Option<LogLevels> {
+    let mut levels = LEVELS.write().unwrap();
+    let levels = levels.as_mut()?;
+    levels.set(target, level);
+    log::set_max_level(levels.max());
+    Some(levels.clone())
+}
+
+/// Replaces all levels of the running logger, returns false if it was not initialized
+pub fn set_levels(new_levels: LogLevels) -> bool {
+    let mut levels = LEVELS.write().unwrap();
+    match levels.as_mut() {
+        Some(levels) => {
+            *levels = new_levels;
+            log::set_max_level(levels.max());
+            true
+        }
+        None => false
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Key {
+    level: Level,
+    target: String,
+    message: String,
+}
+
+#[derive(Debug)]
+struct Entry {
+    since: Instant,
+    repeats: u32,
+}
+
+/// Remembers recent messages and counts their repeats
+#[derive(Debug)]
+struct Throttle {
+    entries: HashMap<Key, Entry>,
+    last_sweep: Instant,
+}
+
+impl Throttle {
+    fn new(now: Instant) -> Self {
+        Throttle { entries: HashMap::new(), last_sweep: now }
+    }
+
+    /// Returns true if the message must be written, or counts it as a repeat
+    fn pass(&mut self, key: &Key, now: Instant) -> bool {
+        match self.entries.get_mut(key) {
+            Some(entry) if now.duration_since(entry.since) < THROTTLE_TIME => {
+                entry.repeats += 1;
+                false
+            }
+            _ => {
+                self.entries.insert(key.clone(), Entry { since: now, repeats: 0 });
+                true
+            }
+        }
+    }
+
+    /// Forgets messages whose time is over, returns those of them that were repeated, with the count
+    fn sweep(&mut self, now: Instant, force: bool) -> Vec<(Key, u32)> {
+        if !force && now.duration_since(self.last_sweep) < SWEEP_TIME {
+            return Vec::new();
+        }
+        self.last_sweep = now;
+        let mut result = Vec::new();
+        self.entries.retain(|key, entry| {
+            if !force && now.duration_since(entry.since) < THROTTLE_TIME {
+                return true;
+            }
+            if entry.repeats > 0 {
+                result.push((key.clone(), entry.repeats));
+            }
+            false
+        });
+        result
+    }
+}
+
+pub struct ThrottledLogger {
+    inner: Box<dyn Log>,
+    throttle: Mutex<Throttle>,
+}
+
+impl ThrottledLogger {
+    pub fn new(inner: Box<dyn Log>) -> Self {
+        ThrottledLogger { inner, throttle: Mutex::new(Throttle::new(Instant::now())) }
+    }
+
+    /// Sets this logger, wrapping the given one, as the global logger.
+    /// The given logger must pass all levels, they are filtered here to be changed in runtime.
+    pub fn init(inner: Box<dyn Log>, level: LevelFilter) -> Result<(), SetLoggerError> {
+        log::set_boxed_logger(Box::new(ThrottledLogger::new(inner)))?;
+        *LEVELS.write().unwrap() = Some(LogLevels::new(level));
+        log::set_max_level(level);
+        Ok(())
+    }
+
+    fn write_repeats(&self, repeats: Vec<(Key, u32)>) {
+        for (key, count) in repeats {
+            self.inner.log(&Record::builder()
+                .level(key.level)
+                .target(&key.target)
+                .args(format_args!("{} (message repeated {} times)", &key.message, count))
+                .build());
+        }
+    }
+}
+
+impl Log for ThrottledLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let allowed = match LEVELS.read().unwrap().as_ref() {
+            Some(levels) => metadata.level() <= levels.get(metadata.target()),
+            None => true
+        };
+        allowed && self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let key = Key { level: record.level(), target: record.target().to_owned(), message: record.args().to_string() };
+        let now = Instant::now();
+        let (pass, repeats) = {
+            let mut throttle = self.throttle.lock().unwrap();
+            let repeats = throttle.sweep(now, false);
+            (throttle.pass(&key, now), repeats)
+        };
+        self.write_repeats(repeats);
+        if pass {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        let repeats = self.throttle.lock().unwrap().sweep(Instant::now(), true);
+        self.write_repeats(repeats);
+        self.inner.flush();
+    }
+}
+
+/// Writes every message as a JSON object in one line
+pub struct JsonLogger {
+    level: LevelFilter,
+    /// Targets that are too verbose, their messages are skipped
+    ignored: Vec<String>,
+    outputs: Mutex<Vec<Box<dyn Write + Send>>>,
+}
+
+impl JsonLogger {
+    pub fn new(level: LevelFilter, ignored: Vec<String>, outputs: Vec<Box<dyn Write + Send>>) -> Self {
+        JsonLogger { level, ignored, outputs: Mutex::new(outputs) }
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level && !self.ignored.iter().any(|target| metadata.target().starts_with(target))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format_json(record, Utc::now(), thread::current().name());
+        for output in self.outputs.lock().unwrap().iter_mut() {
+            let _ = writeln!(output, "{}", &line);
+        }
+    }
+
+    fn flush(&self) {
+        for output in self.outputs.lock().unwrap().iter_mut() {
+            let _ = output.flush();
+        }
+    }
+}
+
+/// Makes a line with timestamp, level, target, message and other fields of the record
+fn format_json(record: &Record, time: DateTime<Utc>, thread: Option<&str>) -> String {
+    let mut fields = Map::new();
+    if let Some(thread) = thread {
+        fields.insert(String::from("thread"), Value::from(thread));
+    }
+    let line = json!({
+        "timestamp": time.to_rfc3339_opts(SecondsFormat::Millis, true),
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+        "fields": fields
+    });
+    line.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn throttle() {
+        let start = Instant::now();
+        let mut throttle = Throttle::new(start);
+        let key = Key { level: Level::Warn, target: String::from("gis"), message: String::from("Ignoring block") };
+        let other = Key { level: Level::Info, target: String::from("gis"), message: String::from("Ignoring block") };
+        assert!(throttle.pass(&key, start));
+        assert!(!throttle.pass(&key, start + Duration::from_secs(1)));
+        assert!(!throttle.pass(&key, start + Duration::from_secs(2)));
+        assert!(throttle.pass(&other, start + Duration::from_secs(2)));
+        assert!(throttle.sweep(start + Duration::from_secs(5), false).is_empty());
+
+        let repeats = throttle.sweep(start + THROTTLE_TIME, false);
+        assert_eq!(vec![(key.clone(), 2)], repeats);
+        assert!(throttle.pass(&key, start + THROTTLE_TIME));
+        assert!(!throttle.pass(&key, start + THROTTLE_TIME));
+        assert_eq!(vec![(key, 1)], throttle.sweep(start + THROTTLE_TIME, true));
+        assert!(throttle.entries.is_empty());
+    }
+
+    #[test]
+    fn levels() {
+        let mut levels = LogLevels::new(LevelFilter::Info);
+        levels.set(Some("gis::p2p"), LevelFilter::Trace);
+        levels.set(Some("gis::p2p::peers"), LevelFilter::Warn);
+        assert_eq!(LevelFilter::Trace, levels.get("gis::p2p"));
+        assert_eq!(LevelFilter::Trace, levels.get("gis::p2p::network"));
+        assert_eq!(LevelFilter::Warn, levels.get("gis::p2p::peers"));
+        assert_eq!(LevelFilter::Info, levels.get("gis::p2peers"));
+        assert_eq!(LevelFilter::Info, levels.get("gis::Main"));
+        assert_eq!(LevelFilter::Trace, levels.max());
+        levels.set(None, LevelFilter::Off);
+        assert_eq!(LevelFilter::Off, levels.get("mio"));
+
+        assert_eq!(Ok((None, LevelFilter::Trace)), parse_level("trace"));
+        assert_eq!(Ok((Some(String::from("gis::p2p")), LevelFilter::Debug)), parse_level("gis::p2p=DEBUG"));
+        assert!(parse_level("loud").is_err());
+        assert!(parse_level("=info").is_err());
+
+        let levels = parse_levels("warn, gis::p2p=debug").unwrap();
+        assert_eq!(LevelFilter::Warn, levels.get("gis::Main"));
+        assert_eq!(LevelFilter::Debug, levels.get("gis::p2p::peers"));
+        assert_eq!(LevelFilter::Info, parse_levels("").unwrap().default);
+        assert!(parse_levels("info,gis=loud").is_err());
+    }
+
+    #[test]
+    fn json() {
+        let time = DateTime::parse_from_rfc3339("2021-05-01T10:20:30.456Z").unwrap().with_timezone(&Utc);
+        let line = format_json(&Record::builder().level(Level::Warn).target("gis::p2p").args(format_args!("Peer \"{}\" is banned", 1)).build(), time, Some("network"));
+        assert_eq!(r#"{"fields":{"thread":"network"},"level":"WARN","message":"Peer \"1\" is banned","target":"gis::p2p","timestamp":"2021-05-01T10:20:30.456Z"}"#, line);
+        let logger = JsonLogger::new(LevelFilter::Info, vec![String::from("mio::poll")], Vec::new());
+        assert!(logger.enabled(&Metadata::builder().level(Level::Info).target("gis").build()));
+        assert!(!logger.enabled(&Metadata::builder().level(Level::Debug).target("gis").build()));
+        assert!(!logger.enabled(&Metadata::builder().level(Level::Info).target("mio::poll").build()));
+    }
+}
@@ -4,28 +4,74 @@
 #![windows_subsystem = "windows"]
 
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
+use chrono::Utc;
 use getopts::{Options, Matches};
 #[allow(unused_imports)]
-use log::{debug, error, info, trace, warn, LevelFilter};
+use log::{debug, error, info, trace, warn, LevelFilter, Log};
+use serde::de::DeserializeOwned;
 use simplelog::*;
 #[cfg(windows)]
 use winapi::um::wincon::{ATTACH_PARENT_PROCESS, AttachConsole, FreeConsole};
 
-use gis::{Block, Bytes, Chain, Miner, Context, Network, Settings, dns_utils, Keystore, ZONE_DIFFICULTY, GIS_DEBUG, DB_NAME};
-use std::fs::OpenOptions;
+use gis::{Block, Bytes, Chain, Miner, Context, Network, Settings, dns_utils, Keystore, Transaction, GIS_DEBUG, CLASS_DOMAIN, KEYSTORE_DIFFICULTY, MEMORY_DB_NAME};
+use gis::{format_wait_time, from_hex, get_domain_zone, is_yggdrasil_record};
+use gis::blockchain::profile::profile_lookup;
+use gis::blockchain::diff::{diff_chains, ChainDiff};
+use gis::blockchain::snapshot::verify_snapshot;
+use gis::blockchain::vectors::make_test_vectors;
+use gis::p2p::query::{fetch_block, resolve_peers};
+use gis::blockchain::primary::export_zone;
+use gis::blockchain::hash_utils::{hash_identity, key_hash_difficulty};
+use gis::keys::{is_encrypted_key, mine_key, KEY_PASSPHRASE_ENV};
+use gis::blockchain::ownership::OwnershipProof;
+use gis::blockchain::delegation::Delegation;
+use gis::blockchain::transfer::{DomainTransfer, SaleOffer};
+use gis::fingerprint::get_fingerprint;
+use gis::blockchain::transaction::DomainData;
+use gis::blockchain::types::MineResult;
+use gis::dns::zonefile::parse_domain_records;
+use gis::dns::protocol::{DnsRecord, QueryType};
+use gis::dns::templates::{expand_zone_text, needs_node_address};
+use gis::dns::trace::trace_resolve;
+use gis::light::LightClient;
+use gis::{api, memory, rpc};
+use gis::chains::start_chains;
+use gis::disk::start_disk_monitor;
+use gis::updates::start_update_checker;
+use gis::checker::start_chain_check;
+use gis::proxy::start_proxies;
+use gis::address::{get_node_address, make_detectors};
+use gis::settings::AddressDetection;
+use gis::cert::Certificate;
+use gis::failure::{fatal, set_failure_hook, FailureKind};
+use gis::logging::{JsonLogger, ThrottledLogger, parse_level, parse_levels, set_levels};
+use gis::port_conflict::free_dns_port;
+use gis::reload::start_settings_watcher;
+use gis::api::http::send_request;
+use std::fs::{self, File, OpenOptions};
 use std::process::exit;
-use std::io::{Seek, SeekFrom};
+use std::io::{self, BufReader, Seek, SeekFrom, Write};
 
 #[cfg(feature = "webgui")]
 mod web_ui;
+#[cfg(feature = "tray")]
+mod tray;
 
 const SETTINGS_FILENAME: &str = "gis.toml";
 const LOG_TARGET_MAIN: &str = "gis::Main";
+const PROFILE_ITERATIONS: u32 = 1000;
+const CERT_VALIDITY_DAYS: i64 = 365;
+const TOKEN_DAYS: i64 = 30;
+/// Directory next to the executable with all files of portable mode
+const PORTABLE_DIR: &str = "gis-data";
+const PORTABLE_LOG: &str = "gis.log";
+/// Passphrase for new keys of `--gen-key` and `--rewrap-key`
+const KEY_NEW_PASSPHRASE_ENV: &str = "GIS_NEW_KEY_PASSPHRASE";
 
 fn main() {
     // When linked with the windows subsystem windows won't automatically attach
@@ -37,24 +83,63 @@ fn main() {
         winapi::um::shellscalingapi::SetProcessDpiAwareness(2);
     }
 
+    set_failure_hook();
     let args: Vec<String> = env::args().collect();
     let program = args[0].clone();
 
     let mut opts = Options::new();
     opts.optflag("h", "help", "Print this help menu");
     opts.optflag("n", "nogui", "Run without graphic user interface (default for no gui builds)");
+    #[cfg(feature = "tray")]
+    opts.optflag("", "tray", "Run in background with an icon in system tray, the window is opened from its menu");
     opts.optflag("v", "version", "Print version and exit");
     opts.optflag("d", "debug", "Show trace messages, more than debug");
     opts.optflag("b", "blocks", "List blocks from DB and exit");
+    opts.optflag("", "reset-chain", "Delete all blocks from DB, to switch networks or recover from corruption, and exit");
+    opts.optopt("", "origin", "Hash of the origin block for the new chain, to use with --reset-chain, the one from settings by default", "HASH");
+    opts.optopt("", "export-snapshot", "Write all blocks to a snapshot file and exit", "FILE");
+    opts.optopt("", "verify-snapshot", "Check blocks of a snapshot file, and compare its last block with checkpoints and peers from settings, and exit", "FILE");
+    opts.optopt("", "import-snapshot", "Add blocks from a snapshot file, checking them like blocks from peers, and exit", "FILE");
+    opts.optopt("", "gen-key", "Mine a new key, save it to the file, encrypted by passphrase if it is given, and exit", "FILE");
+    opts.optopt("", "key-strength", &format!("Difficulty of the hash of new key for --gen-key, {} by default and at least", KEYSTORE_DIFFICULTY), "N");
+    opts.optopt("", "show-key", "Print public key, fingerprint and hash of the key file and exit", "FILE");
+    opts.optopt("", "rewrap-key", "Save the key file with a new passphrase, or without it if it is empty, and exit", "FILE");
+    opts.optopt("", "test-vectors", "Write hashes and signatures of sample names and blocks to a JSON file, to check other implementations, and exit", "FILE");
+    opts.optopt("", "diff-chain", "Compare blocks, domains and zones of this DB with the DB from settings, or with another DB given after options, and exit", "FILE");
+    opts.optopt("", "profile", "Measure time of every step of domain lookup in DB and exit", "DOMAIN");
+    opts.optopt("", "export", "Print zone file with your domains in some zone and exit", "ZONE");
+    opts.optopt("", "format", "Format of exported zone, only 'bind' is supported now", "FORMAT");
+    opts.optopt("", "register", "Mine new domain or update existing one, to use with --from-zonefile", "DOMAIN");
+    opts.optopt("", "from-zonefile", "Zone file in BIND format with records of registered domain", "FILE");
+    opts.optopt("", "delegation", "Token from the owner to update records of somebody's domain, to use with --register", "FILE");
+    opts.optopt("", "offer", "Offer from the seller of the domain, to accept it and get the domain with --register", "FILE");
+    opts.optopt("", "make-cert", "Generate TLS certificate for your domain and publish it in TLSA record", "DOMAIN");
+    opts.optopt("", "cert-port", "TCP port of the service for TLSA record, to use with --make-cert, 443 by default", "PORT");
+    opts.optopt("", "resolve", "Resolve domain name, print the answer and exit", "DOMAIN");
+    opts.optopt("", "qtype", "Record type to resolve, A by default", "TYPE");
+    opts.optflag("", "trace", "Print every step of resolving, to use with --resolve");
+    opts.optflag("", "free-dns-port", "Move the program that has taken port 53 away from it, if it is known how, asking for consent, and exit");
+    opts.optflag("", "detect-address", "Print our own address found by every method from [address] settings and exit");
+    opts.optflag("", "cooldown", "Print when your key can register a new domain and exit");
+    opts.optopt("", "prove-ownership", "Print signed proof that your key owns the domain and exit", "DOMAIN");
+    opts.optopt("", "challenge", "Text from the party that asks for ownership proof, to use with --prove-ownership", "TEXT");
+    opts.optopt("", "verify-ownership", "Check ownership proof against local blockchain and exit", "FILE");
+    opts.optopt("", "delegate", "Print signed token that allows other key to update records of your domain and exit", "DOMAIN");
+    opts.optopt("", "sell", "Print signed offer of your domain to other key and exit, any change of the domain cancels it", "DOMAIN");
+    opts.optopt("", "to", "Public key (HEX) of the delegate or the buyer, to use with --delegate or --sell", "KEY");
+    opts.optopt("", "days", "How long the token or the offer is valid, to use with --delegate or --sell, 30 by default", "DAYS");
     opts.optflag("g", "generate", "Generate new config file. Generated config will be printed to console.");
     opts.optopt("l", "log", "Write log to file", "FILE");
+    opts.optopt("", "loglevel", "Change log level of running node by its API, like 'trace' or 'gis::p2p=debug', and exit", "LEVEL");
+    opts.optopt("", "log-format", "Format of log lines, 'text' by default or 'json'", "FORMAT");
     opts.optopt("c", "config", "Path to config file", "FILE");
     opts.optopt("w", "work-dir", "Path to working directory", "DIRECTORY");
+    opts.optflag("", "portable", "Keep config, DB, keys and logs in 'gis-data' directory next to the executable");
     opts.optopt("u", "upgrade", "Path to config file that you want to upgrade. Upgraded config will be printed to console.", "FILE");
 
     let opt_matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
-        Err(f) => panic!("{}", f.to_string()),
+        Err(f) => fatal(FailureKind::Config, &f.to_string()),
     };
 
     if opt_matches.opt_present("h") {
@@ -86,26 +171,133 @@ fn main() {
         }
     };
 
+    if let Some(path) = opt_matches.opt_str("gen-key") {
+        let strength = match opt_matches.opt_str("key-strength").map(|s| s.parse::<u32>()) {
+            None => KEYSTORE_DIFFICULTY,
+            Some(Ok(strength)) if strength >= KEYSTORE_DIFFICULTY => strength,
+            Some(_) => fatal(FailureKind::Config, &format!("Key strength must be a number from {}", KEYSTORE_DIFFICULTY))
+        };
+        generate_key_file(&path, strength);
+        return;
+    }
+    if let Some(path) = opt_matches.opt_str("show-key") {
+        let keystore = load_key_file(&path);
+        println!("Public key: {:?}", &keystore.get_public());
+        println!("Fingerprint: {}", keystore.get_fingerprint());
+        println!("Hash: {:?}", &keystore.get_hash());
+        println!("Strength: {}", key_hash_difficulty(keystore.get_hash().as_slice()));
+        println!("Encrypted: {}", is_encrypted_key(&path));
+        return;
+    }
+    if let Some(path) = opt_matches.opt_str("rewrap-key") {
+        let mut keystore = load_key_file(&path);
+        let passphrase = read_new_passphrase("New passphrase (empty to save without it): ");
+        if let Err(e) = keystore.save(&path, &passphrase) {
+            fatal(FailureKind::Keys, &format!("Unable to save key to '{}': {}", &path, e));
+        }
+        match passphrase.is_empty() {
+            true => println!("Saved key {} to '{}' without passphrase", keystore.get_fingerprint(), &path),
+            false => println!("Saved key {} to '{}' with new passphrase", keystore.get_fingerprint(), &path)
+        }
+        return;
+    }
+
+    if let Some(path) = opt_matches.opt_str("test-vectors") {
+        let vectors = serde_json::to_string_pretty(&make_test_vectors()).unwrap();
+        if let Err(e) = std::fs::write(&path, vectors) {
+            fatal(FailureKind::Config, &format!("Unable to write test vectors to '{}': {}", &path, e));
+        }
+        println!("Wrote test vectors to '{}'", &path);
+        return;
+    }
+
+    if opt_matches.opt_present("free-dns-port") {
+        if !confirm("The DNS resolver of the system will stop listening on port 53, continue? [y/N] ") {
+            return;
+        }
+        match free_dns_port() {
+            Ok(message) => println!("{}", message),
+            Err(e) => fatal(FailureKind::Bind, &e)
+        }
+        return;
+    }
+
     #[cfg(feature = "webgui")]
     let no_gui = opt_matches.opt_present("n");
     #[cfg(not(feature = "webgui"))]
     let no_gui = true;
+    #[cfg(feature = "tray")]
+    let tray = opt_matches.opt_present("tray");
+    #[cfg(not(feature = "tray"))]
+    let tray = false;
 
-    if let Some(path) = opt_matches.opt_str("w") {
-        env::set_current_dir(Path::new(&path)).expect(&format!("Unable to change working directory to '{}'", &path));
+    let portable = opt_matches.opt_present("portable");
+    let work_dir = match (portable, opt_matches.opt_str("w")) {
+        (true, Some(_)) => fatal(FailureKind::Config, "Options --portable and --work-dir can't be used together"),
+        (true, None) => Some(get_portable_dir().unwrap_or_else(|e| fatal(FailureKind::Config, &format!("Unable to create portable directory: {}", e)))),
+        (false, path) => path.map(PathBuf::from)
+    };
+    if let Some(path) = work_dir {
+        if let Err(e) = env::set_current_dir(&path) {
+            fatal(FailureKind::Config, &format!("Unable to change working directory to '{}': {}", path.display(), e));
+        }
     }
     let config_name = match opt_matches.opt_str("c") {
         None => { SETTINGS_FILENAME.to_owned() }
         Some(path) => { path }
     };
 
-    setup_logger(&opt_matches);
+    let log_file = match (opt_matches.opt_str("l"), portable) {
+        (None, true) => Some(PORTABLE_LOG.to_owned()),
+        (file, _) => file
+    };
+    setup_logger(&opt_matches, log_file);
     info!(target: LOG_TARGET_MAIN, "Starting GIS {}", env!("CARGO_PKG_VERSION"));
+    if portable {
+        info!(target: LOG_TARGET_MAIN, "Portable mode, working in '{}'", env::current_dir().map(|dir| dir.display().to_string()).unwrap_or_default());
+        // On the first start on a new stick there is no config yet
+        if !Path::new(&config_name).exists() {
+            if let Err(e) = fs::write(&config_name, include_str!("../gis.toml")) {
+                fatal(FailureKind::Config, &format!("Unable to write default config to '{}': {}", &config_name, e));
+            }
+            info!(target: LOG_TARGET_MAIN, "Created default config '{}'", &config_name);
+        }
+    }
 
-    let settings = Settings::load(&config_name).expect(&format!("Cannot load settings from {}!", &config_name));
+    let settings = Settings::load(&config_name).unwrap_or_else(|| fatal(FailureKind::Config, &format!("Cannot load settings from {}!", &config_name)));
     debug!(target: LOG_TARGET_MAIN, "Loaded settings: {:?}", &settings);
-    let keystore = Keystore::from_file(&settings.key_file, "");
-    let mut chain: Chain = Chain::new(&settings, DB_NAME);
+    memory::set_budgets(&settings.memory);
+    // Debug option is stronger than levels in settings
+    if !settings.log_level.is_empty() && !opt_matches.opt_present("d") {
+        match parse_levels(&settings.log_level) {
+            Ok(levels) => { set_levels(levels); }
+            Err(e) => warn!(target: LOG_TARGET_MAIN, "Wrong log_level in settings: {}", e)
+        }
+    }
+    if let Some(level) = opt_matches.opt_str("loglevel") {
+        change_log_level(&settings, &level);
+        return;
+    }
+    if opt_matches.opt_present("detect-address") {
+        print_addresses(&settings.address);
+        return;
+    }
+    let passphrase = match is_encrypted_key(&settings.key_file) {
+        true => read_passphrase(&format!("Passphrase of key '{}': ", &settings.key_file), KEY_PASSPHRASE_ENV),
+        false => String::new()
+    };
+    let keystore = Keystore::from_file(&settings.key_file, &passphrase);
+    if keystore.is_none() && Path::new(&settings.key_file).exists() {
+        fatal(FailureKind::Keys, &format!("Unable to load key from '{}'", &settings.key_file));
+    }
+    if let Some(keystore) = &keystore {
+        info!(target: LOG_TARGET_MAIN, "Loaded key {}", keystore.get_fingerprint());
+    }
+    let mut chain: Chain = Chain::new(&settings, settings.get_db_name());
+    if opt_matches.opt_present("reset-chain") {
+        reset_chain(&mut chain, &settings, opt_matches.opt_str("origin"));
+        return;
+    }
     if opt_matches.opt_present("b") {
         for i in 1..(chain.get_height() + 1) {
             if let Some(block) = chain.get_block(i) {
@@ -114,35 +306,202 @@ fn main() {
         }
         return;
     }
-    chain.check_chain(settings.check_blocks);
+    if let Some(path) = opt_matches.opt_str("export-snapshot") {
+        match chain.export_snapshot(&path) {
+            Ok(count) => println!("Exported {} blocks to '{}'", count, &path),
+            Err(e) => {
+                println!("Unable to export blocks: {}", e);
+                exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(path) = opt_matches.opt_str("verify-snapshot") {
+        if !verify_snapshot_file(&settings, &path) {
+            exit(1);
+        }
+        return;
+    }
+    if let Some(path) = opt_matches.opt_str("import-snapshot") {
+        if settings.node.is_light() {
+            println!("Light nodes don't keep blocks");
+            exit(1);
+        }
+        match chain.import_snapshot(&path) {
+            Ok(count) => println!("Imported {} blocks from '{}', height is {} now", count, &path, chain.get_height()),
+            Err(e) => {
+                println!("Unable to import blocks: {}, height is {} now", e, chain.get_height());
+                exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(path) = opt_matches.opt_str("diff-chain") {
+        let other = opt_matches.free.first().cloned().unwrap_or_else(|| settings.get_db_name().to_owned());
+        print!("{}", diff_databases(&settings, &path, &other, chain));
+        return;
+    }
+    if let Some(zone) = opt_matches.opt_str("export") {
+        let format = opt_matches.opt_str("format").unwrap_or(String::from("bind"));
+        if format != "bind" {
+            println!("Unsupported zone format '{}'", &format);
+            exit(1);
+        }
+        if keystore.is_none() {
+            warn!(target: LOG_TARGET_MAIN, "No key loaded, there are no domains to export");
+        }
+        print!("{}", export_zone(&chain, &keystore, &zone));
+        return;
+    }
+    if opt_matches.opt_present("cooldown") {
+        let keystore = match &keystore {
+            Some(keystore) => keystore,
+            None => {
+                println!("You don't have keys loaded");
+                exit(1);
+            }
+        };
+        match chain.next_allowed_domain_time(&keystore.get_public()) {
+            Some(next) => println!("You can register a new domain in {}", format_wait_time(next - Utc::now().timestamp())),
+            None => println!("You can register a new domain now")
+        }
+        return;
+    }
+    if let Some(domain) = opt_matches.opt_str("prove-ownership") {
+        let challenge = opt_matches.opt_str("challenge").unwrap_or_default();
+        match prove_ownership(&chain, &keystore, &domain, &challenge) {
+            Ok(proof) => println!("{}", serde_json::to_string_pretty(&proof).unwrap()),
+            Err(e) => {
+                println!("Unable to prove ownership of '{}': {}", &domain, e);
+                exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(filename) = opt_matches.opt_str("verify-ownership") {
+        match verify_ownership(&chain, &filename) {
+            Ok(proof) => println!("Key {:?} ({}) owns domain '{}' at height {}", &proof.pub_key, get_fingerprint(&proof.pub_key), &proof.domain, proof.height),
+            Err(e) => {
+                println!("Ownership proof is wrong: {}", e);
+                exit(1);
+            }
+        }
+        return;
+    }
+    let days = match opt_matches.opt_str("days").map(|days| days.parse::<i64>()) {
+        None => TOKEN_DAYS,
+        Some(Ok(days)) if days > 0 => days,
+        Some(_) => {
+            println!("Wrong number of days");
+            exit(1);
+        }
+    };
+    if let Some(domain) = opt_matches.opt_str("sell") {
+        match offer_domain(&chain, &keystore, &domain, &opt_matches.opt_str("to").unwrap_or_default(), days) {
+            Ok(offer) => println!("{}", serde_json::to_string_pretty(&offer).unwrap()),
+            Err(e) => {
+                println!("Unable to offer '{}': {}", &domain, e);
+                exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(domain) = opt_matches.opt_str("delegate") {
+        match delegate_domain(&chain, &keystore, &domain, &opt_matches.opt_str("to").unwrap_or_default(), days) {
+            Ok(delegation) => println!("{}", serde_json::to_string_pretty(&delegation).unwrap()),
+            Err(e) => {
+                println!("Unable to delegate '{}': {}", &domain, e);
+                exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(domain) = opt_matches.opt_str("profile") {
+        let profile = profile_lookup(&chain, &domain.to_lowercase(), PROFILE_ITERATIONS);
+        info!(target: LOG_TARGET_MAIN, "Lookup profile for '{}': {}", &domain, &profile);
+        return;
+    }
+    // Blocks are checked in background, when miner is ready to be paused
+    let check = chain.start_check(settings.check_blocks);
 
     match chain.get_block(1) {
         None => { info!(target: LOG_TARGET_MAIN, "No blocks found in DB"); }
         Some(block) => { trace!(target: LOG_TARGET_MAIN, "Loaded DB with origin {:?}", &block.hash); }
     }
     let settings_copy = settings.clone();
-    let context = Context::new(env!("CARGO_PKG_VERSION").to_owned(), settings, keystore, chain);
+    let mut context = Context::new(env!("CARGO_PKG_VERSION").to_owned(), settings, keystore, chain);
+    if settings_copy.node.is_light() {
+        info!(target: LOG_TARGET_MAIN, "Starting in light mode");
+        // Light nodes don't keep blocks, only their headers
+        let schedule = context.chain.get_schedule().clone();
+        context.light = Some(Arc::new(LightClient::load(&settings_copy.node.headers_file, settings_copy.chain.get_checkpoints(&settings_copy.get_origin()), schedule)));
+    }
     let context: Arc<Mutex<Context>> = Arc::new(Mutex::new(context));
-    dns_utils::start_dns_server(&context, &settings_copy);
+    if settings_copy.get_db_name() != MEMORY_DB_NAME {
+        start_disk_monitor(&context, settings_copy.get_db_name(), &settings_copy.db);
+    }
+    if let Some(domain) = opt_matches.opt_str("resolve") {
+        resolve_and_exit(&context, &settings_copy, &opt_matches, &domain);
+    }
+    let chains = start_chains(&context, &settings_copy);
+    #[allow(unused_variables)]
+    let server_context = dns_utils::start_dns_server(&context, &chains, &settings_copy);
 
     let mut miner_obj = Miner::new(Arc::clone(&context));
     miner_obj.start_mining_thread();
     let miner: Arc<Mutex<Miner>> = Arc::new(Mutex::new(miner_obj));
+    start_chain_check(&context, &miner, check);
+    api::start_api_server(&context, &miner, &settings_copy.api);
+    rpc::start_rpc_server(&context, &miner, &settings_copy.rpc);
+    start_proxies(&context, &settings_copy.proxies);
+    start_update_checker(&context, &settings_copy.updates);
 
     let mut network = Network::new(Arc::clone(&context));
-    network.start().expect("Error starting network component");
+    if let Err(e) = network.start() {
+        fatal(FailureKind::Bind, &format!("Error starting network component: {}", e));
+    }
+    start_settings_watcher(&context, Some(Arc::clone(&server_context)));
 
     create_genesis_if_needed(&context, &miner);
+    if let Some(domain) = opt_matches.opt_str("register") {
+        if settings_copy.node.is_light() {
+            error!(target: LOG_TARGET_MAIN, "Light nodes can't mine domains");
+            exit(1);
+        }
+        match opt_matches.opt_str("from-zonefile") {
+            None => error!(target: LOG_TARGET_MAIN, "You need to give a zone file with records of new domain"),
+            Some(filename) => register_domain(&context, &miner, &domain, &filename, opt_matches.opt_str("delegation"), opt_matches.opt_str("offer"))
+        }
+    }
+    if let Some(domain) = opt_matches.opt_str("make-cert") {
+        if settings_copy.node.is_light() {
+            error!(target: LOG_TARGET_MAIN, "Light nodes can't mine domains");
+            exit(1);
+        }
+        let port = match opt_matches.opt_str("cert-port").unwrap_or(String::from("443")).parse::<u16>() {
+            Ok(port) => port,
+            Err(e) => {
+                error!(target: LOG_TARGET_MAIN, "Wrong port for certificate: {}", e);
+                exit(1);
+            }
+        };
+        make_certificate(&context, &miner, &domain, port);
+    }
     if no_gui {
         print_my_domains(&context);
         let sleep = Duration::from_millis(1000);
         loop {
             thread::sleep(sleep);
         }
+    } else if tray {
+        #[cfg(feature = "tray")]
+        tray::run_tray(Arc::clone(&context), miner.clone(), server_context);
     } else {
         #[cfg(feature = "webgui")]
-        web_ui::run_interface(Arc::clone(&context), miner.clone());
+        web_ui::run_interface(Arc::clone(&context), miner.clone(), server_context, true);
     }
+    // Writes counts of repeated messages, if there are some
+    log::logger().flush();
 
     // Without explicitly detaching the console cmd won't redraw it's prompt.
     #[cfg(windows)]
@@ -151,8 +510,234 @@ fn main() {
     }
 }
 
+/// Checks the snapshot and asks peers for its last block, it is trusted if some of them have the same block and none has another one
+fn verify_snapshot_file(settings: &Settings, path: &str) -> bool {
+    let origin = settings.get_origin();
+    let checkpoints = settings.chain.get_checkpoints(&origin);
+    let tip = match File::open(path).map_err(|e| e.to_string()).and_then(|file| verify_snapshot(&mut BufReader::new(file), &origin, &checkpoints)) {
+        Ok(tip) => tip,
+        Err(e) => {
+            println!("Snapshot is broken: {}", e);
+            return false;
+        }
+    };
+    println!("Snapshot has {} consistent blocks, the last one is {:?}, it has passed {} checkpoints", tip.index, &tip.hash, tip.checkpoints);
+
+    let (mut same, mut other) = (0, 0);
+    for addr in resolve_peers(&settings.net.peers) {
+        match fetch_block(&addr, env!("CARGO_PKG_VERSION"), &settings.origin, tip.index) {
+            Ok(block) if block.hash == tip.hash => {
+                println!("{}: has the same block {}", addr, tip.index);
+                same += 1;
+            }
+            Ok(block) => {
+                println!("{}: has another block {} with hash {:?}", addr, tip.index, &block.hash);
+                other += 1;
+            }
+            Err(e) => println!("{}: unable to get block {}: {}", addr, tip.index, e)
+        }
+    }
+    match (same, other) {
+        (_, other) if other > 0 => println!("Snapshot differs from the network, don't import it!"),
+        (0, _) => println!("No peers have confirmed the snapshot, it can't be trusted yet"),
+        (same, _) => println!("Snapshot is confirmed by {} peers, it is safe to import it", same)
+    }
+    same > 0 && other == 0
+}
+
+/// Compares two DBs, `main` is the already opened DB from settings, it is used if one of the paths points to it
+fn diff_databases(settings: &Settings, path_a: &str, path_b: &str, main: Chain) -> ChainDiff {
+    // Without origin DBs of other chains are opened as they are, instead of being reset
+    let mut settings = settings.clone();
+    settings.origin = String::new();
+    let open = |path: &str| {
+        if !Path::new(path).exists() {
+            println!("There is no DB '{}'", path);
+            exit(1);
+        }
+        Chain::new(&settings, path)
+    };
+    let main_name = settings.get_db_name().to_owned();
+    let a = if path_a == main_name { None } else { Some(open(path_a)) };
+    let b = if path_b == main_name { None } else { Some(open(path_b)) };
+    diff_chains(a.as_ref().unwrap_or(&main), b.as_ref().unwrap_or(&main))
+}
+
+/// Deletes all blocks from DB after confirmation, and starts an empty chain with the given origin
+fn reset_chain(chain: &mut Chain, settings: &Settings, origin: Option<String>) {
+    let origin = match origin {
+        None => settings.get_origin(),
+        Some(origin) => match gis::from_hex(&origin) {
+            Ok(hash) if hash.len() == 32 => Bytes::from_bytes(&hash),
+            _ => {
+                println!("Wrong origin '{}', it must be a hash of 32 bytes in hex", &origin);
+                exit(1);
+            }
+        }
+    };
+    println!("All {} blocks will be deleted from '{}', and a new chain will start from origin {}", chain.get_height(), settings.get_db_name(), origin.to_string());
+    println!("Type 'yes' to continue:");
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() || answer.trim() != "yes" {
+        println!("Chain is not changed");
+        exit(1);
+    }
+    if let Err(e) = chain.reset(origin.clone()) {
+        println!("Unable to reset the chain: {}", e);
+        exit(1);
+    }
+    println!("Chain is reset, it will be synced from peers on next start");
+    if origin != settings.get_origin() {
+        println!("Don't forget to set origin = \"{}\" in settings, otherwise the chain will be reset again on start", origin.to_string());
+    }
+}
+
+/// Asks running node to change its log level, through the API
+fn change_log_level(settings: &Settings, level: &str) {
+    if settings.api.listen.is_empty() {
+        println!("API is disabled, enable it in [api] section of settings to change log level");
+        exit(1);
+    }
+    if settings.api.token.is_empty() {
+        println!("API token is not set, set it in [api] section of settings to change log level");
+        exit(1);
+    }
+    let (target, level) = match parse_level(level) {
+        Ok(result) => result,
+        Err(e) => {
+            println!("{}", e);
+            exit(1);
+        }
+    };
+    let mut path = format!("/api/loglevel?level={}", level.as_str());
+    if let Some(target) = target {
+        path.push_str(&format!("&target={}", target));
+    }
+    match send_request(&settings.api.listen, &settings.api.token, "POST", &path) {
+        Ok((200, body)) => println!("Log levels are changed: {}", body),
+        Ok((status, body)) => {
+            println!("Node refused to change log level, status {}: {}", status, body);
+            exit(1);
+        }
+        Err(e) => {
+            println!("Unable to connect to the node on {}: {}", &settings.api.listen, e);
+            exit(1);
+        }
+    }
+}
+
+fn confirm(prompt: &str) -> bool {
+    print!("{}", prompt);
+    let _ = io::stdout().flush();
+    let mut line = String::new();
+    io::stdin().read_line(&mut line).is_ok() && line.trim().eq_ignore_ascii_case("y")
+}
+
+/// Mines a key in all cores and saves it, the file is never overwritten
+fn generate_key_file(path: &str, strength: u32) {
+    if Path::new(path).exists() {
+        fatal(FailureKind::Keys, &format!("File '{}' exists already, choose another one", path));
+    }
+    let passphrase = read_new_passphrase("Passphrase for the new key (empty to save without it): ");
+    println!("Mining a key of strength {}, it can take some minutes...", strength);
+    let mut keystore = mine_key(strength, num_cpus::get());
+    if let Err(e) = keystore.save(path, &passphrase) {
+        fatal(FailureKind::Keys, &format!("Unable to save key to '{}': {}", path, e));
+    }
+    println!("Saved key {} to '{}'", keystore.get_fingerprint(), path);
+    println!("Public key: {:?}", &keystore.get_public());
+    println!("Hash: {:?}", &keystore.get_hash());
+}
+
+fn load_key_file(path: &str) -> Keystore {
+    let passphrase = match is_encrypted_key(path) {
+        true => read_passphrase("Passphrase: ", KEY_PASSPHRASE_ENV),
+        false => String::new()
+    };
+    Keystore::from_file(path, &passphrase).unwrap_or_else(|| fatal(FailureKind::Keys, &format!("Unable to load key from '{}'", path)))
+}
+
+/// Takes the passphrase from environment, for services and scripts, or asks it in console without showing it
+fn read_passphrase(prompt: &str, env_name: &str) -> String {
+    if let Ok(passphrase) = env::var(env_name) {
+        return passphrase;
+    }
+    print!("{}", prompt);
+    let _ = io::stdout().flush();
+    read_hidden_line().unwrap_or_else(|e| fatal(FailureKind::Keys, &format!("Unable to read passphrase: {}", e)))
+}
+
+/// Takes the passphrase for a new key file, it is asked twice, as a typo would make the key unrecoverable
+fn read_new_passphrase(prompt: &str) -> String {
+    if let Ok(passphrase) = env::var(KEY_NEW_PASSPHRASE_ENV) {
+        return passphrase;
+    }
+    let passphrase = read_passphrase(prompt, KEY_NEW_PASSPHRASE_ENV);
+    if !passphrase.is_empty() && read_passphrase("Repeat the passphrase: ", KEY_NEW_PASSPHRASE_ENV) != passphrase {
+        fatal(FailureKind::Keys, "Passphrases don't match");
+    }
+    passphrase
+}
+
+/// Reads a line from console with echo turned off, or just reads it if the input is not a terminal
+#[cfg(unix)]
+fn read_hidden_line() -> io::Result<String> {
+    let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+    if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } != 0 {
+        return read_line();
+    }
+    let mut hidden = termios;
+    hidden.c_lflag &= !libc::ECHO;
+    hidden.c_lflag |= libc::ECHONL;
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &hidden) };
+    let result = read_line();
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) };
+    result
+}
+
+/// Reads a line from console with echo turned off, or just reads it if the input is not a console
+#[cfg(windows)]
+fn read_hidden_line() -> io::Result<String> {
+    use winapi::um::consoleapi::{GetConsoleMode, SetConsoleMode};
+    use winapi::um::processenv::GetStdHandle;
+    use winapi::um::winbase::STD_INPUT_HANDLE;
+    use winapi::um::wincon::ENABLE_ECHO_INPUT;
+
+    let handle = unsafe { GetStdHandle(STD_INPUT_HANDLE) };
+    let mut mode = 0;
+    if unsafe { GetConsoleMode(handle, &mut mode) } == 0 {
+        return read_line();
+    }
+    unsafe { SetConsoleMode(handle, mode & !ENABLE_ECHO_INPUT) };
+    let result = read_line();
+    unsafe { SetConsoleMode(handle, mode) };
+    // The new line is not shown either
+    println!();
+    result
+}
+
+#[cfg(not(any(unix, windows)))]
+fn read_hidden_line() -> io::Result<String> {
+    read_line()
+}
+
+fn read_line() -> io::Result<String> {
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_owned())
+}
+
+/// Gets the directory for portable mode next to the executable, and creates it if needed
+fn get_portable_dir() -> io::Result<PathBuf> {
+    let exe = env::current_exe()?;
+    let dir = exe.parent().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Executable has no parent directory"))?;
+    let dir = dir.join(PORTABLE_DIR);
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
 /// Sets up logger in accordance with command line options
-fn setup_logger(opt_matches: &Matches) {
+fn setup_logger(opt_matches: &Matches, log_file: Option<String>) {
     let mut level = LevelFilter::Info;
     if opt_matches.opt_present("d") || env::var(GIS_DEBUG).is_ok() {
         level = LevelFilter::Trace;
@@ -165,31 +750,333 @@ fn setup_logger(opt_matches: &Matches) {
         .set_time_level(LevelFilter::Error)
         .set_time_to_local(true)
         .build();
-    match opt_matches.opt_str("l") {
-        None => {
-            if let Err(e) = TermLogger::init(level, config, TerminalMode::Stdout, ColorChoice::Auto) {
-                println!("Unable to initialize logger!\n{}", e);
+    // Exported zones and resolve results are printed to stdout, so that logs shouldn't mix with them
+    let mode = match opt_matches.opt_present("export") || opt_matches.opt_present("resolve") {
+        true => TerminalMode::Stderr,
+        false => TerminalMode::Stdout
+    };
+    let file = log_file.map(|path| {
+        match OpenOptions::new().write(true).create(true).open(&path) {
+            Ok(mut file) => {
+                file.seek(SeekFrom::End(0)).unwrap();
+                file
+            }
+            Err(e) => {
+                println!("Could not open log file '{}' for writing!\n{}", &path, e);
+                exit(1);
             }
         }
-        Some(path) => {
-            let file = match OpenOptions::new().write(true).create(true).open(&path) {
-                Ok(mut file) => {
-                    file.seek(SeekFrom::End(0)).unwrap();
-                    file
-                }
-                Err(e) => {
-                    println!("Could not open log file '{}' for writing!\n{}", &path, e);
-                    exit(1);
-                }
-            };
-            CombinedLogger::init(
+    });
+    // Levels are filtered by the throttled logger, to be changed in runtime
+    let all = LevelFilter::Trace;
+    let logger: Box<dyn Log> = match opt_matches.opt_str("log-format").as_deref() {
+        None | Some("text") => match file {
+            None => TermLogger::new(all, config, mode, ColorChoice::Auto),
+            Some(file) => CombinedLogger::new(
                 vec![
-                    TermLogger::new(level, config.clone(), TerminalMode::Stdout, ColorChoice::Auto),
-                    WriteLogger::new(level, config, file),
+                    TermLogger::new(all, config.clone(), mode, ColorChoice::Auto),
+                    WriteLogger::new(all, config, file),
                 ]
-            ).unwrap();
+            )
+        },
+        Some("json") => {
+            let mut outputs: Vec<Box<dyn Write + Send>> = match mode {
+                TerminalMode::Stderr => vec![Box::new(io::stderr())],
+                _ => vec![Box::new(io::stdout())]
+            };
+            if let Some(file) = file {
+                outputs.push(Box::new(file));
+            }
+            Box::new(JsonLogger::new(all, vec![String::from("mio::poll")], outputs))
+        }
+        Some(format) => {
+            println!("Unknown log format '{}', use 'text' or 'json'", format);
+            exit(1);
+        }
+    };
+    // Repeated messages are counted instead of flooding the log
+    if let Err(e) = ThrottledLogger::init(logger, level) {
+        println!("Unable to initialize logger!\n{}", e);
+    }
+}
+
+/// Starts mining of domain with records from zone file, checking it the same way as GUI does
+fn register_domain(context: &Arc<Mutex<Context>>, miner: &Arc<Mutex<Miner>>, domain: &str, filename: &str, delegation: Option<String>, offer: Option<String>) {
+    let name = domain.trim_end_matches('.').to_lowercase();
+    let delegation = match delegation.map(|file| read_json::<Delegation>(&file)).transpose() {
+        Ok(delegation) => delegation,
+        Err(e) => {
+            error!(target: LOG_TARGET_MAIN, "Unable to read delegation token: {}", e);
+            return;
+        }
+    };
+    let offer = match offer.map(|file| read_json::<SaleOffer>(&file)).transpose() {
+        Ok(offer) => offer,
+        Err(e) => {
+            error!(target: LOG_TARGET_MAIN, "Unable to read sale offer: {}", e);
+            return;
+        }
+    };
+    let text = match fs::read_to_string(filename) {
+        Ok(text) => text,
+        Err(e) => {
+            error!(target: LOG_TARGET_MAIN, "Unable to read zone file '{}': {}", filename, e);
+            return;
+        }
+    };
+    let node = if needs_node_address(&text) { get_node_address() } else { None };
+    let text = match expand_zone_text(&text, node) {
+        Ok(text) => text,
+        Err(e) => {
+            error!(target: LOG_TARGET_MAIN, "Error in zone file '{}': {}", filename, e);
+            return;
+        }
+    };
+    let records = match parse_domain_records(&text, &name) {
+        Ok(records) => records,
+        Err(e) => {
+            error!(target: LOG_TARGET_MAIN, "Error in zone file '{}':\n{}", filename, e);
+            return;
+        }
+    };
+    // Delegates can't change owners, so they are kept from the current data
+    let owners = match &delegation {
+        Some(_) => context.lock().unwrap().chain.get_domain_transaction(&name).and_then(|t| t.get_domain_data()).map(|data| data.owners).unwrap_or_default(),
+        None => Vec::new()
+    };
+    let mut data = DomainData::new(Bytes::default(), get_domain_zone(&name), records, Vec::new(), owners);
+    data.delegation = delegation;
+    if let Some(offer) = offer {
+        let context = context.lock().unwrap();
+        let keystore = match context.get_keystore() {
+            Some(keystore) => keystore,
+            None => {
+                error!(target: LOG_TARGET_MAIN, "You don't have keys loaded, unable to accept the offer");
+                return;
+            }
+        };
+        match DomainTransfer::accept(&keystore, offer) {
+            Ok(transfer) => data.transfer = Some(transfer),
+            Err(e) => {
+                error!(target: LOG_TARGET_MAIN, "Unable to accept the offer: {}", e);
+                return;
+            }
+        }
+    }
+    mine_domain(context, miner, &name, data);
+}
+
+fn read_json<T: DeserializeOwned>(filename: &str) -> Result<T, String> {
+    let text = fs::read_to_string(filename).map_err(|e| e.to_string())?;
+    serde_json::from_str(&text).map_err(|e| e.to_string())
+}
+
+/// Generates key and certificate for our domain, saves them to files and mines the domain with new TLSA record
+fn make_certificate(context: &Arc<Mutex<Context>>, miner: &Arc<Mutex<Miner>>, domain: &str, port: u16) {
+    let name = domain.trim_end_matches('.').to_lowercase();
+    let data = {
+        let context = context.lock().unwrap();
+        let keystore = match context.get_keystore() {
+            Some(keystore) => keystore,
+            None => {
+                error!(target: LOG_TARGET_MAIN, "You don't have keys loaded, unable to update domain");
+                return;
+            }
+        };
+        match context.chain.get_domain_transaction(&name) {
+            Some(transaction) if transaction.pub_key == keystore.get_public() => transaction.get_domain_data(),
+            Some(_) => {
+                error!(target: LOG_TARGET_MAIN, "Domain '{}' is owned by another key", &name);
+                return;
+            }
+            None => {
+                error!(target: LOG_TARGET_MAIN, "You need to register domain '{}' first", &name);
+                return;
+            }
+        }
+    };
+    let data = match data {
+        Some(data) => data,
+        None => {
+            error!(target: LOG_TARGET_MAIN, "Unable to read records of domain '{}'", &name);
+            return;
+        }
+    };
+
+    let cert = Certificate::generate(&name, CERT_VALIDITY_DAYS);
+    let key_file = format!("{}.key.pem", &name);
+    let cert_file = format!("{}.crt.pem", &name);
+    for (file, text) in [(&key_file, &cert.key_pem), (&cert_file, &cert.cert_pem)].iter() {
+        if let Err(e) = fs::write(file, text) {
+            error!(target: LOG_TARGET_MAIN, "Unable to write '{}': {}", file, e);
+            return;
+        }
+    }
+    info!(target: LOG_TARGET_MAIN, "Saved key to '{}' and certificate to '{}'", &key_file, &cert_file);
+
+    // The new record replaces the old one for the same port
+    let tlsa = cert.get_tlsa_record(port);
+    let mut records: Vec<DnsRecord> = data.records.into_iter()
+        .filter(|record| !(record.get_querytype() == QueryType::TLSA && record.get_domain() == tlsa.get_domain()))
+        .collect();
+    records.push(tlsa);
+    let data = DomainData::new(Bytes::default(), data.zone, records, data.contacts, data.owners);
+    mine_domain(context, miner, &name, data);
+}
+
+/// Starts mining of domain with this data, if we can mine it, the name in data is encrypted here
+fn mine_domain(context: &Arc<Mutex<Context>>, miner: &Arc<Mutex<Miner>>, name: &str, mut data: DomainData) {
+    let context = context.lock().unwrap();
+    let keystore = match context.get_keystore() {
+        Some(keystore) => keystore,
+        None => {
+            error!(target: LOG_TARGET_MAIN, "You don't have keys loaded, unable to mine domain");
+            return;
+        }
+    };
+    if context.chain.is_waiting_signers() {
+        error!(target: LOG_TARGET_MAIN, "Waiting for last full block to be signed. Try again later.");
+        return;
+    }
+    let yggdrasil = context.chain.get_zones().iter().any(|z| z.name == data.zone && z.yggdrasil);
+    if yggdrasil && !data.records.iter().all(is_yggdrasil_record) {
+        error!(target: LOG_TARGET_MAIN, "Zone {} is Yggdrasil only, you cannot use IPs from clearnet!", &data.zone);
+        return;
+    }
+    match context.chain.can_mine_domain(context.chain.get_height(), name, &keystore.get_public(), &data) {
+        MineResult::Fine => {
+            let difficulty = context.chain.get_zone_difficulty(&data.zone);
+            let confirmation = hash_identity(name, Some(&keystore.get_public()));
+            data.domain = keystore.encrypt(name.as_bytes(), &confirmation.as_slice()[..12]);
+            let data = serde_json::to_string(&data).unwrap();
+            let transaction = Transaction::from_str(name.to_owned(), CLASS_DOMAIN.to_owned(), data, keystore.get_public());
+            let block = Block::new(Some(transaction), keystore.get_public(), Bytes::default(), difficulty);
+            std::mem::drop(context);
+            miner.lock().unwrap().add_block(block, keystore);
+            info!(target: LOG_TARGET_MAIN, "Mining of domain '{}' has started", name);
+        }
+        MineResult::Cooldown { time } => error!(target: LOG_TARGET_MAIN, "You can register a new domain in {}", format_wait_time(time)),
+        result => error!(target: LOG_TARGET_MAIN, "You can't mine domain '{}': {:?}", name, result)
+    }
+}
+
+/// Tries every detection method separately, to check settings
+fn print_addresses(settings: &AddressDetection) {
+    let detectors = match make_detectors(settings) {
+        Ok(detectors) => detectors,
+        Err(e) => {
+            println!("Wrong [address] settings: {}", e);
+            exit(1);
+        }
+    };
+    let mut preferred = None;
+    for detector in detectors.iter() {
+        match detector.detect() {
+            Ok(addr) => {
+                println!("{}: {}", detector.get_name(), addr);
+                preferred = preferred.or(Some(addr));
+            }
+            Err(e) => println!("{}: {}", detector.get_name(), e)
+        }
+    }
+    match preferred {
+        Some(addr) => println!("Preferred address is {}", addr),
+        None => println!("No address found")
+    }
+}
+
+/// Makes signed statement that our key owns the domain at current height
+fn prove_ownership(chain: &Chain, keystore: &Option<Keystore>, domain: &str, challenge: &str) -> Result<OwnershipProof, String> {
+    let keystore = keystore.as_ref().ok_or_else(|| String::from("no key loaded"))?;
+    let domain = domain.trim_end_matches('.').to_lowercase();
+    match chain.get_domain_transaction(&domain) {
+        Some(transaction) if transaction.pub_key == keystore.get_public() => {}
+        Some(_) => return Err(String::from("the domain is owned by another key")),
+        None => return Err(String::from("there is no such domain"))
+    }
+    let last = chain.last_block().ok_or_else(|| String::from("blockchain is empty"))?;
+    let identity = hash_identity(&domain, None);
+    // The chain up to our height is included if it is not too long, otherwise the verifier checks hashes by itself
+    let proof = chain.get_domain_proof(&identity, Some(last.index))
+        .or_else(|| chain.get_domain_proof(&identity, None))
+        .ok_or_else(|| String::from("the domain is not signed yet"))?;
+    Ok(OwnershipProof::create(keystore, &domain, last.index, last.hash, Utc::now().timestamp(), challenge, proof))
+}
+
+/// Makes signed token that allows the key `to` to update records of our domain for some days
+fn delegate_domain(chain: &Chain, keystore: &Option<Keystore>, domain: &str, to: &str, days: i64) -> Result<Delegation, String> {
+    let (keystore, identity, delegate) = check_own_domain(chain, keystore, domain, to)?;
+    Ok(Delegation::create(keystore, identity, delegate, Utc::now().timestamp() + days * 86400))
+}
+
+/// Makes signed offer of our domain to the key `to`, valid for some days or until the domain changes
+fn offer_domain(chain: &Chain, keystore: &Option<Keystore>, domain: &str, to: &str, days: i64) -> Result<SaleOffer, String> {
+    let (keystore, identity, buyer) = check_own_domain(chain, keystore, domain, to)?;
+    let block = chain.get_domain_block_index(&domain.trim_end_matches('.').to_lowercase())
+        .ok_or_else(|| String::from("the domain has expired"))?;
+    Ok(SaleOffer::create(keystore, identity, buyer, block, Utc::now().timestamp() + days * 86400))
+}
+
+/// Checks that our key owns the domain, returns the key, hash of the domain and the other key from HEX
+fn check_own_domain<'a>(chain: &Chain, keystore: &'a Option<Keystore>, domain: &str, to: &str) -> Result<(&'a Keystore, Bytes, Bytes), String> {
+    let keystore = keystore.as_ref().ok_or_else(|| String::from("no key loaded"))?;
+    let domain = domain.trim_end_matches('.').to_lowercase();
+    let other = from_hex(to).map(Bytes::new).map_err(|_| String::from("wrong key of the other side"))?;
+    if other.length() != 32 {
+        return Err(String::from("wrong key of the other side"));
+    }
+    let identity = hash_identity(&domain, None);
+    match chain.get_domain_owner(u64::MAX, &identity) {
+        Some(owner) if owner == keystore.get_public() => Ok((keystore, identity, other)),
+        Some(_) => Err(String::from("the domain is owned by another key")),
+        None => Err(String::from("there is no such domain"))
+    }
+}
+
+/// Checks ownership proof from file, and that its blocks are the same in our blockchain
+fn verify_ownership(chain: &Chain, filename: &str) -> Result<OwnershipProof, String> {
+    let text = fs::read_to_string(filename).map_err(|e| e.to_string())?;
+    let proof: OwnershipProof = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+    proof.verify(chain.get_schedule())?;
+    for (index, hash) in [(proof.height, &proof.block_hash), (proof.proof.block.index, &proof.proof.block.hash)].iter() {
+        match chain.get_block(*index) {
+            Some(block) if &block.hash == *hash => {}
+            Some(_) => return Err(format!("block {} is not from our blockchain", index)),
+            None => return Err(format!("we don't have block {} yet", index))
+        }
+    }
+    // The proven change must be the last one at that height, and not expired yet
+    let identity = hash_identity(&proof.domain, None);
+    chain.check_domain_ownership(proof.height, &identity, proof.proof.block.index, &proof.pub_key)?;
+    if let Some(transaction) = chain.get_domain_transaction(&proof.domain) {
+        if transaction.pub_key != proof.pub_key {
+            warn!(target: LOG_TARGET_MAIN, "Domain '{}' is owned by another key now", &proof.domain);
+        }
+    }
+    Ok(proof)
+}
+
+/// Resolves the domain with all configured sources and exits, used to debug DNS problems
+fn resolve_and_exit(context: &Arc<Mutex<Context>>, settings: &Settings, opt_matches: &Matches, domain: &str) {
+    let qtype = opt_matches.opt_str("qtype").unwrap_or(String::from("A"));
+    let qtype = match QueryType::from_name(&qtype) {
+        Some(qtype) => qtype,
+        None => {
+            println!("Unknown record type '{}'", &qtype);
+            exit(1);
+        }
+    };
+    let server_context = dns_utils::create_server_context(Arc::clone(context), &[], settings);
+    let trace = trace_resolve(server_context, domain, qtype);
+    if opt_matches.opt_present("trace") {
+        println!("{}", serde_json::to_string_pretty(&trace).unwrap());
+    } else {
+        println!("{}", &trace.rescode);
+        for answer in &trace.answers {
+            println!("{:?}", answer);
         }
     }
+    exit(0);
 }
 
 /// Gets own domains by current loaded keystore and writes them to log
@@ -205,10 +1092,11 @@ fn create_genesis_if_needed(context: &Arc<Mutex<Context>>, miner: &Arc<Mutex<Min
     let context = context.lock().unwrap();
     let last_block = context.get_chain().last_block();
     let origin = context.settings.origin.clone();
-    if origin.is_empty() && last_block.is_none() {
+    if origin.is_empty() && last_block.is_none() && context.light.is_none() {
         if let Some(keystore) = &context.keystore {
             // If blockchain is empty, we are going to mine a Genesis block
-            let block = Block::new(None, context.get_keystore().unwrap().get_public(), Bytes::default(), ZONE_DIFFICULTY);
+            let difficulty = context.get_chain().get_params().zone_difficulty;
+            let block = Block::new(None, context.get_keystore().unwrap().get_public(), Bytes::default(), difficulty);
             miner.lock().unwrap().add_block(block, keystore.clone());
         }
     }
@@ -216,7 +1104,7 @@ fn create_genesis_if_needed(context: &Arc<Mutex<Context>>, miner: &Arc<Mutex<Min
 
 #[cfg(test)]
 mod tests {
-    use alfis::dns::protocol::{DnsRecord, TransientTtl};
+    use gis::dns::protocol::{DnsRecord, TransientTtl};
 
     #[test]
     fn record_to_string() {
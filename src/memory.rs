@@ -0,0 +1,88 @@
+//! Accounting of memory that caches and buffers take, with a budget for every subsystem.
+//! Sizes are estimated, not measured exactly, but they are enough to keep the node within limits of small routers.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::settings::Memory;
+
+pub const MEGABYTE: usize = 1024 * 1024;
+
+/// Used bytes of one subsystem, and its budget, zero for no limit
+pub struct Gauge {
+    pub name: &'static str,
+    used: AtomicUsize,
+    budget: AtomicUsize,
+}
+
+impl Gauge {
+    pub const fn new(name: &'static str) -> Self {
+        Gauge { name, used: AtomicUsize::new(0), budget: AtomicUsize::new(0) }
+    }
+
+    pub fn get_used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn set_used(&self, bytes: usize) {
+        self.used.store(bytes, Ordering::Relaxed);
+    }
+
+    pub fn get_budget(&self) -> usize {
+        self.budget.load(Ordering::Relaxed)
+    }
+
+    pub fn set_budget(&self, bytes: usize) {
+        self.budget.store(bytes, Ordering::Relaxed);
+    }
+}
+
+/// Records of DNS cache
+pub static DNS_CACHE: Gauge = Gauge::new("dns_cache");
+/// Blocks that peers have sent us, but we didn't take yet
+pub static PEER_BUFFERS: Gauge = Gauge::new("peer_buffers");
+
+pub fn get_gauges() -> [&'static Gauge; 2] {
+    [&DNS_CACHE, &PEER_BUFFERS]
+}
+
+/// Sets budgets of all subsystems from settings
+pub fn set_budgets(settings: &Memory) {
+    DNS_CACHE.set_budget(settings.dns_cache_mb * MEGABYTE);
+    PEER_BUFFERS.set_budget(settings.peer_buffers_mb * MEGABYTE);
+}
+
+/// Makes gauges in Prometheus text format, for `/metrics`
+pub fn format_metrics() -> String {
+    let mut result = String::new();
+    let _ = writeln!(result, "# HELP gis_memory_used_bytes Estimated memory used by subsystem");
+    let _ = writeln!(result, "# TYPE gis_memory_used_bytes gauge");
+    for gauge in get_gauges().iter() {
+        let _ = writeln!(result, "gis_memory_used_bytes{{subsystem=\"{}\"}} {}", gauge.name, gauge.get_used());
+    }
+    let _ = writeln!(result, "# HELP gis_memory_budget_bytes Memory budget of subsystem, 0 for no limit");
+    let _ = writeln!(result, "# TYPE gis_memory_budget_bytes gauge");
+    for gauge in get_gauges().iter() {
+        let _ = writeln!(result, "gis_memory_budget_bytes{{subsystem=\"{}\"}} {}", gauge.name, gauge.get_budget());
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics() {
+        let gauge = Gauge::new("test");
+        gauge.set_used(100);
+        gauge.set_budget(MEGABYTE);
+        assert_eq!(100, gauge.get_used());
+        assert_eq!(MEGABYTE, gauge.get_budget());
+
+        let text = format_metrics();
+        assert!(text.contains("# TYPE gis_memory_used_bytes gauge\n"));
+        assert!(text.contains("gis_memory_used_bytes{subsystem=\"dns_cache\"} "));
+        assert!(text.contains("gis_memory_budget_bytes{subsystem=\"peer_buffers\"} "));
+    }
+}
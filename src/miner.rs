@@ -10,11 +10,13 @@ use num_cpus;
 
 use crate::{Block, Bytes, Context, Keystore, setup_miner_thread};
 use crate::commons::*;
+use crate::blockchain::consensus::add_signals;
+use crate::blockchain::mempool::check_transaction;
 use crate::blockchain::types::BlockQuality;
 use crate::blockchain::hash_utils::*;
+use crate::blockchain::pow::get_hasher;
 use crate::keys::check_public_key_strength;
 use crate::event::Event;
-use blakeout::blakeout;
 use std::thread::sleep;
 
 #[derive(Clone)]
@@ -33,6 +35,13 @@ impl MineJob {
         self.block.transaction.is_none()
     }
 
+    fn is_renewal(&self) -> bool {
+        match &self.block.transaction {
+            Some(transaction) => transaction.class == CLASS_RENEW,
+            None => false
+        }
+    }
+
     fn is_due(&self) -> bool {
         self.start == 0 || self.start < Utc::now().timestamp()
     }
@@ -49,6 +58,7 @@ pub struct Miner {
     jobs: Arc<Mutex<Vec<MineJob>>>,
     running: Arc<AtomicBool>,
     mining: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
     cond_var: Arc<Condvar>
 }
 
@@ -59,6 +69,7 @@ impl Miner {
             jobs: Arc::new(Mutex::new(Vec::new())),
             running: Arc::new(AtomicBool::new(false)),
             mining: Arc::new(AtomicBool::new(false)),
+            paused: Arc::new(AtomicBool::new(false)),
             cond_var: Arc::new(Condvar::new())
         }
     }
@@ -74,30 +85,59 @@ impl Miner {
         self.cond_var.notify_one();
     }
 
+    /// Renewals have a deadline, so they are mined before new domains, but after signing blocks
+    pub fn add_renewal(&mut self, block: Block, keystore: Keystore) {
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            let position = jobs.iter().position(|job| job.is_full() && !job.is_renewal()).unwrap_or(jobs.len());
+            jobs.insert(position, MineJob { start: 0, block, keystore });
+        }
+        self.cond_var.notify_one();
+    }
+
     pub fn stop(&mut self) {
         self.mining.store(false, Ordering::SeqCst);
         self.running.store(false, Ordering::SeqCst);
         self.cond_var.notify_all();
     }
 
+    /// Stops mining for a while, the current job returns to the queue and waits there with others
+    pub fn pause(&mut self) {
+        self.paused.store(true, Ordering::SeqCst);
+        self.cond_var.notify_all();
+    }
+
+    pub fn resume(&mut self) {
+        self.paused.store(false, Ordering::SeqCst);
+        self.cond_var.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
     pub fn start_mining_thread(&mut self) {
         let context = Arc::clone(&self.context);
         let jobs = self.jobs.clone();
         let running = self.running.clone();
         let mining = self.mining.clone();
+        let paused = self.paused.clone();
         let cond_var = self.cond_var.clone();
         thread::spawn(move || {
-            Miner::run_main_loop(&context, jobs, running, mining, cond_var);
+            Miner::run_main_loop(&context, jobs, running, mining, paused, cond_var);
         });
 
         // Add events listener to a [Bus]
         let running = self.running.clone();
         let mining = self.mining.clone();
+        let cond_var = self.cond_var.clone();
         self.context.lock().unwrap().bus.register(move |_uuid, e| {
             match e {
                 Event::ActionQuit => { running.store(false, Ordering::Relaxed); }
                 Event::NewBlockReceived => {}
                 Event::BlockchainChanged {..} => {}
+                // The queue doesn't wait for its timeout to take the signing job
+                Event::SignersChosen { signer: true, .. } => { cond_var.notify_all(); }
                 Event::ActionStopMining => {
                     mining.store(false, Ordering::SeqCst);
                 }
@@ -107,11 +147,24 @@ impl Miner {
         });
     }
 
-    fn run_main_loop(context: &Arc<Mutex<Context>>, jobs: Arc<Mutex<Vec<MineJob>>>, running: Arc<AtomicBool>, mining: Arc<AtomicBool>, cond_var: Arc<Condvar>) {
+    fn run_main_loop(context: &Arc<Mutex<Context>>, jobs: Arc<Mutex<Vec<MineJob>>>, running: Arc<AtomicBool>, mining: Arc<AtomicBool>, paused: Arc<AtomicBool>, cond_var: Arc<Condvar>) {
         running.store(true, Ordering::SeqCst);
         let delay = Duration::from_secs(30);
         let mut current_job: Option<MineJob> = None;
         while running.load(Ordering::SeqCst) {
+            if paused.load(Ordering::SeqCst) {
+                let mut jobs = jobs.lock().unwrap();
+                // Unfinished job is mined again from the start after resume
+                if let Some(job) = current_job.take() {
+                    if mining.load(Ordering::SeqCst) {
+                        info!("Mining is paused");
+                        mining.store(false, Ordering::SeqCst);
+                        jobs.insert(0, job);
+                    }
+                }
+                let _ = cond_var.wait_timeout(jobs, delay).expect("Error in wait lock!");
+                continue;
+            }
             if let Some(ref cur_job) = current_job {
                 // If we are mining signing block
                 if mining.load(Ordering::Relaxed) && cur_job.is_signing() {
@@ -147,7 +200,8 @@ impl Miner {
                     }
 
                     if !signing_waits {
-                        if let Ok(context) = context.lock() {
+                        if let Ok(mut context) = context.lock() {
+                            context.update_signers();
                             let keystore = context.get_keystore();
                             // Ask the blockchain if we have to sign something
                             if let Some(block) = context.chain.get_sign_block(&keystore) {
@@ -175,14 +229,20 @@ impl Miner {
                     }
                 } else {
                     // If our queue is empty
-                    if let Ok(context) = context.lock() {
+                    if let Ok(mut context) = context.lock() {
+                        context.update_signers();
                         let keystore = context.get_keystore();
                         // Ask the blockchain if we have to sign something
                         if let Some(block) = context.chain.get_sign_block(&keystore) {
                             info!("Got signing job, adding to queue");
                             // We start mining sign block after some time, not everyone in the same time
                             let start = Utc::now().timestamp() + (rand::random::<i64>() % BLOCK_SIGNERS_START_RANDOM);
-                            jobs.push(MineJob { start, block, keystore: keystore.unwrap() });
+                            jobs.push(MineJob { start, block, keystore: keystore.clone().unwrap() });
+                        }
+                        if let Some(keystore) = keystore {
+                            if context.settings.mempool.mine {
+                                jobs.extend(Miner::get_mempool_jobs(&mut context, &keystore));
+                            }
                         }
                     }
                 }
@@ -196,6 +256,26 @@ impl Miner {
         info!("Stopped mining queue thread");
     }
 
+    /// Makes jobs of transactions, that other nodes have built for our key
+    fn get_mempool_jobs(context: &mut Context, keystore: &Keystore) -> Vec<MineJob> {
+        let now = Utc::now().timestamp();
+        let transactions = context.mempool.take_for_key(&keystore.get_public(), now);
+        let mut result = Vec::new();
+        for transaction in transactions {
+            // The chain could change while it waited
+            if let Err(e) = check_transaction(&context.chain, &transaction, now) {
+                warn!("Dropping transaction from mempool: {}", e);
+                continue;
+            }
+            let zone = transaction.get_domain_data().map(|data| data.zone).unwrap_or_default();
+            let difficulty = context.chain.get_zone_difficulty(&zone);
+            info!("Got transaction from mempool, adding to queue");
+            let block = Block::new(Some(transaction), keystore.get_public(), Bytes::default(), difficulty);
+            result.push(MineJob { start: 0, block, keystore: keystore.clone() });
+        }
+        result
+    }
+
     pub fn is_mining(&self) -> bool {
         self.running.load(Ordering::Relaxed)
     }
@@ -204,7 +284,7 @@ impl Miner {
         // Clear signature and hash just in case
         job.block.signature = Bytes::default();
         job.block.hash = Bytes::default();
-        job.block.version = CHAIN_VERSION;
+        job.block.version = context.lock().unwrap().chain.get_block_version();
         // If this block needs to be a signer
         if job.block.index > 0 && !job.block.prev_block_hash.is_empty() {
             info!("Mining signing block");
@@ -227,6 +307,9 @@ impl Miner {
                 }
             }
         } else {
+            if job.is_renewal() {
+                info!("Mining renewal of domain");
+            }
             job.block.index = context.lock().unwrap().chain.get_height() + 1;
             job.block.prev_block_hash = match context.lock().unwrap().chain.last_block() {
                 None => { Bytes::default() }
@@ -276,7 +359,9 @@ impl Miner {
                         let mut context = context.lock().unwrap();
                         block.signature = Bytes::from_bytes(&job.keystore.sign(&block.as_bytes()));
                         let mut success = false;
-                        if context.chain.check_new_block(&block) != BlockQuality::Good {
+                        if context.disk.is_critical() {
+                            error!("Not enough disk space to save mined block!");
+                        } else if context.chain.check_new_block(&block) != BlockQuality::Good {
                             warn!("Error adding mined block!");
                             if index == 0 {
                                 error!("To mine genesis block you need to make 'origin' an empty string in config.");
@@ -286,7 +371,13 @@ impl Miner {
                             if block.index == 1 {
                                 context.settings.origin = block.hash.to_string();
                             }
+                            let domain = block.transaction.as_ref().and_then(|t| t.get_domain_data().map(|data| (data.zone, t.identity.clone())));
+                            let serial = block.index;
                             context.chain.add_block(block);
+                            context.update_signers();
+                            if let Some((zone, identity)) = domain {
+                                context.bus.post(Event::DomainChanged { zone, identity, serial });
+                            }
                             success = true;
                         }
                         context.miner_state.mining = false;
@@ -303,10 +394,11 @@ impl Miner {
 fn find_hash(context: Arc<Mutex<Context>>, mut block: Block, running: Arc<AtomicBool>, thread: usize) -> Option<Block> {
     let target_diff = block.difficulty;
     let full = block.transaction.is_some();
-    let mut digest = blakeout::new();
+    let mut hasher = get_hasher(block.version);
     let mut max_diff = 0;
+    let signals = context.lock().unwrap().chain.get_signal_bits();
     loop {
-        block.random = rand::random();
+        block.random = add_signals(rand::random(), signals);
         block.timestamp = Utc::now().timestamp();
         let waiting_signers = {
             let context = context.lock().unwrap();
@@ -335,11 +427,10 @@ fn find_hash(context: Arc<Mutex<Context>>, mut block: Block, running: Arc<Atomic
             }
             block.nonce = nonce;
 
-            digest.reset();
-            digest.update(&block.as_bytes());
-            let diff = hash_difficulty(digest.result());
+            let hash = hasher.hash(&block.as_bytes());
+            let diff = hash_difficulty(hash);
             if diff >= target_diff {
-                block.hash = Bytes::from_bytes(digest.result());
+                block.hash = Bytes::from_bytes(hash);
                 return Some(block);
             }
             if diff > max_diff {
@@ -0,0 +1,187 @@
+//! Misbehavior scores of peers, when the score gets too high the peer is banned for some time.
+//! Every next ban of the same address is twice longer, so bad peers can't just reconnect and continue.
+
+use std::cmp::min;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde::Serialize;
+
+/// Score that gets the peer banned
+const BAN_SCORE: u32 = 100;
+/// Duration of the first ban, in seconds
+const BAN_TIME: u64 = 600;
+/// Maximum duration of a ban, in seconds
+const MAX_BAN_TIME: u64 = 86400;
+/// Score is lowered by one point every this number of seconds
+const SCORE_DECAY_TIME: u64 = 60;
+/// Peers that behave well for this number of seconds after the last offense are forgotten, with their previous bans
+const FORGET_TIME: u64 = 604800;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Offense {
+    /// Block that doesn't pass our checks
+    BadBlock,
+    /// Message that can't be parsed or has inconsistent data
+    Malformed,
+    /// Block with time too far in the future
+    Timestamp,
+    /// Handshake from other chain or unsupported version
+    WrongChain,
+}
+
+impl Offense {
+    pub fn score(&self) -> u32 {
+        match self {
+            Offense::BadBlock => BAN_SCORE,
+            Offense::Malformed => 25,
+            Offense::Timestamp => 20,
+            Offense::WrongChain => BAN_SCORE
+        }
+    }
+}
+
+/// Score of one address, as it is shown in network status
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PeerScore {
+    pub ip: IpAddr,
+    pub score: u32,
+    /// How many times this address was banned
+    pub bans: u32,
+    /// Seconds until the current ban ends, zero if it is not banned
+    pub banned_for: u64,
+}
+
+#[derive(Debug)]
+struct Record {
+    score: u32,
+    bans: u32,
+    /// Time from which the score decays
+    updated: Instant,
+    punished: Instant,
+    banned_until: Option<Instant>,
+}
+
+impl Record {
+    fn new() -> Self {
+        Record { score: 0, bans: 0, updated: Instant::now(), punished: Instant::now(), banned_until: None }
+    }
+
+    /// Lowers the score by the time passed since last update
+    fn decay(&mut self) {
+        let points = self.updated.elapsed().as_secs() / SCORE_DECAY_TIME;
+        if points > 0 {
+            self.score = self.score.saturating_sub(min(points, u32::MAX as u64) as u32);
+            self.updated += Duration::from_secs(points * SCORE_DECAY_TIME);
+        }
+    }
+
+    fn banned_for(&self) -> Duration {
+        match self.banned_until {
+            Some(until) => until.saturating_duration_since(Instant::now()),
+            None => Duration::ZERO
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct BanList {
+    records: HashMap<IpAddr, Record>,
+}
+
+impl BanList {
+    /// Adds score of the offense to this address, returns true if it is banned now
+    pub fn punish(&mut self, ip: &IpAddr, offense: Offense) -> bool {
+        let record = self.records.entry(*ip).or_insert_with(Record::new);
+        record.decay();
+        record.punished = Instant::now();
+        record.score = record.score.saturating_add(offense.score());
+        if record.score < BAN_SCORE {
+            debug!("Peer {} got {} points for {:?}, score is {}", ip, offense.score(), offense, record.score);
+            return false;
+        }
+        record.bans += 1;
+        record.score = 0;
+        let time = get_ban_time(record.bans);
+        record.banned_until = Some(Instant::now() + time);
+        info!("Peer {} is banned for {} seconds for {:?}, it is ban number {}", ip, time.as_secs(), offense, record.bans);
+        true
+    }
+
+    pub fn is_banned(&self, ip: &IpAddr) -> bool {
+        match self.records.get(ip) {
+            Some(record) => record.banned_for() > Duration::ZERO,
+            None => false
+        }
+    }
+
+    pub fn get_banned_count(&self) -> usize {
+        self.records.values().filter(|record| record.banned_for() > Duration::ZERO).count()
+    }
+
+    /// Gets scores of all addresses that have misbehaved lately
+    pub fn get_scores(&self) -> Vec<PeerScore> {
+        let mut scores: Vec<PeerScore> = self.records
+            .iter()
+            .map(|(ip, record)| PeerScore { ip: *ip, score: record.score, bans: record.bans, banned_for: record.banned_for().as_secs() })
+            .collect();
+        scores.sort_by(|a, b| a.ip.cmp(&b.ip));
+        scores
+    }
+
+    /// Lowers scores and forgets addresses that behave well for a long time
+    pub fn update(&mut self) {
+        self.records.retain(|_, record| {
+            record.decay();
+            record.score > 0 || record.banned_for() > Duration::ZERO || record.punished.elapsed().as_secs() < FORGET_TIME
+        });
+    }
+}
+
+/// Every next ban is twice longer than previous, up to `MAX_BAN_TIME`
+fn get_ban_time(bans: u32) -> Duration {
+    let factor = 1u64 << min(bans.saturating_sub(1), 16);
+    Duration::from_secs(min(BAN_TIME * factor, MAX_BAN_TIME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escalating_bans() {
+        assert_eq!(600, get_ban_time(1).as_secs());
+        assert_eq!(1200, get_ban_time(2).as_secs());
+        assert_eq!(4800, get_ban_time(4).as_secs());
+        assert_eq!(MAX_BAN_TIME, get_ban_time(10).as_secs());
+        assert_eq!(MAX_BAN_TIME, get_ban_time(u32::MAX).as_secs());
+    }
+
+    #[test]
+    fn scores() {
+        let mut bans = BanList::default();
+        let ip: IpAddr = "1.2.3.4".parse().unwrap();
+        let other: IpAddr = "200:1234::1".parse().unwrap();
+        for _ in 0..3 {
+            assert!(!bans.punish(&ip, Offense::Malformed));
+        }
+        assert!(!bans.is_banned(&ip));
+        assert_eq!(75, bans.get_scores()[0].score);
+        assert!(!bans.punish(&ip, Offense::Timestamp));
+        assert!(bans.punish(&ip, Offense::Malformed));
+        assert!(bans.is_banned(&ip));
+        assert!(!bans.is_banned(&other));
+
+        assert!(bans.punish(&other, Offense::BadBlock));
+        assert_eq!(2, bans.get_banned_count());
+        let scores = bans.get_scores();
+        assert_eq!(ip, scores[0].ip);
+        assert_eq!(1, scores[0].bans);
+        assert!(scores[0].banned_for > 590);
+        bans.update();
+        assert_eq!(2, bans.get_scores().len());
+    }
+}
@@ -0,0 +1,139 @@
+//! Addresses of good peers, that we have talked to. They are given to other nodes in peer exchange,
+//! and saved to file, so that after restart the node connects to them without waiting for bootstrap nodes.
+
+use std::collections::HashMap;
+use std::fs;
+use std::net::{SocketAddr, ToSocketAddrs};
+
+use chrono::Utc;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde::{Deserialize, Serialize};
+
+/// How many addresses are kept, the ones that were seen long ago are forgotten first
+const MAX_KNOWN_PEERS: usize = 100;
+/// Peers that were not seen for a week are forgotten
+const KNOWN_PEER_LIFETIME: i64 = 7 * 86400;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct KnownPeer {
+    address: SocketAddr,
+    last_seen: i64,
+}
+
+#[derive(Default)]
+pub struct KnownPeers {
+    peers: HashMap<SocketAddr, i64>,
+    filename: String,
+}
+
+impl KnownPeers {
+    /// Loads addresses from file, nothing is saved or loaded if the file name is empty
+    pub fn load(filename: &str) -> Self {
+        let mut known = KnownPeers { peers: HashMap::new(), filename: filename.to_owned() };
+        if filename.is_empty() {
+            return known;
+        }
+        if let Ok(text) = fs::read_to_string(filename) {
+            match serde_json::from_str::<Vec<KnownPeer>>(&text) {
+                Ok(peers) => {
+                    for peer in peers {
+                        known.peers.insert(peer.address, peer.last_seen);
+                    }
+                    known.forget_old(Utc::now().timestamp());
+                    info!("Loaded {} known peers from '{}'", known.peers.len(), filename);
+                }
+                Err(e) => warn!("Unable to parse known peers from '{}': {}", filename, e)
+            }
+        }
+        known
+    }
+
+    pub fn save(&self) {
+        if self.filename.is_empty() {
+            return;
+        }
+        let mut peers: Vec<KnownPeer> = self.peers.iter().map(|(address, last_seen)| KnownPeer { address: *address, last_seen: *last_seen }).collect();
+        peers.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+        match fs::write(&self.filename, serde_json::to_string(&peers).unwrap()) {
+            Ok(_) => debug!("Saved {} known peers to '{}'", peers.len(), &self.filename),
+            Err(e) => warn!("Unable to save known peers to '{}': {}", &self.filename, e)
+        }
+    }
+
+    /// Remembers the address of a good peer
+    pub fn seen(&mut self, address: SocketAddr, now: i64) {
+        self.peers.insert(address, now);
+        self.forget_old(now);
+    }
+
+    fn forget_old(&mut self, now: i64) {
+        self.peers.retain(|_, last_seen| *last_seen + KNOWN_PEER_LIFETIME > now);
+        if self.peers.len() > MAX_KNOWN_PEERS {
+            let mut times: Vec<i64> = self.peers.values().cloned().collect();
+            times.sort_unstable_by(|a, b| b.cmp(a));
+            let oldest = times[MAX_KNOWN_PEERS - 1];
+            self.peers.retain(|_, last_seen| *last_seen >= oldest);
+        }
+    }
+
+    /// Gets addresses, the recently seen ones go first
+    pub fn get_addresses(&self) -> Vec<String> {
+        let mut peers: Vec<(&SocketAddr, &i64)> = self.peers.iter().collect();
+        peers.sort_by(|a, b| b.1.cmp(a.1));
+        peers.into_iter().map(|(address, _)| address.to_string()).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+}
+
+/// Resolves DNS seeds, these are host names with addresses of nodes, that listen on `port`
+pub fn resolve_seeds(seeds: &[String], port: u16) -> Vec<String> {
+    let mut result = Vec::new();
+    for seed in seeds {
+        match (seed.as_str(), port).to_socket_addrs() {
+            Ok(addresses) => {
+                let addresses: Vec<String> = addresses.map(|address| address.to_string()).collect();
+                debug!("Got {} addresses from seed {}", addresses.len(), seed);
+                result.extend(addresses);
+            }
+            Err(e) => warn!("Unable to resolve seed {}: {}", seed, e)
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_peers() {
+        let filename = std::env::temp_dir().join("gis-known-peers-test.json");
+        let filename = filename.to_str().unwrap();
+        let mut known = KnownPeers::load(filename);
+        let now = Utc::now().timestamp();
+        known.seen("1.2.3.4:46866".parse().unwrap(), now - KNOWN_PEER_LIFETIME);
+        known.seen("5.6.7.8:46866".parse().unwrap(), now - 10);
+        known.seen("[200::1]:46866".parse().unwrap(), now);
+        // The old one is forgotten
+        assert_eq!(vec!["[200::1]:46866", "5.6.7.8:46866"], known.get_addresses());
+
+        for i in 0..MAX_KNOWN_PEERS as i64 {
+            known.seen(format!("10.0.{}.{}:46866", i / 256, i % 256).parse().unwrap(), now + 1 + i);
+        }
+        assert_eq!(MAX_KNOWN_PEERS, known.len());
+        assert!(!known.get_addresses().contains(&String::from("5.6.7.8:46866")));
+
+        known.save();
+        let loaded = KnownPeers::load(filename);
+        let _ = fs::remove_file(filename);
+        assert_eq!(known.get_addresses(), loaded.get_addresses());
+    }
+}
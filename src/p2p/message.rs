@@ -3,6 +3,7 @@ extern crate serde_json;
 
 use serde::{Deserialize, Serialize};
 use crate::Bytes;
+use crate::light::Header;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Message {
@@ -17,6 +18,21 @@ pub enum Message {
     Peers { peers: Vec<String> },
     GetBlock { index: u64 },
     Block { index: u64, block: String },
+    /// Request of domain data by identity hash, used by thin clients.
+    /// If `to` is set, the proof must lead to the block with this index.
+    GetDomain { identity: Bytes, #[serde(default)] to: Option<u64> },
+    /// Request of blocks from `from` to `to`, to check that some block is in the chain
+    GetProof { from: u64, to: u64 },
+    /// Serialized hash-chain proof, or None if we can't make it
+    Proof { from: u64, to: u64, #[serde(default)] proof: Option<String> },
+    /// Serialized proof of domain data, or None if there is no such domain
+    Domain { identity: Bytes, #[serde(default)] proof: Option<String> },
+    /// Serialized domain transaction for the mempool, it waits there for its miner
+    Transaction { transaction: String },
+    /// Request of headers of `count` blocks starting from `from`, for headers-first sync
+    GetHeaders { from: u64, count: u64 },
+    /// Headers of blocks starting from `from`, there are less of them if the peer doesn't have more
+    Headers { from: u64, headers: Vec<Header> },
 }
 
 impl Message {
@@ -64,4 +80,14 @@ mod tests {
         assert!(serde_json::from_str::<Message>("{\"Hand\":{\"origin\":\"\",\"version\":1,\"public\":false}}").is_ok());
     }
 
+    #[test]
+    pub fn test_domain() {
+        assert!(serde_json::from_str::<Message>("{\"GetDomain\":{\"identity\":\"AABB\"}}").is_ok());
+        assert!(serde_json::from_str::<Message>("{\"Domain\":{\"identity\":\"AABB\"}}").is_ok());
+        assert!(serde_json::from_str::<Message>("{\"GetDomain\":{\"identity\":\"AABB\",\"to\":100}}").is_ok());
+        assert!(serde_json::from_str::<Message>("{\"Proof\":{\"from\":1,\"to\":2}}").is_ok());
+        assert!(serde_json::from_str::<Message>("{\"GetHeaders\":{\"from\":1,\"count\":500}}").is_ok());
+        assert!(serde_json::from_str::<Message>("{\"Headers\":{\"from\":1,\"headers\":[{\"index\":1,\"timestamp\":0,\"hash\":\"AABB\",\"prev_block_hash\":\"\",\"difficulty\":20}]}}").is_ok());
+    }
+
 }
\ No newline at end of file
@@ -0,0 +1,81 @@
+//! Connection metrics per transport, to see how much Yggdrasil and clearnet sides of a node are used.
+
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+
+use serde::Serialize;
+
+use crate::is_yggdrasil;
+use crate::p2p::bans::PeerScore;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Transport {
+    Clearnet,
+    Yggdrasil,
+}
+
+impl Transport {
+    pub fn from_addr(addr: &IpAddr) -> Self {
+        match is_yggdrasil(addr) {
+            true => Transport::Yggdrasil,
+            false => Transport::Clearnet
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct TransportStats {
+    /// Active peers, inbound and outbound
+    pub nodes: usize,
+    pub inbound: usize,
+    /// Bytes received and sent since start, including closed connections
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    /// Average round trip time of pings to active peers, if there were any answers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct NetworkMetrics {
+    pub clearnet: TransportStats,
+    pub yggdrasil: TransportStats,
+    /// Misbehavior scores and bans of peer addresses
+    pub scores: Vec<PeerScore>,
+}
+
+impl NetworkMetrics {
+    pub fn get_mut(&mut self, transport: Transport) -> &mut TransportStats {
+        match transport {
+            Transport::Clearnet => &mut self.clearnet,
+            Transport::Yggdrasil => &mut self.yggdrasil
+        }
+    }
+}
+
+impl Display for TransportStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} nodes ({} inbound), {} KiB in, {} KiB out", self.nodes, self.inbound, self.bytes_in / 1024, self.bytes_out / 1024)?;
+        match self.latency_ms {
+            Some(latency) => write!(f, ", latency {} ms", latency),
+            None => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transports() {
+        assert_eq!(Transport::Yggdrasil, Transport::from_addr(&"200:1234::1".parse().unwrap()));
+        assert_eq!(Transport::Yggdrasil, Transport::from_addr(&"300:1234::1".parse().unwrap()));
+        assert_eq!(Transport::Clearnet, Transport::from_addr(&"2a01::1".parse().unwrap()));
+        assert_eq!(Transport::Clearnet, Transport::from_addr(&"1.2.3.4".parse().unwrap()));
+
+        let stats = TransportStats { nodes: 3, inbound: 1, bytes_in: 4096, bytes_out: 2048, latency_ms: Some(40) };
+        assert_eq!("3 nodes (1 inbound), 4 KiB in, 2 KiB out, latency 40 ms", stats.to_string());
+    }
+}
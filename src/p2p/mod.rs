@@ -3,6 +3,11 @@ pub mod message;
 pub mod state;
 pub mod peer;
 pub mod peers;
+pub mod metrics;
+pub mod bans;
+pub mod query;
+pub mod known;
+pub mod sync;
 
 pub use network::Network;
 pub use message::Message;
@@ -10,6 +10,7 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use chrono::Utc;
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
 use mio::{Events, Interest, Poll, Registry, Token};
@@ -17,10 +18,17 @@ use mio::event::Event;
 use mio::net::{TcpListener, TcpStream};
 use rand::random;
 
-use crate::{Block, Context, p2p::Message, p2p::Peer, p2p::Peers, p2p::State, Transaction};
+use crate::{Block, Bytes, Context, p2p::Message, p2p::Peer, p2p::Peers, p2p::State, Transaction};
+use crate::blockchain::hash_utils::check_block_hash;
+use crate::blockchain::mempool::check_transaction;
 use crate::blockchain::transaction::TransactionType;
 use crate::blockchain::types::BlockQuality;
 use crate::commons::*;
+use crate::light::{Header, LightClient};
+use crate::p2p::bans::Offense;
+use crate::p2p::known::resolve_seeds;
+use crate::p2p::sync::{HeadersSync, MAX_HEADERS};
+use crate::sockets::bind_tcp;
 
 const SERVER: Token = Token(0);
 
@@ -34,17 +42,20 @@ impl Network {
     }
 
     pub fn start(&mut self) -> Result<(), String> {
-        let (listen_addr, peers_addrs, yggdrasil_only) = {
+        let (listen_addr, mut peers_addrs, yggdrasil_only, peer_port, light, seeds, peers_file) = {
             let c = self.context.lock().unwrap();
-            (c.settings.net.listen.clone(), c.settings.net.peers.clone(), c.settings.net.yggdrasil_only)
+            let net = &c.settings.net;
+            (net.listen.clone(), net.peers.clone(), net.yggdrasil_only, net.peer_port, c.light.clone(), net.seeds.clone(), net.peers_file.clone())
         };
 
         let running = Arc::new(AtomicBool::new(true));
-        subscribe_to_bus(&mut self.context, Arc::clone(&running));
+        let peers_changed = Arc::new(AtomicBool::new(false));
+        subscribe_to_bus(&mut self.context, Arc::clone(&running), Arc::clone(&peers_changed));
 
         // Starting server socket
-        let addr = listen_addr.parse().expect("Error parsing listen address");
-        let mut server = TcpListener::bind(addr).expect("Can't bind to address");
+        let listener = bind_tcp(&listen_addr, 0).map_err(|e| format!("Can't listen on {}: {}", &listen_addr, e))?;
+        listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+        let mut server = TcpListener::from_std(listener);
         debug!("Started node listener on {}", server.local_addr().unwrap());
 
         let mut events = Events::with_capacity(1024);
@@ -57,20 +68,30 @@ impl Network {
             // Unique token for each incoming connection.
             let mut unique_token = Token(SERVER.0 + 1);
             // States of peer connections, and some data to send when sockets become writable
-            let mut peers = Peers::new();
+            let mut peers = Peers::new(peer_port, &peers_file);
+            // Headers and blocks that are downloaded in parallel, when some peers are far ahead
+            let mut sync = HeadersSync::default();
             // Starting peer connections to bootstrap nodes
             peers.connect_peers(&peers_addrs, &poll.registry(), &mut unique_token, yggdrasil_only);
+            // Nodes from previous runs and from DNS seeds are connected to after them
+            let known = peers.get_known_peers();
+            peers.add_peers_from_exchange(known);
+            peers.add_peers_from_exchange(resolve_seeds(&seeds, peer_port));
 
             let mut ui_timer = Instant::now();
             let mut log_timer = Instant::now();
             let mut bootstrap_timer = Instant::now();
             let mut connect_timer = Instant::now();
+            let mut known_timer = Instant::now();
             let mut last_events_time = Instant::now();
             loop {
                 if peers.get_peers_count() == 0 && bootstrap_timer.elapsed().as_secs() > 60 {
                     warn!("Restarting swarm connections...");
                     // Starting peer connections to bootstrap nodes
                     peers.connect_peers(&peers_addrs, &poll.registry(), &mut unique_token, yggdrasil_only);
+                    let known = peers.get_known_peers();
+                    peers.add_peers_from_exchange(known);
+                    peers.add_peers_from_exchange(resolve_seeds(&seeds, peer_port));
                     bootstrap_timer = Instant::now();
                 }
                 // Poll Mio for events, blocking until we get an event.
@@ -78,6 +99,16 @@ impl Network {
                 if !running.load(Ordering::SeqCst) {
                     break;
                 }
+                // Bootstrap nodes were changed in settings, new ones are connected right away
+                if peers_changed.swap(false, Ordering::SeqCst) {
+                    let new_addrs = context.lock().unwrap().settings.net.peers.clone();
+                    let added: Vec<String> = new_addrs.iter().filter(|addr| !peers_addrs.contains(addr)).cloned().collect();
+                    peers_addrs = new_addrs;
+                    if !added.is_empty() {
+                        info!("Connecting to {} new bootstrap nodes from settings", added.len());
+                        peers.connect_peers(&added, &poll.registry(), &mut unique_token, yggdrasil_only);
+                    }
+                }
 
                 // Process each event.
                 for event in events.iter() {
@@ -104,6 +135,13 @@ impl Network {
                                         continue;
                                     }
 
+                                    if peers.is_over_budget() {
+                                        debug!("Dropping connection from {}, peer buffers are over budget", &address);
+                                        stream.shutdown(Shutdown::Both).unwrap_or_else(|e|{ warn!("Error in shutdown, {}", e); });
+                                        let _ = poll.registry().reregister(&mut server, SERVER, Interest::READABLE);
+                                        continue;
+                                    }
+
                                     if yggdrasil_only && !is_yggdrasil(&address.ip()) {
                                         debug!("Dropping connection from Internet");
                                         stream.shutdown(Shutdown::Both).unwrap_or_else(|e|{ warn!("Error in shutdown, {}", e); });
@@ -126,10 +164,10 @@ impl Network {
                             }
                         }
                         token => {
-                            if !handle_connection_event(Arc::clone(&context), &mut peers, &poll.registry(), &event) {
+                            if !handle_connection_event(Arc::clone(&context), &mut peers, &mut sync, &poll.registry(), &event) {
                                 let _ = peers.close_peer(poll.registry(), &token);
                                 let mut context = context.lock().unwrap();
-                                let blocks_count = context.chain.get_height();
+                                let (blocks_count, _) = get_height_and_hash(&context);
                                 context.bus.post(crate::event::Event::NetworkStatus { nodes: peers.get_peers_active_count(), blocks: blocks_count });
                             }
                         }
@@ -142,18 +180,33 @@ impl Network {
                     peers.close_all_peers(poll.registry());
                 }
 
+                // Light node queries go to random full nodes
+                if let Some(light) = &light {
+                    for message in light.take_requests() {
+                        if !peers.send_to_random_peer(poll.registry(), message) {
+                            debug!("There are no nodes to send light query to");
+                        }
+                    }
+                }
+
                 if ui_timer.elapsed().as_millis() > UI_REFRESH_DELAY_MS {
                     // Send pings to idle peers
-                    let (height, hash) = {
+                    let (height, hash, transactions) = {
                         let mut context = context.lock().unwrap();
-                        let height = context.chain.get_height();
+                        // Blocks of a stalled sync must not wait for the next batch
+                        context.chain.commit_blocks();
+                        let (height, hash) = get_height_and_hash(&context);
                         let nodes = peers.get_peers_active_count();
                         let banned = peers.get_peers_banned_count();
+                        let metrics = peers.get_metrics();
                         if nodes > 0 {
                             context.bus.post(crate::event::Event::NetworkStatus { nodes, blocks: height });
                         }
+                        context.bus.post(crate::event::Event::NetworkMetrics { metrics: metrics.clone() });
                         if log_timer.elapsed().as_secs() > LOG_REFRESH_DELAY_SEC {
                             info!("Active nodes count: {}, banned count: {}, blocks count: {}", nodes, banned, height);
+                            info!("Clearnet: {}", &metrics.clearnet);
+                            info!("Yggdrasil: {}", &metrics.yggdrasil);
                             let elapsed = last_events_time.elapsed().as_secs();
                             if elapsed >= 10 {
                                 warn!("Last network events time {} seconds ago", elapsed);
@@ -164,12 +217,42 @@ impl Network {
                             peers.connect_new_peers(poll.registry(), &mut unique_token, yggdrasil_only);
                             connect_timer = Instant::now();
                         }
-                        (height, context.chain.get_last_hash())
+                        (height, hash, context.mempool.take_outgoing())
                     };
-                    peers.update(poll.registry(), height, hash);
+                    // New transactions of the mempool go to several peers, they pass them on
+                    for transaction in transactions {
+                        let transaction = serde_json::to_string(&transaction).unwrap();
+                        for _ in 0..MEMPOOL_RELAY_PEERS {
+                            if !peers.send_to_random_peer(poll.registry(), Message::Transaction { transaction: transaction.clone() }) {
+                                break;
+                            }
+                        }
+                    }
+                    // Full nodes that are far behind download blocks from all peers in parallel
+                    if light.is_none() {
+                        sync.update(height);
+                        if let Some(from) = sync.need_headers(height, peers.get_best_height()) {
+                            if let Some(token) = peers.request_headers(poll.registry(), from) {
+                                sync.set_headers_requested(token, from);
+                            }
+                        }
+                        peers.request_blocks(poll.registry(), &mut sync, height);
+                    }
+                    peers.update(poll.registry(), height, hash, sync.is_active());
+                    if let Some(light) = &light {
+                        light.save(false);
+                    }
+                    if known_timer.elapsed().as_secs() >= KNOWN_PEERS_SAVE_SEC {
+                        peers.save_known_peers();
+                        known_timer = Instant::now();
+                    }
                     ui_timer = Instant::now();
                 }
             }
+            if let Some(light) = &light {
+                light.save(true);
+            }
+            peers.save_known_peers();
             if !running.load(Ordering::SeqCst) {
                 info!("Network loop finished");
             } else {
@@ -180,7 +263,7 @@ impl Network {
     }
 }
 
-fn subscribe_to_bus(context: &mut Arc<Mutex<Context>>, running: Arc<AtomicBool>) {
+fn subscribe_to_bus(context: &mut Arc<Mutex<Context>>, running: Arc<AtomicBool>, peers_changed: Arc<AtomicBool>) {
     use crate::event::Event;
     context.lock().unwrap().bus.register(move |_uuid, e| {
         match e {
@@ -188,13 +271,16 @@ fn subscribe_to_bus(context: &mut Arc<Mutex<Context>>, running: Arc<AtomicBool>)
                 running.store(false, Ordering::SeqCst);
                 return false;
             }
+            Event::SettingsChanged { changed } if changed.iter().any(|name| name == "net.peers") => {
+                peers_changed.store(true, Ordering::SeqCst);
+            }
             _ => {}
         }
         true
     });
 }
 
-fn handle_connection_event(context: Arc<Mutex<Context>>, peers: &mut Peers, registry: &Registry, event: &Event) -> bool {
+fn handle_connection_event(context: Arc<Mutex<Context>>, peers: &mut Peers, sync: &mut HeadersSync, registry: &Registry, event: &Event) -> bool {
     if event.is_error() || (event.is_read_closed() && event.is_write_closed()) {
         return false;
     }
@@ -226,17 +312,22 @@ fn handle_connection_event(context: Arc<Mutex<Context>>, peers: &mut Peers, regi
                     }
                     peer.reset_spurious();
                     let mut stream = peer.get_stream();
-                    read_message(&mut stream)
+                    let data = read_message(&mut stream);
+                    if data.is_ok() {
+                        peer.got_answer();
+                    }
+                    data
                 }
             }
         };
 
         if data.is_ok() {
             let data = data.unwrap();
+            peers.count_traffic(&event.token(), data.len() + 4, 0);
             match Message::from_bytes(data) {
                 Ok(message) => {
                     //let m = format!("{:?}", &message);
-                    let new_state = handle_message(Arc::clone(&context), message, peers, &event.token());
+                    let new_state = handle_message(Arc::clone(&context), message, peers, sync, &event.token());
                     let peer = peers.get_mut_peer(&event.token()).unwrap();
                     //debug!("Got message from {}: {:?}", &peer.get_addr(), &m);
                     let stream = peer.get_stream();
@@ -271,7 +362,10 @@ fn handle_connection_event(context: Arc<Mutex<Context>>, peers: &mut Peers, regi
                         }
                     }
                 }
-                Err(_) => { return false; }
+                Err(_) => {
+                    peers.punish(&event.token(), Offense::Malformed);
+                    return false;
+                }
             }
         } else {
             return false;
@@ -281,6 +375,7 @@ fn handle_connection_event(context: Arc<Mutex<Context>>, peers: &mut Peers, regi
     if event.is_writable() {
         //trace!("Socket {} is writable", event.token().0);
         let my_id = peers.get_my_id().to_owned();
+        let mut sent = 0;
         match peers.get_mut_peer(&event.token()) {
             None => {}
             Some(peer) => {
@@ -292,23 +387,24 @@ fn handle_connection_event(context: Arc<Mutex<Context>>, peers: &mut Peers, regi
                             let message = Message::hand(&c.app_version, &c.settings.origin, CHAIN_VERSION, c.settings.net.public, &my_id);
                             serde_json::to_string(&message).unwrap()
                         };
-                        send_message(peer.get_stream(), &data.into_bytes()).unwrap_or_else(|e| warn!("Error sending hello {}", e));
+                        sent += send_message(peer.get_stream(), &data.into_bytes()).unwrap_or_else(|e| { warn!("Error sending hello {}", e); 0 });
                         //debug!("Sent hello to {}", &peer.get_addr());
                     }
                     State::Message { data } => {
                         //debug!("Sending data to {}: {}", &peer.get_addr(), &String::from_utf8(data.clone()).unwrap());
-                        send_message(peer.get_stream(), &data).unwrap_or_else(|e| warn!("Error sending message {}", e));
+                        sent += send_message(peer.get_stream(), &data).unwrap_or_else(|e| { warn!("Error sending message {}", e); 0 });
                     }
                     State::Connected => {}
                     State::Idle { from } => {
                         debug!("Odd version of pings :)");
                         if from.elapsed().as_secs() >= 30 {
                             let data: String = {
-                                let c = context.lock().unwrap();
-                                let message = Message::ping(c.chain.get_height(), c.chain.get_last_hash());
+                                let (height, hash) = get_height_and_hash(&context.lock().unwrap());
+                                let message = Message::ping(height, hash);
                                 serde_json::to_string(&message).unwrap()
                             };
-                            send_message(peer.get_stream(), &data.into_bytes()).unwrap_or_else(|e| warn!("Error sending ping {}", e));
+                            sent += send_message(peer.get_stream(), &data.into_bytes()).unwrap_or_else(|e| { warn!("Error sending ping {}", e); 0 });
+                            peer.set_ping_sent();
                         }
                     }
                     State::Error => {}
@@ -317,16 +413,17 @@ fn handle_connection_event(context: Arc<Mutex<Context>>, peers: &mut Peers, regi
                     State::Loop => {}
                     State::SendLoop => {
                         let data = serde_json::to_string(&Message::Loop).unwrap();
-                        send_message(peer.get_stream(), &data.into_bytes()).unwrap_or_else(|e| warn!("Error sending loop {}", e));
+                        sent += send_message(peer.get_stream(), &data.into_bytes()).unwrap_or_else(|e| { warn!("Error sending loop {}", e); 0 });
                     }
                     State::Twin => {
                         let data = serde_json::to_string(&Message::Twin).unwrap();
-                        send_message(peer.get_stream(), &data.into_bytes()).unwrap_or_else(|e| warn!("Error sending loop {}", e));
+                        sent += send_message(peer.get_stream(), &data.into_bytes()).unwrap_or_else(|e| { warn!("Error sending loop {}", e); 0 });
                     }
                 }
                 registry.reregister(peer.get_stream(), event.token(), Interest::READABLE).unwrap();
             }
         }
+        peers.count_traffic(&event.token(), 0, sent);
     }
 
     true
@@ -383,17 +480,20 @@ fn read_message(stream: &mut TcpStream) -> Result<Vec<u8>, ()> {
     }
 }
 
-fn send_message(connection: &mut TcpStream, data: &Vec<u8>) -> io::Result<()> {
+/// Sends the message with its length, returns the number of bytes sent
+fn send_message(connection: &mut TcpStream, data: &Vec<u8>) -> io::Result<usize> {
     connection.write_u32::<BigEndian>(data.len() as u32)?;
     connection.write_all(&data)?;
-    connection.flush()
+    connection.flush()?;
+    Ok(data.len() + 4)
 }
 
-fn handle_message(context: Arc<Mutex<Context>>, message: Message, peers: &mut Peers, token: &Token) -> State {
-    let (my_height, my_hash, my_origin, my_version) = {
+fn handle_message(context: Arc<Mutex<Context>>, message: Message, peers: &mut Peers, sync: &mut HeadersSync, token: &Token) -> State {
+    let (my_height, my_hash, my_origin, my_version, light) = {
         let context = context.lock().unwrap();
         // TODO cache it somewhere
-        (context.chain.get_height(), context.chain.get_last_hash(), &context.settings.origin.clone(), CHAIN_VERSION)
+        let (height, hash) = get_height_and_hash(&context);
+        (height, hash, &context.settings.origin.clone(), CHAIN_VERSION, context.light.clone())
     };
     let answer = match message {
         Message::Hand { app_version, origin, version, public, rand} => {
@@ -410,13 +510,13 @@ fn handle_message(context: Arc<Mutex<Context>>, message: Message, peers: &mut Pe
                     State::message(Message::shake(&app_version, &origin, version, true, my_height))
                 } else {
                     warn!("Handshake from unsupported chain or version");
-                    State::Banned
+                    punish(peers, token, Offense::WrongChain, State::Error)
                 }
             }
         }
         Message::Shake { app_version, origin, version, ok, height } => {
             if origin.ne(my_origin) || version != my_version {
-                return State::Banned;
+                return punish(peers, token, Offense::WrongChain, State::Error);
             }
             if ok {
                 let nodes = peers.get_peers_active_count();
@@ -438,7 +538,7 @@ fn handle_message(context: Arc<Mutex<Context>>, message: Message, peers: &mut Pe
                     State::idle()
                 }
             } else {
-                State::Banned
+                punish(peers, token, Offense::WrongChain, State::Error)
             }
         }
         Message::Error => { State::Error }
@@ -510,13 +610,93 @@ fn handle_message(context: Arc<Mutex<Context>>, message: Message, peers: &mut Pe
             peer.set_active(true);
             let block: Block = match serde_json::from_str(&block) {
                 Ok(block) => block,
-                Err(_) => return State::Banned
+                Err(_) => return punish(peers, token, Offense::Malformed, State::idle())
             };
             if index != block.index {
-                return State::Banned;
+                return punish(peers, token, Offense::Malformed, State::idle());
             }
             info!("Received block {} with hash {:?}", block.index, &block.hash);
-            handle_block(context, peers, token, block)
+            match light {
+                Some(light) => handle_light_block(context, &light, peers, token, block),
+                None if sync.expects(block.index) => handle_sync_block(context, peers, sync, token, block),
+                None => handle_block(context, peers, token, block)
+            }
+        }
+        Message::GetDomain { identity, to } => {
+            let peer = peers.get_mut_peer(token).unwrap();
+            peer.set_active(true);
+            let context = context.lock().unwrap();
+            let proof = context.chain.get_domain_proof(&identity, to).map(|proof| serde_json::to_string(&proof).unwrap());
+            State::message(Message::Domain { identity, proof })
+        }
+        Message::GetProof { from, to } => {
+            let peer = peers.get_mut_peer(token).unwrap();
+            peer.set_active(true);
+            let context = context.lock().unwrap();
+            let proof = context.chain.get_chain_proof(from, to).map(|proof| serde_json::to_string(&proof).unwrap());
+            State::message(Message::Proof { from, to, proof })
+        }
+        Message::Domain { identity, proof } => {
+            let peer = peers.get_mut_peer(token).unwrap();
+            peer.set_active(true);
+            // Full nodes don't ask for domains, they have them all
+            if let Some(light) = light {
+                light.handle_domain(identity, proof);
+            }
+            State::idle()
+        }
+        Message::Proof { .. } => {
+            State::idle()
+        }
+        Message::Transaction { transaction } => {
+            let peer = peers.get_mut_peer(token).unwrap();
+            peer.set_active(true);
+            let addr = peer.get_addr().ip();
+            let transaction: Transaction = match serde_json::from_str(&transaction) {
+                Ok(transaction) => transaction,
+                Err(_) => return punish(peers, token, Offense::Malformed, State::idle())
+            };
+            // Light nodes can't check transactions, so they don't relay them
+            if light.is_none() {
+                let mut context = context.lock().unwrap();
+                let now = Utc::now().timestamp();
+                match check_transaction(&context.chain, &transaction, now) {
+                    Ok(()) => {
+                        if context.mempool.add(transaction, now) {
+                            debug!("Got transaction for mempool from {}", addr);
+                        }
+                    }
+                    Err(e) => debug!("Ignoring transaction from {}: {}", addr, e)
+                }
+            }
+            State::idle()
+        }
+        Message::GetHeaders { from, count } => {
+            let peer = peers.get_mut_peer(token).unwrap();
+            peer.set_active(true);
+            let context = context.lock().unwrap();
+            let headers = context.chain.get_blocks(from, count.min(MAX_HEADERS), false)
+                .iter()
+                .map(Header::from_block)
+                .collect();
+            State::message(Message::Headers { from, headers })
+        }
+        Message::Headers { from, headers } => {
+            let peer = peers.get_mut_peer(token).unwrap();
+            peer.set_active(true);
+            if light.is_some() {
+                return State::idle();
+            }
+            let (prev_hash, min_difficulty) = {
+                let context = context.lock().unwrap();
+                let params = context.chain.get_params();
+                let prev_hash = context.chain.get_block(from.saturating_sub(1)).map(|block| block.hash);
+                (prev_hash, params.signer_difficulty.min(params.zone_min_difficulty))
+            };
+            match sync.add_headers(*token, from, prev_hash, headers, min_difficulty) {
+                Some(_) => State::idle(),
+                None => punish(peers, token, Offense::Malformed, State::idle())
+            }
         }
         Message::Twin => { State::Twin }
         Message::Loop => { State::Loop }
@@ -524,6 +704,49 @@ fn handle_message(context: Arc<Mutex<Context>>, message: Message, peers: &mut Pe
     answer
 }
 
+/// Keeps the block of headers-first sync, adds all blocks that are ready, and asks the next one from the same peer
+fn handle_sync_block(context: Arc<Mutex<Context>>, peers: &mut Peers, sync: &mut HeadersSync, token: &Token, block: Block) -> State {
+    let index = block.index;
+    if let Err(supplier) = sync.add_block(*token, &block) {
+        sync.reset();
+        if !check_block_hash(&block) {
+            debug!("Block {} has wrong hash", index);
+            return punish(peers, token, Offense::BadBlock, State::idle());
+        }
+        // The block is real, so the header was made up or it is from a fork, and the rest of headers is not trusted
+        warn!("Block {} doesn't match its header, dropping headers", index);
+        if peers.punish(&supplier, Offense::Malformed) && supplier == *token {
+            return State::Banned;
+        }
+        return handle_block(context, peers, token, block);
+    }
+    let mut state = State::idle();
+    let height = context.lock().unwrap().chain.get_height();
+    for (block, from) in sync.take_ready(height) {
+        let index = block.index;
+        // The peer that has sent this block can be gone already, then the block is checked as if it came from this one
+        let from = if peers.get_peer(&from).is_some() { from } else { *token };
+        let result = handle_block(Arc::clone(&context), peers, &from, block);
+        if &from == token {
+            state = result;
+        }
+        if context.lock().unwrap().chain.get_height() < index {
+            // Block was bad or forked, the rest is asked one by one
+            sync.reset();
+            return state;
+        }
+    }
+    if !state.is_idle() {
+        return state;
+    }
+    let height = context.lock().unwrap().chain.get_height();
+    let peer_height = peers.get_peer(token).map(|peer| peer.get_height()).unwrap_or(0);
+    match sync.next_request(*token, peer_height, height) {
+        Some(index) => State::message(Message::GetBlock { index }),
+        None => state
+    }
+}
+
 fn handle_block(context: Arc<Mutex<Context>>, peers: &mut Peers, token: &Token, block: Block) -> State {
     let peers_count = peers.get_peers_active_count();
     let peer = peers.get_mut_peer(token).unwrap();
@@ -531,21 +754,30 @@ fn handle_block(context: Arc<Mutex<Context>>, peers: &mut Peers, token: &Token,
     if let Some(transaction) = &block.transaction {
         if context.lock().unwrap().x_zones.has_hash(&transaction.identity.to_string()) {
             // This peer has mined some of the forbidden zones
-            return State::Banned;
+            return punish(peers, token, Offense::BadBlock, State::idle());
         }
     }
 
     let mut context = context.lock().unwrap();
+    if context.disk.is_critical() {
+        debug!("Not enough disk space, ignoring block {}", block.index);
+        return State::idle();
+    }
     let max_height = context.chain.max_height();
     match context.chain.check_new_block(&block) {
         BlockQuality::Good => {
             let zone = matches!(Transaction::get_type(&block.transaction), TransactionType::Zone);
+            let domain = block.transaction.as_ref().and_then(|t| t.get_domain_data().map(|data| (data.zone, t.identity.clone())));
             context.chain.add_block(block);
+            context.update_signers();
             let my_height = context.chain.get_height();
             context.bus.post(crate::event::Event::BlockchainChanged { index: my_height });
             if zone {
                 context.bus.post(crate::event::Event::ZonesChanged);
             }
+            if let Some((zone, identity)) = domain {
+                context.bus.post(crate::event::Event::DomainChanged { zone, identity, serial: my_height });
+            }
             // If it was the last block to sync
             if my_height == max_height {
                 context.bus.post(crate::event::Event::SyncFinished);
@@ -563,23 +795,36 @@ fn handle_block(context: Arc<Mutex<Context>>, peers: &mut Peers, token: &Token,
             let height = context.chain.get_height();
             context.chain.update_max_height(height);
             context.bus.post(crate::event::Event::SyncFinished);
-            return State::Banned;
+            return punish(peers, token, get_block_offense(&context, &block), State::idle());
         }
         BlockQuality::Rewind => {
             debug!("Got some orphan block, requesting its parent");
-            return State::message(Message::GetBlock { index: block.index - 1 });
+            let index = block.index - 1;
+            // It can be the top of a better branch, that will be resolved when its first block comes
+            context.chain.add_branch_block(block);
+            return State::message(Message::GetBlock { index });
         }
         BlockQuality::Fork => {
             debug!("Got forked block {} with hash {:?}", block.index, block.hash);
-            let last_block = context.chain.last_block().unwrap();
-            if block.is_better_than(&last_block) {
-                let zone = matches!(Transaction::get_type(&block.transaction), TransactionType::Zone);
-                context.chain.replace_block(block).expect("Error replacing block with fork");
-                let index = context.chain.get_height();
-                context.bus.post(crate::event::Event::BlockchainChanged { index });
-                if zone {
-                    context.bus.post(crate::event::Event::ZonesChanged);
+            match context.chain.reorg(block) {
+                Ok(Some(reorg)) => {
+                    context.update_signers();
+                    let index = context.chain.get_height();
+                    context.bus.post(crate::event::Event::ChainReorged { index: reorg.index, removed: reorg.removed.len() as u64, added: reorg.added.len() as u64 });
+                    context.bus.post(crate::event::Event::BlockchainChanged { index });
+                    // Domains and zones of both branches have changed
+                    let blocks = reorg.removed.iter().chain(reorg.added.iter());
+                    if blocks.clone().any(|block| matches!(Transaction::get_type(&block.transaction), TransactionType::Zone)) {
+                        context.bus.post(crate::event::Event::ZonesChanged);
+                    }
+                    for transaction in blocks.filter_map(|block| block.transaction.as_ref()) {
+                        if let Some(data) = transaction.get_domain_data() {
+                            context.bus.post(crate::event::Event::DomainChanged { zone: data.zone, identity: transaction.identity.clone(), serial: index });
+                        }
+                    }
                 }
+                Ok(None) => {}
+                Err(e) => error!("Error switching to the forked branch: {}", e)
             }
             let height = context.chain.get_height();
             context.chain.update_max_height(height);
@@ -589,6 +834,62 @@ fn handle_block(context: Arc<Mutex<Context>>, peers: &mut Peers, token: &Token,
     State::idle()
 }
 
+/// Light nodes keep only block headers and check them without transactions
+fn handle_light_block(context: Arc<Mutex<Context>>, light: &LightClient, peers: &mut Peers, token: &Token, block: Block) -> State {
+    let peers_count = peers.get_peers_active_count();
+    let peer = peers.get_mut_peer(token).unwrap();
+    peer.set_received_block(block.index);
+
+    let mut context = context.lock().unwrap();
+    let origin = context.settings.get_origin();
+    match light.add_block(&block, &origin) {
+        BlockQuality::Good | BlockQuality::Fork => {
+            let (my_height, _) = light.get_height_and_hash();
+            let max_height = context.chain.max_height();
+            if my_height >= max_height {
+                context.bus.post(crate::event::Event::SyncFinished);
+            } else {
+                context.bus.post(crate::event::Event::Syncing { have: my_height, height: max_height });
+            }
+            context.bus.post(crate::event::Event::NetworkStatus { nodes: peers_count, blocks: my_height });
+        }
+        BlockQuality::Twin | BlockQuality::Future => { debug!("Ignoring block {}", block.index); }
+        BlockQuality::Bad => {
+            debug!("Ignoring bad block from {}:\n{:?}", peer.get_addr(), &block);
+            return punish(peers, token, get_block_offense(&context, &block), State::idle());
+        }
+        BlockQuality::Rewind => {
+            debug!("Got some orphan block, requesting its parent");
+            return State::message(Message::GetBlock { index: block.index - 1 });
+        }
+    }
+    State::idle()
+}
+
+/// Adds the offense to the score of the peer, gives `Banned` state if it is enough for a ban, or `otherwise`
+fn punish(peers: &mut Peers, token: &Token, offense: Offense, otherwise: State) -> State {
+    match peers.punish(token, offense) {
+        true => State::Banned,
+        false => otherwise
+    }
+}
+
+/// Blocks from the future can be a clock problem, so they are not punished as hard as other bad blocks
+fn get_block_offense(context: &Context, block: &Block) -> Offense {
+    match block.timestamp > Utc::now().timestamp() + context.chain.get_params().max_future_time {
+        true => Offense::Timestamp,
+        false => Offense::BadBlock
+    }
+}
+
+/// Light nodes have only block headers, so the height is taken from them
+fn get_height_and_hash(context: &Context) -> (u64, Bytes) {
+    match &context.light {
+        Some(light) => light.get_height_and_hash(),
+        None => (context.chain.get_height(), context.chain.get_last_hash())
+    }
+}
+
 fn would_block(err: &io::Error) -> bool {
     err.kind() == io::ErrorKind::WouldBlock
 }
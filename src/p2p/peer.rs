@@ -1,7 +1,9 @@
 use std::net::SocketAddr;
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use mio::net::TcpStream;
 use crate::p2p::State;
+use crate::p2p::metrics::Transport;
 use crate::Block;
 
 #[derive(Debug)]
@@ -17,7 +19,11 @@ pub struct Peer {
     reconnects: u32,
     spurious: u32,
     received_block: u64,
-    fork: HashMap<u64, Block>
+    fork: HashMap<u64, Block>,
+    /// When the last ping (or GetPeers) was sent, if it is not answered yet
+    ping_sent: Option<Instant>,
+    /// Smoothed round trip time of pings
+    latency: Option<Duration>
 }
 
 impl Peer {
@@ -34,7 +40,9 @@ impl Peer {
             reconnects: 0,
             spurious: 0,
             received_block: 0,
-            fork: HashMap::new()
+            fork: HashMap::new(),
+            ping_sent: None,
+            latency: None
         }
     }
 
@@ -66,6 +74,10 @@ impl Peer {
         self.height = height;
     }
 
+    pub fn get_height(&self) -> u64 {
+        self.height
+    }
+
     pub fn is_higher(&self, height: u64) -> bool {
         self.height > height
     }
@@ -147,6 +159,42 @@ impl Peer {
         &self.fork
     }
 
+    pub fn clear_fork(&mut self) {
+        self.fork.clear();
+    }
+
+    /// Estimated bytes that this peer keeps: message to send and blocks of its fork
+    pub fn get_buffers_size(&self) -> usize {
+        let message = match &self.state {
+            State::Message { data } => data.len(),
+            _ => 0
+        };
+        message + self.fork.values().map(|block| block.as_bytes().len()).sum::<usize>()
+    }
+
+    pub fn get_transport(&self) -> Transport {
+        Transport::from_addr(&self.addr.ip())
+    }
+
+    pub fn set_ping_sent(&mut self) {
+        self.ping_sent = Some(Instant::now());
+    }
+
+    /// Any message after ping is an answer to it, Pong or GetBlock, so it gives us round trip time
+    pub fn got_answer(&mut self) {
+        if let Some(sent) = self.ping_sent.take() {
+            let time = sent.elapsed();
+            self.latency = match self.latency {
+                None => Some(time),
+                Some(latency) => Some((latency * 3 + time) / 4)
+            };
+        }
+    }
+
+    pub fn get_latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
     /// If loopback address then we care about ip and port.
     /// If regular address then we only care about the ip and ignore the port.
     pub fn equals(&self, addr: &SocketAddr) -> bool {
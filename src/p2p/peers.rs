@@ -12,8 +12,14 @@ use rand::seq::IteratorRandom;
 use crate::{Bytes, commons};
 use crate::commons::*;
 use crate::p2p::{Message, Peer, State};
+use crate::p2p::bans::{BanList, Offense};
+use crate::p2p::known::KnownPeers;
+use crate::p2p::sync::{HeadersSync, MAX_HEADERS};
+use crate::p2p::metrics::{NetworkMetrics, Transport};
+use crate::memory::PEER_BUFFERS;
 use crate::commons::next;
 use std::io;
+use std::time::Duration;
 
 const PING_PERIOD: u64 = 30;
 
@@ -21,18 +27,30 @@ pub struct Peers {
     peers: HashMap<Token, Peer>,
     new_peers: Vec<SocketAddr>,
     ignored: HashSet<IpAddr>,
+    /// Misbehavior scores and temporary bans
+    bans: BanList,
     my_id: String,
+    /// Port that nodes of this network listen on
+    port: u16,
     behind_ping_sent_time: i64,
+    /// Bytes received and sent over every transport
+    traffic: NetworkMetrics,
+    /// Good peers, that we have talked to, they are saved to file
+    known: KnownPeers,
 }
 
 impl Peers {
-    pub fn new() -> Self {
+    pub fn new(port: u16, peers_file: &str) -> Self {
         Peers {
             peers: HashMap::new(),
             new_peers: Vec::new(),
             ignored: HashSet::new(),
+            bans: BanList::default(),
             my_id: commons::random_string(6),
-            behind_ping_sent_time: 0
+            port,
+            behind_ping_sent_time: 0,
+            traffic: NetworkMetrics::default(),
+            known: KnownPeers::load(peers_file)
         }
     }
 
@@ -70,7 +88,6 @@ impl Peers {
                     }
                     State::Banned => {
                         info!("Peer connection {} to {:?} has shut down, banned", &token.0, &peer.get_addr());
-                        self.ignored.insert(peer.get_addr().ip().clone());
                     }
                     State::Offline { .. } => {
                         info!("Peer connection {} to {:?} is offline", &token.0, &peer.get_addr());
@@ -135,7 +152,7 @@ impl Peers {
                 continue;
             }
 
-            if self.ignored.contains(&addr.ip()) {
+            if self.is_ignored(&addr.ip()) {
                 info!("Skipping ignored address from exchange: {}", &addr);
                 continue;
             }
@@ -157,7 +174,21 @@ impl Peers {
     }
 
     pub fn is_ignored(&self, addr: &IpAddr) -> bool {
-        self.ignored.contains(addr)
+        self.ignored.contains(addr) || self.bans.is_banned(addr)
+    }
+
+    /// Adds score of the offense to the peer, returns true if its address is banned for that
+    pub fn punish(&mut self, token: &Token, offense: Offense) -> bool {
+        match self.peers.get_mut(token) {
+            Some(peer) => {
+                let banned = self.bans.punish(&peer.get_addr().ip(), offense);
+                if banned {
+                    peer.set_state(State::Banned);
+                }
+                banned
+            }
+            None => false
+        }
     }
 
     pub fn get_peers_for_exchange(&self, peer_address: &SocketAddr) -> Vec<String> {
@@ -170,15 +201,34 @@ impl Peers {
                 continue;
             }
             if peer.is_public() && peer.active() {
-                result.push(SocketAddr::new(peer.get_addr().ip(), LISTEN_PORT).to_string());
+                result.push(SocketAddr::new(peer.get_addr().ip(), self.port).to_string());
             }
             if result.len() >= 10 {
                 break;
             }
         }
+        // Peers that we have talked to before, but are not connected to now
+        for address in self.known.get_addresses() {
+            if result.len() >= 10 {
+                break;
+            }
+            let asking = address.parse::<SocketAddr>().map_or(false, |address| address.ip() == peer_address.ip());
+            if !asking && !result.contains(&address) {
+                result.push(address);
+            }
+        }
         result
     }
 
+    /// Gets addresses of good peers from previous runs, to connect to them without waiting for bootstrap nodes
+    pub fn get_known_peers(&self) -> Vec<String> {
+        self.known.get_addresses()
+    }
+
+    pub fn save_known_peers(&self) {
+        self.known.save();
+    }
+
     pub fn get_peers_count(&self) -> usize {
         self.peers.len()
     }
@@ -193,8 +243,39 @@ impl Peers {
         count
     }
 
+    /// Counts bytes received from and sent to the peer, for metrics of its transport
+    pub fn count_traffic(&mut self, token: &Token, received: usize, sent: usize) {
+        if let Some(peer) = self.peers.get(token) {
+            let stats = self.traffic.get_mut(peer.get_transport());
+            stats.bytes_in += received as u64;
+            stats.bytes_out += sent as u64;
+        }
+    }
+
+    /// Gets counts, traffic and average latency of active peers for every transport
+    pub fn get_metrics(&self) -> NetworkMetrics {
+        let mut metrics = self.traffic.clone();
+        metrics.scores = self.bans.get_scores();
+        let mut latencies: HashMap<Transport, Vec<Duration>> = HashMap::new();
+        for peer in self.peers.values().filter(|peer| peer.active()) {
+            let stats = metrics.get_mut(peer.get_transport());
+            stats.nodes += 1;
+            if peer.is_inbound() {
+                stats.inbound += 1;
+            }
+            if let Some(latency) = peer.get_latency() {
+                latencies.entry(peer.get_transport()).or_default().push(latency);
+            }
+        }
+        for (transport, times) in latencies {
+            let sum: Duration = times.iter().sum();
+            metrics.get_mut(transport).latency_ms = Some((sum / times.len() as u32).as_millis() as u64);
+        }
+        metrics
+    }
+
     pub fn get_peers_banned_count(&self) -> usize {
-        self.ignored.len()
+        self.ignored.len() + self.bans.get_banned_count()
     }
 
     pub fn ignore_peer(&mut self, registry: &Registry, token: &Token) {
@@ -203,8 +284,12 @@ impl Peers {
             peer.set_state(State::Banned);
         }
         let ip = peer.get_addr().ip().clone();
+        // Banned addresses are ignored only until their ban ends
+        let temporary = self.bans.is_banned(&ip);
         self.close_peer(registry, token);
-        self.ignored.insert(ip);
+        if !temporary {
+            self.ignored.insert(ip);
+        }
         match self.peers
             .iter()
             .find(|(_, p)| p.get_addr().ip() == ip)
@@ -231,9 +316,39 @@ impl Peers {
         false
     }
 
-    pub fn update(&mut self, registry: &Registry, height: u64, hash: Bytes) {
+    /// Counts memory of peer buffers, and drops forks if they take more than the budget
+    fn update_buffers(&mut self) {
+        let mut used = self.peers.values().map(Peer::get_buffers_size).sum();
+        let budget = PEER_BUFFERS.get_budget();
+        if budget > 0 && used > budget && self.peers.values().any(|peer| !peer.get_fork().is_empty()) {
+            warn!("Peer buffers take {} KB, more than the budget of {} KB, dropping forks", used / 1024, budget / 1024);
+            for peer in self.peers.values_mut() {
+                peer.clear_fork();
+            }
+            used = self.peers.values().map(Peer::get_buffers_size).sum();
+        }
+        PEER_BUFFERS.set_used(used);
+    }
+
+    /// New connections are not accepted while peer buffers take more memory than their budget
+    pub fn is_over_budget(&self) -> bool {
+        let budget = PEER_BUFFERS.get_budget();
+        budget > 0 && PEER_BUFFERS.get_used() > budget
+    }
+
+    pub fn update(&mut self, registry: &Registry, height: u64, hash: Bytes, headers_sync: bool) {
         let nodes = self.get_peers_active_count();
 
+        self.bans.update();
+        self.update_buffers();
+
+        let now = Utc::now().timestamp();
+        for (_, peer) in self.peers.iter() {
+            if peer.is_public() && peer.active() && !peer.disabled() {
+                self.known.seen(SocketAddr::new(peer.get_addr().ip(), self.port), now);
+            }
+        }
+
         let random_time = random::<u64>() % PING_PERIOD;
         for (token, peer) in self.peers.iter_mut() {
             match peer.get_state() {
@@ -247,6 +362,7 @@ impl Peers {
                         };
 
                         peer.set_state(State::message(message));
+                        peer.set_ping_sent();
                         let stream = peer.get_stream();
                         registry.reregister(stream, token.clone(), Interest::WRITABLE).unwrap();
                     }
@@ -255,8 +371,8 @@ impl Peers {
             }
         }
 
-        // If someone has more blocks we sync
-        {
+        // If someone has more blocks we sync, unless headers-first sync asks them from all peers
+        if !headers_sync {
             let mut rng = rand::thread_rng();
             match self.peers
                 .iter_mut()
@@ -283,6 +399,7 @@ impl Peers {
                     debug!("Peer {} is behind, sending ping", &peer.get_addr().ip());
                     registry.reregister(peer.get_stream(), token.clone(), Interest::WRITABLE).unwrap();
                     peer.set_state(State::message(Message::Ping { height, hash }));
+                    peer.set_ping_sent();
                     self.update_behind_ping_time();
                 }
             }
@@ -317,6 +434,57 @@ impl Peers {
         }
     }
 
+    /// Sends message to some random active peer, that is not busy, returns false if there is no such peer
+    pub fn send_to_random_peer(&mut self, registry: &Registry, message: Message) -> bool {
+        let mut rng = rand::thread_rng();
+        match self.peers
+            .iter_mut()
+            .filter(|(_, peer)| peer.active() && peer.get_state().is_idle())
+            .choose(&mut rng) {
+            None => false,
+            Some((token, peer)) => {
+                registry.reregister(peer.get_stream(), token.clone(), Interest::WRITABLE).unwrap();
+                peer.set_state(State::message(message));
+                true
+            }
+        }
+    }
+
+    /// Gets the biggest height of active peers
+    pub fn get_best_height(&self) -> u64 {
+        self.peers.values()
+            .filter(|peer| peer.active())
+            .map(|peer| peer.get_height())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Asks headers from some random idle peer, that has blocks from `from`, returns its token
+    pub fn request_headers(&mut self, registry: &Registry, from: u64) -> Option<Token> {
+        let mut rng = rand::thread_rng();
+        let (token, peer) = self.peers
+            .iter_mut()
+            .filter(|(_, peer)| peer.active() && peer.get_state().is_idle() && peer.get_height() >= from)
+            .choose(&mut rng)?;
+        debug!("Requesting headers from {} from {}", from, &peer.get_addr().ip());
+        registry.reregister(peer.get_stream(), token.clone(), Interest::WRITABLE).unwrap();
+        peer.set_state(State::message(Message::GetHeaders { from, count: MAX_HEADERS }));
+        Some(*token)
+    }
+
+    /// Asks blocks of headers-first sync from all idle peers that have them, one block from every peer
+    pub fn request_blocks(&mut self, registry: &Registry, sync: &mut HeadersSync, height: u64) {
+        for (token, peer) in self.peers.iter_mut() {
+            if !peer.active() || !peer.get_state().is_idle() {
+                continue;
+            }
+            if let Some(index) = sync.next_request(*token, peer.get_height(), height) {
+                registry.reregister(peer.get_stream(), token.clone(), Interest::WRITABLE).unwrap();
+                peer.set_state(State::message(Message::GetBlock { index }));
+            }
+        }
+    }
+
     pub fn connect_new_peers(&mut self, registry: &Registry, unique_token: &mut Token, yggdrasil_only: bool) {
         if self.new_peers.is_empty() {
             return;
@@ -369,7 +537,7 @@ impl Peers {
     }
 
     fn connect_peer(&mut self, addr: &SocketAddr, registry: &Registry, unique_token: &mut Token, yggdrasil_only: bool) -> io::Result<()> {
-        if self.ignored.contains(&addr.ip()) {
+        if self.is_ignored(&addr.ip()) {
             return Err(io::Error::from(io::ErrorKind::ConnectionAborted));
         }
         if yggdrasil_only && !is_yggdrasil(&addr.ip()) {
@@ -0,0 +1,75 @@
+//! One-shot requests to peers over blocking sockets, for command line tools that don't start the whole network.
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use crate::{commons, Block, CHAIN_VERSION, MAX_PACKET_SIZE};
+use crate::p2p::Message;
+
+/// Time to connect to a peer and to get the answer from it
+const QUERY_TIMEOUT_SEC: u64 = 10;
+
+/// Resolves addresses of peers from settings, skipping the ones that can't be resolved
+pub fn resolve_peers(peers: &[String]) -> Vec<SocketAddr> {
+    let mut result = Vec::new();
+    for peer in peers.iter().filter(|peer| !peer.is_empty()) {
+        match peer.to_socket_addrs() {
+            Ok(addresses) => result.extend(addresses),
+            Err(e) => warn!("Can't resolve address {}: {}", peer, e)
+        }
+    }
+    result.dedup();
+    result
+}
+
+/// Gets block with this index from a peer of the chain with this origin
+pub fn fetch_block(addr: &SocketAddr, app_version: &str, origin: &str, index: u64) -> Result<Block, String> {
+    let timeout = Duration::from_secs(QUERY_TIMEOUT_SEC);
+    let mut stream = TcpStream::connect_timeout(addr, timeout).map_err(|e| e.to_string())?;
+    stream.set_read_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+    send(&mut stream, &Message::hand(app_version, origin, CHAIN_VERSION, false, &commons::random_string(6)))?;
+    let started = Instant::now();
+    while started.elapsed() < timeout {
+        match receive(&mut stream)? {
+            Message::Shake { origin: their_origin, ok, .. } => {
+                if !ok || their_origin != origin {
+                    return Err(String::from("Peer is of another chain"));
+                }
+                send(&mut stream, &Message::GetBlock { index })?;
+            }
+            Message::Block { index: their_index, block } if their_index == index => {
+                let block: Block = serde_json::from_str(&block).map_err(|_| String::from("Malformed block"))?;
+                if block.index != index {
+                    return Err(String::from("Malformed block"));
+                }
+                return Ok(block);
+            }
+            Message::Error | Message::Twin | Message::Loop => return Err(String::from("Peer has refused the connection")),
+            message => trace!("Ignoring message {:?} from {}", message, addr)
+        }
+    }
+    Err(String::from("Timed out"))
+}
+
+fn send(stream: &mut TcpStream, message: &Message) -> Result<(), String> {
+    let data = serde_json::to_vec(message).unwrap();
+    stream.write_u32::<BigEndian>(data.len() as u32)
+        .and_then(|_| stream.write_all(&data))
+        .and_then(|_| stream.flush())
+        .map_err(|e| e.to_string())
+}
+
+fn receive(stream: &mut TcpStream) -> Result<Message, String> {
+    let size = stream.read_u32::<BigEndian>().map_err(|e| e.to_string())? as usize;
+    if size == 0 || size > MAX_PACKET_SIZE {
+        return Err(String::from("Wrong message size"));
+    }
+    let mut data = vec![0u8; size];
+    stream.read_exact(&mut data).map_err(|e| e.to_string())?;
+    Message::from_bytes(data).map_err(|_| String::from("Malformed message"))
+}
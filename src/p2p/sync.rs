@@ -0,0 +1,242 @@
+//! Headers-first sync of full nodes. When some peer is far ahead, we get headers of its blocks first,
+//! check that they follow each other and have enough work in hashes, and then download blocks of these headers
+//! from all peers that have them, one block from every peer at a time. Blocks that come ahead of our height wait
+//! in memory, and are added to the chain in order, checked as usual.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use mio::Token;
+
+use crate::{Block, Bytes};
+use crate::blockchain::hash_utils::hash_difficulty;
+use crate::light::Header;
+
+/// How many headers are asked at once
+pub const MAX_HEADERS: u64 = 500;
+/// Headers-first sync starts if some peer is ahead by this many blocks, otherwise blocks are asked one by one
+pub const HEADERS_SYNC_MIN: u64 = 20;
+/// Requests without answer are given to other peers after this time
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+/// How far ahead of our height blocks are downloaded, they wait in memory until previous ones are added
+const DOWNLOAD_WINDOW: u64 = 256;
+
+#[derive(Default)]
+pub struct HeadersSync {
+    /// Headers with peers that have sent them, to blame them if blocks don't match
+    headers: BTreeMap<u64, (Header, Token)>,
+    /// Index of block -> the peer that was asked for it, and when
+    requested: HashMap<u64, (Token, Instant)>,
+    /// Blocks ahead of our height, with peers that have sent them
+    downloaded: BTreeMap<u64, (Block, Token)>,
+    /// Headers are asked from one peer at a time, from this index
+    headers_requested: Option<(Token, u64, Instant)>,
+}
+
+impl HeadersSync {
+    pub fn is_active(&self) -> bool {
+        !self.headers.is_empty()
+    }
+
+    /// Gets the index to ask headers from, if some peer is far ahead and headers are not asked already
+    pub fn need_headers(&self, height: u64, best_height: u64) -> Option<u64> {
+        if self.headers_requested.is_some() {
+            return None;
+        }
+        let last = self.headers.keys().next_back().cloned().unwrap_or(height).max(height);
+        match best_height >= last + HEADERS_SYNC_MIN || (self.is_active() && best_height > last) {
+            true => Some(last + 1),
+            false => None
+        }
+    }
+
+    pub fn set_headers_requested(&mut self, token: Token, from: u64) {
+        self.headers_requested = Some((token, from, Instant::now()));
+    }
+
+    /// Adds headers from the peer, that start from `from`. The first one must follow `prev_hash`, the hash of our block
+    /// before them, or the last header that we have. Returns how many are added, or None if these headers are wrong.
+    /// Headers that we haven't asked from this peer are ignored.
+    pub fn add_headers(&mut self, token: Token, from: u64, prev_hash: Option<Bytes>, headers: Vec<Header>, min_difficulty: u32) -> Option<usize> {
+        match self.headers_requested {
+            Some((asked, asked_from, _)) if asked == token && asked_from == from => self.headers_requested = None,
+            _ => {
+                debug!("Ignoring headers from {} that we haven't asked for", from);
+                return Some(0);
+            }
+        }
+        if from == 0 || headers.len() as u64 > MAX_HEADERS {
+            return None;
+        }
+        let mut prev_hash = match self.headers.get(&(from - 1)) {
+            Some((header, _)) => header.hash.clone(),
+            None => match prev_hash {
+                Some(hash) => hash,
+                None => return Some(0)
+            }
+        };
+        let mut index = from;
+        for header in &headers {
+            if header.index != index || hash_difficulty(&header.hash) < header.difficulty || header.difficulty < min_difficulty {
+                return None;
+            }
+            if header.prev_block_hash != prev_hash {
+                // The first one can be from a fork, it is resolved block by block, but others must follow each other
+                return if index == from { Some(0) } else { None };
+            }
+            prev_hash = header.hash.clone();
+            index += 1;
+        }
+        let count = headers.len();
+        for header in headers {
+            self.headers.insert(header.index, (header, token));
+        }
+        if count > 0 {
+            debug!("Got {} headers from {}, last one is {}", count, from, index - 1);
+        }
+        Some(count)
+    }
+
+    /// Gets the next block to ask from the peer that has blocks up to `peer_height`
+    pub fn next_request(&mut self, token: Token, peer_height: u64, height: u64) -> Option<u64> {
+        let last = (height + DOWNLOAD_WINDOW).min(peer_height);
+        let index = self.headers
+            .range(height + 1..=last)
+            .map(|(index, _)| *index)
+            .find(|index| !self.requested.contains_key(index) && !self.downloaded.contains_key(index))?;
+        self.requested.insert(index, (token, Instant::now()));
+        Some(index)
+    }
+
+    /// Tells if we wait for this block from headers-first sync
+    pub fn expects(&self, index: u64) -> bool {
+        self.requested.contains_key(&index)
+    }
+
+    /// Keeps the block until previous ones are added. If it doesn't match its header,
+    /// returns the peer that has sent the header, as the header can be made up.
+    pub fn add_block(&mut self, token: Token, block: &Block) -> Result<(), Token> {
+        self.requested.remove(&block.index);
+        match self.headers.get(&block.index) {
+            Some((header, _)) if header.hash == block.hash && header.prev_block_hash == block.prev_block_hash => {
+                self.downloaded.insert(block.index, (block.clone(), token));
+                Ok(())
+            }
+            Some((_, supplier)) => Err(*supplier),
+            None => Ok(())
+        }
+    }
+
+    /// Takes downloaded blocks that go right after our height
+    pub fn take_ready(&mut self, height: u64) -> Vec<(Block, Token)> {
+        let mut result = Vec::new();
+        let mut next = height + 1;
+        while let Some(entry) = self.downloaded.remove(&next) {
+            result.push(entry);
+            next += 1;
+        }
+        result
+    }
+
+    /// Forgets headers of added blocks, and requests that have timed out
+    pub fn update(&mut self, height: u64) {
+        self.headers = self.headers.split_off(&(height + 1));
+        self.downloaded = self.downloaded.split_off(&(height + 1));
+        self.requested.retain(|index, (_, time)| *index > height && time.elapsed() < REQUEST_TIMEOUT);
+        if matches!(self.headers_requested, Some((_, _, time)) if time.elapsed() >= REQUEST_TIMEOUT) {
+            self.headers_requested = None;
+        }
+    }
+
+    /// Stops the sync, after a bad block or a fork, blocks are asked one by one then
+    pub fn reset(&mut self) {
+        if self.is_active() {
+            info!("Stopping headers-first sync");
+        }
+        *self = HeadersSync::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_blocks(count: u64) -> Vec<Block> {
+        let mut blocks: Vec<Block> = Vec::new();
+        for index in 1..=count {
+            let prev = blocks.last().map(|b| b.hash.clone()).unwrap_or_default();
+            let mut block = Block::new(None, Bytes::default(), prev, 0);
+            block.index = index;
+            block.hash = Bytes::from_bytes(&[index as u8; 32]);
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    #[test]
+    fn headers_first() {
+        let blocks = make_blocks(30);
+        let headers: Vec<Header> = blocks.iter().map(Header::from_block).collect();
+        let mut sync = HeadersSync::default();
+        assert_eq!(None, sync.need_headers(5, 10));
+        assert_eq!(Some(6), sync.need_headers(5, 30));
+        sync.set_headers_requested(Token(1), 6);
+        assert_eq!(None, sync.need_headers(5, 30));
+
+        // Headers that don't follow each other are wrong
+        let mut broken = headers[5..].to_vec();
+        broken.swap(1, 2);
+        assert_eq!(None, sync.add_headers(Token(1), 6, Some(blocks[4].hash.clone()), broken, 0));
+        sync.set_headers_requested(Token(1), 6);
+        assert_eq!(Some(25), sync.add_headers(Token(1), 6, Some(blocks[4].hash.clone()), headers[5..].to_vec(), 0));
+        assert!(sync.is_active());
+
+        // Two peers download blocks in parallel
+        assert_eq!(Some(6), sync.next_request(Token(1), 30, 5));
+        assert_eq!(Some(7), sync.next_request(Token(2), 30, 5));
+        assert!(sync.expects(7));
+        assert_eq!(Ok(()), sync.add_block(Token(2), &blocks[6]));
+        assert!(sync.take_ready(5).is_empty());
+        let mut wrong = blocks[5].clone();
+        wrong.hash = Bytes::from_bytes(&[99u8; 32]);
+        assert_eq!(Err(Token(1)), sync.add_block(Token(1), &wrong));
+        assert_eq!(Ok(()), sync.add_block(Token(1), &blocks[5]));
+        let ready = sync.take_ready(5);
+        assert_eq!(vec![6, 7], ready.iter().map(|(block, _)| block.index).collect::<Vec<u64>>());
+
+        sync.update(7);
+        assert_eq!(Some(8), sync.next_request(Token(1), 30, 7));
+        sync.reset();
+        assert!(!sync.is_active());
+    }
+
+    #[test]
+    fn made_up_headers() {
+        let blocks = make_blocks(30);
+        let mut fake: Vec<Header> = blocks.iter().map(Header::from_block).collect();
+        // They link to each other after block 6
+        for header in fake.iter_mut().skip(6) {
+            header.hash = Bytes::from_bytes(&[header.index as u8 + 100; 32]);
+            if header.index > 7 {
+                header.prev_block_hash = Bytes::from_bytes(&[header.index as u8 + 99; 32]);
+            }
+        }
+        let mut sync = HeadersSync::default();
+
+        // Headers that nobody has asked for are ignored
+        assert_eq!(Some(0), sync.add_headers(Token(9), 6, Some(blocks[4].hash.clone()), fake[5..].to_vec(), 0));
+        sync.set_headers_requested(Token(9), 6);
+        assert_eq!(Some(0), sync.add_headers(Token(9), 7, Some(blocks[5].hash.clone()), fake[6..].to_vec(), 0));
+        assert!(!sync.is_active());
+
+        // Real blocks that don't match made up headers blame the peer that has sent headers, not blocks
+        sync.set_headers_requested(Token(9), 6);
+        assert_eq!(Some(25), sync.add_headers(Token(9), 6, Some(blocks[4].hash.clone()), fake[5..].to_vec(), 0));
+        assert_eq!(Some(6), sync.next_request(Token(1), 30, 5));
+        assert_eq!(Some(7), sync.next_request(Token(2), 30, 5));
+        assert_eq!(Ok(()), sync.add_block(Token(1), &blocks[5]));
+        assert_eq!(Err(Token(9)), sync.add_block(Token(2), &blocks[6]));
+    }
+}
@@ -0,0 +1,299 @@
+//! Finding out what program has taken the DNS port, when our server can't listen on it.
+//! The usual ones are local resolvers of the system, like systemd-resolved, dnsmasq of NetworkManager or libvirt,
+//! and Internet Connection Sharing on Windows, and they can be moved away from the port in known ways.
+
+use std::io;
+#[cfg(any(target_os = "linux", test))]
+use std::net::SocketAddr;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// Drop-in config of systemd-resolved that turns off its stub listener on 127.0.0.53:53
+#[cfg(target_os = "linux")]
+const RESOLVED_DROP_IN: &str = "/etc/systemd/resolved.conf.d/gis.conf";
+
+/// Programs that often take port 53 and what to do with them, names are lowercase and without `.exe`
+const KNOWN_OWNERS: &[(&str, &str)] = &[
+    ("systemd-resolved", "it is the local resolver of systemd, turn off its stub listener by `DNSStubListener=no` in /etc/systemd/resolved.conf and restart it, or run `gis --free-dns-port` to do that"),
+    ("dnsmasq", "it is often started by NetworkManager or libvirt, set `dns=none` in NetworkManager.conf, or `bind-interfaces` with other `listen-address` in dnsmasq.conf, or stop it"),
+    ("named", "BIND is running, stop it or make it listen on other addresses by `listen-on`"),
+    ("unbound", "Unbound is running, stop it or make it listen on other addresses by `interface:`"),
+    ("svchost", "it is usually Internet Connection Sharing, turn off Mobile hotspot or stop the service by `sc stop SharedAccess`"),
+    ("dns", "Windows DNS Server is running, stop it or make it listen on other addresses in DNS Manager"),
+    ("gis", "another GIS node is running already"),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PortOwner {
+    pub name: String,
+    pub pid: Option<u32>,
+}
+
+/// Explains why the DNS server can't listen on the address, if the port is taken by another program
+pub fn explain_bind_error(address: &str, udp: bool, error: &io::Error) -> Option<String> {
+    let port = address.rsplit(':').next()?.parse::<u16>().ok()?;
+    // Windows answers with access error for ports that are taken exclusively or reserved by Hyper-V
+    let taken = error.kind() == io::ErrorKind::AddrInUse || (cfg!(windows) && error.kind() == io::ErrorKind::PermissionDenied);
+    if !taken {
+        return None;
+    }
+    let protocol = if udp { "UDP" } else { "TCP" };
+    let result = match find_port_owner(port, udp) {
+        Some(owner) => {
+            let pid = owner.pid.map(|pid| format!(" (pid {})", pid)).unwrap_or_default();
+            match get_remedy(&owner.name) {
+                Some(remedy) => format!("{} port {} is taken by {}{}, {}", protocol, port, &owner.name, pid, remedy),
+                None => format!("{} port {} is taken by {}{}, stop it or change listen address of GIS", protocol, port, &owner.name, pid)
+            }
+        }
+        None if cfg!(windows) => format!("{} port {} is taken by another program or reserved, see `netstat -ano` and `netsh int ipv4 show excludedportrange protocol={}`", protocol, port, protocol.to_lowercase()),
+        None => format!("{} port {} is taken by another program, run as root to find out which one, or see `ss -lpn 'sport = :{}'`", protocol, port, port)
+    };
+    Some(result)
+}
+
+/// Gets the advice for the program that has taken the port, if it is known
+pub fn get_remedy(name: &str) -> Option<&'static str> {
+    let name = name.to_lowercase();
+    let name = name.trim_end_matches(".exe");
+    // Names of processes in Linux are cut to 15 characters
+    let name = if name == "systemd-resolve" { "systemd-resolved" } else { name };
+    KNOWN_OWNERS.iter().find(|(owner, _)| *owner == name).map(|(_, remedy)| *remedy)
+}
+
+/// Finds the program that listens on the port, by /proc
+#[cfg(target_os = "linux")]
+pub fn find_port_owner(port: u16, udp: bool) -> Option<PortOwner> {
+    use std::fs;
+
+    let tables: &[&str] = if udp { &["/proc/net/udp", "/proc/net/udp6"] } else { &["/proc/net/tcp", "/proc/net/tcp6"] };
+    let mut sockets = Vec::new();
+    for table in tables {
+        if let Ok(text) = fs::read_to_string(table) {
+            sockets.extend(parse_proc_net(&text, port, !udp));
+        }
+    }
+    if sockets.is_empty() {
+        return None;
+    }
+    let inodes: Vec<String> = sockets.iter().map(|(_, inode)| format!("socket:[{}]", inode)).collect();
+    for entry in fs::read_dir("/proc").ok()?.flatten() {
+        let pid = match entry.file_name().to_string_lossy().parse::<u32>() {
+            Ok(pid) => pid,
+            Err(_) => continue
+        };
+        // Descriptors of processes of other users are readable only by root
+        let fds = match fs::read_dir(entry.path().join("fd")) {
+            Ok(fds) => fds,
+            Err(_) => continue
+        };
+        for fd in fds.flatten() {
+            if let Ok(link) = fs::read_link(fd.path()) {
+                if inodes.iter().any(|inode| link.to_string_lossy() == inode.as_str()) {
+                    let name = fs::read_to_string(entry.path().join("comm")).unwrap_or_default().trim().to_owned();
+                    return Some(PortOwner { name, pid: Some(pid) });
+                }
+            }
+        }
+    }
+    // Without root we can tell only the stub resolver of systemd by its address
+    let stub: SocketAddr = "127.0.0.53:53".parse().unwrap();
+    if sockets.iter().any(|(addr, _)| *addr == stub) {
+        return Some(PortOwner { name: String::from("systemd-resolved"), pid: None });
+    }
+    None
+}
+
+/// Finds the program that listens on the port, by `netstat` and `tasklist`
+#[cfg(windows)]
+pub fn find_port_owner(port: u16, udp: bool) -> Option<PortOwner> {
+    use std::process::Command;
+
+    let protocol = if udp { "UDP" } else { "TCP" };
+    let output = Command::new("netstat").args(&["-ano", "-p", protocol]).output().ok()?;
+    let pid = parse_netstat(&String::from_utf8_lossy(&output.stdout), port)?;
+    let filter = format!("PID eq {}", pid);
+    let output = Command::new("tasklist").args(&["/FI", &filter, "/FO", "CSV", "/NH"]).output().ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let name = text.split(',').next()?.trim().trim_matches('"').to_owned();
+    if name.is_empty() || name.starts_with("INFO:") {
+        return Some(PortOwner { name: String::from("unknown program"), pid: Some(pid) });
+    }
+    Some(PortOwner { name, pid: Some(pid) })
+}
+
+/// Finds the program that listens on the port, by `lsof`
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn find_port_owner(port: u16, udp: bool) -> Option<PortOwner> {
+    use std::process::Command;
+
+    let filter = format!("-i{}:{}", if udp { "UDP" } else { "TCP" }, port);
+    let output = Command::new("lsof").args(&["-nP", &filter]).output().ok()?;
+    parse_lsof(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn find_port_owner(_port: u16, _udp: bool) -> Option<PortOwner> {
+    None
+}
+
+/// Gets local addresses and inodes of sockets on the port from /proc/net tables, only listening ones for TCP
+#[cfg(any(target_os = "linux", test))]
+fn parse_proc_net(text: &str, port: u16, tcp: bool) -> Vec<(SocketAddr, u64)> {
+    /// State of listening TCP sockets
+    const TCP_LISTEN: &str = "0A";
+
+    let mut result = Vec::new();
+    for line in text.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 10 || (tcp && fields[3] != TCP_LISTEN) {
+            continue;
+        }
+        let (addr, local_port) = match fields[1].split_once(':') {
+            Some(parts) => parts,
+            None => continue
+        };
+        if u16::from_str_radix(local_port, 16).ok() != Some(port) {
+            continue;
+        }
+        if let (Some(ip), Ok(inode)) = (parse_proc_ip(addr), fields[9].parse::<u64>()) {
+            result.push((SocketAddr::new(ip, port), inode));
+        }
+    }
+    result
+}
+
+/// Addresses in /proc/net are in hex, by 32-bit words in host byte order
+#[cfg(any(target_os = "linux", test))]
+fn parse_proc_ip(hex: &str) -> Option<std::net::IpAddr> {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    let mut bytes = Vec::with_capacity(16);
+    for chunk in hex.as_bytes().chunks(8) {
+        let word = u32::from_str_radix(std::str::from_utf8(chunk).ok()?, 16).ok()?;
+        bytes.extend_from_slice(&word.to_ne_bytes());
+    }
+    match bytes.len() {
+        4 => Some(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]).into()),
+        16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&bytes);
+            Some(Ipv6Addr::from(octets).into())
+        }
+        _ => None
+    }
+}
+
+/// Gets PID of the process that listens on the port from output of `netstat -ano`
+#[cfg(any(windows, test))]
+fn parse_netstat(text: &str, port: u16) -> Option<u32> {
+    let suffix = format!(":{}", port);
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<&str>>())
+        .filter(|fields| fields.len() >= 4 && (fields[0] == "TCP" || fields[0] == "UDP"))
+        .filter(|fields| fields[1].ends_with(&suffix))
+        // TCP lines have a state before PID, UDP lines don't
+        .filter(|fields| fields[0] == "UDP" || fields[3] == "LISTENING")
+        .find_map(|fields| fields.last()?.parse::<u32>().ok())
+}
+
+/// Gets the program from output of `lsof -nP`, the first line is the header
+#[cfg(any(all(unix, not(target_os = "linux")), test))]
+fn parse_lsof(text: &str) -> Option<PortOwner> {
+    let line = text.lines().nth(1)?;
+    let mut fields = line.split_whitespace();
+    let name = fields.next()?.to_owned();
+    let pid = fields.next().and_then(|pid| pid.parse::<u32>().ok());
+    Some(PortOwner { name, pid })
+}
+
+/// Moves the program that has taken port 53 away from it, if it is known how, returns what was done.
+/// Only the stub listener of systemd-resolved is turned off now, the others are only explained.
+#[cfg(target_os = "linux")]
+pub fn free_dns_port() -> Result<String, String> {
+    use std::fs;
+    use std::path::Path;
+    use std::process::Command;
+
+    let owner = match find_port_owner(53, true).or_else(|| find_port_owner(53, false)) {
+        Some(owner) => owner,
+        None => return Ok(String::from("Port 53 is free already"))
+    };
+    if !owner.name.starts_with("systemd-resolve") {
+        let remedy = get_remedy(&owner.name).unwrap_or("stop it or change listen address of GIS");
+        return Err(format!("Port 53 is taken by {}, it can't be freed automatically: {}", &owner.name, remedy));
+    }
+    let dir = Path::new(RESOLVED_DROP_IN).parent().unwrap();
+    fs::create_dir_all(dir).map_err(|e| format!("Unable to create '{}': {}", dir.display(), e))?;
+    fs::write(RESOLVED_DROP_IN, "# Written by GIS to free port 53, remove this file to turn the stub listener back on\n[Resolve]\nDNSStubListener=no\n")
+        .map_err(|e| format!("Unable to write '{}': {}", RESOLVED_DROP_IN, e))?;
+    let status = Command::new("systemctl").args(&["restart", "systemd-resolved"]).status()
+        .map_err(|e| format!("Unable to restart systemd-resolved: {}", e))?;
+    if !status.success() {
+        return Err(format!("Unable to restart systemd-resolved, it exited with {}", status));
+    }
+    Ok(format!("Turned off the stub listener of systemd-resolved by '{}'. If /etc/resolv.conf points to 127.0.0.53, point it to /run/systemd/resolve/resolv.conf or to the address of GIS", RESOLVED_DROP_IN))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn free_dns_port() -> Result<String, String> {
+    match find_port_owner(53, true).or_else(|| find_port_owner(53, false)) {
+        None => Ok(String::from("Port 53 is free already")),
+        Some(owner) => {
+            let remedy = get_remedy(&owner.name).unwrap_or("stop it or change listen address of GIS");
+            Err(format!("Port 53 is taken by {}, it can't be freed automatically: {}", &owner.name, remedy))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proc_net() {
+        let udp = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops\n\
+                   1234: 3500007F:0035 00000000:0000 07 00000000:00000000 00:00000000 00000000   101        0 22345 2 0000000000000000 0\n\
+                   1235: 0100007F:14E9 00000000:0000 07 00000000:00000000 00:00000000 00000000  1000        0 22346 2 0000000000000000 0";
+        assert_eq!(vec![("127.0.0.53:53".parse().unwrap(), 22345)], parse_proc_net(udp, 53, false));
+        assert!(parse_proc_net(udp, 54, false).is_empty());
+
+        let tcp = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n\
+                   0: 00000000:0035 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 31000 1 0000000000000000 100 0 0 10 0\n\
+                   1: 0100007F:0035 0200007F:D431 01 00000000:00000000 00:00000000 00000000     0        0 31001 1 0000000000000000 100 0 0 10 0";
+        assert_eq!(vec![("0.0.0.0:53".parse().unwrap(), 31000)], parse_proc_net(tcp, 53, true));
+
+        let tcp6 = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n\
+                    0: 00000000000000000000000001000000:0035 00000000000000000000000000000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 31002 1";
+        assert_eq!(vec![("[::1]:53".parse().unwrap(), 31002)], parse_proc_net(tcp6, 53, true));
+    }
+
+    #[test]
+    fn netstat_and_lsof() {
+        let netstat = "\r\nActive Connections\r\n\r\n  Proto  Local Address          Foreign Address        State           PID\r\n\
+                       \x20 TCP    0.0.0.0:135            0.0.0.0:0              LISTENING       1012\r\n\
+                       \x20 TCP    192.168.137.1:53       0.0.0.0:0              LISTENING       3320\r\n\
+                       \x20 UDP    192.168.137.1:53       *:*                                    3321\r\n";
+        assert_eq!(Some(3320), parse_netstat(&netstat.replace("UDP", "XXX"), 53));
+        assert_eq!(Some(1012), parse_netstat(netstat, 135));
+        assert_eq!(None, parse_netstat(netstat, 5353));
+        assert_eq!(Some(3321), parse_netstat(&netstat.replace("TCP", "XXX"), 53));
+
+        let lsof = "COMMAND   PID USER   FD   TYPE DEVICE SIZE/OFF NODE NAME\nmDNSRespo 301 root   7u  IPv4 0x1234      0t0  UDP *:53\n";
+        assert_eq!(Some(PortOwner { name: String::from("mDNSRespo"), pid: Some(301) }), parse_lsof(lsof));
+        assert_eq!(None, parse_lsof(""));
+    }
+
+    #[test]
+    fn remedies() {
+        assert!(get_remedy("systemd-resolve").unwrap().contains("DNSStubListener"));
+        assert_eq!(get_remedy("systemd-resolved"), get_remedy("systemd-resolve"));
+        assert!(get_remedy("svchost.exe").unwrap().contains("SharedAccess"));
+        assert!(get_remedy("DNS.EXE").is_some());
+        assert!(get_remedy("nginx").is_none());
+        let error = io::Error::new(io::ErrorKind::AddrInUse, "Address in use");
+        assert!(explain_bind_error("127.0.0.1:53", true, &error).unwrap().starts_with("UDP port 53 is taken by "));
+        assert!(explain_bind_error("127.0.0.1:53", true, &io::Error::new(io::ErrorKind::Other, "Other")).is_none());
+    }
+}
@@ -0,0 +1,182 @@
+//! Configs of reverse proxies (nginx, Caddy, Traefik) made of our domains.
+//! They are rewritten when our domains change, and the proxy is reloaded, so new domains serve sites right away.
+
+use std::fs;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use crate::Context;
+use crate::event::Event;
+use crate::settings::Proxy;
+
+/// How often to check if our domains have changed
+const PROXY_CHECK_INTERVAL_SEC: u64 = 10;
+
+const NGINX_DOMAIN: &str = "server {
+    listen 80;
+    listen [::]:80;
+    server_name {domain} www.{domain};
+    location / {
+        proxy_pass http://{upstream};
+        proxy_set_header Host $host;
+        proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;
+    }
+}
+";
+
+const CADDY_DOMAIN: &str = "http://{domain}, http://www.{domain} {
+    reverse_proxy {upstream}
+}
+";
+
+const TRAEFIK_HEADER: &str = "http:
+  services:
+    gis:
+      loadBalancer:
+        servers:
+          - url: \"http://{upstream}\"
+  routers:
+";
+
+const TRAEFIK_DOMAIN: &str = "    {domain}:
+      rule: \"Host(`{domain}`) || Host(`www.{domain}`)\"
+      service: gis
+";
+
+/// Header of the config and the part that is repeated for every domain
+#[derive(Debug, PartialEq)]
+pub struct Template {
+    header: String,
+    domain: String,
+}
+
+impl Template {
+    /// Gets built-in template by name, or loads the template of one domain from file
+    pub fn load(name: &str) -> Result<Template, String> {
+        let (header, domain) = match name {
+            "nginx" => ("", NGINX_DOMAIN),
+            "caddy" => ("", CADDY_DOMAIN),
+            "traefik" => (TRAEFIK_HEADER, TRAEFIK_DOMAIN),
+            file => {
+                let domain = fs::read_to_string(file).map_err(|e| format!("Unable to read template '{}': {}", file, e))?;
+                return Ok(Template { header: String::new(), domain });
+            }
+        };
+        Ok(Template { header: header.to_owned(), domain: domain.to_owned() })
+    }
+
+    /// Makes the config for these domains, they must be sorted to keep the config the same while domains are the same
+    pub fn render(&self, domains: &[String], upstream: &str) -> String {
+        let mut result = String::from("# Made by GIS from your domains, all changes will be lost\n");
+        result.push_str(&self.header.replace("{upstream}", upstream));
+        for domain in domains {
+            result.push_str(&self.domain.replace("{domain}", domain).replace("{upstream}", upstream));
+        }
+        result
+    }
+}
+
+/// Writes configs of proxies from settings, and rewrites them when our domains change
+pub fn start_proxies(context: &Arc<Mutex<Context>>, proxies: &[Proxy]) {
+    let mut configs = Vec::new();
+    for proxy in proxies {
+        match Template::load(&proxy.template) {
+            Ok(template) => configs.push((proxy.clone(), template)),
+            Err(e) => error!("Wrong proxy config for '{}': {}", &proxy.output, e)
+        }
+    }
+    if configs.is_empty() {
+        return;
+    }
+
+    let changed = Arc::new(AtomicBool::new(true));
+    {
+        let changed = Arc::clone(&changed);
+        // The context is locked while events are posted, so we only mark the change here
+        context.lock().unwrap().bus.register(move |_uuid, e| {
+            if let Event::DomainChanged { .. } | Event::KeyLoaded { .. } = e {
+                changed.store(true, Ordering::Relaxed);
+            }
+            true
+        });
+    }
+
+    let context = Arc::clone(context);
+    let _ = thread::Builder::new().name(String::from("Proxy configs")).spawn(move || {
+        loop {
+            if changed.swap(false, Ordering::Relaxed) {
+                let domains = get_our_domains(&context);
+                for (proxy, template) in configs.iter() {
+                    update_config(proxy, template, &domains);
+                }
+            }
+            thread::sleep(Duration::from_secs(PROXY_CHECK_INTERVAL_SEC));
+        }
+    });
+}
+
+fn get_our_domains(context: &Mutex<Context>) -> Vec<String> {
+    let context = context.lock().unwrap();
+    let mut domains: Vec<String> = context.chain.get_my_domains(&context.keystore)
+        .into_iter()
+        .map(|(_, (name, _, _))| name)
+        .collect();
+    domains.sort();
+    domains
+}
+
+/// Writes the config if it has changed, and reloads the proxy
+fn update_config(proxy: &Proxy, template: &Template, domains: &[String]) {
+    let domains: Vec<String> = match proxy.domains.is_empty() {
+        true => domains.to_vec(),
+        false => domains.iter().filter(|d| proxy.domains.contains(d)).cloned().collect()
+    };
+    let config = template.render(&domains, &proxy.upstream);
+    if fs::read_to_string(&proxy.output).map(|old| old == config).unwrap_or(false) {
+        return;
+    }
+    if let Err(e) = fs::write(&proxy.output, &config) {
+        error!("Unable to write proxy config '{}': {}", &proxy.output, e);
+        return;
+    }
+    info!("Written proxy config '{}' with {} domains", &proxy.output, domains.len());
+    if proxy.reload.is_empty() {
+        return;
+    }
+    let result = if cfg!(windows) {
+        Command::new("cmd").args(&["/C", &proxy.reload]).status()
+    } else {
+        Command::new("sh").args(&["-c", &proxy.reload]).status()
+    };
+    match result {
+        Ok(status) if status.success() => debug!("Proxy reloaded by '{}'", &proxy.reload),
+        Ok(status) => warn!("Proxy reload command '{}' has failed: {}", &proxy.reload, status),
+        Err(e) => warn!("Unable to run proxy reload command '{}': {}", &proxy.reload, e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let domains = vec![String::from("example.ygg"), String::from("site.anon")];
+        let nginx = Template::load("nginx").unwrap().render(&domains, "127.0.0.1:8080");
+        assert!(nginx.contains("server_name example.ygg www.example.ygg;"));
+        assert!(nginx.contains("server_name site.anon www.site.anon;"));
+        assert!(nginx.contains("proxy_pass http://127.0.0.1:8080;"));
+
+        let traefik = Template::load("traefik").unwrap().render(&domains, "127.0.0.1:8080");
+        assert_eq!(1, traefik.matches("routers:").count());
+        assert!(traefik.contains("rule: \"Host(`site.anon`) || Host(`www.site.anon`)\""));
+
+        assert!(Template::load("/nonexistent/template").is_err());
+    }
+}
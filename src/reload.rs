@@ -0,0 +1,157 @@
+//! Reloading of settings while running. The settings file is checked for changes every few seconds, and on unix
+//! it is reloaded on SIGHUP too. Only some settings are applied right away: DNS forwarders and routes, bootstrap peers,
+//! mining threads and log level. The others are taken on next start.
+
+use std::fs;
+use std::sync::{Arc, Mutex};
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use crate::{Context, Settings};
+use crate::dns::context::ServerContext;
+use crate::dns_utils::apply_dns_settings;
+use crate::event::Event;
+use crate::logging::{parse_levels, set_levels};
+
+/// How often to check if the settings file has changed
+const RELOAD_CHECK_INTERVAL_SEC: u64 = 5;
+
+#[cfg(unix)]
+static SIGHUP_RECEIVED: AtomicBool = AtomicBool::new(false);
+
+/// Starts a thread that reloads settings when their file is changed, or on SIGHUP
+pub fn start_settings_watcher(context: &Arc<Mutex<Context>>, server_context: Option<Arc<ServerContext>>) {
+    let filename = context.lock().unwrap().settings.file.clone();
+    if filename.is_empty() {
+        return;
+    }
+    #[cfg(unix)]
+    listen_sighup();
+    let context = Arc::clone(context);
+    let _ = thread::Builder::new().name(String::from("Settings watcher")).spawn(move || {
+        let mut modified = get_modified(&filename);
+        let mut seconds = 0;
+        loop {
+            thread::sleep(Duration::from_secs(1));
+            seconds += 1;
+            #[cfg(unix)]
+            let signaled = SIGHUP_RECEIVED.swap(false, Ordering::SeqCst);
+            #[cfg(not(unix))]
+            let signaled = false;
+            if !signaled && seconds < RELOAD_CHECK_INTERVAL_SEC {
+                continue;
+            }
+            seconds = 0;
+            let time = get_modified(&filename);
+            if !signaled && time == modified {
+                continue;
+            }
+            modified = time;
+            reload_settings(&context, server_context.as_deref(), &filename);
+        }
+    });
+}
+
+/// Loads settings from the file, applies what can be changed while running, and posts `SettingsChanged`
+fn reload_settings(context: &Arc<Mutex<Context>>, server_context: Option<&ServerContext>, filename: &str) {
+    let settings = match Settings::load(filename) {
+        Some(settings) => settings,
+        None => {
+            warn!("Unable to reload settings, '{}' is broken", filename);
+            return;
+        }
+    };
+    let mut context = context.lock().unwrap();
+    let changed = apply_settings(&mut context.settings, &settings);
+    if changed.is_empty() {
+        debug!("Settings in '{}' have no changes that can be applied while running", filename);
+        return;
+    }
+    info!("Reloaded settings from '{}', changed: {}", filename, changed.join(", "));
+    if changed.iter().any(|name| name == "dns") {
+        if let Some(server_context) = server_context {
+            apply_dns_settings(server_context, &settings.dns);
+        }
+    }
+    if changed.iter().any(|name| name == "log_level") {
+        match parse_levels(&settings.log_level) {
+            Ok(levels) => { set_levels(levels); }
+            Err(e) => warn!("Unable to change log level: {}", e)
+        }
+    }
+    context.bus.post(Event::SettingsChanged { changed });
+}
+
+/// Copies settings that can be changed while running, returns their names
+fn apply_settings(current: &mut Settings, new: &Settings) -> Vec<String> {
+    let mut changed = Vec::new();
+    let (dns, new_dns) = (&mut current.dns, &new.dns);
+    if dns.forwarders != new_dns.forwarders || dns.forwarder_retries != new_dns.forwarder_retries
+        || dns.forwarder_parallel != new_dns.forwarder_parallel || dns.recursive != new_dns.recursive || dns.routes != new_dns.routes {
+        dns.forwarders = new_dns.forwarders.clone();
+        dns.forwarder_retries = new_dns.forwarder_retries;
+        dns.forwarder_parallel = new_dns.forwarder_parallel;
+        dns.recursive = new_dns.recursive;
+        dns.routes = new_dns.routes.clone();
+        changed.push(String::from("dns"));
+    }
+    if current.net.peers != new.net.peers {
+        current.net.peers = new.net.peers.clone();
+        changed.push(String::from("net.peers"));
+    }
+    if current.mining.threads != new.mining.threads {
+        current.mining.threads = new.mining.threads;
+        changed.push(String::from("mining.threads"));
+    }
+    if current.log_level != new.log_level {
+        current.log_level = new.log_level.clone();
+        changed.push(String::from("log_level"));
+    }
+    changed
+}
+
+fn get_modified(filename: &str) -> Option<SystemTime> {
+    fs::metadata(filename).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(unix)]
+extern "C" fn on_sighup(_signal: libc::c_int) {
+    SIGHUP_RECEIVED.store(true, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+fn listen_sighup() {
+    let handler = on_sighup as extern "C" fn(libc::c_int);
+    unsafe {
+        libc::signal(libc::SIGHUP, handler as libc::sighandler_t);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_changes() {
+        let mut current = Settings::default();
+        let mut new = current.clone();
+        assert!(apply_settings(&mut current, &new).is_empty());
+
+        new.dns.forwarders = vec![String::from("127.0.0.1:5353")];
+        new.net.peers = vec![String::from("peer.ygg:46866")];
+        new.log_level = String::from("debug");
+        // Number of DNS workers needs restart, it is not changed
+        new.dns.threads += 1;
+        let changed = apply_settings(&mut current, &new);
+        assert_eq!(vec!["dns", "net.peers", "log_level"], changed);
+        assert_eq!(new.dns.forwarders, current.dns.forwarders);
+        assert_eq!(new.net.peers, current.net.peers);
+        assert_ne!(new.dns.threads, current.dns.threads);
+        assert!(apply_settings(&mut current, &new).is_empty());
+    }
+}
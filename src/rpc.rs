@@ -0,0 +1,309 @@
+//! JSON-RPC 2.0 server for wallets and other tools, that drive the node without the GUI.
+//! Requests are sent by POST with `Authorization: Bearer <token>` header, the token is set in `[rpc]` settings.
+
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{Block, Bytes, Context, Miner, Transaction, CLASS_DOMAIN, format_wait_time, get_domain_zone, is_yggdrasil_record};
+use crate::address::get_node_address;
+use crate::api::http::{HttpRequest, HttpResponse};
+use crate::blockchain::hash_utils::hash_identity;
+use crate::blockchain::transaction::{ContactsData, DomainData};
+use crate::blockchain::types::MineResult;
+use crate::dns::templates::{expand_records, needs_node_address};
+use crate::settings::Rpc;
+
+/// Maximum number of connections handled at once
+const MAX_CONNECTIONS: usize = 64;
+/// Time to wait for the client to send its request
+const READ_TIMEOUT_SEC: u64 = 5;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Errors of the node, like absent keys or a domain of another key
+const SERVER_ERROR: i64 = -32000;
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    #[serde(default)]
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Value,
+    #[serde(default)]
+    id: Value,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: &str) -> Self {
+        RpcError { code, message: message.to_owned() }
+    }
+}
+
+#[derive(Deserialize)]
+struct BlockParams {
+    index: u64,
+}
+
+#[derive(Deserialize)]
+struct DomainParams {
+    domain: String,
+}
+
+#[derive(Deserialize)]
+struct TemplateParams {
+    transaction: Transaction,
+}
+
+#[derive(Deserialize)]
+struct MineParams {
+    domain: String,
+    /// Records like in domain data, placeholders like `{ygg}` are expanded
+    records: Vec<Value>,
+    #[serde(default)]
+    contacts: Vec<ContactsData>,
+}
+
+#[derive(Serialize)]
+struct MyDomain {
+    identity: Bytes,
+    domain: String,
+    timestamp: i64,
+    data: DomainData,
+}
+
+struct RpcServer {
+    context: Arc<Mutex<Context>>,
+    miner: Arc<Mutex<Miner>>,
+    token: String,
+    connections: AtomicUsize,
+}
+
+/// Starts JSON-RPC server in a separate thread if it is enabled in settings
+pub fn start_rpc_server(context: &Arc<Mutex<Context>>, miner: &Arc<Mutex<Miner>>, settings: &Rpc) {
+    if settings.listen.is_empty() {
+        return;
+    }
+    if settings.token.is_empty() {
+        error!("JSON-RPC server is not started, it needs a token in [rpc] settings");
+        return;
+    }
+    let listener = match TcpListener::bind(&settings.listen) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Unable to start JSON-RPC server on {}: {}", &settings.listen, e);
+            return;
+        }
+    };
+    info!("JSON-RPC server is listening on {}", &settings.listen);
+    let server = Arc::new(RpcServer { context: Arc::clone(context), miner: Arc::clone(miner), token: settings.token.clone(), connections: AtomicUsize::new(0) });
+    let _ = thread::Builder::new().name(String::from("RPC server")).spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("Error accepting RPC connection: {}", e);
+                    continue;
+                }
+            };
+            if server.connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                server.connections.fetch_sub(1, Ordering::SeqCst);
+                let _ = HttpResponse::error(503, "Too many connections").write(&mut &stream);
+                continue;
+            }
+            let server = Arc::clone(&server);
+            let _ = thread::Builder::new().name(String::from("RPC connection")).spawn(move || {
+                server.handle_connection(stream);
+                server.connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+}
+
+impl RpcServer {
+    fn handle_connection(&self, mut stream: TcpStream) {
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(READ_TIMEOUT_SEC)));
+        let response = match HttpRequest::read(&stream) {
+            None => HttpResponse::error(400, "Bad request"),
+            Some(request) if !request.has_token(&self.token) => HttpResponse::error(401, "Wrong or absent token"),
+            Some(request) if request.method != "POST" => HttpResponse::error(405, "Only POST requests are allowed"),
+            Some(request) => HttpResponse::json(&self.handle(&request.body))
+        };
+        if let Err(e) = response.write(&mut stream) {
+            debug!("Error sending RPC response: {}", e);
+        }
+    }
+
+    /// Handles the body of a request, and makes the body of the response
+    fn handle(&self, body: &[u8]) -> Value {
+        let request = match serde_json::from_slice::<Value>(body) {
+            Ok(request) => request,
+            Err(e) => return make_response(Value::Null, Err(RpcError::new(PARSE_ERROR, &e.to_string())))
+        };
+        let request = match serde_json::from_value::<RpcRequest>(request) {
+            Ok(request) if request.jsonrpc == "2.0" => request,
+            _ => return make_response(Value::Null, Err(RpcError::new(INVALID_REQUEST, "Invalid request")))
+        };
+        trace!("RPC call of {}", &request.method);
+        make_response(request.id, self.call(&request.method, request.params))
+    }
+
+    fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "get_height" => Ok(json!(self.context.lock().unwrap().chain.get_height())),
+            "get_block" => {
+                let params: BlockParams = parse_params(params)?;
+                match self.context.lock().unwrap().chain.get_block(params.index) {
+                    Some(block) => Ok(json!(block)),
+                    None => Err(RpcError::new(SERVER_ERROR, "No such block"))
+                }
+            }
+            "get_domain_info" => {
+                let params: DomainParams = parse_params(params)?;
+                let domain = params.domain.trim_end_matches('.').to_lowercase();
+                let data = self.context.lock().unwrap().chain.get_domain_info(&domain);
+                match data.and_then(|data| serde_json::from_str::<Value>(&data).ok()) {
+                    Some(data) => Ok(data),
+                    None => Ok(Value::Null)
+                }
+            }
+            "get_my_domains" => {
+                let context = self.context.lock().unwrap();
+                let mut domains: Vec<MyDomain> = context.chain.get_my_domains(&context.keystore)
+                    .into_iter()
+                    .map(|(identity, (domain, timestamp, data))| MyDomain { identity, domain, timestamp, data })
+                    .collect();
+                domains.sort_by(|a, b| a.domain.cmp(&b.domain));
+                Ok(json!(domains))
+            }
+            "get_block_template" => {
+                // The block is signed by the key of the transaction, like blocks of `mine_domain`
+                let params: TemplateParams = parse_params(params)?;
+                let pub_key = params.transaction.pub_key.clone();
+                Ok(json!(self.context.lock().unwrap().chain.get_block_template(params.transaction, pub_key)))
+            }
+            "mine_domain" => {
+                let params: MineParams = parse_params(params)?;
+                self.mine_domain(params)
+            }
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, "Method not found"))
+        }
+    }
+
+    /// Starts mining of domain with these records, if we can mine it
+    fn mine_domain(&self, params: MineParams) -> Result<Value, RpcError> {
+        let name = params.domain.trim_end_matches('.').to_lowercase();
+        let text = serde_json::to_string(&params.records).unwrap_or_default();
+        let node = if needs_node_address(&text) { get_node_address() } else { None };
+        let records = expand_records(params.records, &name, node).map_err(|e| RpcError::new(INVALID_PARAMS, &e))?;
+
+        let context = self.context.lock().unwrap();
+        if context.light.is_some() {
+            return Err(RpcError::new(SERVER_ERROR, "Light nodes can't mine domains"));
+        }
+        let keystore = context.get_keystore().ok_or_else(|| RpcError::new(SERVER_ERROR, "Node has no keys loaded"))?;
+        if context.chain.is_waiting_signers() {
+            return Err(RpcError::new(SERVER_ERROR, "Waiting for last full block to be signed"));
+        }
+        let zone = get_domain_zone(&name);
+        let yggdrasil = context.chain.get_zones().iter().any(|z| z.name == zone && z.yggdrasil);
+        if yggdrasil && !records.iter().all(is_yggdrasil_record) {
+            return Err(RpcError::new(SERVER_ERROR, "Zone is Yggdrasil only"));
+        }
+        let mut data = DomainData::new(Bytes::default(), zone.clone(), records, params.contacts, Vec::new());
+        // Owners are kept from the current data
+        if let Some(current) = context.chain.get_domain_transaction(&name).and_then(|t| t.get_domain_data()) {
+            data.owners = current.owners;
+        }
+        match context.chain.can_mine_domain(context.chain.get_height(), &name, &keystore.get_public(), &data) {
+            MineResult::Fine => {}
+            MineResult::Cooldown { time } => {
+                return Err(RpcError::new(SERVER_ERROR, &format!("You can register a new domain in {}", format_wait_time(time))));
+            }
+            result => return Err(RpcError::new(SERVER_ERROR, &format!("Unable to mine domain: {:?}", result)))
+        }
+        let difficulty = context.chain.get_zone_difficulty(&zone);
+        let confirmation = hash_identity(&name, Some(&keystore.get_public()));
+        data.domain = keystore.encrypt(name.as_bytes(), &confirmation.as_slice()[..12]);
+        let data = serde_json::to_string(&data).unwrap();
+        let transaction = Transaction::from_str(name.clone(), CLASS_DOMAIN.to_owned(), data, keystore.get_public());
+        let identity = transaction.identity.clone();
+        let block = Block::new(Some(transaction), keystore.get_public(), Bytes::default(), difficulty);
+        std::mem::drop(context);
+        self.miner.lock().unwrap().add_block(block, keystore);
+        info!("Mining of domain '{}' has started by RPC", &name);
+        Ok(json!({ "identity": identity, "zone": zone, "difficulty": difficulty }))
+    }
+}
+
+fn parse_params<T: for<'de> Deserialize<'de>>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, &e.to_string()))
+}
+
+fn make_response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+        Err(error) => json!({ "jsonrpc": "2.0", "error": error, "id": id })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Chain, Settings};
+
+    use super::*;
+
+    fn make_server() -> RpcServer {
+        let mut settings = Settings::default();
+        settings.db.in_memory = true;
+        let chain = Chain::new(&settings, settings.get_db_name());
+        let context = Arc::new(Mutex::new(Context::new(String::from("test"), settings, None, chain)));
+        let miner = Arc::new(Mutex::new(Miner::new(Arc::clone(&context))));
+        RpcServer { context, miner, token: String::from("secret"), connections: AtomicUsize::new(0) }
+    }
+
+    #[test]
+    fn calls() {
+        let server = make_server();
+        let response = server.handle(br#"{"jsonrpc":"2.0","method":"get_height","id":1}"#);
+        assert_eq!(json!({ "jsonrpc": "2.0", "result": 0, "id": 1 }), response);
+
+        let response = server.handle(br#"{"jsonrpc":"2.0","method":"get_block","params":{"index":5},"id":2}"#);
+        assert_eq!(SERVER_ERROR, response["error"]["code"]);
+        let response = server.handle(br#"{"jsonrpc":"2.0","method":"get_block","params":{},"id":3}"#);
+        assert_eq!(INVALID_PARAMS, response["error"]["code"]);
+        let response = server.handle(br#"{"jsonrpc":"2.0","method":"format_disk","id":4}"#);
+        assert_eq!(METHOD_NOT_FOUND, response["error"]["code"]);
+        let response = server.handle(br#"{"method":"get_height","id":5}"#);
+        assert_eq!(INVALID_REQUEST, response["error"]["code"]);
+        let response = server.handle(b"{");
+        assert_eq!(PARSE_ERROR, response["error"]["code"]);
+
+        let params = r#"{"domain":"test.ygg","records":[{"type":"AAAA","domain":"@","ttl":3600,"addr":"200::1"}]}"#;
+        let response = server.handle(format!(r#"{{"jsonrpc":"2.0","method":"mine_domain","params":{},"id":6}}"#, params).as_bytes());
+        assert_eq!("Node has no keys loaded", response["error"]["message"]);
+
+        let transaction = Transaction::from_str(String::from("ygg"), String::from("zone"), String::from("{}"), Bytes::zero32());
+        let request = json!({ "jsonrpc": "2.0", "method": "get_block_template", "params": { "transaction": transaction }, "id": 7 });
+        let response = server.handle(request.to_string().as_bytes());
+        // The first block of empty chain is genesis
+        assert_eq!(1, response["result"]["index"]);
+        assert_eq!(server.context.lock().unwrap().chain.get_params().zone_difficulty, response["result"]["difficulty"]);
+    }
+}
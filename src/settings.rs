@@ -1,11 +1,15 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 #[allow(unused_imports)]
 use log::{debug, error, info, LevelFilter, trace, warn};
 
 use crate::Bytes;
+use crate::commons::{DB_NAME, LISTEN_PORT, MEMORY_DB_NAME};
+use crate::blockchain::checkpoints::Checkpoints;
+use crate::blockchain::consensus::ConsensusParams;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Settings {
@@ -15,12 +19,44 @@ pub struct Settings {
     pub key_file: String,
     #[serde(default = "default_check_blocks")]
     pub check_blocks: u64,
+    /// Levels of log like "info,gis::p2p=debug", empty to take them from command line
+    #[serde(default)]
+    pub log_level: String,
     #[serde(default)]
     pub net: Net,
     #[serde(default)]
     pub dns: Dns,
     #[serde(default)]
     pub mining: Mining,
+    #[serde(default)]
+    pub db: Db,
+    #[serde(default)]
+    pub storage: Storage,
+    #[serde(default)]
+    pub node: Node,
+    #[serde(default)]
+    pub api: Api,
+    #[serde(default)]
+    pub memory: Memory,
+    #[serde(default)]
+    pub chain: ChainRules,
+    /// Additional chains, served by this node along with the main one
+    #[serde(default)]
+    pub chains: Vec<ExtraChain>,
+    /// Configs of reverse proxies, made of our domains
+    #[serde(default)]
+    pub proxies: Vec<Proxy>,
+    #[serde(default)]
+    pub address: AddressDetection,
+    #[serde(default)]
+    pub rpc: Rpc,
+    #[serde(default)]
+    pub updates: Updates,
+    #[serde(default)]
+    pub mempool: Mempool,
+    /// File these settings were loaded from, to reload some of them while running
+    #[serde(skip)]
+    pub file: String,
 }
 
 impl Settings {
@@ -29,7 +65,8 @@ impl Settings {
             Ok(mut file) => {
                 let mut text = String::new();
                 file.read_to_string(&mut text).unwrap();
-                if let Ok(settings) = toml::from_str(&text) {
+                if let Ok(mut settings) = toml::from_str::<Settings>(&text) {
+                    settings.file = filename.to_owned();
                     return Some(settings);
                 }
                 None
@@ -40,6 +77,31 @@ impl Settings {
         }
     }
 
+    /// Makes settings for additional chain, they have their own origin, network and rules
+    pub fn for_chain(&self, extra: &ExtraChain) -> Settings {
+        let mut settings = self.clone();
+        settings.origin = extra.origin.clone();
+        settings.net = extra.net.clone();
+        // Peers of other networks must not mix with ours
+        if settings.net.peers_file == self.net.peers_file && !settings.net.peers_file.is_empty() {
+            settings.net.peers_file = format!("{}-peers.json", &extra.name);
+        }
+        settings.chain = extra.chain.clone();
+        settings.node = Node::default();
+        settings.api = Api::default();
+        settings.rpc = Rpc::default();
+        settings.chains = Vec::new();
+        settings
+    }
+
+    /// Light nodes and ephemeral nodes keep their DB in memory
+    pub fn get_db_name(&self) -> &'static str {
+        match self.node.is_light() || self.db.in_memory {
+            true => MEMORY_DB_NAME,
+            false => DB_NAME
+        }
+    }
+
     pub fn get_origin(&self) -> Bytes {
         if self.origin.eq("") {
             return Bytes::zero32();
@@ -55,35 +117,260 @@ impl Default for Settings {
             origin: String::from(""),
             key_file: String::from("default.key"),
             check_blocks: default_check_blocks(),
+            log_level: String::new(),
             net: Net::default(),
             dns: Default::default(),
-            mining: Mining::default()
+            mining: Mining::default(),
+            db: Db::default(),
+            storage: Storage::default(),
+            node: Node::default(),
+            api: Api::default(),
+            memory: Memory::default(),
+            chain: ChainRules::default(),
+            chains: Vec::new(),
+            proxies: Vec::new(),
+            address: AddressDetection::default(),
+            rpc: Rpc::default(),
+            updates: Updates::default(),
+            mempool: Mempool::default(),
+            file: String::new()
         }
     }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Dns {
-    #[serde(default = "default_listen_dns")]
-    pub listen: String,
+    /// One address like "127.0.0.1:53", or a list of addresses, or tables with `address` and `allow` networks
+    #[serde(default = "default_listen_dns", deserialize_with = "deserialize_listeners")]
+    pub listen: Vec<DnsListener>,
+    /// Port to listen on if we have no permission to use port 53, zero to disable
+    #[serde(default = "default_fallback_port")]
+    pub fallback_port: u16,
+    /// Worker threads answering queries of all listeners
     #[serde(default = "default_threads")]
     pub threads: usize,
+    /// How many queries can wait for workers, others are dropped
+    #[serde(default = "default_queue_size")]
+    pub queue_size: usize,
     pub forwarders: Vec<String>,
+    /// Resolve names starting from root servers, without any forwarders
+    #[serde(default)]
+    pub recursive: bool,
+    /// Time in milliseconds to wait for the answer from forwarder
+    #[serde(default = "default_forwarder_timeout")]
+    pub forwarder_timeout: u64,
+    /// How many times to repeat failed query, every time to the next forwarder
+    #[serde(default)]
+    pub forwarder_retries: usize,
+    /// Send queries to all forwarders at once and use the fastest answer
+    #[serde(default)]
+    pub forwarder_parallel: bool,
+    /// Send only needed parts of domain names to other servers when resolving without forwarders (RFC 9156)
+    #[serde(default = "default_true")]
+    pub qname_minimization: bool,
+    /// Warn in log about answers for blockchain domains that mix letters of several scripts, like Latin and Cyrillic
+    #[serde(default)]
+    pub flag_confusables: bool,
+    /// Leave out authority and additional records that clients don't need, to make answers smaller on slow links
+    #[serde(default)]
+    pub minimal_responses: bool,
+    /// Remove EDNS Client Subnet from queries to forwarders and from their answers, to keep addresses of clients private
+    #[serde(default = "default_true")]
+    pub strip_client_subnet: bool,
+    /// Answer REFUSED to clients that send their subnet, instead of ignoring it
+    #[serde(default)]
+    pub refuse_client_subnet: bool,
     #[serde(default)]
     pub hosts: Vec<String>,
+    #[serde(default)]
+    pub views: Vec<View>,
+    /// Response policy zone files
+    #[serde(default)]
+    pub rpz: Vec<String>,
+    /// File to keep DNS cache between restarts, empty to disable
+    #[serde(default)]
+    pub cache_file: String,
+    /// Limits of the cache of answers
+    #[serde(default)]
+    pub cache: DnsCache,
+    /// Zones to give to conventional secondary DNS servers
+    #[serde(default)]
+    pub primary: Primary,
+    /// Zones to take from conventional primary DNS servers
+    #[serde(default)]
+    pub imports: Vec<ZoneImport>,
+    /// Sources of zones, like `{ ygg = "chain", corp = "chain:corp", lan = "zone", onion = "forward:127.0.0.1:9053" }`
+    #[serde(default)]
+    pub routes: HashMap<String, String>,
+    #[serde(default)]
+    pub doh: Doh,
 }
 
 impl Default for Dns {
     fn default() -> Self {
         Dns {
-            listen: String::from("127.0.0.1:53"),
-            threads: 20,
+            listen: vec![DnsListener::new("127.0.0.1:53")],
+            fallback_port: default_fallback_port(),
+            threads: default_threads(),
+            queue_size: default_queue_size(),
             forwarders: vec![String::from("94.140.14.14:53"), String::from("94.140.15.15:53")],
-            hosts: Vec::new()
+            recursive: false,
+            forwarder_timeout: default_forwarder_timeout(),
+            forwarder_retries: 0,
+            forwarder_parallel: false,
+            qname_minimization: true,
+            flag_confusables: false,
+            minimal_responses: false,
+            strip_client_subnet: true,
+            refuse_client_subnet: false,
+            hosts: Vec::new(),
+            views: Vec::new(),
+            rpz: Vec::new(),
+            cache_file: String::new(),
+            cache: DnsCache::default(),
+            primary: Primary::default(),
+            imports: Vec::new(),
+            routes: HashMap::new(),
+            doh: Doh::default()
         }
     }
 }
 
+/// Address for DNS server to listen on, with networks of clients that are allowed to use it
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "ListenerConfig")]
+pub struct DnsListener {
+    pub address: String,
+    /// Client networks in CIDR notation, everyone is allowed if empty
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+impl DnsListener {
+    pub fn new(address: &str) -> Self {
+        DnsListener { address: address.to_owned(), allow: Vec::new() }
+    }
+}
+
+/// Listeners can be given just by address, or with allowed networks
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ListenerConfig {
+    Address(String),
+    Full {
+        address: String,
+        #[serde(default)]
+        allow: Vec<String>
+    },
+}
+
+impl From<ListenerConfig> for DnsListener {
+    fn from(config: ListenerConfig) -> Self {
+        match config {
+            ListenerConfig::Address(address) => DnsListener::new(&address),
+            ListenerConfig::Full { address, allow } => DnsListener { address, allow }
+        }
+    }
+}
+
+/// Old configs have only one address in `listen`, empty address means no DNS server
+fn deserialize_listeners<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<DnsListener>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Listeners {
+        One(DnsListener),
+        Many(Vec<DnsListener>),
+    }
+
+    let listeners = match Listeners::deserialize(deserializer)? {
+        Listeners::One(listener) => vec![listener],
+        Listeners::Many(listeners) => listeners
+    };
+    Ok(listeners.into_iter().filter(|listener| !listener.address.is_empty()).collect())
+}
+
+/// Custom filtering for some group of DNS clients
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct View {
+    pub name: String,
+    /// Client networks in CIDR notation
+    pub clients: Vec<String>,
+    /// Additional hosts files, used as blocklists
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Domains that are answered with CNAME to other domain, for safe-search and such
+    #[serde(default)]
+    pub rewrites: HashMap<String, String>,
+}
+
+/// DNS-over-HTTPS endpoint (RFC 8484), for browsers to use the node as their resolver
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Doh {
+    /// Address to listen on, like "0.0.0.0:443", empty to disable
+    #[serde(default)]
+    pub listen: String,
+    /// Client networks in CIDR notation, everyone is allowed if empty
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// Certificate chain in PEM
+    #[serde(default)]
+    pub cert_file: String,
+    /// Private key in PEM, PKCS#8 or RSA
+    #[serde(default)]
+    pub key_file: String,
+    /// Path of the endpoint in URL
+    #[serde(default = "default_doh_path")]
+    pub path: String,
+}
+
+impl Default for Doh {
+    fn default() -> Self {
+        Doh {
+            listen: String::new(),
+            allow: Vec::new(),
+            cert_file: String::new(),
+            key_file: String::new(),
+            path: default_doh_path()
+        }
+    }
+}
+
+/// Cache of answers from forwarders and other servers, its memory is limited in `[memory]`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DnsCache {
+    /// How many names with types of records are kept, the least recently used ones are evicted, zero for no limit
+    #[serde(default = "default_dns_cache_entries")]
+    pub entries: usize,
+    /// Seconds to remember absent names when the answer doesn't tell, zero to not remember them
+    #[serde(default = "default_dns_negative_ttl")]
+    pub negative_ttl: u32,
+}
+
+impl Default for DnsCache {
+    fn default() -> Self {
+        DnsCache { entries: default_dns_cache_entries(), negative_ttl: default_dns_negative_ttl() }
+    }
+}
+
+/// Hidden primary mode, blockchain domains are transferred to secondary servers by AXFR or IXFR
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Primary {
+    /// Our domains to serve, they can't be taken from the blockchain as it has only hashes
+    #[serde(default)]
+    pub domains: Vec<String>,
+    /// Addresses of secondary servers, they get NOTIFY messages and are allowed to transfer zones
+    #[serde(default)]
+    pub secondaries: Vec<String>,
+}
+
+/// Zone that is periodically transferred by AXFR from some primary server and served locally
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ZoneImport {
+    pub zone: String,
+    /// Address of primary server with port, like "192.168.1.1:53"
+    pub primary: String,
+}
+
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Mining {
     #[serde(default)]
@@ -92,6 +379,292 @@ pub struct Mining {
     pub lower: bool
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Db {
+    /// Keep blocks, that were removed by fork resolution or truncation, in separate table
+    #[serde(default)]
+    pub archive_orphans: bool,
+    /// Move corrupted DB aside and resync from scratch, instead of stopping
+    #[serde(default)]
+    pub auto_recover: bool,
+    /// Keep blockchain only in memory, it is synced again on every start
+    #[serde(default)]
+    pub in_memory: bool,
+    /// Warn about low disk space when there are less free megabytes on the DB volume
+    #[serde(default = "default_low_space_mb")]
+    pub low_space_mb: u64,
+    /// Stop accepting blocks when there are less free megabytes, not to break the DB
+    #[serde(default = "default_critical_space_mb")]
+    pub critical_space_mb: u64,
+    /// How many more connections to the DB are opened to look up domains for DNS threads, zero to use only the main one
+    #[serde(default = "default_read_connections")]
+    pub read_connections: usize,
+}
+
+impl Default for Db {
+    fn default() -> Self {
+        Db {
+            archive_orphans: false,
+            auto_recover: false,
+            in_memory: false,
+            low_space_mb: default_low_space_mb(),
+            critical_space_mb: default_critical_space_mb(),
+            read_connections: default_read_connections()
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Storage {
+    /// Where blocks are kept: "sqlite" or "sled", the latter needs a build with `sled` feature
+    #[serde(default = "default_storage_backend")]
+    pub backend: String,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Storage { backend: default_storage_backend() }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Node {
+    /// "full" keeps the whole blockchain, "light" keeps only block headers and asks peers for domains
+    #[serde(default = "default_node_mode")]
+    pub mode: String,
+    /// File to keep block headers in light mode
+    #[serde(default = "default_headers_file")]
+    pub headers_file: String,
+}
+
+impl Node {
+    pub fn is_light(&self) -> bool {
+        self.mode == "light"
+    }
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Node {
+            mode: default_node_mode(),
+            headers_file: default_headers_file()
+        }
+    }
+}
+
+/// HTTP API for explorers and other tools
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Api {
+    /// Address to listen on, like "127.0.0.1:4244", empty to disable the API
+    #[serde(default)]
+    pub listen: String,
+    /// How many requests are allowed from one IP address per minute, zero for no limit
+    #[serde(default = "default_api_rate_limit")]
+    pub rate_limit: u32,
+    /// Services that are notified about changes of domains, they work even if the API is disabled
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+    /// Secret that clients give to submit mining jobs to `/api/jobs`, to change log levels and watched domains, and to get private events, empty to disable them
+    #[serde(default)]
+    pub token: String,
+    /// File to keep watched domains in, they are managed by `/api/watchlist`, empty to not keep them
+    #[serde(default = "default_watchlist_file")]
+    pub watchlist_file: String,
+}
+
+impl Default for Api {
+    fn default() -> Self {
+        Api {
+            listen: String::new(),
+            rate_limit: default_api_rate_limit(),
+            webhooks: Vec::new(),
+            token: String::new(),
+            watchlist_file: default_watchlist_file()
+        }
+    }
+}
+
+/// JSON-RPC server for remote control of the node
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Rpc {
+    /// Address to listen on, like "127.0.0.1:4245", empty to disable the server
+    #[serde(default)]
+    pub listen: String,
+    /// Secret that clients give in `Authorization: Bearer` header, the server doesn't start without it
+    #[serde(default)]
+    pub token: String,
+}
+
+/// Check for new releases by their signed manifest, nothing is installed automatically
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Updates {
+    /// Disabled by default, the node doesn't make any requests to the project servers then
+    #[serde(default)]
+    pub check: bool,
+    /// URL of the manifest of the last release
+    #[serde(default = "default_updates_url")]
+    pub url: String,
+    /// Public key (HEX) that signs the manifests
+    #[serde(default)]
+    pub key: String,
+    #[serde(default = "default_updates_interval")]
+    pub interval_hours: u64,
+    /// Plain HTTP URL that gets new versions by POST requests, empty to disable
+    #[serde(default)]
+    pub webhook: String,
+}
+
+impl Default for Updates {
+    fn default() -> Self {
+        Updates {
+            check: false,
+            url: default_updates_url(),
+            key: String::new(),
+            interval_hours: default_updates_interval(),
+            webhook: String::new()
+        }
+    }
+}
+
+/// Ways to find out our own address, for records of our domains
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AddressDetection {
+    /// Methods in order of preference: "interface" for Yggdrasil address, "stun" and "http" for public address
+    #[serde(default = "default_address_methods")]
+    pub methods: Vec<String>,
+    /// STUN servers with ports
+    #[serde(default = "default_stun_servers")]
+    pub stun_servers: Vec<String>,
+    /// Services that answer with the address of the client in plain text
+    #[serde(default = "default_echo_urls")]
+    pub echo_urls: Vec<String>,
+}
+
+impl Default for AddressDetection {
+    fn default() -> Self {
+        AddressDetection {
+            methods: default_address_methods(),
+            stun_servers: default_stun_servers(),
+            echo_urls: default_echo_urls()
+        }
+    }
+}
+
+/// Config of a reverse proxy with our domains, that is rewritten when they change
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Proxy {
+    /// Built-in template "nginx", "caddy" or "traefik", or a file with template of one domain
+    pub template: String,
+    /// File to write the config to
+    pub output: String,
+    /// Address of the local service, that gets requests for our domains, like "127.0.0.1:8080"
+    pub upstream: String,
+    /// Command to reload the proxy after the config is changed, like "nginx -s reload"
+    #[serde(default)]
+    pub reload: String,
+    /// Domains to put to the config, all our domains if empty
+    #[serde(default)]
+    pub domains: Vec<String>,
+}
+
+/// URL that gets new records of changed domains by POST requests
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Webhook {
+    /// Only plain HTTP is supported, like "http://127.0.0.1:8080/gis"
+    pub url: String,
+    /// Zones to watch, like "ygg"
+    #[serde(default)]
+    pub zones: Vec<String>,
+    /// Domains to watch, like "example.ygg", all changes are sent if there are no zones and domains
+    #[serde(default)]
+    pub domains: Vec<String>,
+}
+
+/// Memory budgets of caches and buffers, in megabytes, zero for no limit
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Memory {
+    #[serde(default = "default_dns_cache_mb")]
+    pub dns_cache_mb: usize,
+    /// Blocks of forks that peers have sent us
+    #[serde(default = "default_peer_buffers_mb")]
+    pub peer_buffers_mb: usize,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Memory {
+            dns_cache_mb: default_dns_cache_mb(),
+            peer_buffers_mb: default_peer_buffers_mb()
+        }
+    }
+}
+
+/// Transactions from other nodes, that wait for their miners
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Mempool {
+    /// How many transactions are kept, new ones are dropped when it is full
+    #[serde(default = "default_mempool_size")]
+    pub size: usize,
+    #[serde(default = "default_mempool_ttl_minutes")]
+    pub ttl_minutes: i64,
+    /// Mine transactions that other nodes have built for our key
+    #[serde(default)]
+    pub mine: bool,
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Mempool {
+            size: default_mempool_size(),
+            ttl_minutes: default_mempool_ttl_minutes(),
+            mine: false
+        }
+    }
+}
+
+/// Block validation rules, they must be the same for all nodes of one network (origin)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChainRules {
+    /// "mainnet" or "devnet", parameters that are not set separately are taken from it
+    #[serde(default)]
+    pub preset: String,
+    /// Check blocks against hashes bundled with this release
+    #[serde(default = "default_true")]
+    pub checkpoints: bool,
+    /// Separate parameters of `ConsensusParams`, like `zone_difficulty = 20`
+    #[serde(flatten)]
+    pub params: HashMap<String, toml::Value>,
+}
+
+impl ChainRules {
+    pub fn get_params(&self) -> Result<ConsensusParams, String> {
+        let preset = ConsensusParams::preset(&self.preset).ok_or(format!("Unknown chain preset '{}'", &self.preset))?;
+        let mut value = toml::Value::try_from(&preset).map_err(|e| e.to_string())?;
+        if let Some(table) = value.as_table_mut() {
+            for (name, param) in &self.params {
+                table.insert(name.clone(), param.clone());
+            }
+        }
+        let params: ConsensusParams = value.try_into().map_err(|e: toml::de::Error| e.to_string())?;
+        // Upgrades are not in settings, they come with the preset
+        Ok(ConsensusParams { upgrades: preset.upgrades, ..params })
+    }
+
+    /// Gets bundled checkpoints for this origin, or none if they are disabled
+    pub fn get_checkpoints(&self, origin: &Bytes) -> Checkpoints {
+        match self.checkpoints {
+            true => Checkpoints::bundled(origin),
+            false => Checkpoints::default()
+        }
+    }
+}
+
+impl Default for ChainRules {
+    fn default() -> Self {
+        ChainRules { preset: String::new(), checkpoints: true, params: HashMap::new() }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Net {
     #[serde(default)]
@@ -102,6 +675,15 @@ pub struct Net {
     pub public: bool,
     #[serde(default)]
     pub yggdrasil_only: bool,
+    /// Port that other nodes of this network listen on, it is given with addresses in peer exchange
+    #[serde(default = "default_peer_port")]
+    pub peer_port: u16,
+    /// Host names, that resolve to addresses of nodes, they are asked when bootstrap nodes are not enough
+    #[serde(default)]
+    pub seeds: Vec<String>,
+    /// File to save addresses of good peers to, empty to not save them
+    #[serde(default = "default_peers_file")]
+    pub peers_file: String,
 }
 
 impl Default for Net {
@@ -110,23 +692,218 @@ impl Default for Net {
             peers: vec![String::from(""), String::from("")],
             listen: String::from("[::]:46866"),
             public: true,
-            yggdrasil_only: false
+            yggdrasil_only: false,
+            peer_port: default_peer_port(),
+            seeds: Vec::new(),
+            peers_file: default_peers_file()
         }
     }
 }
 
+/// Chain with other origin, like a private corporate network, served by the same node
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExtraChain {
+    /// Name for logs and DNS traces
+    pub name: String,
+    #[serde(default)]
+    pub origin: String,
+    /// File of blockchain DB, or ":memory:"
+    pub db_file: String,
+    /// Network of this chain, it must listen on other port than the main one
+    #[serde(default)]
+    pub net: Net,
+    #[serde(default)]
+    pub chain: ChainRules,
+}
+
 fn default_listen() -> String {
     String::from("[::]:46866")
 }
 
-fn default_listen_dns() -> String {
-    String::from("0.0.0.0:53")
+fn default_peer_port() -> u16 {
+    LISTEN_PORT
+}
+
+fn default_watchlist_file() -> String {
+    String::from("watchlist.json")
+}
+
+fn default_peers_file() -> String {
+    String::from("peers.json")
+}
+
+fn default_storage_backend() -> String {
+    String::from("sqlite")
+}
+
+fn default_low_space_mb() -> u64 {
+    500
+}
+
+fn default_critical_space_mb() -> u64 {
+    50
+}
+
+fn default_read_connections() -> usize {
+    4
+}
+
+fn default_mempool_size() -> usize {
+    1000
+}
+
+fn default_mempool_ttl_minutes() -> i64 {
+    60
+}
+
+fn default_dns_cache_mb() -> usize {
+    32
+}
+
+fn default_dns_cache_entries() -> usize {
+    10000
+}
+
+fn default_dns_negative_ttl() -> u32 {
+    300
+}
+
+fn default_peer_buffers_mb() -> usize {
+    8
+}
+
+fn default_node_mode() -> String {
+    String::from("full")
+}
+
+fn default_headers_file() -> String {
+    String::from("headers.json")
+}
+
+fn default_api_rate_limit() -> u32 {
+    60
+}
+
+fn default_address_methods() -> Vec<String> {
+    vec![String::from("interface"), String::from("stun"), String::from("http")]
+}
+
+fn default_stun_servers() -> Vec<String> {
+    vec![String::from("stun.l.google.com:19302"), String::from("stun.cloudflare.com:3478")]
+}
+
+fn default_echo_urls() -> Vec<String> {
+    vec![String::from("https://api64.ipify.org"), String::from("https://ifconfig.co/ip")]
+}
+
+fn default_listen_dns() -> Vec<DnsListener> {
+    vec![DnsListener::new("0.0.0.0:53")]
+}
+
+fn default_fallback_port() -> u16 {
+    5053
 }
 
 fn default_threads() -> usize {
-    100
+    16
+}
+
+fn default_queue_size() -> usize {
+    1024
+}
+
+fn default_updates_url() -> String {
+    String::from("https://guasha.su/releases/latest.json")
+}
+
+fn default_updates_interval() -> u64 {
+    24
+}
+
+fn default_doh_path() -> String {
+    String::from("/dns-query")
+}
+
+fn default_forwarder_timeout() -> u64 {
+    10000
+}
+
+fn default_true() -> bool {
+    true
 }
 
 fn default_check_blocks() -> u64 {
     8
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dns_listeners() {
+        let dns: Dns = toml::from_str("forwarders = []\nlisten = \"127.0.0.1:53\"").unwrap();
+        assert_eq!(vec![DnsListener::new("127.0.0.1:53")], dns.listen);
+
+        let dns: Dns = toml::from_str("forwarders = []\nlisten = \"\"").unwrap();
+        assert!(dns.listen.is_empty());
+
+        let text = r#"
+            forwarders = []
+            listen = ["127.0.0.1:53", { address = "192.168.1.1:5353", allow = ["192.168.1.0/24"] }]
+        "#;
+        let dns: Dns = toml::from_str(text).unwrap();
+        assert_eq!(2, dns.listen.len());
+        assert_eq!("192.168.1.1:5353", dns.listen[1].address);
+        assert_eq!(vec![String::from("192.168.1.0/24")], dns.listen[1].allow);
+
+        let dns: Dns = toml::from_str("forwarders = []").unwrap();
+        assert_eq!(default_listen_dns(), dns.listen);
+    }
+
+    #[test]
+    fn extra_chains() {
+        let text = r#"
+            origin = "AA"
+            [[chains]]
+            name = "corp"
+            origin = "BB"
+            db_file = "corp.db"
+            net = { listen = "[::]:46867", peers = ["node.corp.lan:46867"], peer_port = 46867 }
+            chain = { preset = "devnet" }
+        "#;
+        let settings: Settings = toml::from_str(text).unwrap();
+        assert_eq!(LISTEN_PORT, settings.net.peer_port);
+        assert_eq!(1, settings.chains.len());
+        let corp = settings.for_chain(&settings.chains[0]);
+        assert_eq!("BB", corp.origin);
+        assert_eq!(46867, corp.net.peer_port);
+        assert_eq!("corp-peers.json", corp.net.peers_file);
+        assert_eq!("devnet", corp.chain.preset);
+        assert!(corp.chains.is_empty());
+    }
+
+    #[test]
+    fn chain_rules() {
+        let rules: ChainRules = toml::from_str("").unwrap();
+        assert_eq!(ConsensusParams::mainnet(), rules.get_params().unwrap());
+        assert!(rules.checkpoints);
+
+        let rules: ChainRules = toml::from_str("preset = \"devnet\"\nzone_difficulty = 20").unwrap();
+        let params = rules.get_params().unwrap();
+        assert_eq!(20, params.zone_difficulty);
+        assert_eq!(ConsensusParams::devnet().signer_difficulty, params.signer_difficulty);
+
+        let rules: ChainRules = toml::from_str("checkpoints = false\nzone_difficulty = 20").unwrap();
+        assert!(!rules.checkpoints);
+        assert_eq!(20, rules.get_params().unwrap().zone_difficulty);
+
+        let rules: ChainRules = toml::from_str("zone_dificulty = 20").unwrap();
+        assert!(rules.get_params().is_err());
+        let rules: ChainRules = toml::from_str("preset = \"testnet\"").unwrap();
+        assert!(rules.get_params().is_err());
+        // Upgrades come only with presets
+        let rules: ChainRules = toml::from_str("[[upgrades]]\nname = \"local\"\nbit = 0\nheight = 10").unwrap();
+        assert!(rules.get_params().is_err());
+    }
+}
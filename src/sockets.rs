@@ -0,0 +1,130 @@
+//! Listening sockets for DNS and P2P servers.
+//!
+//! Sockets can be inherited from systemd socket activation (see `contrib/systemd/gis.socket`),
+//! so that GIS doesn't need any privileges to listen on port 53. Otherwise they are bound as usual,
+//! and if the port is privileged and we have no permission for it, a fallback port is used.
+
+use std::io;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs, UdpSocket};
+use std::sync::Mutex;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// Ports below this one need root or CAP_NET_BIND_SERVICE on Linux
+const PRIVILEGED_PORTS_END: u16 = 1024;
+
+/// Sockets that were passed by systemd and not yet taken by our servers
+static INHERITED: Mutex<Option<Inherited>> = Mutex::new(None);
+
+#[derive(Default)]
+struct Inherited {
+    udp: Vec<UdpSocket>,
+    tcp: Vec<TcpListener>,
+}
+
+/// Takes inherited UDP socket for this address, or binds a new one
+pub fn bind_udp(address: &str, fallback_port: u16) -> io::Result<UdpSocket> {
+    bind(address, fallback_port, |inherited, addrs| take(&mut inherited.udp, addrs, |s| s.local_addr()), UdpSocket::bind)
+}
+
+/// Takes inherited TCP listener for this address, or binds a new one
+pub fn bind_tcp(address: &str, fallback_port: u16) -> io::Result<TcpListener> {
+    bind(address, fallback_port, |inherited, addrs| take(&mut inherited.tcp, addrs, |s| s.local_addr()), TcpListener::bind)
+}
+
+fn bind<S, T, B>(address: &str, fallback_port: u16, take: T, bind: B) -> io::Result<S>
+    where T: FnOnce(&mut Inherited, &[SocketAddr]) -> Option<S>, B: Fn(SocketAddr) -> io::Result<S> {
+    let addrs: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+    {
+        let mut inherited = INHERITED.lock().unwrap();
+        let inherited = inherited.get_or_insert_with(Inherited::from_env);
+        if let Some(socket) = take(inherited, &addrs) {
+            info!("Using socket for {} from systemd", address);
+            return Ok(socket);
+        }
+    }
+
+    let addr = match addrs.first() {
+        Some(addr) => *addr,
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "No address to bind"))
+    };
+    match bind(addr) {
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied && addr.port() < PRIVILEGED_PORTS_END && fallback_port != 0 => {
+            let mut fallback = addr;
+            fallback.set_port(fallback_port);
+            warn!("No permission to listen on {}, listening on {} instead!", addr, fallback);
+            warn!("To use port {} give GIS the capability by 'sudo setcap cap_net_bind_service=+ep gis', or use systemd socket activation", addr.port());
+            bind(fallback)
+        }
+        result => result
+    }
+}
+
+fn take<S, F: Fn(&S) -> io::Result<SocketAddr>>(sockets: &mut Vec<S>, addrs: &[SocketAddr], local_addr: F) -> Option<S> {
+    let position = sockets.iter().position(|s| matches!(local_addr(s), Ok(addr) if addrs.contains(&addr)))?;
+    Some(sockets.remove(position))
+}
+
+#[cfg(unix)]
+impl Inherited {
+    /// Takes sockets, that were passed by systemd, as described in sd_listen_fds(3)
+    fn from_env() -> Self {
+        use std::env;
+        use std::os::unix::io::{FromRawFd, RawFd};
+
+        /// The first passed file descriptor
+        const LISTEN_FDS_START: RawFd = 3;
+
+        let mut result = Inherited::default();
+        let pid = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok());
+        let count = env::var("LISTEN_FDS").ok().and_then(|count| count.parse::<RawFd>().ok()).unwrap_or(0);
+        if pid != Some(std::process::id()) || count <= 0 {
+            return result;
+        }
+        // These sockets are only for us, not for processes that we could start
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+
+        for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+            let mut socket_type: libc::c_int = 0;
+            let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+            let ok = unsafe {
+                libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+                libc::getsockopt(fd, libc::SOL_SOCKET, libc::SO_TYPE, &mut socket_type as *mut _ as *mut libc::c_void, &mut len) == 0
+            };
+            match socket_type {
+                libc::SOCK_DGRAM if ok => result.udp.push(unsafe { UdpSocket::from_raw_fd(fd) }),
+                libc::SOCK_STREAM if ok => result.tcp.push(unsafe { TcpListener::from_raw_fd(fd) }),
+                _ => warn!("Inherited file descriptor {} is not a UDP or TCP socket", fd)
+            }
+        }
+        info!("Got {} UDP and {} TCP sockets from systemd", result.udp.len(), result.tcp.len());
+        result
+    }
+}
+
+#[cfg(not(unix))]
+impl Inherited {
+    fn from_env() -> Self {
+        Inherited::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_by_address() {
+        let first = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let second = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = second.local_addr().unwrap();
+        let mut sockets = vec![first, second];
+        let taken = take(&mut sockets, &[addr], |s| s.local_addr()).unwrap();
+        assert_eq!(addr, taken.local_addr().unwrap());
+        assert_eq!(1, sockets.len());
+        assert!(take(&mut sockets, &[addr], |s| s.local_addr()).is_none());
+    }
+}
@@ -0,0 +1,148 @@
+//! Icon in system tray for desktop users, that run the node in background without the window.
+//! It shows the state of sync and mining, and its menu opens the window, pauses mining and quits.
+
+extern crate tray_item;
+
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use tray_item::{IconSource, TrayItem};
+
+use gis::{Context, Miner};
+use gis::dns::context::ServerContext;
+use gis::event::Event;
+
+use crate::web_ui;
+
+/// How often the labels are updated
+const UPDATE_INTERVAL_MS: u64 = 2000;
+/// Icon from resources of the executable on Windows, and icon from the theme on other systems
+#[cfg(windows)]
+const ICON: &str = "gis-icon";
+#[cfg(not(windows))]
+const ICON: &str = "network-workgroup";
+
+enum Command {
+    Open,
+    TogglePause,
+    Quit,
+}
+
+#[derive(Default)]
+struct Status {
+    syncing: Option<(u64, u64)>,
+    checking: Option<(u64, u64)>,
+    mining: bool,
+    nodes: usize,
+}
+
+impl Status {
+    fn sync_label(&self, height: u64) -> String {
+        match (self.checking, self.syncing) {
+            (Some((checked, height)), _) => format!("Checking: {} of {} blocks", checked, height),
+            (None, Some((have, height))) => format!("Syncing: {} of {} blocks", have, height),
+            (None, None) => format!("Blocks: {}, nodes: {}", height, self.nodes)
+        }
+    }
+
+    fn mining_label(&self, paused: bool) -> String {
+        match (paused, self.mining) {
+            (true, _) => String::from("Mining: paused"),
+            (false, true) => String::from("Mining: in progress"),
+            (false, false) => String::from("Mining: idle")
+        }
+    }
+}
+
+/// Shows the icon and handles its menu until the user quits
+pub fn run_tray(context: Arc<Mutex<Context>>, miner: Arc<Mutex<Miner>>, server_context: Arc<ServerContext>) {
+    let status = Arc::new(Mutex::new(Status::default()));
+    {
+        let status = Arc::clone(&status);
+        context.lock().unwrap().bus.register(move |_uuid, e| {
+            let mut status = status.lock().unwrap();
+            match e {
+                Event::Syncing { have, height } => status.syncing = Some((have, height)),
+                Event::SyncFinished => status.syncing = None,
+                Event::ChainCheckProgress { checked, height, finished } => status.checking = if finished { None } else { Some((checked, height)) },
+                Event::NetworkStatus { nodes, .. } => status.nodes = nodes,
+                Event::MinerStarted => status.mining = true,
+                Event::MinerStopped { .. } => status.mining = false,
+                _ => {}
+            }
+            true
+        });
+    }
+
+    let (sender, receiver) = channel();
+    let (mut tray, labels) = match make_tray(sender) {
+        Ok(result) => result,
+        Err(e) => {
+            error!("Unable to show tray icon: {}, opening the window instead", e);
+            web_ui::run_interface(context, miner, server_context, true);
+            return;
+        }
+    };
+
+    loop {
+        match receiver.recv_timeout(Duration::from_millis(UPDATE_INTERVAL_MS)) {
+            Ok(Command::Open) => web_ui::run_interface(Arc::clone(&context), Arc::clone(&miner), Arc::clone(&server_context), false),
+            Ok(Command::TogglePause) => {
+                let mut miner = miner.lock().unwrap();
+                if miner.is_paused() {
+                    info!("Mining is resumed from tray");
+                    miner.resume();
+                } else {
+                    info!("Mining is paused from tray");
+                    miner.pause();
+                }
+            }
+            Ok(Command::Quit) | Err(RecvTimeoutError::Disconnected) => {
+                info!("Quit from tray, exiting");
+                context.lock().unwrap().bus.post(Event::ActionQuit);
+                thread::sleep(Duration::from_millis(100));
+                break;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        let height = context.lock().unwrap().chain.get_height();
+        let paused = miner.lock().unwrap().is_paused();
+        let (sync, mining, pause) = {
+            let status = status.lock().unwrap();
+            (status.sync_label(height), status.mining_label(paused), if paused { "Resume mining" } else { "Pause mining" })
+        };
+        let tray = tray.inner_mut();
+        let result = tray.set_menu_item_label(&sync, labels.sync)
+            .and_then(|_| tray.set_menu_item_label(&mining, labels.mining))
+            .and_then(|_| tray.set_menu_item_label(pause, labels.pause));
+        if let Err(e) = result {
+            debug!("Unable to update tray menu: {}", e);
+        }
+    }
+}
+
+/// Ids of menu items that change
+struct Labels {
+    sync: u32,
+    mining: u32,
+    /// Pause or resume item
+    pause: u32,
+}
+
+fn make_tray(sender: Sender<Command>) -> Result<(TrayItem, Labels), tray_item::TIError> {
+    let title = format!("GIS {}", env!("CARGO_PKG_VERSION"));
+    let mut item = TrayItem::new(&title, IconSource::Resource(ICON))?;
+    let sync = item.inner_mut().add_menu_item_with_id("Starting...", || {})?;
+    let mining = item.inner_mut().add_menu_item_with_id("Mining: idle", || {})?;
+    item.inner_mut().add_separator()?;
+    let open = sender.clone();
+    item.add_menu_item("Open GIS", move || { let _ = open.send(Command::Open); })?;
+    let toggle = sender.clone();
+    let pause = item.inner_mut().add_menu_item_with_id("Pause mining", move || { let _ = toggle.send(Command::TogglePause); })?;
+    item.add_menu_item("Quit", move || { let _ = sender.send(Command::Quit); })?;
+    Ok((item, Labels { sync, mining, pause }))
+}
@@ -0,0 +1,189 @@
+//! Opt-in check for new releases. The manifest of the last release is signed by the release key from settings,
+//! so a hijacked download page can't make nodes believe in fake versions. Nothing is installed, users are only notified.
+
+use std::cmp::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{from_hex, Bytes, Context, Keystore};
+use crate::api::http::post_json;
+use crate::event::Event;
+use crate::settings::Updates;
+
+/// Time to wait for the manifest
+const MANIFEST_TIMEOUT_SEC: u64 = 30;
+/// Version of this build
+const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReleaseManifest {
+    pub version: String,
+    /// Page or archive of the release
+    pub url: String,
+    /// Older versions can't follow the network after a protocol upgrade, empty if there is no such upgrade
+    #[serde(default)]
+    pub min_version: String,
+    /// Block from which `min_version` is needed
+    #[serde(default)]
+    pub required_from: u64,
+    #[serde(default)]
+    pub notes: String,
+}
+
+/// Manifest as it is published, the text of the manifest is signed as is, to avoid any canonical JSON
+#[derive(Debug, Deserialize)]
+struct SignedManifest {
+    manifest: String,
+    signature: Bytes,
+}
+
+/// Body of the webhook request, and what is shown to the user
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct UpdateNotice {
+    pub current: String,
+    pub version: String,
+    pub url: String,
+    /// This version is older than `min_version` of the release
+    pub required: bool,
+    pub required_from: u64,
+}
+
+/// Parses published manifest and checks its signature
+pub fn parse_manifest(text: &str, key: &Bytes) -> Result<ReleaseManifest, String> {
+    let signed: SignedManifest = serde_json::from_str(text).map_err(|e| format!("Wrong manifest: {}", e))?;
+    if !Keystore::check(signed.manifest.as_bytes(), key, &signed.signature) {
+        return Err(String::from("Wrong signature of the manifest"));
+    }
+    serde_json::from_str(&signed.manifest).map_err(|e| format!("Wrong manifest: {}", e))
+}
+
+/// Compares versions like `0.9.1` by numbers, suffixes like `-beta` are ignored
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parse = |version: &str| -> Vec<u64> {
+        version.trim_start_matches('v')
+            .split(|c| c == '-' || c == '+')
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map(|part| part.parse::<u64>().unwrap_or(0))
+            .collect()
+    };
+    let (a, b) = (parse(a), parse(b));
+    for i in 0..a.len().max(b.len()) {
+        let ordering = a.get(i).unwrap_or(&0).cmp(b.get(i).unwrap_or(&0));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Makes a notice if the release is newer than `current`
+pub fn check_release(manifest: &ReleaseManifest, current: &str) -> Option<UpdateNotice> {
+    if compare_versions(&manifest.version, current) != Ordering::Greater {
+        return None;
+    }
+    let required = !manifest.min_version.is_empty() && compare_versions(current, &manifest.min_version) == Ordering::Less;
+    Some(UpdateNotice {
+        current: current.to_owned(),
+        version: manifest.version.clone(),
+        url: manifest.url.clone(),
+        required,
+        required_from: manifest.required_from,
+    })
+}
+
+/// Checks for new releases periodically in a separate thread, if it is enabled in settings
+pub fn start_update_checker(context: &Arc<Mutex<Context>>, settings: &Updates) {
+    if !settings.check {
+        return;
+    }
+    let key = match from_hex(&settings.key) {
+        Ok(key) if key.len() == 32 => Bytes::from_bytes(&key),
+        _ => {
+            warn!("Update check is disabled, it needs a release key (HEX) in [updates] settings");
+            return;
+        }
+    };
+    let context = Arc::clone(context);
+    let settings = settings.clone();
+    let _ = thread::Builder::new().name(String::from("Updates")).spawn(move || {
+        let mut notified = String::new();
+        loop {
+            match fetch_manifest(&settings.url, &key) {
+                Ok(manifest) => {
+                    if let Some(notice) = check_release(&manifest, CURRENT_VERSION) {
+                        if notice.version != notified {
+                            notify(&context, &settings, &notice);
+                            notified = notice.version;
+                        }
+                    }
+                }
+                Err(e) => debug!("Unable to check for updates: {}", e)
+            }
+            thread::sleep(Duration::from_secs(settings.interval_hours.max(1) * 3600));
+        }
+    });
+}
+
+fn fetch_manifest(url: &str, key: &Bytes) -> Result<ReleaseManifest, String> {
+    let response = minreq::get(url)
+        .with_timeout(MANIFEST_TIMEOUT_SEC)
+        .send()
+        .map_err(|e| e.to_string())?;
+    if response.status_code != 200 {
+        return Err(format!("Status {}", response.status_code));
+    }
+    parse_manifest(response.as_str().map_err(|e| e.to_string())?, key)
+}
+
+fn notify(context: &Arc<Mutex<Context>>, settings: &Updates, notice: &UpdateNotice) {
+    if notice.required {
+        warn!("Version {} is required by the network from block {}, you have {}! Get it from {}", &notice.version, notice.required_from, &notice.current, &notice.url);
+    } else {
+        info!("New version {} is available at {}", &notice.version, &notice.url);
+    }
+    let event = Event::UpdateAvailable { version: notice.version.clone(), url: notice.url.clone(), required: notice.required };
+    context.lock().unwrap().bus.post(event);
+    if !settings.webhook.is_empty() {
+        match post_json(&settings.webhook, &serde_json::to_vec(notice).unwrap()) {
+            Ok(status) if (200..300).contains(&status) => {}
+            Ok(status) => warn!("Update webhook {} answered with status {}", &settings.webhook, status),
+            Err(e) => warn!("Unable to send update notice to {}: {}", &settings.webhook, e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions() {
+        assert_eq!(Ordering::Greater, compare_versions("0.10.0", "0.9.9"));
+        assert_eq!(Ordering::Equal, compare_versions("v0.9", "0.9.0"));
+        assert_eq!(Ordering::Less, compare_versions("0.9.0-beta", "0.9.1"));
+    }
+
+    #[test]
+    fn manifest() {
+        let keystore = Keystore::new();
+        let text = r#"{"version":"1.0.0","url":"https://example.com/gis","min_version":"0.9.5","required_from":150000}"#;
+        let signature = Bytes::from_bytes(&keystore.sign(text.as_bytes()));
+        let signed = serde_json::json!({ "manifest": text, "signature": signature }).to_string();
+
+        let manifest = parse_manifest(&signed, &keystore.get_public()).unwrap();
+        assert!(parse_manifest(&signed, &Keystore::new().get_public()).is_err());
+
+        let notice = check_release(&manifest, "0.9.0").unwrap();
+        assert!(notice.required);
+        assert_eq!(150000, notice.required_from);
+        assert!(!check_release(&manifest, "0.9.5").unwrap().required);
+        assert_eq!(None, check_release(&manifest, "1.0.0"));
+    }
+}
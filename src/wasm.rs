@@ -0,0 +1,79 @@
+//! Bindings for web explorers and browser extensions, to verify domain data on the client side.
+//! Build them with `wasm-pack build --target web -- --no-default-features --features wasm`.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{Block, Bytes, from_hex};
+use crate::blockchain::consensus::Schedule;
+use crate::blockchain::hash_utils::{check_block_hash, check_block_signature};
+use crate::blockchain::ownership::OwnershipProof;
+use crate::blockchain::proof::{ChainProof, DomainProof};
+use crate::dns::zonefile;
+
+/// Returns identity hash of domain or zone, as it is stored in blocks
+#[wasm_bindgen(js_name = hashIdentity)]
+pub fn hash_identity(name: &str) -> String {
+    crate::blockchain::hash_utils::hash_identity(&name.to_lowercase(), None).to_string()
+}
+
+/// Verifies signed statement that some key owns a domain, returns the domain transaction in JSON.
+/// Hashes of the domain block and of the block at statement height must be compared with chain data by caller.
+#[wasm_bindgen(js_name = verifyOwnershipProof)]
+pub fn verify_ownership_proof(proof: &str) -> Result<String, JsValue> {
+    let proof: OwnershipProof = serde_json::from_str(proof).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let transaction = proof.verify(&Schedule::default()).map_err(|e| JsValue::from_str(&e))?;
+    serde_json::to_string(&transaction).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Returns human-readable fingerprint of public key, given in HEX
+#[wasm_bindgen(js_name = keyFingerprint)]
+pub fn key_fingerprint(public_key: &str) -> Result<String, JsValue> {
+    let key = from_hex(public_key).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    Ok(crate::fingerprint::get_fingerprint(&key))
+}
+
+/// Checks hash and signature of a block in JSON
+#[wasm_bindgen(js_name = verifyBlock)]
+pub fn verify_block(block: &str) -> bool {
+    match serde_json::from_str::<Block>(block) {
+        Ok(block) => check_block_hash(&block) && check_block_signature(&block),
+        Err(_) => false
+    }
+}
+
+/// Verifies hash-chain proof up to the trusted block hash, returns index of the first block
+#[wasm_bindgen(js_name = verifyChainProof)]
+pub fn verify_chain_proof(proof: &str, trusted_hash: &str) -> Result<u64, JsValue> {
+    let proof: ChainProof = serde_json::from_str(proof).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let trusted = parse_hash(trusted_hash)?;
+    match proof.verify(&trusted) {
+        Some(block) => Ok(block.index),
+        None => Err(JsValue::from_str("Wrong proof"))
+    }
+}
+
+/// Verifies proof of domain data up to the trusted block hash, returns domain data in JSON
+#[wasm_bindgen(js_name = verifyDomainProof)]
+pub fn verify_domain_proof(proof: &str, domain: &str, trusted_hash: &str) -> Result<String, JsValue> {
+    let proof: DomainProof = serde_json::from_str(proof).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let trusted = parse_hash(trusted_hash)?;
+    let schedule = Schedule::default();
+    match proof.verify_to(&domain.to_lowercase(), schedule.at(proof.block.index), &trusted) {
+        Some(transaction) => Ok(transaction.data),
+        None => Err(JsValue::from_str("Wrong proof"))
+    }
+}
+
+/// Parses records of some domain from zone file, returns them in JSON, the same way as they are stored in blocks
+#[wasm_bindgen(js_name = parseDomainRecords)]
+pub fn parse_domain_records(text: &str, domain: &str) -> Result<String, JsValue> {
+    let records = zonefile::parse_domain_records(text, &domain.to_lowercase()).map_err(|e| JsValue::from_str(&e))?;
+    Ok(serde_json::to_string(&records).unwrap())
+}
+
+fn parse_hash(hash: &str) -> Result<Bytes, JsValue> {
+    match from_hex(hash) {
+        Ok(bytes) if bytes.len() == 32 => Ok(Bytes::from_bytes(&bytes)),
+        _ => Err(JsValue::from_str("Wrong hash"))
+    }
+}
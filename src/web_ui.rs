@@ -8,26 +8,34 @@ use std::sync::{Arc, Mutex, MutexGuard};
 use std::thread;
 use std::time::{Duration, Instant};
 
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, Utc};
 #[allow(unused_imports)]
 use log::{debug, error, info, LevelFilter, trace, warn};
 use serde::Deserialize;
 use web_view::Content;
 
-use gis::{Block, Bytes, Context, get_domain_zone, Keystore, Transaction, ZONE_MIN_DIFFICULTY, is_yggdrasil_record};
+use gis::{Block, Bytes, Context, format_wait_time, get_domain_zone, Keystore, Transaction, is_yggdrasil_record};
 use gis::{check_domain, keys};
 use gis::blockchain::transaction::{DomainData, ZoneData};
 use gis::blockchain::types::MineResult;
-use gis::commons::{ZONE_DIFFICULTY, ZONE_MAX_LENGTH, CLASS_DOMAIN, CLASS_ZONE};
-use gis::dns::protocol::DnsRecord;
+use gis::commons::{ZONE_MAX_LENGTH, CLASS_DOMAIN, CLASS_RENEW, CLASS_TRANSFER, CLASS_ZONE};
+use gis::dns::context::ServerContext;
+use gis::dns::protocol::QueryType;
+use gis::dns::trace::trace_resolve;
+use gis::dns::zonefile::parse_domain_records;
+use gis::dns::templates::{APEX, expand_records, needs_node_address};
+use gis::address::get_node_address;
 use gis::event::Event;
+use gis::disk::DiskState;
 use gis::miner::Miner;
 use Cmd::*;
 
 use self::web_view::{Handle, WebView};
 use gis::blockchain::hash_utils::hash_identity;
+use gis::fingerprint::get_fingerprint;
 
-pub fn run_interface(context: Arc<Mutex<Context>>, miner: Arc<Mutex<Miner>>) {
+/// Shows the window until it is closed, then the node quits if `quit_on_close` is set, or keeps running in background (in tray mode)
+pub fn run_interface(context: Arc<Mutex<Context>>, miner: Arc<Mutex<Miner>>, server_context: Arc<ServerContext>, quit_on_close: bool) {
     let file_content = include_str!("webview/index.html");
     let mut styles = inline_style(include_str!("webview/bulma.css"));
     styles.push_str(&inline_style(include_str!("webview/styles.css")));
@@ -53,14 +61,22 @@ pub fn run_interface(context: Arc<Mutex<Context>>, miner: Arc<Mutex<Miner>>) {
                 SaveKey => { action_save_key(&context); }
                 CheckRecord { data } => { action_check_record(web_view, data); }
                 CheckDomain { name } => { action_check_domain(&context, web_view, name); }
-                MineDomain { name, data } => {
-                    action_create_domain(Arc::clone(&context), Arc::clone(&miner), web_view, name, data);
+                ImportZoneFile { name } => { action_import_zonefile(web_view, name); }
+                MineDomain { name, data, confirmed } => {
+                    action_create_domain(Arc::clone(&context), Arc::clone(&miner), web_view, name, data, confirmed);
+                }
+                RenewDomain { name } => {
+                    action_renew_domain(Arc::clone(&context), Arc::clone(&miner), web_view, name);
+                }
+                TransferDomain { name, owner } => {
+                    action_transfer_domain(Arc::clone(&context), Arc::clone(&miner), web_view, name, owner);
                 }
-                TransferDomain { .. } => {}
                 CheckZone { name } => { action_check_zone(&context, web_view, name); }
                 MineZone { name, data } => {
                     action_create_zone(Arc::clone(&context), Arc::clone(&miner), web_view, name, data);
                 }
+                TraceResolve { name, qtype } => { action_trace_resolve(Arc::clone(&server_context), web_view, name, qtype); }
+                ZoneStats => { action_zone_stats(&context, web_view); }
                 StopMining => { context.lock().unwrap().bus.post(Event::ActionStopMining); }
                 Open { link } => {
                     if open::that(&link).is_err() {
@@ -74,12 +90,12 @@ pub fn run_interface(context: Arc<Mutex<Context>>, miner: Arc<Mutex<Miner>>) {
         .expect("Error building GUI");
 
     let mut context = Arc::clone(&context);
-    run_interface_loop(&mut context, &mut interface);
+    run_interface_loop(&mut context, &mut interface, quit_on_close);
     interface.exit();
 }
 
 /// Indefinitely loops through WebView steps
-fn run_interface_loop(context: &mut Arc<Mutex<Context>>, interface: &mut WebView<()>) {
+fn run_interface_loop(context: &mut Arc<Mutex<Context>>, interface: &mut WebView<()>, quit_on_close: bool) {
     // We use this ugly loop to lower CPU usage a lot.
     // If we use .run() or only .step() in a loop without sleeps it will try
     // to support 60FPS and uses more CPU than it should.
@@ -88,6 +104,10 @@ fn run_interface_loop(context: &mut Arc<Mutex<Context>>, interface: &mut WebView
     loop {
         match interface.step() {
             None => {
+                if !quit_on_close {
+                    info!("Interface closed, working in background");
+                    break;
+                }
                 info!("Interface closed, exiting");
                 context.lock().unwrap().bus.post(Event::ActionQuit);
                 thread::sleep(Duration::from_millis(100));
@@ -124,9 +144,54 @@ fn action_check_zone(context: &Arc<Mutex<Context>>, web_view: &mut WebView<()>,
 }
 
 fn action_check_record(web_view: &mut WebView<()>, data: String) {
-    match serde_json::from_str::<DnsRecord>(&data) {
+    // Placeholders stay in the record until the domain is mined, but they must be expandable
+    let node = if needs_node_address(&data) { get_node_address() } else { None };
+    let record = serde_json::from_str::<serde_json::Value>(&data).map_err(|e| e.to_string())
+        .and_then(|record| expand_records(vec![record], APEX, node));
+    match record {
         Ok(_) => { web_view.eval("recordOkay(true)").expect("Error evaluating!"); }
-        Err(e) => { web_view.eval("recordOkay(false)").expect("Error evaluating!"); dbg!(e); }
+        Err(e) => {
+            web_view.eval("recordOkay(false)").expect("Error evaluating!");
+            if needs_node_address(&data) {
+                show_warning(web_view, &e);
+            }
+            debug!("Wrong record: {}", e);
+        }
+    }
+}
+
+/// Parses domain data from the form, expanding placeholders in its records
+fn parse_domain_data(name: &str, data: &str) -> Result<DomainData, String> {
+    let mut value = serde_json::from_str::<serde_json::Value>(data).map_err(|e| e.to_string())?;
+    let node = if needs_node_address(data) { get_node_address() } else { None };
+    if let Some(records) = value.get_mut("records") {
+        let list = serde_json::from_value(records.take()).map_err(|e| e.to_string())?;
+        *records = serde_json::to_value(expand_records(list, name, node)?).unwrap();
+    }
+    serde_json::from_value(value).map_err(|e| e.to_string())
+}
+
+fn action_import_zonefile(web_view: &mut WebView<()>, name: String) {
+    let file_name = match tfd::open_file_dialog("Open zone file", "", None) {
+        None => return,
+        Some(file_name) => file_name
+    };
+    let text = match std::fs::read_to_string(&file_name) {
+        Ok(text) => text,
+        Err(e) => {
+            show_warning(web_view, &format!("Unable to read zone file: {}", e));
+            return;
+        }
+    };
+    match parse_domain_records(&text, &name) {
+        Ok(records) => {
+            let records = serde_json::to_string(&records).unwrap();
+            let _ = web_view.eval(&format!("importRecords({});", &records));
+        }
+        Err(e) => {
+            warn!("Error in zone file '{}': {}", &file_name, &e);
+            show_warning(web_view, &format!("Some records can't be imported:<br>{}", e.replace('\n', "<br>")));
+        }
     }
 }
 
@@ -136,6 +201,43 @@ fn action_check_domain(context: &Arc<Mutex<Context>>, web_view: &mut WebView<()>
         let name = name.to_lowercase();
         let available = c.get_chain().is_domain_available(c.get_chain().get_height(), &name, &keystore);
         web_view.eval(&format!("domainAvailable({})", available)).expect("Error evaluating!");
+        if available {
+            let report = c.get_chain().find_confusables(&name);
+            if !report.confusables.is_empty() {
+                let names: Vec<String> = report.confusables.iter().map(|c| format!("{} ({})", c.unicode, c.domain)).collect();
+                show_warning(web_view, &format!("This name looks like existing domains:<br>{}", names.join("<br>")));
+            } else if report.mixed_scripts {
+                show_warning(web_view, "This name mixes letters of several scripts, it can be taken for another name.");
+            }
+        }
+    }
+}
+
+fn action_trace_resolve(server_context: Arc<ServerContext>, web_view: &mut WebView<()>, name: String, qtype: String) {
+    let qtype = QueryType::from_name(&qtype).unwrap_or(QueryType::A);
+    let handle = web_view.handle();
+    // Resolving can take some time, we don't want to freeze the interface
+    thread::spawn(move || {
+        let trace = trace_resolve(server_context, &name, qtype);
+        if let Ok(trace) = serde_json::to_string(&trace) {
+            let _ = handle.dispatch(move |web_view| {
+                web_view.eval(&format!("showTrace({});", &trace))
+            });
+        }
+    });
+}
+
+fn action_zone_stats(context: &Arc<Mutex<Context>>, web_view: &mut WebView<()>) {
+    let c = context.lock().unwrap();
+    let stats: Vec<_> = match c.get_keystore() {
+        Some(keystore) => c.get_chain().get_owned_zones(&keystore.get_public())
+            .iter()
+            .map(|zone| c.get_chain().get_zone_stats(zone))
+            .collect(),
+        None => Vec::new()
+    };
+    if let Ok(stats) = serde_json::to_string(&stats) {
+        web_view.eval(&format!("showZoneStats({});", &stats)).expect("Error evaluating!");
     }
 }
 
@@ -152,7 +254,10 @@ fn action_save_key(context: &Arc<Mutex<Context>>) {
             if let Some(mut keystore) = context.get_keystore() {
                 let public = keystore.get_public().to_string();
                 let hash = keystore.get_hash().to_string();
-                keystore.save(&new_path, "");
+                if let Err(e) = keystore.save(&new_path, "") {
+                    error!("Unable to save key file to {}: {}", &path, e);
+                    return;
+                }
                 info!("Key file saved to {}", &path);
                 context.bus.post(Event::KeySaved { path, public, hash });
             }
@@ -172,7 +277,7 @@ fn action_load_key(context: &Arc<Mutex<Context>>, web_view: &mut WebView<()>) {
                     event_fail(web_view, &format!("Error loading key from \\'{}\\'!", &file_name));
                 }
                 Some(keystore) => {
-                    info!("Loaded keystore with key: {:?}", &keystore.get_public());
+                    info!("Loaded keystore with key: {:?} ({})", &keystore.get_public(), keystore.get_fingerprint());
                     let mut c = context.lock().unwrap();
                     let path = keystore.get_path().to_owned();
                     let public = keystore.get_public().to_string();
@@ -209,14 +314,14 @@ fn action_loaded(context: &Arc<Mutex<Context>>, web_view: &mut WebView<()>) {
                 Event::KeyCreated { path, public, hash } => {
                     load_domains(&mut context, &handle);
                     event_handle_luck(&handle, "Key successfully created! Don\\'t forget to save it!");
-                    let mut s = format!("keystoreChanged('{}', '{}', '{}');", &path, &public, &hash);
+                    let mut s = format!("keystoreChanged('{}', '{}', '{}', '{}');", &path, &public, &hash, get_hex_fingerprint(&public));
                     s.push_str(" showSuccess('New key mined successfully! Save it to a safe place!')");
                     s
                 }
                 Event::KeyLoaded { path, public, hash } |
                 Event::KeySaved { path, public, hash } => {
                     load_domains(&mut context, &handle);
-                    format!("keystoreChanged('{}', '{}', '{}');", &path, &public, &hash)
+                    format!("keystoreChanged('{}', '{}', '{}', '{}');", &path, &public, &hash, get_hex_fingerprint(&public))
                 }
                 Event::MinerStarted | Event::KeyGeneratorStarted => {
                     status.mining = true;
@@ -289,6 +394,14 @@ fn action_loaded(context: &Arc<Mutex<Context>>, web_view: &mut WebView<()>) {
                         format!("setLeftStatusBarText('Idle'); showMiningIndicator(false, false);")
                     }
                 }
+                Event::ChainCheckProgress { checked, height, finished } => {
+                    if finished {
+                        event_handle_info(&handle, "Blockchain check finished.");
+                        String::from("setLeftStatusBarText('Idle');")
+                    } else {
+                        format!("setLeftStatusBarText('Checking blocks {}/{}');", checked, height)
+                    }
+                }
                 Event::NetworkStatus { nodes, blocks } => {
                     if status.mining || status.syncing || nodes < 3 {
                         format!("setRightStatusBarText('Nodes: {}, Blocks: {}')", nodes, blocks)
@@ -305,11 +418,39 @@ fn action_loaded(context: &Arc<Mutex<Context>>, web_view: &mut WebView<()>) {
                     }
                     String::new() // Nothing
                 }
+                Event::ChainReorged { index, removed, added } => {
+                    event_handle_info(&handle, &format!("Blockchain switched to a better branch at block {}, {} blocks replaced by {}.", index, removed, added));
+                    String::new() // Nothing
+                }
                 Event::BlockchainChanged {index} => {
                     debug!("Current blockchain height is {}", index);
                     event_handle_info(&handle, &format!("Blockchain changed, current block count is {} now.", index));
                     String::new() // Nothing
                 }
+                Event::DiskSpace { state, free_mb } => {
+                    match state {
+                        DiskState::Normal => String::new(),
+                        DiskState::Low => format!("showWarning('Free disk space is low: {} MB');", free_mb),
+                        DiskState::Critical => format!("showWarning('Not enough disk space ({} MB), new blocks are not accepted!');", free_mb)
+                    }
+                }
+                Event::SettingsChanged { changed } => {
+                    event_handle_info(&handle, &format!("Settings are reloaded, changed: {}", changed.join(", ")));
+                    String::new() // Nothing
+                }
+                Event::WatchedDomain { domain, kind, .. } => {
+                    event_handle_info(&handle, &format!("Watched domain {} is {}", domain, kind));
+                    String::new()
+                }
+                Event::UpdateAvailable { version, url, required } => {
+                    match required {
+                        true => format!("showWarning('Version {} is required by the network, please update: {}');", version, url),
+                        false => {
+                            event_handle_info(&handle, &format!("New version {} is available at {}", version, url));
+                            String::new()
+                        }
+                    }
+                }
                 _ => { String::new() }
             };
 
@@ -354,9 +495,15 @@ fn load_domains(context: &mut MutexGuard<Context>, handle: &Handle<()>) {
     let _ = handle.dispatch(move |web_view|{
         web_view.eval("refreshMyDomains();")
     });
+    if let Some(keystore) = &context.keystore {
+        if let Some(next) = context.chain.next_allowed_domain_time(&keystore.get_public()) {
+            let wait = format_wait_time(next - Utc::now().timestamp());
+            event_handle_info(handle, &format!("You can register a new domain in {}", wait));
+        }
+    }
 }
 
-fn action_create_domain(context: Arc<Mutex<Context>>, miner: Arc<Mutex<Miner>>, web_view: &mut WebView<()>, name: String, data: String) {
+fn action_create_domain(context: Arc<Mutex<Context>>, miner: Arc<Mutex<Miner>>, web_view: &mut WebView<()>, name: String, data: String, confirmed: bool) {
     debug!("Creating domain with data: {}", &data);
     let c = Arc::clone(&context);
     let context = context.lock().unwrap();
@@ -371,10 +518,10 @@ fn action_create_domain(context: Arc<Mutex<Context>>, miner: Arc<Mutex<Miner>>,
     }
     let keystore = context.get_keystore().unwrap();
     let pub_key = keystore.get_public();
-    let data = match serde_json::from_str::<DomainData>(&data) {
+    let data = match parse_domain_data(&name, &data) {
         Ok(data) => { data }
         Err(e) => {
-            show_warning(web_view, "Something wrong with domain data. I cannot mine it.");
+            show_warning(web_view, &format!("Something wrong with domain data. I cannot mine it.<br>{}", e));
             warn!("Error parsing data: {}", e);
             return;
         }
@@ -393,8 +540,21 @@ fn action_create_domain(context: Arc<Mutex<Context>>, miner: Arc<Mutex<Miner>>,
             }
         }
     }
-    match context.chain.can_mine_domain(context.chain.get_height(), &name, &pub_key) {
+    match context.chain.can_mine_domain(context.chain.get_height(), &name, &pub_key, &data) {
         MineResult::Fine => {
+            // Updates are shown as a diff first, and identical ones are not mined at all
+            if let Some(current) = context.chain.get_domain_transaction(&name).and_then(|t| t.get_domain_data()) {
+                let diff = current.diff(&data);
+                if diff.is_empty() {
+                    show_warning(web_view, "These records are already in blockchain, nothing to update.");
+                    return;
+                }
+                if !confirmed {
+                    let diff = serde_json::to_string(&diff).unwrap();
+                    let _ = web_view.eval(&format!("confirmDomainUpdate({});", diff));
+                    return;
+                }
+            }
             let zone = get_domain_zone(&name);
             let difficulty = context.chain.get_zone_difficulty(&zone);
             std::mem::drop(context);
@@ -408,12 +568,80 @@ fn action_create_domain(context: Arc<Mutex<Context>>, miner: Arc<Mutex<Miner>>,
         MineResult::WrongZone => { show_warning(web_view, "You can't mine domain in this zone!"); }
         MineResult::NotOwned => { show_warning(web_view, "This domain is already taken, and it is not yours!"); }
         MineResult::Cooldown { time } => {
-            event_info(web_view, &format!("You have cooldown, just {} more minutes!", time / 60));
-            show_warning(web_view, &format!("You have cooldown, just {} more minutes!", time / 60));
+            let message = format!("You can register a new domain in {}", format_wait_time(time));
+            event_info(web_view, &message);
+            show_warning(web_view, &message);
         }
     }
 }
 
+fn action_renew_domain(context: Arc<Mutex<Context>>, miner: Arc<Mutex<Miner>>, web_view: &mut WebView<()>, name: String) {
+    let c = Arc::clone(&context);
+    let context = context.lock().unwrap();
+    let keystore = match context.get_keystore() {
+        Some(keystore) => keystore,
+        None => {
+            show_warning(web_view, "You don't have keys loaded!<br>Load or mine the keys and try again.");
+            return;
+        }
+    };
+    if context.chain.is_waiting_signers() {
+        show_warning(web_view, "Waiting for last full block to be signed. Try again later.");
+        info!("Waiting for last full block to be signed. Try again later.");
+        return;
+    }
+    let data = match context.chain.get_renewal_data(&name, &keystore.get_public()) {
+        Ok(data) => data,
+        Err(e) => {
+            warn!("Can't renew domain {}: {}", &name, &e);
+            show_warning(web_view, &format!("You can't renew this domain!<br>{}", e));
+            return;
+        }
+    };
+    let difficulty = context.chain.get_zone_difficulty(&get_domain_zone(&name));
+    std::mem::drop(context);
+    create_domain(c, miner, CLASS_RENEW, &name, data, difficulty, &keystore);
+    let _ = web_view.eval("domainMiningStarted();");
+    event_info(web_view, &format!("Renewal of domain \\'{}\\' has started", &name));
+}
+
+fn action_transfer_domain(context: Arc<Mutex<Context>>, miner: Arc<Mutex<Miner>>, web_view: &mut WebView<()>, name: String, owner: String) {
+    let c = Arc::clone(&context);
+    let context = context.lock().unwrap();
+    let keystore = match context.get_keystore() {
+        Some(keystore) => keystore,
+        None => {
+            show_warning(web_view, "You don't have keys loaded!<br>Load or mine the keys and try again.");
+            return;
+        }
+    };
+    if context.chain.is_waiting_signers() {
+        show_warning(web_view, "Waiting for last full block to be signed. Try again later.");
+        info!("Waiting for last full block to be signed. Try again later.");
+        return;
+    }
+    let new_owner = match gis::from_hex(&owner) {
+        Ok(key) if key.len() == 32 => Bytes::from_bytes(&key),
+        _ => {
+            show_warning(web_view, "Wrong public key of the new owner!");
+            return;
+        }
+    };
+    let data = match context.chain.get_transfer_data(&name, &keystore, new_owner) {
+        Ok(data) => data,
+        Err(e) => {
+            warn!("Can't transfer domain {}: {}", &name, &e);
+            show_warning(web_view, &format!("You can't transfer this domain!<br>{}", e));
+            return;
+        }
+    };
+    let difficulty = context.chain.get_zone_difficulty(&get_domain_zone(&name));
+    std::mem::drop(context);
+    create_domain(c, miner, CLASS_TRANSFER, &name, data, difficulty, &keystore);
+    let _ = web_view.eval("domainMiningStarted();");
+    event_info(web_view, &format!("Transfer of domain \\'{}\\' has started", &name));
+}
+
 fn action_create_zone(context: Arc<Mutex<Context>>, miner: Arc<Mutex<Miner>>, web_view: &mut WebView<()>, name: String, data: String) {
     if context.lock().unwrap().chain.is_waiting_signers() {
         show_warning(web_view, "Waiting for last full block to be signed. Try again later.");
@@ -427,12 +655,16 @@ fn action_create_zone(context: Arc<Mutex<Context>>, miner: Arc<Mutex<Miner>>, we
         show_warning(web_view, "This zone is unavailable for mining!");
         return;
     }
+    let (zone_difficulty, zone_min_difficulty) = {
+        let params = context.lock().unwrap().chain.get_params().clone();
+        (params.zone_difficulty, params.zone_min_difficulty)
+    };
     let data = data.to_lowercase();
     let mut data = match serde_json::from_str::<ZoneData>(&data) {
         Ok(zone) => {
-            if zone.difficulty < ZONE_MIN_DIFFICULTY {
-                warn!("Zone difficulty cannot be lower than {}!", ZONE_MIN_DIFFICULTY);
-                show_warning(web_view, &format!("Zone difficulty cannot be lower than {}!", ZONE_MIN_DIFFICULTY));
+            if zone.difficulty < zone_min_difficulty {
+                warn!("Zone difficulty cannot be lower than {}!", zone_min_difficulty);
+                show_warning(web_view, &format!("Zone difficulty cannot be lower than {}!", zone_min_difficulty));
                 return;
             }
             if name != zone.name {
@@ -461,12 +693,12 @@ fn action_create_zone(context: Arc<Mutex<Context>>, miner: Arc<Mutex<Miner>>, we
         let data = serde_json::to_string(&data).unwrap();
         match transaction {
             None => {
-                create_zone(Arc::clone(&context), miner.clone(), CLASS_ZONE, &name, &data, ZONE_DIFFICULTY, &keystore);
+                create_zone(Arc::clone(&context), miner.clone(), CLASS_ZONE, &name, &data, zone_difficulty, &keystore);
                 event_info(web_view, &format!("Mining of zone \\'{}\\' has started", &name));
             }
             Some(transaction) => {
                 if transaction.pub_key == keystore.get_public() {
-                    create_zone(Arc::clone(&context), miner.clone(), CLASS_ZONE, &name, &data, ZONE_DIFFICULTY, &keystore);
+                    create_zone(Arc::clone(&context), miner.clone(), CLASS_ZONE, &name, &data, zone_difficulty, &keystore);
                     event_info(web_view, &format!("Mining of zone \\'{}\\' has started", &name));
                 } else {
                     warn!("Tried to mine not owned domain!");
@@ -480,6 +712,11 @@ fn action_create_zone(context: Arc<Mutex<Context>>, miner: Arc<Mutex<Miner>>, we
     }
 }
 
+/// Gets fingerprint of public key, given in HEX
+fn get_hex_fingerprint(public: &str) -> String {
+    gis::from_hex(public).map(|key| get_fingerprint(&key)).unwrap_or_default()
+}
+
 fn show_warning(web_view: &mut WebView<()>, text: &str) {
     let str = text.replace('\'', "\\'");
     match web_view.eval(&format!("showWarning('{}');", &str)) {
@@ -574,7 +811,11 @@ fn create_domain(_context: Arc<Mutex<Context>>, miner: Arc<Mutex<Miner>>, class:
     let data = serde_json::to_string(&data).unwrap();
     let transaction = Transaction::from_str(name, class.to_owned(), data, keystore.get_public().clone());
     let block = Block::new(Some(transaction), keystore.get_public(), Bytes::default(), difficulty);
-    miner.lock().unwrap().add_block(block, keystore.clone());
+    if class == CLASS_RENEW {
+        miner.lock().unwrap().add_renewal(block, keystore.clone());
+    } else {
+        miner.lock().unwrap().add_block(block, keystore.clone());
+    }
 }
 
 #[derive(Deserialize)]
@@ -588,8 +829,16 @@ pub enum Cmd {
     MineZone { name: String, data: String },
     CheckRecord { data: String },
     CheckDomain { name: String },
-    MineDomain { name: String, data: String },
+    ImportZoneFile { name: String },
+    /// Updates of existing domains must be `confirmed` after the diff of records is shown
+    MineDomain { name: String, data: String, #[serde(default)] confirmed: bool },
+    /// Mines the same data of owned domain again, to extend its lifetime
+    RenewDomain { name: String },
+    /// Mines the same data of owned domain with a handover to `owner`, the public key in HEX
     TransferDomain { name: String, owner: String },
+    TraceResolve { name: String, qtype: String },
+    /// Registration statistics of zones that our key owns
+    ZoneStats,
     StopMining,
     Open { link: String },
 }
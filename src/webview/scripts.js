@@ -3,12 +3,34 @@ var ownersBuffer = [];
 var availableZones = [];
 var myDomains = [];
 var currentZone;
+var pendingDomain;
 
 function addRecord(record) {
     recordsBuffer.push(record);
     refreshRecordsList();
 }
 
+function importZoneFile() {
+    if (typeof currentZone == 'undefined') {
+        showWarning("Select a domain zone first");
+        return;
+    }
+    var new_domain = document.getElementById("new_domain").value.toLowerCase();
+    if (new_domain == "") {
+        showWarning("Enter the name of domain first");
+        return;
+    }
+    var domain = new_domain + "." + currentZone.name;
+    external.invoke(JSON.stringify({cmd: 'importZoneFile', name: domain}));
+}
+
+function importRecords(records) {
+    records.forEach(function(record) {
+        recordsBuffer.push(record);
+    });
+    refreshRecordsList();
+}
+
 function delRecord(index) {
     recordsBuffer.splice(index, 1);
     refreshRecordsList();
@@ -107,7 +129,7 @@ function addMyDomain(name, timestamp, data) {
 }
 
 function refreshMyDomains() {
-    var card = '<div class="card is-clickable is-inline-block" onclick="editDomain(\'{domain}\');"><div class="card-content p-3"><div class="content is-small"><h3>{title}</h3><div class="tags">{tags}</div></div></div></div>';
+    var card = '<div class="card is-clickable is-inline-block" onclick="editDomain(\'{domain}\');"><div class="card-content p-3"><div class="content is-small"><h3>{title}</h3><div class="tags">{tags}</div><div class="buttons"><button class="button is-small" onclick="renewDomain(\'{domain}\', event);">Renew</button><button class="button is-small" onclick="showTransferDialog(\'{domain}\', event);">Transfer</button></div></div></div></div>';
     var tag = '<span class="tag" title="{ip}">{domain}</span>';
     var cards = "";
     myDomains.forEach(function(value, index, array) {
@@ -125,7 +147,7 @@ function refreshMyDomains() {
                 tags = tags + buf;
             }
         });
-        cards = cards + card.replace("{title}", title).replace("{domain}", title).replace("{tags}", tags);
+        cards = cards + card.replace("{title}", title).replace(/{domain}/g, title).replace("{tags}", tags);
     });
     document.getElementById("my_domains").innerHTML = cards;
 }
@@ -148,6 +170,37 @@ function editDomain(domain) {
     });
 }
 
+function renewDomain(domain, event) {
+    event.stopPropagation();
+    showModalDialog("Mine the same records of " + domain + " again, to extend its lifetime?", function() {
+        external.invoke(JSON.stringify({cmd: 'renewDomain', name: domain}));
+    });
+}
+
+function showTransferDialog(domain, event) {
+    event.stopPropagation();
+    document.getElementById("transfer_domain").textContent = domain;
+    document.getElementById("transfer_owner").value = "";
+    var dialog = document.getElementById("transfer_dialog");
+    dialog.className = "modal is-active";
+}
+
+function transferPositiveButton() {
+    var domain = document.getElementById("transfer_domain").textContent;
+    var owner = document.getElementById("transfer_owner").value.trim();
+    if (!isValidOwner(owner)) {
+        alert("Wrong owner '{}'!".replace("{}", owner));
+        return;
+    }
+    transferCancelButton();
+    external.invoke(JSON.stringify({cmd: 'transferDomain', name: domain, owner: owner}));
+}
+
+function transferCancelButton() {
+    var dialog = document.getElementById("transfer_dialog");
+    dialog.className = "modal";
+}
+
 function onLoad() {
     // Workaround for Arch Linux Webkit
     // https://github.com/Boscop/web-view/issues/212#issuecomment-671055663
@@ -199,6 +252,53 @@ function saveKey() {
     external.invoke(JSON.stringify({cmd: 'saveKey'}));
 }
 
+function traceResolve() {
+    var name = document.getElementById("trace_name").value;
+    var qtype = document.getElementById("trace_type").value;
+    if (name == "") {
+        return;
+    }
+    document.getElementById("trace_result").textContent = "Resolving...";
+    external.invoke(JSON.stringify({cmd: 'traceResolve', name: name, qtype: qtype}));
+}
+
+function showTrace(trace) {
+    var lines = [trace.name + " " + trace.qtype + ": " + trace.rescode + " in " + trace.elapsed_us + " µs"];
+    trace.steps.forEach(function(step) {
+        var line = "  " + step.source + ": " + step.result + " (" + step.elapsed_us + " µs)";
+        if (step.details) {
+            line += " - " + step.details;
+        }
+        lines.push(line);
+    });
+    trace.answers.forEach(function(answer) {
+        lines.push(JSON.stringify(answer));
+    });
+    document.getElementById("trace_result").textContent = lines.join("\n");
+}
+
+function loadZoneStats() {
+    var element = document.getElementById("zone_stats");
+    element.classList.remove("is-hidden");
+    element.textContent = "Counting...";
+    external.invoke(JSON.stringify({cmd: 'zoneStats'}));
+}
+
+function showZoneStats(stats) {
+    var lines = [];
+    stats.forEach(function(zone) {
+        lines.push(zone.zone + ": " + zone.domains + " domains, " + zone.active + " active, " + zone.expired + " expired");
+        zone.weeks.forEach(function(week) {
+            var from = new Date(week.from * 1000).toLocaleDateString();
+            lines.push("  week from " + from + ": " + week.new_domains + " new, " + week.changes + " changes");
+        });
+    });
+    if (lines.length == 0) {
+        lines.push("Your key doesn't own any zones");
+    }
+    document.getElementById("zone_stats").textContent = lines.join("\n");
+}
+
 function checkRecord(data) {
     external.invoke(JSON.stringify({cmd: 'checkRecord', data: JSON.stringify(data)}));
 }
@@ -235,7 +335,27 @@ function createDomain() {
     data.owners = []; // TODO make a dialog to fill them
     data.contacts = []; // TODO make a dialog to fill them
     data = JSON.stringify(data);
-    external.invoke(JSON.stringify({cmd: 'mineDomain', name: domain, data: data}));
+    pendingDomain = {cmd: 'mineDomain', name: domain, data: data, confirmed: false};
+    external.invoke(JSON.stringify(pendingDomain));
+}
+
+// Shows what the update changes in records of existing domain, and mines it only after confirmation
+function confirmDomainUpdate(diff) {
+    var lines = [];
+    diff.removed.forEach(function(record) {
+        lines.push("- " + JSON.stringify(record));
+    });
+    diff.added.forEach(function(record) {
+        lines.push("+ " + JSON.stringify(record));
+    });
+    if (diff.other) {
+        lines.push("Contacts or owners are changed");
+    }
+    showModalDialog("This domain will be updated:\n" + lines.join("\n") + "\nMine the update?", function() {
+        pendingDomain.confirmed = true;
+        external.invoke(JSON.stringify(pendingDomain));
+    });
+    document.getElementById("modal_text").style.whiteSpace = "pre-line";
 }
 
 function domainMiningStarted() {
@@ -278,14 +398,17 @@ function domainAvailable(available) {
     var input = document.getElementById("new_domain");
     var button = document.getElementById("new_domain_button");
     var button2 = document.getElementById("add_record_button");
+    var button3 = document.getElementById("import_records_button");
     if (available) {
         input.className = "input";
         button.disabled = false
         button2.disabled = false
+        button3.disabled = false
     } else {
         input.className = "input is-danger";
         button.disabled = true
         button2.disabled = true
+        button3.disabled = true
     }
 }
 
@@ -468,13 +591,13 @@ function addEvent(type, time, message) {
     tab_events.innerHTML = tab_events.innerHTML + buf;
 }
 
-function keystoreChanged(path, pub_key, hash) {
+function keystoreChanged(path, pub_key, hash, fingerprint) {
     if (path == '') {
         path = "In memory";
     }
     var public_key_field = document.getElementById("public_key");
     public_key_field.value = pub_key;
-    public_key_field.title = path + "\n" + hash;
+    public_key_field.title = fingerprint + "\n" + path + "\n" + hash;
 
     var save_key = document.getElementById("save_key");
     save_key.disabled = false;